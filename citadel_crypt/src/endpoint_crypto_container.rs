@@ -5,11 +5,18 @@ use crate::prelude::SecurityLevel;
 use crate::stacked_ratchet::constructor::{AliceToBobTransferType, BobToAliceTransferType};
 use crate::stacked_ratchet::{Ratchet, StackedRatchet};
 use crate::toolset::{Toolset, UpdateStatus};
+use citadel_io::Mutex;
 use citadel_pqcrypto::algorithm_dictionary::CryptoParameters;
 use citadel_pqcrypto::constructor_opts::ConstructorOpts;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The default maximum time the truncation of a drill version is deferred while in-flight data
+/// still references it
+pub const DEFAULT_TRUNCATION_GRACE: Duration = Duration::from_secs(30);
 
 /// A container that holds the toolset as well as some boolean flags to ensure validity
 /// in tight concurrency situations. It is up to the networking protocol to ensure
@@ -27,6 +34,40 @@ pub struct PeerSessionCrypto<R: Ratchet = StackedRatchet> {
     /// Alice sends to Bob, then bob updates internally the toolset. However. Bob can't send packets to Alice quite yet using that newest version. He must first wait from Alice to commit on her end and wait for an ACK.
     /// If alice sends a packet using the latest version, that's okay since we already have that drill version on Bob's side; it's just that Bob can't send packets using the latest version until AFTER receiving the ACK
     pub latest_usable_version: u32,
+    /// The maximum time a truncation is deferred while the version to be truncated is pinned.
+    /// A zero duration truncates immediately
+    #[serde(skip, default = "default_truncation_grace")]
+    pub truncation_grace: Duration,
+    #[serde(skip)]
+    version_pins: VersionPins,
+    #[serde(skip)]
+    truncation_deferred_since: Option<Instant>,
+}
+
+type VersionPins = Arc<Mutex<HashMap<u32, usize>>>;
+
+fn default_truncation_grace() -> Duration {
+    DEFAULT_TRUNCATION_GRACE
+}
+
+/// Marks a drill version as referenced by in-flight data, such as an ongoing file transfer.
+/// While held, truncation of the version is deferred for up to the container's truncation grace.
+/// The pin is released once dropped
+pub struct VersionPin {
+    version: u32,
+    pins: VersionPins,
+}
+
+impl Drop for VersionPin {
+    fn drop(&mut self) {
+        let mut pins = self.pins.lock();
+        if let Some(count) = pins.get_mut(&self.version) {
+            *count -= 1;
+            if *count == 0 {
+                let _ = pins.remove(&self.version);
+            }
+        }
+    }
 }
 
 impl<R: Ratchet> PeerSessionCrypto<R> {
@@ -43,9 +84,27 @@ impl<R: Ratchet> PeerSessionCrypto<R> {
             rolling_group_id: 0,
             lock_set_by_alice: None,
             latest_usable_version: 0,
+            truncation_grace: DEFAULT_TRUNCATION_GRACE,
+            version_pins: VersionPins::default(),
+            truncation_deferred_since: None,
         }
     }
 
+    /// Bounds the number of drill versions retained, such that each update beyond the bound
+    /// requires the oldest version to be truncated via the TRUNCATE flow. A truncation is still
+    /// deferred while in-flight data pins the version. Should be set before the first update.
+    /// Defaults to [`MAX_HYPER_RATCHETS_IN_MEMORY`](crate::toolset::MAX_HYPER_RATCHETS_IN_MEMORY)
+    pub fn with_max_retained_ratchet_versions(mut self, max_retained_versions: usize) -> Self {
+        self.toolset
+            .set_max_retained_versions(max_retained_versions);
+        self
+    }
+
+    /// Returns the number of drill versions currently retained
+    pub fn retained_ratchet_versions(&self) -> usize {
+        self.toolset.len()
+    }
+
     /// Derives a new version of self safe to be used in the protocol
     /// Changes made to the returned version will not persist
     pub fn new_session(&self) -> Self {
@@ -57,6 +116,9 @@ impl<R: Ratchet> PeerSessionCrypto<R> {
             rolling_group_id: self.rolling_group_id,
             lock_set_by_alice: self.lock_set_by_alice,
             latest_usable_version: self.latest_usable_version,
+            truncation_grace: self.truncation_grace,
+            version_pins: VersionPins::default(),
+            truncation_deferred_since: None,
         }
     }
 
@@ -66,6 +128,11 @@ impl<R: Ratchet> PeerSessionCrypto<R> {
             .get_hyper_ratchet(version.unwrap_or(self.latest_usable_version))
     }
 
+    /// Returns every drill version currently retained, from oldest to newest
+    pub fn get_active_hyper_ratchet_versions(&self) -> Vec<u32> {
+        self.toolset.get_active_hyper_ratchet_versions()
+    }
+
     /// This should only be called when Bob receives the new DOU during the ReKey phase (will receive transfer), or, when Alice receives confirmation
     /// that the endpoint updated the ratchet (no transfer received, since none needed)
     pub fn commit_next_hyper_ratchet_version(
@@ -109,11 +176,70 @@ impl<R: Ratchet> PeerSessionCrypto<R> {
     }
 
     /// Deregisters the oldest StackedRatchet version. Requires the version input to ensure program/network consistency for debug purposes
+    ///
+    /// If the version is pinned by a [`VersionPin`], the truncation is deferred until the pin is
+    /// released and [`Self::process_deferred_truncations`] is called, or until the truncation grace
+    /// elapses. Later truncations requested in the meantime are deferred behind it
     pub fn deregister_oldest_hyper_ratchet(
         &mut self,
         version: u32,
     ) -> Result<(), CryptError<String>> {
-        self.toolset.deregister_oldest_hyper_ratchet(version)
+        if self.truncation_deferred_since.is_none()
+            && (self.toolset.len() <= self.toolset.max_retained_versions()
+                || self.toolset.get_oldest_hyper_ratchet_version() != version)
+        {
+            // returns the appropriate error
+            return self.toolset.deregister_oldest_hyper_ratchet(version);
+        }
+
+        let now = Instant::now();
+        let _ = self.truncation_deferred_since.get_or_insert(now);
+        self.process_deferred_truncations_at(now);
+        Ok(())
+    }
+
+    /// Pins `version`, deferring its truncation until the returned pin is dropped
+    pub fn pin_version(&self, version: u32) -> VersionPin {
+        *self.version_pins.lock().entry(version).or_default() += 1;
+        VersionPin {
+            version,
+            pins: self.version_pins.clone(),
+        }
+    }
+
+    /// Returns true if a truncation is waiting on a pinned version
+    pub fn has_deferred_truncation(&self) -> bool {
+        self.truncation_deferred_since.is_some()
+    }
+
+    /// Performs the deferred truncations whose versions are no longer pinned, or whose grace has
+    /// elapsed
+    pub fn process_deferred_truncations(&mut self) {
+        self.process_deferred_truncations_at(Instant::now())
+    }
+
+    fn process_deferred_truncations_at(&mut self, now: Instant) {
+        let deferred_since = match self.truncation_deferred_since {
+            Some(deferred_since) => deferred_since,
+            None => return,
+        };
+
+        let grace_elapsed = now.saturating_duration_since(deferred_since) >= self.truncation_grace;
+
+        while self.toolset.len() > self.toolset.max_retained_versions() {
+            let oldest = self.toolset.get_oldest_hyper_ratchet_version();
+            if !grace_elapsed && self.version_pins.lock().contains_key(&oldest) {
+                log::trace!(target: "citadel", "[Toolset] Deferring truncation of pinned version {}", oldest);
+                return;
+            }
+
+            if let Err(err) = self.toolset.deregister_oldest_hyper_ratchet(oldest) {
+                log::error!(target: "citadel", "[Toolset] Unable to perform deferred truncation of {}: {:?}", oldest, err);
+                break;
+            }
+        }
+
+        self.truncation_deferred_since = None;
     }
 
     /// Performs an update internally, only if sync conditions allow
@@ -227,6 +353,25 @@ impl<R: Ratchet> PeerSessionCrypto<R> {
         }
     }
 
+    /// Cancels a stalled update, releasing the lock on future updates. A version committed by
+    /// the update that is not yet usable is discarded, such that the endpoint continues on the
+    /// prior version. Returns the discarded version, if any
+    pub fn cancel_update(&mut self) -> Result<Option<u32>, CryptError> {
+        let most_recent = self.toolset.get_most_recent_hyper_ratchet_version();
+        let discarded = if most_recent != self.latest_usable_version {
+            self.toolset
+                .rollback_most_recent_hyper_ratchet(most_recent)?;
+            Some(most_recent)
+        } else {
+            None
+        };
+
+        self.update_in_progress.store(false, Ordering::SeqCst);
+        self.lock_set_by_alice = None;
+        log::trace!(target: "citadel", "[E2E] Cancelled update for {}. Discarded: {:?}", self.toolset.cid, discarded);
+        Ok(discarded)
+    }
+
     /// Refreshed the internal state to init state
     pub fn refresh_state(&mut self) {
         self.update_in_progress = Arc::new(AtomicBool::new(false));
@@ -228,6 +228,16 @@ impl EntropyBank {
         self.version
     }
 
+    /// Derives a 256-bit key for `context` from the entropy of this drill. Each endpoint holding
+    /// the drill derives the same key without any further exchange, while keys derived for
+    /// different contexts are independent of one another
+    pub fn derive_key(&self, context: &[u8]) -> [u8; 32] {
+        let mut hasher = sha3::Sha3_256::default();
+        hasher.update(context);
+        hasher.update(&*self.entropy);
+        hasher.finalize().into()
+    }
+
     /// Downloads the data necessary to create a drill
     fn generate_raw_3d_array() -> Result<[u8; BYTES_PER_STORE], CryptError<String>> {
         let mut bytes: [u8; BYTES_PER_STORE] = [0u8; BYTES_PER_STORE];
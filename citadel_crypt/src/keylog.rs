@@ -0,0 +1,118 @@
+//! # INSECURE: for authorized diagnostics only
+//!
+//! Exports the key material of each ratchet as it is committed to a [`Toolset`](crate::toolset::Toolset),
+//! akin to TLS's `SSLKEYLOGFILE`, so that captured traffic can be decrypted when testing against a
+//! reference implementation. Anyone holding the logged material can decrypt the corresponding
+//! traffic, defeating every guarantee this crate provides. This module only exists when the
+//! `debug-keylog` feature is explicitly enabled, and must never be enabled in a deployed build
+use crate::stacked_ratchet::Ratchet;
+use std::io::Write;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Identifies which part of a ratchet a [`KeyLogEntry`] was taken from
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum KeyLogLayer {
+    /// The message ratchet at the given security level index
+    Message(usize),
+    /// The entropy bank used to scramble groups of packets alongside the first message layer
+    Scramble,
+}
+
+/// The key material of a single layer of a ratchet
+#[derive(Clone, Debug)]
+pub struct KeyLogEntry {
+    pub cid: u64,
+    pub version: u32,
+    pub layer: KeyLogLayer,
+    /// The shared secret negotiated by the post-quantum KEM. Empty for [`KeyLogLayer::Scramble`]
+    pub shared_secret: Vec<u8>,
+    /// The entropy of the layer's entropy bank
+    pub entropy: Vec<u8>,
+}
+
+impl KeyLogEntry {
+    /// Formats this entry as a single line: `CITADEL_KEY <cid> <version> <layer> <shared secret> <entropy>`,
+    /// where the layer is `M<idx>` or `S`, and the key material is hex-encoded
+    pub fn to_line(&self) -> String {
+        let layer = match self.layer {
+            KeyLogLayer::Message(idx) => format!("M{idx}"),
+            KeyLogLayer::Scramble => "S".to_string(),
+        };
+
+        format!(
+            "CITADEL_KEY {} {} {} {} {}",
+            self.cid,
+            self.version,
+            layer,
+            hex(&self.shared_secret),
+            hex(&self.entropy)
+        )
+    }
+}
+
+/// Receives the key material of each ratchet as it is committed
+pub trait KeyLogSink: Send + Sync + 'static {
+    fn log(&self, entry: &KeyLogEntry);
+}
+
+/// Writes each entry as a line. Typically wraps a [`std::fs::File`]
+impl<W: Write + Send + 'static> KeyLogSink for Mutex<W> {
+    fn log(&self, entry: &KeyLogEntry) {
+        if let Ok(mut writer) = self.lock() {
+            let _ = writeln!(writer, "{}", entry.to_line());
+            let _ = writer.flush();
+        }
+    }
+}
+
+static SINK: RwLock<Option<Arc<dyn KeyLogSink>>> = RwLock::new(None);
+
+/// Sets the process-wide sink receiving key material, replacing any previous sink. Passing `None`
+/// stops key logging
+pub fn set_key_log_sink(sink: Option<Arc<dyn KeyLogSink>>) {
+    if sink.is_some() {
+        log::warn!(target: "citadel", "Key logging is enabled. Session keys will be exported in plaintext");
+    }
+
+    if let Ok(mut lock) = SINK.write() {
+        *lock = sink;
+    }
+}
+
+/// Passes the key material of each layer of `ratchet` to the sink, if one is set
+pub(crate) fn log_ratchet<R: Ratchet>(ratchet: &R) {
+    let sink = match SINK.read().ok().and_then(|sink| sink.clone()) {
+        Some(sink) => sink,
+        None => return,
+    };
+
+    let max_idx = ratchet.get_default_security_level().value() as usize;
+    for idx in 0..=max_idx {
+        let layer = KeyLogLayer::Message(idx);
+        let (pqc, drill) = ratchet.message_pqc_drill(Some(idx));
+        match pqc.get_shared_secret() {
+            Ok(shared_secret) => sink.log(&KeyLogEntry {
+                cid: ratchet.get_cid(),
+                version: ratchet.version(),
+                layer,
+                shared_secret: shared_secret.to_vec(),
+                entropy: drill.entropy.to_vec(),
+            }),
+            Err(err) => {
+                log::warn!(target: "citadel", "Unable to log keys of layer {:?}: {:?}", layer, err)
+            }
+        }
+    }
+
+    sink.log(&KeyLogEntry {
+        cid: ratchet.get_cid(),
+        version: ratchet.version(),
+        layer: KeyLogLayer::Scramble,
+        shared_secret: Vec::new(),
+        entropy: ratchet.get_scramble_drill().entropy.to_vec(),
+    });
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
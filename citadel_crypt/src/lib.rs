@@ -33,6 +33,9 @@ pub mod endpoint_crypto_container;
 pub mod entropy_bank;
 /// Contains the cryptographic primitives for handling FCM interactions on the network
 pub mod fcm;
+/// INSECURE: exports session keys for authorized diagnostics. Only exists with the `debug-keylog` feature
+#[cfg(feature = "debug-keylog")]
+pub mod keylog;
 /// Error type
 pub mod misc;
 /// For endowing packets with coordinates
@@ -20,6 +20,8 @@ pub enum CryptError<T = String> {
     OutOfBoundsError,
     /// This occurs if the byte-valued security level desired does not correspond to an actual [SecurityLevel]
     BadSecuritySetting,
+    /// The object is `size` bytes, but at most `max` bytes may be transferred
+    ObjectTooLarge { size: u64, max: u64 },
 }
 
 impl<T> CryptError<T> {
@@ -34,6 +36,9 @@ impl<T> CryptError<T> {
             CryptError::DrillUpdateError(s) => s.into(),
             CryptError::OutOfBoundsError => "[CryptError] Out of bounds exception".to_string(),
             CryptError::BadSecuritySetting => "[CryptError] Bad security setting".to_string(),
+            CryptError::ObjectTooLarge { size, max } => {
+                format!("[CryptError] Object is {size} bytes, but the maximum is {max} bytes")
+            }
         }
     }
 
@@ -47,13 +52,20 @@ impl<T> CryptError<T> {
             CryptError::DrillUpdateError(s) => s.as_ref(),
             CryptError::OutOfBoundsError => "[CryptError] Out of bounds exception",
             CryptError::BadSecuritySetting => "[CryptError] Bad security setting",
+            CryptError::ObjectTooLarge { .. } => "[CryptError] Object too large",
         }
     }
 }
 
 impl<T: AsRef<str>> std::fmt::Debug for CryptError<T> {
     fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
-        write!(f, "{}", self.as_str())
+        match self {
+            CryptError::ObjectTooLarge { size, max } => write!(
+                f,
+                "[CryptError] Object is {size} bytes, but the maximum is {max} bytes"
+            ),
+            err => write!(f, "{}", err.as_str()),
+        }
     }
 }
 
@@ -24,6 +24,19 @@ use zeroize::Zeroizing;
 /// 3Mb per group
 pub const MAX_BYTES_PER_GROUP: usize = crate::scramble::crypt_splitter::MAX_BYTES_PER_GROUP;
 const DEFAULT_BYTES_PER_GROUP: usize = 1024 * 1024 * 3;
+/// The number of maximum-sized groups a single object may span
+pub const MAX_GROUPS_PER_OBJECT: u64 = u16::MAX as u64;
+
+/// Returns the largest object, in bytes, that may be transferred at `security_level`. Like the
+/// packet sizing in [`get_max_packet_size`](crate::scramble::crypt_splitter::get_max_packet_size),
+/// each security layer halves the bytes per group, with the exponent currently capped at
+/// [`SecurityLevel::Standard`]
+pub fn max_file_transfer_size(security_level: SecurityLevel) -> u64 {
+    let security_exponent =
+        std::cmp::min(security_level.value(), SecurityLevel::Standard.value()) as u32;
+    let bytes_per_group = (MAX_BYTES_PER_GROUP as u64) / 2u64.pow(security_exponent);
+    bytes_per_group * MAX_GROUPS_PER_OBJECT
+}
 
 /// Used for streaming sources of a fixed size
 pub trait FixedSizedSource: Read + Send + 'static {
@@ -173,7 +186,16 @@ pub fn scramble_encrypt_source<S: ObjectSource, F: HeaderInscriberFn, const N: u
     let source = source.try_get_stream()?;
     let object_len = source
         .length()
-        .map_err(|err| CryptError::Encrypt(err.to_string()))? as usize;
+        .map_err(|err| CryptError::Encrypt(err.to_string()))?;
+    let max_object_len = max_file_transfer_size(security_level);
+    if object_len > max_object_len {
+        return Err(CryptError::ObjectTooLarge {
+            size: object_len,
+            max: max_object_len,
+        });
+    }
+
+    let object_len = object_len as usize;
     let max_bytes_per_group = max_group_size.unwrap_or(DEFAULT_BYTES_PER_GROUP);
 
     if max_bytes_per_group > MAX_BYTES_PER_GROUP {
@@ -34,6 +34,12 @@ pub struct Toolset<R: Ratchet> {
     /// designed to derail any currently existing or historical viruses that may look for conventional means of breaking-through data
     #[serde(bound = "")]
     static_auxiliary_hyper_ratchet: R,
+    #[serde(skip, default = "default_max_retained_versions")]
+    max_retained_versions: usize,
+}
+
+fn default_max_retained_versions() -> usize {
+    MAX_HYPER_RATCHETS_IN_MEMORY
 }
 
 // This clone should only be called in the middle of a session
@@ -45,6 +51,7 @@ impl<R: Ratchet> Clone for Toolset<R> {
             oldest_hyper_ratchet_version: self.oldest_hyper_ratchet_version,
             map: self.map.clone(),
             static_auxiliary_hyper_ratchet: self.static_auxiliary_hyper_ratchet.clone(),
+            max_retained_versions: self.max_retained_versions,
         }
     }
 }
@@ -62,6 +69,8 @@ impl<R: Ratchet> Toolset<R> {
     /// Creates a new [Toolset]. Designates the `hyper_ratchet` as the static auxiliary ratchet
     /// hyper_ratchet should be version 0
     pub fn new(cid: u64, hyper_ratchet: R) -> Self {
+        #[cfg(feature = "debug-keylog")]
+        crate::keylog::log_ratchet(&hyper_ratchet);
         let mut map = VecDeque::with_capacity(MAX_HYPER_RATCHETS_IN_MEMORY);
         map.push_front(hyper_ratchet.clone());
         Toolset {
@@ -70,6 +79,7 @@ impl<R: Ratchet> Toolset<R> {
             oldest_hyper_ratchet_version: 0,
             map,
             static_auxiliary_hyper_ratchet: hyper_ratchet,
+            max_retained_versions: MAX_HYPER_RATCHETS_IN_MEMORY,
         }
     }
 
@@ -87,6 +97,7 @@ impl<R: Ratchet> Toolset<R> {
             oldest_hyper_ratchet_version,
             map,
             static_auxiliary_hyper_ratchet: hyper_ratchet,
+            max_retained_versions: MAX_HYPER_RATCHETS_IN_MEMORY,
         }
     }
 
@@ -104,6 +115,8 @@ impl<R: Ratchet> Toolset<R> {
             return None;
         }
 
+        #[cfg(feature = "debug-keylog")]
+        crate::keylog::log_ratchet(&new_hyper_ratchet);
         let update_status = self.append_hyper_ratchet(new_hyper_ratchet);
         let cur_version = match &update_status {
             UpdateStatus::Committed { new_version }
@@ -113,7 +126,7 @@ impl<R: Ratchet> Toolset<R> {
         self.most_recent_hyper_ratchet_version = cur_version;
 
         let prev_version = self.most_recent_hyper_ratchet_version.wrapping_sub(1);
-        log::trace!(target: "citadel", "[{}] Upgraded {} to {}. Adjusted index of current: {}. Adjusted index of (current - 1): {} || OLDEST: {} || LEN: {}", MAX_HYPER_RATCHETS_IN_MEMORY, prev_version, cur_version, self.get_adjusted_index(cur_version), self.get_adjusted_index(prev_version), self.get_oldest_hyper_ratchet_version(), self.map.len());
+        log::trace!(target: "citadel", "[{}] Upgraded {} to {}. Adjusted index of current: {}. Adjusted index of (current - 1): {} || OLDEST: {} || LEN: {}", self.max_retained_versions, prev_version, cur_version, self.get_adjusted_index(cur_version), self.get_adjusted_index(prev_version), self.get_oldest_hyper_ratchet_version(), self.map.len());
         Some(update_status)
     }
 
@@ -126,7 +139,7 @@ impl<R: Ratchet> Toolset<R> {
         let new_version = hyper_ratchet.version();
         //println!("max hypers: {} @ {} bytes ea", MAX_HYPER_RATCHETS_IN_MEMORY, get_approx_bytes_per_hyper_ratchet());
         self.map.push_front(hyper_ratchet);
-        if self.map.len() > MAX_HYPER_RATCHETS_IN_MEMORY {
+        if self.map.len() > self.max_retained_versions {
             let old_version = self.get_oldest_hyper_ratchet_version();
             log::trace!(target: "citadel", "[Toolset Update] Needs Truncation. Old version: {}", old_version);
             UpdateStatus::CommittedNeedsSynchronization {
@@ -145,7 +158,7 @@ impl<R: Ratchet> Toolset<R> {
     /// this function last. By doing this, Alice no longer sends packets that may be no longer be valid
     #[allow(unused_results)]
     pub fn deregister_oldest_hyper_ratchet(&mut self, version: u32) -> Result<(), CryptError> {
-        if self.map.len() <= MAX_HYPER_RATCHETS_IN_MEMORY {
+        if self.map.len() <= self.max_retained_versions {
             return Err(CryptError::DrillUpdateError(
                 "Cannot call for deregistration unless the map len is maxed out".to_string(),
             ));
@@ -164,6 +177,42 @@ impl<R: Ratchet> Toolset<R> {
         }
     }
 
+    /// Discards the most recent version, which must be `version`, such as when the update that
+    /// committed it was cancelled before either endpoint began using it. The only remaining
+    /// version may not be discarded
+    pub fn rollback_most_recent_hyper_ratchet(&mut self, version: u32) -> Result<(), CryptError> {
+        if self.map.len() <= 1 {
+            return Err(CryptError::DrillUpdateError(
+                "Cannot roll back the only version retained".to_string(),
+            ));
+        }
+
+        let most_recent = self.get_most_recent_hyper_ratchet_version();
+        if most_recent != version {
+            Err(CryptError::DrillUpdateError(format!(
+                "Unable to roll back. Provided version: {version}, expected version: {most_recent}",
+            )))
+        } else {
+            let _ = self.map.pop_front().ok_or(CryptError::OutOfBoundsError)?;
+            self.most_recent_hyper_ratchet_version = most_recent.wrapping_sub(1);
+            log::trace!(target: "citadel", "[Toolset] Rolled back version {}. New most recent: {} | LEN: {}", version, self.most_recent_hyper_ratchet_version, self.len());
+            Ok(())
+        }
+    }
+
+    /// Returns the maximum number of versions retained before the oldest must be truncated.
+    /// Defaults to [`MAX_HYPER_RATCHETS_IN_MEMORY`]
+    pub fn max_retained_versions(&self) -> usize {
+        self.max_retained_versions
+    }
+
+    /// Sets the maximum number of versions retained before the oldest must be truncated. At least
+    /// one version is always retained. Lowering the bound below the number of versions already
+    /// retained does not drop any version, so this should be set before the first update
+    pub fn set_max_retained_versions(&mut self, max_retained_versions: usize) {
+        self.max_retained_versions = std::cmp::max(max_retained_versions, 1);
+    }
+
     /// Returns the number of StackedRatchets internally
     #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
@@ -190,6 +239,17 @@ impl<R: Ratchet> Toolset<R> {
         self.most_recent_hyper_ratchet_version
     }
 
+    /// Returns the versions of every drill retained in memory, from oldest to newest. Versions
+    /// older than the newest may still be needed to decrypt in-flight packets, and are only
+    /// dropped once [`Self::deregister_oldest_hyper_ratchet`] is called during truncation
+    pub fn get_active_hyper_ratchet_versions(&self) -> Vec<u32> {
+        self.map
+            .iter()
+            .rev()
+            .map(|ratchet| ratchet.version())
+            .collect()
+    }
+
     /// Returns the static auxiliary drill. There is no "set" function, because this really
     /// shouldn't be changing internally as this is depended upon by datasets which require a fixed encryption
     /// version which would otherwise normally get dropped from the VecDeque semi-actively.
@@ -270,6 +330,7 @@ impl From<(StaticAuxRatchet, StackedRatchet)> for Toolset<StackedRatchet> {
             most_recent_hyper_ratchet_version,
             map,
             static_auxiliary_hyper_ratchet: drill.0,
+            max_retained_versions: MAX_HYPER_RATCHETS_IN_MEMORY,
         }
     }
 }
@@ -4,7 +4,9 @@ mod tests {
     use citadel_crypt::argon::argon_container::{
         ArgonSettings, ArgonStatus, AsyncArgon, ServerArgonContainer,
     };
-    use citadel_crypt::endpoint_crypto_container::EndpointRatchetConstructor;
+    use citadel_crypt::endpoint_crypto_container::{
+        EndpointRatchetConstructor, KemTransferStatus, PeerSessionCrypto,
+    };
     use citadel_crypt::entropy_bank::{EntropyBank, SecurityLevel};
     use citadel_crypt::misc::TransferType;
     use citadel_crypt::packet_vector::PacketVector;
@@ -21,6 +23,7 @@ mod tests {
     use rstest::rstest;
     #[cfg(not(target_family = "wasm"))]
     use std::path::PathBuf;
+    use std::time::Duration;
 
     #[cfg(not(target_family = "wasm"))]
     #[tokio::test]
@@ -406,6 +409,188 @@ mod tests {
         toolset::<citadel_crypt::fcm::fcm_ratchet::ThinRatchet>(enx, kem, sig);
     }
 
+    /// A trivial custom ratchet, which delegates to the stacked ratchet
+    mod custom_ratchet {
+        use citadel_crypt::endpoint_crypto_container::EndpointRatchetConstructor;
+        use citadel_crypt::entropy_bank::{EntropyBank, SecurityLevel};
+        use citadel_crypt::misc::CryptError;
+        use citadel_crypt::stacked_ratchet::constructor::{
+            AliceToBobTransferType, BobToAliceTransferType, StackedRatchetConstructor,
+        };
+        use citadel_crypt::stacked_ratchet::{Ratchet, StackedRatchet};
+        use citadel_pqcrypto::bytes_in_place::EzBuffer;
+        use citadel_pqcrypto::constructor_opts::ConstructorOpts;
+        use citadel_pqcrypto::PostQuantumContainer;
+        use serde::{Deserialize, Serialize};
+        use std::borrow::Cow;
+
+        type Inner = StackedRatchetConstructor;
+
+        #[derive(Clone, Serialize, Deserialize)]
+        pub struct CustomRatchet(StackedRatchet);
+
+        #[derive(Serialize, Deserialize)]
+        pub struct CustomRatchetConstructor(Inner);
+
+        // relies on the default identifier, as an implementation predating it would
+        impl Ratchet for CustomRatchet {
+            type Constructor = CustomRatchetConstructor;
+
+            fn get_cid(&self) -> u64 {
+                Ratchet::get_cid(&self.0)
+            }
+
+            fn version(&self) -> u32 {
+                Ratchet::version(&self.0)
+            }
+
+            fn has_verified_packets(&self) -> bool {
+                Ratchet::has_verified_packets(&self.0)
+            }
+
+            fn reset_ara(&self) {
+                Ratchet::reset_ara(&self.0)
+            }
+
+            fn get_default_security_level(&self) -> SecurityLevel {
+                Ratchet::get_default_security_level(&self.0)
+            }
+
+            fn message_pqc_drill(
+                &self,
+                idx: Option<usize>,
+            ) -> (&PostQuantumContainer, &EntropyBank) {
+                Ratchet::message_pqc_drill(&self.0, idx)
+            }
+
+            fn get_scramble_drill(&self) -> &EntropyBank {
+                Ratchet::get_scramble_drill(&self.0)
+            }
+
+            fn get_next_constructor_opts(&self) -> Vec<ConstructorOpts> {
+                Ratchet::get_next_constructor_opts(&self.0)
+            }
+
+            fn protect_message_packet<T: EzBuffer>(
+                &self,
+                security_level: Option<SecurityLevel>,
+                header_len_bytes: usize,
+                packet: &mut T,
+            ) -> Result<(), CryptError<String>> {
+                Ratchet::protect_message_packet(&self.0, security_level, header_len_bytes, packet)
+            }
+
+            fn validate_message_packet<H: AsRef<[u8]>, T: EzBuffer>(
+                &self,
+                security_level: Option<SecurityLevel>,
+                header: H,
+                packet: &mut T,
+            ) -> Result<(), CryptError<String>> {
+                Ratchet::validate_message_packet(&self.0, security_level, header, packet)
+            }
+
+            fn local_encrypt<'a, T: Into<Cow<'a, [u8]>>>(
+                &self,
+                contents: T,
+                security_level: SecurityLevel,
+            ) -> Result<Vec<u8>, CryptError> {
+                Ratchet::local_encrypt(&self.0, contents, security_level)
+            }
+
+            fn local_decrypt<'a, T: Into<Cow<'a, [u8]>>>(
+                &self,
+                contents: T,
+                security_level: SecurityLevel,
+            ) -> Result<Vec<u8>, CryptError> {
+                Ratchet::local_decrypt(&self.0, contents, security_level)
+            }
+        }
+
+        impl EndpointRatchetConstructor<CustomRatchet> for CustomRatchetConstructor {
+            fn new_alice(
+                opts: Vec<ConstructorOpts>,
+                cid: u64,
+                new_version: u32,
+                security_level: Option<SecurityLevel>,
+            ) -> Option<Self> {
+                <Inner as EndpointRatchetConstructor<StackedRatchet>>::new_alice(
+                    opts,
+                    cid,
+                    new_version,
+                    security_level,
+                )
+                .map(Self)
+            }
+
+            fn new_bob(
+                cid: u64,
+                new_drill_vers: u32,
+                opts: Vec<ConstructorOpts>,
+                transfer: AliceToBobTransferType,
+            ) -> Option<Self> {
+                <Inner as EndpointRatchetConstructor<StackedRatchet>>::new_bob(
+                    cid,
+                    new_drill_vers,
+                    opts,
+                    transfer,
+                )
+                .map(Self)
+            }
+
+            fn stage0_alice(&self) -> Option<AliceToBobTransferType> {
+                EndpointRatchetConstructor::<StackedRatchet>::stage0_alice(&self.0)
+            }
+
+            fn stage0_bob(&self) -> Option<BobToAliceTransferType> {
+                EndpointRatchetConstructor::<StackedRatchet>::stage0_bob(&self.0)
+            }
+
+            fn stage1_alice(&mut self, transfer: BobToAliceTransferType) -> Result<(), CryptError> {
+                EndpointRatchetConstructor::<StackedRatchet>::stage1_alice(&mut self.0, transfer)
+            }
+
+            fn update_version(&mut self, version: u32) -> Option<()> {
+                EndpointRatchetConstructor::<StackedRatchet>::update_version(&mut self.0, version)
+            }
+
+            fn finish_with_custom_cid(self, cid: u64) -> Option<CustomRatchet> {
+                EndpointRatchetConstructor::<StackedRatchet>::finish_with_custom_cid(self.0, cid)
+                    .map(CustomRatchet)
+            }
+
+            fn finish(self) -> Option<CustomRatchet> {
+                EndpointRatchetConstructor::<StackedRatchet>::finish(self.0).map(CustomRatchet)
+            }
+        }
+    }
+
+    #[test]
+    fn custom_ratchet() {
+        use custom_ratchet::CustomRatchet;
+        citadel_logging::setup_log();
+
+        for sec in 0..SecurityLevel::Extreme.value() {
+            let ratchet = hyper_ratchet::<CustomRatchet, _>(
+                KemAlgorithm::Kyber + EncryptionAlgorithm::AES_GCM_256,
+                Some(sec.into()),
+                false,
+            );
+            let ciphertext = ratchet
+                .local_encrypt(b"Hello, world!" as &[u8], sec.into())
+                .unwrap();
+            assert_eq!(
+                ratchet.local_decrypt(ciphertext, sec.into()).unwrap(),
+                b"Hello, world!"
+            );
+        }
+
+        toolset::<CustomRatchet>(
+            EncryptionAlgorithm::AES_GCM_256,
+            KemAlgorithm::Kyber,
+            SigAlgorithm::None,
+        );
+    }
+
     fn toolset<R: Ratchet>(enx: EncryptionAlgorithm, kem: KemAlgorithm, sig: SigAlgorithm) {
         citadel_logging::setup_log();
         const COUNT: u32 = 100;
@@ -435,6 +620,11 @@ mod tests {
             }
         }
 
+        assert_eq!(
+            toolset.get_active_hyper_ratchet_versions(),
+            (0..COUNT).collect::<Vec<u32>>()
+        );
+
         for x in 0..COUNT {
             if toolset.deregister_oldest_hyper_ratchet(x).is_ok() {
                 assert_eq!(x + 1, toolset.get_oldest_hyper_ratchet_version());
@@ -447,6 +637,11 @@ mod tests {
             }
         }
 
+        assert_eq!(
+            toolset.get_active_hyper_ratchet_versions(),
+            (COUNT - MAX_HYPER_RATCHETS_IN_MEMORY as u32..COUNT).collect::<Vec<u32>>()
+        );
+
         let _res = toolset
             .update_from(gen::<R>(0, COUNT, security_level, enx + kem + sig).0)
             .unwrap();
@@ -465,6 +660,82 @@ mod tests {
         assert_eq!(toolset.len(), MAX_HYPER_RATCHETS_IN_MEMORY);
     }
 
+    #[cfg(feature = "debug-keylog")]
+    #[test]
+    fn keylog_receives_each_rotation() {
+        use citadel_crypt::keylog::{set_key_log_sink, KeyLogEntry, KeyLogLayer, KeyLogSink};
+        use std::sync::{Arc, Mutex};
+
+        struct Collector(Mutex<Vec<KeyLogEntry>>);
+
+        impl KeyLogSink for Collector {
+            fn log(&self, entry: &KeyLogEntry) {
+                self.0.lock().unwrap().push(entry.clone());
+            }
+        }
+
+        citadel_logging::setup_log();
+        // the sink is process-wide, so only entries for this CID are considered
+        const CID: u64 = 0xC17AD31;
+        const ROTATIONS: u32 = 3;
+        let security_level = SecurityLevel::Standard;
+        let params = EncryptionAlgorithm::AES_GCM_256 + KemAlgorithm::Kyber;
+        let collector = Arc::new(Collector(Mutex::new(vec![])));
+        set_key_log_sink(Some(collector.clone()));
+
+        let entries = || {
+            collector
+                .0
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|entry| entry.cid == CID)
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        let (alice_v0, bob_v0) = gen::<StackedRatchet>(CID, 0, security_level, params);
+        let mut alice = Toolset::new(CID, alice_v0);
+        let mut bob = Toolset::new(CID, bob_v0);
+
+        for version in 1..=ROTATIONS {
+            let (alice_next, bob_next) =
+                gen::<StackedRatchet>(CID, version, security_level, params);
+            let _ = alice.update_from(alice_next).unwrap();
+            let _ = bob.update_from(bob_next).unwrap();
+        }
+
+        set_key_log_sink(None);
+
+        for version in 0..=ROTATIONS {
+            let logged = entries()
+                .into_iter()
+                .filter(|entry| entry.version == version)
+                .collect::<Vec<_>>();
+            // a message layer and the scramble layer, once for each endpoint
+            assert_eq!(logged.len(), 4);
+
+            let message = logged
+                .iter()
+                .filter(|entry| entry.layer == KeyLogLayer::Message(0))
+                .collect::<Vec<_>>();
+            assert_eq!(message.len(), 2);
+            assert!(!message[0].shared_secret.is_empty());
+            // both endpoints derived the same key material
+            assert_eq!(message[0].shared_secret, message[1].shared_secret);
+            assert_eq!(message[0].entropy, message[1].entropy);
+            assert!(message[0]
+                .to_line()
+                .starts_with(&format!("CITADEL_KEY {CID} {version} M0 ")));
+        }
+
+        // once the sink is removed, nothing further is logged
+        let count = entries().len();
+        let (alice_next, _) = gen::<StackedRatchet>(CID, ROTATIONS + 1, security_level, params);
+        let _ = alice.update_from(alice_next).unwrap();
+        assert_eq!(entries().len(), count);
+    }
+
     fn gen<R: Ratchet>(
         cid: u64,
         version: u32,
@@ -569,6 +840,211 @@ mod tests {
         }
     }
 
+    #[test]
+    fn truncation_deferred_while_version_pinned() {
+        citadel_logging::setup_log();
+        const HEADER: &[u8] = b"header";
+        const MESSAGE: &[u8] = b"in-flight on the oldest version";
+        let security_level = SecurityLevel::Standard;
+        let params = EncryptionAlgorithm::AES_GCM_256 + KemAlgorithm::Kyber;
+        let (alice_v0, bob_v0) = gen::<StackedRatchet>(0, 0, security_level, params);
+        let mut bob = PeerSessionCrypto::new(Toolset::new(0, bob_v0), false);
+        let max = MAX_HYPER_RATCHETS_IN_MEMORY as u32;
+
+        let rotate = |bob: &mut PeerSessionCrypto, version: u32| {
+            let ratchet = gen::<StackedRatchet>(0, version, security_level, params).1;
+            let _ = bob.toolset.update_from(ratchet).unwrap();
+        };
+
+        for version in 1..=max {
+            rotate(&mut bob, version);
+        }
+
+        // a transfer begins on version 0, then the rotation requests that version 0 be truncated
+        let pin = bob.pin_version(0);
+        let mut packet = BytesMut::from(HEADER);
+        packet.put(MESSAGE);
+        alice_v0
+            .protect_message_packet(Some(security_level), HEADER.len(), &mut packet)
+            .unwrap();
+
+        bob.deregister_oldest_hyper_ratchet(0).unwrap();
+        assert!(bob.has_deferred_truncation());
+        assert_eq!(bob.toolset.get_oldest_hyper_ratchet_version(), 0);
+
+        // the next rotation names the same oldest version, and is deferred behind it
+        rotate(&mut bob, max + 1);
+        bob.deregister_oldest_hyper_ratchet(0).unwrap();
+        assert_eq!(bob.toolset.len(), MAX_HYPER_RATCHETS_IN_MEMORY + 2);
+
+        let header = packet.split_to(HEADER.len());
+        bob.get_hyper_ratchet(Some(0))
+            .unwrap()
+            .validate_message_packet(Some(security_level), &header[..], &mut packet)
+            .unwrap();
+        assert_eq!(&packet[..], MESSAGE);
+
+        // once the transfer completes, every deferred truncation is performed
+        drop(pin);
+        bob.process_deferred_truncations();
+        assert!(!bob.has_deferred_truncation());
+        assert_eq!(bob.toolset.len(), MAX_HYPER_RATCHETS_IN_MEMORY);
+        assert_eq!(bob.toolset.get_oldest_hyper_ratchet_version(), 2);
+
+        // without a grace window, pinned versions are truncated immediately
+        bob.truncation_grace = Duration::ZERO;
+        rotate(&mut bob, max + 2);
+        let _pin = bob.pin_version(2);
+        bob.deregister_oldest_hyper_ratchet(2).unwrap();
+        assert!(!bob.has_deferred_truncation());
+        assert_eq!(bob.toolset.get_oldest_hyper_ratchet_version(), 3);
+    }
+
+    #[test]
+    fn retained_ratchet_versions_bounded() {
+        citadel_logging::setup_log();
+        const HEADER: &[u8] = b"header";
+        const MESSAGE: &[u8] = b"in-flight on a retained version";
+        const MAX_RETAINED: usize = 3;
+        const ROTATIONS: u32 = 20;
+        let security_level = SecurityLevel::Standard;
+        let params = EncryptionAlgorithm::AES_GCM_256 + KemAlgorithm::Kyber;
+        let (alice_v0, bob_v0) = gen::<StackedRatchet>(0, 0, security_level, params);
+        let mut alice = PeerSessionCrypto::new(Toolset::new(0, alice_v0), true)
+            .with_max_retained_ratchet_versions(MAX_RETAINED);
+        let mut bob = PeerSessionCrypto::new(Toolset::new(0, bob_v0), false)
+            .with_max_retained_ratchet_versions(MAX_RETAINED);
+
+        // commits the next version on both endpoints, truncating the oldest when required
+        let rotate = |alice: &mut PeerSessionCrypto, bob: &mut PeerSessionCrypto, version: u32| {
+            let (alice_next, bob_next) = gen::<StackedRatchet>(0, version, security_level, params);
+            let _ = alice.toolset.update_from(alice_next).unwrap();
+            let status = bob.toolset.update_from(bob_next).unwrap();
+            if let UpdateStatus::CommittedNeedsSynchronization { old_version, .. } = status {
+                alice.deregister_oldest_hyper_ratchet(old_version).unwrap();
+                bob.deregister_oldest_hyper_ratchet(old_version).unwrap();
+            }
+        };
+
+        for version in 1..=ROTATIONS {
+            rotate(&mut alice, &mut bob, version);
+            assert!(alice.retained_ratchet_versions() <= MAX_RETAINED);
+            assert!(bob.retained_ratchet_versions() <= MAX_RETAINED);
+        }
+
+        assert_eq!(bob.retained_ratchet_versions(), MAX_RETAINED);
+        assert_eq!(
+            bob.get_active_hyper_ratchet_versions(),
+            ((ROTATIONS - MAX_RETAINED as u32 + 1)..=ROTATIONS).collect::<Vec<u32>>()
+        );
+
+        // data encrypted under the oldest retained version is pinned while in flight, so the
+        // next rotation defers its truncation
+        let oldest = bob.toolset.get_oldest_hyper_ratchet_version();
+        let pin = bob.pin_version(oldest);
+        let mut packet = BytesMut::from(HEADER);
+        packet.put(MESSAGE);
+        alice
+            .get_hyper_ratchet(Some(oldest))
+            .unwrap()
+            .protect_message_packet(Some(security_level), HEADER.len(), &mut packet)
+            .unwrap();
+
+        rotate(&mut alice, &mut bob, ROTATIONS + 1);
+        assert!(bob.has_deferred_truncation());
+
+        let header = packet.split_to(HEADER.len());
+        bob.get_hyper_ratchet(Some(oldest))
+            .unwrap()
+            .validate_message_packet(Some(security_level), &header[..], &mut packet)
+            .unwrap();
+        assert_eq!(&packet[..], MESSAGE);
+
+        drop(pin);
+        bob.process_deferred_truncations();
+        assert_eq!(bob.retained_ratchet_versions(), MAX_RETAINED);
+        assert_eq!(alice.retained_ratchet_versions(), MAX_RETAINED);
+    }
+
+    #[test]
+    fn stalled_update_cancelled_and_rolled_back() {
+        citadel_logging::setup_log();
+        const HEADER: &[u8] = b"header";
+        const MESSAGE: &[u8] = b"sent after the stalled update";
+        let security_level = SecurityLevel::Standard;
+        let params = EncryptionAlgorithm::AES_GCM_256 + KemAlgorithm::Kyber;
+        let (alice_v0, bob_v0) = gen::<StackedRatchet>(0, 0, security_level, params);
+        let mut alice = PeerSessionCrypto::new(Toolset::new(0, alice_v0), true);
+        let mut bob = PeerSessionCrypto::new(Toolset::new(0, bob_v0), false);
+
+        // stage 0: alice locks, then bob commits the next version without yet using it
+        let bob_update = |alice: &mut PeerSessionCrypto, bob: &mut PeerSessionCrypto| {
+            let constructor = alice.get_next_constructor(false).unwrap();
+            let transfer = constructor.stage0_alice().unwrap();
+            let opts = bob
+                .get_hyper_ratchet(None)
+                .unwrap()
+                .get_next_constructor_opts();
+            let bob_constructor = <StackedRatchet as Ratchet>::Constructor::new_bob(
+                0,
+                transfer.get_declared_new_version(),
+                opts,
+                transfer,
+            )
+            .unwrap();
+            (
+                constructor,
+                bob.update_sync_safe(bob_constructor, false, 0).unwrap(),
+            )
+        };
+
+        let (_, status) = bob_update(&mut alice, &mut bob);
+        assert!(status.has_some());
+        assert_eq!(bob.toolset.get_most_recent_hyper_ratchet_version(), 1);
+        assert!(alice.get_next_constructor(false).is_none());
+
+        // bob's response never arrives, so both endpoints cancel the update
+        assert_eq!(alice.cancel_update().unwrap(), None);
+        assert_eq!(bob.cancel_update().unwrap(), Some(1));
+        assert_eq!(bob.toolset.get_most_recent_hyper_ratchet_version(), 0);
+        assert_eq!(bob.toolset.len(), 1);
+        assert!(bob.cancel_update().unwrap().is_none());
+
+        // the only version retained may not be rolled back
+        assert!(bob.toolset.rollback_most_recent_hyper_ratchet(0).is_err());
+
+        // the session continues on the prior version
+        let mut packet = BytesMut::from(HEADER);
+        packet.put(MESSAGE);
+        alice
+            .get_hyper_ratchet(None)
+            .unwrap()
+            .protect_message_packet(Some(security_level), HEADER.len(), &mut packet)
+            .unwrap();
+        let header = packet.split_to(HEADER.len());
+        bob.get_hyper_ratchet(None)
+            .unwrap()
+            .validate_message_packet(Some(security_level), &header[..], &mut packet)
+            .unwrap();
+        assert_eq!(&packet[..], MESSAGE);
+
+        // a subsequent update completes from the prior version
+        let (mut constructor, status) = bob_update(&mut alice, &mut bob);
+        let transfer = match status {
+            KemTransferStatus::Some(transfer, _) => transfer,
+            _ => panic!("Bob did not commit the update"),
+        };
+        constructor.stage1_alice(transfer).unwrap();
+        let _ = alice.update_sync_safe(constructor, true, 0).unwrap();
+        alice.post_alice_stage1_or_post_stage1_bob();
+        let _ = alice.maybe_unlock(false).unwrap();
+        bob.post_alice_stage1_or_post_stage1_bob();
+        let _ = bob.maybe_unlock(false).unwrap();
+
+        assert_eq!(alice.get_hyper_ratchet(None).unwrap().version(), 1);
+        assert_eq!(bob.get_hyper_ratchet(None).unwrap().version(), 1);
+    }
+
     #[rstest]
     #[case(
         EncryptionAlgorithm::AES_GCM_256,
@@ -823,6 +1299,77 @@ mod tests {
         assert!(sa_bob.local_decrypt(&bytes_ret, security_level).is_err());
     }
 
+    #[tokio::test]
+    async fn oversized_file_transfer_rejected() {
+        use citadel_crypt::misc::CryptError;
+        use citadel_crypt::streaming_crypt_scrambler::{
+            max_file_transfer_size, scramble_encrypt_source, FixedSizedSource, ObjectSource,
+        };
+        use tokio::sync::mpsc::channel;
+
+        // reports a length one byte over the limit without holding the bytes in memory
+        struct OversizedSource;
+
+        impl std::io::Read for OversizedSource {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                panic!("An oversized source should never be read")
+            }
+        }
+
+        impl FixedSizedSource for OversizedSource {
+            fn length(&self) -> std::io::Result<u64> {
+                Ok(max_file_transfer_size(SecurityLevel::Standard) + 1)
+            }
+        }
+
+        impl ObjectSource for OversizedSource {
+            fn try_get_stream(&mut self) -> Result<Box<dyn FixedSizedSource>, CryptError> {
+                Ok(Box::new(OversizedSource))
+            }
+
+            fn get_source_name(&self) -> Result<String, CryptError> {
+                Ok("oversized.bin".to_string())
+            }
+        }
+
+        citadel_logging::setup_log();
+        let (alice, bob) = gen::<StackedRatchet>(0, 0, SecurityLevel::Standard, Default::default());
+        let (static_aux_ratchet, _) =
+            gen::<StackedRatchet>(0, 0, SecurityLevel::Standard, Default::default());
+        let (group_sender_tx, mut group_sender_rx) = channel(1);
+        let (_stop_tx, stop_rx) = tokio::sync::oneshot::channel();
+        let max = max_file_transfer_size(SecurityLevel::Standard);
+
+        match scramble_encrypt_source::<_, _, HEADER_LEN>(
+            OversizedSource,
+            None,
+            99,
+            group_sender_tx,
+            stop_rx,
+            SecurityLevel::Standard,
+            alice,
+            static_aux_ratchet,
+            HEADER_LEN,
+            bob.get_cid(),
+            0,
+            TransferType::FileTransfer,
+            header_inscribe,
+        ) {
+            Err(CryptError::ObjectTooLarge {
+                size,
+                max: reported,
+            }) => {
+                assert_eq!(size, max + 1);
+                assert_eq!(reported, max);
+            }
+            Err(err) => panic!("Unexpected error: {err:?}"),
+            Ok(_) => panic!("An oversized source should be rejected"),
+        }
+
+        // no groups are produced for a rejected source
+        assert!(group_sender_rx.recv().await.is_none());
+    }
+
     async fn test_file_transfer_inner(
         transfer_type: TransferType,
         enx: EncryptionAlgorithm,
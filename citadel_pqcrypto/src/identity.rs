@@ -0,0 +1,78 @@
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use sha3::Digest;
+use zeroize::Zeroizing;
+
+#[cfg(target_family = "wasm")]
+use crate::functions::AsSlice;
+
+/// The length of an identity fingerprint, in bytes
+pub const FINGERPRINT_LENGTH: usize = 32;
+
+/// A long-term signature keypair. Unlike the keys used during key exchange, this keypair is reused
+/// across sessions, allowing an endpoint to prove that it holds the same identity as before
+#[derive(Serialize, Deserialize, Clone)]
+pub struct IdentityKeyPair {
+    public_key: Vec<u8>,
+    secret_key: Zeroizing<Vec<u8>>,
+}
+
+impl IdentityKeyPair {
+    /// Generates a new keypair
+    pub fn generate() -> Result<Self, Error> {
+        let (public_key, secret_key) = crate::functions::signature_keypair()?;
+        Ok(Self {
+            public_key: public_key.as_slice().to_vec(),
+            secret_key: Zeroizing::new(secret_key.as_slice().to_vec()),
+        })
+    }
+
+    /// Signs `message` with the secret key
+    pub fn sign<T: AsRef<[u8]>>(&self, message: T) -> Result<Vec<u8>, Error> {
+        crate::functions::signature_sign(message, self.secret_key.as_slice())
+    }
+
+    /// Returns the public key
+    pub fn public_key(&self) -> &[u8] {
+        self.public_key.as_slice()
+    }
+
+    /// Returns the fingerprint of the public key
+    pub fn fingerprint(&self) -> [u8; FINGERPRINT_LENGTH] {
+        fingerprint(self.public_key())
+    }
+}
+
+/// Verifies that `signature` was produced over `message` by the secret key belonging to `public_key`
+pub fn verify<T: AsRef<[u8]>, R: AsRef<[u8]>, V: AsRef<[u8]>>(
+    public_key: T,
+    message: R,
+    signature: V,
+) -> Result<(), Error> {
+    crate::functions::signature_verify(message, signature, public_key)
+}
+
+/// Computes the fingerprint of an identity public key
+pub fn fingerprint<T: AsRef<[u8]>>(public_key: T) -> [u8; FINGERPRINT_LENGTH] {
+    let mut hasher = sha3::Sha3_256::new();
+    hasher.update(public_key.as_ref());
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::identity::{fingerprint, verify, IdentityKeyPair};
+
+    #[test]
+    fn sign_and_verify() {
+        let keys = IdentityKeyPair::generate().unwrap();
+        let other = IdentityKeyPair::generate().unwrap();
+        let signature = keys.sign(b"transfer").unwrap();
+
+        assert!(verify(keys.public_key(), b"transfer", &signature).is_ok());
+        assert!(verify(keys.public_key(), b"tampered", &signature).is_err());
+        assert!(verify(other.public_key(), b"transfer", &signature).is_err());
+        assert_eq!(keys.fingerprint(), fingerprint(keys.public_key()));
+        assert_ne!(keys.fingerprint(), other.fingerprint());
+    }
+}
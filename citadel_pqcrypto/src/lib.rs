@@ -52,6 +52,9 @@ pub mod encryption;
 
 pub mod constructor_opts;
 
+/// For long-term identity keys
+pub mod identity;
+
 pub mod wire;
 
 /// For debug purposes
@@ -0,0 +1,96 @@
+//! Compares the cost of splitting the header off each received packet, where the buffer a packet
+//! is received into is reused by the next packet as a receive loop would. Run with
+//! `cargo bench -p citadel_proto --features bench`
+use bytes::BytesMut;
+use citadel_proto::prelude::{HdpHeader, HdpPacket};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::net::SocketAddr;
+use zerocopy::{AsBytes, I64, U128, U32, U64};
+
+const PAYLOAD_LENS: [usize; 3] = [64, 512, 1400];
+const LOCAL_PORT: u16 = 25000;
+
+fn packet(payload_len: usize) -> Vec<u8> {
+    let header = HdpHeader {
+        cmd_primary: 2,
+        cmd_aux: 7,
+        algorithm: 1,
+        security_level: 3,
+        protocol_version: U32::new(1),
+        context_info: U128::new(1234),
+        group: U64::new(99),
+        wave_id: U32::new(12),
+        session_cid: U64::new(123456789),
+        drill_version: U32::new(42),
+        timestamp: I64::new(1000),
+        target_cid: U64::new(987654321),
+    };
+
+    let mut packet = header.as_bytes().to_vec();
+    packet.resize(packet.len() + payload_len, 0xAB);
+    packet
+}
+
+fn bench_recv(c: &mut Criterion) {
+    let addr = SocketAddr::from(([127, 0, 0, 1], 1234));
+    let mut group = c.benchmark_group("packet_recv");
+
+    for payload_len in PAYLOAD_LENS {
+        let received = packet(payload_len);
+        group.throughput(Throughput::Bytes(received.len() as u64));
+
+        // split_off allocates a new Vec for the payload of each packet
+        group.bench_with_input(
+            BenchmarkId::new("vec_decompose", payload_len),
+            &received,
+            |b, received| {
+                let mut buffer = Vec::with_capacity(received.len());
+                b.iter(|| {
+                    buffer.extend_from_slice(received);
+                    let packet = HdpPacket::new_recv(std::mem::take(&mut buffer), addr, LOCAL_PORT);
+                    let (mut header, payload, ..) = packet.decompose();
+                    black_box(payload);
+                    // the header keeps the original allocation
+                    header.clear();
+                    buffer = header;
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("bytes_mut_decompose", payload_len),
+            &received,
+            |b, received| {
+                let mut buffer = BytesMut::with_capacity(received.len());
+                b.iter(|| {
+                    buffer.extend_from_slice(received);
+                    let packet = HdpPacket::new_recv(buffer.split(), addr, LOCAL_PORT);
+                    black_box(packet.decompose());
+                    // reclaims the allocation once both halves are dropped
+                    buffer.reserve(received.len());
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("vec_decompose_header", payload_len),
+            &received,
+            |b, received| {
+                let mut buffer = Vec::with_capacity(received.len());
+                b.iter(|| {
+                    buffer.extend_from_slice(received);
+                    let packet = HdpPacket::new_recv(std::mem::take(&mut buffer), addr, LOCAL_PORT);
+                    let (header, mut payload, ..) = packet.decompose_header().unwrap();
+                    black_box((header, &payload));
+                    payload.clear();
+                    buffer = payload;
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_recv);
+criterion_main!(benches);
@@ -6,7 +6,7 @@ use lazy_static::lazy_static;
 // Note: these values can each be up to 1024 in size, but, to be safe, we fix the upper
 // bound to 255 (u8::MAX) to ensure that the values fit inside the u32 bit packer
 pub const MAJOR_VERSION: u8 = 0;
-pub const MINOR_VERSION: u8 = 3;
+pub const MINOR_VERSION: u8 = 4;
 pub const PATCH_VERSION: u8 = 0;
 
 lazy_static! {
@@ -16,6 +16,12 @@ lazy_static! {
             .unwrap();
 }
 
+/// The first protocol version whose packets carry a trailing CRC32 of the header on the primary stream.
+/// Packets from earlier versions are framed without one
+pub const HEADER_CHECKSUM_VERSION: (u8, u8, u8) = (0, 4, 0);
+/// The length of the trailing header checksum
+pub const HDP_HEADER_CHECKSUM_LEN: usize = 4;
+
 /// by default, the UDP is not initialized
 pub const UDP_MODE: UdpMode = UdpMode::Disabled;
 /// Setting this option to zero will imply an RST gets sent once close() is called. This will lead to packets possibly being undelivered
@@ -54,6 +60,8 @@ pub const INITIAL_RECONNECT_LOCKOUT_TIME_NS: i64 = NANOSECONDS_PER_SECOND;
 pub const KEEP_ALIVE_INTERVAL_MS: u64 = 60000 * 15; // every 15 minutes
 /// The keep alive max interval
 pub const KEEP_ALIVE_TIMEOUT_NS: i64 = (KEEP_ALIVE_INTERVAL_MS * 3 * 1_000_000) as i64;
+/// The longest keep alive timeout a node accepts from the adjacent node
+pub const MAX_KEEP_ALIVE_TIMEOUT_NS: i64 = KEEP_ALIVE_TIMEOUT_NS * 8;
 // 1ms = 1 million ns
 /// Timeout for the drill update subroutine
 pub const DRILL_UPDATE_TIMEOUT_NS: i64 = KEEP_ALIVE_TIMEOUT_NS;
@@ -78,6 +86,10 @@ pub const GROUP_EXPIRE_TIME_MS: std::time::Duration = std::time::Duration::from_
 pub const DO_REGISTER_EXPIRE_TIME_MS: std::time::Duration = std::time::Duration::from_millis(10000);
 /// After this time, the connect state is invalidated
 pub const DO_CONNECT_EXPIRE_TIME_MS: std::time::Duration = std::time::Duration::from_millis(8000);
+/// After waiting this long on a single stage, an in-flight re-key is cancelled and rolled back
+pub const REKEY_STALL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+/// How often in-flight re-keys are checked for stalling
+pub const REKEY_STALL_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
 /// After this timeout,
 pub const UPNP_FIREWALL_LOAD_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(1500);
 pub const MULTIPORT_START: u16 = 25000;
@@ -104,6 +116,9 @@ pub const HOLE_PUNCH_SYNC_TIME_MULTIPLIER: f64 = 2.0f64;
 pub const TIMED_TICKET_LIFETIME: std::time::Duration = std::time::Duration::from_secs(30);
 /// the preconnect + connect stage will be limited by this duration
 pub const LOGIN_EXPIRATION_TIME: std::time::Duration = std::time::Duration::from_secs(20);
+/// A session resumed from a ticket ends unless the client rekeys it within this window. Until then,
+/// the session reuses the ratchet of the previous session, relying on its persisted nonce counter
+pub const RESUMED_SESSION_REKEY_DEADLINE: std::time::Duration = std::time::Duration::from_secs(15);
 /// Every 30 minutes, resync the clocks. This was to fix bugs related to long-lasting connections and reconnections
 pub const NTP_RESYNC_FREQUENCY: std::time::Duration = std::time::Duration::from_secs(60 * 30);
 ///
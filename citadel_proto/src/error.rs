@@ -1,8 +1,12 @@
+use crate::proto::misc::protocol_version::ProtocolVersionRange;
+use crate::proto::remote::Ticket;
 use citadel_crypt::misc::CryptError;
 use citadel_user::misc::AccountError;
+use serde::{Deserialize, Serialize};
 use std::error::Error;
 use std::fmt::Formatter;
 use std::fmt::{Debug, Display};
+use std::time::Duration;
 use tokio::sync::mpsc::error::SendError;
 
 /// The basic error type for this crate
@@ -23,6 +27,12 @@ pub enum NetworkError {
     Generic(String),
     ///
     ProperShutdown,
+    /// The file is `size` bytes, which is over the `max` bytes that may be transferred
+    FileTooLarge { size: u64, max: u64 },
+    /// An inbound handshake packet is `size` bytes, which is over the `max` bytes its stage allows
+    HandshakePayloadTooLarge { size: usize, max: usize },
+    /// The connect attempt was rejected for the given reason
+    Connect(ConnectError),
 }
 
 impl Error for NetworkError {}
@@ -49,6 +59,15 @@ impl NetworkError {
             NetworkError::InvalidRequest(err) => (*err).to_string(),
             NetworkError::InvalidPacket(err) => (*err).to_string(),
             NetworkError::ProperShutdown => "Proper shutdown called".to_string(),
+            NetworkError::FileTooLarge { size, max } => {
+                format!("File is {size} bytes, but at most {max} bytes may be transferred")
+            }
+            NetworkError::HandshakePayloadTooLarge { size, max } => {
+                format!(
+                    "Handshake packet is {size} bytes, but its stage allows at most {max} bytes"
+                )
+            }
+            NetworkError::Connect(err) => err.to_string(),
         }
     }
 
@@ -69,6 +88,9 @@ impl NetworkError {
             NetworkError::ProperShutdown => {
                 format!("{:?}", NetworkError::ProperShutdown)
             }
+            err @ (NetworkError::FileTooLarge { .. }
+            | NetworkError::HandshakePayloadTooLarge { .. }) => err.to_msg(),
+            NetworkError::Connect(err) => err.to_string(),
         }
     }
 
@@ -103,7 +125,16 @@ impl From<anyhow::Error> for NetworkError {
 
 impl From<CryptError> for NetworkError {
     fn from(err: CryptError) -> Self {
-        Self::Generic(err.into_string())
+        match err {
+            CryptError::ObjectTooLarge { size, max } => Self::FileTooLarge { size, max },
+            err => Self::Generic(err.into_string()),
+        }
+    }
+}
+
+impl From<ConnectError> for NetworkError {
+    fn from(err: ConnectError) -> Self {
+        NetworkError::Connect(err)
     }
 }
 
@@ -112,3 +143,109 @@ impl From<std::io::Error> for NetworkError {
         NetworkError::Generic(err.to_string())
     }
 }
+
+/// Errors that may occur when processing a connect attempt
+#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq)]
+pub enum ConnectError {
+    /// The source has failed to authenticate too many times. Further attempts will be rejected
+    /// until `self.0` has elapsed
+    TooManyAttempts(Duration),
+    /// Too many connections are mid-handshake. A slot is expected to free once `self.0` has elapsed
+    TooManyPendingHandshakes(Duration),
+    /// The CID is not registered to this node, and the node requires prior registration
+    UnknownUser(u64),
+    /// The account has been deactivated, and may not connect until reactivated
+    AccountDeactivated(u64),
+    /// The identity presented by peer `self.0` does not match the fingerprint pinned for it
+    FingerprintMismatch(u64),
+    /// No protocol version is supported by both nodes. `self.0` is the local range, and `self.1`
+    /// is the range advertised by the adjacent node
+    IncompatibleProtocolVersions(ProtocolVersionRange, ProtocolVersionRange),
+    /// CID `self.0` already holds the `self.1` sessions it may hold on this node at once
+    SessionLimitReached(u64, u32),
+}
+
+impl Error for ConnectError {}
+
+impl Debug for ConnectError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        <Self as Display>::fmt(self, f)
+    }
+}
+
+impl Display for ConnectError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectError::TooManyAttempts(retry_after) => write!(
+                f,
+                "Too many failed connect attempts. Retry after {}ms",
+                retry_after.as_millis()
+            ),
+            ConnectError::TooManyPendingHandshakes(retry_after) => write!(
+                f,
+                "Too many handshakes are pending. Retry after {}ms",
+                retry_after.as_millis()
+            ),
+            ConnectError::UnknownUser(cid) => {
+                write!(
+                    f,
+                    "CID {cid} is not registered to this node, which requires prior registration"
+                )
+            }
+            ConnectError::AccountDeactivated(cid) => {
+                write!(f, "The account of CID {cid} has been deactivated")
+            }
+            ConnectError::FingerprintMismatch(cid) => {
+                write!(
+                    f,
+                    "The identity of peer {cid} does not match its pinned fingerprint"
+                )
+            }
+            ConnectError::IncompatibleProtocolVersions(local, remote) => {
+                write!(
+                    f,
+                    "No protocol version is supported by both nodes. Local versions: {local} | Adjacent versions: {remote}"
+                )
+            }
+            ConnectError::SessionLimitReached(cid, max) => {
+                write!(
+                    f,
+                    "CID {cid} already holds the maximum of {max} concurrent sessions"
+                )
+            }
+        }
+    }
+}
+
+/// Errors that may occur while awaiting a file transfer
+#[derive(Clone, Eq, PartialEq)]
+pub enum TransferError {
+    /// The transfer failed for the given reason
+    Failed(String),
+    /// The transfer's status stream ended before the transfer finished
+    StreamDied,
+    /// No transfer is tracked under the given ticket
+    UnknownTicket(Ticket),
+}
+
+impl Error for TransferError {}
+
+impl Debug for TransferError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        <Self as Display>::fmt(self, f)
+    }
+}
+
+impl Display for TransferError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransferError::Failed(reason) => write!(f, "Transfer failed: {reason}"),
+            TransferError::StreamDied => {
+                write!(f, "Transfer status stream ended before completion")
+            }
+            TransferError::UnknownTicket(ticket) => {
+                write!(f, "No transfer is tracked under ticket {ticket}")
+            }
+        }
+    }
+}
@@ -1,5 +1,8 @@
-use crate::error::NetworkError;
-use crate::proto::node_result::NodeResult;
+use crate::error::{NetworkError, TransferError};
+use crate::kernel::presence_tracker::PresenceTracker;
+use crate::kernel::transfer_tracker::TransferTracker;
+use crate::proto::node_result::{InternalServerError, NodeResult, ObjectTransferHandle, PeerEvent};
+use crate::proto::peer::peer_layer::PeerSignal;
 use crate::proto::remote::Ticket;
 use citadel_io::Mutex;
 use futures::{Future, Stream};
@@ -11,6 +14,8 @@ use std::task::{Context, Poll};
 #[derive(Default)]
 pub struct KernelAsyncCallbackHandler {
     pub inner: Arc<Mutex<KernelAsyncCallbackHandlerInner>>,
+    pub transfers: TransferTracker,
+    pub presence: PresenceTracker,
 }
 
 #[derive(Default)]
@@ -96,9 +101,29 @@ impl KernelAsyncCallbackHandler {
 
     pub async fn on_message_received<F: Future<Output = Result<(), NetworkError>>>(
         &self,
-        result: NodeResult,
+        mut result: NodeResult,
         default: impl FnOnce(NodeResult) -> F,
     ) -> Result<(), NetworkError> {
+        match &mut result {
+            NodeResult::ObjectTransferHandle(ObjectTransferHandle { ticket, handle }) => {
+                self.transfers.track(*ticket, handle)
+            }
+
+            NodeResult::InternalServerError(InternalServerError {
+                ticket_opt: Some(ticket),
+                message,
+            }) => self
+                .transfers
+                .finish(*ticket, Err(TransferError::Failed(message.clone()))),
+
+            NodeResult::PeerEvent(PeerEvent {
+                event: PeerSignal::PresenceChanged(peer_cid, presence),
+                ..
+            }) => self.presence.notify(*peer_cid, *presence),
+
+            _ => {}
+        }
+
         match self.maybe_notify(result) {
             None => Ok(()),
 
@@ -121,6 +146,8 @@ impl Clone for KernelAsyncCallbackHandler {
     fn clone(&self) -> Self {
         Self {
             inner: self.inner.clone(),
+            transfers: self.transfers.clone(),
+            presence: self.presence.clone(),
         }
     }
 }
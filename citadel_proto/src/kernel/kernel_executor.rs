@@ -10,6 +10,7 @@ use crate::error::NetworkError;
 use crate::kernel::kernel_communicator::KernelAsyncCallbackHandler;
 use crate::kernel::kernel_trait::NetKernel;
 use crate::kernel::{KernelExecutorArguments, KernelExecutorSettings, RuntimeFuture};
+use crate::proto::misc::effective_config::EffectiveConfig;
 use crate::proto::node::HdpServer;
 use crate::proto::node_result::NodeResult;
 use crate::proto::outbound_sender::{unbounded, UnboundedReceiver};
@@ -43,7 +44,24 @@ impl<K: NetKernel> KernelExecutor<K> {
             client_config,
             kernel_executor_settings,
             stun_servers,
+            header_obfuscation,
+            peer_identity_settings,
+            supported_protocol_versions,
+            fault_injector,
+            keep_alive_policy,
         } = args;
+        let effective_config = EffectiveConfig::new(
+            hypernode_type,
+            &account_manager,
+            &underlying_proto,
+            client_config.is_some(),
+            &kernel_executor_settings,
+            stun_servers.as_ref(),
+            header_obfuscation,
+            &peer_identity_settings,
+            supported_protocol_versions,
+            keep_alive_policy,
+        );
         let (server_to_kernel_tx, server_to_kernel_rx) = unbounded();
         let (server_shutdown_alerter_tx, server_shutdown_alerter_rx) =
             tokio::sync::oneshot::channel();
@@ -56,6 +74,12 @@ impl<K: NetKernel> KernelExecutor<K> {
             underlying_proto,
             client_config,
             stun_servers,
+            header_obfuscation,
+            peer_identity_settings,
+            supported_protocol_versions,
+            fault_injector,
+            keep_alive_policy,
+            effective_config,
         )
         .await
         .map_err(|err| NetworkError::Generic(err.to_string()))?;
@@ -8,6 +8,10 @@ use tokio::runtime::Handle;
 use crate::error::NetworkError;
 use crate::macros::ContextRequirements;
 use crate::prelude::ServerUnderlyingProtocol;
+use crate::proto::misc::fault_injection::FaultInjector;
+use crate::proto::misc::keep_alive_policy::KeepAlivePolicy;
+use crate::proto::misc::protocol_version::ProtocolVersionRange;
+use crate::proto::peer::peer_identity::PeerIdentitySettings;
 
 /// for handling easy asynchronous callbacks
 pub mod kernel_communicator;
@@ -16,6 +20,10 @@ pub mod kernel_communicator;
 pub mod kernel_executor;
 /// The entity which interfaces the lower-level [HdpServer] and the higher-level API
 pub mod kernel_trait;
+/// For streaming changes in the presence of peers
+pub mod presence_tracker;
+/// For awaiting file transfers by ticket
+pub mod transfer_tracker;
 
 pub trait RuntimeFuture: Future<Output = Result<(), NetworkError>> + ContextRequirements {}
 impl<T: Future<Output = Result<(), NetworkError>> + ContextRequirements> RuntimeFuture for T {}
@@ -23,7 +31,7 @@ impl<T: Future<Output = Result<(), NetworkError>> + ContextRequirements> Runtime
 #[derive(Default, Debug, Clone, Eq, PartialEq)]
 /// Used for fine-tuning parameters within the [`KernelExecutor`]
 pub struct KernelExecutorSettings {
-    max_concurrency: Option<usize>,
+    pub(crate) max_concurrency: Option<usize>,
 }
 
 impl KernelExecutorSettings {
@@ -44,4 +52,18 @@ pub struct KernelExecutorArguments<K> {
     pub client_config: Option<Arc<ClientConfig>>,
     pub kernel_executor_settings: KernelExecutorSettings,
     pub stun_servers: Option<Vec<String>>,
+    /// If true, the fixed-layout packet headers on the primary stream are obfuscated. Both the
+    /// client and the server must enable this setting
+    pub header_obfuscation: bool,
+    /// The identity proven to peers, and the fingerprints expected of peers, when establishing
+    /// P2P channels
+    pub peer_identity_settings: PeerIdentitySettings,
+    /// The protocol versions this node is able to speak. During the pre-connect stage, the highest
+    /// version supported by both the client and the server is selected
+    pub supported_protocol_versions: ProtocolVersionRange,
+    /// Degrades the outbound primary stream of each session for resilience testing. An injector
+    /// can only be constructed when the `localhost-testing` feature is enabled
+    pub fault_injector: Option<FaultInjector>,
+    /// How often keep alives are sent over each session, and how long sessions may stay silent
+    pub keep_alive_policy: KeepAlivePolicy,
 }
@@ -0,0 +1,140 @@
+use crate::proto::peer::peer_layer::Presence;
+use citadel_io::Mutex;
+use futures::Stream;
+use std::collections::{HashMap, HashSet};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/// Routes changes in the presence of peers, as announced by the server, to each subscription
+/// watching the affected peer
+#[derive(Default, Clone)]
+pub struct PresenceTracker {
+    inner: Arc<Mutex<PresenceTrackerInner>>,
+}
+
+#[derive(Default)]
+struct PresenceTrackerInner {
+    next_id: u64,
+    subscribers: HashMap<u64, PresenceSubscriber>,
+}
+
+struct PresenceSubscriber {
+    peers: HashSet<u64>,
+    tx: UnboundedSender<(u64, Presence)>,
+}
+
+/// A stream of `(peer_cid, presence)` updates for a set of peers, which may be adjusted while the
+/// stream is in use. Dropping the subscription stops the updates
+pub struct PresenceSubscription {
+    rx: UnboundedReceiver<(u64, Presence)>,
+    tracker: PresenceTracker,
+    id: u64,
+}
+
+impl PresenceTracker {
+    /// Creates a subscription receiving the updates for each peer in `peers`
+    pub(crate) fn subscribe(&self, peers: &[u64]) -> PresenceSubscription {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut this = self.inner.lock();
+        let id = this.next_id;
+        this.next_id += 1;
+        let _ = this.subscribers.insert(
+            id,
+            PresenceSubscriber {
+                peers: peers.iter().copied().collect(),
+                tx,
+            },
+        );
+
+        PresenceSubscription {
+            rx,
+            tracker: self.clone(),
+            id,
+        }
+    }
+
+    /// Delivers the change in presence of `peer_cid` to every subscription watching it
+    pub(crate) fn notify(&self, peer_cid: u64, presence: Presence) {
+        let mut this = self.inner.lock();
+        // subscriptions whose receiver dropped are removed
+        this.subscribers.retain(|_, subscriber| {
+            !subscriber.peers.contains(&peer_cid)
+                || subscriber.tx.send((peer_cid, presence)).is_ok()
+        });
+    }
+
+    fn update(&self, id: u64, f: impl FnOnce(&mut HashSet<u64>)) {
+        if let Some(subscriber) = self.inner.lock().subscribers.get_mut(&id) {
+            f(&mut subscriber.peers)
+        }
+    }
+
+    fn unsubscribe(&self, id: u64) {
+        let _ = self.inner.lock().subscribers.remove(&id);
+    }
+}
+
+impl PresenceSubscription {
+    /// Begins watching each peer in `peers`
+    pub fn add(&self, peers: &[u64]) {
+        self.tracker
+            .update(self.id, |watched| watched.extend(peers.iter().copied()))
+    }
+
+    /// Stops watching each peer in `peers`. Updates already queued are still delivered
+    pub fn remove(&self, peers: &[u64]) {
+        self.tracker.update(self.id, |watched| {
+            for peer in peers {
+                let _ = watched.remove(peer);
+            }
+        })
+    }
+}
+
+impl Stream for PresenceSubscription {
+    type Item = (u64, Presence);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl Drop for PresenceSubscription {
+    fn drop(&mut self) {
+        self.tracker.unsubscribe(self.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::kernel::presence_tracker::PresenceTracker;
+    use crate::proto::peer::peer_layer::Presence;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn routes_updates_to_watchers() {
+        let tracker = PresenceTracker::default();
+        let mut first = tracker.subscribe(&[10, 20]);
+        let mut second = tracker.subscribe(&[20]);
+
+        tracker.notify(10, Presence::Online);
+        tracker.notify(20, Presence::Online);
+        tracker.notify(30, Presence::Online);
+        assert_eq!(first.next().await, Some((10, Presence::Online)));
+        assert_eq!(first.next().await, Some((20, Presence::Online)));
+        assert_eq!(second.next().await, Some((20, Presence::Online)));
+
+        first.add(&[30]);
+        first.remove(&[10]);
+        tracker.notify(10, Presence::Offline);
+        tracker.notify(30, Presence::Offline);
+        assert_eq!(first.next().await, Some((30, Presence::Offline)));
+
+        drop(second);
+        assert_eq!(tracker.inner.lock().subscribers.len(), 1);
+        drop(first);
+        assert!(tracker.inner.lock().subscribers.is_empty());
+    }
+}
@@ -0,0 +1,261 @@
+use crate::error::TransferError;
+use crate::proto::remote::Ticket;
+use citadel_io::Mutex;
+use citadel_user::backend::utils::{ObjectTransferHandler, ObjectTransferStatus};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// The number of finished transfers whose outcomes are kept for late callers of
+/// [`NodeRemote::await_transfer`](crate::prelude::NodeRemote::await_transfer)
+const MAX_FINISHED_TRANSFERS: usize = 1024;
+
+/// The outcome of a successfully completed transfer
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TransferResult {
+    /// The ticket of the request that initiated the transfer
+    pub ticket: Ticket,
+    /// The CID of the sending node
+    pub source: u64,
+    /// The CID of the receiving node
+    pub receiver: u64,
+}
+
+type TransferOutcome = Result<TransferResult, TransferError>;
+
+/// Records the outcome of each transfer by ticket, allowing callers to await a transfer
+/// separately from the request that initiated it
+#[derive(Default, Clone)]
+pub struct TransferTracker {
+    inner: Arc<Mutex<TransferTrackerInner>>,
+}
+
+#[derive(Default)]
+struct TransferTrackerInner {
+    transfers: HashMap<Ticket, watch::Sender<Option<TransferOutcome>>>,
+    finished: VecDeque<Ticket>,
+}
+
+impl TransferTracker {
+    /// Begins tracking `ticket`, so that it may be awaited before its transfer starts. Does nothing
+    /// if `ticket` is already tracked
+    pub(crate) fn register(&self, ticket: Ticket) {
+        let _ = self
+            .inner
+            .lock()
+            .transfers
+            .entry(ticket)
+            .or_insert_with(|| watch::channel(None).0);
+    }
+
+    /// Stops tracking `ticket` if its transfer has not finished, e.g., since the request that
+    /// would have initiated it was never sent
+    pub(crate) fn unregister(&self, ticket: Ticket) {
+        let mut this = self.inner.lock();
+        if this
+            .transfers
+            .get(&ticket)
+            .map(|tx| tx.borrow().is_none())
+            .unwrap_or(false)
+        {
+            let _ = this.transfers.remove(&ticket);
+        }
+    }
+
+    /// Observes the statuses of `handle`, recording the outcome once the transfer finishes.
+    /// Statuses are still delivered to the owner of `handle`
+    pub(crate) fn track(&self, ticket: Ticket, handle: &mut ObjectTransferHandler) {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut statuses = handle.replace_status_stream(rx);
+        let result = TransferResult {
+            ticket,
+            source: handle.source,
+            receiver: handle.receiver,
+        };
+        let this = self.clone();
+        self.register(ticket);
+
+        let task = async move {
+            let mut outcome = None;
+            while let Some(status) = statuses.recv().await {
+                if outcome.is_none() {
+                    outcome = match &status {
+                        ObjectTransferStatus::TransferComplete
+                        | ObjectTransferStatus::ReceptionComplete => Some(Ok(result.clone())),
+                        ObjectTransferStatus::Fail(reason) => {
+                            Some(Err(TransferError::Failed(reason.clone())))
+                        }
+                        _ => None,
+                    };
+
+                    if let Some(outcome) = outcome.clone() {
+                        this.finish(ticket, outcome);
+                    }
+                }
+
+                // the owner may have dropped the handle
+                let _ = tx.send(status);
+            }
+
+            if outcome.is_none() {
+                this.finish(ticket, Err(TransferError::StreamDied));
+            }
+        };
+
+        drop(citadel_io::spawn(task));
+    }
+
+    /// Records the outcome for `ticket`. Only the first outcome recorded is kept, and outcomes for
+    /// tickets that are not tracked are ignored
+    pub(crate) fn finish(&self, ticket: Ticket, outcome: TransferOutcome) {
+        let mut this = self.inner.lock();
+        let mut outcome = Some(outcome);
+        let updated = match this.transfers.get(&ticket) {
+            Some(tx) => tx.send_if_modified(|current| {
+                if current.is_none() {
+                    *current = outcome.take();
+                    true
+                } else {
+                    false
+                }
+            }),
+            None => false,
+        };
+
+        if updated {
+            this.finished.push_back(ticket);
+            if this.finished.len() > MAX_FINISHED_TRANSFERS {
+                if let Some(oldest) = this.finished.pop_front() {
+                    let _ = this.transfers.remove(&oldest);
+                }
+            }
+        }
+    }
+
+    /// Resolves once the transfer identified by `ticket` finishes. If it already finished,
+    /// the recorded outcome is returned immediately. Returns [`TransferError::UnknownTicket`] if
+    /// `ticket` is not tracked, or if its outcome was evicted
+    pub(crate) async fn wait(&self, ticket: Ticket) -> TransferOutcome {
+        let mut rx = match self.inner.lock().transfers.get(&ticket) {
+            Some(tx) => tx.subscribe(),
+            None => return Err(TransferError::UnknownTicket(ticket)),
+        };
+        loop {
+            if let Some(outcome) = rx.borrow_and_update().clone() {
+                return outcome;
+            }
+
+            if rx.changed().await.is_err() {
+                // the entry was evicted; the last value is still readable
+                return rx
+                    .borrow()
+                    .clone()
+                    .unwrap_or(Err(TransferError::StreamDied));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::TransferError;
+    use crate::kernel::transfer_tracker::{TransferResult, TransferTracker};
+    use crate::proto::remote::Ticket;
+    use citadel_user::backend::utils::{
+        ObjectTransferHandler, ObjectTransferOrientation, ObjectTransferStatus,
+    };
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn tracked_transfer_completes() {
+        let tracker = TransferTracker::default();
+        let ticket = Ticket(1);
+        let (mut handle, tx) =
+            ObjectTransferHandler::new(10, 20, ObjectTransferOrientation::Sender, None, false);
+        tracker.track(ticket, &mut handle);
+
+        let waiter = tokio::spawn({
+            let tracker = tracker.clone();
+            async move { tracker.wait(ticket).await }
+        });
+
+        tx.send(ObjectTransferStatus::TransferBeginning).unwrap();
+        tx.send(ObjectTransferStatus::TransferComplete).unwrap();
+        drop(tx);
+
+        let expected = TransferResult {
+            ticket,
+            source: 10,
+            receiver: 20,
+        };
+        assert_eq!(waiter.await.unwrap(), Ok(expected.clone()));
+        // the owner of the handle still receives every status
+        assert_eq!(handle.count().await, 2);
+        // awaiting an already-finished transfer returns the recorded outcome
+        assert_eq!(tracker.wait(ticket).await, Ok(expected));
+    }
+
+    #[tokio::test]
+    async fn failures_are_recorded() {
+        let tracker = TransferTracker::default();
+        let (mut handle, tx) =
+            ObjectTransferHandler::new(10, 20, ObjectTransferOrientation::Sender, None, false);
+        tracker.track(Ticket(1), &mut handle);
+        tx.send(ObjectTransferStatus::Fail("declined".into()))
+            .unwrap();
+        assert_eq!(
+            tracker.wait(Ticket(1)).await,
+            Err(TransferError::Failed("declined".into()))
+        );
+
+        let (mut handle, tx) =
+            ObjectTransferHandler::new(10, 20, ObjectTransferOrientation::Sender, None, false);
+        tracker.track(Ticket(2), &mut handle);
+        drop(tx);
+        assert_eq!(
+            tracker.wait(Ticket(2)).await,
+            Err(TransferError::StreamDied)
+        );
+
+        tracker.register(Ticket(3));
+        tracker.finish(Ticket(3), Err(TransferError::Failed("rejected".into())));
+        tracker.finish(Ticket(3), Err(TransferError::StreamDied));
+        assert_eq!(
+            tracker.wait(Ticket(3)).await,
+            Err(TransferError::Failed("rejected".into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_tickets_are_rejected() {
+        let tracker = TransferTracker::default();
+        assert_eq!(
+            tracker.wait(Ticket(1)).await,
+            Err(TransferError::UnknownTicket(Ticket(1)))
+        );
+        // neither waiting nor finishing begins tracking the ticket
+        tracker.finish(Ticket(1), Err(TransferError::StreamDied));
+        assert_eq!(
+            tracker.wait(Ticket(1)).await,
+            Err(TransferError::UnknownTicket(Ticket(1)))
+        );
+        assert!(tracker.inner.lock().transfers.is_empty());
+
+        // a registered ticket may be awaited before its transfer begins
+        tracker.register(Ticket(2));
+        let waiter = tokio::spawn({
+            let tracker = tracker.clone();
+            async move { tracker.wait(Ticket(2)).await }
+        });
+        tokio::task::yield_now().await;
+        tracker.finish(Ticket(2), Err(TransferError::StreamDied));
+        assert_eq!(waiter.await.unwrap(), Err(TransferError::StreamDied));
+
+        tracker.register(Ticket(3));
+        tracker.unregister(Ticket(3));
+        assert_eq!(
+            tracker.wait(Ticket(3)).await,
+            Err(TransferError::UnknownTicket(Ticket(3)))
+        );
+    }
+}
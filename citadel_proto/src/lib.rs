@@ -349,25 +349,44 @@ pub mod prelude {
     pub use citadel_crypt::argon::autotuner::calculate_optimal_argon_params;
     pub use citadel_crypt::fcm::keys::FcmKeys;
     pub use citadel_crypt::secure_buffer::{sec_bytes::SecBuffer, sec_string::SecString};
+    pub use citadel_crypt::toolset::MAX_HYPER_RATCHETS_IN_MEMORY;
     pub use citadel_pqcrypto::algorithm_dictionary::{
         AlgorithmsExt, EncryptionAlgorithm, KemAlgorithm, SigAlgorithm,
     };
+    pub use citadel_user::account_features::FeatureSet;
     pub use citadel_user::account_manager::AccountManager;
     pub use citadel_user::auth::proposed_credentials::ProposedCredentials;
     pub use citadel_user::backend::BackendType;
+    pub use citadel_user::external_services::apns::{ApnsEnvironment, ApnsKeys};
     pub use citadel_user::external_services::{RtdbConfig, ServicesConfig, ServicesObject};
     pub use citadel_user::prelude::{ConnectProtocol, UserIdentifier};
-    pub use citadel_user::server_misc_settings::ServerMiscSettings;
+    pub use citadel_user::server_misc_settings::{
+        HandshakePayloadLimits, PendingHandshakeOverflow, ServerMiscSettings,
+    };
 
-    pub use crate::error::NetworkError;
+    pub use crate::error::{ConnectError, NetworkError, TransferError};
     pub use crate::functional::*;
     pub use crate::kernel::RuntimeFuture;
     pub use crate::kernel::{
-        kernel_executor::KernelExecutor, kernel_trait::NetKernel, KernelExecutorSettings,
+        kernel_executor::KernelExecutor, kernel_trait::NetKernel,
+        presence_tracker::PresenceSubscription, transfer_tracker::TransferResult,
+        KernelExecutorSettings,
+    };
+    pub use crate::proto::misc::effective_config::{EffectiveConfig, EffectiveServerMiscSettings};
+    pub use crate::proto::misc::fault_injection::{
+        FaultInjectionStats, FaultInjector, FaultProfile,
     };
+    pub use crate::proto::misc::keep_alive_policy::KeepAlivePolicy;
     pub use crate::proto::misc::panic_future::ExplicitPanicFuture;
+    pub use crate::proto::misc::pending_handshakes::HandshakeMetrics;
+    pub use crate::proto::misc::protocol_version::{ProtocolVersion, ProtocolVersionRange};
+    pub use crate::proto::misc::session_limit::{SessionLimit, MAX_SESSIONS_PER_CID};
     pub use crate::proto::misc::session_security_settings::{
-        SessionSecuritySettings, SessionSecuritySettingsBuilder,
+        CompressionCodec, HeaderProtection, RatchetVariant, SessionSecuritySettings,
+        SessionSecuritySettingsBuilder, SupportedAlgorithms,
+    };
+    pub use crate::proto::misc::session_state_dump::{
+        CryptoStateDump, SessionStateDump, VirtualConnectionStateDump,
     };
     pub use crate::proto::misc::underlying_proto::ServerUnderlyingProtocol;
     pub use crate::proto::node::ConnectMode;
@@ -382,27 +401,40 @@ pub mod prelude {
     };
     pub use crate::proto::peer::message_group::MessageGroupKey;
     pub use crate::proto::peer::message_group::{GroupType, MessageGroupOptions};
+    pub use crate::proto::peer::peer_identity::{
+        PeerFingerprint, PeerIdentity, PeerIdentitySettings,
+    };
     pub use crate::proto::peer::peer_layer::HypernodeConnectionType;
     pub use crate::proto::peer::peer_layer::PeerResponse;
-    pub use crate::proto::peer::peer_layer::{PeerConnectionType, PeerSignal, UdpMode};
+    pub use crate::proto::peer::peer_layer::{PeerConnectionType, PeerSignal, Presence, UdpMode};
     pub use crate::proto::remote::Ticket;
+    pub use crate::proto::session::SessionState;
     pub use crate::proto::state_container::VirtualTargetType;
+    pub use crate::proto::state_subcontainers::rekey_container::{PendingReKeyStatus, ReKeyStage};
     pub use crate::re_imports::{async_trait, NodeType};
     pub use citadel_user::backend::utils::{
-        ObjectTransferHandler, ObjectTransferOrientation, ObjectTransferStatus,
-        VirtualObjectMetadata,
+        ObjectTransferHandler, ObjectTransferOrientation, ObjectTransferStats,
+        ObjectTransferStatus, VirtualObjectMetadata,
     };
     pub use citadel_user::serialization::SyncIO;
 
+    #[doc(hidden)]
+    pub use crate::proto::misc::handshake_limits::PrimaryStreamCodec;
     #[doc(hidden)]
     pub use crate::proto::misc::net::{safe_split_stream, GenericNetworkStream};
     pub use crate::proto::node_request::*;
     pub use crate::proto::node_result::*;
+    /// Exposed for the benchmarks
+    #[cfg(feature = "bench")]
+    #[doc(hidden)]
+    pub use crate::proto::packet::{HdpBuffer, HdpHeader, HdpPacket};
     pub use crate::proto::remote::*;
 
     pub use citadel_crypt::misc::TransferType;
     pub use citadel_crypt::prelude::SecurityLevel;
-    pub use citadel_crypt::streaming_crypt_scrambler::{BytesSource, ObjectSource};
+    pub use citadel_crypt::streaming_crypt_scrambler::{
+        max_file_transfer_size, BytesSource, ObjectSource,
+    };
     pub use citadel_user::misc::{prepare_virtual_path, validate_virtual_path};
 }
 
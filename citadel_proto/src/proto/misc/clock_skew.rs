@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+/// Each accepted packet moves the estimated offset this fraction of the way towards its own skew,
+/// so that a single delayed packet only nudges it
+const OFFSET_SMOOTHING: i64 = 8;
+
+/// Drops inbound packets whose header timestamp strays too far from the local clock, which bounds
+/// how long a captured packet may be replayed. Optionally, the skew is measured from the clock
+/// offset estimated for the session, tolerating an adjacent node whose clock is consistently off
+pub struct ClockSkewGuard {
+    max_skew_ns: Option<i64>,
+    normalize_offset: bool,
+    offset_ns: Option<i64>,
+}
+
+impl ClockSkewGuard {
+    pub fn new(max_clock_skew: Option<Duration>, normalize_offset: bool) -> Self {
+        Self {
+            max_skew_ns: max_clock_skew
+                .map(|max_skew| max_skew.as_nanos().min(i64::MAX as u128) as i64),
+            normalize_offset,
+            offset_ns: None,
+        }
+    }
+
+    /// Returns false if the packet stamped with `timestamp_ns` must be dropped. Unstamped
+    /// packets, such as UDP and hole punch packets, carry a timestamp of zero and always pass
+    pub fn check(&mut self, timestamp_ns: i64, now_ns: i64) -> bool {
+        if timestamp_ns == 0 {
+            return true;
+        }
+
+        let skew_ns = timestamp_ns.saturating_sub(now_ns);
+        if let Some(max_skew_ns) = self.max_skew_ns {
+            let reference_ns = if self.normalize_offset {
+                self.offset_ns.unwrap_or(skew_ns)
+            } else {
+                0
+            };
+
+            if skew_ns.abs_diff(reference_ns) > max_skew_ns as u64 {
+                return false;
+            }
+        }
+
+        self.offset_ns = Some(match self.offset_ns {
+            Some(offset_ns) => offset_ns + (skew_ns - offset_ns) / OFFSET_SMOOTHING,
+            None => skew_ns,
+        });
+
+        true
+    }
+
+    /// The estimated amount the clock of the adjacent node is ahead of the local clock, including
+    /// the one-way latency. None until a stamped packet passes
+    pub fn offset_ns(&self) -> Option<i64> {
+        self.offset_ns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::misc::clock_skew::ClockSkewGuard;
+    use std::time::Duration;
+
+    const NOW: i64 = 1_700_000_000_000_000_000;
+    const SECOND: i64 = 1_000_000_000;
+
+    #[test]
+    fn drops_timestamps_outside_the_window() {
+        let mut guard = ClockSkewGuard::new(Some(Duration::from_secs(30)), false);
+
+        assert!(guard.check(NOW + 10 * SECOND, NOW));
+        assert!(guard.check(NOW - 10 * SECOND, NOW));
+        // far in the future and far in the past
+        assert!(!guard.check(NOW + 3600 * SECOND, NOW));
+        assert!(!guard.check(NOW - 3600 * SECOND, NOW));
+        assert!(!guard.check(i64::MAX, NOW));
+        assert!(!guard.check(1, NOW));
+        // unstamped packets
+        assert!(guard.check(0, NOW));
+    }
+
+    #[test]
+    fn no_window_accepts_any_timestamp() {
+        let mut guard = ClockSkewGuard::new(None, false);
+        assert!(guard.check(NOW + 3600 * SECOND, NOW));
+        assert!(guard.check(NOW - 3600 * SECOND, NOW));
+    }
+
+    #[test]
+    fn normalized_window_follows_the_session_offset() {
+        let mut guard = ClockSkewGuard::new(Some(Duration::from_secs(30)), true);
+        let offset = 3600 * SECOND;
+
+        // the adjacent clock is an hour ahead, consistently
+        for elapsed in 0..10 {
+            let now = NOW + elapsed * SECOND;
+            assert!(guard.check(now + offset, now));
+        }
+
+        assert_eq!(guard.offset_ns(), Some(offset));
+
+        // a packet replayed long after it was captured strays from the offset
+        assert!(!guard.check(NOW + offset, NOW + 120 * SECOND));
+        assert!(guard.check(NOW + 120 * SECOND + offset, NOW + 120 * SECOND));
+    }
+
+    #[test]
+    fn rejected_timestamps_leave_the_offset_untouched() {
+        let mut guard = ClockSkewGuard::new(Some(Duration::from_secs(30)), true);
+        assert!(guard.check(NOW + SECOND, NOW));
+        assert!(!guard.check(NOW + 3600 * SECOND, NOW));
+        assert_eq!(guard.offset_ns(), Some(SECOND));
+    }
+}
@@ -0,0 +1,151 @@
+use crate::proto::packet::packet_flags::cmd::primary;
+use citadel_user::server_misc_settings::{CommandRateLimit, InboundCommandLimits};
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Enforces the [`InboundCommandLimits`] of a single session using a token bucket for each
+/// limited `cmd_primary`. Commands without a limit, including keep-alives and data, always pass
+pub struct InboundCommandLimiter {
+    buckets: HashMap<u8, TokenBucket>,
+}
+
+struct TokenBucket {
+    limit: CommandRateLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: CommandRateLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            last_refill: now,
+        }
+    }
+
+    fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * self.limit.per_second as f64).min(self.limit.burst as f64);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl InboundCommandLimiter {
+    pub fn new(limits: &InboundCommandLimits) -> Self {
+        Self::new_at(limits, Instant::now())
+    }
+
+    fn new_at(limits: &InboundCommandLimits, now: Instant) -> Self {
+        let buckets = [
+            (primary::DO_PRE_CONNECT, limits.pre_connect),
+            (primary::DO_REGISTER, limits.register),
+            (primary::DO_CONNECT, limits.connect),
+            (primary::DO_DISCONNECT, limits.disconnect),
+            (primary::DO_DRILL_UPDATE, limits.drill_update),
+            (primary::DO_DEREGISTER, limits.deregister),
+            (primary::PEER_CMD, limits.peer_cmd),
+            (primary::FILE, limits.file),
+            (primary::HOLE_PUNCH, limits.hole_punch),
+        ]
+        .into_iter()
+        .filter_map(|(cmd_primary, limit)| Some((cmd_primary, TokenBucket::new(limit?, now))))
+        .collect();
+
+        Self { buckets }
+    }
+
+    /// Returns false if an inbound command of type `cmd_primary` exceeds its limit and must be
+    /// dropped
+    pub fn try_acquire(&mut self, cmd_primary: u8) -> bool {
+        self.try_acquire_at(cmd_primary, Instant::now())
+    }
+
+    fn try_acquire_at(&mut self, cmd_primary: u8, now: Instant) -> bool {
+        match self.buckets.get_mut(&cmd_primary) {
+            Some(bucket) => bucket.try_take(now),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::misc::command_rate_limit::InboundCommandLimiter;
+    use crate::proto::packet::packet_flags::cmd::primary;
+    use citadel_user::server_misc_settings::{CommandRateLimit, InboundCommandLimits};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn drill_update_flood_is_throttled() {
+        let limits = InboundCommandLimits {
+            drill_update: Some(CommandRateLimit::new(5, 2)),
+            ..Default::default()
+        };
+        let now = Instant::now();
+        let mut limiter = InboundCommandLimiter::new_at(&limits, now);
+
+        let accepted = (0..100)
+            .filter(|_| limiter.try_acquire_at(primary::DO_DRILL_UPDATE, now))
+            .count();
+        assert_eq!(accepted, 5);
+
+        // data and keep-alives continue unimpeded during the flood
+        for _ in 0..1000 {
+            assert!(limiter.try_acquire_at(primary::GROUP_PACKET, now));
+            assert!(limiter.try_acquire_at(primary::UDP, now));
+            assert!(limiter.try_acquire_at(primary::KEEP_ALIVE, now));
+        }
+
+        // so do commands without a limit
+        assert!(limiter.try_acquire_at(primary::PEER_CMD, now));
+
+        // the bucket refills over time, up to the burst
+        let later = now + Duration::from_secs(1);
+        assert!(limiter.try_acquire_at(primary::DO_DRILL_UPDATE, later));
+        assert!(limiter.try_acquire_at(primary::DO_DRILL_UPDATE, later));
+        assert!(!limiter.try_acquire_at(primary::DO_DRILL_UPDATE, later));
+
+        let much_later = later + Duration::from_secs(60);
+        let accepted = (0..100)
+            .filter(|_| limiter.try_acquire_at(primary::DO_DRILL_UPDATE, much_later))
+            .count();
+        assert_eq!(accepted, 5);
+    }
+
+    #[test]
+    fn limits_are_per_command() {
+        let limits = InboundCommandLimits {
+            drill_update: Some(CommandRateLimit::new(1, 0)),
+            peer_cmd: Some(CommandRateLimit::new(2, 0)),
+            ..Default::default()
+        };
+        let now = Instant::now();
+        let mut limiter = InboundCommandLimiter::new_at(&limits, now);
+
+        assert!(limiter.try_acquire_at(primary::DO_DRILL_UPDATE, now));
+        assert!(!limiter.try_acquire_at(primary::DO_DRILL_UPDATE, now));
+        assert!(limiter.try_acquire_at(primary::PEER_CMD, now));
+        assert!(limiter.try_acquire_at(primary::PEER_CMD, now));
+        assert!(!limiter.try_acquire_at(primary::PEER_CMD, now));
+    }
+
+    #[test]
+    fn no_limits_by_default() {
+        let mut limiter = InboundCommandLimiter::new(&InboundCommandLimits::default());
+        for _ in 0..1000 {
+            assert!(limiter.try_acquire(primary::DO_DRILL_UPDATE));
+            assert!(limiter.try_acquire(primary::PEER_CMD));
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use crate::error::ConnectError;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Tracks failed connect attempts per source address. Once a source reaches `max_failures`
+/// consecutive failures, further attempts are rejected for a cooldown window that doubles
+/// with each additional failure. A successful connect clears the source's record, and a record
+/// expires once the source has neither failed for [`FAILURE_EXPIRY`] nor remains in a cooldown
+pub struct ConnectThrottle {
+    max_failures: usize,
+    base_cooldown: Duration,
+    failure_expiry: Duration,
+    max_sources: usize,
+    sources: HashMap<IpAddr, FailureRecord>,
+}
+
+struct FailureRecord {
+    failures: usize,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// The cooldown window will not grow past 2^MAX_COOLDOWN_SHIFT times the base window
+const MAX_COOLDOWN_SHIFT: usize = 16;
+/// How long the failures of a source are remembered after its last failure
+pub const FAILURE_EXPIRY: Duration = Duration::from_secs(60 * 15);
+/// The maximum number of sources tracked at once. Past this, the source that failed least recently
+/// is forgotten, so that spraying attempts from many addresses cannot grow the map without bound
+pub const MAX_TRACKED_SOURCES: usize = 1 << 16;
+
+impl FailureRecord {
+    fn is_expired(&self, now: Instant, failure_expiry: Duration) -> bool {
+        let expires_at = self.last_failure + failure_expiry;
+        let expires_at = self
+            .locked_until
+            .map(|locked_until| locked_until.max(expires_at))
+            .unwrap_or(expires_at);
+        now >= expires_at
+    }
+}
+
+impl ConnectThrottle {
+    pub fn new(max_failures: usize, base_cooldown: Duration) -> Self {
+        Self {
+            max_failures,
+            base_cooldown,
+            failure_expiry: FAILURE_EXPIRY,
+            max_sources: MAX_TRACKED_SOURCES,
+            sources: HashMap::new(),
+        }
+    }
+
+    /// Returns an error if the source is currently in a cooldown window
+    pub fn check(&mut self, source: IpAddr) -> Result<(), ConnectError> {
+        self.check_at(source, Instant::now())
+    }
+
+    /// Records a failed attempt from the source, starting or extending the cooldown if the
+    /// threshold was reached
+    pub fn on_failure(&mut self, source: IpAddr) {
+        self.on_failure_at(source, Instant::now())
+    }
+
+    /// Clears any failures recorded for the source
+    pub fn on_success(&mut self, source: IpAddr) {
+        let _ = self.sources.remove(&source);
+    }
+
+    fn check_at(&mut self, source: IpAddr, now: Instant) -> Result<(), ConnectError> {
+        if let Some(locked_until) = self.sources.get(&source).and_then(|r| r.locked_until) {
+            if now < locked_until {
+                return Err(ConnectError::TooManyAttempts(locked_until - now));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn on_failure_at(&mut self, source: IpAddr, now: Instant) {
+        let failure_expiry = self.failure_expiry;
+        if let Some(record) = self.sources.get(&source) {
+            if record.is_expired(now, failure_expiry) {
+                let _ = self.sources.remove(&source);
+            }
+        } else if self.sources.len() >= self.max_sources {
+            self.make_room(now);
+        }
+
+        let record = self.sources.entry(source).or_insert(FailureRecord {
+            failures: 0,
+            last_failure: now,
+            locked_until: None,
+        });
+
+        record.failures += 1;
+        record.last_failure = now;
+
+        if self.max_failures != 0 && record.failures >= self.max_failures {
+            let shift = std::cmp::min(record.failures - self.max_failures, MAX_COOLDOWN_SHIFT);
+            let cooldown = self.base_cooldown * (1u32 << shift);
+            record.locked_until = Some(now + cooldown);
+            log::warn!(target: "citadel", "Source {} failed to connect {} times. Rejecting attempts for {:?}", source, record.failures, cooldown);
+        }
+    }
+
+    /// Prunes every expired record. If the map is still full, the source that failed least
+    /// recently is evicted
+    fn make_room(&mut self, now: Instant) {
+        let failure_expiry = self.failure_expiry;
+        self.sources
+            .retain(|_, record| !record.is_expired(now, failure_expiry));
+
+        if self.sources.len() >= self.max_sources {
+            if let Some(oldest) = self
+                .sources
+                .iter()
+                .min_by_key(|(_, record)| record.last_failure)
+                .map(|(source, _)| *source)
+            {
+                let _ = self.sources.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ConnectError;
+    use crate::proto::misc::connect_throttle::{ConnectThrottle, FAILURE_EXPIRY};
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::{Duration, Instant};
+
+    const SOURCE: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    const BASE: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn cooldown_after_threshold() {
+        let mut throttle = ConnectThrottle::new(3, BASE);
+        let now = Instant::now();
+
+        for _ in 0..2 {
+            throttle.on_failure_at(SOURCE, now);
+            assert!(throttle.check_at(SOURCE, now).is_ok());
+        }
+
+        throttle.on_failure_at(SOURCE, now);
+        assert_eq!(
+            throttle.check_at(SOURCE, now),
+            Err(ConnectError::TooManyAttempts(BASE))
+        );
+
+        // other sources are unaffected
+        assert!(throttle
+            .check_at(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), now)
+            .is_ok());
+
+        // after the window elapses, the source may try again
+        assert!(throttle.check_at(SOURCE, now + BASE).is_ok());
+    }
+
+    #[test]
+    fn cooldown_grows_exponentially() {
+        let mut throttle = ConnectThrottle::new(1, BASE);
+        let now = Instant::now();
+
+        throttle.on_failure_at(SOURCE, now);
+        assert_eq!(
+            throttle.check_at(SOURCE, now),
+            Err(ConnectError::TooManyAttempts(BASE))
+        );
+
+        let later = now + BASE;
+        throttle.on_failure_at(SOURCE, later);
+        assert_eq!(
+            throttle.check_at(SOURCE, later),
+            Err(ConnectError::TooManyAttempts(BASE * 2))
+        );
+
+        let later = later + BASE * 2;
+        throttle.on_failure_at(SOURCE, later);
+        assert_eq!(
+            throttle.check_at(SOURCE, later),
+            Err(ConnectError::TooManyAttempts(BASE * 4))
+        );
+    }
+
+    #[test]
+    fn success_resets_failures() {
+        let mut throttle = ConnectThrottle::new(2, BASE);
+        let now = Instant::now();
+
+        throttle.on_failure_at(SOURCE, now);
+        throttle.on_failure_at(SOURCE, now);
+        assert!(throttle.check_at(SOURCE, now).is_err());
+
+        let later = now + BASE;
+        assert!(throttle.check_at(SOURCE, later).is_ok());
+        throttle.on_success(SOURCE);
+
+        // the counter starts over, so a single failure does not re-trigger the cooldown
+        throttle.on_failure_at(SOURCE, later);
+        assert!(throttle.check_at(SOURCE, later).is_ok());
+    }
+
+    #[test]
+    fn zero_threshold_disables_throttle() {
+        let mut throttle = ConnectThrottle::new(0, BASE);
+        let now = Instant::now();
+
+        for _ in 0..100 {
+            throttle.on_failure_at(SOURCE, now);
+        }
+
+        assert!(throttle.check_at(SOURCE, now).is_ok());
+    }
+
+    #[test]
+    fn failures_expire() {
+        let mut throttle = ConnectThrottle::new(2, BASE);
+        let now = Instant::now();
+
+        // a single failure long ago no longer counts towards the threshold
+        throttle.on_failure_at(SOURCE, now);
+        let later = now + FAILURE_EXPIRY;
+        throttle.on_failure_at(SOURCE, later);
+        assert!(throttle.check_at(SOURCE, later).is_ok());
+
+        // a record does not expire while its cooldown runs
+        throttle.on_failure_at(SOURCE, later);
+        assert!(throttle.check_at(SOURCE, later).is_err());
+        assert!(!throttle.sources[&SOURCE].is_expired(later + BASE / 2, BASE / 4));
+        assert!(throttle.sources[&SOURCE].is_expired(later + BASE, BASE / 4));
+    }
+
+    #[test]
+    fn tracked_sources_are_capped() {
+        let mut throttle = ConnectThrottle::new(1, BASE);
+        throttle.max_sources = 4;
+        let now = Instant::now();
+        let source = |idx: u8| IpAddr::V4(Ipv4Addr::new(10, 0, 0, idx));
+
+        for idx in 0..4 {
+            throttle.on_failure_at(source(idx), now + BASE * idx as u32);
+        }
+
+        // the source that failed least recently is evicted first
+        throttle.on_failure_at(source(4), now + BASE * 4);
+        assert_eq!(throttle.sources.len(), 4);
+        assert!(!throttle.sources.contains_key(&source(0)));
+        assert!(throttle.sources.contains_key(&source(1)));
+
+        // expired records are pruned before any live record is evicted
+        let later = now + BASE * 4 + FAILURE_EXPIRY;
+        throttle.on_failure_at(source(5), later);
+        assert_eq!(throttle.sources.len(), 1);
+        assert!(throttle.check_at(source(5), later).is_err());
+
+        // sprayed attempts never grow the map past the cap
+        for idx in 0..=u8::MAX {
+            throttle.on_failure_at(IpAddr::V4(Ipv4Addr::new(192, 168, 0, idx)), later);
+        }
+        assert_eq!(throttle.sources.len(), 4);
+    }
+}
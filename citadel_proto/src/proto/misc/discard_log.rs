@@ -0,0 +1,159 @@
+use citadel_io::Mutex;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Bounds the log volume caused by discarded packets. The first `detailed_limit` discards are
+/// logged in full. Thereafter, one of every `sample_rate` discards is logged in full, while the
+/// rest are only counted and summarized at most once per `summary_interval`. Clones share the
+/// same counters
+#[derive(Clone)]
+pub struct DiscardLog {
+    inner: Arc<Mutex<DiscardLogState>>,
+}
+
+struct DiscardLogState {
+    detailed_limit: u64,
+    sample_rate: u64,
+    summary_interval: Duration,
+    total: u64,
+    suppressed: u64,
+    last_summary: Option<Instant>,
+}
+
+/// How a single discard should be logged
+#[derive(Debug, Eq, PartialEq)]
+enum DiscardLogEntry {
+    /// Log the discard in full
+    Detailed,
+    /// Log a summary of the `suppressed` discards not logged in full since the last summary,
+    /// including this one
+    Summary { suppressed: u64 },
+    /// Only count the discard
+    Suppressed,
+}
+
+impl Default for DiscardLog {
+    fn default() -> Self {
+        Self::new(10, 1000, Duration::from_secs(10))
+    }
+}
+
+impl DiscardLog {
+    /// A `sample_rate` of 0 disables sampling, such that only the summaries are logged once
+    /// `detailed_limit` is reached
+    pub fn new(detailed_limit: u64, sample_rate: u64, summary_interval: Duration) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(DiscardLogState {
+                detailed_limit,
+                sample_rate,
+                summary_interval,
+                total: 0,
+                suppressed: 0,
+                last_summary: None,
+            })),
+        }
+    }
+
+    /// Records a discarded packet. `reason` is only evaluated if the discard is logged in full
+    pub fn on_discard<T: std::fmt::Display>(&self, reason: impl FnOnce() -> T) {
+        match self.record_at(Instant::now()) {
+            DiscardLogEntry::Detailed => {
+                log::error!(target: "citadel", "Discarding packet: {}", reason())
+            }
+
+            DiscardLogEntry::Summary { suppressed } => {
+                log::error!(target: "citadel", "Discarded {} more invalid packets ({} total)", suppressed, self.total())
+            }
+
+            DiscardLogEntry::Suppressed => {}
+        }
+    }
+
+    /// Returns the total number of discarded packets recorded
+    pub fn total(&self) -> u64 {
+        self.inner.lock().total
+    }
+
+    fn record_at(&self, now: Instant) -> DiscardLogEntry {
+        let mut state = self.inner.lock();
+        state.total += 1;
+
+        if state.total <= state.detailed_limit {
+            return DiscardLogEntry::Detailed;
+        }
+
+        let sampled_idx = state.total - state.detailed_limit;
+        if state.sample_rate != 0 && sampled_idx.is_multiple_of(state.sample_rate) {
+            return DiscardLogEntry::Detailed;
+        }
+
+        state.suppressed += 1;
+        let last_summary = *state.last_summary.get_or_insert(now);
+        if now.duration_since(last_summary) >= state.summary_interval {
+            state.last_summary = Some(now);
+            DiscardLogEntry::Summary {
+                suppressed: std::mem::take(&mut state.suppressed),
+            }
+        } else {
+            DiscardLogEntry::Suppressed
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::misc::discard_log::{DiscardLog, DiscardLogEntry};
+    use std::time::{Duration, Instant};
+
+    const INTERVAL: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn first_discards_logged_in_full() {
+        let log = DiscardLog::new(3, 0, INTERVAL);
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(log.record_at(now), DiscardLogEntry::Detailed);
+        }
+
+        assert_eq!(log.record_at(now), DiscardLogEntry::Suppressed);
+        assert_eq!(
+            log.record_at(now + INTERVAL),
+            DiscardLogEntry::Summary { suppressed: 2 }
+        );
+        assert_eq!(log.total(), 5);
+    }
+
+    #[test]
+    fn flood_produces_bounded_logs_and_exact_total() {
+        const FLOOD: u64 = 100_000;
+        const DETAILED: u64 = 10;
+        const SAMPLE_RATE: u64 = 10_000;
+
+        let log = DiscardLog::new(DETAILED, SAMPLE_RATE, INTERVAL);
+        let start = Instant::now();
+        let mut detailed = 0;
+        let mut summaries = 0;
+        let mut summarized = 0;
+
+        for idx in 0..FLOOD {
+            // the flood spans one minute
+            let now = start + Duration::from_millis(idx * 60_000 / FLOOD);
+            match log.record_at(now) {
+                DiscardLogEntry::Detailed => detailed += 1,
+                DiscardLogEntry::Summary { suppressed } => {
+                    summaries += 1;
+                    summarized += suppressed;
+                }
+                DiscardLogEntry::Suppressed => {}
+            }
+        }
+
+        let pending = log.inner.lock().suppressed;
+        assert_eq!(log.total(), FLOOD);
+        assert_eq!(detailed + summarized + pending, FLOOD);
+        assert_eq!(detailed, DETAILED + (FLOOD - DETAILED) / SAMPLE_RATE);
+        // one summary per elapsed interval
+        assert!(summaries <= 6);
+        assert!(detailed + summaries <= 30);
+    }
+}
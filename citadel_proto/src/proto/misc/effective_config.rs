@@ -0,0 +1,215 @@
+use crate::kernel::KernelExecutorSettings;
+use crate::proto::misc::keep_alive_policy::KeepAlivePolicy;
+use crate::proto::misc::protocol_version::ProtocolVersionRange;
+use crate::proto::misc::underlying_proto::ServerUnderlyingProtocol;
+use crate::proto::node::TlsDomain;
+use crate::proto::peer::peer_identity::PeerIdentitySettings;
+use citadel_user::account_features::FeatureSet;
+use citadel_user::account_manager::AccountManager;
+use citadel_user::misc::CredentialPolicy;
+use citadel_user::peer_list::PeerListLoading;
+use citadel_user::server_misc_settings::{
+    HandshakePayloadLimits, InboundCommandLimits, OnSessionLimit, PendingHandshakeOverflow,
+    ServerMiscSettings,
+};
+use citadel_wire::hypernode_type::NodeType;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// A snapshot of the settings a node runs with once every default has been resolved, meant to be
+/// attached to bug reports. It contains no secrets: backend credentials are redacted, and keys,
+/// certificates and identities are only reported as present or absent
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EffectiveConfig {
+    pub node_type: NodeType,
+    /// The protocol listeners accept connections over: TCP, TLS or QUIC
+    pub underlying_protocol: String,
+    pub tls_domain: TlsDomain,
+    pub self_signed_certificate: bool,
+    /// Whether certificates presented by servers are checked against a custom TLS client config
+    /// rather than the certificates trusted by the local operating system
+    pub custom_client_tls_config: bool,
+    /// The backend in the URL format accepted by `BackendType::new`, with its password redacted
+    pub backend: String,
+    /// None when the default STUN servers are used
+    pub stun_servers: Option<Vec<String>>,
+    pub header_obfuscation: bool,
+    pub supported_protocol_versions: ProtocolVersionRange,
+    pub keep_alive_policy: KeepAlivePolicy,
+    /// None when the kernel handles events without a concurrency limit
+    pub kernel_max_concurrency: Option<usize>,
+    /// Whether a fixed identity is proven to peers instead of one generated per account
+    pub fixed_peer_identity: bool,
+    pub pinned_peer_fingerprints: usize,
+    pub server_misc_settings: EffectiveServerMiscSettings,
+    /// The cargo features this node was compiled with
+    pub features: Vec<String>,
+}
+
+/// The resolved [`ServerMiscSettings`]. Custom providers are only reported as present or absent
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EffectiveServerMiscSettings {
+    pub allow_passwordless: bool,
+    pub max_failed_connect_attempts: usize,
+    pub failed_connect_cooldown: Duration,
+    pub max_pending_handshakes: usize,
+    pub pending_handshake_overflow: PendingHandshakeOverflow,
+    pub pending_handshake_timeout: Duration,
+    pub session_resumption_lifetime: Option<Duration>,
+    pub credential_policy: CredentialPolicy,
+    pub revfs_deduplication: bool,
+    pub require_prior_registration: bool,
+    pub discard_log_detailed_limit: u64,
+    pub discard_log_sample_rate: u64,
+    pub discard_log_summary_interval: Duration,
+    pub drill_truncation_grace: Duration,
+    pub default_account_features: FeatureSet,
+    pub peer_list_loading: PeerListLoading,
+    pub custom_push_provider: bool,
+    pub account_listener: bool,
+    pub handshake_payload_limits: HandshakePayloadLimits,
+    pub max_registrations_per_minute: usize,
+    pub custom_registration_rate_limiter: bool,
+    pub inbound_command_limits: InboundCommandLimits,
+    pub max_sessions_per_cid: Option<u32>,
+    pub on_session_limit: OnSessionLimit,
+    pub account_export_key: bool,
+    pub max_registered_accounts: Option<usize>,
+    pub max_clock_skew: Option<Duration>,
+    pub normalize_clock_offset: bool,
+}
+
+impl EffectiveConfig {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        node_type: NodeType,
+        account_manager: &AccountManager,
+        underlying_proto: &ServerUnderlyingProtocol,
+        custom_client_tls_config: bool,
+        kernel_executor_settings: &KernelExecutorSettings,
+        stun_servers: Option<&Vec<String>>,
+        header_obfuscation: bool,
+        peer_identity_settings: &PeerIdentitySettings,
+        supported_protocol_versions: ProtocolVersionRange,
+        keep_alive_policy: KeepAlivePolicy,
+    ) -> Self {
+        let self_signed_certificate = match underlying_proto {
+            ServerUnderlyingProtocol::Tcp => false,
+            ServerUnderlyingProtocol::Tls(.., self_signed)
+            | ServerUnderlyingProtocol::Quic(.., self_signed) => *self_signed,
+        };
+
+        Self {
+            node_type,
+            underlying_protocol: format!("{underlying_proto:?}"),
+            tls_domain: underlying_proto.maybe_get_identity(),
+            self_signed_certificate,
+            custom_client_tls_config,
+            backend: account_manager.get_backend_type().to_redacted_url(),
+            stun_servers: stun_servers.cloned(),
+            header_obfuscation,
+            supported_protocol_versions,
+            keep_alive_policy,
+            kernel_max_concurrency: kernel_executor_settings.max_concurrency,
+            fixed_peer_identity: peer_identity_settings.identity.is_some(),
+            pinned_peer_fingerprints: peer_identity_settings.pinned_fingerprints.len(),
+            server_misc_settings: account_manager.get_misc_settings().into(),
+            features: enabled_features(),
+        }
+    }
+}
+
+impl From<&ServerMiscSettings> for EffectiveServerMiscSettings {
+    fn from(settings: &ServerMiscSettings) -> Self {
+        Self {
+            allow_passwordless: settings.allow_passwordless,
+            max_failed_connect_attempts: settings.max_failed_connect_attempts,
+            failed_connect_cooldown: settings.failed_connect_cooldown,
+            max_pending_handshakes: settings.max_pending_handshakes,
+            pending_handshake_overflow: settings.pending_handshake_overflow,
+            pending_handshake_timeout: settings.pending_handshake_timeout,
+            session_resumption_lifetime: settings.session_resumption_lifetime,
+            credential_policy: settings.credential_policy.clone(),
+            revfs_deduplication: settings.revfs_deduplication,
+            require_prior_registration: settings.require_prior_registration,
+            discard_log_detailed_limit: settings.discard_log_detailed_limit,
+            discard_log_sample_rate: settings.discard_log_sample_rate,
+            discard_log_summary_interval: settings.discard_log_summary_interval,
+            drill_truncation_grace: settings.drill_truncation_grace,
+            default_account_features: settings.default_account_features.clone(),
+            peer_list_loading: settings.peer_list_loading,
+            custom_push_provider: settings.push_provider.is_some(),
+            account_listener: settings.account_listener.is_some(),
+            handshake_payload_limits: settings.handshake_payload_limits,
+            max_registrations_per_minute: settings.max_registrations_per_minute,
+            custom_registration_rate_limiter: settings.registration_rate_limiter.is_some(),
+            inbound_command_limits: settings.inbound_command_limits,
+            max_sessions_per_cid: settings.max_sessions_per_cid,
+            on_session_limit: settings.on_session_limit,
+            account_export_key: settings.account_export_key.is_some(),
+            max_registered_accounts: settings.max_registered_accounts,
+            max_clock_skew: settings.max_clock_skew,
+            normalize_clock_offset: settings.normalize_clock_offset,
+        }
+    }
+}
+
+/// Every feature declared in Cargo.toml besides `default`, paired with whether it is enabled
+const FEATURES: &[(&str, bool)] = &[
+    ("filesystem", cfg!(feature = "filesystem")),
+    ("multi-threaded", cfg!(feature = "multi-threaded")),
+    ("sql", cfg!(feature = "sql")),
+    ("redis", cfg!(feature = "redis")),
+    ("mongo", cfg!(feature = "mongo")),
+    ("sled", cfg!(feature = "sled")),
+    ("webrtc", cfg!(feature = "webrtc")),
+    ("localhost-testing", cfg!(feature = "localhost-testing")),
+    (
+        "localhost-testing-assert-no-proxy",
+        cfg!(feature = "localhost-testing-assert-no-proxy"),
+    ),
+    (
+        "localhost-testing-loopback-only",
+        cfg!(feature = "localhost-testing-loopback-only"),
+    ),
+    ("google-services", cfg!(feature = "google-services")),
+    ("apns", cfg!(feature = "apns")),
+    ("debug-keylog", cfg!(feature = "debug-keylog")),
+    ("bench", cfg!(feature = "bench")),
+    ("std", cfg!(feature = "std")),
+    ("wasm", cfg!(feature = "wasm")),
+];
+
+fn enabled_features() -> Vec<String> {
+    FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(feature, _)| feature.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FEATURES;
+
+    #[test]
+    fn every_cargo_feature_is_reported() {
+        let manifest = include_str!("../../../Cargo.toml");
+        let declared = manifest
+            .lines()
+            .skip_while(|line| line.trim() != "[features]")
+            .skip(1)
+            .take_while(|line| !line.starts_with('['))
+            .filter_map(|line| line.split_once('='))
+            .map(|(feature, _)| feature.trim())
+            .filter(|feature| !feature.is_empty() && !feature.starts_with('#'))
+            .filter(|feature| *feature != "default");
+
+        for feature in declared {
+            assert!(
+                FEATURES.iter().any(|(name, _)| *name == feature),
+                "feature {feature} is missing from the effective config"
+            );
+        }
+    }
+}
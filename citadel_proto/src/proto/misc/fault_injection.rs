@@ -0,0 +1,232 @@
+//! Degrades the outbound primary stream of every session on a node, allowing the resilience of
+//! the protocol to be tested under latency, loss, reordering and corruption without an external
+//! network simulator. An injector can only be constructed when the `localhost-testing` feature is enabled
+use crate::constants::HDP_HEADER_BYTE_LEN;
+use crate::proto::outbound_sender::{unbounded, OutboundPrimaryStreamReceiver, UnboundedSender};
+use crate::proto::packet::packet_flags;
+use bytes::BytesMut;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tokio_stream::StreamExt;
+
+/// The minimum delay before the transport recovers a packet lost by the injector
+const MIN_RETRANSMISSION_TIMEOUT: Duration = Duration::from_millis(200);
+/// The longest a packet selected for reordering waits for the packet it swaps places with
+const MAX_REORDER_HOLD: Duration = Duration::from_millis(10);
+/// The bytes of the header holding the protocol version, which follow the four single-byte fields
+const PROTOCOL_VERSION_BYTES: std::ops::Range<usize> = 4..8;
+
+/// Describes the degradation applied to each outbound packet
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq)]
+pub struct FaultProfile {
+    /// Added to the delivery time of every packet
+    pub extra_latency: Duration,
+    /// The probability, in [0, 1], that the first transmission of a packet is lost on the link.
+    /// The primary stream is reliable, so the transport recovers the packet and the loss surfaces
+    /// as a stall of [`Self::retransmission_timeout`], blocking the packets queued behind it
+    pub loss_rate: f64,
+    /// The probability, in [0, 1], that a group payload packet swaps places with the group payload
+    /// packet sent after it, if one follows shortly
+    pub reorder_rate: f64,
+    /// The probability, in [0, 1], that a group payload packet is preceded by a copy whose header
+    /// has a bit flipped on the link. The receiver must discard the copy by its header checksum
+    pub corruption_rate: f64,
+}
+
+impl FaultProfile {
+    /// The time the transport takes to recover a lost packet, modelled as twice the extra latency
+    pub fn retransmission_timeout(&self) -> Duration {
+        std::cmp::max(self.extra_latency * 2, MIN_RETRANSMISSION_TIMEOUT)
+    }
+}
+
+/// Counts the faults injected across all sessions of a node
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct FaultInjectionStats {
+    /// The number of packets passed through the injector
+    pub packets: usize,
+    /// The number of packets stalled by a simulated loss
+    pub stalls: usize,
+    /// The number of packets delivered out of order
+    pub reordered: usize,
+    /// The number of corrupted copies delivered
+    pub corrupted: usize,
+}
+
+/// Applies a [`FaultProfile`] to the outbound primary stream of each session. Clones share the
+/// same counters
+#[derive(Clone, Debug)]
+pub struct FaultInjector {
+    profile: FaultProfile,
+    counters: Arc<FaultCounters>,
+}
+
+#[derive(Debug, Default)]
+struct FaultCounters {
+    packets: AtomicUsize,
+    stalls: AtomicUsize,
+    reordered: AtomicUsize,
+    corrupted: AtomicUsize,
+}
+
+impl FaultInjector {
+    #[cfg(feature = "localhost-testing")]
+    pub fn new(profile: FaultProfile) -> Self {
+        Self {
+            profile,
+            counters: Arc::new(FaultCounters::default()),
+        }
+    }
+
+    pub fn profile(&self) -> FaultProfile {
+        self.profile
+    }
+
+    pub fn stats(&self) -> FaultInjectionStats {
+        FaultInjectionStats {
+            packets: self.counters.packets.load(Ordering::Relaxed),
+            stalls: self.counters.stalls.load(Ordering::Relaxed),
+            reordered: self.counters.reordered.load(Ordering::Relaxed),
+            corrupted: self.counters.corrupted.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns a receiver yielding the packets of `outbound` once degraded by the profile
+    pub(crate) fn degrade(
+        &self,
+        mut outbound: OutboundPrimaryStreamReceiver,
+    ) -> OutboundPrimaryStreamReceiver {
+        let (scheduled_tx, mut scheduled_rx) = unbounded::<(Instant, BytesMut)>();
+        let (degraded_tx, degraded_rx) = unbounded();
+        let this = self.clone();
+
+        // packets are scheduled as soon as they are sent so that the latency of each packet
+        // overlaps with the latency of the packets before it
+        let scheduler = async move {
+            let mut last_deadline = Instant::now();
+            let mut held: Option<BytesMut> = None;
+
+            loop {
+                // a held packet is never kept waiting longer than the hold timeout for a successor,
+                // since the sender may be awaiting an acknowledgement before sending more
+                let next = if held.is_some() {
+                    match tokio::time::timeout(MAX_REORDER_HOLD, outbound.0.next()).await {
+                        Ok(next) => next,
+                        Err(_) => {
+                            let previous = held.take().unwrap();
+                            send_scheduled(&scheduled_tx, last_deadline, previous)?;
+                            continue;
+                        }
+                    }
+                } else {
+                    outbound.0.next().await
+                };
+
+                let packet = match next {
+                    Some(packet) => packet,
+                    None => break,
+                };
+
+                let deadline = std::cmp::max(this.schedule(), last_deadline);
+                last_deadline = deadline;
+
+                if is_group_payload(&packet) {
+                    if let Some(previous) = held.take() {
+                        this.counters.reordered.fetch_add(1, Ordering::Relaxed);
+                        send_scheduled(&scheduled_tx, deadline, packet)?;
+                        send_scheduled(&scheduled_tx, deadline, previous)?;
+                        continue;
+                    }
+
+                    if rand::random::<f64>() < this.profile.reorder_rate {
+                        held = Some(packet);
+                        continue;
+                    }
+                }
+
+                if let Some(previous) = held.take() {
+                    send_scheduled(&scheduled_tx, deadline, previous)?;
+                }
+
+                send_scheduled(&scheduled_tx, deadline, packet)?;
+            }
+
+            if let Some(previous) = held {
+                send_scheduled(&scheduled_tx, last_deadline, previous)?;
+            }
+
+            Ok::<_, ()>(())
+        };
+
+        let deliverer = async move {
+            while let Some((deadline, packet)) = scheduled_rx.recv().await {
+                tokio::time::sleep_until(deadline).await;
+                degraded_tx.unbounded_send(packet).map_err(|_| ())?;
+            }
+
+            Ok::<_, ()>(())
+        };
+
+        spawn!(async move {
+            let _ = tokio::join!(scheduler, deliverer);
+        });
+
+        OutboundPrimaryStreamReceiver::from(degraded_rx)
+    }
+
+    /// Returns a copy of `packet` with a bit of its header flipped if the packet is selected for
+    /// corruption. Must be applied after the header checksum is appended, since corruption on the
+    /// link occurs after the sender computes it
+    pub(crate) fn corrupt(&self, packet: &[u8]) -> Option<BytesMut> {
+        if !is_group_payload(packet)
+            || packet.len() < HDP_HEADER_BYTE_LEN
+            || rand::random::<f64>() >= self.profile.corruption_rate
+        {
+            return None;
+        }
+
+        // the protocol version is left intact, since a version predating the checksum would have
+        // the receiver skip verification
+        let idx = loop {
+            let idx = rand::random::<usize>() % HDP_HEADER_BYTE_LEN;
+            if !PROTOCOL_VERSION_BYTES.contains(&idx) {
+                break idx;
+            }
+        };
+
+        let mut corrupted = BytesMut::from(packet);
+        corrupted[idx] ^= 1 << (rand::random::<u8>() % 8);
+        self.counters.corrupted.fetch_add(1, Ordering::Relaxed);
+        Some(corrupted)
+    }
+
+    /// Returns the time at which the next packet is delivered, ignoring the packets before it
+    fn schedule(&self) -> Instant {
+        self.counters.packets.fetch_add(1, Ordering::Relaxed);
+        let mut deadline = Instant::now() + self.profile.extra_latency;
+
+        if rand::random::<f64>() < self.profile.loss_rate {
+            self.counters.stalls.fetch_add(1, Ordering::Relaxed);
+            deadline += self.profile.retransmission_timeout();
+        }
+
+        deadline
+    }
+}
+
+fn send_scheduled(
+    tx: &UnboundedSender<(Instant, BytesMut)>,
+    deadline: Instant,
+    packet: BytesMut,
+) -> Result<(), ()> {
+    tx.unbounded_send((deadline, packet)).map_err(|_| ())
+}
+
+/// Group payloads are the only packets whose receivers tolerate being delivered out of order
+fn is_group_payload(packet: &[u8]) -> bool {
+    packet.len() >= 2
+        && packet[0] == packet_flags::cmd::primary::GROUP_PACKET
+        && packet[1] == packet_flags::cmd::aux::group::GROUP_PAYLOAD
+}
@@ -0,0 +1,292 @@
+use crate::error::NetworkError;
+use crate::proto::packet::packet_flags::cmd::primary;
+use crate::proto::packet::HeaderObfuscator;
+use crate::proto::session::SessionState;
+use atomic::Atomic;
+use bytes::{Bytes, BytesMut};
+use citadel_user::server_misc_settings::HandshakePayloadLimits;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio_util::codec::{Decoder, Encoder, LengthDelimitedCodec};
+
+/// The largest frame accepted over the primary stream once the handshake is complete
+pub const MAX_PRIMARY_FRAME_LENGTH: usize = 1024 * 1024 * 64;
+const LENGTH_FIELD_LEN: usize = std::mem::size_of::<u32>();
+
+/// Checks the length of each inbound frame against the [`HandshakePayloadLimits`] while the
+/// session is provisional. Only the length field and the first byte of the header are inspected,
+/// so an oversized frame is rejected before its payload is buffered
+#[derive(Clone)]
+pub(crate) struct HandshakeGuard {
+    limits: HandshakePayloadLimits,
+    state: Arc<Atomic<SessionState>>,
+    header_obfuscator: Option<HeaderObfuscator>,
+}
+
+impl HandshakeGuard {
+    pub(crate) fn new(
+        limits: HandshakePayloadLimits,
+        state: Arc<Atomic<SessionState>>,
+        header_obfuscator: Option<HeaderObfuscator>,
+    ) -> Self {
+        Self {
+            limits,
+            state,
+            header_obfuscator,
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.state.load(Ordering::Relaxed).is_provisional()
+    }
+
+    /// `first_byte` is None for empty frames. Returns the largest length permitted for the frame
+    fn limit_for(&self, first_byte: Option<u8>) -> usize {
+        let cmd_primary = match (first_byte, self.header_obfuscator.as_ref()) {
+            (Some(byte), Some(obfuscator)) => obfuscator.peek_cmd_primary(byte),
+            (first_byte, _) => first_byte,
+        };
+
+        match cmd_primary {
+            Some(primary::DO_PRE_CONNECT) => self.limits.pre_connect,
+            Some(primary::DO_REGISTER) => self.limits.register,
+            Some(primary::DO_CONNECT) => self.limits.connect,
+            // the obfuscator's init packet, or a packet the processors will reject
+            _ => self.limits.max(),
+        }
+    }
+
+    fn check(&self, frame_len: usize, first_byte: Option<u8>) -> Result<(), NetworkError> {
+        let max = self.limit_for(first_byte);
+        if frame_len > max {
+            Err(NetworkError::HandshakePayloadTooLarge {
+                size: frame_len,
+                max,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The length-delimited codec used by the primary stream, optionally guarded by a [`HandshakeGuard`]
+pub struct PrimaryStreamCodec {
+    inner: LengthDelimitedCodec,
+    handshake_guard: Option<HandshakeGuard>,
+    // whether the frame the inner codec is currently reading was already checked
+    frame_checked: bool,
+}
+
+impl PrimaryStreamCodec {
+    pub(crate) fn new(handshake_guard: Option<HandshakeGuard>) -> Self {
+        let inner = LengthDelimitedCodec::builder()
+            .length_field_offset(0) // default value
+            .max_frame_length(MAX_PRIMARY_FRAME_LENGTH)
+            .length_field_type::<u32>()
+            .length_adjustment(0) // default value
+            // `num_skip` is not needed, the default is to skip
+            .new_codec();
+
+        Self {
+            inner,
+            handshake_guard,
+            frame_checked: false,
+        }
+    }
+}
+
+impl Decoder for PrimaryStreamCodec {
+    type Item = BytesMut;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if !self.frame_checked {
+            if let Some(guard) = self
+                .handshake_guard
+                .as_ref()
+                .filter(|guard| guard.is_active())
+            {
+                // the inner codec consumes the length field once read, so the frame must be
+                // checked before handing it over
+                if src.len() < LENGTH_FIELD_LEN {
+                    return Ok(None);
+                }
+
+                let mut len_bytes = [0u8; LENGTH_FIELD_LEN];
+                len_bytes.copy_from_slice(&src[..LENGTH_FIELD_LEN]);
+                let frame_len = u32::from_be_bytes(len_bytes) as usize;
+                let first_byte = src.get(LENGTH_FIELD_LEN).copied();
+
+                if frame_len > guard.limits.max() {
+                    return Err(into_io_error(NetworkError::HandshakePayloadTooLarge {
+                        size: frame_len,
+                        max: guard.limits.max(),
+                    }));
+                }
+
+                if frame_len > 0 && first_byte.is_none() {
+                    return Ok(None);
+                }
+
+                guard.check(frame_len, first_byte).map_err(into_io_error)?;
+            }
+
+            self.frame_checked = true;
+        }
+
+        let frame = self.inner.decode(src)?;
+        if frame.is_some() {
+            self.frame_checked = false;
+        }
+
+        Ok(frame)
+    }
+}
+
+impl Encoder<Bytes> for PrimaryStreamCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: Bytes, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.inner.encode(item, dst)
+    }
+}
+
+fn into_io_error(err: NetworkError) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::NetworkError;
+    use crate::proto::misc::handshake_limits::{HandshakeGuard, PrimaryStreamCodec};
+    use crate::proto::packet::packet_flags::cmd::primary;
+    use crate::proto::packet::HeaderObfuscator;
+    use crate::proto::session::SessionState;
+    use atomic::Atomic;
+    use bytes::{BufMut, BytesMut};
+    use citadel_user::server_misc_settings::HandshakePayloadLimits;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    fn limits() -> HandshakePayloadLimits {
+        HandshakePayloadLimits {
+            pre_connect: 4096,
+            register: 4096,
+            connect: 1024,
+        }
+    }
+
+    fn guarded_codec(
+        header_obfuscator: Option<HeaderObfuscator>,
+    ) -> (PrimaryStreamCodec, Arc<Atomic<SessionState>>) {
+        let state = Arc::new(Atomic::new(SessionState::SocketJustOpened));
+        let guard = HandshakeGuard::new(limits(), state.clone(), header_obfuscator);
+        (PrimaryStreamCodec::new(Some(guard)), state)
+    }
+
+    fn frame(cmd_primary: u8, len: usize) -> BytesMut {
+        let mut payload = BytesMut::zeroed(len);
+        payload[0] = cmd_primary;
+        let mut framed = BytesMut::new();
+        PrimaryStreamCodec::new(None)
+            .encode(payload.freeze(), &mut framed)
+            .unwrap();
+        framed
+    }
+
+    fn oversized_error(err: std::io::Error) -> Option<(usize, usize)> {
+        match *err.into_inner()?.downcast::<NetworkError>().ok()? {
+            NetworkError::HandshakePayloadTooLarge { size, max } => Some((size, max)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn handshake_packet_within_limit_proceeds() {
+        let (mut codec, _state) = guarded_codec(None);
+        let mut src = frame(primary::DO_CONNECT, 1024);
+        let packet = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(packet.len(), 1024);
+        assert_eq!(packet[0], primary::DO_CONNECT);
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn oversized_handshake_packet_rejected_before_buffering() {
+        let (mut codec, _state) = guarded_codec(None);
+        // only the length field and the first byte of the header have arrived
+        let mut src = frame(primary::DO_CONNECT, 1025);
+        src.truncate(5);
+        let err = codec.decode(&mut src).unwrap_err();
+        assert_eq!(oversized_error(err), Some((1025, 1024)));
+
+        // over the largest stage limit, rejected from the length field alone
+        let (mut codec, _state) = guarded_codec(None);
+        let mut src = BytesMut::new();
+        src.put_u32(u32::MAX);
+        let err = codec.decode(&mut src).unwrap_err();
+        assert_eq!(oversized_error(err), Some((u32::MAX as usize, 4096)));
+    }
+
+    #[test]
+    fn limits_are_per_command() {
+        let (mut codec, _state) = guarded_codec(None);
+        let mut src = frame(primary::DO_REGISTER, 4096);
+        assert_eq!(codec.decode(&mut src).unwrap().unwrap().len(), 4096);
+
+        let mut src = frame(primary::DO_PRE_CONNECT, 4097);
+        let err = codec.decode(&mut src).unwrap_err();
+        assert_eq!(oversized_error(err), Some((4097, 4096)));
+    }
+
+    #[test]
+    fn limits_lifted_once_connected() {
+        let (mut codec, state) = guarded_codec(None);
+        state.store(SessionState::Connected, Ordering::Relaxed);
+        let mut src = frame(primary::DO_CONNECT, 8192);
+        assert_eq!(codec.decode(&mut src).unwrap().unwrap().len(), 8192);
+    }
+
+    #[test]
+    fn partial_frames_are_reassembled() {
+        let (mut codec, _state) = guarded_codec(None);
+        let full = frame(primary::DO_CONNECT, 512);
+        let mut src = BytesMut::new();
+        for chunk in full.chunks(3) {
+            assert!(src.is_empty() || codec.decode(&mut src).unwrap().is_none());
+            src.extend_from_slice(chunk);
+        }
+
+        let packet = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(packet.len(), 512);
+    }
+
+    #[test]
+    fn obfuscated_command_is_recovered() {
+        let (client, init_packet) = HeaderObfuscator::new_client();
+        let server = HeaderObfuscator::new_server();
+        let (mut codec, _state) = guarded_codec(Some(server.clone()));
+
+        let mut init_packet = init_packet;
+        assert!(server.on_packet_received(&mut init_packet).is_none());
+
+        let mut payload = BytesMut::zeroed(2048);
+        payload[0] = primary::DO_CONNECT;
+        let mut src = BytesMut::new();
+        codec
+            .encode(client.prepare_outbound(payload), &mut src)
+            .unwrap();
+        let err = codec.decode(&mut src).unwrap_err();
+        assert_eq!(oversized_error(err), Some((2048, 1024)));
+
+        let mut payload = BytesMut::zeroed(2048);
+        payload[0] = primary::DO_REGISTER;
+        let mut src = BytesMut::new();
+        codec
+            .encode(client.prepare_outbound(payload), &mut src)
+            .unwrap();
+        let (mut codec, _state) = guarded_codec(Some(server));
+        assert_eq!(codec.decode(&mut src).unwrap().unwrap().len(), 2048);
+    }
+}
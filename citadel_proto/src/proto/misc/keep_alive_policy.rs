@@ -0,0 +1,218 @@
+use crate::constants::{KEEP_ALIVE_INTERVAL_MS, MAX_KEEP_ALIVE_TIMEOUT_NS};
+use crate::error::NetworkError;
+use crate::proto::packet_processor::includes::Instant;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Determines how often keep alives are sent over a session. When `backoff_on_idle` is set, the
+/// interval doubles each time a keep alive is sent over an otherwise idle session, up to
+/// `max_interval`, and returns to `base_interval` as soon as other traffic is received
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub struct KeepAlivePolicy {
+    /// The interval used while the session is active
+    pub base_interval: Duration,
+    /// The longest interval reached while backing off
+    pub max_interval: Duration,
+    pub backoff_on_idle: bool,
+}
+
+impl Default for KeepAlivePolicy {
+    fn default() -> Self {
+        let interval = Duration::from_millis(KEEP_ALIVE_INTERVAL_MS);
+        Self {
+            base_interval: interval,
+            max_interval: interval,
+            backoff_on_idle: false,
+        }
+    }
+}
+
+impl KeepAlivePolicy {
+    /// Returns an error if `base_interval` is zero or exceeds `max_interval`, or if `max_interval`
+    /// is too long for the adjacent node to tolerate
+    pub fn validate(&self) -> Result<(), NetworkError> {
+        if self.base_interval.is_zero() {
+            return Err(NetworkError::InvalidRequest(
+                "The base keep alive interval must be non-zero",
+            ));
+        }
+
+        if self.base_interval > self.max_interval {
+            return Err(NetworkError::InvalidRequest(
+                "The base keep alive interval may not exceed the maximum",
+            ));
+        }
+
+        if self.min_timeout_ns() > MAX_KEEP_ALIVE_TIMEOUT_NS {
+            return Err(NetworkError::InvalidRequest(
+                "The maximum keep alive interval is too long to be tolerated by the adjacent node",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The shortest keep alive timeout compatible with this policy: three of its longest
+    /// intervals, the same margin the default timeout leaves for the default interval
+    pub fn min_timeout_ns(&self) -> i64 {
+        (self.max_interval.as_nanos() * 3).min(i64::MAX as u128) as i64
+    }
+
+    /// Bounds the keep alive timeout advertised by the adjacent node to the range this node
+    /// tolerates: long enough to span this node's own longest interval, yet no longer than
+    /// [`MAX_KEEP_ALIVE_TIMEOUT_NS`]. A timeout of zero disables keep alives, and is kept as-is
+    pub(crate) fn tolerate_timeout_ns(&self, timeout_ns: i64) -> i64 {
+        if timeout_ns == 0 {
+            return 0;
+        }
+
+        let min_timeout_ns = self.min_timeout_ns();
+        timeout_ns
+            .min(MAX_KEEP_ALIVE_TIMEOUT_NS.max(min_timeout_ns))
+            .max(min_timeout_ns)
+    }
+}
+
+/// Tracks the interval between the keep alives sent over a session under a [`KeepAlivePolicy`]
+pub(crate) struct KeepAliveBackoff {
+    policy: KeepAlivePolicy,
+    interval: Duration,
+    last_scheduled: Option<Instant>,
+}
+
+impl KeepAliveBackoff {
+    pub fn new(policy: KeepAlivePolicy) -> Self {
+        Self {
+            policy,
+            interval: policy.base_interval,
+            last_scheduled: None,
+        }
+    }
+
+    /// Returns how long to wait before sending the next keep alive, given the last time traffic
+    /// besides keep alives was received. If nothing was received since the previous keep alive
+    /// was scheduled, the interval backs off
+    pub fn next_interval(&mut self, last_activity: Instant, now: Instant) -> Duration {
+        let idle = self
+            .last_scheduled
+            .map(|last_scheduled| last_activity <= last_scheduled)
+            .unwrap_or(false);
+
+        self.interval = if idle && self.policy.backoff_on_idle {
+            self.interval
+                .saturating_mul(2)
+                .min(self.policy.max_interval)
+        } else {
+            self.policy.base_interval
+        };
+
+        self.last_scheduled = Some(now);
+        self.interval
+    }
+
+    /// The interval most recently returned by [`Self::next_interval`]
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::MAX_KEEP_ALIVE_TIMEOUT_NS;
+    use crate::proto::misc::keep_alive_policy::{KeepAliveBackoff, KeepAlivePolicy};
+    use crate::proto::packet_processor::includes::Instant;
+    use std::time::Duration;
+
+    fn policy(backoff_on_idle: bool) -> KeepAlivePolicy {
+        KeepAlivePolicy {
+            base_interval: Duration::from_secs(10),
+            max_interval: Duration::from_secs(60),
+            backoff_on_idle,
+        }
+    }
+
+    #[test]
+    fn interval_grows_while_idle_and_resets_on_activity() {
+        let mut backoff = KeepAliveBackoff::new(policy(true));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(backoff.next_interval(start, at(1)), Duration::from_secs(10));
+        // nothing but keep alives since then
+        assert_eq!(
+            backoff.next_interval(start, at(11)),
+            Duration::from_secs(20)
+        );
+        assert_eq!(
+            backoff.next_interval(start, at(31)),
+            Duration::from_secs(40)
+        );
+        assert_eq!(
+            backoff.next_interval(start, at(71)),
+            Duration::from_secs(60)
+        );
+        assert_eq!(
+            backoff.next_interval(start, at(131)),
+            Duration::from_secs(60)
+        );
+
+        // a packet arrives while waiting to send the next keep alive
+        assert_eq!(
+            backoff.next_interval(at(150), at(191)),
+            Duration::from_secs(10)
+        );
+        assert_eq!(backoff.interval(), Duration::from_secs(10));
+        assert_eq!(
+            backoff.next_interval(at(150), at(201)),
+            Duration::from_secs(20)
+        );
+    }
+
+    #[test]
+    fn interval_is_fixed_without_backoff() {
+        let mut backoff = KeepAliveBackoff::new(policy(false));
+        let start = Instant::now();
+
+        for secs in [1, 11, 21, 31] {
+            assert_eq!(
+                backoff.next_interval(start, start + Duration::from_secs(secs)),
+                Duration::from_secs(10)
+            );
+        }
+    }
+
+    #[test]
+    fn validate_rejects_invalid_intervals() {
+        assert!(KeepAlivePolicy::default().validate().is_ok());
+        assert!(policy(true).validate().is_ok());
+
+        let mut zero = policy(true);
+        zero.base_interval = Duration::ZERO;
+        assert!(zero.validate().is_err());
+
+        let mut inverted = policy(true);
+        inverted.base_interval = Duration::from_secs(120);
+        assert!(inverted.validate().is_err());
+
+        let mut too_long = policy(true);
+        too_long.max_interval = Duration::from_secs(60 * 60 * 24);
+        assert!(too_long.validate().is_err());
+    }
+
+    #[test]
+    fn tolerates_advertised_timeouts_within_range() {
+        let policy = policy(true);
+        let min_timeout_ns = policy.min_timeout_ns();
+
+        assert_eq!(policy.tolerate_timeout_ns(0), 0);
+        assert_eq!(policy.tolerate_timeout_ns(1), min_timeout_ns);
+        assert_eq!(
+            policy.tolerate_timeout_ns(min_timeout_ns * 2),
+            min_timeout_ns * 2
+        );
+        assert_eq!(
+            policy.tolerate_timeout_ns(i64::MAX),
+            MAX_KEEP_ALIVE_TIMEOUT_NS
+        );
+    }
+}
@@ -8,14 +8,27 @@ use tokio_stream::StreamExt;
 use tokio_util::codec::LengthDelimitedCodec;
 
 pub mod clean_shutdown;
+pub mod clock_skew;
+pub mod command_rate_limit;
+pub mod connect_throttle;
+pub mod discard_log;
 pub mod dual_cell;
 pub mod dual_late_init;
 pub mod dual_rwlock;
+pub mod effective_config;
+pub mod fault_injection;
+pub mod handshake_limits;
+pub mod keep_alive_policy;
 pub mod lock_holder;
 pub mod net;
 pub mod ordered_channel;
 pub mod panic_future;
+pub mod pending_handshakes;
+pub mod protocol_version;
+pub mod session_limit;
+pub mod session_resumption;
 pub mod session_security_settings;
+pub mod session_state_dump;
 pub mod udp_internal_interface;
 pub mod underlying_proto;
 
@@ -3,6 +3,7 @@ use crate::macros::{ContextRequirements, SyncContextRequirements};
 use crate::proto::misc::clean_shutdown::{
     clean_framed_shutdown, CleanShutdownSink, CleanShutdownStream,
 };
+use crate::proto::misc::handshake_limits::{HandshakeGuard, PrimaryStreamCodec};
 use crate::proto::node::TlsDomain;
 use crate::proto::peer::p2p_conn_handler::generic_error;
 use bytes::Bytes;
@@ -24,7 +25,7 @@ use std::task::{Context, Poll};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::net::{TcpListener, TcpStream};
 use tokio_stream::{Stream, StreamExt};
-use tokio_util::codec::LengthDelimitedCodec;
+use tokio_util::codec::Framed;
 
 /// Wraps a stream into a split interface for I/O that safely shuts-down the interface
 /// upon drop
@@ -32,17 +33,21 @@ use tokio_util::codec::LengthDelimitedCodec;
 pub fn safe_split_stream<S: AsyncWrite + AsyncRead + Unpin + ContextRequirements>(
     stream: S,
 ) -> (
-    CleanShutdownSink<S, LengthDelimitedCodec, Bytes>,
-    CleanShutdownStream<S, LengthDelimitedCodec, Bytes>,
+    CleanShutdownSink<S, PrimaryStreamCodec, Bytes>,
+    CleanShutdownStream<S, PrimaryStreamCodec, Bytes>,
 ) {
-    let framed = LengthDelimitedCodec::builder()
-        .length_field_offset(0) // default value
-        .max_frame_length(1024 * 1024 * 64) // 64 MB
-        .length_field_type::<u32>()
-        .length_adjustment(0) // default value
-        // `num_skip` is not needed, the default is to skip
-        .new_framed(stream);
+    guarded_split_stream(stream, None)
+}
 
+/// Like [`safe_split_stream`], but inbound frames are checked by `handshake_guard`, if any
+pub(crate) fn guarded_split_stream<S: AsyncWrite + AsyncRead + Unpin + ContextRequirements>(
+    stream: S,
+    handshake_guard: Option<HandshakeGuard>,
+) -> (
+    CleanShutdownSink<S, PrimaryStreamCodec, Bytes>,
+    CleanShutdownStream<S, PrimaryStreamCodec, Bytes>,
+) {
+    let framed = Framed::new(stream, PrimaryStreamCodec::new(handshake_guard));
     clean_framed_shutdown(framed)
 }
 
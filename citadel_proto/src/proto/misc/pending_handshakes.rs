@@ -0,0 +1,248 @@
+use crate::error::ConnectError;
+use citadel_user::server_misc_settings::PendingHandshakeOverflow;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Counters describing the handshakes of a node that have not yet completed
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct HandshakeMetrics {
+    /// The number of handshakes currently in progress
+    pub pending: usize,
+    /// The number of connections rejected because too many handshakes were in progress
+    pub rejected: u64,
+    /// The number of handshakes dropped to make room for newer connections
+    pub evicted: u64,
+    /// The number of handshakes dropped for not completing within the timeout
+    pub reaped: u64,
+}
+
+/// Connections whose handshake has not yet completed, keyed by the remote address. The number of
+/// entries is capped so that half-open connections cannot exhaust the table. Since entries are
+/// only reaped when the table is accessed, a stale entry may outlive its timeout while idle
+pub struct PendingHandshakes<T> {
+    max_pending: usize,
+    timeout: Duration,
+    overflow: PendingHandshakeOverflow,
+    entries: HashMap<SocketAddr, (Instant, T)>,
+    metrics: HandshakeMetrics,
+}
+
+impl<T> PendingHandshakes<T> {
+    pub fn new(max_pending: usize, timeout: Duration, overflow: PendingHandshakeOverflow) -> Self {
+        Self {
+            max_pending,
+            timeout,
+            overflow,
+            entries: HashMap::new(),
+            metrics: HandshakeMetrics::default(),
+        }
+    }
+
+    /// Returns the time the handshake with `addr` began, along with its entry
+    pub fn get(&self, addr: &SocketAddr) -> Option<&(Instant, T)> {
+        self.entries.get(addr)
+    }
+
+    /// Adds an entry for `addr`, returning the entry it replaced, if any. Capacity is not checked,
+    /// since [`Self::make_room`] is expected to have been called first for inbound connections
+    pub fn insert(&mut self, addr: SocketAddr, entry: T) -> Option<T> {
+        self.insert_at(addr, entry, Instant::now())
+    }
+
+    /// Removes the entry for `addr` once its handshake completes or fails
+    pub fn remove(&mut self, addr: &SocketAddr) -> Option<T> {
+        self.entries.remove(addr).map(|(_, entry)| entry)
+    }
+
+    /// Ensures room exists for one more entry. Depending on the overflow policy, the oldest entry
+    /// is evicted and returned so the caller may close it, or an error containing the time until
+    /// the oldest entry expires is returned. Stale entries should be reaped beforehand
+    pub fn make_room(&mut self) -> Result<Option<T>, ConnectError> {
+        self.make_room_at(Instant::now())
+    }
+
+    /// Removes every entry whose handshake began at least the timeout ago, returning them so the
+    /// caller may close them
+    pub fn reap(&mut self) -> Vec<T> {
+        self.reap_at(Instant::now())
+    }
+
+    /// Returns the counters describing this table
+    pub fn metrics(&self) -> HandshakeMetrics {
+        HandshakeMetrics {
+            pending: self.entries.len(),
+            ..self.metrics
+        }
+    }
+
+    fn insert_at(&mut self, addr: SocketAddr, entry: T, now: Instant) -> Option<T> {
+        self.entries
+            .insert(addr, (now, entry))
+            .map(|(_, entry)| entry)
+    }
+
+    fn make_room_at(&mut self, now: Instant) -> Result<Option<T>, ConnectError> {
+        if self.max_pending == 0 || self.entries.len() < self.max_pending {
+            return Ok(None);
+        }
+
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, (init, _))| *init)
+            .map(|(addr, (init, _))| (*addr, *init));
+        let (oldest_addr, oldest_init) = match oldest {
+            Some(oldest) => oldest,
+            None => return Ok(None),
+        };
+
+        match self.overflow {
+            PendingHandshakeOverflow::Reject => {
+                self.metrics.rejected += 1;
+                let retry_after = (oldest_init + self.timeout).saturating_duration_since(now);
+                log::warn!(target: "citadel", "{} handshakes are pending. Rejecting new connections for {:?}", self.entries.len(), retry_after);
+                Err(ConnectError::TooManyPendingHandshakes(retry_after))
+            }
+
+            PendingHandshakeOverflow::EvictOldest => {
+                self.metrics.evicted += 1;
+                log::warn!(target: "citadel", "{} handshakes are pending. Evicting the handshake with {}", self.entries.len(), oldest_addr);
+                Ok(self.remove(&oldest_addr))
+            }
+        }
+    }
+
+    fn reap_at(&mut self, now: Instant) -> Vec<T> {
+        let stale = self
+            .entries
+            .iter()
+            .filter(|(_, (init, _))| now.saturating_duration_since(*init) >= self.timeout)
+            .map(|(addr, _)| *addr)
+            .collect::<Vec<_>>();
+
+        if !stale.is_empty() {
+            log::warn!(target: "citadel", "Reaping {} handshakes that did not complete within {:?}", stale.len(), self.timeout);
+        }
+
+        self.metrics.reaped += stale.len() as u64;
+        stale
+            .into_iter()
+            .filter_map(|addr| self.remove(&addr))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ConnectError;
+    use crate::proto::misc::pending_handshakes::{HandshakeMetrics, PendingHandshakes};
+    use citadel_user::server_misc_settings::PendingHandshakeOverflow;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::time::{Duration, Instant};
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port)
+    }
+
+    /// Opens `count` handshakes one second apart, none of which complete
+    fn open_handshakes(
+        table: &mut PendingHandshakes<u16>,
+        count: u16,
+        start: Instant,
+    ) -> Result<(), ConnectError> {
+        for port in 0..count {
+            let now = start + Duration::from_secs(port as u64);
+            assert!(table.reap_at(now).is_empty());
+            assert!(table.make_room_at(now)?.is_none());
+            assert!(table.insert_at(addr(port), port, now).is_none());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_once_full() {
+        let mut table = PendingHandshakes::new(3, TIMEOUT, PendingHandshakeOverflow::Reject);
+        let start = Instant::now();
+        open_handshakes(&mut table, 3, start).unwrap();
+
+        // the oldest handshake expires 10 seconds after it began
+        let now = start + Duration::from_secs(4);
+        assert_eq!(
+            table.make_room_at(now),
+            Err(ConnectError::TooManyPendingHandshakes(Duration::from_secs(
+                6
+            )))
+        );
+
+        // completing a handshake frees a slot
+        assert_eq!(table.remove(&addr(1)), Some(1));
+        assert_eq!(table.make_room_at(now), Ok(None));
+        assert_eq!(
+            table.metrics(),
+            HandshakeMetrics {
+                pending: 2,
+                rejected: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn evicts_oldest_once_full() {
+        let mut table = PendingHandshakes::new(3, TIMEOUT, PendingHandshakeOverflow::EvictOldest);
+        let start = Instant::now();
+        open_handshakes(&mut table, 3, start).unwrap();
+
+        let now = start + Duration::from_secs(4);
+        assert_eq!(table.make_room_at(now), Ok(Some(0)));
+        assert!(table.get(&addr(0)).is_none());
+        assert!(table.insert_at(addr(3), 3, now).is_none());
+        assert_eq!(table.make_room_at(now), Ok(Some(1)));
+        assert_eq!(
+            table.metrics(),
+            HandshakeMetrics {
+                pending: 2,
+                evicted: 2,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn reaps_stale_handshakes() {
+        let mut table = PendingHandshakes::new(3, TIMEOUT, PendingHandshakeOverflow::Reject);
+        let start = Instant::now();
+        open_handshakes(&mut table, 3, start).unwrap();
+
+        assert!(table
+            .reap_at(start + TIMEOUT - Duration::from_millis(1))
+            .is_empty());
+        assert_eq!(table.reap_at(start + TIMEOUT), vec![0]);
+
+        // reaping makes room for new connections without rejecting or evicting
+        let now = start + TIMEOUT + Duration::from_secs(1);
+        assert_eq!(table.reap_at(now), vec![1]);
+        assert_eq!(table.make_room_at(now), Ok(None));
+        assert_eq!(
+            table.metrics(),
+            HandshakeMetrics {
+                pending: 1,
+                reaped: 2,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn zero_cap_disables_limit() {
+        let timeout = Duration::from_secs(1000);
+        let mut table = PendingHandshakes::new(0, timeout, PendingHandshakeOverflow::Reject);
+        open_handshakes(&mut table, 100, Instant::now()).unwrap();
+        assert_eq!(table.metrics().pending, 100);
+    }
+}
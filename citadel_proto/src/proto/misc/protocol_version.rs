@@ -0,0 +1,208 @@
+use crate::constants::{MAJOR_VERSION, MINOR_VERSION, PATCH_VERSION};
+use crate::error::NetworkError;
+use embedded_semver::Semver;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+/// A version of the protocol, as stamped into the `protocol_version` of each packet header
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct ProtocolVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+/// An inclusive range of protocol versions a node is able to speak. During the pre-connect stage,
+/// the client advertises its range and the server selects the highest version within both ranges
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ProtocolVersionRange {
+    min: ProtocolVersion,
+    max: ProtocolVersion,
+}
+
+impl ProtocolVersion {
+    /// The version implemented by this build
+    pub const CURRENT: Self = Self::new(MAJOR_VERSION, MINOR_VERSION, PATCH_VERSION);
+
+    pub const fn new(major: u8, minor: u8, patch: u8) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Packs the version into the format carried by packet headers
+    pub fn to_u32(&self) -> u32 {
+        Semver::new(self.major as _, self.minor as _, self.patch as _)
+            .to_u32()
+            .unwrap()
+    }
+
+    /// Unpacks a version carried by a packet header
+    pub fn from_u32(version: u32) -> Option<Self> {
+        let version = Semver::from_u32(version).ok()?;
+        Some(Self::new(
+            version.major.try_into().ok()?,
+            version.minor.try_into().ok()?,
+            version.patch.try_into().ok()?,
+        ))
+    }
+}
+
+impl Display for ProtocolVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl ProtocolVersionRange {
+    /// Creates a range spanning `min` through `max`. Since a node cannot speak versions newer than
+    /// its own, `max` may not exceed [`ProtocolVersion::CURRENT`]
+    pub fn new(min: ProtocolVersion, max: ProtocolVersion) -> Result<Self, NetworkError> {
+        if min > max {
+            return Err(NetworkError::InvalidRequest(
+                "The minimum protocol version may not exceed the maximum",
+            ));
+        }
+
+        if max > ProtocolVersion::CURRENT {
+            return Err(NetworkError::InvalidRequest(
+                "The maximum protocol version may not exceed the current protocol version",
+            ));
+        }
+
+        Ok(Self { min, max })
+    }
+
+    pub fn min(&self) -> ProtocolVersion {
+        self.min
+    }
+
+    pub fn max(&self) -> ProtocolVersion {
+        self.max
+    }
+
+    /// Returns true if `version` falls within this range
+    pub fn contains(&self, version: ProtocolVersion) -> bool {
+        self.min <= version && version <= self.max
+    }
+
+    /// Returns the highest version within both this range and `remote`, or None if the ranges
+    /// do not overlap
+    pub fn negotiate(&self, remote: &Self) -> Option<ProtocolVersion> {
+        let highest = self.max.min(remote.max);
+        if highest >= self.min.max(remote.min) {
+            Some(highest)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for ProtocolVersionRange {
+    /// Every patch release of the current minor version is supported, since only major and minor
+    /// releases introduce breaking changes
+    fn default() -> Self {
+        Self {
+            min: ProtocolVersion::new(MAJOR_VERSION, MINOR_VERSION, 0),
+            max: ProtocolVersion::CURRENT,
+        }
+    }
+}
+
+impl Display for ProtocolVersionRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}..={}", self.min, self.max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::PROTOCOL_VERSION;
+    use crate::proto::misc::protocol_version::{ProtocolVersion, ProtocolVersionRange};
+
+    const fn range(min: ProtocolVersion, max: ProtocolVersion) -> ProtocolVersionRange {
+        ProtocolVersionRange { min, max }
+    }
+
+    #[test]
+    fn packs_into_header_format() {
+        assert_eq!(ProtocolVersion::CURRENT.to_u32(), *PROTOCOL_VERSION);
+        let version = ProtocolVersion::new(1, 12, 7);
+        assert_eq!(ProtocolVersion::from_u32(version.to_u32()), Some(version));
+    }
+
+    #[test]
+    fn negotiates_highest_overlapping_version() {
+        let local = range(ProtocolVersion::new(0, 2, 0), ProtocolVersion::new(0, 4, 1));
+        let remote = range(ProtocolVersion::new(0, 3, 5), ProtocolVersion::new(1, 0, 0));
+        assert_eq!(
+            local.negotiate(&remote),
+            Some(ProtocolVersion::new(0, 4, 1))
+        );
+        assert_eq!(
+            remote.negotiate(&local),
+            Some(ProtocolVersion::new(0, 4, 1))
+        );
+
+        // ranges overlapping at a single version
+        let remote = range(ProtocolVersion::new(0, 4, 1), ProtocolVersion::new(0, 5, 0));
+        assert_eq!(
+            local.negotiate(&remote),
+            Some(ProtocolVersion::new(0, 4, 1))
+        );
+
+        let current = ProtocolVersionRange::default();
+        assert_eq!(current.negotiate(&current), Some(ProtocolVersion::CURRENT));
+    }
+
+    #[test]
+    fn rejects_disjoint_ranges() {
+        let local = range(ProtocolVersion::new(0, 2, 0), ProtocolVersion::new(0, 3, 9));
+        let remote = range(ProtocolVersion::new(0, 4, 0), ProtocolVersion::new(0, 5, 0));
+        assert_eq!(local.negotiate(&remote), None);
+        assert_eq!(remote.negotiate(&local), None);
+    }
+
+    #[test]
+    fn validates_bounds() {
+        let current = ProtocolVersion::CURRENT;
+        let newer = ProtocolVersion::new(current.major, current.minor, current.patch + 1);
+        assert!(ProtocolVersionRange::new(ProtocolVersion::new(0, 1, 0), current).is_ok());
+        assert!(ProtocolVersionRange::new(current, ProtocolVersion::new(0, 1, 0)).is_err());
+        assert!(ProtocolVersionRange::new(current, newer).is_err());
+    }
+
+    #[test]
+    fn default_range_accepts_patch_releases() {
+        let current = ProtocolVersion::CURRENT;
+        for shift in 1..3 {
+            let newer = ProtocolVersion::new(current.major, current.minor, current.patch + shift);
+            let remote = range(ProtocolVersion::new(current.major, current.minor, 0), newer);
+            assert_eq!(
+                ProtocolVersionRange::default().negotiate(&remote),
+                Some(current)
+            );
+        }
+    }
+
+    #[test]
+    fn default_range_rejects_major_and_minor_releases() {
+        let current = ProtocolVersion::CURRENT;
+        for shift in 1..3 {
+            for newer in [
+                ProtocolVersion::new(current.major + shift, current.minor, current.patch),
+                ProtocolVersion::new(current.major, current.minor + shift, current.patch),
+            ] {
+                let remote = range(newer, newer);
+                assert_eq!(ProtocolVersionRange::default().negotiate(&remote), None);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unparsable_versions() {
+        assert_eq!(ProtocolVersion::from_u32(u32::MAX), None);
+    }
+}
@@ -0,0 +1,107 @@
+use crate::error::ConnectError;
+use citadel_user::server_misc_settings::OnSessionLimit;
+
+/// The most sessions a node holds for a single CID, since live sessions are tracked by CID.
+/// Nodes configured with a larger `max_sessions_per_cid` fail to build
+pub const MAX_SESSIONS_PER_CID: u32 = 1;
+
+/// The outcome of a connect that passed the session limit
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SessionAdmission {
+    /// The CID holds fewer sessions than the limit
+    Admit,
+    /// The CID is at the limit, and its oldest session must be ended before the connect proceeds
+    EvictOldest,
+}
+
+/// Caps the number of sessions each CID may hold at once
+#[derive(Copy, Clone, Debug)]
+pub struct SessionLimit {
+    max: u32,
+    policy: OnSessionLimit,
+}
+
+impl SessionLimit {
+    pub fn new(max_sessions_per_cid: Option<u32>, policy: OnSessionLimit) -> Self {
+        let max = max_sessions_per_cid.unwrap_or(MAX_SESSIONS_PER_CID);
+        debug_assert!(Self::validate(Some(max)).is_ok());
+        Self {
+            max: max.min(MAX_SESSIONS_PER_CID),
+            policy,
+        }
+    }
+
+    /// Ensures that a node can honor `max_sessions_per_cid`
+    pub fn validate(max_sessions_per_cid: Option<u32>) -> Result<(), String> {
+        match max_sessions_per_cid {
+            Some(max) if max > MAX_SESSIONS_PER_CID => Err(format!(
+                "max_sessions_per_cid is {max}, yet a node holds at most {MAX_SESSIONS_PER_CID} session(s) per CID"
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// The number of sessions each CID may hold at once
+    pub fn max(&self) -> u32 {
+        self.max
+    }
+
+    /// Checks a connect for `cid`, which currently holds `active` sessions
+    pub fn check(&self, cid: u64, active: usize) -> Result<SessionAdmission, ConnectError> {
+        if active < self.max as usize {
+            return Ok(SessionAdmission::Admit);
+        }
+
+        match self.policy {
+            // with a limit of zero, there is no room to make
+            OnSessionLimit::EvictOldest if self.max != 0 => Ok(SessionAdmission::EvictOldest),
+            _ => Err(ConnectError::SessionLimitReached(cid, self.max)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ConnectError;
+    use crate::proto::misc::session_limit::{SessionAdmission, SessionLimit, MAX_SESSIONS_PER_CID};
+    use citadel_user::server_misc_settings::OnSessionLimit;
+
+    const CID: u64 = 10;
+
+    #[test]
+    fn rejects_once_full() {
+        let limit = SessionLimit::new(Some(1), OnSessionLimit::Reject);
+        assert_eq!(limit.check(CID, 0), Ok(SessionAdmission::Admit));
+        assert_eq!(
+            limit.check(CID, 1),
+            Err(ConnectError::SessionLimitReached(CID, 1))
+        );
+    }
+
+    #[test]
+    fn evicts_oldest_once_full() {
+        let limit = SessionLimit::new(Some(1), OnSessionLimit::EvictOldest);
+        assert_eq!(limit.check(CID, 0), Ok(SessionAdmission::Admit));
+        assert_eq!(limit.check(CID, 1), Ok(SessionAdmission::EvictOldest));
+    }
+
+    #[test]
+    fn limits_above_sessions_per_cid_are_invalid() {
+        for max in [None, Some(0), Some(MAX_SESSIONS_PER_CID)] {
+            assert!(SessionLimit::validate(max).is_ok());
+        }
+        assert!(SessionLimit::validate(Some(MAX_SESSIONS_PER_CID + 1)).is_err());
+        assert!(SessionLimit::validate(Some(20)).is_err());
+    }
+
+    #[test]
+    fn zero_limit_rejects_every_connect() {
+        for policy in [OnSessionLimit::Reject, OnSessionLimit::EvictOldest] {
+            let limit = SessionLimit::new(Some(0), policy);
+            assert_eq!(
+                limit.check(CID, 0),
+                Err(ConnectError::SessionLimitReached(CID, 0))
+            );
+        }
+    }
+}
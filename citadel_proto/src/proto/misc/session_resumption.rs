@@ -0,0 +1,266 @@
+use crate::error::NetworkError;
+use citadel_crypt::entropy_bank::SecurityLevel;
+use citadel_crypt::stacked_ratchet::{Ratchet, StackedRatchet};
+use citadel_user::backend::PersistenceHandler;
+use rand::RngCore;
+use std::time::Duration;
+
+/// The number of random bytes inside a resumption ticket
+pub const RESUMPTION_TICKET_LEN: usize = 32;
+
+const SESSION_RESUMPTION: &str = "session_resumption";
+/// The sub key under which the server keeps the tickets it issued
+pub const ISSUED: &str = "issued";
+/// The sub key under which the client keeps the tickets it received
+pub const HELD: &str = "held";
+
+/// Stores session resumption tickets in the byte map of each account, so that they survive a
+/// restart of either node. The server keeps the tickets it issued, while the client keeps the
+/// tickets it received. Tickets are single-use, and are kept encrypted under the account's static
+/// auxiliary ratchet while at rest
+pub struct ResumptionTicketStore<'a> {
+    persistence_handler: &'a PersistenceHandler,
+    sub_key: &'static str,
+}
+
+impl<'a> ResumptionTicketStore<'a> {
+    /// `sub_key` is either [`ISSUED`] or [`HELD`]
+    pub fn new(persistence_handler: &'a PersistenceHandler, sub_key: &'static str) -> Self {
+        Self {
+            persistence_handler,
+            sub_key,
+        }
+    }
+
+    /// Generates a new random ticket
+    pub fn generate_ticket() -> Vec<u8> {
+        let mut ticket = vec![0u8; RESUMPTION_TICKET_LEN];
+        rand::thread_rng().fill_bytes(&mut ticket);
+        ticket
+    }
+
+    /// Stores the ticket for `cid`, replacing any previous ticket. If `lifetime` is None, the
+    /// ticket does not expire locally
+    pub async fn insert(
+        &self,
+        cid: u64,
+        ticket: &[u8],
+        lifetime: Option<Duration>,
+        static_aux_ratchet: &StackedRatchet,
+    ) -> Result<(), NetworkError> {
+        let encrypted_ticket = seal(ticket, static_aux_ratchet)?;
+        let _ = match lifetime {
+            Some(lifetime) => {
+                self.persistence_handler
+                    .store_byte_map_value_with_expiry(
+                        cid,
+                        0,
+                        SESSION_RESUMPTION,
+                        self.sub_key,
+                        encrypted_ticket,
+                        lifetime,
+                    )
+                    .await?
+            }
+
+            None => {
+                self.persistence_handler
+                    .store_byte_map_value(
+                        cid,
+                        0,
+                        SESSION_RESUMPTION,
+                        self.sub_key,
+                        encrypted_ticket,
+                    )
+                    .await?
+            }
+        };
+
+        Ok(())
+    }
+
+    /// Returns the ticket for `cid` without removing it, unless it is missing or expired
+    pub async fn peek(
+        &self,
+        cid: u64,
+        static_aux_ratchet: &StackedRatchet,
+    ) -> Result<Option<Vec<u8>>, NetworkError> {
+        let encrypted_ticket = self
+            .persistence_handler
+            .get_byte_map_value(cid, 0, SESSION_RESUMPTION, self.sub_key)
+            .await?;
+        Ok(
+            encrypted_ticket
+                .and_then(|encrypted_ticket| open(encrypted_ticket, static_aux_ratchet)),
+        )
+    }
+
+    /// Passes the ticket for `cid`, if any, to `validate`, which returns its output alongside
+    /// whether the ticket was redeemed. The ticket is only removed once `validate` succeeds and
+    /// redeems it, so that a packet which fails validation, such as one forged with another
+    /// client's CID, cannot consume the ticket
+    pub async fn redeem_validated<T>(
+        &self,
+        cid: u64,
+        static_aux_ratchet: &StackedRatchet,
+        validate: impl FnOnce(Option<&[u8]>) -> Result<(T, bool), NetworkError>,
+    ) -> Result<T, NetworkError> {
+        let stored = self.peek(cid, static_aux_ratchet).await?;
+        let (output, redeemed) = validate(stored.as_deref())?;
+        if redeemed {
+            // another packet may have redeemed the same ticket while this one was validated
+            let taken = self.take(cid, static_aux_ratchet).await?;
+            if !redeem(taken.as_deref(), stored.as_deref().unwrap_or_default()) {
+                return Err(NetworkError::InternalError(
+                    "Resumption ticket was already redeemed",
+                ));
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Removes and returns the ticket for `cid`, unless it is missing or expired
+    pub async fn take(
+        &self,
+        cid: u64,
+        static_aux_ratchet: &StackedRatchet,
+    ) -> Result<Option<Vec<u8>>, NetworkError> {
+        let encrypted_ticket = self
+            .persistence_handler
+            .remove_byte_map_value(cid, 0, SESSION_RESUMPTION, self.sub_key)
+            .await?;
+        Ok(
+            encrypted_ticket
+                .and_then(|encrypted_ticket| open(encrypted_ticket, static_aux_ratchet)),
+        )
+    }
+}
+
+/// Returns true only if the `presented` ticket matches the `stored` ticket, if any
+pub fn redeem(stored: Option<&[u8]>, presented: &[u8]) -> bool {
+    stored
+        .map(|ticket| constant_time_eq(ticket, presented))
+        .unwrap_or(false)
+}
+
+fn seal(ticket: &[u8], static_aux_ratchet: &StackedRatchet) -> Result<Vec<u8>, NetworkError> {
+    static_aux_ratchet
+        .local_encrypt(ticket, SecurityLevel::Standard)
+        .map_err(|err| NetworkError::Generic(err.into_string()))
+}
+
+fn open(encrypted_ticket: Vec<u8>, static_aux_ratchet: &StackedRatchet) -> Option<Vec<u8>> {
+    static_aux_ratchet
+        .local_decrypt(encrypted_ticket, SecurityLevel::Standard)
+        .ok()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::NetworkError;
+    use crate::proto::misc::session_resumption::{
+        open, redeem, seal, ResumptionTicketStore, ISSUED,
+    };
+    use citadel_crypt::stacked_ratchet::constructor::{
+        BobToAliceTransferType, StackedRatchetConstructor,
+    };
+    use citadel_crypt::stacked_ratchet::StackedRatchet;
+    use citadel_pqcrypto::algorithm_dictionary::CryptoParameters;
+    use citadel_pqcrypto::constructor_opts::ConstructorOpts;
+    use citadel_user::account_manager::AccountManager;
+    use citadel_user::backend::BackendType;
+
+    const CID: u64 = 99;
+
+    fn ratchet() -> StackedRatchet {
+        let opts = || ConstructorOpts::new_vec_init(None::<CryptoParameters>, 1);
+        let mut alice = StackedRatchetConstructor::new_alice(opts(), CID, 0, None).unwrap();
+        let bob = StackedRatchetConstructor::new_bob(CID, 0, opts(), alice.stage0_alice().unwrap())
+            .unwrap();
+        alice
+            .stage1_alice(BobToAliceTransferType::Default(bob.stage0_bob().unwrap()))
+            .unwrap();
+        alice.finish().unwrap()
+    }
+
+    #[test]
+    fn only_matching_ticket_redeems() {
+        let ticket = ResumptionTicketStore::generate_ticket();
+        let stored = Some(ticket.as_slice());
+        assert!(redeem(stored, &ticket));
+        assert!(!redeem(stored, &ResumptionTicketStore::generate_ticket()));
+        assert!(!redeem(stored, &ticket[1..]));
+        // a ticket that was never issued, or that expired, is absent
+        assert!(!redeem(None, &ticket));
+    }
+
+    #[tokio::test]
+    async fn invalid_packet_does_not_consume_ticket() {
+        let account_manager = AccountManager::new(BackendType::InMemory, None, None, None)
+            .await
+            .unwrap();
+        let store = ResumptionTicketStore::new(account_manager.get_persistence_handler(), ISSUED);
+        let ratchet = ratchet();
+        let ticket = ResumptionTicketStore::generate_ticket();
+        store.insert(CID, &ticket, None, &ratchet).await.unwrap();
+
+        // e.g., a SYN naming this CID that fails authentication
+        let forged = store
+            .redeem_validated(CID, &ratchet, |_| {
+                Err::<((), bool), _>(NetworkError::InternalError("Unable to validate"))
+            })
+            .await;
+        assert!(forged.is_err());
+        assert_eq!(
+            store.peek(CID, &ratchet).await.unwrap(),
+            Some(ticket.clone())
+        );
+
+        // a valid SYN presenting another ticket falls back without redeeming the stored one
+        store
+            .redeem_validated(CID, &ratchet, |stored| {
+                let presented = ResumptionTicketStore::generate_ticket();
+                Ok(((), redeem(stored, &presented)))
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            store.peek(CID, &ratchet).await.unwrap(),
+            Some(ticket.clone())
+        );
+
+        // the valid SYN presenting the ticket redeems it exactly once
+        store
+            .redeem_validated(CID, &ratchet, |stored| {
+                assert!(redeem(stored, &ticket));
+                Ok(((), true))
+            })
+            .await
+            .unwrap();
+        assert_eq!(store.peek(CID, &ratchet).await.unwrap(), None);
+        store
+            .redeem_validated(CID, &ratchet, |stored| {
+                assert!(!redeem(stored, &ticket));
+                Ok(((), false))
+            })
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn ticket_stored_encrypted() {
+        let ratchet = ratchet();
+        let ticket = ResumptionTicketStore::generate_ticket();
+        let stored = seal(&ticket, &ratchet).unwrap();
+
+        assert!(!stored.windows(ticket.len()).any(|window| window == ticket));
+        assert_eq!(open(stored.clone(), &ratchet), Some(ticket));
+        // a ticket sealed under another account's ratchet does not open
+        assert_eq!(open(stored, &ratchet()), None);
+    }
+}
@@ -1,6 +1,8 @@
 use crate::proto::node::SecrecyMode;
 use citadel_crypt::entropy_bank::SecurityLevel;
-use citadel_pqcrypto::algorithm_dictionary::CryptoParameters;
+use citadel_pqcrypto::algorithm_dictionary::{
+    AlgorithmsExt, CryptoParameters, EncryptionAlgorithm, KemAlgorithm, SigAlgorithm,
+};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, Default)]
@@ -8,6 +10,22 @@ pub struct SessionSecuritySettings {
     pub security_level: SecurityLevel,
     pub secrecy_mode: SecrecyMode,
     pub crypto_params: CryptoParameters,
+    pub header_protection: HeaderProtection,
+}
+
+/// Determines how the header of each packet is protected once the session is connected. Header
+/// protection only applies when header obfuscation is enabled on both nodes
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum HeaderProtection {
+    /// Headers remain scrambled with the key sent in the plaintext init packet for the lifetime
+    /// of the session. This resists fingerprinting, but an observer who captured the init packet
+    /// may recover every header
+    #[default]
+    Obfuscated,
+    /// Once connected, headers are encrypted with a key derived from the session's ratchet, such
+    /// that an observer without the session's secret cannot recover them, even if the init packet
+    /// was captured
+    SessionKeyed,
 }
 
 #[derive(Default)]
@@ -15,6 +33,7 @@ pub struct SessionSecuritySettingsBuilder {
     security_level: Option<SecurityLevel>,
     secrecy_mode: Option<SecrecyMode>,
     crypto_params: Option<CryptoParameters>,
+    header_protection: Option<HeaderProtection>,
 }
 
 impl SessionSecuritySettingsBuilder {
@@ -59,15 +78,152 @@ impl SessionSecuritySettingsBuilder {
         self
     }
 
+    /// Sets how headers are protected once the session is connected (default: Obfuscated)
+    /// ```
+    /// use citadel_proto::prelude::{SessionSecuritySettingsBuilder, HeaderProtection};
+    /// SessionSecuritySettingsBuilder::default()
+    /// .with_header_protection(HeaderProtection::SessionKeyed)
+    /// .build();
+    /// ```
+    pub fn with_header_protection(mut self, header_protection: HeaderProtection) -> Self {
+        self.header_protection = Some(header_protection);
+        self
+    }
+
     /// Constructs the [`SessionSecuritySettings`]
     pub fn build(self) -> Result<SessionSecuritySettings, anyhow::Error> {
         let settings = SessionSecuritySettings {
             security_level: self.security_level.unwrap_or(SecurityLevel::Standard),
             secrecy_mode: self.secrecy_mode.unwrap_or(SecrecyMode::BestEffort),
             crypto_params: self.crypto_params.unwrap_or_default(),
+            header_protection: self.header_protection.unwrap_or_default(),
         };
 
         citadel_pqcrypto::validate_crypto_params(&settings.crypto_params)?;
         Ok(settings)
     }
 }
+
+/// The algorithms a node supports, as reported by [`NodeRemote::supported_algorithms`](crate::prelude::NodeRemote::supported_algorithms)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SupportedAlgorithms {
+    /// Every supported symmetric or asymmetric encryption algorithm
+    pub encryption_algorithms: Vec<EncryptionAlgorithm>,
+    /// Every supported key encapsulation mechanism
+    pub kem_algorithms: Vec<KemAlgorithm>,
+    /// Every supported signature algorithm
+    pub sig_algorithms: Vec<SigAlgorithm>,
+    /// Every combination of the above that negotiation accepts. Not every combination is valid;
+    /// for example, Kyber encryption requires a signature algorithm
+    pub crypto_params: Vec<CryptoParameters>,
+    /// Every supported secrecy mode
+    pub secrecy_modes: Vec<SecrecyMode>,
+    /// Every ratchet variant used to derive session keys
+    pub ratchet_variants: Vec<RatchetVariant>,
+    /// Every codec used to compress payloads. Compression is applied transparently and is not
+    /// negotiated
+    pub compression_codecs: Vec<CompressionCodec>,
+}
+
+/// A variant of ratchet used to derive session keys
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RatchetVariant {
+    /// The multi-layered ratchet used for client-server and peer-to-peer sessions
+    Stacked,
+    /// The single-layered ratchet used for FCM messaging
+    Thin,
+}
+
+/// A codec used to compress payloads
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CompressionCodec {
+    /// Used for large peer lists
+    Deflate,
+}
+
+impl Default for SupportedAlgorithms {
+    fn default() -> Self {
+        let mut crypto_params = Vec::new();
+        for encryption_algorithm in EncryptionAlgorithm::list() {
+            for kem_algorithm in KemAlgorithm::list() {
+                for sig_algorithm in SigAlgorithm::list() {
+                    let params = encryption_algorithm + kem_algorithm + sig_algorithm;
+                    if citadel_pqcrypto::validate_crypto_params(&params).is_ok() {
+                        crypto_params.push(params);
+                    }
+                }
+            }
+        }
+
+        Self {
+            encryption_algorithms: EncryptionAlgorithm::list(),
+            kem_algorithms: KemAlgorithm::list(),
+            sig_algorithms: SigAlgorithm::list(),
+            crypto_params,
+            secrecy_modes: vec![SecrecyMode::Perfect, SecrecyMode::BestEffort],
+            ratchet_variants: vec![RatchetVariant::Stacked, RatchetVariant::Thin],
+            compression_codecs: vec![CompressionCodec::Deflate],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::misc::session_security_settings::{
+        SessionSecuritySettingsBuilder, SupportedAlgorithms,
+    };
+    use citadel_pqcrypto::algorithm_dictionary::CryptoParameters;
+
+    #[test]
+    fn supported_algorithms_match_negotiation() {
+        let supported = SupportedAlgorithms::default();
+
+        // every packed parameter byte is either advertised and accepted, or neither
+        let mut accepted = 0;
+        for packed in 0..=u8::MAX {
+            let advertised = supported
+                .crypto_params
+                .iter()
+                .any(|params| u8::from(*params) == packed);
+            match CryptoParameters::try_from(packed) {
+                Ok(params) => {
+                    assert!(advertised, "{params:?} is accepted but not advertised");
+                    assert!(supported
+                        .encryption_algorithms
+                        .contains(&params.encryption_algorithm));
+                    assert!(supported.kem_algorithms.contains(&params.kem_algorithm));
+                    assert!(supported.sig_algorithms.contains(&params.sig_algorithm));
+                    accepted += 1;
+                }
+
+                Err(_) => assert!(!advertised, "{packed} is advertised but rejected"),
+            }
+        }
+
+        assert_eq!(accepted, supported.crypto_params.len());
+
+        for params in supported.crypto_params.iter().copied() {
+            for secrecy_mode in supported.secrecy_modes.iter().copied() {
+                assert!(SessionSecuritySettingsBuilder::default()
+                    .with_crypto_params(params)
+                    .with_secrecy_mode(secrecy_mode)
+                    .build()
+                    .is_ok());
+            }
+        }
+
+        // every advertised algorithm is usable in at least one accepted combination
+        for alg in supported.encryption_algorithms {
+            assert!(supported
+                .crypto_params
+                .iter()
+                .any(|params| params.encryption_algorithm == alg));
+        }
+        for alg in supported.sig_algorithms {
+            assert!(supported
+                .crypto_params
+                .iter()
+                .any(|params| params.sig_algorithm == alg));
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use crate::proto::misc::session_security_settings::SessionSecuritySettings;
+use crate::proto::peer::peer_layer::UdpMode;
+use crate::proto::session::SessionState;
+use crate::proto::state_container::VirtualConnectionType;
+use citadel_crypt::endpoint_crypto_container::PeerSessionCrypto;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+
+/// A snapshot of a session's internal state, meant to be attached to bug reports when a session
+/// misbehaves. It contains no key material
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionStateDump {
+    /// The CID of the client this session belongs to. None until the session is connected
+    pub implicated_cid: Option<u64>,
+    pub remote_peer: SocketAddr,
+    pub is_server: bool,
+    /// The position of the session's state machine
+    pub state: SessionState,
+    /// The parameters negotiated for the session
+    pub security_settings: Option<SessionSecuritySettings>,
+    pub udp_mode: UdpMode,
+    /// The drill state of the client-to-server channel
+    pub c2s_crypto: Option<CryptoStateDump>,
+    /// The virtual connections to peers
+    pub virtual_connections: Vec<VirtualConnectionStateDump>,
+    /// The number of packets waiting for a re-key to finish before being sent
+    pub enqueued_packets: usize,
+    pub inbound_file_transfers: usize,
+    pub outbound_file_transfers: usize,
+    pub inbound_groups: usize,
+    pub outbound_groups: usize,
+    /// The global timestamp of the last keep alive, in nanoseconds
+    pub last_keep_alive_ns: Option<i64>,
+    pub ping_ns: Option<i64>,
+    pub jitter_ns: Option<i64>,
+    pub rtt_ns: Option<i64>,
+    /// The estimated amount the clock of the adjacent node is ahead of the local clock, including
+    /// the one-way latency
+    pub clock_offset_ns: Option<i64>,
+    /// The time elapsed since a packet was last confirmed, in milliseconds
+    pub millis_since_last_valid_event: u64,
+    pub total_plaintext_bytes_sent: isize,
+    /// The number of inbound packets discarded because their header checksum did not match
+    pub corrupt_headers_discarded: u64,
+}
+
+/// The drill state of a channel
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CryptoStateDump {
+    /// Every drill version retained, from oldest to newest
+    pub active_drill_versions: Vec<u32>,
+    pub latest_usable_version: u32,
+    pub update_in_progress: bool,
+    pub has_deferred_truncation: bool,
+}
+
+/// The state of a single virtual connection
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VirtualConnectionStateDump {
+    pub peer_cid: u64,
+    pub connection_type: VirtualConnectionType,
+    pub is_active: bool,
+    /// None when this node only relays traffic for the connection
+    pub crypto: Option<CryptoStateDump>,
+}
+
+impl From<&PeerSessionCrypto> for CryptoStateDump {
+    fn from(crypt: &PeerSessionCrypto) -> Self {
+        Self {
+            active_drill_versions: crypt.get_active_hyper_ratchet_versions(),
+            latest_usable_version: crypt.latest_usable_version,
+            update_in_progress: crypt.update_in_progress.load(Ordering::Relaxed),
+            has_deferred_truncation: crypt.has_deferred_truncation(),
+        }
+    }
+}
@@ -22,18 +22,27 @@ use crate::functional::PairMap;
 use crate::kernel::kernel_communicator::KernelAsyncCallbackHandler;
 use crate::kernel::RuntimeFuture;
 use crate::prelude::{DeleteObject, PullObject};
+use crate::proto::misc::effective_config::EffectiveConfig;
+use crate::proto::misc::fault_injection::FaultInjector;
+use crate::proto::misc::keep_alive_policy::KeepAlivePolicy;
 use crate::proto::misc::net::{
     DualListener, FirstPacket, GenericNetworkListener, GenericNetworkStream, TlsListener,
 };
+use crate::proto::misc::protocol_version::ProtocolVersionRange;
 use crate::proto::misc::underlying_proto::ServerUnderlyingProtocol;
 use crate::proto::node_request::{
-    ConnectToHypernode, DeregisterFromHypernode, DisconnectFromHypernode, GroupBroadcastCommand,
-    NodeRequest, PeerCommand, ReKey, RegisterToHypernode, SendObject,
+    ConnectToHypernode, DeregisterFromHypernode, DisconnectFromHypernode, GetActiveDrillVersions,
+    GetPendingReKeyStatus, GetSessionState, GroupBroadcastCommand, NodeRequest, PeerCommand, ReKey,
+    RegisterToHypernode, SendObject,
+};
+use crate::proto::node_result::{
+    ActiveDrillVersions, HandshakeMetricsResult, InternalServerError, NodeResult,
+    PendingReKeyStatusResult, SessionList, SessionStateDumpResult,
 };
-use crate::proto::node_result::{InternalServerError, NodeResult, SessionList};
 use crate::proto::outbound_sender::{unbounded, BoundedReceiver, BoundedSender, UnboundedSender};
 use crate::proto::packet_processor::includes::Duration;
 use crate::proto::peer::p2p_conn_handler::generic_error;
+use crate::proto::peer::peer_identity::PeerIdentitySettings;
 use crate::proto::remote::{NodeRemote, Ticket};
 use crate::proto::session::{HdpSession, HdpSessionInitMode};
 use crate::proto::session_manager::HdpSessionManager;
@@ -73,6 +82,12 @@ impl HdpServer {
         underlying_proto: ServerUnderlyingProtocol,
         client_config: Option<Arc<ClientConfig>>,
         stun_servers: Option<Vec<String>>,
+        header_obfuscation: bool,
+        peer_identity_settings: PeerIdentitySettings,
+        supported_protocol_versions: ProtocolVersionRange,
+        fault_injector: Option<FaultInjector>,
+        keep_alive_policy: KeepAlivePolicy,
+        effective_config: EffectiveConfig,
     ) -> io::Result<(
         NodeRemote,
         Pin<Box<dyn RuntimeFuture>>,
@@ -113,6 +128,11 @@ impl HdpServer {
             time_tracker,
             client_config.clone(),
             stun_servers.clone(),
+            header_obfuscation,
+            Arc::new(peer_identity_settings),
+            supported_protocol_versions,
+            fault_injector,
+            keep_alive_policy,
         );
 
         let nat_type = NatType::identify(stun_servers)
@@ -130,7 +150,12 @@ impl HdpServer {
         };
 
         let this = Self::from(inner);
-        Ok(HdpServer::load(this, account_manager, shutdown))
+        Ok(HdpServer::load(
+            this,
+            account_manager,
+            effective_config,
+            shutdown,
+        ))
     }
 
     /// Note: spawning via handle is more efficient than joining futures. Source: https://cafbit.com/post/tokio_internals/
@@ -143,6 +168,7 @@ impl HdpServer {
     fn load(
         this: HdpServer,
         account_manager: AccountManager,
+        effective_config: EffectiveConfig,
         shutdown: tokio::sync::oneshot::Sender<()>,
     ) -> (
         NodeRemote,
@@ -169,6 +195,7 @@ impl HdpServer {
             kernel_async_callback_handler.clone(),
             account_manager,
             node_type,
+            effective_config,
         );
         let tt = read
             .session_manager
@@ -905,6 +932,74 @@ impl HdpServer {
                     }
                 }
 
+                NodeRequest::GetActiveDrillVersions(GetActiveDrillVersions {
+                    v_conn_type: virtual_target,
+                }) => match session_manager.get_active_drill_versions(virtual_target) {
+                    Ok(versions) => {
+                        if let Err(err) = to_kernel_tx.unbounded_send(
+                            NodeResult::ActiveDrillVersions(ActiveDrillVersions {
+                                ticket: ticket_id,
+                                versions,
+                            }),
+                        ) {
+                            send_error(ticket_id, NetworkError::Generic(err.to_string()))?;
+                        }
+                    }
+
+                    Err(err) => {
+                        send_error(ticket_id, err)?;
+                    }
+                },
+
+                NodeRequest::GetSessionState(GetSessionState { session_cid }) => {
+                    match session_manager.dump_session_state(session_cid) {
+                        Ok(dump) => {
+                            if let Err(err) = to_kernel_tx.unbounded_send(
+                                NodeResult::SessionStateDump(SessionStateDumpResult {
+                                    ticket: ticket_id,
+                                    dump,
+                                }),
+                            ) {
+                                send_error(ticket_id, NetworkError::Generic(err.to_string()))?;
+                            }
+                        }
+
+                        Err(err) => {
+                            send_error(ticket_id, err)?;
+                        }
+                    }
+                }
+
+                NodeRequest::GetPendingReKeyStatus(GetPendingReKeyStatus { peer_cid }) => {
+                    match session_manager.pending_rekey_status(peer_cid) {
+                        Ok(status) => {
+                            if let Err(err) = to_kernel_tx.unbounded_send(
+                                NodeResult::PendingReKeyStatus(PendingReKeyStatusResult {
+                                    ticket: ticket_id,
+                                    status,
+                                }),
+                            ) {
+                                send_error(ticket_id, NetworkError::Generic(err.to_string()))?;
+                            }
+                        }
+
+                        Err(err) => {
+                            send_error(ticket_id, err)?;
+                        }
+                    }
+                }
+
+                NodeRequest::GetHandshakeMetrics => {
+                    if let Err(err) = to_kernel_tx.unbounded_send(NodeResult::HandshakeMetrics(
+                        HandshakeMetricsResult {
+                            ticket: ticket_id,
+                            metrics: session_manager.handshake_metrics(),
+                        },
+                    )) {
+                        send_error(ticket_id, NetworkError::Generic(err.to_string()))?;
+                    }
+                }
+
                 NodeRequest::Shutdown => {
                     break;
                 }
@@ -919,6 +1014,7 @@ pub(crate) struct HdpServerRemoteInner {
     pub callback_handler: KernelAsyncCallbackHandler,
     pub node_type: NodeType,
     pub account_manager: AccountManager,
+    pub effective_config: EffectiveConfig,
 }
 
 #[derive(Copy, Clone, Serialize, Deserialize, Debug)]
@@ -38,6 +38,18 @@ pub struct ReKey {
     pub v_conn_type: VirtualTargetType,
 }
 
+pub struct GetActiveDrillVersions {
+    pub v_conn_type: VirtualTargetType,
+}
+
+pub struct GetSessionState {
+    pub session_cid: u64,
+}
+
+pub struct GetPendingReKeyStatus {
+    pub peer_cid: u64,
+}
+
 // Also used for updating objects
 pub struct SendObject {
     pub source: Box<dyn ObjectSource>,
@@ -96,6 +108,14 @@ pub enum NodeRequest {
     DisconnectFromHypernode(DisconnectFromHypernode),
     /// Returns a list of connected sessions
     GetActiveSessions,
+    /// Returns the drill versions currently retained for a virtual connection
+    GetActiveDrillVersions(GetActiveDrillVersions),
+    /// Returns a snapshot of a session's state for diagnostics
+    GetSessionState(GetSessionState),
+    /// Returns the state of the re-keying process with a peer
+    GetPendingReKeyStatus(GetPendingReKeyStatus),
+    /// Returns the counters describing the handshakes that have not yet completed
+    GetHandshakeMetrics,
     /// shutdown signal
     Shutdown,
 }
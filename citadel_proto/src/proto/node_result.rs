@@ -1,7 +1,11 @@
+use crate::error::ConnectError;
 use crate::prelude::{GroupBroadcast, GroupChannel, PeerChannel, PeerSignal, UdpChannel};
+use crate::proto::misc::pending_handshakes::HandshakeMetrics;
+use crate::proto::misc::session_state_dump::SessionStateDump;
 use crate::proto::peer::peer_layer::MailboxTransfer;
 use crate::proto::remote::Ticket;
 use crate::proto::state_container::VirtualConnectionType;
+use crate::proto::state_subcontainers::rekey_container::PendingReKeyStatus;
 
 use citadel_user::backend::utils::ObjectTransferHandler;
 use citadel_user::client_account::ClientNetworkAccount;
@@ -39,6 +43,7 @@ pub struct ConnectSuccess {
     pub welcome_message: String,
     pub channel: PeerChannel,
     pub udp_rx_opt: Option<tokio::sync::oneshot::Receiver<UdpChannel>>,
+    pub session_resumed: bool,
 }
 
 #[derive(Debug)]
@@ -46,6 +51,8 @@ pub struct ConnectFail {
     pub ticket: Ticket,
     pub cid_opt: Option<u64>,
     pub error_message: String,
+    /// Why the server rejected the connect, if it said so
+    pub connect_error: Option<ConnectError>,
 }
 
 #[derive(Debug)]
@@ -127,6 +134,30 @@ pub struct SessionList {
     pub sessions: Vec<u64>,
 }
 
+#[derive(Debug)]
+pub struct ActiveDrillVersions {
+    pub ticket: Ticket,
+    pub versions: Vec<u32>,
+}
+
+#[derive(Debug)]
+pub struct SessionStateDumpResult {
+    pub ticket: Ticket,
+    pub dump: SessionStateDump,
+}
+
+#[derive(Debug)]
+pub struct PendingReKeyStatusResult {
+    pub ticket: Ticket,
+    pub status: PendingReKeyStatus,
+}
+
+#[derive(Debug)]
+pub struct HandshakeMetricsResult {
+    pub ticket: Ticket,
+    pub metrics: HandshakeMetrics,
+}
+
 #[derive(Debug)]
 pub struct ReVFSResult {
     pub error_message: Option<String>,
@@ -169,6 +200,14 @@ pub enum NodeResult {
     PeerChannelCreated(PeerChannelCreated),
     /// A list of running sessions
     SessionList(SessionList),
+    /// The drill versions retained for a virtual connection, from oldest to newest
+    ActiveDrillVersions(ActiveDrillVersions),
+    /// A snapshot of a session's state
+    SessionStateDump(SessionStateDumpResult),
+    /// The state of the re-keying process with a peer
+    PendingReKeyStatus(PendingReKeyStatusResult),
+    /// The counters describing the handshakes that have not yet completed
+    HandshakeMetrics(HandshakeMetricsResult),
     /// For shutdowns
     Shutdown,
 }
@@ -199,6 +238,7 @@ impl NodeResult {
                 ticket: t,
                 cid_opt: _,
                 error_message: _,
+                connect_error: _,
             }) => Some(*t),
             NodeResult::OutboundRequestRejected(OutboundRequestRejected {
                 ticket: t,
@@ -239,6 +279,15 @@ impl NodeResult {
                 ticket: t,
                 sessions: _,
             }) => Some(*t),
+            NodeResult::ActiveDrillVersions(ActiveDrillVersions {
+                ticket: t,
+                versions: _,
+            }) => Some(*t),
+            NodeResult::SessionStateDump(SessionStateDumpResult { ticket, .. }) => Some(*ticket),
+            NodeResult::PendingReKeyStatus(PendingReKeyStatusResult { ticket, .. }) => {
+                Some(*ticket)
+            }
+            NodeResult::HandshakeMetrics(HandshakeMetricsResult { ticket, .. }) => Some(*ticket),
             NodeResult::Shutdown => None,
             NodeResult::ReKeyResult(ReKeyResult { ticket, .. }) => Some(*ticket),
             NodeResult::ReVFS(ReVFSResult { ticket, .. }) => Some(*ticket),
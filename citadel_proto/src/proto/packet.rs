@@ -1,8 +1,13 @@
 use byteorder::NetworkEndian;
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use rand::rngs::ThreadRng;
+use rand::{Rng, RngCore};
 use zerocopy::{AsBytes, FromBytes, LayoutVerified, Unaligned, I64, U128, U32, U64};
 
-use crate::constants::HDP_HEADER_BYTE_LEN;
+use crate::constants::{HDP_HEADER_BYTE_LEN, HDP_HEADER_CHECKSUM_LEN, HEADER_CHECKSUM_VERSION};
+use crate::proto::misc::discard_log::DiscardLog;
+use crate::proto::misc::dual_cell::DualCell;
+use sha3::Digest;
 use std::net::SocketAddr;
 
 pub(crate) mod packet_flags {
@@ -126,22 +131,56 @@ pub(crate) mod packet_flags {
             pub(crate) const TCP_ONLY: u8 = 1;
         }
     }
+
+    /// The values of the header fields that select the cryptography applied to a packet. Headers
+    /// outside of them are rejected by [`HdpPacket::parse`](crate::proto::packet::HdpPacket::parse)
+    pub(crate) mod header_bounds {
+        use citadel_pqcrypto::algorithm_dictionary::CryptoParameters;
+        use std::convert::TryFrom;
+
+        /// The lowest security level, [`SecurityLevel::Standard`](citadel_crypt::entropy_bank::SecurityLevel::Standard)
+        pub(crate) const MIN_SECURITY_LEVEL: u8 = 0;
+        /// The highest security level, [`SecurityLevel::Extreme`](citadel_crypt::entropy_bank::SecurityLevel::Extreme)
+        pub(crate) const MAX_SECURITY_LEVEL: u8 = 4;
+
+        /// The algorithm byte is either a flag (such as
+        /// [`TCP_ONLY`](super::payload_identifiers::do_preconnect::TCP_ONLY)) or packed
+        /// [`CryptoParameters`]. The flags in use coincide with valid parameters, so any byte that
+        /// does not unpack into a valid combination of algorithms is unknown
+        pub(crate) fn is_known_algorithm(algorithm: u8) -> bool {
+            CryptoParameters::try_from(algorithm).is_ok()
+        }
+    }
 }
 
 pub(crate) mod packet_sizes {
     use crate::constants::HDP_HEADER_BYTE_LEN;
 
-    /// Group packets
+    /// Group packets. The initial capacity reserved for a group header, which grows as the
+    /// variable-length [`GroupHeader`](crate::proto::validation::group::GroupHeader) is written
     pub(crate) const GROUP_HEADER_BASE_LEN: usize = HDP_HEADER_BYTE_LEN + 1;
+    /// The header, followed by the fixed-width fields of a
+    /// [`GroupHeaderAck::ReadyToReceive`](crate::proto::validation::group::GroupHeaderAck::ReadyToReceive)
+    /// carrying a window: the fast message flag, the tag of the window, and the bounds of the window
     pub(crate) const GROUP_HEADER_ACK_LEN: usize = HDP_HEADER_BYTE_LEN + 1 + 1 + 4 + 4;
 
     pub(crate) mod do_drill_update {
         use crate::constants::HDP_HEADER_BYTE_LEN;
 
+        /// The initial capacity reserved for a stage 1 packet, which grows as the KEM transfer is
+        /// written
         pub(crate) const STAGE1: usize = HDP_HEADER_BYTE_LEN + HDP_HEADER_BYTE_LEN;
     }
 }
 
+// The header is the wire format, so adding, removing, or resizing one of its fields fails to
+// compile until this sum (and every packet size derived from the header) is updated deliberately.
+// The fields are Unaligned, so the struct has no padding
+const _: () = assert!(
+    HDP_HEADER_BYTE_LEN == 1 + 1 + 1 + 1 + 4 + 16 + 8 + 4 + 8 + 4 + 8 + 8,
+    "the size of HdpHeader changed; update the wire format and packet_sizes"
+);
+
 #[derive(Debug, AsBytes, FromBytes, Unaligned, Clone)]
 #[repr(C)]
 /// The header for each [HdpPacket]
@@ -152,7 +191,7 @@ pub struct HdpHeader {
     pub cmd_aux: u8,
     // This tells the encryption protocol what algorithm to use to decrypt the payload
     pub algorithm: u8,
-    /// A value [0,4]
+    /// A value [0,4]. Headers outside this range are rejected on parse
     pub security_level: u8,
     pub protocol_version: U32<NetworkEndian>,
     /// Some commands require arguments; the u128 can hold 16 bytes
@@ -187,6 +226,72 @@ impl HdpHeader {
     pub fn as_packet(&self) -> BytesMut {
         BytesMut::from(self.as_bytes())
     }
+
+    /// Returns true if packets bearing this header carry a trailing CRC32 of the header. Headers
+    /// from protocol versions predating [`HEADER_CHECKSUM_VERSION`] never do
+    pub fn has_checksum(&self) -> bool {
+        match embedded_semver::Semver::from_u32(self.protocol_version.get()) {
+            Ok(version) => {
+                let (major, minor, patch) = HEADER_CHECKSUM_VERSION;
+                (version.major, version.minor, version.patch)
+                    >= (major as usize, minor as usize, patch as usize)
+            }
+
+            Err(_) => false,
+        }
+    }
+
+    /// Computes the CRC32 of the header bytes
+    pub fn checksum(&self) -> u32 {
+        HEADER_CRC.checksum(self.as_bytes())
+    }
+
+    /// Returns false if the security level or algorithm lies outside the
+    /// [`header_bounds`](packet_flags::header_bounds), as is the case for corrupt or malicious headers
+    pub fn is_valid(&self) -> bool {
+        (packet_flags::header_bounds::MIN_SECURITY_LEVEL
+            ..=packet_flags::header_bounds::MAX_SECURITY_LEVEL)
+            .contains(&self.security_level)
+            && packet_flags::header_bounds::is_known_algorithm(self.algorithm)
+    }
+}
+
+const HEADER_CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+/// Appends the CRC32 of the header to an outbound packet if the header's protocol version calls for
+/// one. The checksum trails the entire packet, since everything after the header may be ciphertext.
+/// Must be applied before header obfuscation
+pub fn append_header_checksum(packet: &mut BytesMut) {
+    let checksum = match LayoutVerified::<_, HdpHeader>::new_from_prefix(&packet[..]) {
+        Some((header, _)) if header.has_checksum() => header.checksum(),
+        _ => return,
+    };
+
+    packet.put_u32(checksum);
+}
+
+/// Verifies and strips the trailing header checksum from an inbound packet if the header's protocol
+/// version calls for one. Returns None if the checksum is missing or does not match, in which case
+/// the packet should be discarded before it reaches the packet processors. Must be applied after
+/// the header is de-obfuscated
+pub fn verify_header_checksum(packet: &mut BytesMut) -> Option<()> {
+    let (header, payload) = LayoutVerified::<_, HdpHeader>::new_from_prefix(&packet[..])?;
+    if !header.has_checksum() {
+        return Some(());
+    }
+
+    if payload.len() < HDP_HEADER_CHECKSUM_LEN {
+        return None;
+    }
+
+    let expected = header.checksum();
+    let checksum_offset = packet.len() - HDP_HEADER_CHECKSUM_LEN;
+    if (&packet[checksum_offset..]).get_u32() != expected {
+        return None;
+    }
+
+    packet.truncate(checksum_offset);
+    Some(())
 }
 
 /// The HdpPacket structure
@@ -208,9 +313,24 @@ impl<B: HdpBuffer> HdpPacket<B> {
         }
     }
 
-    /// Parses the zerocopy header
+    /// Parses the zerocopy header. Returns None if the packet is shorter than the header, or if
+    /// the header is not [valid](HdpHeader::is_valid), so that it is dropped before reaching the
+    /// cryptography
     pub fn parse(&self) -> Option<ParsedPacket> {
-        LayoutVerified::new_from_prefix(self.packet.as_ref())
+        let (header, payload) =
+            LayoutVerified::<_, HdpHeader>::new_from_prefix(self.packet.as_ref())?;
+        if !header.is_valid() {
+            return None;
+        }
+
+        Some((header, payload))
+    }
+
+    /// Borrows the header and the payload that follows it from the underlying buffer, without
+    /// copying nor consuming the packet. Returns None if the packet is shorter than the header
+    pub fn header_and_payload(&self) -> Option<(&HdpHeader, &[u8])> {
+        let (header, payload) = self.parse()?;
+        Some((header.into_ref(), payload))
     }
 
     /// Creates a packet out of the inner device
@@ -232,82 +352,170 @@ impl<B: HdpBuffer> HdpPacket<B> {
 
         (header_bytes, payload_bytes, remote_peer, local_port)
     }
+
+    /// Like [`Self::decompose`], but without allocating: the header is copied out by value, and
+    /// the payload is left at the front of the original buffer, which keeps its capacity so that
+    /// it may be reused by a receive loop. Returns None if the packet is shorter than the header,
+    /// or if the header is not [valid](HdpHeader::is_valid)
+    pub fn decompose_header(mut self) -> Option<(HdpHeader, B, SocketAddr, u16)> {
+        let header = HdpHeader::read_from_prefix(self.packet.as_ref())?;
+        if !header.is_valid() {
+            return None;
+        }
+
+        self.packet.discard_front(HDP_HEADER_BYTE_LEN);
+        Some((header, self.packet, self.remote_peer, self.local_port))
+    }
 }
 
-/*
+/// The context from which the session key of a [`HeaderObfuscator`] is derived
+pub(crate) const HEADER_PROTECTION_KEY_CONTEXT: &[u8] = b"CITADEL_HEADER_PROTECTION";
+/// The number of bytes following the header that seed the keystream of a session-keyed header
+const HEADER_PROTECTION_SAMPLE_LEN: usize = 16;
+
+/// Scrambles the fixed-layout [`HdpHeader`] of each outbound packet so that the header
+/// cannot be trivially fingerprinted. The client generates a random u128 key and sends it
+/// inside an init packet, which the server uses to latch the same key.
+///
+/// Since the init packet is sent in the clear, a session key may be scheduled with
+/// [`Self::schedule_session_key`], which replaces the init key once the session is connected
 #[derive(Clone)]
 pub struct HeaderObfuscator {
-    inner: DualCell<Option<u128>>
+    inner: DualCell<Option<u128>>,
+    pending_session_key: DualCell<Option<[u8; 32]>>,
+    session_key: DualCell<Option<[u8; 32]>>,
+    discard_log: DiscardLog,
 }
 
 impl HeaderObfuscator {
+    /// Returns the obfuscator, as well as the init packet that must be sent first if this node is a client
     pub fn new(is_server: bool) -> (Self, Option<BytesMut>) {
         if is_server {
             (Self::new_server(), None)
         } else {
-            Self::new_client()
-                .map_right(Some)
+            let (this, packet) = Self::new_client();
+            (this, Some(packet))
         }
     }
 
+    /// Returns None if the packet should be discarded (either because it was the init packet, or because it is invalid)
     pub fn on_packet_received(&self, packet: &mut BytesMut) -> Option<()> {
-        //log::trace!(target: "citadel", "[Header-scrambler] RECV {:?}", &packet[..]);
         if let Some(val) = self.load() {
-            //log::trace!(target: "citadel", "[Header-scrambler] received ordinary packet");
-            apply_cipher(val, true, packet);
+            if packet.len() < HDP_HEADER_BYTE_LEN {
+                self.discard_log.on_discard(|| {
+                    format!(
+                        "[Header obfuscator] Packet shorter than header (LEN: {})",
+                        packet.len()
+                    )
+                });
+                return None;
+            }
+
+            if let Some(key) = self.session_key.get() {
+                apply_session_keystream(&key, packet);
+            } else {
+                apply_cipher(val, true, packet);
+                // the connect success packet is the last one scrambled with the init key
+                if is_connect_success(packet) {
+                    self.activate_session_key();
+                }
+            }
+
             Some(())
         } else {
             if packet.len() >= 16 && packet.len() < HDP_HEADER_BYTE_LEN {
-                //log::trace!(target: "citadel", "[Header-Scrambler] Loading first-time packet {:?}", &packet[..]);
                 // we are only interested in taking the first 16 bytes
                 let val0 = packet.get_u64();
                 let val1 = packet.get_u64();
                 self.store(val0, val1);
                 log::trace!(target: "citadel", "[Header obfuscator] initial packet set");
             } else {
-                log::error!(target: "citadel", "Discarding invalid packet (LEN: {})", packet.len());
+                self.discard_log.on_discard(|| {
+                    format!(
+                        "[Header obfuscator] Invalid init packet (LEN: {})",
+                        packet.len()
+                    )
+                });
             }
 
             None
         }
     }
 
+    /// Records discarded packets into `discard_log` instead of a log private to this obfuscator
+    pub fn with_discard_log(mut self, discard_log: DiscardLog) -> Self {
+        self.discard_log = discard_log;
+        self
+    }
+
+    /// Schedules the session key, derived from the session's ratchet, to replace the init key
+    /// once the connect success packet is sent or received. Thereafter, each header is encrypted
+    /// with a keystream derived from the session key and a sample of the bytes following the
+    /// header, such that an observer without the session key cannot recover it. Both nodes must
+    /// schedule the same key before the connect success packet is exchanged
+    pub fn schedule_session_key(&self, key: [u8; 32]) {
+        self.pending_session_key.set(Some(key));
+    }
+
+    /// Returns true once headers are protected by the session key
+    pub fn is_session_keyed(&self) -> bool {
+        self.session_key.get().is_some()
+    }
+
+    fn activate_session_key(&self) {
+        if let Some(key) = self.pending_session_key.get() {
+            self.session_key.set(Some(key));
+            self.pending_session_key.set(None);
+            log::trace!(target: "citadel", "[Header obfuscator] session key activated");
+        }
+    }
+
     /// This will only obfuscate packets that are at least HDP_HEADER_BYTE_LEN
     pub fn prepare_outbound(&self, mut packet: BytesMut) -> Bytes {
         if packet.len() >= HDP_HEADER_BYTE_LEN {
-            //log::trace!(target: "citadel", "[Header-scrambler] Before: {:?}", &packet[..]);
-            // it is assumed that the value is already loaded
-            let val = self.load().unwrap();
-            apply_cipher(val, false, &mut packet);
-            //log::trace!(target: "citadel", "[Header-scrambler] After: {:?}", &packet[..]);
+            if let Some(key) = self.session_key.get() {
+                apply_session_keystream(&key, &mut packet);
+            } else if let Some(val) = self.load() {
+                let activate_session_key = is_connect_success(&packet);
+                apply_cipher(val, false, &mut packet);
+                if activate_session_key {
+                    self.activate_session_key();
+                }
+            } else {
+                log::error!(target: "citadel", "[Header obfuscator] Key not yet loaded; sending header as-is");
+            }
         }
 
         packet.freeze()
     }
 
+    /// Recovers the primary command from the first byte of an obfuscated header without
+    /// de-obfuscating the packet. Returns None if the key has not yet been latched, or if the
+    /// header is protected by the session key
+    pub(crate) fn peek_cmd_primary(&self, first_byte: u8) -> Option<u8> {
+        if self.is_session_keyed() {
+            return None;
+        }
+
+        let bytes = self.load()?.to_be_bytes();
+        let mut cmd_primary = first_byte;
+        cipher_inner(bytes[0], bytes[8], &mut cmd_primary, true);
+        Some(cmd_primary)
+    }
+
     /// Returns to the client an instance of self coupled with the required init packet
     pub fn new_client() -> (Self, BytesMut) {
         let mut rng = ThreadRng::default();
-        let mut fill0 = [0u8; 8];
-        let mut fill1 = [0u8; 8];
-
-        rng.fill(&mut fill0);
-        rng.fill(&mut fill1);
-
-        let val0 = u64::from_be_bytes(fill0);
-        let val1 = u64::from_be_bytes(fill1);
-        //log::trace!(target: "citadel", "[header-scrambler] {} -> {:?} | {} -> {:?}", val0, &fill0, val1, &fill1);
+        let val0 = rng.gen::<u64>();
+        let val1 = rng.gen::<u64>();
         // we have 16 bytes used. Now, choose a random number of bytes between 0 and HDP_HEADER_BYTE_LEN - 16 to fill
-        let bytes_to_add = rng.gen_range(0, HDP_HEADER_BYTE_LEN - 17);
-        let mut packet = vec![0; 16 + bytes_to_add];
-        let tmp = &mut packet[..];
-        let mut tmp = tmp.writer();
-        tmp.write_all(&fill0 as &[u8]).unwrap();
-        tmp.write_all(&fill1 as &[u8]).unwrap();
-
+        let bytes_to_add = rng.gen_range(0..HDP_HEADER_BYTE_LEN - 17);
+        let mut packet = BytesMut::with_capacity(16 + bytes_to_add);
+        packet.put_u64(val0);
+        packet.put_u64(val1);
+        packet.resize(16 + bytes_to_add, 0);
         rng.fill_bytes(&mut packet[16..]);
-        //log::trace!(target: "citadel", "[Header-scrambler] Prepared packet: {:?}", &packet[..]);
-        let packet = BytesMut::from(&packet[..]);
+
         let this = Self::new_from_u64s(val0, val1);
         (this, packet)
     }
@@ -330,29 +538,50 @@ impl HeaderObfuscator {
 }
 
 fn u64s_to_u128(val0: u64, val1: u64) -> u128 {
-    let mut ret = [0u8; 16];
-    let val0_bytes = val0.to_be_bytes();
-    let val1_bytes = val1.to_be_bytes();
-    for x in 0..8 {
-        ret[x] = val0_bytes[x];
-        ret[x + 8] = val1_bytes[x];
-    }
-
-    u128::from_be_bytes(ret)
+    (u128::from(val0) << 64) | u128::from(val1)
 }
 
 /// panics if packet is not of proper length
 #[inline]
 fn apply_cipher(val: u128, inverse: bool, packet: &mut BytesMut) {
-    let ref bytes = val.to_be_bytes();
+    let bytes = &val.to_be_bytes();
     let (bytes0, bytes1) = bytes.split_at(8);
     let packet = &mut packet[..HDP_HEADER_BYTE_LEN];
-    bytes0.iter().zip(bytes1.iter())
+    bytes0
+        .iter()
+        .zip(bytes1.iter())
         .cycle()
         .zip(packet.iter_mut())
         .for_each(|((a, b), c)| cipher_inner(*a, *b, c, inverse))
 }
 
+/// XORs the header with a keystream derived from `key` and a sample of the bytes following the
+/// header, which differ between packets since the payload is encrypted. XORing is its own
+/// inverse, so the same call protects and recovers the header. Panics if packet is not of
+/// proper length
+fn apply_session_keystream(key: &[u8; 32], packet: &mut BytesMut) {
+    let (header, rest) = packet.split_at_mut(HDP_HEADER_BYTE_LEN);
+    let sample = &rest[..rest.len().min(HEADER_PROTECTION_SAMPLE_LEN)];
+
+    for (block_idx, block) in header.chunks_mut(32).enumerate() {
+        let mut hasher = sha3::Sha3_256::default();
+        hasher.update(key);
+        hasher.update([block_idx as u8]);
+        hasher.update(sample);
+        let keystream = hasher.finalize();
+        block
+            .iter_mut()
+            .zip(keystream.iter())
+            .for_each(|(byte, key_byte)| *byte ^= key_byte);
+    }
+}
+
+/// Returns true if the plaintext header belongs to a connect success packet
+fn is_connect_success(packet: &[u8]) -> bool {
+    packet[0] == packet_flags::cmd::primary::DO_CONNECT
+        && packet[1] == packet_flags::cmd::aux::do_connect::SUCCESS
+}
+
 #[inline]
 fn cipher_inner(a: u8, b: u8, c: &mut u8, inverse: bool) {
     if inverse {
@@ -362,18 +591,23 @@ fn cipher_inner(a: u8, b: u8, c: &mut u8, inverse: bool) {
     }
 }
 
-
 impl From<Option<u128>> for HeaderObfuscator {
     fn from(inner: Option<u128>) -> Self {
-        Self { inner: DualCell::from(inner) }
+        Self {
+            inner: DualCell::from(inner),
+            pending_session_key: DualCell::from(None),
+            session_key: DualCell::from(None),
+            discard_log: DiscardLog::default(),
+        }
     }
 }
-*/
 
 pub trait HdpBuffer: BufMut + AsRef<[u8]> + AsMut<[u8]> {
     type Immutable;
     fn len(&self) -> usize;
     fn split_to(&mut self, idx: usize) -> Self;
+    /// Removes `[0, idx)` from the buffer without allocating
+    fn discard_front(&mut self, idx: usize);
     fn freeze(self) -> Self::Immutable;
 }
 
@@ -388,6 +622,10 @@ impl HdpBuffer for BytesMut {
         self.split_to(idx)
     }
 
+    fn discard_front(&mut self, idx: usize) {
+        self.advance(idx)
+    }
+
     fn freeze(self) -> Self::Immutable {
         self.freeze()
     }
@@ -408,7 +646,435 @@ impl HdpBuffer for Vec<u8> {
         tail // now, tail is the head
     }
 
+    // shifts [idx, len) to the front, keeping the capacity
+    fn discard_front(&mut self, idx: usize) {
+        let len = self.len();
+        self.copy_within(idx.., 0);
+        self.truncate(len - idx);
+    }
+
     fn freeze(self) -> Self::Immutable {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::HDP_HEADER_BYTE_LEN;
+    use crate::constants::{HEADER_CHECKSUM_VERSION, PROTOCOL_VERSION};
+    use crate::proto::misc::discard_log::DiscardLog;
+    use crate::proto::packet::{
+        append_header_checksum, packet_flags, packet_flags::header_bounds, packet_sizes,
+        verify_header_checksum, HdpHeader, HdpPacket, HeaderObfuscator,
+        HEADER_PROTECTION_KEY_CONTEXT,
+    };
+    use crate::proto::validation::group::GroupHeaderAck;
+    use bytes::{BufMut, BytesMut};
+    use citadel_crypt::endpoint_crypto_container::KemTransferStatus;
+    use citadel_crypt::entropy_bank::EntropyBank;
+    use citadel_pqcrypto::algorithm_dictionary::{EncryptionAlgorithm, KemAlgorithm};
+    use citadel_user::serialization::SyncIO;
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+    use zerocopy::{AsBytes, I64, U128, U32, U64};
+
+    const PAYLOAD: &[u8] = b"Hello, world!";
+
+    fn header() -> HdpHeader {
+        HdpHeader {
+            cmd_primary: 2,
+            cmd_aux: 7,
+            algorithm: 1,
+            security_level: 3,
+            protocol_version: U32::new(1),
+            context_info: U128::new(u128::MAX - 1234),
+            group: U64::new(99),
+            wave_id: U32::new(12),
+            session_cid: U64::new(123456789),
+            drill_version: U32::new(42),
+            timestamp: I64::new(-1000),
+            target_cid: U64::new(987654321),
+        }
+    }
+
+    fn packet() -> BytesMut {
+        let mut packet = BytesMut::new();
+        packet.put_slice(header().as_bytes());
+        packet.put_slice(PAYLOAD);
+        packet
+    }
+
+    fn latched_pair() -> (HeaderObfuscator, HeaderObfuscator) {
+        let (client, init_packet) = HeaderObfuscator::new(false);
+        let (server, server_init_packet) = HeaderObfuscator::new(true);
+        assert!(server_init_packet.is_none());
+
+        let init_packet = init_packet.unwrap();
+        assert!(init_packet.len() >= 16 && init_packet.len() < HDP_HEADER_BYTE_LEN);
+
+        // the init packet is too short to be obfuscated, and is consumed by the server
+        let mut init_packet = BytesMut::from(&client.prepare_outbound(init_packet)[..]);
+        assert!(server.on_packet_received(&mut init_packet).is_none());
+        (client, server)
+    }
+
+    fn assert_round_trip(sender: &HeaderObfuscator, receiver: &HeaderObfuscator) {
+        let original = packet();
+        let obfuscated = sender.prepare_outbound(original.clone());
+        assert_eq!(obfuscated.len(), original.len());
+        assert_ne!(
+            &obfuscated[..HDP_HEADER_BYTE_LEN],
+            &original[..HDP_HEADER_BYTE_LEN]
+        );
+        // only the header is obfuscated
+        assert_eq!(&obfuscated[HDP_HEADER_BYTE_LEN..], PAYLOAD);
+
+        let mut received = BytesMut::from(&obfuscated[..]);
+        assert!(receiver.on_packet_received(&mut received).is_some());
+        assert_eq!(&received[..HDP_HEADER_BYTE_LEN], header().as_bytes());
+        assert_eq!(received, original);
+    }
+
+    #[test]
+    fn header_wire_layout() {
+        let bytes = header();
+        let bytes = bytes.as_bytes();
+        assert_eq!(bytes.len(), HDP_HEADER_BYTE_LEN);
+        assert_eq!(&bytes[..4], &[2, 7, 1, 3]);
+        assert_eq!(&bytes[4..8], &1u32.to_be_bytes());
+        assert_eq!(&bytes[8..24], &(u128::MAX - 1234).to_be_bytes());
+        assert_eq!(&bytes[24..32], &99u64.to_be_bytes());
+        assert_eq!(&bytes[32..36], &12u32.to_be_bytes());
+        assert_eq!(&bytes[36..44], &123456789u64.to_be_bytes());
+        assert_eq!(&bytes[44..48], &42u32.to_be_bytes());
+        assert_eq!(&bytes[48..56], &(-1000i64).to_be_bytes());
+        assert_eq!(&bytes[56..64], &987654321u64.to_be_bytes());
+    }
+
+    #[test]
+    fn packet_sizes_match_sample_packets() {
+        let addr = SocketAddr::from_str("127.0.0.1:25000").unwrap();
+        let sample = |capacity: usize, body: &dyn Fn(&mut BytesMut)| {
+            let mut packet = BytesMut::with_capacity(capacity);
+            header().inscribe_into(&mut packet);
+            assert_eq!(packet.len(), HDP_HEADER_BYTE_LEN);
+            body(&mut packet);
+            packet
+        };
+
+        // everything after the header is the ack, which begins with its enum tag. The transfer
+        // trails the fixed-width fields
+        let ack = GroupHeaderAck::ReadyToReceive {
+            fast_msg: true,
+            initial_window: Some(0..=15),
+            transfer: KemTransferStatus::Empty,
+        };
+        let packet = sample(packet_sizes::GROUP_HEADER_ACK_LEN, &|packet| {
+            ack.serialize_into_buf(packet).unwrap()
+        });
+        const ENUM_TAG_LEN: usize = 4;
+        assert_eq!(
+            packet.len(),
+            packet_sizes::GROUP_HEADER_ACK_LEN
+                + ENUM_TAG_LEN
+                + KemTransferStatus::Empty.serialized_size().unwrap()
+        );
+        let packet = HdpPacket::new_recv(packet, addr, 25000);
+        let (header, payload) = packet.parse().unwrap();
+        assert_eq!(header.as_bytes(), self::header().as_bytes());
+        assert!(matches!(
+            GroupHeaderAck::deserialize_from_vector(&payload[..]).unwrap(),
+            GroupHeaderAck::ReadyToReceive {
+                fast_msg: true,
+                initial_window: Some(window),
+                transfer: KemTransferStatus::Empty,
+            } if window == (0..=15)
+        ));
+
+        // the remaining sizes only reserve capacity for variable-length payloads, so each must
+        // at least fit the header
+        for capacity in [
+            packet_sizes::GROUP_HEADER_BASE_LEN,
+            packet_sizes::do_drill_update::STAGE1,
+        ] {
+            assert!(capacity > HDP_HEADER_BYTE_LEN);
+            let packet = sample(capacity, &|packet| packet.put_slice(PAYLOAD));
+            assert_eq!(packet.len(), HDP_HEADER_BYTE_LEN + PAYLOAD.len());
+            let packet = HdpPacket::new_recv(packet, addr, 25000);
+            assert_eq!(&packet.parse().unwrap().1[..], PAYLOAD);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_out_of_range_headers() {
+        let addr = SocketAddr::from_str("127.0.0.1:25000").unwrap();
+        let parse = |header: HdpHeader| {
+            let mut packet = BytesMut::new();
+            header.inscribe_into(&mut packet);
+            packet.put_slice(PAYLOAD);
+            HdpPacket::new_recv(packet, addr, 25000).parse().is_some()
+        };
+        let with = |algorithm: u8, security_level: u8| HdpHeader {
+            algorithm,
+            security_level,
+            ..header()
+        };
+
+        for security_level in header_bounds::MIN_SECURITY_LEVEL..=header_bounds::MAX_SECURITY_LEVEL
+        {
+            assert!(parse(with(0, security_level)));
+        }
+        for security_level in [header_bounds::MAX_SECURITY_LEVEL + 1, 100, u8::MAX] {
+            assert!(!parse(with(0, security_level)));
+        }
+
+        // the flags, along with packed crypto parameters
+        let params = KemAlgorithm::Kyber + EncryptionAlgorithm::ChaCha20Poly_1305;
+        for algorithm in [0, 1, u8::from(params)] {
+            assert!(parse(with(algorithm, 0)));
+        }
+        // an encryption algorithm that does not exist, and Kyber encryption without a signature
+        for algorithm in [0b1110_0000, 0b0100_0000] {
+            assert!(!header_bounds::is_known_algorithm(algorithm));
+            assert!(!parse(with(algorithm, 0)));
+        }
+    }
+
+    #[test]
+    fn header_obfuscation_round_trip() {
+        let (client, server) = latched_pair();
+        for _ in 0..10 {
+            assert_round_trip(&client, &server);
+            assert_round_trip(&server, &client);
+        }
+    }
+
+    #[test]
+    fn short_init_packet_discarded() {
+        let server = HeaderObfuscator::new_server();
+        for len in [0, 1, 15] {
+            let mut packet = BytesMut::from(&vec![0u8; len][..]);
+            assert!(server.on_packet_received(&mut packet).is_none());
+        }
+
+        // the server has not latched a key, so a valid init packet may still follow
+        let (client, mut init_packet) = HeaderObfuscator::new_client();
+        assert!(server.on_packet_received(&mut init_packet).is_none());
+        assert_round_trip(&client, &server);
+    }
+
+    #[test]
+    fn header_and_payload_borrows_without_consuming() {
+        let addr = SocketAddr::from_str("127.0.0.1:25000").unwrap();
+        let packet = HdpPacket::new_recv(packet(), addr, 25000);
+        let (header, payload) = packet.header_and_payload().unwrap();
+        assert_eq!(header.as_bytes(), self::header().as_bytes());
+        assert_eq!(payload, PAYLOAD);
+        // the slices point into the packet's own buffer
+        assert_eq!(payload.as_ptr(), packet.parse().unwrap().1.as_ptr());
+
+        // the packet remains intact for the consuming path
+        let (header_bytes, payload_bytes, remote_peer, _) = packet.decompose();
+        assert_eq!(&header_bytes[..], self::header().as_bytes());
+        assert_eq!(&payload_bytes[..], PAYLOAD);
+        assert_eq!(remote_peer, addr);
+
+        for len in [0, 1, HDP_HEADER_BYTE_LEN - 1] {
+            let packet = HdpPacket::new_recv(BytesMut::from(&vec![0u8; len][..]), addr, 25000);
+            assert!(packet.header_and_payload().is_none());
+        }
+
+        // a header without a payload yields an empty payload
+        let packet = HdpPacket::new_recv(BytesMut::from(self::header().as_bytes()), addr, 25000);
+        assert!(packet.header_and_payload().unwrap().1.is_empty());
+    }
+
+    #[test]
+    fn short_packet_after_init_discarded() {
+        let (_client, server) = latched_pair();
+        let mut packet = BytesMut::from(&[1u8; 15][..]);
+        assert!(server.on_packet_received(&mut packet).is_none());
+    }
+
+    fn checksummed_packet() -> BytesMut {
+        let (major, minor, patch) = HEADER_CHECKSUM_VERSION;
+        let version = embedded_semver::Semver::new(major as _, minor as _, patch as _);
+        let mut header = header();
+        header.protocol_version = U32::new(version.to_u32().unwrap());
+        assert!(header.has_checksum());
+
+        let mut packet = BytesMut::new();
+        packet.put_slice(header.as_bytes());
+        packet.put_slice(PAYLOAD);
+        packet
+    }
+
+    #[test]
+    fn header_checksum_round_trip() {
+        let original = checksummed_packet();
+        let mut packet = original.clone();
+        append_header_checksum(&mut packet);
+        assert_eq!(packet.len(), original.len() + 4);
+
+        assert!(verify_header_checksum(&mut packet).is_some());
+        assert_eq!(packet, original);
+    }
+
+    #[test]
+    fn header_checksum_rejects_flipped_byte() {
+        let mut packet = checksummed_packet();
+        append_header_checksum(&mut packet);
+
+        for idx in 0..HDP_HEADER_BYTE_LEN {
+            let mut corrupted = packet.clone();
+            corrupted[idx] ^= 0x01;
+            assert!(verify_header_checksum(&mut corrupted).is_none());
+        }
+    }
+
+    #[test]
+    fn header_checksum_present_for_current_version() {
+        let mut header = header();
+        header.protocol_version = U32::new(*PROTOCOL_VERSION);
+        assert!(header.has_checksum());
+    }
+
+    #[test]
+    fn header_checksum_absent_for_earlier_version() {
+        // the wire format of versions predating HEADER_CHECKSUM_VERSION is unchanged
+        let mut header = header();
+        header.protocol_version = U32::new(embedded_semver::Semver::new(0, 3, 0).to_u32().unwrap());
+        assert!(!header.has_checksum());
+
+        let mut original = BytesMut::new();
+        original.put_slice(header.as_bytes());
+        original.put_slice(PAYLOAD);
+
+        let mut packet = original.clone();
+        append_header_checksum(&mut packet);
+        assert_eq!(packet, original);
+        assert!(verify_header_checksum(&mut packet).is_some());
+        assert_eq!(packet, original);
+    }
+
+    fn connect_success_packet() -> BytesMut {
+        let mut header = header();
+        header.cmd_primary = packet_flags::cmd::primary::DO_CONNECT;
+        header.cmd_aux = packet_flags::cmd::aux::do_connect::SUCCESS;
+
+        let mut packet = BytesMut::new();
+        packet.put_slice(header.as_bytes());
+        packet.put_slice(PAYLOAD);
+        packet
+    }
+
+    #[test]
+    fn session_keyed_header_protection() {
+        let (client, server) = latched_pair();
+        // the observer captured the init packet, and so holds the init key
+        let observer = HeaderObfuscator::new_server();
+        let mut init_packet = BytesMut::from(&server.inner.get().unwrap().to_be_bytes()[..]);
+        assert!(observer.on_packet_received(&mut init_packet).is_none());
+
+        let drill = EntropyBank::new(0, 0, EncryptionAlgorithm::default()).unwrap();
+        let key = drill.derive_key(HEADER_PROTECTION_KEY_CONTEXT);
+        client.schedule_session_key(key);
+        server.schedule_session_key(key);
+
+        // until connected, the init key remains in use
+        assert_round_trip(&client, &server);
+        assert!(!client.is_session_keyed() && !server.is_session_keyed());
+
+        let mut success = BytesMut::from(&server.prepare_outbound(connect_success_packet())[..]);
+        assert!(server.is_session_keyed());
+        let mut observed = success.clone();
+        assert!(client.on_packet_received(&mut success).is_some());
+        assert_eq!(success, connect_success_packet());
+        assert!(client.is_session_keyed());
+        assert!(observer.on_packet_received(&mut observed).is_some());
+        assert!(!observer.is_session_keyed());
+
+        for (sender, receiver) in [(&client, &server), (&server, &client)] {
+            assert_round_trip(sender, receiver);
+
+            // the observer is unable to recover the header using the init key
+            let obfuscated = sender.prepare_outbound(packet());
+            let mut observed = BytesMut::from(&obfuscated[..]);
+            assert!(observer.on_packet_received(&mut observed).is_some());
+            assert_ne!(
+                &observed[..HDP_HEADER_BYTE_LEN],
+                header().as_bytes(),
+                "the observer recovered the header"
+            );
+            assert_eq!(sender.peek_cmd_primary(obfuscated[0]), None);
+
+            // identical headers are protected differently when the payloads differ
+            let mut other = BytesMut::new();
+            other.put_slice(header().as_bytes());
+            other.put_slice(b"Goodbye, world!");
+            let other = sender.prepare_outbound(other);
+            assert_ne!(
+                &obfuscated[..HDP_HEADER_BYTE_LEN],
+                &other[..HDP_HEADER_BYTE_LEN]
+            );
+        }
+
+        // a key derived from a different drill does not recover the header either
+        let other_drill = EntropyBank::new(0, 0, EncryptionAlgorithm::default()).unwrap();
+        let (other_client, other_server) = latched_pair();
+        other_server.schedule_session_key(other_drill.derive_key(HEADER_PROTECTION_KEY_CONTEXT));
+        let mut success =
+            BytesMut::from(&other_client.prepare_outbound(connect_success_packet())[..]);
+        assert!(other_server.on_packet_received(&mut success).is_some());
+        assert!(other_server.is_session_keyed());
+        let mut received = BytesMut::from(&client.prepare_outbound(packet())[..]);
+        assert!(other_server.on_packet_received(&mut received).is_some());
+        assert_ne!(&received[..HDP_HEADER_BYTE_LEN], header().as_bytes());
+    }
+
+    #[test]
+    fn invalid_packet_flood_counted() {
+        let discard_log = DiscardLog::default();
+        let server = HeaderObfuscator::new_server().with_discard_log(discard_log.clone());
+        for _ in 0..10_000 {
+            let mut packet = BytesMut::from(&[0u8; 4][..]);
+            assert!(server.on_packet_received(&mut packet).is_none());
+        }
+
+        assert_eq!(discard_log.total(), 10_000);
+    }
+
+    #[test]
+    fn decompose_header_reuses_buffer() {
+        let addr = SocketAddr::from_str("127.0.0.1:1234").unwrap();
+        let mut buffer = Vec::with_capacity(1024);
+        for _ in 0..3 {
+            buffer.clear();
+            buffer.extend_from_slice(&packet());
+            let ptr = buffer.as_ptr();
+
+            let (header, payload, remote_peer, local_port) =
+                HdpPacket::new_recv(buffer, addr, 25000)
+                    .decompose_header()
+                    .unwrap();
+            assert_eq!(header.as_bytes(), self::header().as_bytes());
+            assert_eq!(payload, PAYLOAD);
+            assert_eq!((remote_peer, local_port), (addr, 25000));
+            // the payload was shifted within the original allocation
+            assert_eq!(payload.as_ptr(), ptr);
+            assert_eq!(payload.capacity(), 1024);
+            buffer = payload;
+        }
+
+        let (header, payload, ..) = HdpPacket::new_recv(packet(), addr, 25000)
+            .decompose_header()
+            .unwrap();
+        assert_eq!(header.as_bytes(), self::header().as_bytes());
+        assert_eq!(&payload[..], PAYLOAD);
+
+        let short = vec![0u8; HDP_HEADER_BYTE_LEN - 1];
+        assert!(HdpPacket::new_recv(short, addr, 25000)
+            .decompose_header()
+            .is_none());
+    }
+}
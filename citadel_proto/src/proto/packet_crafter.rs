@@ -68,6 +68,7 @@ pub struct GroupTransmitter {
     ticket: Ticket,
     security_level: SecurityLevel,
     bytes_encrypted: usize,
+    protocol_version: u32,
     time_tracker: TimeTracker,
     is_message: bool,
 }
@@ -94,6 +95,7 @@ impl<R: Ratchet> RatchetPacketCrafterContainer<R> {
 
 impl GroupTransmitter {
     /// Scrambled packets will use this
+    #[allow(clippy::too_many_arguments)]
     pub fn new_from_group_sender(
         to_primary_stream: OutboundPrimaryStreamSender,
         group_sender: GroupSenderDevice<HDP_HEADER_BYTE_LEN>,
@@ -101,6 +103,7 @@ impl GroupTransmitter {
         object_id: u32,
         ticket: Ticket,
         security_level: SecurityLevel,
+        protocol_version: u32,
         time_tracker: TimeTracker,
     ) -> Self {
         let cfg = group_sender.get_receiver_config();
@@ -118,6 +121,7 @@ impl GroupTransmitter {
             ticket,
             security_level,
             bytes_encrypted,
+            protocol_version,
             time_tracker,
         }
     }
@@ -132,6 +136,7 @@ impl GroupTransmitter {
         security_level: SecurityLevel,
         group_id: u64,
         ticket: Ticket,
+        protocol_version: u32,
         time_tracker: TimeTracker,
     ) -> Option<Self> {
         // Gets the latest drill version by default for this operation
@@ -162,6 +167,7 @@ impl GroupTransmitter {
                     security_level,
                     group_id,
                     ticket,
+                    protocol_version,
                     time_tracker,
                 })
             }
@@ -249,7 +255,7 @@ pub(crate) mod group {
         let is_fast_message = u8::from(processor.is_message);
 
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: processor.protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::GROUP_PACKET,
             cmd_aux: packet_flags::cmd::aux::group::GROUP_HEADER,
             algorithm: is_fast_message,
@@ -319,12 +325,13 @@ pub(crate) mod group {
         ticket: Ticket,
         initial_wave_window: Option<RangeInclusive<u32>>,
         fast_msg: bool,
+        protocol_version: u32,
         timestamp: i64,
         transfer: KemTransferStatus,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::GROUP_PACKET,
             cmd_aux: packet_flags::cmd::aux::group::GROUP_HEADER_ACK,
             algorithm: 0,
@@ -364,10 +371,11 @@ pub(crate) mod group {
         scramble_drill: &EntropyBank,
         object_id: u32,
         target_cid: u64,
+        protocol_version: u32,
         mut buffer: &mut BytesMut,
     ) {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::GROUP_PACKET,
             cmd_aux: packet_flags::cmd::aux::group::GROUP_PAYLOAD,
             algorithm: 0,
@@ -399,12 +407,13 @@ pub(crate) mod group {
         target_cid: u64,
         group_id: u64,
         wave_id: u32,
+        protocol_version: u32,
         timestamp: i64,
         range: Option<RangeInclusive<u32>>,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::GROUP_PACKET,
             cmd_aux: packet_flags::cmd::aux::group::WAVE_ACK,
             algorithm: 0,
@@ -436,6 +445,7 @@ pub(crate) mod do_connect {
     use zerocopy::{I64, U128, U32, U64};
 
     use crate::constants::HDP_HEADER_BYTE_LEN;
+    use crate::error::ConnectError;
     use crate::proto::packet::{packet_flags, HdpHeader};
     use crate::proto::peer::peer_layer::MailboxTransfer;
     use citadel_crypt::prelude::SecurityLevel;
@@ -455,11 +465,12 @@ pub(crate) mod do_connect {
     pub(crate) fn craft_stage0_packet(
         hyper_ratchet: &StackedRatchet,
         proposed_credentials: ProposedCredentials,
+        protocol_version: u32,
         timestamp: i64,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_CONNECT,
             cmd_aux: packet_flags::cmd::aux::do_connect::STAGE0,
             algorithm: 0,
@@ -492,7 +503,11 @@ pub(crate) mod do_connect {
     #[derive(Serialize, Deserialize)]
     pub struct DoConnectFinalStatusPacket<'a> {
         pub mailbox: Option<MailboxTransfer>,
+        #[serde(with = "citadel_user::misc::compressed_peer_list")]
         pub peers: Vec<MutualPeer>,
+        pub resumption_ticket: Option<Vec<u8>>,
+        /// Why the connect was rejected, if it was, so the client may act upon the reason
+        pub connect_error: Option<ConnectError>,
         // in order to allow interoperability between protocols that have fields in the services object
         // and those that don't, default on error
         #[serde(deserialize_with = "ok_or_default")]
@@ -518,12 +533,17 @@ pub(crate) mod do_connect {
         post_login_object: citadel_user::external_services::ServicesObject,
         message: T,
         peers: Vec<MutualPeer>,
+        resumption_ticket: Option<Vec<u8>>,
+        connect_error: Option<ConnectError>,
+        protocol_version: u32,
         timestamp: i64,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let payload = DoConnectFinalStatusPacket {
             mailbox,
             peers,
+            resumption_ticket,
+            connect_error,
             message: message.as_ref(),
             post_login_object,
         };
@@ -535,7 +555,7 @@ pub(crate) mod do_connect {
         };
 
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_CONNECT,
             cmd_aux,
             algorithm: 0,
@@ -564,11 +584,12 @@ pub(crate) mod do_connect {
     #[allow(unused_results)]
     pub(crate) fn craft_success_ack(
         hyper_ratchet: &StackedRatchet,
+        protocol_version: u32,
         timestamp: i64,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_CONNECT,
             cmd_aux: packet_flags::cmd::aux::do_connect::SUCCESS_ACK,
             algorithm: 0,
@@ -604,11 +625,12 @@ pub(crate) mod keep_alive {
 
     pub(crate) fn craft_keep_alive_packet(
         hyper_ratchet: &StackedRatchet,
+        protocol_version: u32,
         timestamp: i64,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::KEEP_ALIVE,
             cmd_aux: 0,
             algorithm: 0,
@@ -656,13 +678,14 @@ pub(crate) mod do_register {
     /// We also use the NID in place of the CID because the CID only exists AFTER registration completes
     pub(crate) fn craft_stage0(
         algorithm: u8,
+        protocol_version: u32,
         timestamp: i64,
         transfer: AliceToBobTransfer,
         passwordless: bool,
         proposed_cid: u64,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_REGISTER,
             cmd_aux: packet_flags::cmd::aux::do_register::STAGE0,
             algorithm,
@@ -692,12 +715,13 @@ pub(crate) mod do_register {
     /// Bob crafts a packet with the ciphertext
     pub(crate) fn craft_stage1(
         algorithm: u8,
+        protocol_version: u32,
         timestamp: i64,
         transfer: BobToAliceTransfer,
         proposed_cid: u64,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_REGISTER,
             cmd_aux: packet_flags::cmd::aux::do_register::STAGE1,
             algorithm,
@@ -729,12 +753,13 @@ pub(crate) mod do_register {
     pub(crate) fn craft_stage2(
         hyper_ratchet: &StackedRatchet,
         algorithm: u8,
+        protocol_version: u32,
         timestamp: i64,
         credentials: &ProposedCredentials,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_REGISTER,
             cmd_aux: packet_flags::cmd::aux::do_register::STAGE2,
             algorithm,
@@ -767,6 +792,7 @@ pub(crate) mod do_register {
     pub(crate) fn craft_success<T: AsRef<[u8]>>(
         hyper_ratchet: &StackedRatchet,
         algorithm: u8,
+        protocol_version: u32,
         timestamp: i64,
         success_message: T,
         security_level: SecurityLevel,
@@ -774,7 +800,7 @@ pub(crate) mod do_register {
         let success_message = success_message.as_ref();
         let success_message_len = success_message.len();
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_REGISTER,
             cmd_aux: packet_flags::cmd::aux::do_register::SUCCESS,
             algorithm,
@@ -802,6 +828,7 @@ pub(crate) mod do_register {
     /// No encryption used for this packet
     pub(crate) fn craft_failure<T: AsRef<[u8]>>(
         algorithm: u8,
+        protocol_version: u32,
         timestamp: i64,
         error_message: T,
         proposed_cid: u64,
@@ -809,7 +836,7 @@ pub(crate) mod do_register {
         let error_message = error_message.as_ref();
 
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_REGISTER,
             cmd_aux: packet_flags::cmd::aux::do_register::FAILURE,
             algorithm,
@@ -847,11 +874,12 @@ pub mod do_disconnect {
     pub(crate) fn craft_stage0(
         hyper_ratchet: &StackedRatchet,
         ticket: Ticket,
+        protocol_version: u32,
         timestamp: i64,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_DISCONNECT,
             cmd_aux: packet_flags::cmd::aux::do_disconnect::STAGE0,
             algorithm: 0,
@@ -878,11 +906,12 @@ pub mod do_disconnect {
     pub(crate) fn craft_final(
         hyper_ratchet: &StackedRatchet,
         ticket: Ticket,
+        protocol_version: u32,
         timestamp: i64,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_DISCONNECT,
             cmd_aux: packet_flags::cmd::aux::do_disconnect::FINAL,
             algorithm: 0,
@@ -922,12 +951,13 @@ pub(crate) mod do_drill_update {
     pub(crate) fn craft_stage0(
         hyper_ratchet: &StackedRatchet,
         transfer: AliceToBobTransfer,
+        protocol_version: u32,
         timestamp: i64,
         target_cid: u64,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_DRILL_UPDATE,
             cmd_aux: packet_flags::cmd::aux::do_drill_update::STAGE0,
             algorithm: 0,
@@ -960,12 +990,13 @@ pub(crate) mod do_drill_update {
     pub(crate) fn craft_stage1(
         hyper_ratchet: &StackedRatchet,
         update_status: KemTransferStatus,
+        protocol_version: u32,
         timestamp: i64,
         target_cid: u64,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_DRILL_UPDATE,
             cmd_aux: packet_flags::cmd::aux::do_drill_update::STAGE1,
             algorithm: 0,
@@ -1001,11 +1032,12 @@ pub(crate) mod do_drill_update {
         hyper_ratchet: &StackedRatchet,
         truncate_version: Option<u32>,
         target_cid: u64,
+        protocol_version: u32,
         timestamp: i64,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_DRILL_UPDATE,
             cmd_aux: packet_flags::cmd::aux::do_drill_update::TRUNCATE,
             algorithm: 0,
@@ -1041,11 +1073,12 @@ pub(crate) mod do_drill_update {
         hyper_ratchet: &StackedRatchet,
         truncated_version: u32,
         target_cid: u64,
+        protocol_version: u32,
         timestamp: i64,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_DRILL_UPDATE,
             cmd_aux: packet_flags::cmd::aux::do_drill_update::TRUNCATE_ACK,
             algorithm: 0,
@@ -1083,11 +1116,12 @@ pub(crate) mod do_deregister {
 
     pub(crate) fn craft_stage0(
         hyper_ratchet: &StackedRatchet,
+        protocol_version: u32,
         timestamp: i64,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_DEREGISTER,
             cmd_aux: packet_flags::cmd::aux::do_drill_update::STAGE0,
             algorithm: 0,
@@ -1114,6 +1148,7 @@ pub(crate) mod do_deregister {
     pub(crate) fn craft_final(
         hyper_ratchet: &StackedRatchet,
         success: bool,
+        protocol_version: u32,
         timestamp: i64,
         security_level: SecurityLevel,
     ) -> BytesMut {
@@ -1124,7 +1159,7 @@ pub(crate) mod do_deregister {
         };
 
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_DEREGISTER,
             cmd_aux,
             algorithm: 0,
@@ -1154,6 +1189,7 @@ pub(crate) mod pre_connect {
     use citadel_wire::hypernode_type::NodeType;
 
     use crate::constants::HDP_HEADER_BYTE_LEN;
+    use crate::proto::misc::protocol_version::ProtocolVersionRange;
     use crate::proto::misc::session_security_settings::SessionSecuritySettings;
     use crate::proto::node::ConnectMode;
     use crate::proto::packet::packet_flags::payload_identifiers;
@@ -1177,6 +1213,17 @@ pub(crate) mod pre_connect {
         pub nat_type: NatType,
         pub udp_mode: UdpMode,
         pub keep_alive_timeout: i64,
+        pub resumption: Option<ResumptionAttempt>,
+        /// The protocol versions the client is able to speak
+        pub protocol_versions: ProtocolVersionRange,
+    }
+
+    /// Presented inside the SYN by a client holding a resumption ticket. The server skips the
+    /// key agreement if the ticket is valid and it holds the same ratchet version
+    #[derive(Serialize, Deserialize)]
+    pub struct ResumptionAttempt {
+        pub ticket: Vec<u8>,
+        pub ratchet_version: u32,
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -1185,15 +1232,18 @@ pub(crate) mod pre_connect {
         transfer: AliceToBobTransfer,
         nat_type: NatType,
         udp_mode: UdpMode,
+        protocol_versions: ProtocolVersionRange,
+        protocol_version: u32,
         timestamp: i64,
         keep_alive_timeout: i64,
         security_level: SecurityLevel,
         session_security_settings: SessionSecuritySettings,
         peer_only_connect_protocol: ConnectProtocol,
         connect_mode: ConnectMode,
+        resumption: Option<ResumptionAttempt>,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_PRE_CONNECT,
             cmd_aux: packet_flags::cmd::aux::do_preconnect::SYN,
             algorithm: 0,
@@ -1218,6 +1268,8 @@ pub(crate) mod pre_connect {
             udp_mode,
             keep_alive_timeout,
             nat_type,
+            resumption,
+            protocol_versions,
         }
         .serialize_into_buf(&mut packet)
         .unwrap();
@@ -1238,11 +1290,12 @@ pub(crate) mod pre_connect {
         static_aux_hr: &StaticAuxRatchet,
         transfer: BobToAliceTransfer,
         nat_type: NatType,
+        protocol_version: u32,
         timestamp: i64,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_PRE_CONNECT,
             cmd_aux: packet_flags::cmd::aux::do_preconnect::SYN_ACK,
             algorithm: 0,
@@ -1278,12 +1331,13 @@ pub(crate) mod pre_connect {
     // This gets sent from Alice to Bob
     pub(crate) fn craft_stage0(
         hyper_ratchet: &StackedRatchet,
+        protocol_version: u32,
         timestamp: i64,
         node_type: NodeType,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_PRE_CONNECT,
             cmd_aux: packet_flags::cmd::aux::do_preconnect::STAGE0,
             algorithm: 0,
@@ -1316,6 +1370,7 @@ pub(crate) mod pre_connect {
         hyper_ratchet: &StackedRatchet,
         success: bool,
         tcp_only: bool,
+        protocol_version: u32,
         timestamp: i64,
         security_level: SecurityLevel,
     ) -> BytesMut {
@@ -1332,7 +1387,7 @@ pub(crate) mod pre_connect {
         };
 
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_PRE_CONNECT,
             cmd_aux,
             algorithm,
@@ -1356,11 +1411,12 @@ pub(crate) mod pre_connect {
 
     pub(crate) fn craft_begin_connect(
         hyper_ratchet: &StackedRatchet,
+        protocol_version: u32,
         timestamp: i64,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::DO_PRE_CONNECT,
             cmd_aux: packet_flags::cmd::aux::do_preconnect::BEGIN_CONNECT,
             algorithm: 0,
@@ -1383,7 +1439,7 @@ pub(crate) mod pre_connect {
 
     pub fn craft_halt<T: AsRef<[u8]>>(prev_header: &HdpHeader, fail_reason: T) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: prev_header.protocol_version,
             cmd_primary: packet_flags::cmd::primary::DO_PRE_CONNECT,
             cmd_aux: packet_flags::cmd::aux::do_preconnect::HALT,
             algorithm: 0,
@@ -1430,11 +1486,12 @@ pub(crate) mod peer_cmd {
         hyper_ratchet: &StackedRatchet,
         peer_command: T,
         ticket: Ticket,
+        protocol_version: u32,
         timestamp: i64,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::PEER_CMD,
             cmd_aux: packet_flags::cmd::aux::peer_cmd::SIGNAL,
             algorithm: 0,
@@ -1467,12 +1524,13 @@ pub(crate) mod peer_cmd {
         hyper_ratchet: &StackedRatchet,
         peer_command: T,
         ticket: Ticket,
+        protocol_version: u32,
         timestamp: i64,
         target_cid: u64,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::PEER_CMD,
             cmd_aux: packet_flags::cmd::aux::peer_cmd::SIGNAL,
             algorithm: 0,
@@ -1507,11 +1565,12 @@ pub(crate) mod peer_cmd {
         payload: ChannelPacket,
         ticket: Ticket,
         proxy_target_cid: u64,
+        protocol_version: u32,
         timestamp: i64,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::PEER_CMD,
             cmd_aux: packet_flags::cmd::aux::peer_cmd::CHANNEL,
             algorithm: 0,
@@ -1545,11 +1604,12 @@ pub(crate) mod peer_cmd {
         payload: &GroupBroadcast,
         ticket: Ticket,
         proxy_target_cid: u64,
+        protocol_version: u32,
         timestamp: i64,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::PEER_CMD,
             cmd_aux: packet_flags::cmd::aux::peer_cmd::GROUP_BROADCAST,
             algorithm: 0,
@@ -1605,11 +1665,12 @@ pub(crate) mod file {
         security_level: SecurityLevel,
         virtual_target: VirtualTargetType,
         file_metadata: VirtualObjectMetadata,
+        protocol_version: u32,
         timestamp: i64,
         local_encryption_level: Option<SecurityLevel>,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::FILE,
             cmd_aux: packet_flags::cmd::aux::file::FILE_HEADER,
             algorithm: 0,
@@ -1657,10 +1718,11 @@ pub(crate) mod file {
         ticket: Ticket,
         security_level: SecurityLevel,
         virtual_target: VirtualTargetType,
+        protocol_version: u32,
         timestamp: i64,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::FILE,
             cmd_aux: packet_flags::cmd::aux::file::FILE_HEADER_ACK,
             algorithm: 0,
@@ -1705,13 +1767,14 @@ pub(crate) mod file {
         hyper_ratchet: &StackedRatchet,
         security_level: SecurityLevel,
         ticket: Ticket,
+        protocol_version: u32,
         timestamp: i64,
         target_cid: u64,
         virtual_path: PathBuf,
         delete_on_pull: bool,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::FILE,
             cmd_aux: packet_flags::cmd::aux::file::REVFS_PULL,
             algorithm: 0,
@@ -1751,12 +1814,13 @@ pub(crate) mod file {
         hyper_ratchet: &StackedRatchet,
         security_level: SecurityLevel,
         ticket: Ticket,
+        protocol_version: u32,
         timestamp: i64,
         target_cid: u64,
         virtual_path: PathBuf,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::FILE,
             cmd_aux: packet_flags::cmd::aux::file::REVFS_DELETE,
             algorithm: 0,
@@ -1793,12 +1857,13 @@ pub(crate) mod file {
         hyper_ratchet: &StackedRatchet,
         security_level: SecurityLevel,
         ticket: Ticket,
+        protocol_version: u32,
         timestamp: i64,
         target_cid: u64,
         error_msg: Option<String>,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::FILE,
             cmd_aux: packet_flags::cmd::aux::file::REVFS_ACK,
             algorithm: 0,
@@ -1836,12 +1901,13 @@ pub(crate) mod file {
         hyper_ratchet: &StackedRatchet,
         security_level: SecurityLevel,
         ticket: Ticket,
+        protocol_version: u32,
         timestamp: i64,
         target_cid: u64,
         payload: ReVFSPullAckPacket,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::FILE,
             cmd_aux: packet_flags::cmd::aux::file::REVFS_PULL_ACK,
             algorithm: 0,
@@ -1880,10 +1946,11 @@ pub(crate) mod udp {
         cmd_aux: u8,
         payload: BytesMut,
         target_cid: u64,
+        protocol_version: u32,
         security_level: SecurityLevel,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::UDP,
             cmd_aux,
             algorithm: 0,
@@ -1920,11 +1987,12 @@ pub(crate) mod hole_punch {
     pub fn generate_packet(
         hyper_ratchet: &StackedRatchet,
         plaintext: &[u8],
+        protocol_version: u32,
         security_level: SecurityLevel,
         target_cid: u64,
     ) -> BytesMut {
         let header = HdpHeader {
-            protocol_version: (*crate::constants::PROTOCOL_VERSION).into(),
+            protocol_version: protocol_version.into(),
             cmd_primary: packet_flags::cmd::primary::HOLE_PUNCH,
             cmd_aux: packet_flags::cmd::aux::udp::HOLE_PUNCH,
             algorithm: 0,
@@ -1,8 +1,12 @@
 use super::includes::*;
-use crate::error::NetworkError;
+use crate::constants::RESUMED_SESSION_REKEY_DEADLINE;
+use crate::error::{ConnectError, NetworkError};
 use crate::proto::node::ConnectMode;
 use crate::proto::node_result::{ConnectFail, ConnectSuccess, MailboxDelivery};
+use crate::proto::packet_crafter::peer_cmd::C2S_ENCRYPTION_ONLY;
 use crate::proto::packet_processor::primary_group_packet::get_proper_hyper_ratchet;
+use crate::proto::peer::peer_layer::Presence;
+use crate::proto::session_queue_handler::QueueWorkerResult;
 use crate::proto::state_container::VirtualConnectionType;
 use citadel_user::external_services::ServicesObject;
 use std::sync::atomic::Ordering;
@@ -57,7 +61,45 @@ pub async fn process_connect(
             packet_flags::cmd::aux::do_connect::STAGE0 => {
                 log::trace!(target: "citadel", "STAGE 2 CONNECT PACKET");
                 let task = {
-                    match validation::do_connect::validate_stage0_packet(&cnac, &payload).await {
+                    // reject sources that are cooling down after too many failed attempts
+                    let source = session.remote_peer.ip();
+                    let validation = match session.session_manager.check_connect_throttle(source) {
+                        Ok(_) => {
+                            let res =
+                                validation::do_connect::validate_stage0_packet(&cnac, &payload)
+                                    .await;
+                            session
+                                .session_manager
+                                .on_connect_attempt_finished(source, res.is_ok());
+                            // deactivated accounts retain valid credentials, but may not connect
+                            match res {
+                                Ok(_) => {
+                                    let cid = cnac.get_cid();
+                                    if session
+                                        .account_manager
+                                        .get_persistence_handler()
+                                        .cnac_is_active(cid)
+                                        .await?
+                                    {
+                                        session
+                                            .session_manager
+                                            .enforce_session_limit(cid)
+                                            .map_err(NetworkError::from)
+                                    } else {
+                                        log::warn!(target: "citadel", "Rejecting connect attempt for deactivated CID {}", cid);
+                                        Err(NetworkError::from(ConnectError::AccountDeactivated(
+                                            cid,
+                                        )))
+                                    }
+                                }
+
+                                err => err,
+                            }
+                        }
+                        Err(err) => Err(NetworkError::from(err)),
+                    };
+
+                    match validation {
                         Ok(_) => {
                             let mut state_container = inner_mut_state!(session.state_container);
 
@@ -66,6 +108,9 @@ pub async fn process_connect(
                             let addr = session.remote_peer;
                             let is_personal = !session.is_server;
                             let kernel_ticket = session.kernel_ticket.get();
+                            let session_resumed = state_container.pre_connect_state.session_resumed;
+                            let session_security_settings =
+                                state_container.session_security_settings;
 
                             //let pqc = state_container.connect_stage.generated_pqc.take();
                             state_container.connect_state.last_stage =
@@ -86,6 +131,11 @@ pub async fn process_connect(
                             );
 
                             std::mem::drop(state_container);
+                            // headers following the success packet may be session-keyed
+                            session.schedule_header_protection(
+                                &hyper_ratchet,
+                                session_security_settings,
+                            );
 
                             // Upgrade the connect BEFORE updating the CNAC
                             if !session.session_manager.upgrade_connection(addr, cid) {
@@ -107,6 +157,15 @@ pub async fn process_connect(
                                     .get_hyperlan_peer_list_as_server(cid)
                                     .await?
                                     .unwrap_or_default();
+                                session.session_manager.notify_presence(
+                                    cid,
+                                    Presence::Online,
+                                    peers.iter().map(|peer| peer.cid),
+                                );
+                                // a failure to record the time should not fail the connect itself
+                                if let Err(err) = account_manager.record_connect(cid).await {
+                                    log::warn!(target: "citadel", "Unable to record the connect of {}: {}", cid, err);
+                                }
 
                                 #[cfg(feature = "google-services")]
                                 let post_login_object = account_manager
@@ -117,6 +176,19 @@ pub async fn process_connect(
                                 let post_login_object =
                                     citadel_user::external_services::ServicesObject::default();
 
+                                // a failure to issue a ticket should not fail the connect itself
+                                let resumption_ticket = session
+                                    .session_manager
+                                    .issue_resumption_ticket(
+                                        cid,
+                                        &cnac.get_static_auxiliary_hyper_ratchet(),
+                                    )
+                                    .await
+                                    .unwrap_or_else(|err| {
+                                        log::warn!(target: "citadel", "Unable to issue a resumption ticket to {}: {}", cid, err);
+                                        None
+                                    });
+
                                 let success_packet =
                                     packet_crafter::do_connect::craft_final_status_packet(
                                         &hyper_ratchet,
@@ -125,6 +197,9 @@ pub async fn process_connect(
                                         post_login_object.clone(),
                                         session.create_welcome_message(cid),
                                         peers,
+                                        resumption_ticket,
+                                        None,
+                                        session.protocol_version.get(),
                                         success_time,
                                         security_level,
                                     );
@@ -144,7 +219,8 @@ pub async fn process_connect(
                                     services: post_login_object,
                                     welcome_message: format!("Client {cid} successfully established a connection to the local HyperNode"),
                                     channel,
-                                    udp_rx_opt: udp_channel_rx
+                                    udp_rx_opt: udp_channel_rx,
+                                    session_resumed
                                 });
                                 // safe unwrap. Store the signal
                                 inner_mut_state!(session.state_container)
@@ -152,6 +228,33 @@ pub async fn process_connect(
                                     .as_mut()
                                     .unwrap()
                                     .channel_signal = Some(channel_signal);
+
+                                // the resumed ratchet was already used by the previous session, so the client must
+                                // replace it with fresh key material before the deadline
+                                if session_resumed {
+                                    let resumed_version = hyper_ratchet.version();
+                                    session.queue_handle.insert_reserved(
+                                        None,
+                                        RESUMED_SESSION_REKEY_DEADLINE,
+                                        move |state_container| {
+                                            let rekeyed = state_container
+                                                .c2s_channel_container
+                                                .as_ref()
+                                                .and_then(|c2s| {
+                                                    c2s.peer_session_crypto.get_hyper_ratchet(None)
+                                                })
+                                                .map(|ratchet| ratchet.version() > resumed_version)
+                                                .unwrap_or(false);
+                                            if rekeyed {
+                                                QueueWorkerResult::Complete
+                                            } else {
+                                                log::warn!(target: "citadel", "The resumed session of {} was not rekeyed in time. Ending session", cid);
+                                                QueueWorkerResult::EndSession
+                                            }
+                                        },
+                                    );
+                                }
+
                                 Ok(PrimaryProcessorResult::ReplyToSender(success_packet))
                             }
                         }
@@ -159,6 +262,10 @@ pub async fn process_connect(
                         Err(err) => {
                             log::error!(target: "citadel", "Error validating stage2 packet. Reason: {}", err.to_string());
                             let fail_time = time_tracker.get_global_time_ns();
+                            let connect_error = match &err {
+                                NetworkError::Connect(err) => Some(*err),
+                                _ => None,
+                            };
 
                             //session.state = SessionState::NeedsConnect;
                             let packet = packet_crafter::do_connect::craft_final_status_packet(
@@ -168,6 +275,9 @@ pub async fn process_connect(
                                 ServicesObject::default(),
                                 err.to_string(),
                                 Vec::new(),
+                                None,
+                                connect_error,
+                                session.protocol_version.get(),
                                 fail_time,
                                 security_level,
                             );
@@ -206,6 +316,7 @@ pub async fn process_connect(
                         ticket: kernel_ticket,
                         cid_opt: Some(cid),
                         error_message: message,
+                        connect_error: payload.connect_error,
                     }))?;
                     Ok(PrimaryProcessorResult::EndSession(
                         "Failed connecting. Try again",
@@ -238,6 +349,7 @@ pub async fn process_connect(
                             state_container.connect_state.on_connect_packet_received();
 
                             let use_ka = state_container.keep_alive_timeout_ns != 0;
+                            let session_resumed = state_container.pre_connect_state.session_resumed;
                             let connect_mode = return_if_none!(
                                 state_container.connect_state.connect_mode,
                                 "Unable to load connect mode"
@@ -272,6 +384,8 @@ pub async fn process_connect(
 
                             log::trace!(target: "citadel", "The login to the server was a success. Welcome Message: {}", &message);
 
+                            let resumption_ticket = payload.resumption_ticket.clone();
+
                             let _post_login_object = payload.post_login_object.clone();
                             //session.post_quantum = pqc;
                             let cxn_type = VirtualConnectionType::LocalGroupServer(cid);
@@ -288,6 +402,7 @@ pub async fn process_connect(
 
                             let success_ack = packet_crafter::do_connect::craft_success_ack(
                                 &hyper_ratchet,
+                                session.protocol_version.get(),
                                 timestamp,
                                 security_level,
                             );
@@ -303,6 +418,7 @@ pub async fn process_connect(
                                 welcome_message: message,
                                 channel,
                                 udp_rx_opt: udp_channel_rx,
+                                session_resumed,
                             }))?;
                             //finally, if there are any mailbox items, send them to the kernel for processing
                             if let Some(mailbox_delivery) = payload.mailbox {
@@ -314,11 +430,36 @@ pub async fn process_connect(
                                     },
                                 ))?;
                             }
+                            // the resumed ratchet was already used by the previous session, so replace it with fresh key material
+                            if session_resumed {
+                                log::trace!(target: "citadel", "Rekeying the resumed session of {}", cid);
+                                inner_mut_state!(session.state_container).initiate_drill_update(
+                                    timestamp,
+                                    VirtualConnectionType::LocalGroupServer(C2S_ENCRYPTION_ONLY),
+                                    None,
+                                )?;
+                            }
+
                             // TODO: Clean this up to prevent multiple saves
                             async move {
-                                persistence_handler
+                                if let Some(ticket) = resumption_ticket {
+                                    if let Err(err) = session
+                                        .session_manager
+                                        .store_resumption_ticket(
+                                            cid,
+                                            &ticket,
+                                            &cnac.get_static_auxiliary_hyper_ratchet(),
+                                        )
+                                        .await
+                                    {
+                                        log::warn!(target: "citadel", "Unable to store the resumption ticket of {}: {}", cid, err);
+                                    }
+                                }
+
+                                let delta = persistence_handler
                                     .synchronize_hyperlan_peer_list_as_client(&cnac, peers)
                                     .await?;
+                                log::trace!(target: "citadel", "Synchronized the peer list of {}: {} added, {} removed, {} changed", cnac.get_cid(), delta.added.len(), delta.removed.len(), delta.changed.len());
                                 #[cfg(feature = "google-services")]
                                 if let (Some(rtdb_cfg), Some(jwt)) =
                                     (_post_login_object.rtdb, _post_login_object.google_auth_jwt)
@@ -366,6 +507,7 @@ pub async fn process_connect(
                                 if use_ka {
                                     let ka = packet_crafter::keep_alive::craft_keep_alive_packet(
                                         &hyper_ratchet,
+                                        session.protocol_version.get(),
                                         timestamp,
                                         security_level,
                                     );
@@ -111,6 +111,7 @@ async fn deregister_client_from_self(
             let stage_success_packet = packet_crafter::do_deregister::craft_final(
                 hyper_ratchet,
                 true,
+                session.protocol_version.get(),
                 timestamp,
                 security_level,
             );
@@ -126,6 +127,7 @@ async fn deregister_client_from_self(
             let stage_failure_packet = packet_crafter::do_deregister::craft_final(
                 hyper_ratchet,
                 false,
+                session.protocol_version.get(),
                 timestamp,
                 security_level,
             );
@@ -39,6 +39,7 @@ pub fn process_disconnect(
             let packet = packet_crafter::do_disconnect::craft_final(
                 &hyper_ratchet,
                 ticket,
+                session.protocol_version.get(),
                 timestamp,
                 security_level,
             );
@@ -36,6 +36,7 @@ pub fn process_file_packet(
     let security_level = header.security_level.into();
     let ticket: Ticket = header.context_info.get().into();
     let ts = session.time_tracker.get_global_time_ns();
+    let protocol_version = session.protocol_version.get();
 
     // ALL FILE packets must be authenticated
     match validation::group::validate(&hyper_ratchet, security_level, header_bytes, payload) {
@@ -71,6 +72,29 @@ pub fn process_file_packet(
                                 }
                             };
 
+                            // transfers to the server are subject to the capabilities of the account
+                            let size = vfm.plaintext_length as u64;
+                            if session.is_server
+                                && !state_container
+                                    .account_features()
+                                    .permits_file_transfer(size)
+                            {
+                                log::warn!(target: "citadel", "Rejecting transfer of {} bytes from {}: not permitted for the account", size, header.session_cid.get());
+                                let file_header_ack =
+                                    packet_crafter::file::craft_file_header_ack_packet(
+                                        &hyper_ratchet,
+                                        false,
+                                        vfm.object_id,
+                                        target_cid,
+                                        ticket,
+                                        security_level,
+                                        v_target_flipped,
+                                        protocol_version,
+                                        ts,
+                                    );
+                                return Ok(PrimaryProcessorResult::ReplyToSender(file_header_ack));
+                            }
+
                             let preferred_primary_stream = return_if_none!(
                                 get_preferred_primary_stream(&header, session, &state_container)
                             );
@@ -197,6 +221,7 @@ pub fn process_file_packet(
                                     &hyper_ratchet,
                                     security_level,
                                     ticket,
+                                    protocol_version,
                                     ts,
                                     resp_target_cid,
                                     response_payload,
@@ -240,6 +265,7 @@ pub fn process_file_packet(
                                     &hyper_ratchet,
                                     security_level,
                                     ticket,
+                                    protocol_version,
                                     ts,
                                     resp_target_cid,
                                     err_opt,
@@ -50,18 +50,23 @@ pub async fn process_keep_alive(
                     .on_keep_alive_received(header.timestamp.get(), current_timestamp_ns)
                     || !state_container.meta_expiry_state.expired()
                 {
+                    let last_activity = state_container.meta_expiry_state.last_valid_event();
+                    let interval = state_container
+                        .keep_alive_backoff
+                        .next_interval(last_activity, Instant::now());
                     std::mem::drop(state_container);
                     // We no longer send the ka here since the sleeping blocked the ENTIRE task
-                    const DELTA_NS: i64 = (KEEP_ALIVE_INTERVAL_MS * 1_000_000) as i64;
+                    let delta_ns = interval.as_nanos() as i64;
                     // we can no longer hold-on to the StackedRatchet due to truncation
                     // ever since creating the anti-replay attack, we can no longer withhold packets; they must be sent outbound
                     // immediately, otherwise other packets will fail, invalidating the session
                     async move {
-                        tokio::time::sleep(Duration::from_millis(KEEP_ALIVE_INTERVAL_MS)).await;
+                        tokio::time::sleep(interval).await;
                         accessor.borrow_hr(None, |hr, _| {
                             let next_ka = packet_crafter::keep_alive::craft_keep_alive_packet(
                                 hr,
-                                current_timestamp_ns + DELTA_NS,
+                                session.protocol_version.get(),
+                                current_timestamp_ns + delta_ns,
                                 security_level,
                             );
                             to_primary_stream
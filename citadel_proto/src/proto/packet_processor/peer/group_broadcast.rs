@@ -74,23 +74,32 @@ pub async fn process_group_broadcast(
     log::trace!(target: "citadel", "[GROUP:{}] message: {:?}", session.is_server.if_true("server").if_false("client"), signal);
     match signal {
         GroupBroadcast::Create(initial_peers, options) => {
-            let key = session
-                .session_manager
-                .create_message_group_and_notify(
-                    timestamp,
-                    ticket,
-                    implicated_cid,
-                    initial_peers,
-                    security_level,
-                    options,
-                )
-                .await;
+            let group_creation_permitted = inner_state!(session.state_container)
+                .account_features()
+                .group_creation;
+            let key = if group_creation_permitted {
+                session
+                    .session_manager
+                    .create_message_group_and_notify(
+                        timestamp,
+                        ticket,
+                        implicated_cid,
+                        initial_peers,
+                        security_level,
+                        options,
+                    )
+                    .await
+            } else {
+                log::warn!(target: "citadel", "Rejecting group creation from {}: not permitted for the account", implicated_cid);
+                None
+            };
             let signal = GroupBroadcast::CreateResponse(key);
             let return_packet = packet_crafter::peer_cmd::craft_group_message_packet(
                 sess_hyper_ratchet,
                 &signal,
                 ticket,
                 C2S_ENCRYPTION_ONLY,
+                session.protocol_version.get(),
                 timestamp,
                 security_level,
             );
@@ -118,6 +127,7 @@ pub async fn process_group_broadcast(
                             &error,
                             ticket,
                             C2S_ENCRYPTION_ONLY,
+                            session.protocol_version.get(),
                             timestamp,
                             security_level,
                         );
@@ -132,6 +142,7 @@ pub async fn process_group_broadcast(
                             &success,
                             ticket,
                             C2S_ENCRYPTION_ONLY,
+                            session.protocol_version.get(),
                             timestamp,
                             security_level,
                         );
@@ -140,16 +151,20 @@ pub async fn process_group_broadcast(
 
                     Some(false) => {
                         // auto-accept is not enabled. Relay signal to owner
-                        let res = session.session_manager.route_packet_to(key.cid, |peer_hr| {
-                            packet_crafter::peer_cmd::craft_group_message_packet(
-                                peer_hr,
-                                &GroupBroadcast::RequestJoin(key),
-                                ticket,
-                                C2S_ENCRYPTION_ONLY,
-                                timestamp,
-                                security_level,
-                            )
-                        });
+                        let res = session.session_manager.route_packet_to(
+                            key.cid,
+                            |peer_hr, protocol_version| {
+                                packet_crafter::peer_cmd::craft_group_message_packet(
+                                    peer_hr,
+                                    &GroupBroadcast::RequestJoin(key),
+                                    ticket,
+                                    C2S_ENCRYPTION_ONLY,
+                                    protocol_version,
+                                    timestamp,
+                                    security_level,
+                                )
+                            },
+                        );
 
                         let signal = GroupBroadcast::SignalResponse(res);
                         let return_packet = packet_crafter::peer_cmd::craft_group_message_packet(
@@ -157,6 +172,7 @@ pub async fn process_group_broadcast(
                             &signal,
                             ticket,
                             C2S_ENCRYPTION_ONLY,
+                            session.protocol_version.get(),
                             timestamp,
                             security_level,
                         );
@@ -180,6 +196,7 @@ pub async fn process_group_broadcast(
                 &signal,
                 ticket,
                 C2S_ENCRYPTION_ONLY,
+                session.protocol_version.get(),
                 timestamp,
                 security_level,
             );
@@ -212,6 +229,7 @@ pub async fn process_group_broadcast(
                 &signal,
                 ticket,
                 C2S_ENCRYPTION_ONLY,
+                session.protocol_version.get(),
                 timestamp,
                 security_level,
             );
@@ -266,6 +284,7 @@ pub async fn process_group_broadcast(
                     &resp,
                     ticket,
                     C2S_ENCRYPTION_ONLY,
+                    session.protocol_version.get(),
                     timestamp,
                     security_level,
                 );
@@ -323,6 +342,7 @@ pub async fn process_group_broadcast(
                 &signal,
                 ticket,
                 C2S_ENCRYPTION_ONLY,
+                session.protocol_version.get(),
                 timestamp,
                 security_level,
             );
@@ -375,6 +395,7 @@ pub async fn process_group_broadcast(
                 &signal,
                 ticket,
                 C2S_ENCRYPTION_ONLY,
+                session.protocol_version.get(),
                 timestamp,
                 security_level,
             );
@@ -435,6 +456,7 @@ pub async fn process_group_broadcast(
                     &signal,
                     ticket,
                     C2S_ENCRYPTION_ONLY,
+                    session.protocol_version.get(),
                     timestamp,
                     security_level,
                 );
@@ -447,6 +469,7 @@ pub async fn process_group_broadcast(
                     &signal,
                     ticket,
                     C2S_ENCRYPTION_ONLY,
+                    session.protocol_version.get(),
                     timestamp,
                     security_level,
                 );
@@ -483,6 +506,7 @@ pub async fn process_group_broadcast(
                 &resp,
                 ticket,
                 C2S_ENCRYPTION_ONLY,
+                session.protocol_version.get(),
                 timestamp,
                 security_level,
             );
@@ -282,6 +282,14 @@ pub async fn process_peer_cmd(
                                         //log::trace!(target: "citadel", "0. Len: {}, {:?}", alice_pub_key.len(), &alice_pub_key[..10]);
                                         let msg_bytes =
                                             return_if_none!(transfer.serialize_to_vec());
+                                        let identity_proof = session
+                                            .peer_identity_settings
+                                            .load_identity(
+                                                session.account_manager.get_persistence_handler(),
+                                                *original_target_cid,
+                                            )
+                                            .await?
+                                            .prove(&msg_bytes)?;
                                         peer_kem_state_container.constructor =
                                             Some(alice_constructor);
                                         inner_mut_state!(session.state_container)
@@ -299,6 +307,7 @@ pub async fn process_peer_cmd(
                                             ),
                                             KeyExchangeProcess::Stage0(
                                                 msg_bytes,
+                                                identity_proof,
                                                 *endpoint_security_settings,
                                                 *udp_enabled,
                                             ),
@@ -309,6 +318,7 @@ pub async fn process_peer_cmd(
                                                 &sess_hyper_ratchet,
                                                 signal,
                                                 ticket,
+                                                session.protocol_version.get(),
                                                 timestamp,
                                                 security_level,
                                             );
@@ -329,6 +339,7 @@ pub async fn process_peer_cmd(
                             return match kep {
                                 KeyExchangeProcess::Stage0(
                                     transfer,
+                                    identity_proof,
                                     session_security_settings,
                                     udp_enabled,
                                 ) => {
@@ -337,6 +348,22 @@ pub async fn process_peer_cmd(
                                     //let mut state_container = inner_mut!(session.state_container);
                                     //let this_cid = conn.get_original_target_cid();
                                     let peer_cid = conn.get_original_implicated_cid();
+                                    if let Err(err) = session.peer_identity_settings.verify(
+                                        peer_cid,
+                                        identity_proof,
+                                        transfer,
+                                    ) {
+                                        return reject_peer_identity(
+                                            session,
+                                            conn,
+                                            err,
+                                            &sess_hyper_ratchet,
+                                            ticket,
+                                            timestamp,
+                                            security_level,
+                                        );
+                                    }
+
                                     let transfer_deser = return_if_none!(
                                         AliceToBobTransfer::deserialize_from(transfer)
                                     );
@@ -356,10 +383,22 @@ pub async fn process_peer_cmd(
 
                                     let bob_transfer =
                                         return_if_none!(transfer.serialize_to_vector().ok());
+                                    let identity_proof = session
+                                        .peer_identity_settings
+                                        .load_identity(
+                                            session.account_manager.get_persistence_handler(),
+                                            conn.get_original_target_cid(),
+                                        )
+                                        .await?
+                                        .prove(&bob_transfer)?;
 
                                     let signal = PeerSignal::Kem(
                                         conn.reverse(),
-                                        KeyExchangeProcess::Stage1(bob_transfer, None),
+                                        KeyExchangeProcess::Stage1(
+                                            bob_transfer,
+                                            identity_proof,
+                                            None,
+                                        ),
                                     );
 
                                     let mut state_container_kem = PeerKemStateContainer::new(
@@ -375,6 +414,7 @@ pub async fn process_peer_cmd(
                                         &sess_hyper_ratchet,
                                         signal,
                                         ticket,
+                                        session.protocol_version.get(),
                                         timestamp,
                                         security_level,
                                     );
@@ -382,12 +422,32 @@ pub async fn process_peer_cmd(
                                     Ok(PrimaryProcessorResult::ReplyToSender(stage1_kem))
                                 }
 
-                                KeyExchangeProcess::Stage1(transfer, Some(bob_nat_info)) => {
+                                KeyExchangeProcess::Stage1(
+                                    transfer,
+                                    identity_proof,
+                                    Some(bob_nat_info),
+                                ) => {
                                     // Here, we finalize the creation of the pqc for alice, and then, generate the new toolset
                                     // The toolset gets encrypted to ensure the central server doesn't see the toolset. This is
                                     // to combat a "chinese communist hijack" scenario wherein a rogue government takes over our
                                     // central servers
                                     log::trace!(target: "citadel", "RECV STAGE 1 PEER KEM");
+                                    if let Err(err) = session.peer_identity_settings.verify(
+                                        conn.get_original_implicated_cid(),
+                                        identity_proof,
+                                        transfer,
+                                    ) {
+                                        return reject_peer_identity(
+                                            session,
+                                            conn,
+                                            err,
+                                            &sess_hyper_ratchet,
+                                            ticket,
+                                            timestamp,
+                                            security_level,
+                                        );
+                                    }
+
                                     //let security_level = session.security_level;
 
                                     let (
@@ -487,6 +547,7 @@ pub async fn process_peer_cmd(
                                                 endpoint_hyper_ratchet,
                                                 SecurityLevel::Standard,
                                                 peer_cid,
+                                                session.protocol_version.get(),
                                                 stun_servers,
                                             );
 
@@ -496,6 +557,7 @@ pub async fn process_peer_cmd(
                                                 &sess_hyper_ratchet,
                                                 signal,
                                                 ticket,
+                                                session.protocol_version.get(),
                                                 timestamp,
                                                 security_level,
                                             );
@@ -660,6 +722,7 @@ pub async fn process_peer_cmd(
                                                 endpoint_hyper_ratchet,
                                                 SecurityLevel::Standard,
                                                 peer_cid,
+                                                session.protocol_version.get(),
                                                 stun_servers,
                                             );
                                         let diff = Duration::from_nanos(i64::abs(
@@ -699,6 +762,21 @@ pub async fn process_peer_cmd(
                                     Ok(PrimaryProcessorResult::Void)
                                 }
 
+                                KeyExchangeProcess::IdentityRejected => {
+                                    let peer_cid = conn.get_original_implicated_cid();
+                                    log::warn!(target: "citadel", "Peer {} rejected the identity of this node", peer_cid);
+                                    let ticket =
+                                        abort_peer_kem(session, peer_cid).unwrap_or(ticket);
+                                    session.send_to_kernel(NodeResult::PeerEvent(PeerEvent {
+                                        event: PeerSignal::SignalError(
+                                            ticket,
+                                            format!("Peer {peer_cid} rejected the identity of this node"),
+                                        ),
+                                        ticket,
+                                    }))?;
+                                    Ok(PrimaryProcessorResult::Void)
+                                }
+
                                 _ => {
                                     log::error!(target: "citadel", "INVALID KEM signal");
                                     Ok(PrimaryProcessorResult::Void)
@@ -777,7 +855,7 @@ async fn process_signal_command_as_server(
             };
 
             match &mut kep {
-                KeyExchangeProcess::Stage1(_, val) | KeyExchangeProcess::Stage2(_, val) => {
+                KeyExchangeProcess::Stage1(_, _, val) | KeyExchangeProcess::Stage2(_, val) => {
                     *val = Some(peer_nat_info);
                 }
 
@@ -794,11 +872,12 @@ async fn process_signal_command_as_server(
 
             let res = sess_mgr.send_signal_to_peer_direct(
                 conn.get_original_target_cid(),
-                move |peer_hyper_ratchet| {
+                move |peer_hyper_ratchet, protocol_version| {
                     packet_crafter::peer_cmd::craft_peer_signal(
                         peer_hyper_ratchet,
                         signal_to,
                         ticket,
+                        protocol_version,
                         timestamp,
                         security_level,
                     )
@@ -806,7 +885,14 @@ async fn process_signal_command_as_server(
             );
 
             if let Err(err) = res {
-                reply_to_sender_err(err, &sess_hyper_ratchet, ticket, timestamp, security_level)
+                reply_to_sender_err(
+                    err,
+                    &sess_hyper_ratchet,
+                    ticket,
+                    session.protocol_version.get(),
+                    timestamp,
+                    security_level,
+                )
             } else {
                 Ok(PrimaryProcessorResult::Void)
             }
@@ -824,7 +910,25 @@ async fn process_signal_command_as_server(
                 PeerConnectionType::HyperLANPeerToHyperLANPeer(_implicated_cid, target_cid) => {
                     let implicated_cid = header.session_cid.get();
                     const TIMEOUT: Duration = Duration::from_secs(60 * 60); // 1 hour
-                                                                            // if the peer response is some, then HyperLAN Client B responded
+
+                    // both the initiator and the accepting peer are subject to their peer limits
+                    let registers_peer = match peer_response.as_ref() {
+                        None | Some(PeerResponse::Accept(_)) => true,
+                        Some(_) => false,
+                    };
+                    if registers_peer && !permits_new_peer(session, implicated_cid).await? {
+                        log::warn!(target: "citadel", "Rejecting peer registration for {}: peer limit reached", implicated_cid);
+                        return reply_to_sender_err(
+                            "The peer limit of this account has been reached",
+                            &sess_hyper_ratchet,
+                            ticket,
+                            session.protocol_version.get(),
+                            timestamp,
+                            security_level,
+                        );
+                    }
+
+                    // if the peer response is some, then HyperLAN Client B responded
                     if let Some(peer_response) = peer_response {
                         // the signal is going to be routed from HyperLAN Client B to HyperLAN client A (response phase)
                         super::server::post_register::handle_response_phase_post_register(
@@ -908,6 +1012,7 @@ async fn process_signal_command_as_server(
                                 &sess_hyper_ratchet,
                                 cmd,
                                 ticket,
+                                session.protocol_version.get(),
                                 timestamp,
                                 security_level,
                             );
@@ -928,6 +1033,7 @@ async fn process_signal_command_as_server(
                                 TIMEOUT,
                                 implicated_cid,
                                 target_cid,
+                                session.protocol_version.get(),
                                 timestamp,
                                 ticket,
                                 &to_primary_stream,
@@ -1009,6 +1115,7 @@ async fn process_signal_command_as_server(
                                 &sess_hyper_ratchet,
                                 success_cmd,
                                 ticket,
+                                session.protocol_version.get(),
                                 timestamp,
                                 security_level,
                             );
@@ -1023,6 +1130,7 @@ async fn process_signal_command_as_server(
                                 &sess_hyper_ratchet,
                                 error_signal,
                                 ticket,
+                                session.protocol_version.get(),
                                 timestamp,
                                 security_level,
                             );
@@ -1111,6 +1219,7 @@ async fn process_signal_command_as_server(
                                 TIMEOUT,
                                 implicated_cid,
                                 target_cid,
+                                session.protocol_version.get(),
                                 timestamp,
                                 ticket,
                                 &to_primary_stream,
@@ -1182,12 +1291,13 @@ async fn process_signal_command_as_server(
                             let _ = session_manager.disconnect_virtual_conn(
                                 implicated_cid,
                                 target_cid,
-                                move |peer_hyper_ratchet| {
+                                move |peer_hyper_ratchet, protocol_version| {
                                     // send signal to peer
                                     packet_crafter::peer_cmd::craft_peer_signal(
                                         peer_hyper_ratchet,
                                         signal_to_peer,
                                         ticket,
+                                        protocol_version,
                                         timestamp,
                                         security_level,
                                     )
@@ -1243,6 +1353,7 @@ async fn process_signal_command_as_server(
                         rebound_signal,
                         &sess_hyper_ratchet,
                         ticket,
+                        session.protocol_version.get(),
                         timestamp,
                         security_level,
                     )
@@ -1279,6 +1390,7 @@ async fn process_signal_command_as_server(
                     rebound_signal,
                     &sess_hyper_ratchet,
                     ticket,
+                    session.protocol_version.get(),
                     timestamp,
                     security_level,
                 )
@@ -1323,6 +1435,9 @@ async fn process_signal_command_as_server(
 
         PeerSignal::DeregistrationSuccess(..) => Ok(PrimaryProcessorResult::Void),
 
+        // only the server may announce changes in presence
+        PeerSignal::PresenceChanged(..) => Ok(PrimaryProcessorResult::Void),
+
         PeerSignal::DisconnectUDP(v_conn) => {
             // close this UDP channel
             inner_mut_state!(session.state_container).remove_udp_channel(v_conn.get_target_cid());
@@ -1331,12 +1446,66 @@ async fn process_signal_command_as_server(
     }
 }
 
+/// Returns true if the capabilities of this session's account permit registering to another peer
+async fn permits_new_peer(session: &HdpSession, implicated_cid: u64) -> Result<bool, NetworkError> {
+    let features = inner_state!(session.state_container).account_features();
+    if features.max_peers.is_none() {
+        return Ok(true);
+    }
+
+    let peer_count = session
+        .account_manager
+        .get_hyperlan_peer_count(implicated_cid)
+        .await?;
+    Ok(features.permits_new_peer(peer_count))
+}
+
+/// Removes the key exchange state for `peer_cid`, returning the ticket of the local attempt to
+/// connect to `peer_cid`, if any
+fn abort_peer_kem(session: &HdpSession, peer_cid: u64) -> Option<Ticket> {
+    let mut state_container = inner_mut_state!(session.state_container);
+    let _ = state_container.peer_kem_states.remove(&peer_cid);
+    state_container
+        .outgoing_peer_connect_attempts
+        .remove(&peer_cid)
+}
+
+/// Aborts the key exchange after the identity proof of the peer fails verification, alerting
+/// both the local kernel and the peer
+fn reject_peer_identity(
+    session: &HdpSession,
+    conn: &PeerConnectionType,
+    err: NetworkError,
+    hyper_ratchet: &StackedRatchet,
+    ticket: Ticket,
+    timestamp: i64,
+    security_level: SecurityLevel,
+) -> Result<PrimaryProcessorResult, NetworkError> {
+    let peer_cid = conn.get_original_implicated_cid();
+    log::warn!(target: "citadel", "Rejecting the identity of peer {}: {:?}", peer_cid, err);
+    let kernel_ticket = abort_peer_kem(session, peer_cid).unwrap_or(ticket);
+    session.send_to_kernel(NodeResult::PeerEvent(PeerEvent {
+        event: PeerSignal::SignalError(kernel_ticket, err.into_string()),
+        ticket: kernel_ticket,
+    }))?;
+
+    reply_to_sender(
+        PeerSignal::Kem(conn.reverse(), KeyExchangeProcess::IdentityRejected),
+        hyper_ratchet,
+        ticket,
+        session.protocol_version.get(),
+        timestamp,
+        security_level,
+    )
+}
+
 #[inline]
 /// This just makes the repeated operation above cleaner. By itself does not send anything; must return the result of this closure directly
 fn reply_to_sender(
     signal: PeerSignal,
     hyper_ratchet: &StackedRatchet,
     ticket: Ticket,
+    protocol_version: u32,
     timestamp: i64,
     security_level: SecurityLevel,
 ) -> Result<PrimaryProcessorResult, NetworkError> {
@@ -1344,6 +1513,7 @@ fn reply_to_sender(
         hyper_ratchet,
         signal,
         ticket,
+        protocol_version,
         timestamp,
         security_level,
     );
@@ -1354,11 +1524,19 @@ fn reply_to_sender_err<E: ToString>(
     err: E,
     hyper_ratchet: &StackedRatchet,
     ticket: Ticket,
+    protocol_version: u32,
     timestamp: i64,
     security_level: SecurityLevel,
 ) -> Result<PrimaryProcessorResult, NetworkError> {
     Ok(PrimaryProcessorResult::ReplyToSender(
-        construct_error_signal(err, hyper_ratchet, ticket, timestamp, security_level),
+        construct_error_signal(
+            err,
+            hyper_ratchet,
+            ticket,
+            protocol_version,
+            timestamp,
+            security_level,
+        ),
     ))
 }
 
@@ -1366,6 +1544,7 @@ fn construct_error_signal<E: ToString>(
     err: E,
     hyper_ratchet: &StackedRatchet,
     ticket: Ticket,
+    protocol_version: u32,
     timestamp: i64,
     security_level: SecurityLevel,
 ) -> BytesMut {
@@ -1374,6 +1553,7 @@ fn construct_error_signal<E: ToString>(
         hyper_ratchet,
         err_signal,
         ticket,
+        protocol_version,
         timestamp,
         security_level,
     )
@@ -1386,6 +1566,7 @@ pub(crate) async fn route_signal_and_register_ticket_forwards(
     timeout: Duration,
     implicated_cid: u64,
     target_cid: u64,
+    protocol_version: u32,
     timestamp: i64,
     ticket: Ticket,
     to_primary_stream: &OutboundPrimaryStreamSender,
@@ -1397,25 +1578,33 @@ pub(crate) async fn route_signal_and_register_ticket_forwards(
     let to_primary_stream = to_primary_stream.clone();
 
     // Give the target_cid 10 seconds to respond
-    let res = sess_mgr.route_signal_primary(peer_layer, implicated_cid, target_cid, ticket, signal.clone(), move |peer_hyper_ratchet| {
-        packet_crafter::peer_cmd::craft_peer_signal(peer_hyper_ratchet, signal.clone(), ticket, timestamp, security_level)
+    let res = sess_mgr.route_signal_primary(peer_layer, implicated_cid, target_cid, ticket, signal.clone(), move |peer_hyper_ratchet, peer_protocol_version| {
+        packet_crafter::peer_cmd::craft_peer_signal(peer_hyper_ratchet, signal.clone(), ticket, peer_protocol_version, timestamp, security_level)
     }, timeout, move |stale_signal| {
         // on timeout, run this
         // TODO: Use latest ratchet, otherwise, may expire
         log::warn!(target: "citadel", "Running timeout closure. Sending error message to {}", implicated_cid);
-        let error_packet = packet_crafter::peer_cmd::craft_peer_signal(&sess_hyper_ratchet_2, stale_signal, ticket, timestamp, security_level);
+        let error_packet = packet_crafter::peer_cmd::craft_peer_signal(&sess_hyper_ratchet_2, stale_signal, ticket, protocol_version, timestamp, security_level);
         let _ = to_primary_stream.unbounded_send(error_packet);
     }).await;
 
     // Then, we tell the implicated_cid's node that we have handled the message. However, the peer has yet to respond
     if let Err(err) = res {
-        reply_to_sender_err(err, sess_hyper_ratchet, ticket, timestamp, security_level)
+        reply_to_sender_err(
+            err,
+            sess_hyper_ratchet,
+            ticket,
+            protocol_version,
+            timestamp,
+            security_level,
+        )
     } else {
         let received_signal = PeerSignal::SignalReceived(ticket);
         reply_to_sender(
             received_signal,
             sess_hyper_ratchet,
             ticket,
+            protocol_version,
             timestamp,
             security_level,
         )
@@ -1438,6 +1627,7 @@ pub(crate) async fn route_signal_response(
 ) -> Result<PrimaryProcessorResult, NetworkError> {
     log::trace!(target: "citadel", "Routing signal {:?} | impl: {} | target: {}", signal, implicated_cid, target_cid);
     let sess_ref = &session;
+    let protocol_version = session.protocol_version.get();
 
     let res = session
         .session_manager
@@ -1446,11 +1636,12 @@ pub(crate) async fn route_signal_response(
             target_cid,
             ticket,
             peer_layer,
-            move |peer_hyper_ratchet| {
+            move |peer_hyper_ratchet, peer_protocol_version| {
                 packet_crafter::peer_cmd::craft_peer_signal(
                     peer_hyper_ratchet,
                     signal,
                     ticket,
+                    peer_protocol_version,
                     timestamp,
                     security_level,
                 )
@@ -1462,6 +1653,7 @@ pub(crate) async fn route_signal_response(
                     received_signal,
                     sess_hyper_ratchet,
                     ticket,
+                    protocol_version,
                     timestamp,
                     security_level,
                 );
@@ -1478,7 +1670,14 @@ pub(crate) async fn route_signal_response(
 
         Err(err) => {
             log::warn!(target: "citadel", "Unable to route signal! {:?}", err);
-            reply_to_sender_err(err, sess_hyper_ratchet, ticket, timestamp, security_level)
+            reply_to_sender_err(
+                err,
+                sess_hyper_ratchet,
+                ticket,
+                protocol_version,
+                timestamp,
+                security_level,
+            )
         }
     }
 }
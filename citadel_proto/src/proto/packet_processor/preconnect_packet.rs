@@ -4,7 +4,8 @@ use citadel_wire::udp_traversal::targetted_udp_socket_addr::HolePunchedUdpSocket
 use netbeam::sync::RelativeNodeType;
 
 use crate::constants::HOLE_PUNCH_SYNC_TIME_MULTIPLIER;
-use crate::error::NetworkError;
+use crate::error::{ConnectError, NetworkError};
+use crate::proto::misc::protocol_version::ProtocolVersion;
 use crate::proto::misc::udp_internal_interface::{
     QuicUdpSocketConnector, RawUdpSocketConnector, UdpSplittableTypes,
 };
@@ -18,6 +19,7 @@ use super::includes::*;
 use crate::proto::node_result::ConnectFail;
 use crate::proto::packet_processor::primary_group_packet::get_proper_hyper_ratchet;
 use crate::proto::state_subcontainers::preconnect_state_container::UdpChannelSender;
+use crate::proto::validation::pre_connect::SynKeyExchange;
 use citadel_wire::exports::Connection;
 use citadel_wire::udp_traversal::udp_hole_puncher::EndpointHolePunchExt;
 use netbeam::sync::network_endpoint::NetworkEndpoint;
@@ -46,16 +48,12 @@ pub async fn process_preconnect(
         match header.cmd_aux {
             packet_flags::cmd::aux::do_preconnect::SYN => {
                 log::trace!(target: "citadel", "RECV STAGE SYN PRE_CONNECT PACKET");
-                // TODO: prevent logins if semvers out of sync. For now, don't
-                let adjacent_proto_version = header.protocol_version.get();
-                if proto_version_out_of_sync(adjacent_proto_version)? {
-                    log::warn!(target: "citadel", "\nLocal protocol version: {} | Adjacent protocol version: {} | Versions out of sync; program may not function\n", *crate::constants::PROTOCOL_VERSION, adjacent_proto_version);
-                    // TODO: protocol translations for inter-version compatibility
-                }
-                // first make sure the cid isn't already connected
-                let session_already_active = session
+                // first make sure the cid has room for another session. If the oldest session
+                // is to be evicted instead, that happens once the connect stage validates the
+                // credentials
+                let session_limit = session
                     .session_manager
-                    .session_active(header.session_cid.get());
+                    .check_session_limit(header.session_cid.get());
                 let account_manager = session.account_manager.clone();
                 let header_if_err_occurs = header.clone();
 
@@ -67,57 +65,117 @@ pub async fn process_preconnect(
                     Ok(PrimaryProcessorResult::ReplyToSender(packet))
                 };
 
-                if session_already_active {
-                    return error(NetworkError::InvalidRequest("Session Already Connected"));
+                // on nodes that require prior registration, reject unknown CIDs before loading any account
+                // or performing key agreement, reducing the cost of probing
+                if account_manager
+                    .get_misc_settings()
+                    .require_prior_registration
+                {
+                    let cid = header.session_cid.get();
+                    if !account_manager
+                        .get_persistence_handler()
+                        .cid_is_registered(cid)
+                        .await?
+                    {
+                        log::warn!(target: "citadel", "Rejecting connect attempt for unregistered CID {}", cid);
+                        return error(ConnectError::UnknownUser(cid).into());
+                    }
+                }
+
+                if let Err(err) = session_limit {
+                    return error(err.into());
                 }
 
                 if let Some(cnac) = account_manager
                     .get_client_by_cid(header.session_cid.get())
                     .await?
                 {
+                    // tickets are single-use, yet only a SYN that passes validation may consume one. Otherwise,
+                    // any SYN naming the CID would revoke the ticket of its client
+                    let validation = session
+                        .session_manager
+                        .redeem_issued_resumption_ticket(
+                            cnac.get_cid(),
+                            &cnac.get_static_auxiliary_hyper_ratchet(),
+                            |issued_resumption_ticket| {
+                                validation::pre_connect::validate_syn(
+                                    &cnac,
+                                    packet,
+                                    &session.session_manager,
+                                    &session.supported_protocol_versions,
+                                    issued_resumption_ticket,
+                                )
+                                .map(|result| {
+                                    let resumed = matches!(result.0, SynKeyExchange::Resumed);
+                                    (result, resumed)
+                                })
+                            },
+                        )
+                        .await;
                     let mut state_container = inner_mut_state!(session.state_container);
 
-                    match validation::pre_connect::validate_syn(
-                        &cnac,
-                        packet,
-                        &session.session_manager,
-                    ) {
+                    match validation {
                         Ok((
-                            static_aux_ratchet,
-                            transfer,
+                            key_exchange,
                             session_security_settings,
                             peer_only_connect_mode,
                             udp_mode,
                             kat,
                             nat_type,
                             new_hyper_ratchet,
+                            protocol_version,
                         )) => {
+                            // every packet from here on, including the reply, carries the negotiated version
+                            log::trace!(target: "citadel", "Negotiated protocol version {}", protocol_version);
+                            session.protocol_version.set(protocol_version.to_u32());
                             session.adjacent_nat_type.set_once(Some(nat_type));
-                            state_container.pre_connect_state.generated_ratchet =
-                                Some(new_hyper_ratchet);
-                            // since the SYN's been validated, the CNACs toolset has been updated
-                            let new_session_sec_lvl = transfer.security_level;
-
-                            log::trace!(target: "citadel", "Synchronizing toolsets. UDP mode: {:?}. Session security level: {:?}", udp_mode, new_session_sec_lvl);
                             // TODO: Rate limiting to prevent SYN flooding
                             let timestamp = session.time_tracker.get_global_time_ns();
 
                             state_container.pre_connect_state.on_packet_received();
+                            state_container.keep_alive_timeout_ns =
+                                session.keep_alive_policy.tolerate_timeout_ns(kat);
+
+                            let reply = match key_exchange {
+                                SynKeyExchange::Full(static_aux_ratchet, transfer) => {
+                                    // since the SYN's been validated, the CNACs toolset has been updated
+                                    let new_session_sec_lvl = transfer.security_level;
+                                    log::trace!(target: "citadel", "Synchronizing toolsets. UDP mode: {:?}. Session security level: {:?}", udp_mode, new_session_sec_lvl);
+
+                                    state_container.pre_connect_state.last_stage =
+                                        packet_flags::cmd::aux::do_preconnect::SYN_ACK;
+
+                                    // here, we also send the peer's external address to itself
+                                    // Also, we use the security level that was created on init b/c the other side still uses the static aux ratchet
+                                    packet_crafter::pre_connect::craft_syn_ack(
+                                        &static_aux_ratchet,
+                                        transfer,
+                                        session.local_nat_type.clone(),
+                                        session.protocol_version.get(),
+                                        timestamp,
+                                        security_level,
+                                    )
+                                }
+
+                                SynKeyExchange::Resumed => {
+                                    // the ticket was valid, so skip the remainder of the pre-connect stage and
+                                    // allow the client to begin the connect stage immediately
+                                    log::trace!(target: "citadel", "Resuming session. Skipping key agreement");
+                                    state_container.pre_connect_state.last_stage =
+                                        packet_flags::cmd::aux::do_preconnect::SUCCESS;
+                                    state_container.pre_connect_state.success = true;
+                                    state_container.pre_connect_state.session_resumed = true;
+                                    packet_crafter::pre_connect::craft_begin_connect(
+                                        &new_hyper_ratchet,
+                                        session.protocol_version.get(),
+                                        timestamp,
+                                        security_level,
+                                    )
+                                }
+                            };
 
-                            state_container.pre_connect_state.last_stage =
-                                packet_flags::cmd::aux::do_preconnect::SYN_ACK;
-                            state_container.keep_alive_timeout_ns = kat;
-
-                            // here, we also send the peer's external address to itself
-                            // Also, we use the security level that was created on init b/c the other side still uses the static aux ratchet
-                            let syn_ack = packet_crafter::pre_connect::craft_syn_ack(
-                                &static_aux_ratchet,
-                                transfer,
-                                session.local_nat_type.clone(),
-                                timestamp,
-                                security_level,
-                            );
-
+                            state_container.pre_connect_state.generated_ratchet =
+                                Some(new_hyper_ratchet);
                             state_container.udp_mode = udp_mode;
                             state_container.cnac = Some(cnac);
                             state_container.session_security_settings =
@@ -126,7 +184,7 @@ pub async fn process_preconnect(
                                 .peer_only_connect_protocol
                                 .set(Some(peer_only_connect_mode));
 
-                            Ok(PrimaryProcessorResult::ReplyToSender(syn_ack))
+                            Ok(PrimaryProcessorResult::ReplyToSender(reply))
                         }
 
                         Err(err) => {
@@ -155,12 +213,15 @@ pub async fn process_preconnect(
                     if state_container.pre_connect_state.last_stage
                         == packet_flags::cmd::aux::do_preconnect::SYN_ACK
                     {
+                        // if we presented a resumption ticket, the server rejected it
+                        state_container.pre_connect_state.resumption_ratchet = None;
                         // cnac should already be loaded locally
                         let alice_constructor = return_if_none!(
                             state_container.pre_connect_state.constructor.take(),
                             "Alice constructor not loaded"
                         );
                         let implicated_cid = header.session_cid.get();
+                        let selected_protocol_version = header.protocol_version.get();
                         if let Some((new_hyper_ratchet, nat_type)) =
                             validation::pre_connect::validate_syn_ack(
                                 cnac,
@@ -168,6 +229,16 @@ pub async fn process_preconnect(
                                 packet,
                             )
                         {
+                            if !adopt_protocol_version(
+                                session,
+                                implicated_cid,
+                                selected_protocol_version,
+                            )? {
+                                return Ok(PrimaryProcessorResult::EndSession(
+                                    "Unsupported protocol version selected",
+                                ));
+                            }
+
                             // The toolset, at this point, has already been updated. The CNAC can be used to
                             //let ref drill = cnac.get_drill_blocking(None)?;
                             session.adjacent_nat_type.set_once(Some(nat_type));
@@ -183,6 +254,7 @@ pub async fn process_preconnect(
                                 let stage0_preconnect_packet =
                                     packet_crafter::pre_connect::craft_stage0(
                                         &new_hyper_ratchet,
+                                        session.protocol_version.get(),
                                         timestamp,
                                         local_node_type,
                                         security_level,
@@ -215,6 +287,7 @@ pub async fn process_preconnect(
                             let stage0_preconnect_packet =
                                 packet_crafter::pre_connect::craft_stage0(
                                     &new_hyper_ratchet,
+                                    session.protocol_version.get(),
                                     timestamp,
                                     local_node_type,
                                     security_level,
@@ -254,6 +327,7 @@ pub async fn process_preconnect(
                         new_hyper_ratchet.clone(),
                         SecurityLevel::Standard,
                         C2S_ENCRYPTION_ONLY,
+                        session.protocol_version.get(),
                         stun_servers,
                     ))
                     .await;
@@ -318,6 +392,7 @@ pub async fn process_preconnect(
                                 state_container.pre_connect_state.success = true;
                                 let packet = packet_crafter::pre_connect::craft_begin_connect(
                                     &hyper_ratchet,
+                                    session.protocol_version.get(),
                                     timestamp,
                                     security_level,
                                 );
@@ -358,6 +433,7 @@ pub async fn process_preconnect(
                         hyper_ratchet.clone(),
                         SecurityLevel::Standard,
                         C2S_ENCRYPTION_ONLY,
+                        session.protocol_version.get(),
                         stun_servers,
                     ))
                     .await;
@@ -415,6 +491,7 @@ pub async fn process_preconnect(
                         log::warn!(target: "citadel", "Received signal to fall-back to TCP only mode");
                         let begin_connect = packet_crafter::pre_connect::craft_begin_connect(
                             &hyper_ratchet,
+                            session.protocol_version.get(),
                             timestamp,
                             security_level,
                         );
@@ -448,6 +525,7 @@ pub async fn process_preconnect(
                             ticket,
                             cid_opt: Some(cnac.get_cid()),
                             error_message: "Preconnect stage failed".to_string(),
+                            connect_error: None,
                         }))?;
                         Ok(PrimaryProcessorResult::EndSession(
                             "Failure packet received",
@@ -455,6 +533,7 @@ pub async fn process_preconnect(
                     } else {
                         let begin_connect = packet_crafter::pre_connect::craft_begin_connect(
                             &hyper_ratchet,
+                            session.protocol_version.get(),
                             timestamp,
                             security_level,
                         );
@@ -470,6 +549,22 @@ pub async fn process_preconnect(
             packet_flags::cmd::aux::do_preconnect::BEGIN_CONNECT => {
                 log::trace!(target: "citadel", "RECV STAGE BEGIN_CONNECT PRE CONNECT PACKET");
                 let mut state_container = inner_mut_state!(session.state_container);
+                // Receiving BEGIN_CONNECT in place of a SYN_ACK means the server accepted our resumption ticket
+                if state_container.pre_connect_state.last_stage
+                    == packet_flags::cmd::aux::do_preconnect::SYN_ACK
+                {
+                    if let Some(latest_ratchet) =
+                        state_container.pre_connect_state.resumption_ratchet.take()
+                    {
+                        log::trace!(target: "citadel", "Server accepted resumption ticket. Skipping key agreement");
+                        state_container.pre_connect_state.constructor = None;
+                        state_container.pre_connect_state.generated_ratchet = Some(latest_ratchet);
+                        state_container.pre_connect_state.last_stage =
+                            packet_flags::cmd::aux::do_preconnect::SUCCESS;
+                        state_container.pre_connect_state.session_resumed = true;
+                    }
+                }
+
                 let hr = return_if_none!(
                     get_proper_hyper_ratchet(header_drill_vers, &state_container, None),
                     "Could not get proper HR [preconnect1]"
@@ -479,9 +574,20 @@ pub async fn process_preconnect(
                     == packet_flags::cmd::aux::do_preconnect::SUCCESS
                 {
                     let (header, payload, _, _) = packet.decompose();
-                    if let Some((_, _, hyper_ratchet)) =
+                    if let Some((header, _, hyper_ratchet)) =
                         validation::aead::validate(hr, &header, payload)
                     {
+                        // a resumed session receives no SYN_ACK, so the version is adopted here
+                        if !adopt_protocol_version(
+                            session,
+                            header.session_cid.get(),
+                            header.protocol_version.get(),
+                        )? {
+                            return Ok(PrimaryProcessorResult::EndSession(
+                                "Unsupported protocol version selected",
+                            ));
+                        }
+
                         state_container.pre_connect_state.success = true;
                         std::mem::drop(state_container);
                         // now, begin stage 0 connect
@@ -504,6 +610,7 @@ pub async fn process_preconnect(
                     ticket,
                     cid_opt: Some(header.session_cid.get()),
                     error_message: message,
+                    connect_error: None,
                 }))?;
                 //session.needs_close_message.set(false);
                 Ok(PrimaryProcessorResult::EndSession(
@@ -521,6 +628,34 @@ pub async fn process_preconnect(
     to_concurrent_processor!(task)
 }
 
+/// Adopts the protocol version the server selected, as carried by the header of its reply. Returns
+/// false, alerting the kernel, if the server selected a version outside of the supported range
+fn adopt_protocol_version(
+    session: &HdpSession,
+    implicated_cid: u64,
+    selected: u32,
+) -> Result<bool, NetworkError> {
+    if let Some(version) = ProtocolVersion::from_u32(selected)
+        .filter(|version| session.supported_protocol_versions.contains(*version))
+    {
+        log::trace!(target: "citadel", "Adopting protocol version {}", version);
+        session.protocol_version.set(selected);
+        return Ok(true);
+    }
+
+    log::warn!(target: "citadel", "The server selected protocol version {} which is outside of {}", selected, session.supported_protocol_versions);
+    session.send_to_kernel(NodeResult::ConnectFail(ConnectFail {
+        ticket: session.kernel_ticket.get(),
+        cid_opt: Some(implicated_cid),
+        error_message: format!(
+            "The server selected a protocol version outside of the supported versions {}",
+            session.supported_protocol_versions
+        ),
+        connect_error: None,
+    }))?;
+    Ok(false)
+}
+
 fn begin_connect_process(
     session: &HdpSession,
     hyper_ratchet: &StackedRatchet,
@@ -537,13 +672,17 @@ fn begin_connect_process(
     let stage0_connect_packet = crate::proto::packet_crafter::do_connect::craft_stage0_packet(
         hyper_ratchet,
         proposed_credentials,
+        session.protocol_version.get(),
         timestamp,
         security_level,
     );
     state_container.connect_state.last_stage = packet_flags::cmd::aux::do_connect::STAGE1;
+    let session_security_settings = state_container.session_security_settings;
     // we now store the pqc temporarily in the state container
     //session.post_quantum = Some(new_pqc);
     std::mem::drop(state_container);
+    // the server replies with the connect success packet, after which headers may be session-keyed
+    session.schedule_header_protection(hyper_ratchet, session_security_settings);
     session
         .state
         .store(SessionState::ConnectionProcess, Ordering::Relaxed);
@@ -568,6 +707,7 @@ fn send_success_as_initiator(
         hyper_ratchet,
         true,
         false,
+        session.protocol_version.get(),
         session.time_tracker.get_global_time_ns(),
         security_level,
     );
@@ -617,6 +757,7 @@ pub(crate) fn generate_hole_punch_crypt_container(
     hyper_ratchet: StackedRatchet,
     security_level: SecurityLevel,
     target_cid: u64,
+    protocol_version: u32,
     stun_servers: Option<Vec<String>>,
 ) -> HolePunchConfigContainer {
     let hyper_ratchet_cloned = hyper_ratchet.clone();
@@ -626,6 +767,7 @@ pub(crate) fn generate_hole_punch_crypt_container(
             packet_crafter::hole_punch::generate_packet(
                 &hyper_ratchet,
                 plaintext,
+                protocol_version,
                 security_level,
                 target_cid,
             )
@@ -653,23 +795,6 @@ pub fn calculate_sync_time(current: i64, header: i64) -> (Instant, i64) {
     (sync_time_instant, sync_time_ns)
 }
 
-fn proto_version_out_of_sync(adjacent_proto_version: u32) -> Result<bool, NetworkError> {
-    use embedded_semver::Semver;
-    match Semver::from_u32(adjacent_proto_version) {
-        Ok(their_version) => {
-            // if either major or minor releases are not equal, assume breaking change
-            Ok(
-                their_version.major != crate::constants::MAJOR_VERSION as usize
-                    || their_version.minor != crate::constants::MINOR_VERSION as usize,
-            )
-        }
-
-        Err(_) => Err(NetworkError::InvalidRequest(
-            "Unable to parse incoming protocol semver",
-        )),
-    }
-}
-
 fn get_raw_udp_interface(socket: HolePunchedUdpSocket) -> UdpSplittableTypes {
     log::trace!(target: "citadel", "Will use Raw UDP for UDP transmission");
     UdpSplittableTypes::Raw(RawUdpSocketConnector::new(
@@ -682,53 +807,3 @@ fn get_quic_udp_interface(quic_conn: Connection, local_addr: SocketAddr) -> UdpS
     log::trace!(target: "citadel", "Will use QUIC UDP for UDP transmission");
     UdpSplittableTypes::Quic(QuicUdpSocketConnector::new(quic_conn, local_addr))
 }
-
-#[cfg(test)]
-mod tests {
-    use crate::constants::PROTOCOL_VERSION;
-    use crate::proto::packet_processor::preconnect_packet::proto_version_out_of_sync;
-
-    #[test]
-    fn test_good_version() {
-        let our_version = embedded_semver::Semver::from_u32(*PROTOCOL_VERSION).unwrap();
-        for shift in 1..3 {
-            let their_version = embedded_semver::Semver::new(
-                our_version.major,
-                our_version.minor,
-                our_version.patch + shift,
-            );
-            assert!(!proto_version_out_of_sync(their_version.to_u32().unwrap()).unwrap())
-        }
-    }
-
-    #[test]
-    fn test_bad_major_version() {
-        let our_version = embedded_semver::Semver::from_u32(*PROTOCOL_VERSION).unwrap();
-        for shift in 1..3 {
-            let their_version = embedded_semver::Semver::new(
-                our_version.major + shift,
-                our_version.minor,
-                our_version.patch,
-            );
-            assert!(proto_version_out_of_sync(their_version.to_u32().unwrap()).unwrap())
-        }
-    }
-
-    #[test]
-    fn test_bad_minor_version() {
-        let our_version = embedded_semver::Semver::from_u32(*PROTOCOL_VERSION).unwrap();
-        for shift in 1..3 {
-            let their_version = embedded_semver::Semver::new(
-                our_version.major,
-                our_version.minor + shift,
-                our_version.patch,
-            );
-            assert!(proto_version_out_of_sync(their_version.to_u32().unwrap()).unwrap())
-        }
-    }
-
-    #[test]
-    fn test_bad_parse() {
-        assert!(proto_version_out_of_sync(u32::MAX).is_err());
-    }
-}
@@ -153,6 +153,7 @@ pub fn process_primary_packet(
                                         ticket,
                                         None,
                                         true,
+                                        session.protocol_version.get(),
                                         timestamp,
                                         transfer,
                                         security_level,
@@ -244,6 +245,7 @@ pub fn process_primary_packet(
                                                 ticket,
                                                 initial_wave_window,
                                                 false,
+                                                session.protocol_version.get(),
                                                 timestamp,
                                                 KemTransferStatus::Empty,
                                                 security_level,
@@ -327,6 +329,7 @@ pub fn process_primary_packet(
                                                     &hyper_ratchet,
                                                     needs_truncate,
                                                     target_cid,
+                                                    session.protocol_version.get(),
                                                     timestamp,
                                                     security_level,
                                                 );
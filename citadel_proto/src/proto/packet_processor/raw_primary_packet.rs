@@ -26,10 +26,19 @@ pub async fn process_raw_packet(
     local_primary_port: u16,
     packet: BytesMut,
 ) -> Result<PrimaryProcessorResult, NetworkError> {
-    //return_if_none!(header_obfuscator.on_packet_received(&mut packet));
     let packet = HdpPacket::new_recv(packet, remote_peer, local_primary_port);
-    log::trace!(target: "citadel", "RECV Raw packet: {:?}", &packet.parse().unwrap().0);
     let (header, _payload) = return_if_none!(packet.parse(), "Unable to parse packet");
+    log::trace!(target: "citadel", "RECV Raw packet: {:?}", &header);
+
+    let timestamp = header.timestamp.get();
+    if !inner_mut!(session.clock_skew_guard)
+        .check(timestamp, session.time_tracker.get_global_time_ns())
+    {
+        session.session_manager.discard_log().on_discard(|| {
+            format!("[Clock skew] Header timestamp {timestamp} is outside of the allowed window. Dropping")
+        });
+        return Ok(PrimaryProcessorResult::Void);
+    }
 
     let target_cid = header.target_cid.get();
     let mut endpoint_cid_info = None;
@@ -49,6 +58,13 @@ pub async fn process_raw_packet(
         ReceivePortType::OrderedReliable,
         packet,
     ) {
+        Some(_) if !inner_mut!(session.inbound_command_limiter).try_acquire(cmd_primary) => {
+            session.session_manager.discard_log().on_discard(|| {
+                format!("Inbound command {cmd_primary}:{cmd_aux} exceeded its rate limit. Dropping")
+            });
+            Ok(PrimaryProcessorResult::Void)
+        }
+
         Some(packet) => match cmd_primary {
             packet_flags::cmd::primary::DO_REGISTER => {
                 super::register_packet::process_register(session, packet, remote_peer).await
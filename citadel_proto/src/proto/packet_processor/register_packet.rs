@@ -35,6 +35,24 @@ pub async fn process_register(
         match header.cmd_aux {
             packet_flags::cmd::aux::do_register::STAGE0 => {
                 log::trace!(target: "citadel", "STAGE 0 REGISTER PACKET");
+                let limiter = session.session_manager.registration_limiter();
+                let allowed = limiter.try_acquire(remote_addr).await.unwrap_or_else(|err| {
+                    log::error!(target: "citadel", "Unable to check the registration rate limit: {:?}", err);
+                    false
+                });
+
+                if !allowed {
+                    let timestamp = session.time_tracker.get_global_time_ns();
+                    let err = packet_crafter::do_register::craft_failure(
+                        header.algorithm,
+                        session.protocol_version.get(),
+                        timestamp,
+                        "Too many registration attempts from this address. Please try again later",
+                        header.session_cid.get(),
+                    );
+                    return Ok(PrimaryProcessorResult::ReplyToSender(err));
+                }
+
                 let task = {
                     let mut state_container = inner_mut_state!(session.state_container);
                     // This node is Bob (receives a stage 0 packet from Alice). The payload should have Alice's public key
@@ -56,7 +74,7 @@ pub async fn process_register(
                                         .allow_passwordless
                                 {
                                     // passwordless is not allowed on this node
-                                    let err = packet_crafter::do_register::craft_failure(algorithm, timestamp, "Passwordless connections are not enabled on the target node", header.session_cid.get());
+                                    let err = packet_crafter::do_register::craft_failure(algorithm, session.protocol_version.get(), timestamp, "Passwordless connections are not enabled on the target node", header.session_cid.get());
                                     return Ok(PrimaryProcessorResult::ReplyToSender(err));
                                 }
 
@@ -81,6 +99,7 @@ pub async fn process_register(
 
                                     let stage1_packet = packet_crafter::do_register::craft_stage1(
                                         algorithm,
+                                        session.protocol_version.get(),
                                         timestamp,
                                         transfer,
                                         header.session_cid.get(),
@@ -159,6 +178,7 @@ pub async fn process_register(
                         let stage2_packet = packet_crafter::do_register::craft_stage2(
                             &new_hyper_ratchet,
                             algorithm,
+                            session.protocol_version.get(),
                             timestamp,
                             proposed_credentials,
                             security_level,
@@ -226,6 +246,7 @@ pub async fn process_register(
                                         let packet = packet_crafter::do_register::craft_success(
                                             &hyper_ratchet,
                                             algorithm,
+                                            session.protocol_version.get(),
                                             timestamp,
                                             success_message,
                                             security_level,
@@ -238,6 +259,7 @@ pub async fn process_register(
                                         log::error!(target: "citadel", "Server unsuccessfully created a CNAC during the DO_REGISTER process. Reason: {}", &err);
                                         let packet = packet_crafter::do_register::craft_failure(
                                             algorithm,
+                                            session.protocol_version.get(),
                                             timestamp,
                                             err,
                                             header.session_cid.get(),
@@ -312,7 +334,7 @@ pub async fn process_register(
                                 {
                                     Ok(new_cnac) => {
                                         if passwordless {
-                                            HdpSession::begin_connect(&session, &new_cnac)?;
+                                            HdpSession::begin_connect(&session, &new_cnac).await?;
                                             inner_mut_state!(session.state_container).cnac =
                                                 Some(new_cnac);
                                             // begin_connect will handle the connection process from here on out
@@ -363,12 +385,16 @@ pub async fn process_register(
             packet_flags::cmd::aux::do_register::FAILURE => {
                 log::trace!(target: "citadel", "STAGE FAILURE REGISTER PACKET");
                 // This node is again Bob. Alice received Bob's stage1 packet, but was unable to connect
-                // A failure can be sent at any stage greater than the zeroth
-                if inner_state!(session.state_container)
-                    .register_state
-                    .last_stage
-                    > packet_flags::cmd::aux::do_register::STAGE0
-                {
+                // A failure can be sent at any stage greater than the zeroth, or in place of the stage 1
+                // packet Alice awaits, such as when she is rate-limited
+                let may_fail = {
+                    let state_container = inner_state!(session.state_container);
+                    let register_state = &state_container.register_state;
+                    register_state.last_stage > packet_flags::cmd::aux::do_register::STAGE0
+                        || (!session.is_server && register_state.constructor.is_some())
+                };
+
+                if may_fail {
                     if let Some(error_message) =
                         validation::do_register::validate_failure(&header, &payload[..])
                     {
@@ -8,8 +8,9 @@ use crate::proto::packet_processor::primary_group_packet::{
     attempt_kem_as_alice_finish, attempt_kem_as_bob, get_proper_hyper_ratchet,
     get_resp_target_cid_from_header, ToolsetUpdate,
 };
+use crate::proto::state_subcontainers::rekey_container::ReKeyStage;
 use citadel_crypt::stacked_ratchet::constructor::{AliceToBobTransferType, ConstructorType};
-use citadel_crypt::stacked_ratchet::RatchetType;
+use citadel_crypt::stacked_ratchet::{Ratchet, RatchetType};
 use std::ops::Deref;
 use std::sync::atomic::Ordering;
 
@@ -64,9 +65,17 @@ pub fn process_rekey(
                         ),
                         "Unable to attempt KEM as Bob"
                     );
+                    if status.has_some() {
+                        // the new version is committed, but unusable until TRUNCATE arrives
+                        state_container
+                            .ratchet_update_state
+                            .on_stage(resp_target_cid, ReKeyStage::AwaitingTruncate);
+                    }
+
                     let packet = packet_crafter::do_drill_update::craft_stage1(
                         &hyper_ratchet,
                         status,
+                        session.protocol_version.get(),
                         timestamp,
                         resp_target_cid,
                         security_level,
@@ -128,10 +137,21 @@ pub fn process_rekey(
                         &latest_hr,
                         needs_truncate,
                         resp_target_cid,
+                        session.protocol_version.get(),
                         timestamp,
                         security_level,
                     );
 
+                    if needs_truncate.is_some() && !needs_early_kernel_alert {
+                        state_container
+                            .ratchet_update_state
+                            .on_stage(resp_target_cid, ReKeyStage::AwaitingTruncateAck);
+                    } else {
+                        state_container
+                            .ratchet_update_state
+                            .on_finished(resp_target_cid, latest_hr.version());
+                    }
+
                     if needs_truncate.is_none() || needs_early_kernel_alert {
                         // we only alert the user once truncate_ack received
                         state_container.ratchet_update_state.on_complete(
@@ -161,6 +181,13 @@ pub fn process_rekey(
                 "Invalid truncate"
             );
             let resp_target_cid = get_resp_target_cid_from_header(&header);
+            if state_container
+                .ratchet_update_state
+                .was_rolled_back(resp_target_cid)
+            {
+                log::error!(target: "citadel", "Received TRUNCATE for a stalled re-key with {} that was already rolled back. Dropping", resp_target_cid);
+                return Ok(PrimaryProcessorResult::Void);
+            }
 
             let (mut method, secrecy_mode) = if resp_target_cid != C2S_ENCRYPTION_ONLY {
                 let endpoint_container = return_if_none!(return_if_none!(state_container
@@ -204,7 +231,14 @@ pub fn process_rekey(
             // We update the internal latest version usable
             method.post_stage1_alice_or_bob();
 
-            let lock_set_by_alice = return_if_none!(method.unlock(false)).1;
+            let (latest_hr, lock_set_by_alice) = return_if_none!(method.unlock(false));
+            let version = match latest_hr {
+                RatchetType::Default(ratchet) => ratchet.version(),
+                RatchetType::Fcm(ratchet) => ratchet.version(),
+            };
+            state_container
+                .ratchet_update_state
+                .on_finished(resp_target_cid, version);
 
             // if lock set by bob, do poll
             let do_poll = lock_set_by_alice.map(|r| !r).unwrap_or(false);
@@ -216,6 +250,7 @@ pub fn process_rekey(
                     &hyper_ratchet,
                     truncate_vers,
                     resp_target_cid,
+                    session.protocol_version.get(),
                     timestamp,
                     security_level,
                 );
@@ -269,6 +304,9 @@ pub fn process_rekey(
             };
 
             let _ = return_if_none!(method.unlock(true)); // unconditional unlock
+            state_container
+                .ratchet_update_state
+                .on_finished(resp_target_cid, hyper_ratchet.version());
 
             // now, we can poll any packets
             //std::mem::drop(state_container);
@@ -17,6 +17,7 @@ pub(crate) struct ReliableOrderedCompatStream {
     local_bind_addr: SocketAddr,
     hr: StackedRatchet,
     security_level: SecurityLevel,
+    protocol_version: u32,
     target_cid: u64,
 }
 
@@ -32,6 +33,7 @@ impl ReliableOrderedCompatStream {
         security_level: SecurityLevel,
     ) -> Self {
         let (from_stream_tx, from_stream_rx) = tokio::sync::mpsc::unbounded_channel();
+        let protocol_version = state_container.protocol_version.get();
 
         // insert from_stream_tx into state container so that the protocol can deliver packets to the hole puncher
         // NOTE: The protocol must strip the header when passing packets to the from_stream function!
@@ -48,6 +50,7 @@ impl ReliableOrderedCompatStream {
             local_bind_addr,
             hr,
             security_level,
+            protocol_version,
             target_cid,
         }
     }
@@ -59,6 +62,7 @@ impl ReliableOrderedStreamToTarget for ReliableOrderedCompatStream {
         let packet = crate::proto::packet_crafter::hole_punch::generate_packet(
             &self.hr,
             input,
+            self.protocol_version,
             self.security_level,
             self.target_cid,
         );
@@ -33,6 +33,8 @@ pub struct MessageGroup {
 pub struct MessageGroupOptions {
     pub group_type: GroupType,
     pub id: u128,
+    /// An optional display name, returned when listing the groups of a client
+    pub name: Option<String>,
 }
 
 impl Default for MessageGroupOptions {
@@ -40,6 +42,7 @@ impl Default for MessageGroupOptions {
         Self {
             group_type: GroupType::Private,
             id: uuid::Uuid::new_v4().as_u128(),
+            name: None,
         }
     }
 }
@@ -7,6 +7,8 @@ pub mod group_channel;
 
 pub mod peer_crypt;
 
+pub mod peer_identity;
+
 pub mod message_group;
 
 pub mod p2p_conn_handler;
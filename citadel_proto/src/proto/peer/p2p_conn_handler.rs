@@ -189,9 +189,9 @@ fn handle_p2p_stream(
         kernel_tx,
         p2p_primary_stream_tx.clone(),
     );
-    let writer_future = HdpSession::outbound_stream(p2p_primary_stream_rx, sink);
+    let writer_future = HdpSession::outbound_stream(p2p_primary_stream_rx, sink, None, None);
     let reader_future =
-        HdpSession::execute_inbound_stream(stream, session.clone(), Some(p2p_handle));
+        HdpSession::execute_inbound_stream(stream, session.clone(), Some(p2p_handle), None);
     let stopper_future = p2p_stopper(stopper_rx);
 
     let direct_p2p_remote = DirectP2PRemote::new(stopper_tx, p2p_primary_stream_tx, from_listener);
@@ -1,5 +1,6 @@
 use crate::proto::misc::session_security_settings::SessionSecuritySettings;
 use crate::proto::node::TlsDomain;
+use crate::proto::peer::peer_identity::PeerIdentityProof;
 use crate::proto::peer::peer_layer::UdpMode;
 use citadel_wire::nat_identification::NatType;
 use serde::{Deserialize, Serialize};
@@ -8,13 +9,15 @@ use std::net::SocketAddr;
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum KeyExchangeProcess {
     // alice sends public key
-    Stage0(Vec<u8>, SessionSecuritySettings, UdpMode),
+    Stage0(Vec<u8>, PeerIdentityProof, SessionSecuritySettings, UdpMode),
     // Bob sends ciphertext, addr
-    Stage1(Vec<u8>, Option<PeerNatInfo>),
+    Stage1(Vec<u8>, PeerIdentityProof, Option<PeerNatInfo>),
     // Alice sends a sync time over. Server takes care of external addr
     Stage2(i64, Option<PeerNatInfo>),
     // The hole-punch failed
     HolePunchFailed,
+    // The identity proven by the sender was rejected
+    IdentityRejected,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -0,0 +1,256 @@
+use crate::error::{ConnectError, NetworkError};
+use citadel_crypt::entropy_bank::SecurityLevel;
+use citadel_crypt::stacked_ratchet::{Ratchet, StackedRatchet};
+use citadel_pqcrypto::identity::{IdentityKeyPair, FINGERPRINT_LENGTH};
+use citadel_user::backend::PersistenceHandler;
+use citadel_user::serialization::SyncIO;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+
+// the identity is private key material, so it is kept under an internal byte map key, and encrypted
+// under the account's static auxiliary ratchet while at rest
+const PEER_IDENTITY: &str = "_INTERNAL_PEER_IDENTITY";
+const KEYPAIR: &str = "keypair";
+
+/// The fingerprint of a peer's long-term identity key. Pinning the fingerprint of a peer ensures
+/// that a P2P channel is only established if the peer proves possession of the same identity
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct PeerFingerprint([u8; FINGERPRINT_LENGTH]);
+
+/// A long-term identity proven to peers when establishing a P2P channel. Since the identity signs
+/// the key exchange, a server relaying the exchange cannot substitute its own keys without the
+/// fingerprint changing
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PeerIdentity {
+    keys: IdentityKeyPair,
+}
+
+/// Sent alongside a peer key exchange transfer to prove the sender's identity
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PeerIdentityProof {
+    public_key: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+/// Determines the identity local accounts prove to peers, and the fingerprints expected of peers
+#[derive(Clone, Default)]
+pub struct PeerIdentitySettings {
+    /// If set, this identity is proven for every local account. Otherwise, each account uses an
+    /// identity generated on first use and persisted to the backend, encrypted under the
+    /// account's static auxiliary ratchet
+    pub identity: Option<PeerIdentity>,
+    /// Maps peer CIDs to the fingerprint their identity must match
+    pub pinned_fingerprints: HashMap<u64, PeerFingerprint>,
+}
+
+impl PeerFingerprint {
+    /// Returns the raw bytes of the fingerprint
+    pub fn as_bytes(&self) -> &[u8; FINGERPRINT_LENGTH] {
+        &self.0
+    }
+}
+
+impl From<[u8; FINGERPRINT_LENGTH]> for PeerFingerprint {
+    fn from(bytes: [u8; FINGERPRINT_LENGTH]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl Display for PeerFingerprint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Debug for PeerFingerprint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        <Self as Display>::fmt(self, f)
+    }
+}
+
+impl PeerIdentity {
+    /// Generates a new identity
+    pub fn generate() -> Result<Self, NetworkError> {
+        IdentityKeyPair::generate()
+            .map(|keys| Self { keys })
+            .map_err(|err| NetworkError::Generic(err.to_string()))
+    }
+
+    /// Returns the fingerprint peers may pin for this identity
+    pub fn fingerprint(&self) -> PeerFingerprint {
+        PeerFingerprint(self.keys.fingerprint())
+    }
+
+    /// Signs the key exchange `transfer`
+    pub(crate) fn prove(&self, transfer: &[u8]) -> Result<PeerIdentityProof, NetworkError> {
+        let signature = self
+            .keys
+            .sign(transfer)
+            .map_err(|err| NetworkError::Generic(err.to_string()))?;
+        Ok(PeerIdentityProof {
+            public_key: self.keys.public_key().to_vec(),
+            signature,
+        })
+    }
+}
+
+impl PeerIdentitySettings {
+    /// Returns the identity `cid` proves to its peers
+    pub(crate) async fn load_identity(
+        &self,
+        persistence_handler: &PersistenceHandler,
+        cid: u64,
+    ) -> Result<PeerIdentity, NetworkError> {
+        if let Some(identity) = self.identity.as_ref() {
+            return Ok(identity.clone());
+        }
+
+        let static_aux_ratchet = persistence_handler
+            .get_cnac_by_cid(cid)
+            .await?
+            .ok_or(NetworkError::InvalidRequest(
+                "Cannot load the peer identity of an unregistered account",
+            ))?
+            .get_static_auxiliary_hyper_ratchet();
+
+        loop {
+            if let Some(stored) = persistence_handler
+                .get_byte_map_value(cid, 0, PEER_IDENTITY, KEYPAIR)
+                .await?
+            {
+                return open(stored, &static_aux_ratchet);
+            }
+
+            // only store the identity if another task has not concurrently done so
+            let identity = PeerIdentity::generate()?;
+            if persistence_handler
+                .compare_and_swap_byte_map_value(
+                    cid,
+                    0,
+                    PEER_IDENTITY,
+                    KEYPAIR,
+                    None,
+                    seal(&identity, &static_aux_ratchet)?,
+                )
+                .await?
+            {
+                return Ok(identity);
+            }
+        }
+    }
+
+    /// Verifies that `proof` was produced over `transfer`, and that the identity matches the
+    /// fingerprint pinned for `peer_cid`, if any
+    pub(crate) fn verify(
+        &self,
+        peer_cid: u64,
+        proof: &PeerIdentityProof,
+        transfer: &[u8],
+    ) -> Result<PeerFingerprint, NetworkError> {
+        citadel_pqcrypto::identity::verify(&proof.public_key, transfer, &proof.signature)
+            .map_err(|_| NetworkError::InvalidPacket("Invalid peer identity proof"))?;
+        let fingerprint =
+            PeerFingerprint(citadel_pqcrypto::identity::fingerprint(&proof.public_key));
+
+        match self.pinned_fingerprints.get(&peer_cid) {
+            Some(pinned) if *pinned != fingerprint => {
+                Err(ConnectError::FingerprintMismatch(peer_cid).into())
+            }
+
+            _ => Ok(fingerprint),
+        }
+    }
+}
+
+fn seal(
+    identity: &PeerIdentity,
+    static_aux_ratchet: &StackedRatchet,
+) -> Result<Vec<u8>, NetworkError> {
+    static_aux_ratchet
+        .local_encrypt(identity.serialize_to_vector()?, SecurityLevel::Standard)
+        .map_err(|err| NetworkError::Generic(err.into_string()))
+}
+
+fn open(
+    stored: Vec<u8>,
+    static_aux_ratchet: &StackedRatchet,
+) -> Result<PeerIdentity, NetworkError> {
+    let decrypted = static_aux_ratchet
+        .local_decrypt(stored, SecurityLevel::Standard)
+        .map_err(|err| NetworkError::Generic(err.into_string()))?;
+    Ok(PeerIdentity::deserialize_from_vector(&decrypted)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ConnectError;
+    use crate::proto::peer::peer_identity::{
+        open, seal, PeerIdentity, PeerIdentitySettings, PEER_IDENTITY,
+    };
+    use citadel_crypt::stacked_ratchet::constructor::{
+        BobToAliceTransferType, StackedRatchetConstructor,
+    };
+    use citadel_crypt::stacked_ratchet::StackedRatchet;
+    use citadel_pqcrypto::algorithm_dictionary::CryptoParameters;
+    use citadel_pqcrypto::constructor_opts::ConstructorOpts;
+    use citadel_user::backend::is_internal_byte_map_key;
+    use citadel_user::serialization::SyncIO;
+
+    fn ratchet() -> StackedRatchet {
+        let opts = || ConstructorOpts::new_vec_init(None::<CryptoParameters>, 1);
+        let mut alice = StackedRatchetConstructor::new_alice(opts(), 10, 0, None).unwrap();
+        let bob = StackedRatchetConstructor::new_bob(10, 0, opts(), alice.stage0_alice().unwrap())
+            .unwrap();
+        alice
+            .stage1_alice(BobToAliceTransferType::Default(bob.stage0_bob().unwrap()))
+            .unwrap();
+        alice.finish().unwrap()
+    }
+
+    #[test]
+    fn identity_is_sealed_at_rest() {
+        let identity = PeerIdentity::generate().unwrap();
+        let aux_ratchet = ratchet();
+        let sealed = seal(&identity, &aux_ratchet).unwrap();
+        assert_ne!(sealed, identity.serialize_to_vector().unwrap());
+        assert_eq!(
+            open(sealed.clone(), &aux_ratchet).unwrap().fingerprint(),
+            identity.fingerprint()
+        );
+        // another account cannot recover the identity
+        assert!(open(sealed, &ratchet()).is_err());
+        // nor is it listed or exported alongside the account's own byte map values
+        assert!(is_internal_byte_map_key(PEER_IDENTITY));
+    }
+
+    #[test]
+    fn verifies_pinned_fingerprints() {
+        let identity = PeerIdentity::generate().unwrap();
+        let other = PeerIdentity::generate().unwrap();
+        let proof = identity.prove(b"transfer").unwrap();
+
+        let mut settings = PeerIdentitySettings::default();
+        assert_eq!(
+            settings.verify(10, &proof, b"transfer").unwrap(),
+            identity.fingerprint()
+        );
+        assert!(settings.verify(10, &proof, b"tampered").is_err());
+
+        let _ = settings
+            .pinned_fingerprints
+            .insert(10, identity.fingerprint());
+        assert!(settings.verify(10, &proof, b"transfer").is_ok());
+
+        let _ = settings.pinned_fingerprints.insert(10, other.fingerprint());
+        let err = settings.verify(10, &proof, b"transfer").unwrap_err();
+        assert_eq!(
+            err.into_string(),
+            ConnectError::FingerprintMismatch(10).to_string()
+        );
+    }
+}
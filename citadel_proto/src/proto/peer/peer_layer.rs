@@ -144,13 +144,21 @@ impl HyperNodePeerLayer {
     /// Cleans up the internal entries
     #[allow(unused_results)]
     pub async fn on_session_shutdown(&self, implicated_cid: u64) -> Result<(), NetworkError> {
-        let pers = {
+        let (pers, message_groups) = {
             let mut this = self.inner.write().await;
-            this.message_groups.remove(&implicated_cid);
+            let message_groups = this.message_groups.remove(&implicated_cid);
             this.inner.write().observed_postings.remove(&implicated_cid);
-            this.persistence_handler.clone()
+            (this.persistence_handler.clone(), message_groups)
         };
 
+        // the groups owned by this session do not outlive it
+        for mgid in message_groups
+            .into_iter()
+            .flat_map(|groups| groups.into_keys())
+        {
+            Self::remove_stored_group(&pers, MessageGroupKey::new(implicated_cid, mgid)).await;
+        }
+
         let _ = pers
             .remove_byte_map_values_by_key(implicated_cid, 0, MAILBOX)
             .await?;
@@ -166,8 +174,10 @@ impl HyperNodePeerLayer {
         options: MessageGroupOptions,
     ) -> Option<MessageGroupKey> {
         let mut this = self.inner.write().await;
+        let pers = this.persistence_handler.clone();
         let map = this.message_groups.get_mut(&implicated_cid)?;
         let mgid = options.id;
+        let name = options.name.clone();
         if map.len() <= u8::MAX as usize {
             if let std::collections::hash_map::Entry::Vacant(e) = map.entry(mgid) {
                 let mut message_group = MessageGroup {
@@ -192,6 +202,13 @@ impl HyperNodePeerLayer {
                 );
 
                 e.insert(message_group);
+                std::mem::drop(this);
+
+                // the initial peers are only pending, so the owner is the sole member for now
+                if let Err(err) = pers.store_group(implicated_cid, mgid, name, &[]).await {
+                    log::warn!(target: "citadel", "Unable to store message group {}: {}", mgid, err.into_string());
+                }
+
                 Some(MessageGroupKey {
                     cid: implicated_cid,
                     mgid,
@@ -207,9 +224,30 @@ impl HyperNodePeerLayer {
 
     /// removes a [MessageGroup]
     pub async fn remove_message_group(&self, key: MessageGroupKey) -> Option<MessageGroup> {
-        let mut this = self.inner.write().await;
-        let map = this.message_groups.get_mut(&key.cid)?;
-        map.remove(&key.mgid)
+        let (group, pers) = {
+            let mut this = self.inner.write().await;
+            let group = this.message_groups.get_mut(&key.cid)?.remove(&key.mgid)?;
+            (group, this.persistence_handler.clone())
+        };
+
+        Self::remove_stored_group(&pers, key).await;
+        Some(group)
+    }
+
+    async fn remove_stored_group(pers: &PersistenceHandler, key: MessageGroupKey) {
+        if let Err(err) = pers.remove_group(key.cid, key.mgid).await {
+            log::warn!(target: "citadel", "Unable to remove stored message group {}: {}", key, err.into_string());
+        }
+    }
+
+    async fn add_stored_group_member(
+        pers: &PersistenceHandler,
+        key: MessageGroupKey,
+        peer_cid: u64,
+    ) {
+        if let Err(err) = pers.add_group_members(key.cid, key.mgid, &[peer_cid]).await {
+            log::warn!(target: "citadel", "Unable to add {} to stored message group {}: {}", peer_cid, key, err.into_string());
+        }
     }
 
     #[allow(unused_results)]
@@ -231,10 +269,13 @@ impl HyperNodePeerLayer {
     // Upgrades a peer from pending to concurrent (enabled reception of broadcasts)
     pub async fn upgrade_peer_in_group(&self, key: MessageGroupKey, peer_cid: u64) -> bool {
         let mut this = self.inner.write().await;
+        let pers = this.persistence_handler.clone();
         if let Some(map) = this.message_groups.get_mut(&key.cid) {
             if let Some(entry) = map.get_mut(&key.mgid) {
                 if let Some(peer) = entry.pending_peers.remove(&peer_cid) {
                     entry.concurrent_peers.insert(peer_cid, peer);
+                    std::mem::drop(this);
+                    Self::add_stored_group_member(&pers, key, peer_cid).await;
                     return true;
                 }
             }
@@ -277,6 +318,7 @@ impl HyperNodePeerLayer {
         mut peers: Vec<u64>,
     ) -> Result<(Vec<u64>, Vec<u64>), ()> {
         let mut this = self.inner.write().await;
+        let pers = this.persistence_handler.clone();
         let map = this.message_groups.get_mut(&key.cid).ok_or(())?;
         let message_group = map.get_mut(&key.mgid).ok_or(())?;
         //let mut peers_removed = Vec::new();
@@ -290,6 +332,14 @@ impl HyperNodePeerLayer {
             .cloned()
             .collect::<Vec<u64>>();
         let peers_successfully_removed = peers;
+        std::mem::drop(this);
+
+        if let Err(err) = pers
+            .remove_group_members(key.cid, key.mgid, &peers_successfully_removed)
+            .await
+        {
+            log::warn!(target: "citadel", "Unable to remove peers from stored message group {}: {}", key, err.into_string());
+        }
 
         Ok((peers_successfully_removed, peers_remaining))
     }
@@ -312,11 +362,14 @@ impl HyperNodePeerLayer {
     /// returns None if the key does not match an active group
     pub async fn request_join(&self, peer_cid: u64, key: MessageGroupKey) -> Option<bool> {
         let mut write = self.inner.write().await;
+        let pers = write.persistence_handler.clone();
         let group = write.message_groups.get_mut(&key.cid)?.get_mut(&key.mgid)?;
         if group.options.group_type == GroupType::Public {
             let _ = group
                 .concurrent_peers
                 .insert(peer_cid, MessageGroupPeer { peer_cid });
+            std::mem::drop(write);
+            Self::add_stored_group_member(&pers, key, peer_cid).await;
             Some(true)
         } else {
             Some(false)
@@ -560,6 +613,15 @@ pub enum PeerSignal {
     SignalReceived(Ticket),
     // for key-exchange
     Kem(PeerConnectionType, KeyExchangeProcess),
+    // sent by the server to mutual peers when the presence of a cid changes (peer cid, presence)
+    PresenceChanged(u64, Presence),
+}
+
+/// Whether a peer currently has a session with the server
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Presence {
+    Online,
+    Offline,
 }
 
 #[derive(Debug, Serialize, Deserialize, Copy, Clone, Eq, PartialEq)]
@@ -1,8 +1,20 @@
-use crate::error::NetworkError;
+use crate::error::{NetworkError, TransferError};
 use crate::kernel::kernel_communicator::{KernelAsyncCallbackHandler, KernelStreamSubscription};
-use crate::prelude::{NodeRequest, NodeResult};
+use crate::kernel::presence_tracker::PresenceSubscription;
+use crate::kernel::transfer_tracker::TransferResult;
+use crate::prelude::{NodeRequest, NodeResult, VirtualTargetType};
+use crate::proto::misc::effective_config::EffectiveConfig;
+use crate::proto::misc::pending_handshakes::HandshakeMetrics;
+use crate::proto::misc::session_security_settings::SupportedAlgorithms;
+use crate::proto::misc::session_state_dump::SessionStateDump;
 use crate::proto::node::HdpServerRemoteInner;
+use crate::proto::node_request::{GetActiveDrillVersions, GetPendingReKeyStatus, GetSessionState};
+use crate::proto::node_result::{
+    ActiveDrillVersions, HandshakeMetricsResult, InternalServerError, PendingReKeyStatusResult,
+    SessionStateDumpResult,
+};
 use crate::proto::outbound_sender::BoundedSender;
+use crate::proto::state_subcontainers::rekey_container::PendingReKeyStatus;
 use citadel_user::account_manager::AccountManager;
 use citadel_wire::hypernode_type::NodeType;
 use futures::channel::mpsc::TrySendError;
@@ -92,6 +104,7 @@ impl NodeRemote {
         callback_handler: KernelAsyncCallbackHandler,
         account_manager: AccountManager,
         node_type: NodeType,
+        effective_config: EffectiveConfig,
     ) -> Self {
         // starts at 1. Ticket 0 is for reserved
         Self {
@@ -100,6 +113,7 @@ impl NodeRemote {
                 callback_handler,
                 account_manager,
                 node_type,
+                effective_config,
             }),
         }
     }
@@ -110,7 +124,21 @@ impl NodeRemote {
         ticket: Ticket,
         request: NodeRequest,
     ) -> Result<(), NetworkError> {
-        self.outbound_send_request_tx.send((request, ticket)).await
+        // file transfers may be awaited as soon as their ticket is returned
+        let is_transfer = matches!(
+            request,
+            NodeRequest::SendObject(..) | NodeRequest::PullObject(..)
+        );
+        if is_transfer {
+            self.inner.callback_handler.transfers.register(ticket);
+        }
+
+        let result = self.outbound_send_request_tx.send((request, ticket)).await;
+        if result.is_err() && is_transfer {
+            self.inner.callback_handler.transfers.unregister(ticket);
+        }
+
+        result
     }
 
     /// Sends a request to the HDP server. This should always be used to communicate with the server
@@ -188,6 +216,110 @@ impl NodeRemote {
             .map_err(|_| NetworkError::Timeout(0))?
     }
 
+    /// Resolves once the file transfer initiated by the request with `ticket` completes or fails.
+    /// May be called after the transfer has finished, in which case the recorded outcome is returned.
+    /// Returns [`TransferError::UnknownTicket`] if no transfer was initiated with `ticket`
+    pub async fn await_transfer(&self, ticket: Ticket) -> Result<TransferResult, TransferError> {
+        self.inner.callback_handler.transfers.wait(ticket).await
+    }
+
+    /// Returns a single stream of `(peer_cid, presence)` updates for every mutual peer in `peers`,
+    /// as announced by the server whenever one of them connects or disconnects. The watched peers
+    /// may be adjusted through [`PresenceSubscription::add`] and [`PresenceSubscription::remove`]
+    pub fn watch_presence(&self, peers: &[u64]) -> PresenceSubscription {
+        self.inner.callback_handler.presence.subscribe(peers)
+    }
+
+    /// Returns every drill version retained for `v_conn_type`, from oldest to newest. Older
+    /// versions are kept so that in-flight packets remain decryptable after a re-key. Once the
+    /// toolset exceeds its capacity, the oldest version is dropped by the TRUNCATE/TRUNCATE_ACK
+    /// exchange that concludes the re-key, after which it is no longer reported here
+    pub async fn active_drill_versions(
+        &mut self,
+        v_conn_type: VirtualTargetType,
+    ) -> Result<Vec<u32>, NetworkError> {
+        let request = NodeRequest::GetActiveDrillVersions(GetActiveDrillVersions { v_conn_type });
+        match self.send_callback(request).await? {
+            NodeResult::ActiveDrillVersions(ActiveDrillVersions { versions, .. }) => Ok(versions),
+            NodeResult::InternalServerError(InternalServerError { message, .. }) => {
+                Err(NetworkError::Generic(message))
+            }
+            res => Err(NetworkError::Generic(format!(
+                "Unexpected response when querying drill versions: {res:?}"
+            ))),
+        }
+    }
+
+    /// Returns the state of the re-keying process with `peer_cid`. For the channel between the
+    /// client and server, `peer_cid` is the session CID. A re-key that waits too long on the
+    /// adjacent node is cancelled and rolled back, after which it is reported as
+    /// [`PendingReKeyStatus::TimedOut`] while the channel continues on the prior version
+    pub async fn pending_rekey_status(
+        &mut self,
+        peer_cid: u64,
+    ) -> Result<PendingReKeyStatus, NetworkError> {
+        let request = NodeRequest::GetPendingReKeyStatus(GetPendingReKeyStatus { peer_cid });
+        match self.send_callback(request).await? {
+            NodeResult::PendingReKeyStatus(PendingReKeyStatusResult { status, .. }) => Ok(status),
+            NodeResult::InternalServerError(InternalServerError { message, .. }) => {
+                Err(NetworkError::Generic(message))
+            }
+            res => Err(NetworkError::Generic(format!(
+                "Unexpected response when querying re-key status: {res:?}"
+            ))),
+        }
+    }
+
+    /// Returns a snapshot of the state of the session for `session_cid`: the negotiated
+    /// parameters, drill versions, queue depths, in-flight transfers and keep alive statistics.
+    /// No key material is included, so the snapshot is safe to log or attach to bug reports.
+    /// On a client, `session_cid` is the local CID; on a server, the CID of the connected client
+    pub async fn dump_session_state(
+        &mut self,
+        session_cid: u64,
+    ) -> Result<SessionStateDump, NetworkError> {
+        let request = NodeRequest::GetSessionState(GetSessionState { session_cid });
+        match self.send_callback(request).await? {
+            NodeResult::SessionStateDump(SessionStateDumpResult { dump, .. }) => Ok(dump),
+            NodeResult::InternalServerError(InternalServerError { message, .. }) => {
+                Err(NetworkError::Generic(message))
+            }
+            res => Err(NetworkError::Generic(format!(
+                "Unexpected response when dumping session state: {res:?}"
+            ))),
+        }
+    }
+
+    /// Returns the counters describing the handshakes this node has not yet completed. A server
+    /// caps the number of pending handshakes through [`ServerMiscSettings`], rejecting or evicting
+    /// handshakes past the cap and reaping those that do not complete in time
+    ///
+    /// [`ServerMiscSettings`]: citadel_user::server_misc_settings::ServerMiscSettings
+    pub async fn handshake_metrics(&mut self) -> Result<HandshakeMetrics, NetworkError> {
+        match self.send_callback(NodeRequest::GetHandshakeMetrics).await? {
+            NodeResult::HandshakeMetrics(HandshakeMetricsResult { metrics, .. }) => Ok(metrics),
+            NodeResult::InternalServerError(InternalServerError { message, .. }) => {
+                Err(NetworkError::Generic(message))
+            }
+            res => Err(NetworkError::Generic(format!(
+                "Unexpected response when querying handshake metrics: {res:?}"
+            ))),
+        }
+    }
+
+    /// Returns the encryption algorithms, key encapsulation mechanisms, signature algorithms,
+    /// ratchet variants and compression codecs this node supports. Every combination listed in
+    /// [`SupportedAlgorithms::crypto_params`] is accepted during negotiation
+    pub fn supported_algorithms(&self) -> SupportedAlgorithms {
+        SupportedAlgorithms::default()
+    }
+
+    /// Returns the settings this node runs with, after every default left unset in the builder
+    /// was resolved. Secrets are redacted, so the snapshot is safe to log or attach to bug reports
+    pub fn effective_config(&self) -> EffectiveConfig {
+        self.inner.effective_config.clone()
+    }
+
     /// Safely shutsdown the internal server
     pub async fn shutdown(&mut self) -> Result<(), NetworkError> {
         let _ = self.send(NodeRequest::Shutdown).await?;
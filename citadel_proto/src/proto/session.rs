@@ -5,12 +5,12 @@ use std::sync::Arc;
 //use async_std::prelude::*;
 use bytes::{Bytes, BytesMut};
 use futures::{SinkExt, StreamExt, TryFutureExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
 use tokio::time::Instant;
-use tokio_util::codec::LengthDelimitedCodec;
 
 use citadel_crypt::entropy_bank::SecurityLevel;
 use citadel_crypt::stacked_ratchet::constructor::StackedRatchetConstructor;
-use citadel_crypt::stacked_ratchet::Ratchet;
+use citadel_crypt::stacked_ratchet::{Ratchet, StackedRatchet};
 use citadel_user::account_manager::AccountManager;
 use citadel_user::auth::proposed_credentials::ProposedCredentials;
 use citadel_user::client_account::ClientNetworkAccount;
@@ -21,20 +21,31 @@ use netbeam::time_tracker::TimeTracker;
 
 use crate::constants::{
     DRILL_UPDATE_FREQUENCY_LOW_BASE, FIREWALL_KEEP_ALIVE_UDP, GROUP_EXPIRE_TIME_MS,
-    HDP_HEADER_BYTE_LEN, INITIAL_RECONNECT_LOCKOUT_TIME_NS, KEEP_ALIVE_INTERVAL_MS,
-    KEEP_ALIVE_TIMEOUT_NS, LOGIN_EXPIRATION_TIME,
+    HDP_HEADER_BYTE_LEN, INITIAL_RECONNECT_LOCKOUT_TIME_NS, KEEP_ALIVE_TIMEOUT_NS,
+    LOGIN_EXPIRATION_TIME, REKEY_STALL_CHECK_INTERVAL, REKEY_STALL_TIMEOUT,
 };
 use crate::error::NetworkError;
-use crate::proto::packet::{packet_flags, HdpPacket};
+use crate::proto::packet::{
+    append_header_checksum, packet_flags, verify_header_checksum, HdpPacket, HeaderObfuscator,
+    HEADER_PROTECTION_KEY_CONTEXT,
+};
 use crate::proto::packet_crafter::peer_cmd::C2S_ENCRYPTION_ONLY;
+use crate::proto::packet_crafter::pre_connect::ResumptionAttempt;
 use crate::proto::packet_crafter::{self, GroupTransmitter, RatchetPacketCrafterContainer};
-use citadel_user::backend::utils::VirtualObjectMetadata;
+use citadel_user::backend::utils::{ObjectTransferStatsTracker, VirtualObjectMetadata};
 //use futures_codec::Framed;
 use crate::proto::misc;
 use crate::proto::misc::clean_shutdown::{CleanShutdownSink, CleanShutdownStream};
+use crate::proto::misc::clock_skew::ClockSkewGuard;
+use crate::proto::misc::command_rate_limit::InboundCommandLimiter;
 use crate::proto::misc::dual_rwlock::DualRwLock;
+use crate::proto::misc::fault_injection::FaultInjector;
+use crate::proto::misc::handshake_limits::{HandshakeGuard, PrimaryStreamCodec};
 use crate::proto::misc::net::GenericNetworkStream;
-use crate::proto::misc::session_security_settings::SessionSecuritySettings;
+use crate::proto::misc::session_security_settings::{HeaderProtection, SessionSecuritySettings};
+use crate::proto::misc::session_state_dump::{
+    CryptoStateDump, SessionStateDump, VirtualConnectionStateDump,
+};
 use crate::proto::node::ConnectMode;
 use crate::proto::packet_processor::includes::{Duration, SocketAddr};
 use crate::proto::packet_processor::{self, PrimaryProcessorResult};
@@ -46,6 +57,8 @@ use crate::prelude::{GroupBroadcast, SecureProtocolPacket};
 use crate::proto::endpoint_crypto_accessor::EndpointCryptoAccessor;
 use crate::proto::misc::dual_cell::DualCell;
 use crate::proto::misc::dual_late_init::DualLateInit;
+use crate::proto::misc::keep_alive_policy::KeepAlivePolicy;
+use crate::proto::misc::protocol_version::ProtocolVersionRange;
 use crate::proto::misc::udp_internal_interface::{UdpSplittableTypes, UdpStream};
 use crate::proto::outbound_sender::{
     channel, unbounded, SendError, UnboundedReceiver, UnboundedSender,
@@ -55,11 +68,12 @@ use crate::proto::outbound_sender::{
 };
 use crate::proto::packet_processor::raw_primary_packet::{check_proxy, ReceivePortType};
 use crate::proto::peer::p2p_conn_handler::P2PInboundHandle;
+use crate::proto::peer::peer_identity::PeerIdentitySettings;
 use crate::proto::peer::peer_layer::{HyperNodePeerLayer, PeerSignal, UdpMode};
 use crate::proto::session_queue_handler::{
     QueueWorkerResult, QueueWorkerTicket, SessionQueueWorker, SessionQueueWorkerHandle,
     DRILL_REKEY_WORKER, FIREWALL_KEEP_ALIVE, KEEP_ALIVE_CHECKER, PROVISIONAL_CHECKER,
-    RESERVED_CID_IDX,
+    RESERVED_CID_IDX, STALLED_REKEY_CHECKER,
 };
 use crate::proto::state_container::{
     FileKey, GroupKey, OutboundFileTransfer, OutboundTransmitterContainer, StateContainer,
@@ -109,6 +123,19 @@ impl HdpSession {
         }
     }
 
+    /// Returns true if both handles refer to the same session
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        #[cfg(not(feature = "multi-threaded"))]
+        {
+            std::rc::Rc::ptr_eq(&self.inner, &other.inner)
+        }
+
+        #[cfg(feature = "multi-threaded")]
+        {
+            std::sync::Arc::ptr_eq(&self.inner, &other.inner)
+        }
+    }
+
     #[cfg(not(feature = "multi-threaded"))]
     pub fn as_weak(&self) -> std::rc::Weak<HdpSessionInner> {
         std::rc::Rc::downgrade(&self.inner)
@@ -197,11 +224,24 @@ pub struct HdpSessionInner {
     pub(super) client_config: Arc<rustls::ClientConfig>,
     pub(super) hypernode_peer_layer: HyperNodePeerLayer,
     pub(super) stun_servers: Option<Vec<String>>,
+    pub(super) header_obfuscation: bool,
+    /// Set once the primary stream starts, if header obfuscation is enabled
+    pub(super) header_obfuscator: DualLateInit<Option<HeaderObfuscator>>,
+    pub(super) peer_identity_settings: Arc<PeerIdentitySettings>,
+    pub(super) supported_protocol_versions: ProtocolVersionRange,
+    pub(super) keep_alive_policy: KeepAlivePolicy,
+    /// The protocol version stamped into each outbound header. Until the pre-connect stage selects
+    /// a version, this is the highest supported version
+    pub(super) protocol_version: DualCell<u32>,
+    pub(super) inbound_command_limiter: DualRwLock<InboundCommandLimiter>,
+    pub(super) clock_skew_guard: DualRwLock<ClockSkewGuard>,
+    /// The number of inbound packets discarded because their header checksum did not match
+    pub(super) corrupt_headers_discarded: DualCell<u64>,
     on_drop: UnboundedSender<()>,
 }
 
 /// allows each session worker to check the state of the session
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum SessionState {
     /// In impersonal mode, the primary socket may receive a new stream. This category implies that
     /// the next packet should be a welcome packet with information implying if it is already registered
@@ -220,6 +260,17 @@ pub enum SessionState {
     Disconnected,
 }
 
+impl SessionState {
+    /// Returns true if the session has yet to complete its handshake
+    pub(crate) fn is_provisional(self) -> bool {
+        // SocketJustOpened is only the state for a session created from an incoming connection
+        self == SessionState::SocketJustOpened
+            || self == SessionState::NeedsConnect
+            || self == SessionState::ConnectionProcess
+            || self == SessionState::NeedsRegister
+    }
+}
+
 #[derive(Debug, Clone)]
 #[allow(variant_size_differences)]
 pub enum HdpSessionInitMode {
@@ -244,6 +295,10 @@ pub(crate) struct SessionInitParams {
     // this is set only when a local client is attempting to start an outbound session
     pub client_only_settings: Option<ClientOnlySessionInitSettings>,
     pub stun_servers: Option<Vec<String>>,
+    pub header_obfuscation: bool,
+    pub peer_identity_settings: Arc<PeerIdentitySettings>,
+    pub supported_protocol_versions: ProtocolVersionRange,
+    pub keep_alive_policy: KeepAlivePolicy,
 }
 
 pub(crate) struct ClientOnlySessionInitSettings {
@@ -332,11 +387,26 @@ impl HdpSession {
             .unwrap_or(UdpMode::Disabled);
         let account_manager = session_init_params.account_manager;
         let client_config = session_init_params.client_config;
+        let keep_alive_policy = session_init_params.keep_alive_policy;
+        // a client advertises its timeout during the pre-connect stage, so it must span the
+        // longest interval the client may back off to
         let keep_alive_timeout_ns = client_only_settings
             .as_ref()
-            .map(|r| r.keep_alive_timeout_ns)
+            .map(|r| keep_alive_policy.tolerate_timeout_ns(r.keep_alive_timeout_ns))
             .unwrap_or(KEEP_ALIVE_TIMEOUT_NS);
         let stun_servers = session_init_params.stun_servers;
+        let header_obfuscation = session_init_params.header_obfuscation;
+        let peer_identity_settings = session_init_params.peer_identity_settings;
+        let supported_protocol_versions = session_init_params.supported_protocol_versions;
+        let protocol_version = DualCell::new(supported_protocol_versions.max().to_u32());
+        let inbound_command_limiter =
+            InboundCommandLimiter::new(&account_manager.get_misc_settings().inbound_command_limits)
+                .into();
+        let clock_skew_guard = ClockSkewGuard::new(
+            account_manager.get_misc_settings().max_clock_skew,
+            account_manager.get_misc_settings().normalize_clock_offset,
+        )
+        .into();
 
         let mut inner = HdpSessionInner {
             hypernode_peer_layer,
@@ -368,6 +438,8 @@ impl HdpSession {
                 is_server,
                 TransferStats::new(timestamp, 0),
                 udp_mode,
+                protocol_version.clone(),
+                keep_alive_policy,
             ),
             to_primary_stream: DualLateInit::default(),
             state,
@@ -375,8 +447,17 @@ impl HdpSession {
             is_server,
             stopper_tx: stopper_tx.clone().into(),
             queue_handle: DualLateInit::default(),
+            header_obfuscator: DualLateInit::default(),
             client_config,
             stun_servers,
+            header_obfuscation,
+            peer_identity_settings,
+            supported_protocol_versions,
+            keep_alive_policy,
+            protocol_version,
+            inbound_command_limiter,
+            clock_skew_guard,
+            corrupt_headers_discarded: DualCell::new(0),
         };
 
         if let Some(proposed_credentials) = session_init_params
@@ -412,22 +493,43 @@ impl HdpSession {
 
         let (session_future, handle_zero_state, implicated_cid) = {
             let quic_conn_opt = primary_stream.take_quic_connection();
-            let (writer, reader) = misc::net::safe_split_stream(primary_stream);
-
             let (primary_outbound_tx, primary_outbound_rx) = unbounded();
             let primary_outbound_tx = OutboundPrimaryStreamSender::from(primary_outbound_tx);
-            let primary_outbound_rx = OutboundPrimaryStreamReceiver::from(primary_outbound_rx);
+            let mut primary_outbound_rx = OutboundPrimaryStreamReceiver::from(primary_outbound_rx);
+            let fault_injector = this.session_manager.fault_injector();
+            if let Some(fault_injector) = fault_injector.as_ref() {
+                primary_outbound_rx = fault_injector.degrade(primary_outbound_rx);
+            }
 
             // if the primary stream uses QUIC, load this inside for both client and server
             if let Some(quic_conn) = quic_conn_opt {
                 *inner_mut!(this.primary_stream_quic_conn) = Some(quic_conn);
             }
 
-            //let (obfuscator, packet_opt) = HeaderObfuscator::new(this.is_server);
-            //let sess_id = this_ref.kernel_ticket;
+            // the client sends the obfuscator's init packet first, from which the server latches the key
+            let (header_obfuscator, zero_packet) = if this.header_obfuscation {
+                let (obfuscator, packet_opt) = HeaderObfuscator::new(this.is_server);
+                let obfuscator = obfuscator.with_discard_log(this.session_manager.discard_log());
+                (Some(obfuscator), packet_opt)
+            } else {
+                (None, None)
+            };
+
+            // only inbound connections are untrusted while mid-handshake
+            let handshake_guard = this.is_server.then(|| {
+                HandshakeGuard::new(
+                    this.account_manager
+                        .get_misc_settings()
+                        .handshake_payload_limits,
+                    this.state.clone(),
+                    header_obfuscator.clone(),
+                )
+            });
+            let (writer, reader) = misc::net::guarded_split_stream(primary_stream, handshake_guard);
 
             this.to_primary_stream
                 .set_once(Some(primary_outbound_tx.clone()));
+            this.header_obfuscator.set_once(header_obfuscator.clone());
 
             let timestamp = this.time_tracker.get_global_time_ns();
             let cnac_opt = inner_state!(this.state_container).cnac.clone();
@@ -437,13 +539,19 @@ impl HdpSession {
             let stopper = inner!(this.stopper_tx).subscribe();
 
             // Ensure the tx forwards to the writer
-            let writer_future = Self::outbound_stream(primary_outbound_rx, writer);
-            let reader_future = Self::execute_inbound_stream(reader, this_inbound, None);
+            let writer_future = Self::outbound_stream(
+                primary_outbound_rx,
+                writer,
+                header_obfuscator.clone(),
+                fault_injector,
+            );
+            let reader_future =
+                Self::execute_inbound_stream(reader, this_inbound, None, header_obfuscator);
             //let timer_future = Self::execute_timer(this.clone());
             let queue_worker_future = Self::execute_queue_worker(this_queue_worker);
             let stopper_future = Self::stopper(stopper);
             let handle_zero_state = Self::handle_zero_state(
-                None,
+                zero_packet,
                 persistence_handler,
                 primary_outbound_tx,
                 this_outbound,
@@ -586,6 +694,7 @@ impl HdpSession {
                 let stage0_register_packet =
                     crate::proto::packet_crafter::do_register::craft_stage0(
                         session_security_settings.crypto_params.into(),
+                        session_ref.protocol_version.get(),
                         timestamp,
                         transfer,
                         passwordless,
@@ -600,7 +709,7 @@ impl HdpSession {
             }
 
             SessionState::NeedsConnect => {
-                Self::begin_connect(&session, cnac.as_ref().unwrap())?;
+                Self::begin_connect(&session, cnac.as_ref().unwrap()).await?;
             }
 
             // This implies this node received a new incoming connection. It is up to the other node, Alice, to send a stage 0 packet
@@ -623,7 +732,7 @@ impl HdpSession {
         Ok(())
     }
 
-    pub(crate) fn begin_connect(
+    pub(crate) async fn begin_connect(
         session: &HdpSession,
         cnac: &ClientNetworkAccount,
     ) -> Result<(), NetworkError> {
@@ -631,9 +740,21 @@ impl HdpSession {
         let session_ref = session;
         let connect_mode = (*inner!(session.connect_mode))
             .ok_or(NetworkError::InternalError("Connect mode not loaded"))?;
+
+        // Resumption skips the NAT traversal the UDP channel depends on, so it is never attempted when UDP is
+        // enabled. The ticket is kept for a later connect without UDP
+        let udp_mode = inner_state!(session_ref.state_container).udp_mode;
+        let resumption_ticket = if udp_mode == UdpMode::Disabled {
+            session_ref
+                .session_manager
+                .take_resumption_ticket(cnac.get_cid(), &cnac.get_static_auxiliary_hyper_ratchet())
+                .await?
+        } else {
+            None
+        };
+
         let mut state_container = inner_mut_state!(session_ref.state_container);
 
-        let udp_mode = state_container.udp_mode;
         let timestamp = session_ref.time_tracker.get_global_time_ns();
         let session_security_settings = state_container.session_security_settings.unwrap();
         let peer_only_connect_mode = session_ref.peer_only_connect_protocol.get().unwrap();
@@ -669,18 +790,40 @@ impl HdpSession {
             state_container.pre_connect_state.udp_channel_oneshot_tx = UdpChannelSender::default();
         }
 
+        // If a ticket from a previous session is held, present it alongside the transfer. The server skips
+        // the key agreement if the ticket is still valid, or otherwise continues with the transfer as usual
+        let mut resumption = None;
+        if let Some(ticket) = resumption_ticket {
+            let latest_ratchet = cnac
+                .read()
+                .crypt_container
+                .toolset
+                .get_most_recent_hyper_ratchet()
+                .cloned();
+            if let Some(latest_ratchet) = latest_ratchet {
+                resumption = Some(ResumptionAttempt {
+                    ticket,
+                    ratchet_version: latest_ratchet.version(),
+                });
+                state_container.pre_connect_state.resumption_ratchet = Some(latest_ratchet);
+            }
+        }
+
         // NEXT STEP: check preconnect, and update internal security-level recv side to the security level found in transfer to ensure all future packages are at that security-level
         let syn = packet_crafter::pre_connect::craft_syn(
             static_aux_hr,
             transfer,
             nat_type,
             udp_mode,
+            session_ref.supported_protocol_versions,
+            session_ref.protocol_version.get(),
             timestamp,
             state_container.keep_alive_timeout_ns,
             max_usable_level,
             session_security_settings,
             peer_only_connect_mode,
             connect_mode,
+            resumption,
         );
 
         state_container.pre_connect_state.last_stage =
@@ -861,20 +1004,39 @@ impl HdpSession {
     )]
     pub async fn outbound_stream(
         primary_outbound_rx: OutboundPrimaryStreamReceiver,
-        writer: CleanShutdownSink<GenericNetworkStream, LengthDelimitedCodec, Bytes>,
+        writer: CleanShutdownSink<GenericNetworkStream, PrimaryStreamCodec, Bytes>,
+        header_obfuscator: Option<HeaderObfuscator>,
+        fault_injector: Option<FaultInjector>,
     ) -> Result<(), NetworkError> {
         primary_outbound_rx
             .0
-            .map(|r| {
+            .flat_map(|r| {
                 #[cfg_attr(
                     feature = "localhost-testing",
                     tracing::instrument(target = "citadel", skip_all, fields(packet_length = r.len()))
                 )]
-                fn process_outbound_packet(r: BytesMut) -> Bytes {
-                    r.freeze()
+                fn process_outbound_packet(
+                    mut r: BytesMut,
+                    header_obfuscator: Option<&HeaderObfuscator>,
+                    fault_injector: Option<&FaultInjector>,
+                ) -> (Option<Bytes>, Bytes) {
+                    append_header_checksum(&mut r);
+                    // a corrupted copy is sent ahead of the packet, as if damaged on the link
+                    let corrupted = fault_injector.and_then(|injector| injector.corrupt(&r));
+                    let obfuscate = |r: BytesMut| {
+                        if let Some(header_obfuscator) = header_obfuscator {
+                            header_obfuscator.prepare_outbound(r)
+                        } else {
+                            r.freeze()
+                        }
+                    };
+
+                    (corrupted.map(obfuscate), obfuscate(r))
                 }
 
-                Ok(process_outbound_packet(r))
+                let (corrupted, packet) =
+                    process_outbound_packet(r, header_obfuscator.as_ref(), fault_injector.as_ref());
+                futures::stream::iter(corrupted.into_iter().chain(Some(packet)).map(Ok))
             })
             .forward(writer)
             .map_err(|err| NetworkError::Generic(err.to_string()))
@@ -887,9 +1049,10 @@ impl HdpSession {
         tracing::instrument(target = "citadel", skip_all, ret, err(Debug))
     )]
     pub async fn execute_inbound_stream(
-        ref mut reader: CleanShutdownStream<GenericNetworkStream, LengthDelimitedCodec, Bytes>,
+        ref mut reader: CleanShutdownStream<GenericNetworkStream, PrimaryStreamCodec, Bytes>,
         ref this_main: HdpSession,
         p2p_handle: Option<P2PInboundHandle>,
+        header_obfuscator: Option<HeaderObfuscator>,
     ) -> Result<(), NetworkError> {
         log::trace!(target: "citadel", "HdpSession async inbound-stream subroutine executed");
         let (
@@ -986,11 +1149,39 @@ impl HdpSession {
                 log::error!(target: "citadel", "primary port reader error {}: {}. is server: {}. P2P: {}", error, err.to_string(), is_server, p2p);
             }
 
-            NetworkError::Generic(err.to_string())
+            let message = err.to_string();
+            // errors raised by the codec, such as oversized handshake packets, are passed through
+            match err
+                .into_inner()
+                .map(|inner| inner.downcast::<NetworkError>())
+            {
+                Some(Ok(err)) => *err,
+                _ => NetworkError::Generic(message),
+            }
         }
 
         let reader = async_stream::stream! {
-            while let Some(packet) = reader.next().await {
+            while let Some(mut packet) = reader.next().await {
+                // de-obfuscation must occur in order, since the first packet latches the key
+                if let (Ok(packet), Some(header_obfuscator)) = (packet.as_mut(), header_obfuscator.as_ref()) {
+                    if header_obfuscator.on_packet_received(packet).is_none() {
+                        continue;
+                    }
+                }
+
+                // corrupt headers are dropped before they can steer the packet processors
+                if let Ok(packet) = packet.as_mut() {
+                    if verify_header_checksum(packet).is_none() {
+                        this_main
+                            .corrupt_headers_discarded
+                            .set(this_main.corrupt_headers_discarded.get() + 1);
+                        this_main.session_manager.discard_log().on_discard(|| {
+                            format!("[Header checksum] Mismatch (LEN: {})", packet.len())
+                        });
+                        continue;
+                    }
+                }
+
                 yield packet
             }
         };
@@ -1052,6 +1243,7 @@ impl HdpSession {
 
             let kernel_ticket = borrow.kernel_ticket.get();
             let is_server = borrow.is_server;
+            let keep_alive_check_interval = borrow.keep_alive_policy.base_interval;
             std::mem::drop(borrow);
 
             // now, begin loading the subroutines
@@ -1110,7 +1302,7 @@ impl HdpSession {
                 });
             }
 
-            queue_worker.insert_reserved_fn(Some(QueueWorkerTicket::Periodic(KEEP_ALIVE_CHECKER, 0)), Duration::from_millis(KEEP_ALIVE_INTERVAL_MS), move |state_container| {
+            queue_worker.insert_reserved_fn(Some(QueueWorkerTicket::Periodic(KEEP_ALIVE_CHECKER, 0)), keep_alive_check_interval, move |state_container| {
                 let timestamp = time_tracker_2.get_global_time_ns();
                 if state_container.state.load(Ordering::SeqCst) == SessionState::Connected {
                     if state_container.keep_alive_timeout_ns != 0 {
@@ -1130,6 +1322,18 @@ impl HdpSession {
                 }
             });
 
+            queue_worker.insert_reserved_fn(
+                Some(QueueWorkerTicket::Periodic(STALLED_REKEY_CHECKER, 0)),
+                REKEY_STALL_CHECK_INTERVAL,
+                move |state_container| {
+                    if state_container.state.load(Ordering::Relaxed) == SessionState::Connected {
+                        state_container.cancel_stalled_rekeys(REKEY_STALL_TIMEOUT);
+                    }
+
+                    QueueWorkerResult::Incomplete
+                },
+            );
+
             // TODO: Rework UDP keep-alive subsystem to take QUIC into consideration.
             // QUIC already handles KA, but raw udp does not
             queue_worker.insert_reserved_fn(
@@ -1188,6 +1392,7 @@ impl HdpSession {
                     latest_hr,
                     security_level,
                     ticket,
+                    self.protocol_version.get(),
                     ts,
                     C2S_ENCRYPTION_ONLY,
                     virtual_path,
@@ -1206,6 +1411,7 @@ impl HdpSession {
                     latest_hr,
                     security_level,
                     ticket,
+                    self.protocol_version.get(),
                     ts,
                     target_cid,
                     virtual_path,
@@ -1250,6 +1456,7 @@ impl HdpSession {
                     latest_hr,
                     security_level,
                     ticket,
+                    self.protocol_version.get(),
                     ts,
                     C2S_ENCRYPTION_ONLY,
                     virtual_path,
@@ -1267,6 +1474,7 @@ impl HdpSession {
                     latest_hr,
                     security_level,
                     ticket,
+                    self.protocol_version.get(),
                     ts,
                     target_cid,
                     virtual_path,
@@ -1320,6 +1528,7 @@ impl HdpSession {
 
         let time_tracker = this.time_tracker;
         let timestamp = this.time_tracker.get_global_time_ns();
+        let protocol_version = this.protocol_version.get();
         let (group_sender, group_sender_rx) = channel(5);
         let mut group_sender_rx = tokio_stream::wrappers::ReceiverStream::new(group_sender_rx);
         let (stop_tx, stop_rx) = tokio::sync::oneshot::channel();
@@ -1330,168 +1539,202 @@ impl HdpSession {
         log::trace!(target: "citadel", "Transmit file name: {}", &file_name);
         // the key cid must be differentiated from the target cid because the target_cid needs to be zero if
         // there is no proxying. the key cid cannot be zero; if client -> server, key uses implicated cid
-        let (to_primary_stream, file_header, object_id, target_cid, key_cid, groups_needed) =
-            match virtual_target {
-                VirtualTargetType::LocalGroupServer(implicated_cid) => {
-                    // if we are sending this just to the HyperLAN server (in the case of file uploads),
-                    // then, we use this session's pqc, the cnac's latest drill, and 0 for target_cid
-                    let crypt_container = &mut state_container
-                        .c2s_channel_container
-                        .as_mut()
-                        .unwrap()
-                        .peer_session_crypto;
-                    let object_id = crypt_container.get_and_increment_object_id();
-                    let group_id_start = crypt_container.get_and_increment_group_id();
-                    let latest_hr = crypt_container.get_hyper_ratchet(None).cloned().unwrap();
-                    let static_aux_ratchet = crypt_container
-                        .toolset
-                        .get_static_auxiliary_ratchet()
-                        .clone();
-
-                    let to_primary_stream = this.to_primary_stream.clone().unwrap();
-                    let target_cid = 0;
-                    let (file_size, groups_needed, _max_bytes_per_group) = scramble_encrypt_source(
-                        source,
-                        max_group_size,
-                        object_id,
-                        group_sender,
-                        stop_rx,
-                        security_level,
-                        latest_hr.clone(),
-                        static_aux_ratchet,
-                        HDP_HEADER_BYTE_LEN,
-                        target_cid,
-                        group_id_start,
-                        transfer_type.clone(),
-                        packet_crafter::group::craft_wave_payload_packet_into,
-                    )
-                    .map_err(|err| NetworkError::Generic(err.to_string()))?;
+        let (
+            to_primary_stream,
+            file_header,
+            object_id,
+            target_cid,
+            key_cid,
+            groups_needed,
+            version_pin,
+        ) = match virtual_target {
+            VirtualTargetType::LocalGroupServer(implicated_cid) => {
+                // if we are sending this just to the HyperLAN server (in the case of file uploads),
+                // then, we use this session's pqc, the cnac's latest drill, and 0 for target_cid
+                let crypt_container = &mut state_container
+                    .c2s_channel_container
+                    .as_mut()
+                    .unwrap()
+                    .peer_session_crypto;
+                let object_id = crypt_container.get_and_increment_object_id();
+                let group_id_start = crypt_container.get_and_increment_group_id();
+                let latest_hr = crypt_container.get_hyper_ratchet(None).cloned().unwrap();
+                let version_pin = crypt_container.pin_version(latest_hr.version());
+                let static_aux_ratchet = crypt_container
+                    .toolset
+                    .get_static_auxiliary_ratchet()
+                    .clone();
+
+                let to_primary_stream = this.to_primary_stream.clone().unwrap();
+                let target_cid = 0;
+                let (file_size, groups_needed, _max_bytes_per_group) = scramble_encrypt_source(
+                    source,
+                    max_group_size,
+                    object_id,
+                    group_sender,
+                    stop_rx,
+                    security_level,
+                    latest_hr.clone(),
+                    static_aux_ratchet,
+                    HDP_HEADER_BYTE_LEN,
+                    target_cid,
+                    group_id_start,
+                    transfer_type.clone(),
+                    move |coords, scramble_drill, object_id, target_cid, buffer| {
+                        packet_crafter::group::craft_wave_payload_packet_into(
+                            coords,
+                            scramble_drill,
+                            object_id,
+                            target_cid,
+                            protocol_version,
+                            buffer,
+                        )
+                    },
+                )
+                .map_err(NetworkError::from)?;
+
+                let file_metadata = VirtualObjectMetadata {
+                    object_id,
+                    name: file_name,
+                    // TODO: update metadata using the local file handle metadata
+                    date_created: "".to_string(),
+                    author: "N/A".to_string(),
+                    plaintext_length: file_size,
+                    group_count: groups_needed,
+                    cid: implicated_cid,
+                    transfer_type,
+                };
 
-                    let file_metadata = VirtualObjectMetadata {
-                        object_id,
-                        name: file_name,
-                        // TODO: update metadata using the local file handle metadata
-                        date_created: "".to_string(),
-                        author: "N/A".to_string(),
-                        plaintext_length: file_size,
-                        group_count: groups_needed,
-                        cid: implicated_cid,
-                        transfer_type,
-                    };
+                // if 1 group, we don't need to reserve any more group IDs. If 2, then we reserve just one. 3, then 2
+                let amt_to_reserve = groups_needed - 1;
+                crypt_container.rolling_group_id += amt_to_reserve as u64;
+                let file_header = packet_crafter::file::craft_file_header_packet(
+                    &latest_hr,
+                    group_id_start,
+                    ticket,
+                    security_level,
+                    virtual_target,
+                    file_metadata,
+                    this.protocol_version.get(),
+                    timestamp,
+                    local_encryption_level,
+                );
+                (
+                    to_primary_stream,
+                    file_header,
+                    object_id,
+                    target_cid,
+                    implicated_cid,
+                    groups_needed,
+                    version_pin,
+                )
+            }
 
-                    // if 1 group, we don't need to reserve any more group IDs. If 2, then we reserve just one. 3, then 2
-                    let amt_to_reserve = groups_needed - 1;
-                    crypt_container.rolling_group_id += amt_to_reserve as u64;
-                    let file_header = packet_crafter::file::craft_file_header_packet(
-                        &latest_hr,
-                        group_id_start,
-                        ticket,
-                        security_level,
-                        virtual_target,
-                        file_metadata,
-                        timestamp,
-                        local_encryption_level,
-                    );
-                    (
-                        to_primary_stream,
-                        file_header,
-                        object_id,
-                        target_cid,
-                        implicated_cid,
-                        groups_needed,
-                    )
-                }
+            VirtualConnectionType::LocalGroupPeer(implicated_cid, target_cid) => {
+                log::trace!(target: "citadel", "Sending HyperLAN peer ({}) <-> HyperLAN Peer ({})", implicated_cid, target_cid);
+                // here, we don't use the base session's PQC. Instead, we use the vconn's pqc and
+                let endpoint_container =
+                    state_container.get_peer_endpoint_container_mut(target_cid)?;
 
-                VirtualConnectionType::LocalGroupPeer(implicated_cid, target_cid) => {
-                    log::trace!(target: "citadel", "Sending HyperLAN peer ({}) <-> HyperLAN Peer ({})", implicated_cid, target_cid);
-                    // here, we don't use the base session's PQC. Instead, we use the vconn's pqc and
-                    let endpoint_container =
-                        state_container.get_peer_endpoint_container_mut(target_cid)?;
-
-                    let object_id = endpoint_container
-                        .endpoint_crypto
-                        .get_and_increment_object_id();
-                    // reserve group ids
-                    let start_group_id = endpoint_container
-                        .endpoint_crypto
-                        .get_and_increment_group_id();
-
-                    let latest_usable_ratchet = endpoint_container
-                        .endpoint_crypto
-                        .get_hyper_ratchet(None)
-                        .unwrap();
-
-                    let static_aux_ratchet = endpoint_container
-                        .endpoint_crypto
-                        .toolset
-                        .get_static_auxiliary_ratchet()
-                        .clone();
-
-                    let preferred_primary_stream = endpoint_container
-                        .get_direct_p2p_primary_stream()
-                        .cloned()
-                        .unwrap_or_else(|| this.to_primary_stream.clone().unwrap());
-
-                    let (file_size, groups_needed, _max_bytes_per_group) = scramble_encrypt_source(
-                        source,
-                        max_group_size,
-                        object_id,
-                        group_sender,
-                        stop_rx,
-                        security_level,
-                        latest_usable_ratchet.clone(),
-                        static_aux_ratchet,
-                        HDP_HEADER_BYTE_LEN,
-                        target_cid,
-                        start_group_id,
-                        transfer_type.clone(),
-                        packet_crafter::group::craft_wave_payload_packet_into,
-                    )
-                    .map_err(|err| NetworkError::Generic(err.to_string()))?;
+                let object_id = endpoint_container
+                    .endpoint_crypto
+                    .get_and_increment_object_id();
+                // reserve group ids
+                let start_group_id = endpoint_container
+                    .endpoint_crypto
+                    .get_and_increment_group_id();
 
-                    let file_metadata = VirtualObjectMetadata {
-                        object_id,
-                        name: file_name,
-                        date_created: "".to_string(),
-                        author: "".to_string(),
-                        plaintext_length: file_size,
-                        group_count: groups_needed,
-                        cid: implicated_cid,
-                        transfer_type,
-                    };
+                let latest_usable_ratchet = endpoint_container
+                    .endpoint_crypto
+                    .get_hyper_ratchet(None)
+                    .unwrap();
 
-                    let file_header = packet_crafter::file::craft_file_header_packet(
-                        latest_usable_ratchet,
-                        start_group_id,
-                        ticket,
-                        security_level,
-                        virtual_target,
-                        file_metadata,
-                        timestamp,
-                        local_encryption_level,
-                    );
+                let version_pin = endpoint_container
+                    .endpoint_crypto
+                    .pin_version(latest_usable_ratchet.version());
 
-                    // if 1 group, we don't need to reserve any more group IDs. If 2, then we reserve just one. 3, then 2
-                    let amt_to_reserve = groups_needed - 1;
-                    endpoint_container.endpoint_crypto.rolling_group_id += amt_to_reserve as u64;
-
-                    (
-                        preferred_primary_stream,
-                        file_header,
-                        object_id,
-                        target_cid,
-                        target_cid,
-                        groups_needed,
-                    )
-                }
+                let static_aux_ratchet = endpoint_container
+                    .endpoint_crypto
+                    .toolset
+                    .get_static_auxiliary_ratchet()
+                    .clone();
 
-                _ => {
-                    log::error!(target: "citadel", "HyperWAN functionality not yet implemented");
-                    return Err(NetworkError::InternalError(
-                        "HyperWAN functionality not yet implemented",
-                    ));
-                }
-            };
+                let preferred_primary_stream = endpoint_container
+                    .get_direct_p2p_primary_stream()
+                    .cloned()
+                    .unwrap_or_else(|| this.to_primary_stream.clone().unwrap());
+
+                let (file_size, groups_needed, _max_bytes_per_group) = scramble_encrypt_source(
+                    source,
+                    max_group_size,
+                    object_id,
+                    group_sender,
+                    stop_rx,
+                    security_level,
+                    latest_usable_ratchet.clone(),
+                    static_aux_ratchet,
+                    HDP_HEADER_BYTE_LEN,
+                    target_cid,
+                    start_group_id,
+                    transfer_type.clone(),
+                    move |coords, scramble_drill, object_id, target_cid, buffer| {
+                        packet_crafter::group::craft_wave_payload_packet_into(
+                            coords,
+                            scramble_drill,
+                            object_id,
+                            target_cid,
+                            protocol_version,
+                            buffer,
+                        )
+                    },
+                )
+                .map_err(NetworkError::from)?;
+
+                let file_metadata = VirtualObjectMetadata {
+                    object_id,
+                    name: file_name,
+                    date_created: "".to_string(),
+                    author: "".to_string(),
+                    plaintext_length: file_size,
+                    group_count: groups_needed,
+                    cid: implicated_cid,
+                    transfer_type,
+                };
+
+                let file_header = packet_crafter::file::craft_file_header_packet(
+                    latest_usable_ratchet,
+                    start_group_id,
+                    ticket,
+                    security_level,
+                    virtual_target,
+                    file_metadata,
+                    this.protocol_version.get(),
+                    timestamp,
+                    local_encryption_level,
+                );
+
+                // if 1 group, we don't need to reserve any more group IDs. If 2, then we reserve just one. 3, then 2
+                let amt_to_reserve = groups_needed - 1;
+                endpoint_container.endpoint_crypto.rolling_group_id += amt_to_reserve as u64;
+
+                (
+                    preferred_primary_stream,
+                    file_header,
+                    object_id,
+                    target_cid,
+                    target_cid,
+                    groups_needed,
+                    version_pin,
+                )
+            }
+
+            _ => {
+                log::error!(target: "citadel", "HyperWAN functionality not yet implemented");
+                return Err(NetworkError::InternalError(
+                    "HyperWAN functionality not yet implemented",
+                ));
+            }
+        };
 
         // now that the async cryptscrambler tasks have been spawned on the threadpool, we need to also
         // spawn tasks that read the [GroupSenders] from there. We also need to store an [OutboundFileMetadataTransmitter]
@@ -1516,6 +1759,8 @@ impl HdpSession {
             ticket,
             next_gs_alerter: next_gs_alerter.clone(),
             start: Some(start),
+            stats: ObjectTransferStatsTracker::default(),
+            version_pin,
         };
         let file_key = FileKey::new(key_cid, object_id);
         let _ = state_container
@@ -1608,6 +1853,7 @@ impl HdpSession {
                                 object_id,
                                 ticket,
                                 security_level,
+                                sess.protocol_version.get(),
                                 time_tracker,
                             );
                             // group_id is unique per session
@@ -1640,6 +1886,11 @@ impl HdpSession {
                                 .outbound_transmitters
                                 .insert(key, outbound_container)
                                 .is_none());
+                            if let Some(outbound_file) =
+                                state_container.outbound_files.get(&file_key)
+                            {
+                                outbound_file.stats.on_group_sent();
+                            }
                             // We can't just add the outbound container. We need to wait til we get the signal to. When the > 50% WAVE_ACKs
                             // are received, the OutboundFileContainer (which should have a group_notifier) should send a signal which we await for
                             // here. Also: DROP `sess`!
@@ -1865,6 +2116,7 @@ impl HdpSession {
                 hyper_ratchet,
                 signal_processed,
                 ticket,
+                this.protocol_version.get(),
                 timestamp,
                 security_level,
             );
@@ -1915,12 +2167,13 @@ impl HdpSession {
 
         while let Some((cmd_aux, packet)) = receiver.next().await {
             let send_addr = hole_punched_addr.send_address;
-            let packet = peer_session_accessor.borrow_hr(None, |hr, _| {
+            let packet = peer_session_accessor.borrow_hr(None, |hr, state_container| {
                 packet_crafter::udp::craft_udp_packet(
                     hr,
                     cmd_aux,
                     packet,
                     target_cid,
+                    state_container.protocol_version.get(),
                     SecurityLevel::Standard,
                 )
             })?;
@@ -2019,6 +2272,7 @@ impl HdpSession {
                 let disconnect_stage0_packet = packet_crafter::do_disconnect::craft_stage0(
                     hr,
                     ticket,
+                    session.protocol_version.get(),
                     timestamp,
                     security_level,
                 );
@@ -2048,6 +2302,25 @@ impl HdpSessionInner {
         )
     }
 
+    /// Schedules headers to be protected by a key derived from `hyper_ratchet` once the session
+    /// is connected, if the session's security settings request it
+    pub(super) fn schedule_header_protection(
+        &self,
+        hyper_ratchet: &StackedRatchet,
+        session_security_settings: Option<SessionSecuritySettings>,
+    ) {
+        let session_keyed = session_security_settings
+            .map(|settings| settings.header_protection == HeaderProtection::SessionKeyed)
+            .unwrap_or(false);
+
+        if let (true, Some(header_obfuscator)) = (session_keyed, self.header_obfuscator.as_ref()) {
+            let key = hyper_ratchet
+                .get_scramble_drill()
+                .derive_key(HEADER_PROTECTION_KEY_CONTEXT);
+            header_obfuscator.schedule_session_key(key);
+        }
+    }
+
     pub(super) fn create_register_success_message(&self) -> String {
         "Citadel register::success. Welcome to your new post-quantum network! Login to interact with your new network".to_string()
     }
@@ -2138,8 +2411,12 @@ impl HdpSessionInner {
                 .session_security_settings
                 .map(|r| r.security_level)
                 .unwrap();
-            let stage0_packet =
-                packet_crafter::do_deregister::craft_stage0(hr, timestamp, security_level);
+            let stage0_packet = packet_crafter::do_deregister::craft_stage0(
+                hr,
+                self.protocol_version.get(),
+                timestamp,
+                security_level,
+            );
 
             state_container.deregister_state.on_init(timestamp, ticket);
             self.send_to_primary_stream(Some(ticket), stage0_packet)
@@ -2147,13 +2424,57 @@ impl HdpSessionInner {
     }
 
     pub(crate) fn is_provisional(&self) -> bool {
-        let state = self.state.load(Ordering::Relaxed);
-        //self.implicated_cid.is_none()
-        // SocketJustOpened is only the state for a session created from an incoming connection
-        state == SessionState::SocketJustOpened
-            || state == SessionState::NeedsConnect
-            || state == SessionState::ConnectionProcess
-            || state == SessionState::NeedsRegister
+        self.state.load(Ordering::Relaxed).is_provisional()
+    }
+
+    /// Takes a snapshot of the session's state for diagnostics
+    pub(crate) fn dump_state(&self) -> SessionStateDump {
+        let state_container = inner_state!(self.state_container);
+        let virtual_connections = state_container
+            .active_virtual_connections
+            .iter()
+            .map(|(peer_cid, vconn)| VirtualConnectionStateDump {
+                peer_cid: *peer_cid,
+                connection_type: vconn.connection_type,
+                is_active: vconn.is_active.load(Ordering::Relaxed),
+                crypto: vconn
+                    .endpoint_container
+                    .as_ref()
+                    .map(|container| CryptoStateDump::from(&container.endpoint_crypto)),
+            })
+            .collect();
+
+        SessionStateDump {
+            implicated_cid: self.implicated_cid.get(),
+            remote_peer: self.remote_peer,
+            is_server: self.is_server,
+            state: self.state.load(Ordering::Relaxed),
+            security_settings: state_container.session_security_settings,
+            udp_mode: state_container.udp_mode,
+            c2s_crypto: state_container.get_c2s_crypto().map(CryptoStateDump::from),
+            virtual_connections,
+            enqueued_packets: state_container
+                .enqueued_packets
+                .values()
+                .map(std::collections::VecDeque::len)
+                .sum(),
+            inbound_file_transfers: state_container.inbound_files.len(),
+            outbound_file_transfers: state_container.outbound_files.len(),
+            inbound_groups: state_container.inbound_groups.len(),
+            outbound_groups: state_container.outbound_transmitters.len(),
+            last_keep_alive_ns: state_container.network_stats.last_keep_alive,
+            ping_ns: state_container.network_stats.ping_ns,
+            jitter_ns: state_container.network_stats.jitter_ns,
+            rtt_ns: state_container.network_stats.rtt_ns,
+            clock_offset_ns: inner!(self.clock_skew_guard).offset_ns(),
+            millis_since_last_valid_event: state_container
+                .meta_expiry_state
+                .last_valid_event()
+                .elapsed()
+                .as_millis() as u64,
+            total_plaintext_bytes_sent: state_container.transfer_stats.total_plaintext_bytes_sent,
+            corrupt_headers_discarded: self.corrupt_headers_discarded.get(),
+        }
     }
 
     pub(crate) fn send_session_dc_signal<T: Into<String>>(
@@ -1,5 +1,5 @@
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::atomic::Ordering;
@@ -11,18 +11,28 @@ use citadel_crypt::stacked_ratchet::StackedRatchet;
 use citadel_user::account_manager::AccountManager;
 use citadel_user::auth::proposed_credentials::ProposedCredentials;
 use citadel_user::prelude::ConnectProtocol;
+use citadel_user::registration_limit::{LocalRegistrationRateLimiter, RegistrationRateLimiter};
 use citadel_wire::hypernode_type::NodeType;
 use citadel_wire::nat_identification::NatType;
 use netbeam::time_tracker::TimeTracker;
 
 use crate::auth::AuthenticationRequest;
 use crate::constants::{DO_CONNECT_EXPIRE_TIME_MS, KEEP_ALIVE_TIMEOUT_NS, UDP_MODE};
-use crate::error::NetworkError;
+use crate::error::{ConnectError, NetworkError};
 use crate::kernel::RuntimeFuture;
 use crate::macros::SyncContextRequirements;
 use crate::proto::endpoint_crypto_accessor::EndpointCryptoAccessor;
+use crate::proto::misc::connect_throttle::ConnectThrottle;
+use crate::proto::misc::discard_log::DiscardLog;
+use crate::proto::misc::fault_injection::FaultInjector;
+use crate::proto::misc::keep_alive_policy::KeepAlivePolicy;
 use crate::proto::misc::net::GenericNetworkStream;
+use crate::proto::misc::pending_handshakes::{HandshakeMetrics, PendingHandshakes};
+use crate::proto::misc::protocol_version::ProtocolVersionRange;
+use crate::proto::misc::session_limit::{SessionAdmission, SessionLimit};
+use crate::proto::misc::session_resumption::{ResumptionTicketStore, HELD, ISSUED};
 use crate::proto::misc::session_security_settings::SessionSecuritySettings;
+use crate::proto::misc::session_state_dump::SessionStateDump;
 use crate::proto::misc::underlying_proto::ServerUnderlyingProtocol;
 use crate::proto::node::{ConnectMode, HdpServer};
 use crate::proto::node_result::NodeResult;
@@ -34,9 +44,10 @@ use crate::proto::packet_processor::peer::group_broadcast::{
 };
 use crate::proto::packet_processor::PrimaryProcessorResult;
 use crate::proto::peer::message_group::{MessageGroupKey, MessageGroupOptions};
+use crate::proto::peer::peer_identity::PeerIdentitySettings;
 use crate::proto::peer::peer_layer::{
     HyperNodePeerLayer, HyperNodePeerLayerInner, MailboxTransfer, PeerConnectionType, PeerResponse,
-    PeerSignal, UdpMode,
+    PeerSignal, Presence, UdpMode,
 };
 use crate::proto::remote::{NodeRemote, Ticket};
 use crate::proto::session::{
@@ -63,13 +74,22 @@ pub struct HdpSessionManagerInner {
     /// Connections which have no implicated CID go herein. They are strictly expected to be
     /// in the state of NeedsRegister. Once they leave that state, they are eventually polled
     /// by the [HdpSessionManager] and thereafter placed inside an appropriate session
-    provisional_connections: HashMap<SocketAddr, (Instant, Sender<()>, HdpSession)>,
+    provisional_connections: PendingHandshakes<(Sender<()>, HdpSession)>,
     kernel_tx: UnboundedSender<NodeResult>,
     time_tracker: TimeTracker,
     clean_shutdown_tracker_tx: UnboundedSender<()>,
     clean_shutdown_tracker: Option<UnboundedReceiver<()>>,
     client_config: Arc<rustls::ClientConfig>,
     stun_servers: Option<Vec<String>>,
+    header_obfuscation: bool,
+    peer_identity_settings: Arc<PeerIdentitySettings>,
+    supported_protocol_versions: ProtocolVersionRange,
+    fault_injector: Option<FaultInjector>,
+    keep_alive_policy: KeepAlivePolicy,
+    connect_throttle: ConnectThrottle,
+    registration_limiter: Arc<dyn RegistrationRateLimiter>,
+    discard_log: DiscardLog,
+    session_limit: SessionLimit,
 }
 
 impl HdpSessionManager {
@@ -81,9 +101,41 @@ impl HdpSessionManager {
         time_tracker: TimeTracker,
         client_config: Arc<rustls::ClientConfig>,
         stun_servers: Option<Vec<String>>,
+        header_obfuscation: bool,
+        peer_identity_settings: Arc<PeerIdentitySettings>,
+        supported_protocol_versions: ProtocolVersionRange,
+        fault_injector: Option<FaultInjector>,
+        keep_alive_policy: KeepAlivePolicy,
     ) -> Self {
         let incoming_cxn_count = 0;
         let (clean_shutdown_tracker_tx, clean_shutdown_tracker_rx) = unbounded();
+        let misc_settings = account_manager.get_misc_settings();
+        let connect_throttle = ConnectThrottle::new(
+            misc_settings.max_failed_connect_attempts,
+            misc_settings.failed_connect_cooldown,
+        );
+        let registration_limiter = misc_settings
+            .registration_rate_limiter
+            .clone()
+            .unwrap_or_else(|| {
+                Arc::new(LocalRegistrationRateLimiter::per_minute(
+                    misc_settings.max_registrations_per_minute,
+                ))
+            });
+        let discard_log = DiscardLog::new(
+            misc_settings.discard_log_detailed_limit,
+            misc_settings.discard_log_sample_rate,
+            misc_settings.discard_log_summary_interval,
+        );
+        let provisional_connections = PendingHandshakes::new(
+            misc_settings.max_pending_handshakes,
+            misc_settings.pending_handshake_timeout,
+            misc_settings.pending_handshake_overflow,
+        );
+        let session_limit = SessionLimit::new(
+            misc_settings.max_sessions_per_cid,
+            misc_settings.on_session_limit,
+        );
         let inner = HdpSessionManagerInner {
             clean_shutdown_tracker_tx,
             clean_shutdown_tracker: Some(clean_shutdown_tracker_rx),
@@ -95,11 +147,20 @@ impl HdpSessionManager {
             sessions: HashMap::new(),
             incoming_cxn_count,
             account_manager,
-            provisional_connections: HashMap::new(),
+            provisional_connections,
             kernel_tx,
             time_tracker,
             client_config,
             stun_servers,
+            header_obfuscation,
+            peer_identity_settings,
+            supported_protocol_versions,
+            fault_injector,
+            keep_alive_policy,
+            connect_throttle,
+            registration_limiter,
+            discard_log,
+            session_limit,
         };
 
         Self::from(inner)
@@ -113,12 +174,131 @@ impl HdpSessionManager {
         this.time_tracker
     }
 
+    /// Returns the node-wide log that bounds the output caused by discarded packets
+    pub(crate) fn discard_log(&self) -> DiscardLog {
+        inner!(self).discard_log.clone()
+    }
+
+    pub(crate) fn fault_injector(&self) -> Option<FaultInjector> {
+        inner!(self).fault_injector.clone()
+    }
+
+    /// Returns an error if `source` has failed to connect too many times and is still cooling down
+    pub(crate) fn check_connect_throttle(&self, source: IpAddr) -> Result<(), ConnectError> {
+        inner_mut!(self).connect_throttle.check(source)
+    }
+
+    /// Records the outcome of a connect attempt from `source`. A success clears prior failures
+    pub(crate) fn on_connect_attempt_finished(&self, source: IpAddr, success: bool) {
+        let mut this = inner_mut!(self);
+        if success {
+            this.connect_throttle.on_success(source)
+        } else {
+            this.connect_throttle.on_failure(source)
+        }
+    }
+
+    /// Returns the limiter consulted before each inbound registration
+    pub(crate) fn registration_limiter(&self) -> Arc<dyn RegistrationRateLimiter> {
+        inner!(self).registration_limiter.clone()
+    }
+
+    /// Issues a new resumption ticket for `cid` if session resumption is enabled, replacing any
+    /// ticket previously issued to it
+    pub(crate) async fn issue_resumption_ticket(
+        &self,
+        cid: u64,
+        static_aux_ratchet: &StackedRatchet,
+    ) -> Result<Option<Vec<u8>>, NetworkError> {
+        let account_manager = inner!(self).account_manager.clone();
+        let lifetime = match account_manager
+            .get_misc_settings()
+            .session_resumption_lifetime
+        {
+            Some(lifetime) => lifetime,
+            None => return Ok(None),
+        };
+
+        let ticket = ResumptionTicketStore::generate_ticket();
+        ResumptionTicketStore::new(account_manager.get_persistence_handler(), ISSUED)
+            .insert(cid, &ticket, Some(lifetime), static_aux_ratchet)
+            .await?;
+        Ok(Some(ticket))
+    }
+
+    /// Validates a SYN from `cid` against the unexpired ticket issued to it, if any. See
+    /// [`ResumptionTicketStore::redeem_validated`]
+    pub(crate) async fn redeem_issued_resumption_ticket<T>(
+        &self,
+        cid: u64,
+        static_aux_ratchet: &StackedRatchet,
+        validate: impl FnOnce(Option<&[u8]>) -> Result<(T, bool), NetworkError>,
+    ) -> Result<T, NetworkError> {
+        let account_manager = inner!(self).account_manager.clone();
+        ResumptionTicketStore::new(account_manager.get_persistence_handler(), ISSUED)
+            .redeem_validated(cid, static_aux_ratchet, validate)
+            .await
+    }
+
+    /// Stores a ticket received from the server for use on the next connect
+    pub(crate) async fn store_resumption_ticket(
+        &self,
+        cid: u64,
+        ticket: &[u8],
+        static_aux_ratchet: &StackedRatchet,
+    ) -> Result<(), NetworkError> {
+        let account_manager = inner!(self).account_manager.clone();
+        ResumptionTicketStore::new(account_manager.get_persistence_handler(), HELD)
+            .insert(cid, ticket, None, static_aux_ratchet)
+            .await
+    }
+
+    /// Removes and returns the ticket received from the server for `cid`, if any
+    pub(crate) async fn take_resumption_ticket(
+        &self,
+        cid: u64,
+        static_aux_ratchet: &StackedRatchet,
+    ) -> Result<Option<Vec<u8>>, NetworkError> {
+        let account_manager = inner!(self).account_manager.clone();
+        ResumptionTicketStore::new(account_manager.get_persistence_handler(), HELD)
+            .take(cid, static_aux_ratchet)
+            .await
+    }
+
     /// Determines if `cid` is connected
     pub fn session_active(&self, cid: u64) -> bool {
         let this = inner!(self);
         this.sessions.contains_key(&cid)
     }
 
+    /// Returns the number of sessions `cid` holds on this node
+    pub fn active_session_count(&self, cid: u64) -> usize {
+        inner!(self).active_session_count(cid)
+    }
+
+    /// Checks whether a connect for `cid` may proceed without ending any of its sessions
+    pub(crate) fn check_session_limit(&self, cid: u64) -> Result<SessionAdmission, ConnectError> {
+        let this = inner!(self);
+        this.session_limit
+            .check(cid, this.active_session_count(cid))
+    }
+
+    /// Makes room for a new session of `cid`, ending its oldest session if the policy allows.
+    /// Returns an error if the connect would exceed the limit
+    pub(crate) fn enforce_session_limit(&self, cid: u64) -> Result<(), ConnectError> {
+        let mut this = inner_mut!(self);
+        let active = this.active_session_count(cid);
+        if this.session_limit.check(cid, active)? == SessionAdmission::EvictOldest {
+            if let Some((stopper, session)) = this.sessions.remove(&cid) {
+                log::warn!(target: "citadel", "Ending the oldest session of {} to make room for a new one", cid);
+                session.do_static_hr_refresh_atexit.set(false);
+                let _ = stopper.send(());
+            }
+        }
+
+        Ok(())
+    }
+
     /// Called by the higher-level [HdpServer] async writer loop
     /// `nid_local` is only needed in case a provisional id is needed.
     ///
@@ -283,6 +463,20 @@ impl HdpSessionManager {
                 peer_only_connect_proto: peer_only_connect_mode,
             };
 
+            let (
+                header_obfuscation,
+                peer_identity_settings,
+                supported_protocol_versions,
+                keep_alive_policy,
+            ) = {
+                let this = inner!(self);
+                (
+                    this.header_obfuscation,
+                    this.peer_identity_settings.clone(),
+                    this.supported_protocol_versions,
+                    this.keep_alive_policy,
+                )
+            };
             let session_init_params = SessionInitParams {
                 local_nat_type,
                 remote_peer: peer_addr,
@@ -299,13 +493,17 @@ impl HdpSessionManager {
                 hypernode_peer_layer: peer_layer,
                 client_only_settings: Some(client_only_settings),
                 stun_servers,
+                header_obfuscation,
+                peer_identity_settings,
+                supported_protocol_versions,
+                keep_alive_policy,
             };
 
             let (stopper, new_session) = HdpSession::new(session_init_params)?;
 
             let _ = inner_mut!(self)
                 .provisional_connections
-                .insert(peer_addr, (Instant::now(), stopper, new_session.clone()));
+                .insert(peer_addr, (stopper, new_session.clone()));
 
             (
                 session_manager_clone,
@@ -340,7 +538,7 @@ impl HdpSessionManager {
             Ok(cid_opt) | Err((_, cid_opt)) => {
                 if let Some(cid) = *cid_opt {
                     //log::trace!(target: "citadel", "[safe] Deleting full connection from CID {} (IP: {})", cid, &peer_addr);
-                    session_manager.clear_session(cid);
+                    session_manager.clear_session_if_current(cid, &new_session);
                     session_manager.clear_provisional_session(&peer_addr);
                 } else {
                     //log::trace!(target: "citadel", "[safe] deleting provisional connection to {}", &peer_addr);
@@ -361,6 +559,33 @@ impl HdpSessionManager {
         let peer_layer = sess_mgr.hypernode_peer_layer.clone();
         let mut state_container = inner_mut_state!(sess.state_container);
 
+        // alerts the mutual peers of the client that it went offline, unless the client already
+        // reconnected. The peer list must be read before a passwordless account is deleted below
+        let mut notify_offline = sess
+            .implicated_cid
+            .get()
+            .filter(|_| sess.is_server)
+            .map(|implicated_cid| {
+                let pers = pers.clone();
+                let session_manager = session_manager.clone();
+                async move {
+                    if session_manager.session_active(implicated_cid) {
+                        return;
+                    }
+
+                    match pers.get_hyperlan_peer_list(implicated_cid).await {
+                        Ok(peers) => session_manager.notify_presence(
+                            implicated_cid,
+                            Presence::Offline,
+                            peers.unwrap_or_default(),
+                        ),
+                        Err(err) => {
+                            log::warn!(target: "citadel", "Unable to alert the peers of {} that it went offline: {:?}", implicated_cid, err)
+                        }
+                    }
+                }
+            });
+
         if let Some(cnac) = state_container.cnac.as_ref() {
             // we do not need to save here. When the ratchet is reloaded, it will be zeroed out anyways.
             // the only reason we call this is to ensure that FCM packets that get protected on their way out
@@ -374,12 +599,23 @@ impl HdpSessionManager {
             if cnac.passwordless() {
                 // delete
                 let cid = cnac.get_cid();
-                let task = async move { pers.delete_cnac_by_cid(cid).await };
+                let notify_offline = notify_offline.take();
+                let task = async move {
+                    if let Some(notify_offline) = notify_offline {
+                        notify_offline.await;
+                    }
+
+                    pers.delete_cnac_by_cid(cid).await
+                };
                 spawn!(task);
                 log::trace!(target: "citadel", "Deleting passwordless CNAC ...");
             }
         }
 
+        if let Some(task) = notify_offline {
+            spawn!(task);
+        }
+
         // the following shutdown sequence is valid for only for the HyperLAN server
         // This final sequence alerts all CIDs in the network
         if sess.is_server {
@@ -408,8 +644,8 @@ impl HdpSessionManager {
                                 log::trace!(target: "citadel", "Alerting {} that {} disconnected", peer_cid, implicated_cid);
                                 let peer_conn_type = PeerConnectionType::HyperLANPeerToHyperLANPeer(implicated_cid, peer_cid);
                                 let signal = PeerSignal::Disconnect(peer_conn_type, Some(PeerResponse::Disconnected(format!("{peer_cid} disconnected from {implicated_cid} forcibly"))));
-                                if let Err(_err) = sess_mgr.send_signal_to_peer_direct(peer_cid, |peer_hyper_ratchet| {
-                                    super::packet_crafter::peer_cmd::craft_peer_signal(peer_hyper_ratchet, signal, Ticket(0), timestamp, security_level)
+                                if let Err(_err) = sess_mgr.send_signal_to_peer_direct(peer_cid, |peer_hyper_ratchet, protocol_version| {
+                                    super::packet_crafter::peer_cmd::craft_peer_signal(peer_hyper_ratchet, signal, Ticket(0), protocol_version, timestamp, security_level)
                                 }) {
                                     //log::error!(target: "citadel", "Unable to send shutdown signal to {}: {:?}", peer_cid, err);
                                 }
@@ -477,6 +713,15 @@ impl HdpSessionManager {
             }
         }
 
+        // half-open connections may not grow the table without bound
+        for (stopper, _) in this.provisional_connections.reap() {
+            let _ = stopper.send(());
+        }
+
+        if let Some((stopper, _)) = this.provisional_connections.make_room()? {
+            let _ = stopper.send(());
+        }
+
         // Regardless if the IpAddr existed as a client before, we must treat the connection temporarily as provisional
         // However, two concurrent provisional connections from the same IP cannot be connecting at once
         let local_node_type = this.local_node_type;
@@ -499,11 +744,15 @@ impl HdpSessionManager {
             hypernode_peer_layer: peer_layer,
             client_only_settings: None,
             stun_servers,
+            header_obfuscation: this.header_obfuscation,
+            peer_identity_settings: this.peer_identity_settings.clone(),
+            supported_protocol_versions: this.supported_protocol_versions,
+            keep_alive_policy: this.keep_alive_policy,
         };
 
         let (stopper, new_session) = HdpSession::new(session_init_params)?;
         this.provisional_connections
-            .insert(peer_addr, (Instant::now(), stopper, new_session.clone()));
+            .insert(peer_addr, (stopper, new_session.clone()));
         std::mem::drop(this);
 
         // Note: Must send TICKET on finish
@@ -622,6 +871,75 @@ impl HdpSessionManager {
         }
     }
 
+    /// Returns every drill version retained for the virtual connection, from oldest to newest
+    pub fn get_active_drill_versions(
+        &self,
+        virtual_target: VirtualTargetType,
+    ) -> Result<Vec<u32>, NetworkError> {
+        let implicated_cid = virtual_target.get_implicated_cid();
+        let this = inner!(self);
+        if let Some(sess) = this.sessions.get(&implicated_cid) {
+            let state_container = inner_state!(sess.1.state_container);
+            let target_cid = virtual_target.get_target_cid();
+            let crypt = if target_cid == C2S_ENCRYPTION_ONLY {
+                state_container.get_c2s_crypto()
+            } else {
+                state_container.get_peer_session_crypto(target_cid)
+            };
+
+            crypt
+                .map(|crypt| crypt.get_active_hyper_ratchet_versions())
+                .ok_or_else(|| {
+                    NetworkError::Generic(format!(
+                        "Unable to find the crypto container for {virtual_target}"
+                    ))
+                })
+        } else {
+            Err(NetworkError::Generic(format!(
+                "Unable to get drill versions for {implicated_cid} (not an active session)"
+            )))
+        }
+    }
+
+    /// Returns the state of the re-keying process with `peer_cid`. A `peer_cid` equal to a session
+    /// CID refers to the channel between the client and server
+    pub fn pending_rekey_status(&self, peer_cid: u64) -> Result<PendingReKeyStatus, NetworkError> {
+        let this = inner!(self);
+        if let Some(sess) = this.sessions.get(&peer_cid) {
+            return Ok(inner_state!(sess.1.state_container)
+                .ratchet_update_state
+                .status(C2S_ENCRYPTION_ONLY));
+        }
+
+        this.sessions
+            .values()
+            .find_map(|sess| {
+                let state_container = inner_state!(sess.1.state_container);
+                state_container
+                    .active_virtual_connections
+                    .contains_key(&peer_cid)
+                    .then(|| state_container.ratchet_update_state.status(peer_cid))
+            })
+            .ok_or_else(|| {
+                NetworkError::Generic(format!(
+                    "Unable to get re-key status for {peer_cid} (not an active session or peer)"
+                ))
+            })
+    }
+
+    /// Returns a snapshot of the state of the session for `session_cid`
+    pub fn dump_session_state(&self, session_cid: u64) -> Result<SessionStateDump, NetworkError> {
+        let this = inner!(self);
+        this.sessions
+            .get(&session_cid)
+            .map(|sess| sess.1.dump_state())
+            .ok_or_else(|| {
+                NetworkError::Generic(format!(
+                    "Unable to dump the state of {session_cid} (not an active session)"
+                ))
+            })
+    }
+
     /// Returns true if the process initiated successfully
     pub fn initiate_deregistration_subroutine(
         &self,
@@ -687,7 +1005,7 @@ impl HdpSessionManager {
     /// slow connections, or during background execution on android/ios
     pub fn upgrade_connection(&self, socket_addr: SocketAddr, implicated_cid: u64) -> bool {
         let mut this = inner_mut!(self);
-        if let Some((_, stopper, session)) = this.provisional_connections.remove(&socket_addr) {
+        if let Some((stopper, session)) = this.provisional_connections.remove(&socket_addr) {
             //let _ = this.hypernode_peer_layer.register_peer(implicated_cid, true);
             if let Some(lingering_conn) = this.sessions.insert(implicated_cid, (stopper, session)) {
                 // sometimes (especially on cellular networks), when the network changes due to
@@ -730,6 +1048,20 @@ impl HdpSessionManager {
         this.clear_session(cid);
     }
 
+    /// Clears the session of `cid` from the internal map, unless `session` was since replaced,
+    /// such as when evicted to make room for a newer session
+    fn clear_session_if_current(&self, cid: u64, session: &HdpSession) {
+        let mut this = inner_mut!(self);
+        let is_current = this
+            .sessions
+            .get(&cid)
+            .map(|(_, current)| current.ptr_eq(session))
+            .unwrap_or(true);
+        if is_current {
+            this.clear_session(cid);
+        }
+    }
+
     /// When the registration process completes, and before sending the kernel a message, this should be called on BOTH ends
     pub fn clear_provisional_session(&self, addr: &SocketAddr) {
         //log::trace!(target: "citadel", "Attempting to clear provisional session ...");
@@ -742,6 +1074,17 @@ impl HdpSessionManager {
         }
     }
 
+    /// Returns the counters describing the handshakes that have not yet completed. Stale
+    /// handshakes are reaped beforehand
+    pub fn handshake_metrics(&self) -> HandshakeMetrics {
+        let mut this = inner_mut!(self);
+        for (stopper, _) in this.provisional_connections.reap() {
+            let _ = stopper.send(());
+        }
+
+        this.provisional_connections.metrics()
+    }
+
     /// Creates a new message group. Returns a key if successful
     pub async fn create_message_group_and_notify(
         &self,
@@ -760,17 +1103,20 @@ impl HdpSessionManager {
         // notify all the peers
         for peer_cid in peers_to_notify {
             let this = inner!(self);
-            if let Err(err) = this.send_signal_to_peer_direct(peer_cid, |peer_hyper_ratchet| {
-                let signal = GroupBroadcast::Invitation(key);
-                super::packet_crafter::peer_cmd::craft_group_message_packet(
-                    peer_hyper_ratchet,
-                    &signal,
-                    ticket,
-                    C2S_ENCRYPTION_ONLY,
-                    timestamp,
-                    security_level,
-                )
-            }) {
+            if let Err(err) =
+                this.send_signal_to_peer_direct(peer_cid, |peer_hyper_ratchet, protocol_version| {
+                    let signal = GroupBroadcast::Invitation(key);
+                    super::packet_crafter::peer_cmd::craft_group_message_packet(
+                        peer_hyper_ratchet,
+                        &signal,
+                        ticket,
+                        C2S_ENCRYPTION_ONLY,
+                        protocol_version,
+                        timestamp,
+                        security_level,
+                    )
+                })
+            {
                 log::warn!(target: "citadel", "Unable to send signal to peer {}: {}", peer_cid, err.to_string());
             }
         }
@@ -793,19 +1139,21 @@ impl HdpSessionManager {
             let this = inner!(self);
             for peer_cid in group.concurrent_peers.keys() {
                 if *peer_cid != cid_host {
-                    if let Err(err) =
-                        this.send_signal_to_peer_direct(*peer_cid, |peer_hyper_ratchet| {
+                    if let Err(err) = this.send_signal_to_peer_direct(
+                        *peer_cid,
+                        |peer_hyper_ratchet, protocol_version| {
                             let signal = GroupBroadcast::Disconnected(key);
                             super::packet_crafter::peer_cmd::craft_group_message_packet(
                                 peer_hyper_ratchet,
                                 &signal,
                                 ticket,
                                 C2S_ENCRYPTION_ONLY,
+                                protocol_version,
                                 timestamp,
                                 security_level,
                             )
-                        })
-                    {
+                        },
+                    ) {
                         log::warn!(target: "citadel", "Unable to send d/c signal to peer {}: {}", peer_cid, err.to_string());
                     }
                 }
@@ -956,6 +1304,7 @@ impl HdpSessionManager {
                             hr,
                             signal,
                             ticket,
+                            sess.protocol_version.get(),
                             timestamp,
                             security_level,
                         );
@@ -971,6 +1320,27 @@ impl HdpSessionManager {
         false
     }
 
+    /// Alerts each online peer in `peers` that the presence of `implicated_cid` changed
+    pub fn notify_presence(
+        &self,
+        implicated_cid: u64,
+        presence: Presence,
+        peers: impl IntoIterator<Item = u64>,
+    ) {
+        let timestamp = inner!(self).time_tracker.get_global_time_ns();
+        for peer_cid in peers {
+            if peer_cid != implicated_cid {
+                let _ = self.send_signal_to_peer(
+                    peer_cid,
+                    Ticket(0),
+                    PeerSignal::PresenceChanged(implicated_cid, presence),
+                    timestamp,
+                    SecurityLevel::Standard,
+                );
+            }
+        }
+    }
+
     /// Ensures the mailbox and tracked event queue are loaded into the [PeerLayer]
     pub async fn register_session_with_peer_layer(
         &self,
@@ -986,7 +1356,7 @@ impl HdpSessionManager {
         &self,
         implicated_cid: u64,
         peer_cid: u64,
-        on_internal_disconnect: impl FnOnce(&StackedRatchet) -> BytesMut,
+        on_internal_disconnect: impl FnOnce(&StackedRatchet, u32) -> BytesMut,
     ) -> Result<(), String> {
         if implicated_cid == peer_cid {
             return Err("Implicated CID cannot equal peer cid".to_string());
@@ -1004,7 +1374,8 @@ impl HdpSessionManager {
                         .active_virtual_connections
                         .remove(&implicated_cid);
                     if removed.is_some() {
-                        let packet = on_internal_disconnect(hr);
+                        let packet =
+                            on_internal_disconnect(hr, state_container.protocol_version.get());
                         to_primary
                             .unbounded_send(packet)
                             .map_err(|err| err.to_string())
@@ -1021,7 +1392,7 @@ impl HdpSessionManager {
     pub fn route_packet_to(
         &self,
         target_cid: u64,
-        packet: impl FnOnce(&StackedRatchet) -> BytesMut,
+        packet: impl FnOnce(&StackedRatchet, u32) -> BytesMut,
     ) -> Result<(), String> {
         let lock = inner!(self);
         let (_, sess_ref) = lock
@@ -1030,9 +1401,9 @@ impl HdpSessionManager {
             .ok_or_else(|| format!("Target cid {target_cid} does not exist (route err)"))?;
         let peer_sender = sess_ref.to_primary_stream.as_ref().unwrap();
         let accessor = EndpointCryptoAccessor::C2S(sess_ref.state_container.clone());
-        accessor.borrow_hr(None, |hr, _| {
+        accessor.borrow_hr(None, |hr, state_container| {
             log::trace!(target: "citadel", "Routing packet through primary stream -> {}", target_cid);
-            let packet = packet(hr);
+            let packet = packet(hr, state_container.protocol_version.get());
             peer_sender.unbounded_send(packet).map_err(|err| err.to_string())
         }).map_err(|err| err.into_string())?
     }
@@ -1047,7 +1418,7 @@ impl HdpSessionManager {
         target_cid: u64,
         ticket: Ticket,
         signal: PeerSignal,
-        packet: impl FnOnce(&StackedRatchet) -> BytesMut,
+        packet: impl FnOnce(&StackedRatchet, u32) -> BytesMut,
         timeout: Duration,
         on_timeout: impl Fn(PeerSignal) + SyncContextRequirements,
     ) -> Result<(), String> {
@@ -1077,9 +1448,9 @@ impl HdpSessionManager {
                 let peer_sender = sess_ref.to_primary_stream.as_ref().unwrap();
                 let accessor = EndpointCryptoAccessor::C2S(sess_ref.state_container.clone());
 
-                accessor.borrow_hr(None, |hr, _| {
+                accessor.borrow_hr(None, |hr, state_container| {
                     log::trace!(target: "citadel", "Routing packet through primary stream ({} -> {})", implicated_cid, target_cid);
-                    let packet = packet(hr);
+                    let packet = packet(hr, state_container.protocol_version.get());
                     peer_sender.unbounded_send(packet).map_err(|err| err.to_string())
                 }).map_err(|err| err.into_string())?
             } else {
@@ -1152,12 +1523,13 @@ impl HdpSessionManager {
             for (peer, is_registered) in peers_and_statuses {
                 if is_registered {
                     if this
-                        .send_signal_to_peer_direct(peer, |peer_hyper_ratchet| {
+                        .send_signal_to_peer_direct(peer, |peer_hyper_ratchet, protocol_version| {
                             super::packet_crafter::peer_cmd::craft_group_message_packet(
                                 peer_hyper_ratchet,
                                 &signal,
                                 ticket,
                                 C2S_ENCRYPTION_ONLY,
+                                protocol_version,
                                 timestamp,
                                 security_level,
                             )
@@ -1207,7 +1579,7 @@ impl HdpSessionManager {
         target_cid: u64,
         ticket: Ticket,
         peer_layer: &mut HyperNodePeerLayerInner,
-        packet: impl FnOnce(&StackedRatchet) -> BytesMut,
+        packet: impl FnOnce(&StackedRatchet, u32) -> BytesMut,
         post_send: impl FnOnce(&HdpSession, PeerSignal) -> Result<PrimaryProcessorResult, NetworkError>,
     ) -> Result<Result<PrimaryProcessorResult, NetworkError>, String> {
         // Instead of checking for registration, check the `implicated_cid`'s timed queue for a ticket corresponding to Ticket.
@@ -1222,8 +1594,8 @@ impl HdpSessionManager {
                 let accessor = EndpointCryptoAccessor::C2S(sess_ref.state_container.clone());
 
                 accessor
-                    .borrow_hr(None, |hr, _| {
-                        let packet = packet(hr);
+                    .borrow_hr(None, |hr, state_container| {
+                        let packet = packet(hr, state_container.protocol_version.get());
                         peer_sender
                             .unbounded_send(packet)
                             .map_err(|err| err.to_string())
@@ -1247,6 +1619,10 @@ impl HdpSessionManager {
 }
 
 impl HdpSessionManagerInner {
+    fn active_session_count(&self, cid: u64) -> usize {
+        usize::from(self.sessions.contains_key(&cid))
+    }
+
     /// Clears a session from the SessionManager
     pub fn clear_session(&mut self, cid: u64) {
         if self.sessions.remove(&cid).is_none() {
@@ -1258,7 +1634,7 @@ impl HdpSessionManagerInner {
     pub fn send_signal_to_peer_direct(
         &self,
         target_cid: u64,
-        packet: impl FnOnce(&StackedRatchet) -> BytesMut,
+        packet: impl FnOnce(&StackedRatchet, u32) -> BytesMut,
     ) -> Result<(), NetworkError> {
         if let Some(peer_sess) = self.sessions.get(&target_cid) {
             let peer_sess = &peer_sess.1;
@@ -1268,8 +1644,8 @@ impl HdpSessionManagerInner {
                 .ok_or(NetworkError::InternalError("Peer stream absent"))?;
             let accessor = EndpointCryptoAccessor::C2S(peer_sess.state_container.clone());
 
-            accessor.borrow_hr(None, |hr, _| {
-                let packet = packet(hr);
+            accessor.borrow_hr(None, |hr, state_container| {
+                let packet = packet(hr, state_container.protocol_version.get());
                 peer_sender
                     .unbounded_send(packet)
                     .map_err(|err| NetworkError::msg(err.to_string()))
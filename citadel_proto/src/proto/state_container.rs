@@ -2,6 +2,7 @@ use std::collections::{HashMap, VecDeque};
 use std::fmt::{Debug, Display, Formatter};
 use std::ops::RangeInclusive;
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::proto::packet_processor::primary_group_packet::{
     attempt_kem_as_alice_finish, get_resp_target_cid_from_header,
@@ -15,17 +16,20 @@ use zerocopy::LayoutVerified;
 use citadel_crypt::scramble::crypt_splitter::{
     GroupReceiver, GroupReceiverConfig, GroupReceiverStatus,
 };
+use citadel_user::account_features::FeatureSet;
 use citadel_user::client_account::ClientNetworkAccount;
 use netbeam::time_tracker::TimeTracker;
 
 use crate::constants::{
-    GROUP_EXPIRE_TIME_MS, GROUP_TIMEOUT_MS, INDIVIDUAL_WAVE_TIMEOUT_MS, KEEP_ALIVE_INTERVAL_MS,
+    GROUP_EXPIRE_TIME_MS, GROUP_TIMEOUT_MS, INDIVIDUAL_WAVE_TIMEOUT_MS,
     MAX_OUTGOING_UNPROCESSED_REQUESTS,
 };
 use crate::error::NetworkError;
 use crate::functional::IfEqConditional;
 use crate::prelude::{InternalServerError, MessageGroupKey, ReKeyResult, ReKeyReturnType};
+use crate::proto::misc::dual_cell::DualCell;
 use crate::proto::misc::dual_late_init::DualLateInit;
+use crate::proto::misc::keep_alive_policy::{KeepAliveBackoff, KeepAlivePolicy};
 use crate::proto::misc::ordered_channel::OrderedChannel;
 use crate::proto::misc::session_security_settings::SessionSecuritySettings;
 use crate::proto::node::SecrecyMode;
@@ -53,12 +57,12 @@ use crate::proto::state_subcontainers::meta_expiry_container::MetaExpiryState;
 use crate::proto::state_subcontainers::peer_kem_state_container::PeerKemStateContainer;
 use crate::proto::state_subcontainers::preconnect_state_container::PreConnectState;
 use crate::proto::state_subcontainers::register_state_container::RegisterState;
-use crate::proto::state_subcontainers::rekey_container::RatchetUpdateState;
+use crate::proto::state_subcontainers::rekey_container::{RatchetUpdateState, ReKeyStage};
 use crate::proto::transfer_stats::TransferStats;
 use crate::proto::{packet_crafter, send_with_error_logging};
 use atomic::Atomic;
 use bytes::Bytes;
-use citadel_crypt::endpoint_crypto_container::{KemTransferStatus, PeerSessionCrypto};
+use citadel_crypt::endpoint_crypto_container::{KemTransferStatus, PeerSessionCrypto, VersionPin};
 use citadel_crypt::entropy_bank::SecurityLevel;
 use citadel_crypt::prelude::SecBuffer;
 use citadel_crypt::stacked_ratchet::{Ratchet, StackedRatchet};
@@ -112,6 +116,7 @@ pub struct StateContainerInner {
     pub(super) active_virtual_connections: HashMap<u64, VirtualConnection>,
     pub(super) c2s_channel_container: Option<C2SChannelContainer>,
     pub(crate) keep_alive_timeout_ns: i64,
+    pub(crate) keep_alive_backoff: KeepAliveBackoff,
     pub(crate) state: Arc<Atomic<SessionState>>,
     // whenever a c2s or p2p channel is loaded, this is fired to signal any UDP loaders that it is safe to store the UDP conn in the corresponding v_conn
     pub(super) tcp_loaded_status: Option<tokio::sync::oneshot::Sender<()>>,
@@ -123,6 +128,7 @@ pub struct StateContainerInner {
     pub(super) group_channels: HashMap<MessageGroupKey, UnboundedSender<GroupBroadcastPayload>>,
     pub(super) transfer_stats: TransferStats,
     pub(super) udp_mode: UdpMode,
+    pub(super) protocol_version: DualCell<u32>,
     is_server: bool,
 }
 
@@ -155,6 +161,8 @@ pub(crate) struct InboundFileTransfer {
     pub stream_to_hd: UnboundedSender<Vec<u8>>,
     pub reception_complete_tx: tokio::sync::oneshot::Sender<HdpHeader>,
     pub local_encryption_level: Option<SecurityLevel>,
+    // keeps the ratchet version used by the transfer from being truncated mid-transfer
+    pub version_pin: Option<VersionPin>,
 }
 
 #[allow(dead_code)]
@@ -167,6 +175,10 @@ pub(crate) struct OutboundFileTransfer {
     pub start: Option<tokio::sync::oneshot::Sender<bool>>,
     // This sends a shutdown signal to the async cryptscambler
     pub stop_tx: Option<tokio::sync::oneshot::Sender<()>>,
+    // shared with the sender's ObjectTransferHandler
+    pub stats: ObjectTransferStatsTracker,
+    // keeps the ratchet version used by the transfer from being truncated mid-transfer
+    pub version_pin: VersionPin,
 }
 
 impl GroupKey {
@@ -490,12 +502,15 @@ impl StateContainerInner {
         is_server: bool,
         transfer_stats: TransferStats,
         udp_mode: UdpMode,
+        protocol_version: DualCell<u32>,
+        keep_alive_policy: KeepAlivePolicy,
     ) -> StateContainer {
         let inner = Self {
             outgoing_peer_connect_attempts: Default::default(),
             file_transfer_handles: HashMap::new(),
             group_channels: Default::default(),
             udp_mode,
+            protocol_version,
             transfer_stats,
             queue_handle: Default::default(),
             is_server,
@@ -509,6 +524,7 @@ impl StateContainerInner {
             state,
             c2s_channel_container: None,
             keep_alive_timeout_ns,
+            keep_alive_backoff: KeepAliveBackoff::new(keep_alive_policy),
             hdp_server_remote,
             meta_expiry_state: Default::default(),
             pre_connect_state: Default::default(),
@@ -726,12 +742,13 @@ impl StateContainerInner {
         channel_ticket: Ticket,
         target_cid: u64,
         connection_type: VirtualConnectionType,
-        endpoint_crypto: PeerSessionCrypto,
+        mut endpoint_crypto: PeerSessionCrypto,
         sess: &HdpSession,
     ) -> PeerChannel {
         let (channel_tx, channel_rx) = unbounded();
         let (tx, rx) = crate::proto::outbound_sender::channel(MAX_OUTGOING_UNPROCESSED_REQUESTS);
         let is_active = Arc::new(AtomicBool::new(true));
+        endpoint_crypto.truncation_grace = self.drill_truncation_grace();
 
         self.updates_in_progress
             .insert(target_cid, endpoint_crypto.update_in_progress.clone());
@@ -798,13 +815,16 @@ impl StateContainerInner {
         );
         HdpSession::spawn_message_sender_function(session.clone(), rx);
 
+        let mut peer_session_crypto = cnac.read().crypt_container.new_session();
+        peer_session_crypto.truncation_grace = self.drill_truncation_grace();
+
         let c2s = C2SChannelContainer {
             to_channel: OrderedChannel::new(channel_tx),
             to_unordered_channel: None,
             is_active,
             to_primary_stream: session.to_primary_stream.clone().unwrap(),
             channel_signal: None,
-            peer_session_crypto: cnac.read().crypt_container.new_session(),
+            peer_session_crypto,
         };
 
         let updates_in_progress = c2s.peer_session_crypto.update_in_progress.clone();
@@ -820,6 +840,84 @@ impl StateContainerInner {
         peer_channel
     }
 
+    /// Returns the capabilities granted to the client of this session. Only the server enforces
+    /// these
+    pub fn account_features(&self) -> FeatureSet {
+        self.cnac
+            .as_ref()
+            .map(|cnac| cnac.read().account_features.clone())
+            .unwrap_or_default()
+    }
+
+    fn drill_truncation_grace(&self) -> Duration {
+        self.hdp_server_remote
+            .account_manager()
+            .get_misc_settings()
+            .drill_truncation_grace
+    }
+
+    /// Performs any drill truncations that were deferred while in-flight transfers referenced
+    /// the truncated versions
+    pub fn process_deferred_truncations(&mut self) {
+        if let Some(c2s) = self.c2s_channel_container.as_mut() {
+            c2s.peer_session_crypto.process_deferred_truncations();
+        }
+
+        for endpoint_container in self
+            .active_virtual_connections
+            .values_mut()
+            .filter_map(|vconn| vconn.endpoint_container.as_mut())
+        {
+            endpoint_container
+                .endpoint_crypto
+                .process_deferred_truncations();
+        }
+    }
+
+    /// Cancels each re-key that has waited on a single stage for at least `timeout`. A version
+    /// committed but not yet used is rolled back, such that the channel continues on the prior
+    /// version
+    pub(crate) fn cancel_stalled_rekeys(&mut self, timeout: Duration) {
+        for (peer_cid, stage) in self
+            .ratchet_update_state
+            .take_stalled(Instant::now(), timeout)
+        {
+            let crypt = if peer_cid == C2S_ENCRYPTION_ONLY {
+                self.ratchet_update_state.alice_hyper_ratchet = None;
+                self.c2s_channel_container
+                    .as_mut()
+                    .map(|c2s| &mut c2s.peer_session_crypto)
+            } else {
+                let _ = self.ratchet_update_state.p2p_updates.remove(&peer_cid);
+                self.active_virtual_connections
+                    .get_mut(&peer_cid)
+                    .and_then(|vconn| vconn.endpoint_container.as_mut())
+                    .map(|endpoint_container| &mut endpoint_container.endpoint_crypto)
+            };
+
+            let crypt = match crypt {
+                Some(crypt) => crypt,
+                None => continue,
+            };
+
+            if let Err(err) = crypt.cancel_update() {
+                log::error!(target: "citadel", "Unable to roll back stalled re-key with {}: {:?}", peer_cid, err);
+            }
+
+            let version = crypt.latest_usable_version;
+            log::warn!(target: "citadel", "Re-key with {} stalled at {:?}. Continuing on version {}", peer_cid, stage, version);
+            self.ratchet_update_state
+                .on_timed_out(peer_cid, stage, version);
+
+            if let Err(err) = self
+                .ratchet_update_state
+                .on_cancelled(peer_cid, &self.kernel_tx)
+            {
+                log::warn!(target: "citadel", "Unable to alert kernel of stalled re-key: {:?}", err);
+            }
+        }
+    }
+
     pub fn setup_tcp_alert_if_udp_c2s(&mut self) -> tokio::sync::oneshot::Receiver<()> {
         let (tx, rx) = tokio::sync::oneshot::channel();
         self.tcp_loaded_status = Some(tx);
@@ -895,6 +993,8 @@ impl StateContainerInner {
         inbound_packet_timestamp_ns: i64,
         mut current_timestamp_ns: i64,
     ) -> bool {
+        self.process_deferred_truncations();
+
         if self.keep_alive_timeout_ns == 0 {
             return true;
         }
@@ -920,11 +1020,11 @@ impl StateContainerInner {
                 self.network_stats
                     .last_keep_alive
                     .replace(current_timestamp_ns);
-                // We subtract two keep alive intervals, since it pauses that long on each end. We multiply by 1 million to convert ms to ns
-                const PROCESS_TIME_NS: i64 = 2 * KEEP_ALIVE_INTERVAL_MS as i64 * 1_000_000;
+                // We subtract two keep alive intervals, since it pauses that long on each end
+                let process_time_ns = 2 * self.keep_alive_backoff.interval().as_nanos() as i64;
                 self.network_stats
                     .rtt_ns
-                    .replace(current_timestamp_ns - last_ka - PROCESS_TIME_NS);
+                    .replace(current_timestamp_ns - last_ka - process_time_ns);
                 true
             }
         } else {
@@ -1023,6 +1123,13 @@ impl StateContainerInner {
         let ticket = header.context_info.get().into();
         let is_revfs_pull = local_encryption_level.is_some();
 
+        let version_pin = if target_cid == 0 {
+            self.get_c2s_crypto()
+        } else {
+            self.get_peer_session_crypto(target_cid)
+        }
+        .map(|crypt| crypt.pin_version(hyper_ratchet.version()));
+
         if let std::collections::hash_map::Entry::Vacant(e) = self.inbound_files.entry(key) {
             let (stream_to_hd, stream_to_hd_rx) = unbounded::<Vec<u8>>();
             let (start_recv_tx, start_recv_rx) = tokio::sync::oneshot::channel::<bool>();
@@ -1033,6 +1140,7 @@ impl StateContainerInner {
             let pers = pers.clone();
             let metadata = metadata_orig.clone();
             let tt = self.time_tracker;
+            let protocol_version = self.protocol_version.get();
             let (reception_complete_tx, success_receiving_rx) = tokio::sync::oneshot::channel();
             let entry = InboundFileTransfer {
                 last_group_finish_time: Instant::now(),
@@ -1046,6 +1154,7 @@ impl StateContainerInner {
                 reception_complete_tx,
                 stream_to_hd,
                 local_encryption_level,
+                version_pin,
             };
 
             e.insert(entry);
@@ -1089,6 +1198,7 @@ impl StateContainerInner {
                     ticket,
                     security_level_rebound,
                     v_target_flipped,
+                    protocol_version,
                     timestamp,
                 );
 
@@ -1121,6 +1231,7 @@ impl StateContainerInner {
                                                 get_resp_target_cid_from_header(&header),
                                                 header.group.get(),
                                                 header.wave_id.get(),
+                                                protocol_version,
                                                 tt.get_global_time_ns(),
                                                 None,
                                                 header.security_level.into(),
@@ -1164,6 +1275,7 @@ impl StateContainerInner {
                             ticket,
                             security_level_rebound,
                             virtual_target,
+                            protocol_version,
                             timestamp,
                         );
                         let _ = preferred_primary_stream.unbounded_send(err_packet);
@@ -1216,6 +1328,7 @@ impl StateContainerInner {
                     true, //this value does not matter here since start_recv_tx is false. TODO: refactor
                 );
                 tx.send(ObjectTransferStatus::TransferBeginning).ok()?;
+                file_transfer.stats = handle.stats_tracker();
                 let _ = self
                     .file_transfer_handles
                     .insert(key, crate::proto::outbound_sender::UnboundedSender(tx));
@@ -1436,6 +1549,8 @@ impl StateContainerInner {
             log::trace!(target: "citadel", "Finished receiving file {:?}", file_key);
             let _ = self.inbound_files.remove(&file_key);
             let _ = self.file_transfer_handles.remove(&file_key);
+            // the transfer no longer pins its version
+            self.process_deferred_truncations();
         }
 
         if send_wave_ack {
@@ -1447,6 +1562,7 @@ impl StateContainerInner {
                     get_resp_target_cid_from_header(header),
                     header.group.get(),
                     header.wave_id.get(),
+                    self.protocol_version.get(),
                     ts,
                     None,
                     header.security_level.into(),
@@ -1501,6 +1617,13 @@ impl StateContainerInner {
 
                 let file_key = FileKey::new(target_cid, object_id as u32);
 
+                if let Some(outbound_file) = self.outbound_files.get(&file_key) {
+                    outbound_file.stats.on_group_acknowledged(
+                        transmitter_container.group_plaintext_length,
+                        transmitter_container.transmission_start_time.elapsed(),
+                    );
+                }
+
                 if let Some(tx) = self.file_transfer_handles.get(&file_key) {
                     let status = if relative_group_id as usize
                         != transmitter_container.parent_object_total_groups - 1
@@ -1675,6 +1798,7 @@ impl StateContainerInner {
                 .ok_or(NetworkError::InternalError("Secrecy mode not loaded"))?;
 
             let time_tracker = this.time_tracker;
+            let protocol_version = this.protocol_version.get();
 
             if secrecy_mode == SecrecyMode::Perfect && !called_from_poll {
                 //let mut enqueued = inner_mut!(this.enqueued_packets);
@@ -1756,6 +1880,7 @@ impl StateContainerInner {
                                     security_level,
                                     group_id,
                                     ticket,
+                                    protocol_version,
                                     time_tracker,
                                 )
                                 .ok_or({
@@ -1828,6 +1953,7 @@ impl StateContainerInner {
                                             security_level,
                                             group_id,
                                             ticket,
+                                            protocol_version,
                                             time_tracker,
                                         )
                                         .ok_or({
@@ -1859,6 +1985,7 @@ impl StateContainerInner {
                                                 security_level,
                                                 group_id,
                                                 ticket,
+                                                protocol_version,
                                                 time_tracker,
                                             )
                                             .ok_or(
@@ -2006,11 +2133,14 @@ impl StateContainerInner {
                             alice_constructor
                                 .stage0_alice()
                                 .ok_or(NetworkError::InternalError("Alice Construction failed"))?,
+                            self.protocol_version.get(),
                             timestamp,
                             C2S_ENCRYPTION_ONLY,
                             security_level,
                         );
                         self.ratchet_update_state.alice_hyper_ratchet = Some(alice_constructor);
+                        self.ratchet_update_state
+                            .on_stage(C2S_ENCRYPTION_ONLY, ReKeyStage::AwaitingStage1);
                         if let Some(ticket) = ticket {
                             // this request requires tracking
                             let _ = self
@@ -2067,6 +2197,7 @@ impl StateContainerInner {
                                 alice_constructor.stage0_alice().ok_or(
                                     NetworkError::InternalError("Alice constructor (2) failed"),
                                 )?,
+                                self.protocol_version.get(),
                                 timestamp,
                                 peer_cid,
                                 security_level,
@@ -2085,6 +2216,9 @@ impl StateContainerInner {
                             log::error!(target: "citadel", "Overwrote pre-existing peer kem. Report to developers");
                         }
 
+                        self.ratchet_update_state
+                            .on_stage(peer_cid, ReKeyStage::AwaitingStage1);
+
                         if let Some(ticket) = ticket {
                             // this request requires tracking
                             let _ = self
@@ -2148,6 +2282,7 @@ impl StateContainerInner {
                 command,
                 ticket,
                 C2S_ENCRYPTION_ONLY,
+                self.protocol_version.get(),
                 timestamp,
                 security_level,
             ),
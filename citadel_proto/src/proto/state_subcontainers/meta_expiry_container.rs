@@ -13,6 +13,10 @@ impl MetaExpiryState {
     pub fn expired(&self) -> bool {
         self.last_valid_event.elapsed() > GROUP_EXPIRE_TIME_MS
     }
+    /// Returns when a packet was last confirmed
+    pub fn last_valid_event(&self) -> Instant {
+        self.last_valid_event
+    }
     /// Whenever a packet is confirmed, call this
     pub fn on_event_confirmation(&mut self) {
         self.last_valid_event = Instant::now()
@@ -18,6 +18,9 @@ pub struct PreConnectState {
     pub(crate) udp_channel_oneshot_tx: UdpChannelSender,
     pub(crate) success: bool,
     pub(crate) generated_ratchet: Option<StackedRatchet>,
+    // The most recent ratchet of the previous session, reused if the server accepts our resumption ticket
+    pub(crate) resumption_ratchet: Option<StackedRatchet>,
+    pub(crate) session_resumed: bool,
 }
 
 impl PreConnectState {
@@ -30,6 +33,8 @@ impl Default for PreConnectState {
     fn default() -> Self {
         Self {
             generated_ratchet: None,
+            resumption_ratchet: None,
+            session_resumed: false,
             udp_channel_oneshot_tx: UdpChannelSender::empty(),
             constructor: None,
             last_packet_time: None,
@@ -1,4 +1,4 @@
-use tokio::time::Duration;
+use tokio::time::{Duration, Instant};
 
 use crate::constants::{
     DRILL_UPDATE_FREQUENCY_DIVINE_BASE, DRILL_UPDATE_FREQUENCY_HIGH_BASE,
@@ -8,8 +8,10 @@ use crate::constants::{
 use crate::error::NetworkError;
 use crate::prelude::{NodeResult, ReKeyResult, ReKeyReturnType, Ticket, VirtualTargetType};
 use crate::proto::outbound_sender::UnboundedSender;
+use crate::proto::packet_crafter::peer_cmd::C2S_ENCRYPTION_ONLY;
 use crate::proto::transfer_stats::TransferStats;
 use citadel_crypt::stacked_ratchet::constructor::StackedRatchetConstructor;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 #[derive(Default)]
@@ -19,9 +21,137 @@ pub struct RatchetUpdateState {
     // if this is present (in the case of manual mode), an alert will be sent
     // to the kernel once the re-key has finished
     pub current_local_requests: HashMap<VirtualTargetType, Ticket>,
+    // keyed by the peer cid, or C2S_ENCRYPTION_ONLY for the c2s channel
+    in_progress: HashMap<u64, InProgressReKey>,
+    last_outcomes: HashMap<u64, PendingReKeyStatus>,
+}
+
+struct InProgressReKey {
+    stage: ReKeyStage,
+    since: Instant,
+}
+
+/// The message a re-key in progress is waiting on
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ReKeyStage {
+    /// The local node sent STAGE0, and awaits STAGE1
+    AwaitingStage1,
+    /// The local node committed the next version, and awaits TRUNCATE before using it
+    AwaitingTruncate,
+    /// The local node sent TRUNCATE, and awaits TRUNCATE_ACK before unlocking
+    AwaitingTruncateAck,
+}
+
+/// The state of the re-keying process between the local node and a peer
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PendingReKeyStatus {
+    /// No re-key has run since the channel was established
+    Idle,
+    InProgress {
+        stage: ReKeyStage,
+        elapsed: Duration,
+    },
+    /// The last re-key completed, and the channel now uses `version`
+    Completed { version: u32 },
+    /// The last re-key stalled at `stage` and was cancelled. The channel continues on `version`
+    TimedOut { stage: ReKeyStage, version: u32 },
 }
 
 impl RatchetUpdateState {
+    /// Marks the re-key with `peer_cid` as waiting on `stage`
+    pub(crate) fn on_stage(&mut self, peer_cid: u64, stage: ReKeyStage) {
+        let _ = self.in_progress.insert(
+            peer_cid,
+            InProgressReKey {
+                stage,
+                since: Instant::now(),
+            },
+        );
+    }
+
+    pub(crate) fn on_finished(&mut self, peer_cid: u64, version: u32) {
+        let _ = self.in_progress.remove(&peer_cid);
+        let _ = self
+            .last_outcomes
+            .insert(peer_cid, PendingReKeyStatus::Completed { version });
+    }
+
+    pub(crate) fn on_timed_out(&mut self, peer_cid: u64, stage: ReKeyStage, version: u32) {
+        let _ = self
+            .last_outcomes
+            .insert(peer_cid, PendingReKeyStatus::TimedOut { stage, version });
+    }
+
+    /// Alerts the kernel, if tracking was requested, that the re-key with `peer_cid` failed
+    pub(crate) fn on_cancelled(
+        &mut self,
+        peer_cid: u64,
+        to_kernel_tx: &UnboundedSender<NodeResult>,
+    ) -> Result<(), NetworkError> {
+        let v_conn_type = self
+            .current_local_requests
+            .keys()
+            .copied()
+            .find(|v_conn_type| match v_conn_type {
+                VirtualTargetType::LocalGroupServer(_) => peer_cid == C2S_ENCRYPTION_ONLY,
+                VirtualTargetType::LocalGroupPeer(_, target_cid) => *target_cid == peer_cid,
+                _ => false,
+            });
+
+        if let Some(v_conn_type) = v_conn_type {
+            self.on_complete(v_conn_type, to_kernel_tx, ReKeyReturnType::Failure)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Removes and returns each re-key that has waited on its current stage for at least `timeout`
+    pub(crate) fn take_stalled(
+        &mut self,
+        now: Instant,
+        timeout: Duration,
+    ) -> Vec<(u64, ReKeyStage)> {
+        let stalled = self
+            .in_progress
+            .iter()
+            .filter(|(_, rekey)| now.saturating_duration_since(rekey.since) >= timeout)
+            .map(|(peer_cid, rekey)| (*peer_cid, rekey.stage))
+            .collect::<Vec<_>>();
+
+        for (peer_cid, _) in &stalled {
+            let _ = self.in_progress.remove(peer_cid);
+        }
+
+        stalled
+    }
+
+    /// Returns true if the local node rolled back the version committed by the last re-key with
+    /// `peer_cid` before the TRUNCATE packet arrived
+    pub(crate) fn was_rolled_back(&self, peer_cid: u64) -> bool {
+        !self.in_progress.contains_key(&peer_cid)
+            && matches!(
+                self.last_outcomes.get(&peer_cid),
+                Some(PendingReKeyStatus::TimedOut {
+                    stage: ReKeyStage::AwaitingTruncate,
+                    ..
+                })
+            )
+    }
+
+    pub(crate) fn status(&self, peer_cid: u64) -> PendingReKeyStatus {
+        if let Some(rekey) = self.in_progress.get(&peer_cid) {
+            PendingReKeyStatus::InProgress {
+                stage: rekey.stage,
+                elapsed: rekey.since.elapsed(),
+            }
+        } else {
+            self.last_outcomes
+                .get(&peer_cid)
+                .copied()
+                .unwrap_or(PendingReKeyStatus::Idle)
+        }
+    }
+
     pub(crate) fn on_complete(
         &mut self,
         v_conn_type: VirtualTargetType,
@@ -52,3 +182,56 @@ pub fn calculate_update_frequency(security_level: u8, _transfer_stats: &Transfer
         _ => Duration::from_nanos(DRILL_UPDATE_FREQUENCY_DIVINE_BASE),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::state_subcontainers::rekey_container::{
+        PendingReKeyStatus, RatchetUpdateState, ReKeyStage,
+    };
+    use tokio::time::{Duration, Instant};
+
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    #[test]
+    fn stalled_rekey_reported_as_timed_out() {
+        let mut state = RatchetUpdateState::default();
+        assert_eq!(state.status(0), PendingReKeyStatus::Idle);
+
+        state.on_stage(0, ReKeyStage::AwaitingStage1);
+        state.on_stage(0, ReKeyStage::AwaitingTruncate);
+        assert!(matches!(
+            state.status(0),
+            PendingReKeyStatus::InProgress {
+                stage: ReKeyStage::AwaitingTruncate,
+                ..
+            }
+        ));
+
+        // not yet stalled
+        assert!(state.take_stalled(Instant::now(), TIMEOUT).is_empty());
+
+        let stalled = state.take_stalled(Instant::now() + TIMEOUT, TIMEOUT);
+        assert_eq!(stalled, vec![(0, ReKeyStage::AwaitingTruncate)]);
+        state.on_timed_out(0, ReKeyStage::AwaitingTruncate, 4);
+        assert_eq!(
+            state.status(0),
+            PendingReKeyStatus::TimedOut {
+                stage: ReKeyStage::AwaitingTruncate,
+                version: 4
+            }
+        );
+        assert!(state.was_rolled_back(0));
+
+        // the next re-key proceeds, and replaces the outcome once finished
+        state.on_stage(0, ReKeyStage::AwaitingTruncate);
+        assert!(!state.was_rolled_back(0));
+        state.on_finished(0, 5);
+        assert_eq!(
+            state.status(0),
+            PendingReKeyStatus::Completed { version: 5 }
+        );
+        assert!(state
+            .take_stalled(Instant::now() + TIMEOUT, TIMEOUT)
+            .is_empty());
+    }
+}
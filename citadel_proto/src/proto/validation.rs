@@ -205,7 +205,9 @@ pub(crate) mod pre_connect {
     use citadel_user::client_account::ClientNetworkAccount;
     use citadel_wire::hypernode_type::NodeType;
 
-    use crate::error::NetworkError;
+    use crate::error::{ConnectError, NetworkError};
+    use crate::proto::misc::protocol_version::{ProtocolVersion, ProtocolVersionRange};
+    use crate::proto::misc::session_resumption;
     use crate::proto::misc::session_security_settings::SessionSecuritySettings;
     use crate::proto::node::ConnectMode;
     use crate::proto::packet::HdpPacket;
@@ -221,21 +223,38 @@ pub(crate) mod pre_connect {
     use citadel_user::serialization::SyncIO;
     use citadel_wire::nat_identification::NatType;
 
+    /// Determines how the session keys were established when validating a SYN
+    pub(crate) enum SynKeyExchange {
+        /// A new ratchet was agreed upon. The transfer must be sent back to the client
+        Full(StaticAuxRatchet, BobToAliceTransfer),
+        /// The client presented a valid resumption ticket, so the latest ratchet is reused until the
+        /// client performs the rekey mandated after resumption.
+        ///
+        /// The reused ratchet already encrypted the packets of the previous session. Its nonces stay
+        /// unique only because the transient counter of each entropy bank is persisted alongside the
+        /// account and resumes where it left off. Should a node lose counter progress, such as by
+        /// restarting before its account was saved, nonces of the previous session may repeat until
+        /// the rekey enforced by [`RESUMED_SESSION_REKEY_DEADLINE`](crate::constants::RESUMED_SESSION_REKEY_DEADLINE)
+        Resumed,
+    }
+
     pub(crate) type SynValidationResult = (
-        StaticAuxRatchet,
-        BobToAliceTransfer,
+        SynKeyExchange,
         SessionSecuritySettings,
         ConnectProtocol,
         UdpMode,
         i64,
         NatType,
         StackedRatchet,
+        ProtocolVersion,
     );
 
     pub(crate) fn validate_syn(
         cnac: &ClientNetworkAccount,
         packet: HdpPacket,
         session_manager: &HdpSessionManager,
+        supported_protocol_versions: &ProtocolVersionRange,
+        issued_resumption_ticket: Option<&[u8]>,
     ) -> Result<SynValidationResult, NetworkError> {
         // TODO: NOTE: This can interrupt any active session's. This should be moved up after checking the connect mode
         let static_auxiliary_ratchet = cnac.refresh_static_hyper_ratchet();
@@ -249,6 +268,14 @@ pub(crate) mod pre_connect {
         let transfer = SynPacket::deserialize_from_vector(&payload)
             .map_err(|err| NetworkError::Generic(err.into_string()))?;
 
+        // negotiate the version before altering the toolset, since no session can be established otherwise
+        let protocol_version = supported_protocol_versions
+            .negotiate(&transfer.protocol_versions)
+            .ok_or(ConnectError::IncompatibleProtocolVersions(
+                *supported_protocol_versions,
+                transfer.protocol_versions,
+            ))?;
+
         // TODO: Consider adding connect_mode to the HdpSession to sync between both nodes. For now, there's no need
         match transfer.connect_mode {
             ConnectMode::Fetch { force_login: false }
@@ -267,6 +294,44 @@ pub(crate) mod pre_connect {
         let nat_type = transfer.nat_type;
         let udp_mode = transfer.udp_mode;
         let kat = transfer.keep_alive_timeout;
+
+        if let Some(resumption) = transfer.resumption {
+            // the issued ticket is only consumed if this returns SynKeyExchange::Resumed. If it's invalid, or
+            // if the ratchet versions diverged, fall back to the full key agreement below
+            let cid = header.session_cid.get();
+            if udp_mode == UdpMode::Enabled {
+                // resumption skips the NAT traversal the UDP channel depends on
+                log::warn!(target: "citadel", "Rejecting resumption ticket for {}, since session resumption does not support UDP", cid);
+            } else if session_resumption::redeem(issued_resumption_ticket, &resumption.ticket) {
+                let latest_ratchet = cnac
+                    .read()
+                    .crypt_container
+                    .toolset
+                    .get_most_recent_hyper_ratchet()
+                    .cloned();
+                if let Some(latest_ratchet) = latest_ratchet.filter(|ratchet| {
+                    ratchet.version() == resumption.ratchet_version
+                        && ratchet
+                            .verify_level(Some(session_security_settings.security_level))
+                            .is_ok()
+                }) {
+                    log::trace!(target: "citadel", "Resuming session for {} with ratchet v{}", cid, latest_ratchet.version());
+                    return Ok((
+                        SynKeyExchange::Resumed,
+                        session_security_settings,
+                        peer_only_connect_mode,
+                        udp_mode,
+                        kat,
+                        nat_type,
+                        latest_ratchet,
+                        protocol_version,
+                    ));
+                }
+            }
+
+            log::trace!(target: "citadel", "Resumption ticket for {} rejected. Falling back to a full key agreement", cid);
+        }
+
         let _ = static_auxiliary_ratchet
             .verify_level(Some(transfer.session_security_settings.security_level))
             .map_err(|err| NetworkError::Generic(err.into_string()))?;
@@ -299,14 +364,14 @@ pub(crate) mod pre_connect {
 
         cnac.replace_toolset(toolset);
         Ok((
-            static_auxiliary_ratchet,
-            transfer,
+            SynKeyExchange::Full(static_auxiliary_ratchet, transfer),
             session_security_settings,
             peer_only_connect_mode,
             udp_mode,
             kat,
             nat_type,
             new_hyper_ratchet,
+            protocol_version,
         ))
     }
 
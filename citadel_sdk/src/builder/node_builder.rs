@@ -23,6 +23,11 @@ pub struct NodeBuilder {
     client_tls_config: Option<RustlsClientConfig>,
     kernel_executor_settings: Option<KernelExecutorSettings>,
     stun_servers: Option<Vec<String>>,
+    header_obfuscation: Option<bool>,
+    peer_identity_settings: PeerIdentitySettings,
+    supported_protocol_versions: Option<ProtocolVersionRange>,
+    fault_injector: Option<FaultInjector>,
+    keep_alive_policy: Option<KeepAlivePolicy>,
 }
 
 /// An awaitable future whose return value propagates any internal protocol or kernel-level errors
@@ -75,6 +80,12 @@ impl NodeBuilder {
         let client_config = self.client_tls_config.take().map(Arc::new);
         let kernel_executor_settings = self.kernel_executor_settings.take().unwrap_or_default();
         let stun_servers = self.stun_servers.take();
+        let header_obfuscation = self.header_obfuscation.take().unwrap_or_default();
+        let peer_identity_settings = std::mem::take(&mut self.peer_identity_settings);
+        let supported_protocol_versions =
+            self.supported_protocol_versions.take().unwrap_or_default();
+        let fault_injector = self.fault_injector.take();
+        let keep_alive_policy = self.keep_alive_policy.take().unwrap_or_default();
 
         let underlying_proto = if let Some(proto) = self.underlying_protocol.take() {
             proto
@@ -108,6 +119,11 @@ impl NodeBuilder {
                     client_config,
                     kernel_executor_settings,
                     stun_servers,
+                    header_obfuscation,
+                    peer_identity_settings,
+                    supported_protocol_versions,
+                    fault_injector,
+                    keep_alive_policy,
                 };
 
                 log::trace!(target: "citadel", "[NodeBuilder] Creating KernelExecutor ...");
@@ -251,6 +267,73 @@ impl NodeBuilder {
         self
     }
 
+    /// Obfuscates the headers of packets sent over the primary stream, making the traffic harder to classify
+    /// by middleboxes. Both the client and the server must enable this for a connection to succeed.
+    /// Default: false
+    pub fn with_header_obfuscation(&mut self, enabled: bool) -> &mut Self {
+        self.header_obfuscation = Some(enabled);
+        self
+    }
+
+    /// Sets the identity this node proves to peers when establishing P2P channels. By default, each
+    /// local account uses an identity generated on first use and persisted to the backend
+    pub fn with_peer_identity(&mut self, identity: PeerIdentity) -> &mut Self {
+        self.peer_identity_settings.identity = Some(identity);
+        self
+    }
+
+    /// Pins the identity of `peer_cid`. Establishing a P2P channel with `peer_cid` fails with
+    /// [`ConnectError::FingerprintMismatch`] if the identity it proves does not match `fingerprint`.
+    /// Since the peer's identity signs the key exchange, this prevents a compromised server from
+    /// intercepting the channel
+    pub fn with_pinned_peer_fingerprint(
+        &mut self,
+        peer_cid: u64,
+        fingerprint: PeerFingerprint,
+    ) -> &mut Self {
+        let _ = self
+            .peer_identity_settings
+            .pinned_fingerprints
+            .insert(peer_cid, fingerprint);
+        self
+    }
+
+    /// Sets the protocol versions this node is able to speak. When connecting, the highest version
+    /// supported by both the client and the server is used. If the ranges do not overlap, the
+    /// connection fails with [`ConnectError::IncompatibleProtocolVersions`].
+    /// Default: every patch release of the current minor version
+    pub fn with_supported_protocol_versions(
+        &mut self,
+        versions: ProtocolVersionRange,
+    ) -> &mut Self {
+        self.supported_protocol_versions = Some(versions);
+        self
+    }
+
+    /// Sets how often keep alives are sent over each session. With `backoff_on_idle`, the interval
+    /// doubles whenever a session stays idle, up to `max_interval`, and resets once traffic
+    /// resumes. Default: a fixed interval of 15 minutes
+    pub fn with_keep_alive_policy(&mut self, policy: KeepAlivePolicy) -> &mut Self {
+        self.keep_alive_policy = Some(policy);
+        self
+    }
+
+    /// Degrades every packet this node sends over the primary stream of its sessions by the given
+    /// profile, allowing the behavior of an application under latency, loss, reordering and
+    /// corruption to be tested locally. Only available with the `localhost-testing` feature
+    #[cfg(feature = "localhost-testing")]
+    pub fn with_fault_injection(&mut self, profile: FaultProfile) -> &mut Self {
+        self.fault_injector = Some(FaultInjector::new(profile));
+        self
+    }
+
+    /// Returns a handle to the injector set by [`Self::with_fault_injection`], whose
+    /// [`FaultInjector::stats`] reflect the faults injected once the node is running
+    #[cfg(feature = "localhost-testing")]
+    pub fn fault_injector(&self) -> Option<FaultInjector> {
+        self.fault_injector.clone()
+    }
+
     fn check(&self) -> anyhow::Result<()> {
         #[cfg(feature = "google-services")]
         if let Some(svc) = self.services.as_ref() {
@@ -269,6 +352,31 @@ impl NodeBuilder {
             }
         }
 
+        if let Some(keep_alive_policy) = self.keep_alive_policy.as_ref() {
+            keep_alive_policy
+                .validate()
+                .map_err(|err| anyhow::Error::msg(err.into_string()))?;
+        }
+
+        if let Some(fault_injector) = self.fault_injector.as_ref() {
+            let profile = fault_injector.profile();
+            let rates = [
+                profile.loss_rate,
+                profile.reorder_rate,
+                profile.corruption_rate,
+            ];
+            if rates.iter().any(|rate| !(0.0..=1.0).contains(rate)) {
+                return Err(anyhow::Error::msg(
+                    "Fault injection loss, reorder and corruption rates must be within [0, 1]",
+                ));
+            }
+        }
+
+        if let Some(misc_settings) = self.server_misc_settings.as_ref() {
+            SessionLimit::validate(misc_settings.max_sessions_per_cid)
+                .map_err(anyhow::Error::msg)?;
+        }
+
         Ok(())
     }
 }
@@ -278,9 +386,11 @@ mod tests {
     use crate::builder::node_builder::NodeBuilder;
     use crate::prefabs::server::empty::EmptyKernel;
     use crate::prelude::{BackendType, NodeType};
-    use citadel_proto::prelude::{KernelExecutorSettings, ServerUnderlyingProtocol};
+    use citadel_io::Mutex;
+    use citadel_proto::prelude::*;
     use rstest::rstest;
     use std::str::FromStr;
+    use std::sync::Arc;
 
     #[test]
     #[cfg(feature = "google-services")]
@@ -309,6 +419,29 @@ mod tests {
             .is_err());
     }
 
+    #[test]
+    fn bad_keep_alive_policy() {
+        assert!(NodeBuilder::default()
+            .with_keep_alive_policy(KeepAlivePolicy {
+                base_interval: std::time::Duration::from_secs(60),
+                max_interval: std::time::Duration::from_secs(30),
+                backoff_on_idle: true,
+            })
+            .build(EmptyKernel::default())
+            .is_err());
+    }
+
+    #[test]
+    fn bad_max_sessions_per_cid() {
+        assert!(NodeBuilder::default()
+            .with_server_misc_settings(ServerMiscSettings {
+                max_sessions_per_cid: Some(MAX_SESSIONS_PER_CID + 1),
+                ..Default::default()
+            })
+            .build(EmptyKernel::default())
+            .is_err());
+    }
+
     #[rstest]
     #[tokio::test]
     #[timeout(std::time::Duration::from_secs(60))]
@@ -345,4 +478,95 @@ mod tests {
         #[allow(clippy::let_underscore_must_use)]
         let _ = builder.build(EmptyKernel::default()).unwrap();
     }
+
+    /// Records the effective config of its node, then shuts the node down
+    struct EffectiveConfigKernel {
+        remote: Option<NodeRemote>,
+        config: Arc<Mutex<Option<EffectiveConfig>>>,
+    }
+
+    #[async_trait]
+    impl NetKernel for EffectiveConfigKernel {
+        fn load_remote(&mut self, node_remote: NodeRemote) -> Result<(), NetworkError> {
+            self.remote = Some(node_remote);
+            Ok(())
+        }
+
+        async fn on_start(&self) -> Result<(), NetworkError> {
+            let mut remote = self.remote.clone().unwrap();
+            *self.config.lock() = Some(remote.effective_config());
+            remote.shutdown().await
+        }
+
+        async fn on_node_event_received(&self, _message: NodeResult) -> Result<(), NetworkError> {
+            Ok(())
+        }
+
+        async fn on_stop(&mut self) -> Result<(), NetworkError> {
+            Ok(())
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    #[timeout(std::time::Duration::from_secs(60))]
+    async fn test_effective_config() {
+        citadel_logging::setup_log();
+        let config = Arc::new(Mutex::new(None));
+        let kernel = EffectiveConfigKernel {
+            remote: None,
+            config: config.clone(),
+        };
+
+        let mut builder = NodeBuilder::default();
+        let _ = builder
+            .with_backend(BackendType::InMemory)
+            .with_header_obfuscation(true)
+            .with_kernel_executor_settings(
+                KernelExecutorSettings::default().with_max_concurrency(2),
+            )
+            .with_server_misc_settings(ServerMiscSettings {
+                max_pending_handshakes: 16,
+                ..Default::default()
+            })
+            .with_insecure_skip_cert_verification();
+        let _ = builder.build(kernel).unwrap().await.unwrap();
+
+        let config = config.lock().take().unwrap();
+        // the overrides
+        assert_eq!(config.backend, "memory");
+        assert!(config.header_obfuscation);
+        assert_eq!(config.kernel_max_concurrency, Some(2));
+        assert_eq!(config.server_misc_settings.max_pending_handshakes, 16);
+        assert!(config.custom_client_tls_config);
+
+        // the resolved defaults
+        let defaults = ServerMiscSettings::default();
+        assert_eq!(config.node_type, NodeType::Peer);
+        assert_eq!(config.underlying_protocol, "TLS");
+        assert!(config.self_signed_certificate);
+        assert_eq!(config.stun_servers, None);
+        assert_eq!(
+            config.supported_protocol_versions,
+            ProtocolVersionRange::default()
+        );
+        assert_eq!(
+            config.server_misc_settings.pending_handshake_timeout,
+            defaults.pending_handshake_timeout
+        );
+        assert_eq!(
+            config.server_misc_settings.credential_policy,
+            defaults.credential_policy
+        );
+        assert_eq!(config.keep_alive_policy, KeepAlivePolicy::default());
+        assert!(!config.server_misc_settings.custom_push_provider);
+        assert!(!config.fixed_peer_identity);
+
+        // the snapshot must survive being serialized for a bug report
+        let serialized = config.serialize_to_vector().unwrap();
+        assert_eq!(
+            EffectiveConfig::deserialize_from_vector(&serialized).unwrap(),
+            config
+        );
+    }
 }
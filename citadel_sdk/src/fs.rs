@@ -318,4 +318,68 @@ mod tests {
 
         assert!(client_success.load(Ordering::Relaxed));
     }
+
+    #[rstest]
+    #[timeout(std::time::Duration::from_secs(90))]
+    #[tokio::test]
+    async fn test_revfs_pull_discards_corrupted_headers() {
+        citadel_logging::setup_log();
+        let client_success = &AtomicBool::new(false);
+        let port = crate::test_common::get_unused_tcp_port();
+        let server_addr = SocketAddr::from_str(&format!("127.0.0.1:{port}")).unwrap();
+        // the server precedes the payloads it sends with copies whose headers are corrupted
+        let profile = FaultProfile {
+            corruption_rate: 0.5,
+            ..Default::default()
+        };
+        let mut fault_injector = None;
+        let server = crate::test_common::server_test_node(
+            server_addr,
+            AcceptFileTransferKernel::default(),
+            |builder| {
+                let _ = builder.with_fault_injection(profile);
+                fault_injector = builder.fault_injector();
+            },
+        );
+        let fault_injector = &fault_injector.unwrap();
+        let uuid = Uuid::new_v4();
+
+        let source_dir = PathBuf::from("../resources/TheBridge.pdf");
+
+        let client_kernel = SingleClientServerConnectionKernel::new_passwordless_defaults(
+            uuid,
+            server_addr,
+            |_channel, mut remote| async move {
+                let virtual_path = PathBuf::from("/home/john.doe/TheBridge.pdf");
+                crate::fs::write(&mut remote, source_dir.clone(), &virtual_path).await?;
+                let save_dir = crate::fs::read(&mut remote, &virtual_path).await?;
+                let original_bytes = tokio::fs::read(&source_dir).await.unwrap();
+                let revfs_pulled_bytes = tokio::fs::read(&save_dir).await.unwrap();
+                assert_eq!(original_bytes, revfs_pulled_bytes);
+
+                // every corrupted copy preceded a payload of the pull, so each has been received
+                // and rejected by its header checksum rather than reaching the packet processors
+                let corrupted = fault_injector.stats().corrupted;
+                assert!(corrupted > 0);
+                let cid = remote.user().get_implicated_cid();
+                let dump = remote.remote().dump_session_state(cid).await?;
+                assert_eq!(dump.corrupt_headers_discarded, corrupted as u64);
+
+                client_success.store(true, Ordering::Relaxed);
+                remote.shutdown_kernel().await
+            },
+        )
+        .unwrap();
+
+        let client = NodeBuilder::default().build(client_kernel).unwrap();
+
+        let result = tokio::select! {
+            res0 = client => res0.map(|_| ()),
+            res1 = server => res1.map(|_| ())
+        };
+
+        result.unwrap();
+
+        assert!(client_success.load(Ordering::Relaxed));
+    }
 }
@@ -118,6 +118,7 @@ where
                     MessageGroupOptions {
                         group_type: GroupType::Public,
                         id: group_id.as_u128(),
+                        name: None,
                     },
                 )
             }
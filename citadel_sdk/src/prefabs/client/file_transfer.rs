@@ -0,0 +1,433 @@
+use crate::prefabs::client::peer_connection::{PeerConnectionKernel, Shared};
+use crate::prefabs::client::PrefabFunctions;
+use crate::prefabs::ClientServerRemote;
+use crate::prelude::results::PeerConnectSuccess;
+use crate::prelude::*;
+use crate::remote_ext::map_errors;
+use futures::future::BoxFuture;
+use futures::StreamExt;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc::Receiver;
+
+type PeerHandler<'a> = fn(
+    Receiver<Result<PeerConnectSuccess, NetworkError>>,
+    ClientServerRemote,
+) -> BoxFuture<'a, Result<(), NetworkError>>;
+
+type InnerKernel<'a> =
+    PeerConnectionKernel<'a, PeerHandler<'a>, BoxFuture<'a, Result<(), NetworkError>>>;
+
+/// Sent by the receiver once the file is at its destination, such that the sender does not
+/// disconnect before then
+const RECEPTION_CONFIRMATION: &[u8] = b"FILE_TRANSFER_RECEIVED";
+
+/// Describes the file a [`FileTransferKernel`] sends to, or receives from, a peer
+#[derive(Debug, Clone)]
+pub enum FileTransferRequest {
+    /// Sends the file at `path` to `peer`
+    Send { peer: UserIdentifier, path: PathBuf },
+    /// Accepts the first file sent by `peer`, and writes it to `destination`
+    Receive {
+        peer: UserIdentifier,
+        destination: PathBuf,
+    },
+}
+
+impl FileTransferRequest {
+    fn peer(&self) -> &UserIdentifier {
+        match self {
+            Self::Send { peer, .. } | Self::Receive { peer, .. } => peer,
+        }
+    }
+}
+
+/// A kernel that connects to a peer like the [`PeerConnectionKernel`], registering with the peer
+/// first if needed, then sends or receives a single file as described by the
+/// [`FileTransferRequest`]. The handler is invoked with the number of bytes transferred and the
+/// size of the file each time a group of the file is acknowledged, and once more when the
+/// transfer completes. On the receiving side, the bytes transferred are estimated from the
+/// number of groups received.
+///
+/// Once the transfer completes, the kernel shuts down. If the transfer fails, or the peer
+/// disconnects before it completes, the kernel shuts down with the error
+pub struct FileTransferKernel<'a, F> {
+    inner_kernel: InnerKernel<'a>,
+    _pd: PhantomData<fn() -> F>,
+}
+
+#[async_trait]
+impl<'a, F> PrefabFunctions<'a, FileTransferRequest> for FileTransferKernel<'a, F>
+where
+    F: FnMut(u64, u64) + Send + 'a,
+{
+    type UserLevelInputFunction = F;
+    type SharedBundle = Shared;
+
+    fn get_shared_bundle(&self) -> Self::SharedBundle {
+        <InnerKernel<'a> as PrefabFunctions<'a, UserIdentifier>>::get_shared_bundle(
+            &self.inner_kernel,
+        )
+    }
+
+    async fn on_c2s_channel_received(
+        connect_success: ConnectionSuccess,
+        remote: ClientServerRemote,
+        request: FileTransferRequest,
+        on_progress: Self::UserLevelInputFunction,
+        shared: Self::SharedBundle,
+    ) -> Result<(), NetworkError> {
+        let peer = request.peer().clone();
+        let on_peer_connected =
+            move |conns, remote| transfer_file(conns, remote, request, on_progress);
+
+        <PeerConnectionKernel<'a, _, _> as PrefabFunctions<'a, UserIdentifier>>::on_c2s_channel_received(
+            connect_success,
+            remote,
+            peer,
+            on_peer_connected,
+            shared,
+        )
+        .await
+    }
+
+    fn construct(kernel: Box<dyn NetKernel + 'a>) -> Self {
+        Self {
+            inner_kernel: <InnerKernel<'a> as PrefabFunctions<'a, UserIdentifier>>::construct(
+                kernel,
+            ),
+            _pd: Default::default(),
+        }
+    }
+}
+
+async fn transfer_file<F: FnMut(u64, u64)>(
+    mut conns: Receiver<Result<PeerConnectSuccess, NetworkError>>,
+    remote: ClientServerRemote,
+    request: FileTransferRequest,
+    on_progress: F,
+) -> Result<(), NetworkError> {
+    let conn = conns.recv().await.ok_or(NetworkError::InternalError(
+        "The peer connection was never established",
+    ))??;
+
+    let result = match request {
+        FileTransferRequest::Send { path, .. } => send_file(conn, path, on_progress).await,
+        FileTransferRequest::Receive { destination, .. } => {
+            receive_file(conn, destination, on_progress).await
+        }
+    };
+
+    let shutdown = remote.shutdown_kernel().await;
+    result.and(shutdown)
+}
+
+async fn send_file<F: FnMut(u64, u64)>(
+    conn: PeerConnectSuccess,
+    path: PathBuf,
+    mut on_progress: F,
+) -> Result<(), NetworkError> {
+    let total = tokio::fs::metadata(&path)
+        .await
+        .map_err(|err| NetworkError::Generic(format!("Unable to read {}: {err}", path.display())))?
+        .len();
+
+    let PeerConnectSuccess {
+        channel,
+        mut remote,
+        ..
+    } = conn;
+    // dropping the receiving half would disconnect from the peer
+    let (_, mut peer_messages) = channel.split();
+    let implicated_cid = remote.user().get_implicated_cid();
+    let v_conn_type = *remote.user();
+
+    let result = remote
+        .remote()
+        .send_callback(NodeRequest::SendObject(SendObject {
+            source: Box::new(path),
+            chunk_size: None,
+            implicated_cid,
+            v_conn_type,
+            transfer_type: TransferType::FileTransfer,
+        }))
+        .await?;
+
+    let mut handle = match map_errors(result)? {
+        NodeResult::ObjectTransferHandle(ObjectTransferHandle { handle, .. }) => handle,
+        res => {
+            return Err(NetworkError::Generic(format!(
+                "Invalid NodeResult for FileTransfer request received: {res:?}"
+            )))
+        }
+    };
+
+    let stats = handle.stats_tracker();
+    let mut transfer_complete = false;
+    on_progress(0, total);
+
+    // the transfer is only done once the receiver confirms the file is at its destination
+    loop {
+        tokio::select! {
+            status = handle.next(), if !transfer_complete => match status {
+                Some(ObjectTransferStatus::TransferTick(..)) => {
+                    on_progress(stats.snapshot().bytes_acknowledged as u64, total)
+                }
+                Some(ObjectTransferStatus::TransferComplete) => transfer_complete = true,
+                Some(ObjectTransferStatus::Fail(reason)) => return Err(transfer_failed(reason)),
+                Some(_) => {}
+                None => return Err(NetworkError::InternalError("File transfer stream died")),
+            },
+
+            message = peer_messages.next() => match message {
+                Some(message) if message.as_ref() == RECEPTION_CONFIRMATION => {
+                    on_progress(total, total);
+                    return Ok(());
+                }
+                Some(_) => {}
+                None => return Err(peer_disconnected()),
+            },
+        }
+    }
+}
+
+async fn receive_file<F: FnMut(u64, u64)>(
+    conn: PeerConnectSuccess,
+    destination: PathBuf,
+    mut on_progress: F,
+) -> Result<(), NetworkError> {
+    let PeerConnectSuccess {
+        channel,
+        incoming_object_transfer_handles,
+        ..
+    } = conn;
+    let (peer_sender, mut peer_messages) = channel.split();
+    let mut handles = incoming_object_transfer_handles.ok_or(NetworkError::InternalError(
+        "Incoming file transfers are not being routed to this connection",
+    ))?;
+
+    let mut handle = tokio::select! {
+        handle = handles.recv() => handle.ok_or_else(peer_disconnected)?,
+        _ = wait_for_disconnect(&mut peer_messages) => return Err(peer_disconnected()),
+    };
+
+    handle
+        .accept()
+        .map_err(|err| NetworkError::Generic(err.into_string()))?;
+
+    let mut received_path = None;
+    let mut total = 0;
+
+    loop {
+        let status = tokio::select! {
+            status = handle.next() => status,
+            _ = wait_for_disconnect(&mut peer_messages) => return Err(peer_disconnected()),
+        };
+
+        match status {
+            Some(ObjectTransferStatus::ReceptionBeginning(path, metadata)) => {
+                total = metadata.get_metadata_file().plaintext_length as u64;
+                received_path = Some(path);
+                on_progress(0, total);
+            }
+            Some(ObjectTransferStatus::ReceptionTick(group, groups, _)) => {
+                on_progress(total * (group as u64 + 1) / groups.max(1) as u64, total)
+            }
+            Some(ObjectTransferStatus::ReceptionComplete) => break,
+            Some(ObjectTransferStatus::Fail(reason)) => return Err(transfer_failed(reason)),
+            Some(_) => {}
+            None => return Err(NetworkError::InternalError("File transfer stream died")),
+        }
+    }
+
+    let received_path = received_path.ok_or(NetworkError::InternalError(
+        "The file transfer completed before it began",
+    ))?;
+    move_file(&received_path, &destination).await?;
+    on_progress(total, total);
+
+    peer_sender
+        .send_message(RECEPTION_CONFIRMATION.into())
+        .await
+}
+
+/// Discards inbound messages until the peer disconnects
+async fn wait_for_disconnect(peer_messages: &mut PeerChannelRecvHalf) {
+    while peer_messages.next().await.is_some() {}
+}
+
+async fn move_file(from: &Path, to: &Path) -> Result<(), NetworkError> {
+    let map_err = |err: std::io::Error| {
+        NetworkError::Generic(format!("Unable to write {}: {err}", to.display()))
+    };
+
+    // renaming fails across filesystems, in which case the file is copied instead
+    if tokio::fs::rename(from, to).await.is_err() {
+        let _ = tokio::fs::copy(from, to).await.map_err(map_err)?;
+        tokio::fs::remove_file(from).await.map_err(map_err)?;
+    }
+
+    Ok(())
+}
+
+fn transfer_failed(reason: String) -> NetworkError {
+    NetworkError::Generic(format!("The file transfer failed: {reason}"))
+}
+
+fn peer_disconnected() -> NetworkError {
+    NetworkError::msg("The peer disconnected before the file transfer completed")
+}
+
+#[async_trait]
+impl<F> NetKernel for FileTransferKernel<'_, F> {
+    fn load_remote(&mut self, node_remote: NodeRemote) -> Result<(), NetworkError> {
+        self.inner_kernel.load_remote(node_remote)
+    }
+
+    async fn on_start(&self) -> Result<(), NetworkError> {
+        self.inner_kernel.on_start().await
+    }
+
+    async fn on_node_event_received(&self, message: NodeResult) -> Result<(), NetworkError> {
+        self.inner_kernel.on_node_event_received(message).await
+    }
+
+    async fn on_stop(&mut self) -> Result<(), NetworkError> {
+        self.inner_kernel.on_stop().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::node_builder::NodeBuilder;
+    use crate::prefabs::client::file_transfer::{FileTransferKernel, FileTransferRequest};
+    use crate::prefabs::client::peer_connection::PeerConnectionKernel;
+    use crate::prefabs::client::PrefabFunctions;
+    use crate::prelude::*;
+    use crate::test_common::{server_info, TestBarrier};
+    use citadel_io::Mutex;
+    use futures::prelude::stream::FuturesUnordered;
+    use futures::TryStreamExt;
+    use rstest::rstest;
+    use uuid::Uuid;
+
+    const SOURCE: &str = "../resources/TheBridge.pdf";
+
+    #[rstest]
+    #[timeout(std::time::Duration::from_secs(90))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn file_transfer_reports_progress() -> Result<(), Box<dyn std::error::Error>> {
+        citadel_logging::setup_log();
+        TestBarrier::setup(2);
+
+        let sent_progress = &Mutex::new(vec![]);
+        let received_progress = &Mutex::new(vec![]);
+        let (server, server_addr) = server_info();
+
+        let sender = Uuid::new_v4();
+        let receiver = Uuid::new_v4();
+        let destination = std::env::temp_dir().join(format!("{}.pdf", Uuid::new_v4()));
+
+        let sender_kernel = FileTransferKernel::new_passwordless_defaults(
+            sender,
+            server_addr,
+            FileTransferRequest::Send {
+                peer: receiver.into(),
+                path: SOURCE.into(),
+            },
+            move |sent, total| sent_progress.lock().push((sent, total)),
+        )
+        .unwrap();
+
+        let receiver_kernel = FileTransferKernel::new_passwordless_defaults(
+            receiver,
+            server_addr,
+            FileTransferRequest::Receive {
+                peer: sender.into(),
+                destination: destination.clone(),
+            },
+            move |received, total| received_progress.lock().push((received, total)),
+        )
+        .unwrap();
+
+        let client_kernels = FuturesUnordered::new();
+        for client_kernel in [sender_kernel, receiver_kernel] {
+            let client = NodeBuilder::default().build(client_kernel).unwrap();
+            client_kernels.push(async move { client.await.map(|_| ()) });
+        }
+
+        let clients = Box::pin(async move { client_kernels.try_collect::<()>().await.map(|_| ()) });
+
+        if let Err(err) = futures::future::try_select(server, clients).await {
+            return match err {
+                futures::future::Either::Left(res) => Err(res.0.into_string().into()),
+                futures::future::Either::Right(res) => Err(res.0.into_string().into()),
+            };
+        }
+
+        let cmp = include_bytes!("../../../../resources/TheBridge.pdf");
+        let streamed_data = tokio::fs::read(&destination).await?;
+        let _ = tokio::fs::remove_file(&destination).await;
+        assert_eq!(
+            cmp,
+            streamed_data.as_slice(),
+            "Original data and streamed data does not match"
+        );
+
+        let total = cmp.len() as u64;
+        for progress in [sent_progress.lock(), received_progress.lock()] {
+            assert_eq!(progress.last(), Some(&(total, total)));
+            assert!(progress.iter().all(|(_, reported)| *reported == total));
+            assert!(progress.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        }
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[timeout(std::time::Duration::from_secs(90))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn peer_disconnect_fails_the_transfer() {
+        citadel_logging::setup_log();
+        TestBarrier::setup(2);
+
+        let (server, server_addr) = server_info();
+        let sender = Uuid::new_v4();
+        let receiver = Uuid::new_v4();
+        let destination = std::env::temp_dir().join(format!("{}.pdf", Uuid::new_v4()));
+
+        // the sender disconnects as soon as it connects, without sending anything
+        let sender_kernel = PeerConnectionKernel::new_passwordless_defaults(
+            sender,
+            server_addr,
+            UserIdentifier::from(receiver),
+            move |mut results, remote| async move {
+                let _conn = results.recv().await.unwrap()?;
+                remote.shutdown_kernel().await
+            },
+        )
+        .unwrap();
+
+        let receiver_kernel = FileTransferKernel::new_passwordless_defaults(
+            receiver,
+            server_addr,
+            FileTransferRequest::Receive {
+                peer: sender.into(),
+                destination: destination.clone(),
+            },
+            |_, _| {},
+        )
+        .unwrap();
+
+        let sender = NodeBuilder::default().build(sender_kernel).unwrap();
+        let receiver = NodeBuilder::default().build(receiver_kernel).unwrap();
+        let clients = async move { tokio::join!(receiver, sender).0 };
+
+        tokio::select! {
+            _ = server => panic!("The server stopped before the clients"),
+            receiver = clients => assert!(receiver.is_err()),
+        }
+
+        assert!(!destination.exists());
+    }
+}
@@ -0,0 +1,279 @@
+use crate::prefabs::client::broadcast::{BroadcastKernel, BroadcastShared, GroupInitRequestType};
+use crate::prefabs::client::PrefabFunctions;
+use crate::prefabs::ClientServerRemote;
+use crate::prelude::*;
+use futures::future::BoxFuture;
+use futures::{Future, StreamExt};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use tokio::sync::{watch, Notify};
+
+type ChannelHandler<'a> =
+    fn(GroupChannel, ClientServerRemote) -> BoxFuture<'a, Result<(), NetworkError>>;
+
+/// A kernel that creates or joins a group like the [`BroadcastKernel`], then invokes the handler
+/// with the sender's CID and the payload of each inbound group message. Messages may be sent
+/// through the [`GroupChatRemote`] passed to the handler, or through the one obtained via
+/// [`GroupChatKernel::remote`] before the kernel starts.
+///
+/// Once [`GroupChatRemote::leave`] is called or the group closes, the group is left and the
+/// kernel shuts down
+pub struct GroupChatKernel<'a, F, Fut> {
+    inner_kernel: BroadcastKernel<'a, ChannelHandler<'a>, BoxFuture<'a, Result<(), NetworkError>>>,
+    remote: GroupChatRemote,
+    _pd: PhantomData<fn() -> (F, Fut)>,
+}
+
+/// A handle for sending to, and leaving, the group of a [`GroupChatKernel`]
+#[derive(Clone)]
+pub struct GroupChatRemote {
+    state: Arc<watch::Sender<GroupChatState>>,
+    leave_signal: Arc<Notify>,
+}
+
+#[derive(Clone)]
+enum GroupChatState {
+    Pending,
+    Joined(Arc<GroupChannelSendHalf>),
+    Left,
+}
+
+impl GroupChatRemote {
+    fn new() -> Self {
+        let (state, _) = watch::channel(GroupChatState::Pending);
+        Self {
+            state: Arc::new(state),
+            leave_signal: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Broadcasts `message` to every other member of the group. If the group has not yet been
+    /// joined, this waits until it is
+    pub async fn send_to_group<T: Into<SecBuffer>>(&self, message: T) -> Result<(), NetworkError> {
+        let mut state = self.state.subscribe();
+        loop {
+            let current = state.borrow_and_update().clone();
+            match current {
+                GroupChatState::Pending => {
+                    state
+                        .changed()
+                        .await
+                        .map_err(|err| NetworkError::Generic(err.to_string()))?;
+                }
+
+                GroupChatState::Joined(sender) => return sender.send_message(message.into()).await,
+
+                GroupChatState::Left => {
+                    return Err(NetworkError::InvalidRequest(
+                        "The group has already been left",
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Leaves the group, thereafter shutting down the kernel
+    pub fn leave(&self) {
+        self.leave_signal.notify_one();
+    }
+}
+
+impl<'a, F, Fut> GroupChatKernel<'a, F, Fut> {
+    /// Returns a handle to the group. Messages sent through it before the group is joined are
+    /// held until joining completes
+    pub fn remote(&self) -> GroupChatRemote {
+        self.remote.clone()
+    }
+}
+
+#[async_trait]
+impl<'a, F, Fut> PrefabFunctions<'a, GroupInitRequestType> for GroupChatKernel<'a, F, Fut>
+where
+    F: FnMut(u64, SecBuffer, GroupChatRemote) -> Fut + Send + 'a,
+    Fut: Future<Output = Result<(), NetworkError>> + Send + 'a,
+{
+    type UserLevelInputFunction = F;
+    type SharedBundle = (Arc<BroadcastShared>, GroupChatRemote);
+
+    fn get_shared_bundle(&self) -> Self::SharedBundle {
+        (self.inner_kernel.get_shared_bundle(), self.remote.clone())
+    }
+
+    async fn on_c2s_channel_received(
+        connect_success: ConnectionSuccess,
+        remote: ClientServerRemote,
+        arg: GroupInitRequestType,
+        fx: Self::UserLevelInputFunction,
+        shared: Self::SharedBundle,
+    ) -> Result<(), NetworkError> {
+        let (broadcast_shared, chat_remote) = shared;
+        let on_channel_received =
+            move |channel, remote| handle_group_messages(channel, remote, chat_remote, fx);
+
+        BroadcastKernel::on_c2s_channel_received(
+            connect_success,
+            remote,
+            arg,
+            on_channel_received,
+            broadcast_shared,
+        )
+        .await
+    }
+
+    fn construct(kernel: Box<dyn NetKernel + 'a>) -> Self {
+        Self {
+            inner_kernel: BroadcastKernel::construct(kernel),
+            remote: GroupChatRemote::new(),
+            _pd: Default::default(),
+        }
+    }
+}
+
+async fn handle_group_messages<F, Fut>(
+    channel: GroupChannel,
+    remote: ClientServerRemote,
+    chat_remote: GroupChatRemote,
+    mut on_message: F,
+) -> Result<(), NetworkError>
+where
+    F: FnMut(u64, SecBuffer, GroupChatRemote) -> Fut,
+    Fut: Future<Output = Result<(), NetworkError>>,
+{
+    let (send_half, recv_half) = channel.split();
+    let _ = chat_remote
+        .state
+        .send_replace(GroupChatState::Joined(Arc::new(send_half)));
+
+    let leave_signal = chat_remote.leave_signal.clone();
+    let mut payloads = recv_half.take_until(Box::pin(leave_signal.notified()));
+
+    while let Some(payload) = payloads.next().await {
+        match payload {
+            GroupBroadcastPayload::Message { payload, sender } => {
+                on_message(sender, payload, chat_remote.clone()).await?
+            }
+
+            GroupBroadcastPayload::Event { payload } => {
+                log::trace!(target: "citadel", "Group chat received event: {:?}", payload)
+            }
+        }
+    }
+
+    // dropping the receiving half of the channel leaves the group
+    std::mem::drop(payloads);
+    let _ = chat_remote.state.send_replace(GroupChatState::Left);
+    remote.shutdown_kernel().await
+}
+
+#[async_trait]
+impl<F, Fut> NetKernel for GroupChatKernel<'_, F, Fut> {
+    fn load_remote(&mut self, node_remote: NodeRemote) -> Result<(), NetworkError> {
+        self.inner_kernel.load_remote(node_remote)
+    }
+
+    async fn on_start(&self) -> Result<(), NetworkError> {
+        self.inner_kernel.on_start().await
+    }
+
+    async fn on_node_event_received(&self, message: NodeResult) -> Result<(), NetworkError> {
+        self.inner_kernel.on_node_event_received(message).await
+    }
+
+    async fn on_stop(&mut self) -> Result<(), NetworkError> {
+        self.inner_kernel.on_stop().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::node_builder::NodeBuilder;
+    use crate::prefabs::client::broadcast::GroupInitRequestType;
+    use crate::prefabs::client::group_chat::GroupChatKernel;
+    use crate::prefabs::client::PrefabFunctions;
+    use crate::prelude::*;
+    use crate::test_common::{server_info, wait_for_peers, TestBarrier};
+    use futures::prelude::stream::FuturesUnordered;
+    use futures::TryStreamExt;
+    use rstest::rstest;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use uuid::Uuid;
+
+    #[rstest]
+    #[timeout(std::time::Duration::from_secs(90))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn group_chat_messages() -> Result<(), Box<dyn std::error::Error>> {
+        citadel_logging::setup_log();
+        TestBarrier::setup(2);
+
+        let messages_received = &AtomicUsize::new(0);
+        let (server, server_addr) = server_info();
+
+        let client_kernels = FuturesUnordered::new();
+        let owner = Uuid::new_v4();
+        let member = Uuid::new_v4();
+        let group_id = Uuid::new_v4();
+
+        for (idx, uuid) in [owner, member].into_iter().enumerate() {
+            let request = if idx == 0 {
+                GroupInitRequestType::Create {
+                    local_user: UserIdentifier::from(uuid),
+                    invite_list: vec![],
+                    group_id,
+                    accept_registrations: true,
+                }
+            } else {
+                GroupInitRequestType::Join {
+                    local_user: UserIdentifier::from(uuid),
+                    owner: owner.into(),
+                    group_id,
+                    do_peer_register: true,
+                }
+            };
+
+            // the member greets the owner, who replies. Both leave once the reply is received
+            let client_kernel = GroupChatKernel::new_passwordless_defaults(
+                uuid,
+                server_addr,
+                request,
+                move |peer_cid, message, remote| async move {
+                    log::trace!(target: "citadel", "***GROUP PEER {} RECV {:?} FROM {}***", idx, message, peer_cid);
+                    let _ = messages_received.fetch_add(1, Ordering::Relaxed);
+
+                    if idx == 0 {
+                        assert_eq!(message.as_ref(), b"hello");
+                        remote.send_to_group("hi").await?;
+                    } else {
+                        assert_eq!(message.as_ref(), b"hi");
+                    }
+
+                    wait_for_peers().await;
+                    remote.leave();
+                    Ok(())
+                },
+            )
+            .unwrap();
+
+            if idx != 0 {
+                let remote = client_kernel.remote();
+                drop(tokio::spawn(async move {
+                    remote.send_to_group("hello").await.unwrap();
+                }));
+            }
+
+            let client = NodeBuilder::default().build(client_kernel).unwrap();
+            client_kernels.push(async move { client.await.map(|_| ()) });
+        }
+
+        let clients = Box::pin(async move { client_kernels.try_collect::<()>().await.map(|_| ()) });
+
+        if let Err(err) = futures::future::try_select(server, clients).await {
+            return match err {
+                futures::future::Either::Left(res) => Err(res.0.into_string().into()),
+                futures::future::Either::Right(res) => Err(res.0.into_string().into()),
+            };
+        }
+
+        assert_eq!(messages_received.load(Ordering::Relaxed), 2);
+        Ok(())
+    }
+}
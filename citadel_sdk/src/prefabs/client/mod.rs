@@ -6,6 +6,11 @@ use uuid::Uuid;
 
 /// A kernel that assists in creating and/or connecting to a group
 pub mod broadcast;
+/// A kernel that sends a file to, or receives a file from, a peer while reporting progress
+#[cfg(feature = "filesystem")]
+pub mod file_transfer;
+/// A kernel that invokes a handler on each message sent to a group
+pub mod group_chat;
 /// A kernel that assists in allowing multiple possible peer-to-peer connections
 pub mod peer_connection;
 /// A kernel that only makes a single client-to-server connection
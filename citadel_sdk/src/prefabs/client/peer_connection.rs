@@ -331,6 +331,7 @@ mod tests {
     };
     use crate::prelude::*;
     use crate::test_common::{server_info, wait_for_peers, TestBarrier, PEERS};
+    use citadel_proto::auth::AuthenticationRequest;
     use futures::stream::FuturesUnordered;
     use futures::TryStreamExt;
     use rstest::rstest;
@@ -450,6 +451,83 @@ mod tests {
         assert_eq!(client_success.load(Ordering::Relaxed), peer_count);
     }
 
+    #[rstest]
+    #[case(true)]
+    #[case(false)]
+    #[timeout(std::time::Duration::from_secs(90))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn peer_to_peer_connect_pinned_fingerprint(#[case] matching: bool) {
+        citadel_logging::setup_log();
+        TestBarrier::setup(2);
+
+        let client_success = &AtomicUsize::new(0);
+        let (server, server_addr) = server_info();
+        let identities = [
+            PeerIdentity::generate().unwrap(),
+            PeerIdentity::generate().unwrap(),
+        ];
+        // the first peer pins the identity of the second peer
+        let pinned_fingerprint = if matching {
+            identities[1].fingerprint()
+        } else {
+            PeerIdentity::generate().unwrap().fingerprint()
+        };
+
+        let client_kernels = FuturesUnordered::new();
+
+        for (idx, identity) in identities.into_iter().enumerate() {
+            let (username, password, full_name) = PEERS.get(idx).unwrap();
+            let peer = UserIdentifier::Username(PEERS.get(1 - idx).unwrap().0.clone());
+            let peer_cid = peer.get_cid();
+
+            let client_kernel = PeerConnectionKernel::new_register_defaults(
+                full_name.as_str(),
+                username.as_str(),
+                password.as_str(),
+                peer,
+                server_addr,
+                move |mut results, remote| async move {
+                    let conn = results.recv().await.unwrap();
+                    match (matching, idx) {
+                        (true, _) => {
+                            let _ = conn?;
+                        }
+
+                        (false, 0) => {
+                            assert_eq!(
+                                conn.unwrap_err().into_string(),
+                                ConnectError::FingerprintMismatch(peer_cid).to_string()
+                            );
+                        }
+
+                        (false, _) => {
+                            assert!(conn.is_err());
+                        }
+                    }
+
+                    let _ = client_success.fetch_add(1, Ordering::Relaxed);
+                    wait_for_peers().await;
+                    remote.shutdown_kernel().await
+                },
+            )
+            .unwrap();
+
+            let mut builder = NodeBuilder::default();
+            let _ = builder.with_peer_identity(identity);
+            if idx == 0 {
+                let _ = builder.with_pinned_peer_fingerprint(peer_cid, pinned_fingerprint);
+            }
+
+            let client = builder.build(client_kernel).unwrap();
+            client_kernels.push(async move { client.await.map(|_| ()) });
+        }
+
+        let clients = Box::pin(async move { client_kernels.try_collect::<()>().await.map(|_| ()) });
+
+        assert!(futures::future::try_select(server, clients).await.is_ok());
+        assert_eq!(client_success.load(Ordering::Relaxed), 2);
+    }
+
     #[rstest]
     #[case(2)]
     #[case(3)]
@@ -748,4 +826,108 @@ mod tests {
         assert_eq!(client_success.load(Ordering::Relaxed), peer_count);
         Ok(())
     }
+
+    #[rstest]
+    #[timeout(std::time::Duration::from_secs(90))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_watch_presence() {
+        const PEER_COUNT: usize = 3;
+        citadel_logging::setup_log();
+        TestBarrier::setup(PEER_COUNT + 1);
+
+        let watcher_success = &AtomicBool::new(false);
+        let (server, server_addr) = server_info();
+
+        let watcher = UserIdentifier::Username(PEERS.get(0).unwrap().0.clone());
+        let peers = (1..=PEER_COUNT)
+            .map(|idx| UserIdentifier::Username(PEERS.get(idx).unwrap().0.clone()))
+            .collect::<Vec<UserIdentifier>>();
+        let peer_cids = peers
+            .iter()
+            .map(UserIdentifier::get_cid)
+            .collect::<Vec<u64>>();
+
+        let client_kernels = FuturesUnordered::new();
+
+        // the first client watches the other clients, each of which disconnects then reconnects
+        for idx in 0..=PEER_COUNT {
+            let (username, password, full_name) = PEERS.get(idx).unwrap();
+            let targets = if idx == 0 {
+                peers.clone()
+            } else {
+                vec![watcher.clone()]
+            };
+            let peer_cids = peer_cids.clone();
+
+            let client_kernel = PeerConnectionKernel::new_register_defaults(
+                full_name.as_str(),
+                username.as_str(),
+                password.as_str(),
+                targets,
+                server_addr,
+                move |mut results, remote| async move {
+                    use futures::StreamExt;
+                    let expected_conns = if idx == 0 { PEER_COUNT } else { 1 };
+                    for _ in 0..expected_conns {
+                        let _ = results.recv().await.unwrap()?;
+                    }
+
+                    let implicated_cid = remote.conn_type.get_implicated_cid();
+
+                    if idx == 0 {
+                        let mut presence = remote.inner.watch_presence(&peer_cids);
+                        wait_for_peers().await;
+
+                        let mut updates = HashMap::<u64, Vec<Presence>>::new();
+                        for _ in 0..PEER_COUNT * 2 {
+                            let (peer_cid, update) = presence.next().await.unwrap();
+                            updates.entry(peer_cid).or_default().push(update);
+                        }
+
+                        for peer_cid in peer_cids {
+                            assert_eq!(
+                                updates.remove(&peer_cid).unwrap(),
+                                vec![Presence::Offline, Presence::Online]
+                            );
+                        }
+
+                        watcher_success.store(true, Ordering::Relaxed);
+                    } else {
+                        wait_for_peers().await;
+                        let mut node_remote = remote.inner.clone();
+                        let _ = node_remote
+                            .send(NodeRequest::DisconnectFromHypernode(
+                                DisconnectFromHypernode {
+                                    implicated_cid,
+                                    v_conn_type: VirtualTargetType::LocalGroupServer(
+                                        implicated_cid,
+                                    ),
+                                },
+                            ))
+                            .await?;
+                        // give the previous session time to end before starting a new one
+                        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+                        let _ = node_remote
+                            .connect_with_defaults(AuthenticationRequest::credentialed(
+                                implicated_cid,
+                                password.as_str(),
+                            ))
+                            .await?;
+                    }
+
+                    wait_for_peers().await;
+                    remote.shutdown_kernel().await
+                },
+            )
+            .unwrap();
+
+            let client = NodeBuilder::default().build(client_kernel).unwrap();
+            client_kernels.push(async move { client.await.map(|_| ()) });
+        }
+
+        let clients = Box::pin(async move { client_kernels.try_collect::<()>().await.map(|_| ()) });
+
+        assert!(futures::future::try_select(server, clients).await.is_ok());
+        assert!(watcher_success.load(Ordering::Relaxed));
+    }
 }
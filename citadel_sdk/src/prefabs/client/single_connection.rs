@@ -286,8 +286,12 @@ mod tests {
     use crate::prefabs::ClientServerRemote;
     use crate::prelude::*;
     use crate::test_common::{server_info_reactive, wait_for_peers, TestBarrier};
+    use citadel_proto::auth::AuthenticationRequest;
     use rstest::rstest;
-    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
     use uuid::Uuid;
 
     #[cfg_attr(
@@ -371,6 +375,61 @@ mod tests {
         assert!(server_success.load(Ordering::Relaxed));
     }
 
+    #[rstest]
+    #[timeout(std::time::Duration::from_secs(90))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_single_connection_records_last_connect() {
+        citadel_logging::setup_log();
+        TestBarrier::setup(2);
+
+        let client_success = &AtomicBool::new(false);
+        let server_success = &AtomicBool::new(false);
+
+        let (server, server_addr) = server_info_reactive(
+            move |conn, remote| async move {
+                let last_connect = remote
+                    .inner
+                    .account_manager()
+                    .get_last_connect(conn.cid)
+                    .await?
+                    .expect("The connect should be recorded");
+                let elapsed = std::time::SystemTime::now()
+                    .duration_since(last_connect.into())
+                    .unwrap_or_default();
+                assert!(elapsed < Duration::from_secs(60));
+                default_server_harness(UdpMode::Disabled, conn, remote, server_success).await
+            },
+            |_| (),
+        );
+
+        let client_kernel = SingleClientServerConnectionKernel::new_register(
+            "Thomas P Braun",
+            "nologik",
+            "password",
+            server_addr,
+            UdpMode::Disabled,
+            Default::default(),
+            |channel, remote| async move {
+                wait_for_peers().await;
+                crate::test_common::udp_mode_assertions(UdpMode::Disabled, channel.udp_channel_rx)
+                    .await;
+                client_success.store(true, Ordering::Relaxed);
+                wait_for_peers().await;
+                remote.shutdown_kernel().await
+            },
+        )
+        .unwrap();
+
+        let client = NodeBuilder::default().build(client_kernel).unwrap();
+
+        let joined = futures::future::try_join(server, client);
+
+        let _ = joined.await.unwrap();
+
+        assert!(client_success.load(Ordering::Relaxed));
+        assert!(server_success.load(Ordering::Relaxed));
+    }
+
     #[rstest]
     #[case(false, UdpMode::Enabled)]
     #[case(true, UdpMode::Disabled)]
@@ -428,6 +487,57 @@ mod tests {
         assert!(server_success.load(Ordering::Relaxed));
     }
 
+    #[rstest]
+    #[timeout(std::time::Duration::from_secs(90))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_single_connection_header_obfuscation(
+        #[values(UdpMode::Enabled, UdpMode::Disabled)] udp_mode: UdpMode,
+    ) {
+        citadel_logging::setup_log();
+        TestBarrier::setup(2);
+
+        let client_success = &AtomicBool::new(false);
+        let server_success = &AtomicBool::new(false);
+        let (server, server_addr) = server_info_reactive(
+            |conn, remote| async move {
+                default_server_harness(udp_mode, conn, remote, server_success).await
+            },
+            |builder| {
+                let _ = builder.with_header_obfuscation(true);
+            },
+        );
+
+        let uuid = Uuid::new_v4();
+
+        let client_kernel = SingleClientServerConnectionKernel::new_passwordless(
+            uuid,
+            server_addr,
+            udp_mode,
+            Default::default(),
+            |channel, remote| async move {
+                log::trace!(target: "citadel", "***CLIENT TEST SUCCESS***");
+                wait_for_peers().await;
+                crate::test_common::udp_mode_assertions(udp_mode, channel.udp_channel_rx).await;
+                client_success.store(true, Ordering::Relaxed);
+                wait_for_peers().await;
+                remote.shutdown_kernel().await
+            },
+        )
+        .unwrap();
+
+        let client = NodeBuilder::default()
+            .with_header_obfuscation(true)
+            .build(client_kernel)
+            .unwrap();
+
+        let joined = futures::future::try_join(server, client);
+
+        let _ = joined.await.unwrap();
+
+        assert!(client_success.load(Ordering::Relaxed));
+        assert!(server_success.load(Ordering::Relaxed));
+    }
+
     #[rstest]
     #[case(UdpMode::Disabled)]
     #[timeout(std::time::Duration::from_secs(90))]
@@ -600,4 +710,726 @@ mod tests {
         assert!(client_success.load(Ordering::Relaxed));
         assert!(server_success.load(Ordering::Relaxed));
     }
+
+    #[rstest]
+    #[timeout(std::time::Duration::from_secs(90))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_active_drill_versions_c2s() {
+        citadel_logging::setup_log();
+        TestBarrier::setup(2);
+
+        let udp_mode = UdpMode::Disabled;
+
+        let client_success = &AtomicBool::new(false);
+        let server_success = &AtomicBool::new(false);
+        let (server, server_addr) = server_info_reactive(
+            |conn, remote| async move {
+                default_server_harness(udp_mode, conn, remote, server_success).await
+            },
+            |_| (),
+        );
+
+        let uuid = Uuid::new_v4();
+
+        let client_kernel = SingleClientServerConnectionKernel::new_passwordless(
+            uuid,
+            server_addr,
+            udp_mode,
+            Default::default(),
+            |_channel, mut remote| async move {
+                wait_for_peers().await;
+                let capacity = MAX_HYPER_RATCHETS_IN_MEMORY as u32;
+
+                // fill the toolset without exceeding its capacity, so nothing gets truncated
+                for x in 1..capacity {
+                    assert_eq!(remote.rekey().await?, Some(x));
+                }
+                assert_eq!(
+                    remote.active_drill_versions().await?,
+                    (0..capacity).collect::<Vec<u32>>()
+                );
+
+                // each further re-key truncates the oldest version
+                let latest = capacity + 2;
+                for x in capacity..=latest {
+                    assert_eq!(remote.rekey().await?, Some(x));
+                }
+                assert_eq!(
+                    remote.active_drill_versions().await?,
+                    (latest + 1 - capacity..=latest).collect::<Vec<u32>>()
+                );
+
+                client_success.store(true, Ordering::Relaxed);
+                wait_for_peers().await;
+                remote.shutdown_kernel().await
+            },
+        )
+        .unwrap();
+
+        let client = NodeBuilder::default().build(client_kernel).unwrap();
+
+        let joined = futures::future::try_join(server, client);
+
+        let _ = joined.await.unwrap();
+
+        assert!(client_success.load(Ordering::Relaxed));
+        assert!(server_success.load(Ordering::Relaxed));
+    }
+
+    #[rstest]
+    #[timeout(std::time::Duration::from_secs(90))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dump_session_state_c2s() {
+        citadel_logging::setup_log();
+        TestBarrier::setup(2);
+
+        let udp_mode = UdpMode::Disabled;
+
+        let client_success = &AtomicBool::new(false);
+        let server_success = &AtomicBool::new(false);
+        let (server, server_addr) = server_info_reactive(
+            |conn, remote| async move {
+                default_server_harness(udp_mode, conn, remote, server_success).await
+            },
+            |_| (),
+        );
+
+        let uuid = Uuid::new_v4();
+
+        let client_kernel = SingleClientServerConnectionKernel::new_passwordless(
+            uuid,
+            server_addr,
+            udp_mode,
+            Default::default(),
+            |_channel, mut remote| async move {
+                wait_for_peers().await;
+                assert_eq!(remote.rekey().await?, Some(1));
+
+                let cid = remote.user().get_implicated_cid();
+                let dump = remote.remote().dump_session_state(cid).await?;
+                assert_eq!(dump.implicated_cid, Some(cid));
+                assert_eq!(dump.state, SessionState::Connected);
+                assert!(!dump.is_server);
+                assert_eq!(dump.udp_mode, udp_mode);
+                assert!(dump.security_settings.is_some());
+                assert!(dump.virtual_connections.is_empty());
+                assert_eq!(dump.corrupt_headers_discarded, 0);
+                assert_eq!(dump.inbound_file_transfers, 0);
+                assert_eq!(dump.outbound_file_transfers, 0);
+
+                let c2s_crypto = dump.c2s_crypto.as_ref().unwrap();
+                assert_eq!(c2s_crypto.active_drill_versions, vec![0, 1]);
+                assert_eq!(c2s_crypto.latest_usable_version, 1);
+                assert!(!c2s_crypto.has_deferred_truncation);
+
+                // the dump must survive being serialized for a bug report
+                let serialized = dump.serialize_to_vector().unwrap();
+                let deserialized = SessionStateDump::deserialize_from_vector(&serialized).unwrap();
+                assert_eq!(deserialized.implicated_cid, Some(cid));
+                assert_eq!(deserialized.remote_peer, dump.remote_peer);
+
+                assert!(remote.remote().dump_session_state(cid + 1).await.is_err());
+
+                client_success.store(true, Ordering::Relaxed);
+                wait_for_peers().await;
+                remote.shutdown_kernel().await
+            },
+        )
+        .unwrap();
+
+        let client = NodeBuilder::default().build(client_kernel).unwrap();
+
+        let joined = futures::future::try_join(server, client);
+
+        let _ = joined.await.unwrap();
+
+        assert!(client_success.load(Ordering::Relaxed));
+        assert!(server_success.load(Ordering::Relaxed));
+    }
+
+    const MAX_FAILED_CONNECT_ATTEMPTS: usize = 3;
+    const FAILED_CONNECT_COOLDOWN: Duration = Duration::from_secs(3);
+
+    /// Registers, then repeatedly attempts to connect with a bad password until the server
+    /// begins rejecting attempts. After the cooldown, connects with the correct password
+    struct BadPasswordKernel {
+        remote: Option<NodeRemote>,
+        server_addr: SocketAddr,
+        client_success: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl NetKernel for BadPasswordKernel {
+        fn load_remote(&mut self, node_remote: NodeRemote) -> Result<(), NetworkError> {
+            self.remote = Some(node_remote);
+            Ok(())
+        }
+
+        async fn on_start(&self) -> Result<(), NetworkError> {
+            let mut remote = self.remote.clone().unwrap();
+            let _ = remote
+                .register_with_defaults(self.server_addr, "Thomas P Braun", "nologik", "password")
+                .await?;
+
+            let attempt = |password: &'static str| {
+                let mut remote = remote.clone();
+                async move {
+                    // give the previous session time to end before starting a new one
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    remote
+                        .connect(
+                            AuthenticationRequest::credentialed("nologik", password),
+                            Default::default(),
+                            UdpMode::Disabled,
+                            None,
+                            Default::default(),
+                        )
+                        .await
+                }
+            };
+
+            for _ in 0..MAX_FAILED_CONNECT_ATTEMPTS {
+                assert!(attempt("bad_password").await.is_err());
+            }
+
+            // the correct password is still rejected during the cooldown, and the caller learns for how long
+            let retry_after = match attempt("password").await {
+                Err(NetworkError::Connect(ConnectError::TooManyAttempts(retry_after))) => {
+                    retry_after
+                }
+                res => panic!(
+                    "Connect should be rejected during the cooldown: {:?}",
+                    res.err()
+                ),
+            };
+            assert!(retry_after > Duration::ZERO && retry_after <= FAILED_CONNECT_COOLDOWN);
+
+            tokio::time::sleep(retry_after).await;
+            let connection = attempt("password").await?;
+
+            wait_for_peers().await;
+            crate::test_common::udp_mode_assertions(UdpMode::Disabled, connection.udp_channel_rx)
+                .await;
+            self.client_success.store(true, Ordering::Relaxed);
+            wait_for_peers().await;
+            remote.shutdown().await
+        }
+
+        async fn on_node_event_received(&self, _message: NodeResult) -> Result<(), NetworkError> {
+            Ok(())
+        }
+
+        async fn on_stop(&mut self) -> Result<(), NetworkError> {
+            Ok(())
+        }
+    }
+
+    #[rstest]
+    #[timeout(std::time::Duration::from_secs(90))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_connect_cooldown_after_failed_attempts() {
+        citadel_logging::setup_log();
+        TestBarrier::setup(2);
+
+        let udp_mode = UdpMode::Disabled;
+        let client_success = Arc::new(AtomicBool::new(false));
+        let server_success = &AtomicBool::new(false);
+
+        let (server, server_addr) = server_info_reactive(
+            |conn, remote| async move {
+                default_server_harness(udp_mode, conn, remote, server_success).await
+            },
+            |builder| {
+                let _ = builder.with_server_misc_settings(ServerMiscSettings {
+                    max_failed_connect_attempts: MAX_FAILED_CONNECT_ATTEMPTS,
+                    failed_connect_cooldown: FAILED_CONNECT_COOLDOWN,
+                    ..Default::default()
+                });
+            },
+        );
+
+        let client_kernel = BadPasswordKernel {
+            remote: None,
+            server_addr,
+            client_success: client_success.clone(),
+        };
+
+        let client = NodeBuilder::default().build(client_kernel).unwrap();
+
+        let joined = futures::future::try_join(server, client);
+
+        let _ = joined.await.unwrap();
+
+        assert!(client_success.load(Ordering::Relaxed));
+        assert!(server_success.load(Ordering::Relaxed));
+    }
+
+    /// Registers and connects, disconnects, then connects again. The second connect presents the
+    /// resumption ticket issued during the first
+    struct ResumptionKernel {
+        remote: Option<NodeRemote>,
+        server_addr: SocketAddr,
+        expect_resumed: bool,
+        client_success: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl NetKernel for ResumptionKernel {
+        fn load_remote(&mut self, node_remote: NodeRemote) -> Result<(), NetworkError> {
+            self.remote = Some(node_remote);
+            Ok(())
+        }
+
+        async fn on_start(&self) -> Result<(), NetworkError> {
+            let mut remote = self.remote.clone().unwrap();
+            let _ = remote
+                .register_with_defaults(self.server_addr, "Thomas P Braun", "nologik", "password")
+                .await?;
+
+            let connect = || {
+                let mut remote = remote.clone();
+                async move {
+                    remote
+                        .connect(
+                            AuthenticationRequest::credentialed("nologik", "password"),
+                            Default::default(),
+                            UdpMode::Disabled,
+                            None,
+                            Default::default(),
+                        )
+                        .await
+                }
+            };
+
+            let connection = connect().await?;
+            assert!(!connection.session_resumed);
+            let cid = connection.cid;
+
+            let _ = remote
+                .clone()
+                .send(NodeRequest::DisconnectFromHypernode(
+                    DisconnectFromHypernode {
+                        implicated_cid: cid,
+                        v_conn_type: VirtualTargetType::LocalGroupServer(cid),
+                    },
+                ))
+                .await?;
+            // give the previous session time to end before starting a new one
+            tokio::time::sleep(Duration::from_millis(500)).await;
+
+            // with a valid ticket, the SYN_ACK and STAGE0 round trips are skipped. Otherwise, the
+            // server falls back to the full key agreement
+            let connection = connect().await?;
+            assert_eq!(connection.session_resumed, self.expect_resumed);
+
+            wait_for_peers().await;
+            crate::test_common::udp_mode_assertions(UdpMode::Disabled, connection.udp_channel_rx)
+                .await;
+            self.client_success.store(true, Ordering::Relaxed);
+            wait_for_peers().await;
+            remote.shutdown().await
+        }
+
+        async fn on_node_event_received(&self, _message: NodeResult) -> Result<(), NetworkError> {
+            Ok(())
+        }
+
+        async fn on_stop(&mut self) -> Result<(), NetworkError> {
+            Ok(())
+        }
+    }
+
+    #[rstest]
+    #[case(Duration::from_secs(60), true)]
+    #[case(Duration::from_millis(1), false)]
+    #[timeout(std::time::Duration::from_secs(90))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_session_resumption(
+        #[case] resumption_lifetime: Duration,
+        #[case] expect_resumed: bool,
+    ) {
+        citadel_logging::setup_log();
+        TestBarrier::setup(2);
+
+        let udp_mode = UdpMode::Disabled;
+        let client_success = Arc::new(AtomicBool::new(false));
+        let server_success = &AtomicBool::new(false);
+        let server_connections = &AtomicUsize::new(0);
+
+        let (server, server_addr) = server_info_reactive(
+            |conn, remote| async move {
+                // the first connection is dropped by the client, so only the second is tested
+                if server_connections.fetch_add(1, Ordering::SeqCst) == 0 {
+                    assert!(!conn.session_resumed);
+                    return Ok(());
+                }
+
+                assert_eq!(conn.session_resumed, expect_resumed);
+                default_server_harness(udp_mode, conn, remote, server_success).await
+            },
+            |builder| {
+                let _ = builder.with_server_misc_settings(ServerMiscSettings {
+                    session_resumption_lifetime: Some(resumption_lifetime),
+                    ..Default::default()
+                });
+            },
+        );
+
+        let client_kernel = ResumptionKernel {
+            remote: None,
+            server_addr,
+            expect_resumed,
+            client_success: client_success.clone(),
+        };
+
+        let client = NodeBuilder::default().build(client_kernel).unwrap();
+
+        let joined = futures::future::try_join(server, client);
+
+        let _ = joined.await.unwrap();
+
+        assert!(client_success.load(Ordering::Relaxed));
+        assert!(server_success.load(Ordering::Relaxed));
+        assert_eq!(server_connections.load(Ordering::Relaxed), 2);
+    }
+
+    /// Registers and connects, then deregisters while keeping the local account. Connecting with
+    /// the now-unknown CID must be rejected, after which a freshly registered account connects
+    struct UnregisteredCidKernel {
+        remote: Option<NodeRemote>,
+        server_addr: SocketAddr,
+        client_success: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl NetKernel for UnregisteredCidKernel {
+        fn load_remote(&mut self, node_remote: NodeRemote) -> Result<(), NetworkError> {
+            self.remote = Some(node_remote);
+            Ok(())
+        }
+
+        async fn on_start(&self) -> Result<(), NetworkError> {
+            let mut remote = self.remote.clone().unwrap();
+            let connect_remote = remote.clone();
+            let connect = move |username: &'static str| {
+                let mut remote = connect_remote.clone();
+                async move {
+                    remote
+                        .connect(
+                            AuthenticationRequest::credentialed(username, "password"),
+                            Default::default(),
+                            UdpMode::Disabled,
+                            None,
+                            Default::default(),
+                        )
+                        .await
+                }
+            };
+
+            let _ = remote
+                .register_with_defaults(self.server_addr, "Thomas P Braun", "nologik", "password")
+                .await?;
+            let cid = connect("nologik").await?.cid;
+            let cnac = remote
+                .account_manager()
+                .get_client_by_cid(cid)
+                .await?
+                .unwrap();
+
+            let mut c2s_remote = ClientServerRemote {
+                inner: remote.clone(),
+                unprocessed_signals_rx: Default::default(),
+                conn_type: VirtualTargetType::LocalGroupServer(cid),
+            };
+            c2s_remote.deregister().await?;
+            // keep the local account so that the client still attempts to connect with the old CID
+            remote
+                .account_manager()
+                .get_persistence_handler()
+                .save_cnac(&cnac)
+                .await?;
+            tokio::time::sleep(Duration::from_millis(500)).await;
+
+            let err = connect("nologik")
+                .await
+                .err()
+                .expect("Connect should be rejected for an unregistered CID");
+            let err = err.into_string();
+            assert!(err.contains("requires prior registration"), "{err}");
+
+            let _ = remote
+                .register_with_defaults(self.server_addr, "Thomas P Braun", "nologik2", "password")
+                .await?;
+            let connection = connect("nologik2").await?;
+
+            wait_for_peers().await;
+            crate::test_common::udp_mode_assertions(UdpMode::Disabled, connection.udp_channel_rx)
+                .await;
+            self.client_success.store(true, Ordering::Relaxed);
+            wait_for_peers().await;
+            remote.shutdown().await
+        }
+
+        async fn on_node_event_received(&self, _message: NodeResult) -> Result<(), NetworkError> {
+            Ok(())
+        }
+
+        async fn on_stop(&mut self) -> Result<(), NetworkError> {
+            Ok(())
+        }
+    }
+
+    #[rstest]
+    #[timeout(std::time::Duration::from_secs(90))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_reject_unregistered_cid() {
+        citadel_logging::setup_log();
+        TestBarrier::setup(2);
+
+        let udp_mode = UdpMode::Disabled;
+        let client_success = Arc::new(AtomicBool::new(false));
+        let server_success = &AtomicBool::new(false);
+        let server_connections = &AtomicUsize::new(0);
+
+        let (server, server_addr) = server_info_reactive(
+            |conn, remote| async move {
+                // the first connection is deregistered by the client, so only the last is tested
+                if server_connections.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return Ok(());
+                }
+
+                default_server_harness(udp_mode, conn, remote, server_success).await
+            },
+            |builder| {
+                let _ = builder.with_server_misc_settings(ServerMiscSettings {
+                    require_prior_registration: true,
+                    ..Default::default()
+                });
+            },
+        );
+
+        let client_kernel = UnregisteredCidKernel {
+            remote: None,
+            server_addr,
+            client_success: client_success.clone(),
+        };
+
+        let client = NodeBuilder::default().build(client_kernel).unwrap();
+
+        let joined = futures::future::try_join(server, client);
+
+        let _ = joined.await.unwrap();
+
+        assert!(client_success.load(Ordering::Relaxed));
+        assert!(server_success.load(Ordering::Relaxed));
+        // the unregistered CID never reaches the connect stage
+        assert_eq!(server_connections.load(Ordering::Relaxed), 2);
+    }
+
+    /// Registers, then connects to a server supporting the given protocol versions
+    struct ProtocolVersionKernel {
+        remote: Option<NodeRemote>,
+        server_addr: SocketAddr,
+        compatible: bool,
+        client_success: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl NetKernel for ProtocolVersionKernel {
+        fn load_remote(&mut self, node_remote: NodeRemote) -> Result<(), NetworkError> {
+            self.remote = Some(node_remote);
+            Ok(())
+        }
+
+        async fn on_start(&self) -> Result<(), NetworkError> {
+            let mut remote = self.remote.clone().unwrap();
+            let _ = remote
+                .register_with_defaults(self.server_addr, "Thomas P Braun", "nologik", "password")
+                .await?;
+
+            let result = remote
+                .connect(
+                    AuthenticationRequest::credentialed("nologik", "password"),
+                    Default::default(),
+                    UdpMode::Disabled,
+                    None,
+                    Default::default(),
+                )
+                .await;
+
+            if !self.compatible {
+                let err = result
+                    .err()
+                    .expect("Connect should fail without a common protocol version");
+                assert!(err
+                    .into_string()
+                    .contains("No protocol version is supported by both nodes"));
+                self.client_success.store(true, Ordering::Relaxed);
+                return remote.shutdown().await;
+            }
+
+            let connection = result?;
+            wait_for_peers().await;
+            crate::test_common::udp_mode_assertions(UdpMode::Disabled, connection.udp_channel_rx)
+                .await;
+            self.client_success.store(true, Ordering::Relaxed);
+            wait_for_peers().await;
+            remote.shutdown().await
+        }
+
+        async fn on_node_event_received(&self, _message: NodeResult) -> Result<(), NetworkError> {
+            Ok(())
+        }
+
+        async fn on_stop(&mut self) -> Result<(), NetworkError> {
+            Ok(())
+        }
+    }
+
+    #[rstest]
+    #[case(ProtocolVersion::new(0, 0, 0), ProtocolVersion::CURRENT, true)]
+    #[case(ProtocolVersion::new(0, 0, 0), ProtocolVersion::new(0, 0, 0), false)]
+    #[timeout(std::time::Duration::from_secs(90))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_protocol_version_negotiation(
+        #[case] server_min: ProtocolVersion,
+        #[case] server_max: ProtocolVersion,
+        #[case] compatible: bool,
+    ) {
+        citadel_logging::setup_log();
+        TestBarrier::setup(2);
+
+        let udp_mode = UdpMode::Disabled;
+        let client_success = Arc::new(AtomicBool::new(false));
+        let server_success = &AtomicBool::new(false);
+
+        let (server, server_addr) = server_info_reactive(
+            |conn, remote| async move {
+                default_server_harness(udp_mode, conn, remote, server_success).await
+            },
+            |builder| {
+                let _ = builder.with_supported_protocol_versions(
+                    ProtocolVersionRange::new(server_min, server_max).unwrap(),
+                );
+            },
+        );
+
+        let client_kernel = ProtocolVersionKernel {
+            remote: None,
+            server_addr,
+            compatible,
+            client_success: client_success.clone(),
+        };
+
+        // the client supports only the current version
+        let client = NodeBuilder::default()
+            .with_supported_protocol_versions(
+                ProtocolVersionRange::new(ProtocolVersion::CURRENT, ProtocolVersion::CURRENT)
+                    .unwrap(),
+            )
+            .build(client_kernel)
+            .unwrap();
+
+        if compatible {
+            let _ = futures::future::try_join(server, client).await.unwrap();
+        } else {
+            // the server never receives a connection, and thus never shuts down on its own
+            tokio::select! {
+                res0 = client => res0.map(|_| ()),
+                res1 = server => res1.map(|_| ())
+            }
+            .unwrap();
+        }
+
+        assert!(client_success.load(Ordering::Relaxed));
+        assert_eq!(server_success.load(Ordering::Relaxed), compatible);
+    }
+
+    /// Registers one more account than the server permits per minute. Only the last registration
+    /// must be rejected
+    struct RegistrationRateLimitKernel {
+        remote: Option<NodeRemote>,
+        server_addr: SocketAddr,
+        max_registrations: usize,
+        client_success: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl NetKernel for RegistrationRateLimitKernel {
+        fn load_remote(&mut self, node_remote: NodeRemote) -> Result<(), NetworkError> {
+            self.remote = Some(node_remote);
+            Ok(())
+        }
+
+        async fn on_start(&self) -> Result<(), NetworkError> {
+            let mut remote = self.remote.clone().unwrap();
+            for idx in 0..self.max_registrations {
+                let _ = remote
+                    .register_with_defaults(
+                        self.server_addr,
+                        "Thomas P Braun",
+                        format!("nologik{idx}"),
+                        "password",
+                    )
+                    .await?;
+            }
+
+            let err = remote
+                .register_with_defaults(self.server_addr, "Thomas P Braun", "nologik", "password")
+                .await
+                .err()
+                .expect("Registration should be rejected once the limit is exceeded");
+            let err = err.into_string();
+            assert!(err.contains("Too many registration attempts"), "{err}");
+
+            self.client_success.store(true, Ordering::Relaxed);
+            remote.shutdown().await
+        }
+
+        async fn on_node_event_received(&self, _message: NodeResult) -> Result<(), NetworkError> {
+            Ok(())
+        }
+
+        async fn on_stop(&mut self) -> Result<(), NetworkError> {
+            Ok(())
+        }
+    }
+
+    #[rstest]
+    #[timeout(std::time::Duration::from_secs(90))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_registration_rate_limit() {
+        citadel_logging::setup_log();
+
+        const MAX_REGISTRATIONS: usize = 2;
+        let client_success = Arc::new(AtomicBool::new(false));
+
+        let (server, server_addr) = server_info_reactive(
+            |_conn, _remote| async move { Ok(()) },
+            |builder| {
+                let _ = builder.with_server_misc_settings(ServerMiscSettings {
+                    max_registrations_per_minute: MAX_REGISTRATIONS,
+                    ..Default::default()
+                });
+            },
+        );
+
+        let client_kernel = RegistrationRateLimitKernel {
+            remote: None,
+            server_addr,
+            max_registrations: MAX_REGISTRATIONS,
+            client_success: client_success.clone(),
+        };
+
+        let client = NodeBuilder::default().build(client_kernel).unwrap();
+
+        // the server never receives a connection, and thus never shuts down on its own
+        tokio::select! {
+            res0 = client => res0.map(|_| ()),
+            res1 = server => res1.map(|_| ())
+        }
+        .unwrap();
+
+        assert!(client_success.load(Ordering::Relaxed));
+    }
 }
@@ -53,6 +53,7 @@ where
                 welcome_message: _,
                 channel,
                 udp_rx_opt: udp_channel_rx,
+                session_resumed,
             }) => {
                 let client_server_remote = ClientServerRemote {
                     inner: self.node_remote.clone().unwrap(),
@@ -65,6 +66,7 @@ where
                         udp_channel_rx,
                         services,
                         cid,
+                        session_resumed,
                     },
                     client_server_remote,
                 )
@@ -123,6 +123,9 @@ pub struct ConnectionSuccess {
     /// Contains the Google auth minted at the central server (if the central server enabled it), as well as any other services enabled by the central server
     pub services: ServicesObject,
     pub cid: u64,
+    /// True if a resumption ticket from a previous session allowed the key agreement to be skipped.
+    /// The session is rekeyed right after connecting. Always false if UdpMode was enabled
+    pub session_resumed: bool,
 }
 
 /// Contains the elements entailed by a successful registration
@@ -221,16 +224,23 @@ pub trait ProtocolRemoteExt: Remote {
                 welcome_message: _,
                 channel,
                 udp_rx_opt: udp_channel_rx,
+                session_resumed,
             }) => Ok(ConnectionSuccess {
                 channel,
                 udp_channel_rx,
                 services,
                 cid,
+                session_resumed,
             }),
+            NodeResult::ConnectFail(ConnectFail {
+                connect_error: Some(err),
+                ..
+            }) => Err(NetworkError::Connect(err)),
             NodeResult::ConnectFail(ConnectFail {
                 ticket: _,
                 cid_opt: _,
                 error_message: err,
+                connect_error: None,
             }) => Err(NetworkError::Generic(err)),
             res => Err(NetworkError::msg(format!(
                 "[connect] An unexpected response occurred: {res:?}"
@@ -465,6 +475,7 @@ pub trait ProtocolRemoteTargetExt: TargetLockedRemote {
                 while let Some(res) = handle.next().await {
                     log::trace!(target: "citadel", "Client received RES {:?}", res);
                     if let ObjectTransferStatus::TransferComplete = res {
+                        log::trace!(target: "citadel", "File transfer stats: {:?}", handle.stats());
                         return Ok(());
                     }
                 }
@@ -861,6 +872,14 @@ pub trait ProtocolRemoteTargetExt: TargetLockedRemote {
         ))
     }
 
+    /// Returns every key matrix version currently retained for this connection, from oldest
+    /// to newest. Older versions remain until the re-key that pushes the toolset past capacity
+    /// truncates them
+    async fn active_drill_versions(&mut self) -> Result<Vec<u32>, NetworkError> {
+        let v_conn_type = *self.user();
+        self.remote().active_drill_versions(v_conn_type).await
+    }
+
     #[doc(hidden)]
     async fn try_as_peer_connection(&mut self) -> Result<PeerConnectionType, NetworkError> {
         let verified_return = |user: &VirtualTargetType| {
@@ -1115,4 +1134,203 @@ mod tests {
         assert!(client_success.load(Ordering::Relaxed));
         assert!(server_success.load(Ordering::Relaxed));
     }
+
+    #[rstest]
+    #[timeout(std::time::Duration::from_secs(90))]
+    #[tokio::test]
+    async fn test_c2s_file_transfer_under_packet_loss() {
+        citadel_logging::setup_log();
+        let client_success = &AtomicBool::new(false);
+        let server_success = &Arc::new(AtomicBool::new(false));
+        let (server, server_addr) = server_info(server_success.clone());
+        let uuid = Uuid::new_v4();
+        let profile = FaultProfile {
+            extra_latency: std::time::Duration::from_millis(1),
+            loss_rate: 0.1,
+            reorder_rate: 0.2,
+            ..Default::default()
+        };
+        let transfer_stats = &citadel_io::Mutex::new(None);
+
+        let client_kernel = SingleClientServerConnectionKernel::new_passwordless_defaults(
+            uuid,
+            server_addr,
+            |_channel, mut remote| async move {
+                let implicated_cid = remote.user().get_implicated_cid();
+                let v_conn_type = *remote.user();
+                let result = remote
+                    .remote()
+                    .send_callback(NodeRequest::SendObject(SendObject {
+                        source: Box::new("../resources/TheBridge.pdf"),
+                        chunk_size: Some(32 * 1024),
+                        implicated_cid,
+                        v_conn_type,
+                        transfer_type: TransferType::FileTransfer,
+                    }))
+                    .await?;
+
+                if let NodeResult::ObjectTransferHandle(ObjectTransferHandle {
+                    mut handle, ..
+                }) = map_errors(result)?
+                {
+                    use futures::StreamExt;
+                    while let Some(status) = handle.next().await {
+                        if let ObjectTransferStatus::TransferComplete = status {
+                            *transfer_stats.lock() = Some(handle.stats());
+                            client_success.store(true, Ordering::Relaxed);
+                            break;
+                        }
+                    }
+                }
+
+                remote.shutdown_kernel().await
+            },
+        )
+        .unwrap();
+
+        let mut builder = NodeBuilder::default();
+        let _ = builder.with_fault_injection(profile);
+        let fault_injector = builder.fault_injector().unwrap();
+        let client = builder.build(client_kernel).unwrap();
+
+        let joined = futures::future::try_join(server, client);
+        let _ = joined.await.unwrap();
+
+        // the server compares the received file against the original, despite payloads having
+        // arrived out of order and behind stalls
+        assert!(client_success.load(Ordering::Relaxed));
+        assert!(server_success.load(Ordering::Relaxed));
+        let faults = fault_injector.stats();
+        assert!(faults.stalls > 0);
+        assert!(faults.reordered > 0);
+
+        // every group the protocol sent was acknowledged, and the stalls held up acknowledgements
+        let stats = transfer_stats.lock().take().unwrap();
+        assert!(stats.groups_sent > 0);
+        assert_eq!(stats.groups_acknowledged, stats.groups_sent);
+        assert_eq!(
+            stats.bytes_acknowledged,
+            include_bytes!("../../resources/TheBridge.pdf").len()
+        );
+        assert!(
+            stats.rtt_samples.iter().max().unwrap() >= &profile.retransmission_timeout(),
+            "No group was held up by a stall: {stats:?}"
+        );
+    }
+
+    #[rstest]
+    #[case(true)]
+    #[case(false)]
+    #[timeout(std::time::Duration::from_secs(90))]
+    #[tokio::test]
+    async fn test_c2s_file_transfer_account_features(#[case] file_transfer: bool) {
+        citadel_logging::setup_log();
+        let client_success = &AtomicBool::new(false);
+        let server_success = &Arc::new(AtomicBool::new(false));
+        let port = crate::test_common::get_unused_tcp_port();
+        let server_addr = SocketAddr::from_str(&format!("127.0.0.1:{port}")).unwrap();
+        let server = crate::test_common::server_test_node(
+            server_addr,
+            ReceiverFileTransferKernel(None, server_success.clone()),
+            |builder| {
+                let _ = builder.with_server_misc_settings(ServerMiscSettings {
+                    default_account_features: FeatureSet {
+                        file_transfer,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                });
+            },
+        );
+        let uuid = Uuid::new_v4();
+
+        let client_kernel = SingleClientServerConnectionKernel::new_passwordless_defaults(
+            uuid,
+            server_addr,
+            |_channel, mut remote| async move {
+                let result = remote
+                    .send_file_with_custom_opts(
+                        "../resources/TheBridge.pdf",
+                        32 * 1024,
+                        TransferType::FileTransfer,
+                    )
+                    .await;
+                // the server must reject the transfer at the header stage if the account may not transfer files
+                assert_eq!(result.is_ok(), file_transfer);
+                client_success.store(true, Ordering::Relaxed);
+                remote.shutdown_kernel().await
+            },
+        )
+        .unwrap();
+
+        let client = NodeBuilder::default().build(client_kernel).unwrap();
+
+        if file_transfer {
+            let _ = futures::future::try_join(server, client).await.unwrap();
+        } else {
+            // the server never receives the file, and thus never shuts down on its own
+            tokio::select! {
+                res0 = client => res0.map(|_| ()),
+                res1 = server => res1.map(|_| ())
+            }
+            .unwrap();
+        }
+
+        assert!(client_success.load(Ordering::Relaxed));
+        assert_eq!(server_success.load(Ordering::Relaxed), file_transfer);
+    }
+
+    #[rstest]
+    #[timeout(std::time::Duration::from_secs(90))]
+    #[tokio::test]
+    async fn test_c2s_await_transfer() {
+        citadel_logging::setup_log();
+        let client_success = &AtomicBool::new(false);
+        let server_success = &Arc::new(AtomicBool::new(false));
+        let (server, server_addr) = server_info(server_success.clone());
+        let uuid = Uuid::new_v4();
+
+        let client_kernel = SingleClientServerConnectionKernel::new_passwordless_defaults(
+            uuid,
+            server_addr,
+            |_channel, mut remote| async move {
+                let implicated_cid = remote.user().get_implicated_cid();
+                let v_conn_type = *remote.user();
+                let ticket = remote
+                    .remote()
+                    .send(NodeRequest::SendObject(SendObject {
+                        source: Box::new("../resources/TheBridge.pdf"),
+                        chunk_size: Some(32 * 1024),
+                        implicated_cid,
+                        v_conn_type,
+                        transfer_type: TransferType::FileTransfer,
+                    }))
+                    .await?;
+
+                let result = remote.remote().await_transfer(ticket).await.unwrap();
+                assert_eq!(result.ticket, ticket);
+                // awaiting an already-finished transfer returns the same result
+                assert_eq!(
+                    remote.remote().await_transfer(ticket).await.unwrap(),
+                    result
+                );
+                // while a ticket that never initiated a transfer fails instead of waiting forever
+                let unknown = remote.remote().get_next_ticket();
+                assert_eq!(
+                    remote.remote().await_transfer(unknown).await,
+                    Err(TransferError::UnknownTicket(unknown))
+                );
+                client_success.store(true, Ordering::Relaxed);
+                remote.shutdown_kernel().await
+            },
+        )
+        .unwrap();
+
+        let client = NodeBuilder::default().build(client_kernel).unwrap();
+        let joined = futures::future::try_join(server, client);
+        let _ = joined.await.unwrap();
+
+        assert!(client_success.load(Ordering::Relaxed));
+        assert!(server_success.load(Ordering::Relaxed));
+    }
 }
@@ -0,0 +1,126 @@
+use crate::client_account::MutualPeer;
+use crate::misc::AccountError;
+use crate::serialization::SyncIO;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+/// Bumped whenever the format of [`CnacExport`] changes
+pub const CNAC_EXPORT_VERSION: u32 = 1;
+
+const NONCE_LEN: usize = 12;
+
+/// An account as exported by
+/// [`AccountManager::export_cnac`](crate::account_manager::AccountManager::export_cnac), before
+/// it is encrypted
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CnacExport {
+    pub cid: u64,
+    /// The serialized [`ClientNetworkAccountInner`](crate::client_account::ClientNetworkAccountInner)
+    pub cnac: Vec<u8>,
+    pub peers: Vec<MutualPeer>,
+    /// peer cid -> the byte map in the format of
+    /// [`BackendConnection::export_byte_map_for_peer`](crate::backend::BackendConnection::export_byte_map_for_peer),
+    /// including internal values. Peer cid 0 holds the values the client stores for itself
+    pub byte_maps: Vec<(u64, Vec<u8>)>,
+}
+
+/// The blob handed to the caller. The version is left in plaintext, so that blobs of another
+/// format are told apart from blobs encrypted with another key
+#[derive(Serialize, Deserialize)]
+struct SealedCnacExport {
+    version: u32,
+    nonce: [u8; NONCE_LEN],
+    ciphertext: Vec<u8>,
+}
+
+impl CnacExport {
+    /// Serializes and encrypts the export with `key`
+    pub fn seal(&self, key: &[u8; 32]) -> Result<Vec<u8>, AccountError> {
+        let plaintext = self.serialize_to_vector()?;
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(key))
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &plaintext,
+                    aad: &CNAC_EXPORT_VERSION.to_be_bytes(),
+                },
+            )
+            .map_err(|_| AccountError::msg("Unable to encrypt the account export"))?;
+
+        SealedCnacExport {
+            version: CNAC_EXPORT_VERSION,
+            nonce,
+            ciphertext,
+        }
+        .serialize_to_vector()
+    }
+
+    /// Decrypts and deserializes the output of [`Self::seal`]
+    pub fn open(blob: &[u8], key: &[u8; 32]) -> Result<Self, AccountError> {
+        let sealed = SealedCnacExport::deserialize_from_vector(blob)?;
+        if sealed.version != CNAC_EXPORT_VERSION {
+            return Err(AccountError::msg(format!(
+                "Unsupported account export version {}",
+                sealed.version
+            )));
+        }
+
+        let plaintext = ChaCha20Poly1305::new(Key::from_slice(key))
+            .decrypt(
+                Nonce::from_slice(&sealed.nonce),
+                Payload {
+                    msg: &sealed.ciphertext,
+                    aad: &sealed.version.to_be_bytes(),
+                },
+            )
+            .map_err(|_| {
+                AccountError::msg(
+                    "Unable to decrypt the account export. It was either altered, or encrypted with a different key",
+                )
+            })?;
+
+        Self::deserialize_from_owned_vector(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::account_export::{CnacExport, SealedCnacExport, CNAC_EXPORT_VERSION};
+    use crate::serialization::SyncIO;
+
+    fn export() -> CnacExport {
+        CnacExport {
+            cid: 10,
+            cnac: vec![1, 2, 3],
+            peers: Vec::new(),
+            byte_maps: vec![(0, vec![4, 5])],
+        }
+    }
+
+    #[test]
+    fn round_trips_with_the_same_key() {
+        let blob = export().seal(&[7; 32]).unwrap();
+        let opened = CnacExport::open(&blob, &[7; 32]).unwrap();
+        assert_eq!(opened.cid, 10);
+        assert_eq!(opened.cnac, vec![1, 2, 3]);
+        assert_eq!(opened.byte_maps, vec![(0, vec![4, 5])]);
+
+        assert!(CnacExport::open(&blob, &[8; 32]).is_err());
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let blob = export().seal(&[7; 32]).unwrap();
+        let mut sealed = SealedCnacExport::deserialize_from_vector(&blob).unwrap();
+        sealed.version = CNAC_EXPORT_VERSION + 1;
+        let blob = sealed.serialize_to_vector().unwrap();
+
+        let err = CnacExport::open(&blob, &[7; 32]).err().unwrap();
+        assert!(err.into_string().contains("version"));
+    }
+}
@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+/// The capabilities granted to an account. These are enforced by the server to which the account
+/// is registered. The default grants every capability without limits
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct FeatureSet {
+    /// If disabled, the account may not transfer files or RE-VFS objects to the server
+    pub file_transfer: bool,
+    /// The maximum size, in bytes, of each object the account may transfer to the server
+    pub max_file_transfer_size: Option<u64>,
+    /// If disabled, the account may not create message groups
+    pub group_creation: bool,
+    /// The maximum number of peers the account may be registered to
+    pub max_peers: Option<usize>,
+}
+
+impl Default for FeatureSet {
+    fn default() -> Self {
+        Self {
+            file_transfer: true,
+            max_file_transfer_size: None,
+            group_creation: true,
+            max_peers: None,
+        }
+    }
+}
+
+impl FeatureSet {
+    /// Returns true if the account may transfer an object of `size` bytes
+    pub fn permits_file_transfer(&self, size: u64) -> bool {
+        self.file_transfer && self.max_file_transfer_size.is_none_or(|max| size <= max)
+    }
+
+    /// Returns true if an account currently registered to `peer_count` peers may register to
+    /// another
+    pub fn permits_new_peer(&self, peer_count: usize) -> bool {
+        self.max_peers.is_none_or(|max| peer_count < max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::account_features::FeatureSet;
+
+    #[test]
+    fn default_permits_everything() {
+        let features = FeatureSet::default();
+        assert!(features.permits_file_transfer(u64::MAX));
+        assert!(features.permits_new_peer(usize::MAX));
+        assert!(features.group_creation);
+    }
+
+    #[test]
+    fn limits_are_enforced() {
+        let features = FeatureSet {
+            max_file_transfer_size: Some(1024),
+            max_peers: Some(2),
+            ..Default::default()
+        };
+
+        assert!(features.permits_file_transfer(1024));
+        assert!(!features.permits_file_transfer(1025));
+        assert!(features.permits_new_peer(1));
+        assert!(!features.permits_new_peer(2));
+
+        let features = FeatureSet {
+            file_transfer: false,
+            ..Default::default()
+        };
+        assert!(!features.permits_file_transfer(0));
+    }
+}
@@ -4,15 +4,16 @@ use crate::hypernode_account::CNAC_SERIALIZED_EXTENSION;
 use crate::misc::AccountError;
 use crate::prelude::ClientNetworkAccount;
 use citadel_crypt::stacked_ratchet::Ratchet;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 /// Loads all locally-stored CNACs, as well as the highest CID (used to update local nac in case improper shutdown)
 #[allow(unused_results)]
 pub fn load_cnac_files<R: Ratchet, Fcm: Ratchet>(
     ds: &DirectoryStore,
-) -> Result<HashMap<u64, ClientNetworkAccount<R, Fcm>>, AccountError> {
+) -> Result<BTreeMap<u64, ClientNetworkAccount<R, Fcm>>, AccountError> {
     let hyxe_nac_dir_impersonal = ds.nac_dir_impersonal.as_str();
     let hyxe_nac_dir_personal = ds.nac_dir_personal.as_str();
+    let hyxe_nac_dir_deactivated = ds.nac_dir_deactivated.as_str();
 
     let cnacs_impersonal = load_file_types_by_ext::<ClientNetworkAccountInner<R, Fcm>, _>(
         CNAC_SERIALIZED_EXTENSION,
@@ -22,11 +23,16 @@ pub fn load_cnac_files<R: Ratchet, Fcm: Ratchet>(
         CNAC_SERIALIZED_EXTENSION,
         hyxe_nac_dir_personal,
     )?;
-    log::trace!(target: "citadel", "[CNAC Loader] Impersonal client network accounts loaded: {} | Personal client network accounts loaded: {}", cnacs_impersonal.len(), cnacs_personal.len());
+    let cnacs_deactivated = load_file_types_by_ext::<ClientNetworkAccountInner<R, Fcm>, _>(
+        CNAC_SERIALIZED_EXTENSION,
+        hyxe_nac_dir_deactivated,
+    )?;
+    log::trace!(target: "citadel", "[CNAC Loader] Impersonal client network accounts loaded: {} | Personal client network accounts loaded: {} | Deactivated client network accounts loaded: {}", cnacs_impersonal.len(), cnacs_personal.len(), cnacs_deactivated.len());
 
     Ok(cnacs_impersonal
         .into_iter()
         .chain(cnacs_personal.into_iter())
+        .chain(cnacs_deactivated)
         .map(|r| {
             let cid = r.0.cid;
             (cid, r.0.into())
@@ -48,8 +54,9 @@ pub fn load_file_types_by_ext<D: DeserializeOwned, P: AsRef<Path>>(
     ext: &str,
     path: P,
 ) -> Result<Vec<(D, PathBuf)>, AccountError> {
-    let mut dir =
-        std::fs::read_dir(path.as_ref()).map_err(|err| AccountError::IoError(err.to_string()))?;
+    let path = path.as_ref();
+    let mut dir = std::fs::read_dir(path)
+        .map_err(|err| AccountError::io(err, format!("Unable to read {}", path.display())))?;
     let mut files = Vec::new();
     while let Some(Ok(child)) = dir.next() {
         let path_buf = child.path();
@@ -80,8 +87,9 @@ pub fn load_file_types_by_ext<D: DeserializeOwned, P: AsRef<Path>>(
 
 /// Reads the given path as the given type, D
 pub fn read<D: DeserializeOwned, P: AsRef<Path>>(path: P) -> Result<D, AccountError> {
-    std::fs::File::open(path.as_ref())
-        .map_err(|err| AccountError::IoError(err.to_string()))
+    let path = path.as_ref();
+    std::fs::File::open(path)
+        .map_err(|err| AccountError::io(err, format!("Unable to open {}", path.display())))
         .and_then(|file| {
             bincode_config()
                 .deserialize_from(std::io::BufReader::new(file))
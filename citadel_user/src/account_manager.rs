@@ -1,15 +1,31 @@
+use crate::account_export::CnacExport;
+use crate::account_features::FeatureSet;
+use crate::account_quota::{AccountQuota, QuotaReservation};
 use crate::auth::proposed_credentials::ProposedCredentials;
 use crate::backend::memory::MemoryBackend;
-use crate::backend::{BackendType, PersistenceHandler};
-use crate::client_account::{ClientNetworkAccount, MutualPeer};
+use crate::backend::{
+    export_byte_map, import_byte_map, normalize_username, BackendType, PersistenceHandler,
+};
+use crate::client_account::{
+    ClientNetworkAccount, ClientNetworkAccountInner, MutualPeer, HYPERLAN_IDX,
+};
+use crate::external_services::apns::ApnsKeys;
+use crate::external_services::push::{PushDeliveryReport, TEST_PUSH_PAYLOAD};
 use crate::external_services::{ServicesConfig, ServicesHandler};
-use crate::misc::AccountError;
+use crate::misc::{format_timestamp, AccountError, CNACMetadata, GroupInfo};
 use crate::prelude::{ConnectionInfo, UserIdentifier};
+use crate::serialization::SyncIO;
 use crate::server_misc_settings::ServerMiscSettings;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use citadel_crypt::argon::argon_container::{ArgonDefaultServerSettings, ArgonSettings};
 use citadel_crypt::fcm::fcm_ratchet::ThinRatchet;
 use citadel_crypt::stacked_ratchet::Ratchet;
 use citadel_crypt::stacked_ratchet::StackedRatchet;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::SystemTime;
 
 /// The default manager for handling the list of users stored locally. It also allows for user creation, and is used especially
 /// for when creating a new user via the registration service.
@@ -19,7 +35,84 @@ pub struct AccountManager<R: Ratchet = StackedRatchet, Fcm: Ratchet = ThinRatche
     persistence_handler: PersistenceHandler<R, Fcm>,
     node_argon_settings: ArgonSettings,
     server_misc_settings: ServerMiscSettings,
+    account_quota: Option<Arc<AccountQuota>>,
     backend_ty: BackendType,
+    #[cfg(all(feature = "apns", not(target_family = "wasm")))]
+    apns_client: crate::external_services::apns::ApnsClient,
+}
+
+/// Describes the changes made by [`AccountManager::migrate_device`]
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct MigrationReport {
+    /// The peers that were re-registered to the new device
+    pub peers_migrated: Vec<u64>,
+    /// The number of byte map values copied to the new device
+    pub byte_map_entries_copied: usize,
+    /// Whether the old device was deregistered from this node
+    pub old_deregistered: bool,
+}
+
+/// Describes the changes made by [`AccountManager::reconcile_usernames`]
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ReconcileReport {
+    /// The clients renamed to their authoritative username
+    pub renamed: Vec<u64>,
+    /// The clients left unchanged because their authoritative username belongs to another client
+    pub conflicts: Vec<UsernameConflict>,
+    /// The clients the resolver had no username for
+    pub unresolved: Vec<u64>,
+}
+
+/// A client whose authoritative username is already taken by another client
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UsernameConflict {
+    /// The client that could not be renamed
+    pub cid: u64,
+    /// The authoritative username of the client
+    pub username: String,
+    /// The client that holds the username
+    pub holder_cid: u64,
+}
+
+/// Observes the mutations made through an [`AccountManager`], such as for metrics or auditing.
+/// Each method is called once the mutation succeeds. Errors are logged rather than returned, so a
+/// failing listener never fails the mutation itself. Every method defaults to doing nothing. Set
+/// via [`ServerMiscSettings`]
+#[async_trait]
+pub trait AccountManagerListener: Send + Sync {
+    /// Called once a client is registered to this node, whether as a server or as a client
+    async fn on_register(&self, _cid: u64, _username: &str) -> Result<(), AccountError> {
+        Ok(())
+    }
+
+    /// Called once a client is deleted from this node
+    async fn on_deregister(&self, _cid: u64) -> Result<(), AccountError> {
+        Ok(())
+    }
+
+    /// Called once `cid` and `peer_cid` are registered to each other
+    async fn on_p2p_registered(&self, _cid: u64, _peer_cid: u64) -> Result<(), AccountError> {
+        Ok(())
+    }
+
+    /// Called once `cid` and `peer_cid` are no longer registered to each other
+    async fn on_p2p_deregistered(&self, _cid: u64, _peer_cid: u64) -> Result<(), AccountError> {
+        Ok(())
+    }
+
+    /// Called once an existing client is saved after a change to its account
+    async fn on_cnac_saved(&self, _cid: u64) -> Result<(), AccountError> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum AccountEvent<'a> {
+    Register(u64, &'a str),
+    Deregister(u64),
+    P2PRegistered(u64, u64),
+    P2PDeregistered(u64, u64),
+    CnacSaved(u64),
 }
 
 impl<R: Ratchet, Fcm: Ratchet> AccountManager<R, Fcm> {
@@ -33,15 +126,7 @@ impl<R: Ratchet, Fcm: Ratchet> AccountManager<R, Fcm> {
         _services_cfg: Option<ServicesConfig>,
         server_misc_settings: Option<ServerMiscSettings>,
     ) -> Result<Self, AccountError> {
-        // The below map should locally store: impersonal mode CNAC's, as well as personal remote server CNAC's
-        #[cfg(feature = "google-services")]
-        let services_handler = _services_cfg
-            .unwrap_or_default()
-            .into_services_handler()
-            .await?;
-
-        #[cfg(not(feature = "google-services"))]
-        let services_handler = ServicesHandler;
+        let server_misc_settings = server_misc_settings.unwrap_or_default();
 
         let persistence_handler = match &backend_type {
             BackendType::InMemory => {
@@ -52,25 +137,81 @@ impl<R: Ratchet, Fcm: Ratchet> AccountManager<R, Fcm> {
             #[cfg(all(feature = "filesystem", not(target_family = "wasm")))]
             BackendType::Filesystem(dir) => {
                 use crate::backend::filesystem_backend::FilesystemBackend;
-                let backend = FilesystemBackend::from(dir.clone());
+                let backend = FilesystemBackend::from(dir.clone())
+                    .with_revfs_deduplication(server_misc_settings.revfs_deduplication);
                 PersistenceHandler::create(backend).await?
             }
 
             #[cfg(all(feature = "sql", not(coverage)))]
             BackendType::SQLDatabase(..) => {
+                use crate::backend::metrics::MeteredBackend;
                 use crate::backend::mysql_backend::SqlBackend;
                 let backend = SqlBackend::try_from(backend_type.clone()).map_err(|_| AccountError::Generic("Invalid database URL format. Please check documentation for preferred format".to_string()))?;
-                PersistenceHandler::create(backend).await?
+                PersistenceHandler::create(MeteredBackend::new(backend)).await?
             }
 
             #[cfg(all(feature = "redis", not(coverage)))]
             BackendType::Redis(url, opts) => {
+                use crate::backend::metrics::MeteredBackend;
                 use crate::backend::redis_backend::RedisBackend;
                 let backend = RedisBackend::new(url.clone(), opts.clone());
-                PersistenceHandler::create(backend).await?
+                PersistenceHandler::create(MeteredBackend::new(backend)).await?
+            }
+
+            #[cfg(all(feature = "mongo", not(coverage)))]
+            BackendType::Mongo(url, opts) => {
+                use crate::backend::metrics::MeteredBackend;
+                use crate::backend::mongo_backend::MongoBackend;
+                let backend = MongoBackend::new(url.clone(), opts.clone());
+                PersistenceHandler::create(MeteredBackend::new(backend)).await?
+            }
+
+            #[cfg(all(feature = "sled", not(target_family = "wasm")))]
+            BackendType::Sled(path) => {
+                use crate::backend::metrics::MeteredBackend;
+                use crate::backend::sled_backend::SledBackend;
+                let backend = SledBackend::new(path.clone());
+                PersistenceHandler::create(MeteredBackend::new(backend)).await?
             }
         };
 
+        Self::from_persistence_handler(
+            persistence_handler,
+            backend_type,
+            server_argon_settings,
+            _services_cfg,
+            Some(server_misc_settings),
+        )
+        .await
+    }
+
+    /// Creates an account manager around a `persistence_handler` built elsewhere, such as one
+    /// whose backend is shared with other subsystems, or an in-memory backend in tests. Unlike
+    /// [`Self::new`], this does not connect the backend, so the handler must come from
+    /// [`PersistenceHandler::create`], which connects it and sets up its tables. `backend_type`
+    /// describes the backend, as returned by [`Self::get_backend_type`]. The CID generator of the
+    /// handler is replaced by the one in `server_misc_settings`
+    pub async fn from_persistence_handler(
+        persistence_handler: PersistenceHandler<R, Fcm>,
+        backend_type: BackendType,
+        server_argon_settings: Option<ArgonDefaultServerSettings>,
+        _services_cfg: Option<ServicesConfig>,
+        server_misc_settings: Option<ServerMiscSettings>,
+    ) -> Result<Self, AccountError> {
+        // The below map should locally store: impersonal mode CNAC's, as well as personal remote server CNAC's
+        #[cfg(feature = "google-services")]
+        let services_handler = _services_cfg
+            .unwrap_or_default()
+            .into_services_handler()
+            .await?;
+
+        #[cfg(not(feature = "google-services"))]
+        let services_handler = ServicesHandler;
+
+        let server_misc_settings = server_misc_settings.unwrap_or_default();
+        let persistence_handler =
+            persistence_handler.with_cid_generator(server_misc_settings.cid_generator.clone());
+
         if !persistence_handler.is_connected().await? {
             return Err(AccountError::msg(
                 "Unable to connect to remote database via account manager",
@@ -79,22 +220,99 @@ impl<R: Ratchet, Fcm: Ratchet> AccountManager<R, Fcm> {
 
         log::info!(target: "citadel", "Successfully established connection to backend {:?}...", backend_type);
 
+        let account_quota = match server_misc_settings.max_registered_accounts {
+            Some(max) => {
+                let registered = count_registered_accounts(&persistence_handler).await?;
+                Some(Arc::new(AccountQuota::new(max, registered)))
+            }
+            None => None,
+        };
+
         let this = Self {
             backend_ty: backend_type,
             persistence_handler,
             services_handler,
             node_argon_settings: server_argon_settings.unwrap_or_default().into(),
-            server_misc_settings: server_misc_settings.unwrap_or_default(),
+            server_misc_settings,
+            account_quota,
+            #[cfg(all(feature = "apns", not(target_family = "wasm")))]
+            apns_client: crate::external_services::apns::ApnsClient::new()?,
         };
 
         Ok(this)
     }
 
+    /// Overwrites the creation date of a newly created account with the time of the configured
+    /// clock, if any
+    fn stamp_creation_date(&self, cnac: &ClientNetworkAccount<R, Fcm>) {
+        if let Some(clock) = self.server_misc_settings.clock.as_ref() {
+            cnac.write().creation_date = format_timestamp(clock.now());
+        }
+    }
+
     /// Returns a reference to the services handler
     pub fn services_handler(&self) -> &ServicesHandler {
         &self.services_handler
     }
 
+    /// Sends a harmless test notification to the push provider of `cid` and returns the provider's
+    /// response, for diagnosing why a device is not waking. The notification is not a wake payload.
+    /// If `cid` has APNs keys and the `apns` feature is enabled, the notification is sent to APNs
+    pub async fn send_test_push(&self, cid: u64) -> Result<PushDeliveryReport, AccountError> {
+        if !self.persistence_handler.cid_is_registered(cid).await? {
+            return Err(AccountError::ClientNonExists(cid));
+        }
+
+        #[cfg(all(feature = "apns", not(target_family = "wasm")))]
+        if let Some(keys) = self.get_apns_keys(cid).await? {
+            let report = self
+                .apns_client
+                .send_test_push(&keys, cid, TEST_PUSH_PAYLOAD)
+                .await?;
+            log::trace!(target: "citadel", "Test push to {} over APNs: {:?}", cid, report);
+            return Ok(report);
+        }
+
+        let report = if let Some(provider) = self.server_misc_settings.push_provider.as_ref() {
+            provider.send_test_push(cid, TEST_PUSH_PAYLOAD).await?
+        } else {
+            self.services_handler.send_test_push(cid).await?
+        };
+
+        log::trace!(target: "citadel", "Test push to {}: {:?}", cid, report);
+        Ok(report)
+    }
+
+    /// Sends a push-wake to `peer_cid` on behalf of `implicated_cid` through the push provider
+    /// set in [`ServerMiscSettings`], or otherwise through the external services. With a
+    /// [`NoopPushProvider`](crate::external_services::push::NoopPushProvider), the wake is
+    /// discarded and this returns `Ok`. If `peer_cid` has APNs keys and the `apns` feature is
+    /// enabled, the wake is sent to APNs instead
+    pub async fn send_push_wake(
+        &self,
+        payload: Vec<u8>,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<(), AccountError> {
+        #[cfg(all(feature = "apns", not(target_family = "wasm")))]
+        if let Some(keys) = self.get_apns_keys(peer_cid).await? {
+            return self
+                .apns_client
+                .send_push_wake(&keys, &payload, implicated_cid)
+                .await;
+        }
+
+        if let Some(provider) = self.server_misc_settings.push_provider.as_ref() {
+            provider
+                .send_push_wake(payload, implicated_cid, peer_cid)
+                .await
+        } else {
+            self.services_handler
+                .send_push_wake(payload, implicated_cid, peer_cid)
+                .await
+        }
+    }
+
     /// Once a valid and decrypted stage 4 packet gets received by the server (Bob), this function should be called
     /// to create the new CNAC. The generated CNAC will be assumed to be an impersonal hyperlan client
     ///
@@ -105,6 +323,8 @@ impl<R: Ratchet, Fcm: Ratchet> AccountManager<R, Fcm> {
         creds: ProposedCredentials,
         init_hyper_ratchet: R,
     ) -> Result<ClientNetworkAccount<R, Fcm>, AccountError> {
+        // checked before hashing, which is costly
+        creds.check_formatting(&self.server_misc_settings.credential_policy)?;
         let reserved_cid = self
             .persistence_handler
             .get_cid_by_username(creds.username());
@@ -125,6 +345,8 @@ impl<R: Ratchet, Fcm: Ratchet> AccountManager<R, Fcm> {
             )));
         }
 
+        let reservation = self.reserve_account_slot()?;
+
         // cnac gets saved below
         let new_cnac = ClientNetworkAccount::<R, Fcm>::new(
             reserved_cid,
@@ -132,14 +354,85 @@ impl<R: Ratchet, Fcm: Ratchet> AccountManager<R, Fcm> {
             conn_info,
             auth_store,
             init_hyper_ratchet,
+            &self.server_misc_settings.credential_policy,
         )
         .await?;
+        new_cnac.write().account_features =
+            self.server_misc_settings.default_account_features.clone();
+        new_cnac.set_peer_list_loading(self.server_misc_settings.peer_list_loading);
+        self.stamp_creation_date(&new_cnac);
         log::trace!(target: "citadel", "Created impersonal CNAC ...");
         self.persistence_handler.save_cnac(&new_cnac).await?;
+        if let Some(reservation) = reservation {
+            reservation.commit();
+        }
+        self.notify_listener(AccountEvent::Register(reserved_cid, &username))
+            .await;
 
         Ok(new_cnac)
     }
 
+    /// Like [`Self::register_impersonal_hyperlan_client_network_account`], but idempotent under
+    /// retries. Returns the CID of the client, along with true if the client was newly created. If
+    /// the username is already registered with credentials matching `creds`, its CID is returned
+    /// instead of an error. Since [`ProposedCredentials::new_register`] salts the password
+    /// randomly, a retry must reuse the credentials of the first attempt to match
+    pub async fn register_or_get(
+        &self,
+        conn_info: ConnectionInfo,
+        creds: ProposedCredentials,
+        init_hyper_ratchet: R,
+    ) -> Result<(u64, bool), AccountError> {
+        if let Some(cid) = self.find_matching_registration(&creds).await? {
+            return Ok((cid, false));
+        }
+
+        match self
+            .register_impersonal_hyperlan_client_network_account(
+                conn_info,
+                creds.clone(),
+                init_hyper_ratchet,
+            )
+            .await
+        {
+            Ok(cnac) => Ok((cnac.get_cid(), true)),
+            // a concurrent retry may have registered the username first
+            Err(err) => match self.find_matching_registration(&creds).await? {
+                Some(cid) => Ok((cid, false)),
+                None => Err(err),
+            },
+        }
+    }
+
+    /// Returns the CID registered to the username of `creds`, if any. Fails if the client was
+    /// registered with other credentials
+    async fn find_matching_registration(
+        &self,
+        creds: &ProposedCredentials,
+    ) -> Result<Option<u64>, AccountError> {
+        let username = creds.username();
+        let cnac = match self
+            .persistence_handler
+            .get_client_by_username(username)
+            .await?
+        {
+            Some(cnac) => cnac,
+            None => return Ok(None),
+        };
+
+        let conflict = || AccountError::Generic(format!("Username {username} already exists!"));
+        // passwordless credentials would otherwise validate against any account
+        if cnac.read().auth_store.is_passwordless() != creds.is_passwordless() {
+            return Err(conflict());
+        }
+
+        match cnac.validate_credentials(creds.clone()).await {
+            Ok(()) => Ok(Some(cnac.get_cid())),
+            Err(AccountError::InvalidUsername | AccountError::InvalidPassword) => Err(conflict()),
+            Err(err) => Err(err),
+        }
+    }
+
     /// whereas the HyperLAN server (Bob) runs `register_impersonal_hyperlan_client_network_account`, the registering
     /// HyperLAN Client (Alice) runs this function below
     pub async fn register_personal_hyperlan_server(
@@ -157,9 +450,14 @@ impl<R: Ratchet, Fcm: Ratchet> AccountManager<R, Fcm> {
             hyper_ratchet,
             client_auth_store,
             conn_info,
+            &self.server_misc_settings.credential_policy,
         )
         .await?;
+        cnac.set_peer_list_loading(self.server_misc_settings.peer_list_loading);
+        self.stamp_creation_date(&cnac);
         self.persistence_handler.save_cnac(&cnac).await?;
+        self.notify_listener(AccountEvent::Register(valid_cid, &cnac.get_username()))
+            .await;
 
         Ok(cnac)
     }
@@ -180,6 +478,30 @@ impl<R: Ratchet, Fcm: Ratchet> AccountManager<R, Fcm> {
             .await
     }
 
+    /// Returns the active impersonal cids created strictly after `created_after` and strictly
+    /// before `created_before`, where either bound may be omitted
+    pub async fn get_registered_impersonal_cids_created_between(
+        &self,
+        created_after: Option<DateTime<Utc>>,
+        created_before: Option<DateTime<Utc>>,
+        limit: Option<i32>,
+    ) -> Result<Option<Vec<u64>>, AccountError> {
+        self.persistence_handler
+            .get_registered_impersonal_cids_created_between(created_after, created_before, limit)
+            .await
+    }
+
+    /// Returns a page of impersonal cids in ascending order
+    pub async fn get_registered_impersonal_cids_paged(
+        &self,
+        offset: u64,
+        limit: u32,
+    ) -> Result<Vec<u64>, AccountError> {
+        self.persistence_handler
+            .get_registered_impersonal_cids_paged(offset, limit)
+            .await
+    }
+
     /// Returns the CNAC with the supplied CID
     pub async fn get_client_by_cid(
         &self,
@@ -204,9 +526,51 @@ impl<R: Ratchet, Fcm: Ratchet> AccountManager<R, Fcm> {
             .await
     }
 
-    /// Returns the number of accounts purged
+    /// Returns the number of accounts purged. Use [`Self::purge_preview`] to see which accounts
+    /// would be purged first
     pub async fn purge(&self) -> Result<usize, AccountError> {
-        self.persistence_handler.purge().await
+        let purged = self.persistence_handler.purge().await?;
+        self.sync_account_quota().await?;
+        Ok(purged)
+    }
+
+    /// Returns the metadata of every account [`Self::purge`] would delete, without deleting
+    /// anything
+    pub async fn purge_preview(&self) -> Result<Vec<CNACMetadata>, AccountError> {
+        self.persistence_handler.get_clients_metadata(None).await
+    }
+
+    /// Deletes only the accounts whose metadata matches `predicate`, such as test accounts.
+    /// Returns the number of accounts purged
+    pub async fn purge_where(
+        &self,
+        predicate: impl Fn(&CNACMetadata) -> bool,
+    ) -> Result<usize, AccountError> {
+        // matches are collected before deleting, so the stream is not read while it is mutated
+        let matches = self
+            .purge_preview()
+            .await?
+            .into_iter()
+            .filter(|metadata| predicate(metadata))
+            .map(|metadata| (metadata.cid, metadata.is_personal))
+            .collect::<Vec<(u64, bool)>>();
+
+        let mut purged = 0;
+        for (cid, is_personal) in matches {
+            match self.persistence_handler.delete_cnac_by_cid(cid).await {
+                Ok(()) => {
+                    purged += 1;
+                    if !is_personal {
+                        self.release_account_slot();
+                    }
+                }
+                // deleted concurrently
+                Err(AccountError::ClientNonExists(_)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(purged)
     }
 
     /// Does not execute the registration process between two peers; it only consolidates the changes to the local CNAC
@@ -221,7 +585,10 @@ impl<R: Ratchet, Fcm: Ratchet> AccountManager<R, Fcm> {
         log::trace!(target: "citadel", "Registering {} ({}) to {} (local/endpoints)", &adjacent_username, peer_cid, implicated_cid);
         self.persistence_handler
             .register_p2p_as_client(implicated_cid, peer_cid, adjacent_username)
-            .await
+            .await?;
+        self.notify_listener(AccountEvent::P2PRegistered(implicated_cid, peer_cid))
+            .await;
+        Ok(())
     }
 
     /// Registers the two accounts together at the server
@@ -272,13 +639,376 @@ impl<R: Ratchet, Fcm: Ratchet> AccountManager<R, Fcm> {
 
         self.persistence_handler
             .register_p2p_as_server(cid0, cid1)
-            .await
+            .await?;
+        self.notify_listener(AccountEvent::P2PRegistered(cid0, cid1))
+            .await;
+        Ok(())
     }
 
     /// Deletes a client by cid. Returns true if a success
     #[allow(unused_results)]
     pub async fn delete_client_by_cid(&self, cid: u64) -> Result<(), AccountError> {
-        self.persistence_handler.delete_cnac_by_cid(cid).await
+        let is_personal = match self.account_quota.as_ref() {
+            Some(_) => self
+                .persistence_handler
+                .get_client_metadata(cid)
+                .await?
+                .map(|metadata| metadata.is_personal),
+            None => None,
+        };
+
+        self.persistence_handler.delete_cnac_by_cid(cid).await?;
+        if is_personal == Some(false) {
+            self.release_account_slot();
+        }
+        self.notify_listener(AccountEvent::Deregister(cid)).await;
+        Ok(())
+    }
+
+    /// Deactivates a client by cid, preventing it from connecting while retaining its data
+    pub async fn deactivate_client_by_cid(&self, cid: u64) -> Result<(), AccountError> {
+        self.persistence_handler.deactivate_cnac(cid).await
+    }
+
+    /// Reactivates a client previously deactivated by cid
+    pub async fn reactivate_client_by_cid(&self, cid: u64) -> Result<(), AccountError> {
+        self.persistence_handler.reactivate_cnac(cid).await
+    }
+
+    /// Deletes every client deactivated before `cutoff`. Returns the number of clients deleted
+    pub async fn purge_deactivated_before(
+        &self,
+        cutoff: SystemTime,
+    ) -> Result<usize, AccountError> {
+        let purged = self
+            .persistence_handler
+            .purge_deactivated_before(cutoff)
+            .await?;
+        self.sync_account_quota().await?;
+        Ok(purged)
+    }
+
+    /// Returns the capabilities granted to a client by cid
+    pub async fn get_account_features(&self, cid: u64) -> Result<FeatureSet, AccountError> {
+        self.get_client_by_cid(cid)
+            .await?
+            .map(|cnac| cnac.read().account_features.clone())
+            .ok_or(AccountError::ClientNonExists(cid))
+    }
+
+    /// Replaces the capabilities granted to a client by cid. Sessions connected before the change
+    /// may continue to use the previous capabilities until they reconnect
+    pub async fn set_account_features(
+        &self,
+        cid: u64,
+        features: FeatureSet,
+    ) -> Result<(), AccountError> {
+        let cnac = self
+            .get_client_by_cid(cid)
+            .await?
+            .ok_or(AccountError::ClientNonExists(cid))?;
+        cnac.write().account_features = features;
+        self.persistence_handler.save_cnac(&cnac).await?;
+        self.notify_listener(AccountEvent::CnacSaved(cid)).await;
+        Ok(())
+    }
+
+    /// Moves the data of `old_cid` onto `new_cid`, where both CIDs are devices of the same account.
+    /// Every peer of the old device is re-registered to the new device (and removed from the old
+    /// one), and all unexpired byte map values are copied over. If `deregister_old` is true, the
+    /// old device is deleted from this node afterwards
+    pub async fn migrate_device(
+        &self,
+        old_cid: u64,
+        new_cid: u64,
+        deregister_old: bool,
+    ) -> Result<MigrationReport, AccountError> {
+        if old_cid == new_cid {
+            return Err(AccountError::msg("Cannot migrate a device onto itself"));
+        }
+
+        let old = self
+            .get_client_by_cid(old_cid)
+            .await?
+            .ok_or(AccountError::ClientNonExists(old_cid))?
+            .get_metadata();
+        let new = self
+            .get_client_by_cid(new_cid)
+            .await?
+            .ok_or(AccountError::ClientNonExists(new_cid))?
+            .get_metadata();
+
+        if old.full_name != new.full_name || old.is_personal != new.is_personal {
+            return Err(AccountError::msg(format!(
+                "Clients {old_cid} and {new_cid} do not belong to the same account"
+            )));
+        }
+
+        let existing_peers = self
+            .persistence_handler
+            .get_hyperlan_peer_list_as_server(new_cid)
+            .await?
+            .unwrap_or_default()
+            .into_iter()
+            .map(|peer| peer.cid)
+            .collect::<Vec<u64>>();
+        let old_peers = self
+            .persistence_handler
+            .get_hyperlan_peer_list_as_server(old_cid)
+            .await?
+            .unwrap_or_default();
+
+        let mut report = MigrationReport::default();
+        for peer in old_peers {
+            if peer.cid != new_cid && !existing_peers.contains(&peer.cid) {
+                self.persistence_handler
+                    .register_p2p_as_server(new_cid, peer.cid)
+                    .await?;
+                self.notify_listener(AccountEvent::P2PRegistered(new_cid, peer.cid))
+                    .await;
+                report.peers_migrated.push(peer.cid);
+            }
+
+            self.persistence_handler
+                .deregister_p2p_as_server(old_cid, peer.cid)
+                .await?;
+            self.notify_listener(AccountEvent::P2PDeregistered(old_cid, peer.cid))
+                .await;
+        }
+
+        report.byte_map_entries_copied = self
+            .persistence_handler
+            .copy_byte_map(old_cid, new_cid)
+            .await?;
+
+        if deregister_old {
+            self.persistence_handler.delete_cnac_by_cid(old_cid).await?;
+            if !old.is_personal {
+                self.release_account_slot();
+            }
+            self.notify_listener(AccountEvent::Deregister(old_cid))
+                .await;
+            report.old_deregistered = true;
+        }
+
+        log::trace!(target: "citadel", "Migrated {} to {}: {:?}", old_cid, new_cid, report);
+        Ok(report)
+    }
+
+    /// Exports `cid` along with its peers and byte map as a versioned blob encrypted with
+    /// [`ServerMiscSettings::account_export_key`], to be restored with [`Self::import_cnac`] on this
+    /// or another node, such as when a server is migrated
+    pub async fn export_cnac(&self, cid: u64) -> Result<Vec<u8>, AccountError> {
+        let key = self.account_export_key()?;
+        let cnac = self
+            .get_client_by_cid(cid)
+            .await?
+            .ok_or(AccountError::ClientNonExists(cid))?;
+        let peers = self
+            .persistence_handler
+            .get_hyperlan_peer_list_as_server(cid)
+            .await?
+            .unwrap_or_default();
+
+        let mut byte_maps = Vec::with_capacity(peers.len() + 1);
+        for peer_cid in std::iter::once(0).chain(peers.iter().map(|peer| peer.cid)) {
+            // internal values, such as group records, are carried along with the account
+            let byte_map =
+                export_byte_map::<R, Fcm, _>(&**self.persistence_handler, cid, peer_cid, true)
+                    .await?;
+            byte_maps.push((peer_cid, byte_map));
+        }
+
+        CnacExport {
+            cid,
+            cnac: cnac.generate_proper_bytes()?,
+            peers,
+            byte_maps,
+        }
+        .seal(key)
+    }
+
+    /// Recreates the client exported by [`Self::export_cnac`], returning its CID. Fails if the CID
+    /// or its username is already registered to this node. Peers that are not registered to this
+    /// node are skipped, along with their byte maps
+    pub async fn import_cnac(&self, blob: &[u8]) -> Result<u64, AccountError> {
+        let export = CnacExport::open(blob, self.account_export_key()?)?;
+        let cid = export.cid;
+        let pers = &self.persistence_handler;
+        if pers.cid_is_registered(cid).await? {
+            return Err(AccountError::ClientExists(cid));
+        }
+
+        let mut inner =
+            ClientNetworkAccountInner::<R, Fcm>::deserialize_from_owned_vector(export.cnac)?;
+        let username = inner.auth_store.username().to_string();
+        if pers.username_exists(&username).await? {
+            return Err(AccountError::Generic(format!(
+                "Username {username} already exists!"
+            )));
+        }
+
+        // the peers and byte maps are restored below, so that both sides of each relationship
+        // are restored, whichever backend stores them
+        inner.mutuals.replace(HYPERLAN_IDX, Vec::new());
+        inner.byte_map.clear();
+        let reservation = if inner.is_local_personal {
+            None
+        } else {
+            self.reserve_account_slot()?
+        };
+        let cnac = ClientNetworkAccount::from(inner);
+        pers.save_cnac(&cnac).await?;
+        if let Some(reservation) = reservation {
+            reservation.commit();
+        }
+        self.notify_listener(AccountEvent::Register(cid, &username))
+            .await;
+
+        let mut restored_peers = vec![0];
+        for peer in &export.peers {
+            if !pers.cid_is_registered(peer.cid).await? {
+                log::warn!(target: "citadel", "Skipping peer {} of imported client {}: not registered to this node", peer.cid, cid);
+                continue;
+            }
+
+            pers.register_p2p_as_server(cid, peer.cid).await?;
+            self.notify_listener(AccountEvent::P2PRegistered(cid, peer.cid))
+                .await;
+            restored_peers.push(peer.cid);
+        }
+
+        for (peer_cid, byte_map) in &export.byte_maps {
+            if restored_peers.contains(peer_cid) {
+                let _ =
+                    import_byte_map::<R, Fcm, _>(&***pers, cid, *peer_cid, byte_map, false, true)
+                        .await?;
+            }
+        }
+
+        Ok(cid)
+    }
+
+    /// Changes the username of `cid`, checking `new_username` against the credential policy of this
+    /// node. See [`BackendConnection::change_username`]
+    pub async fn change_username(&self, cid: u64, new_username: &str) -> Result<(), AccountError> {
+        self.persistence_handler
+            .change_username(
+                cid,
+                new_username,
+                &self.server_misc_settings.credential_policy,
+            )
+            .await?;
+        self.notify_listener(AccountEvent::CnacSaved(cid)).await;
+        Ok(())
+    }
+
+    /// Renames every client whose username differs from the one `resolver` returns for its CID,
+    /// for deployments where usernames are authoritative in an external directory. Usernames are
+    /// compared ignoring case, so a client is not reported as conflicting with itself. Clients keep
+    /// their CID, and thus remain reachable by the username it was derived from
+    pub async fn reconcile_usernames<F, Fut>(
+        &self,
+        resolver: F,
+    ) -> Result<ReconcileReport, AccountError>
+    where
+        F: Fn(u64) -> Fut,
+        Fut: Future<Output = Option<String>>,
+    {
+        let mut clients = self.persistence_handler.get_clients_metadata(None).await?;
+        clients.sort_unstable_by_key(|metadata| metadata.cid);
+        let mut holders = clients
+            .iter()
+            .map(|metadata| (normalize_username(&metadata.username), metadata.cid))
+            .collect::<HashMap<String, u64>>();
+
+        let mut report = ReconcileReport::default();
+        for metadata in clients {
+            let cid = metadata.cid;
+            let username = match resolver(cid).await {
+                Some(username) if !username.trim().is_empty() => username.trim().to_string(),
+                _ => {
+                    report.unresolved.push(cid);
+                    continue;
+                }
+            };
+
+            if username == metadata.username {
+                continue;
+            }
+
+            let normalized = normalize_username(&username);
+            match holders.get(&normalized) {
+                Some(holder_cid) if *holder_cid != cid => {
+                    log::warn!(target: "citadel", "Unable to rename {} to {}: held by {}", cid, username, holder_cid);
+                    report.conflicts.push(UsernameConflict {
+                        cid,
+                        username,
+                        holder_cid: *holder_cid,
+                    });
+                    continue;
+                }
+
+                _ => {}
+            }
+
+            let cnac = self
+                .get_client_by_cid(cid)
+                .await?
+                .ok_or(AccountError::ClientNonExists(cid))?;
+            cnac.set_username(username.as_str());
+            self.persistence_handler.save_cnac(&cnac).await?;
+            self.notify_listener(AccountEvent::CnacSaved(cid)).await;
+
+            let _ = holders.remove(&normalize_username(&metadata.username));
+            let _ = holders.insert(normalized, cid);
+            log::trace!(target: "citadel", "Renamed {} from {} to {}", cid, metadata.username, username);
+            report.renamed.push(cid);
+        }
+
+        Ok(report)
+    }
+
+    /// Returns each username held by more than one client, along with the CIDs holding it, so
+    /// that operators may resolve the conflicts. See [`BackendConnection::find_duplicate_usernames`]
+    pub async fn find_duplicate_usernames(&self) -> Result<Vec<(String, Vec<u64>)>, AccountError> {
+        self.persistence_handler.find_duplicate_usernames().await
+    }
+
+    /// Records the present time as the last successful connect of `cid`
+    pub async fn record_connect(&self, cid: u64) -> Result<(), AccountError> {
+        self.persistence_handler
+            .set_last_connect(cid, Utc::now())
+            .await
+    }
+
+    /// Returns the time `cid` last connected successfully, or None if it never has
+    pub async fn get_last_connect(&self, cid: u64) -> Result<Option<DateTime<Utc>>, AccountError> {
+        self.persistence_handler.get_last_connect(cid).await
+    }
+
+    /// Returns the time each of `cids` last connected successfully. Clients that never connected
+    /// are omitted
+    pub async fn get_last_connects(
+        &self,
+        cids: &[u64],
+    ) -> Result<HashMap<u64, DateTime<Utc>>, AccountError> {
+        self.persistence_handler.get_last_connects(cids).await
+    }
+
+    /// Stores the APNs keys of `cid`, through which its device is woken instead of the configured
+    /// push provider. Passing `None` removes them
+    pub async fn update_apns_keys(
+        &self,
+        cid: u64,
+        keys: Option<ApnsKeys>,
+    ) -> Result<(), AccountError> {
+        self.persistence_handler.update_apns_keys(cid, keys).await
+    }
+
+    /// Returns the APNs keys of `cid`, or None if none were stored
+    pub async fn get_apns_keys(&self, cid: u64) -> Result<Option<ApnsKeys>, AccountError> {
+        self.persistence_handler.get_apns_keys(cid).await
     }
 
     /// Gets a list of hyperlan peers for the given peer
@@ -291,6 +1021,32 @@ impl<R: Ratchet, Fcm: Ratchet> AccountManager<R, Fcm> {
             .await
     }
 
+    /// Returns the number of hyperlan peers for the given peer, without fetching the list
+    pub async fn get_hyperlan_peer_count(
+        &self,
+        implicated_cid: u64,
+    ) -> Result<usize, AccountError> {
+        self.persistence_handler
+            .get_hyperlan_peer_count(implicated_cid)
+            .await
+    }
+
+    /// Returns every group the client is a member of
+    pub async fn get_client_groups(&self, cid: u64) -> Result<Vec<GroupInfo>, AccountError> {
+        self.persistence_handler.get_client_groups(cid).await
+    }
+
+    /// Returns the members of the group `group_id` owned by `owner_cid`, or None if the group does not exist
+    pub async fn get_group_members(
+        &self,
+        owner_cid: u64,
+        group_id: u128,
+    ) -> Result<Option<Vec<u64>>, AccountError> {
+        self.persistence_handler
+            .get_group_members(owner_cid, group_id)
+            .await
+    }
+
     /// Finds a hyperlan peer for a given user. Returns the implicated CID and mutual peer info
     pub async fn find_target_information(
         &self,
@@ -349,12 +1105,76 @@ impl<R: Ratchet, Fcm: Ratchet> AccountManager<R, Fcm> {
         }
     }
 
+    /// Reserves a slot for a new impersonal account if [`ServerMiscSettings::max_registered_accounts`]
+    /// is set, failing if the node is full
+    fn reserve_account_slot(&self) -> Result<Option<QuotaReservation<'_>>, AccountError> {
+        self.account_quota
+            .as_deref()
+            .map(AccountQuota::try_reserve)
+            .transpose()
+    }
+
+    /// Frees the slot of a deleted impersonal account
+    fn release_account_slot(&self) {
+        if let Some(quota) = self.account_quota.as_ref() {
+            quota.release(1);
+        }
+    }
+
+    /// Recounts the impersonal accounts in the backend once many may have been deleted at once
+    async fn sync_account_quota(&self) -> Result<(), AccountError> {
+        if let Some(quota) = self.account_quota.as_ref() {
+            quota.reset(count_registered_accounts(&self.persistence_handler).await?);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of impersonal accounts counted towards
+    /// [`ServerMiscSettings::max_registered_accounts`], or `None` if no cap is set
+    pub fn registered_account_count(&self) -> Option<usize> {
+        self.account_quota.as_ref().map(|quota| quota.registered())
+    }
+
+    async fn notify_listener(&self, event: AccountEvent<'_>) {
+        if let Some(listener) = self.server_misc_settings.account_listener.as_ref() {
+            let result = match event {
+                AccountEvent::Register(cid, username) => listener.on_register(cid, username).await,
+                AccountEvent::Deregister(cid) => listener.on_deregister(cid).await,
+                AccountEvent::P2PRegistered(cid, peer_cid) => {
+                    listener.on_p2p_registered(cid, peer_cid).await
+                }
+                AccountEvent::P2PDeregistered(cid, peer_cid) => {
+                    listener.on_p2p_deregistered(cid, peer_cid).await
+                }
+                AccountEvent::CnacSaved(cid) => listener.on_cnac_saved(cid).await,
+            };
+
+            if let Err(err) = result {
+                log::warn!(target: "citadel", "Account listener failed to handle {:?}: {:?}", event, err);
+            }
+        }
+    }
+
+    /// Flushes any pending writes to the backend, then releases its connections. Should be called
+    /// once the node stops, since neither this account manager nor its clones may be used afterwards
+    pub async fn shutdown(&self) -> Result<(), AccountError> {
+        self.persistence_handler.disconnect().await
+    }
+
     /// Returns the persistence handler
     #[doc(hidden)]
     pub fn get_persistence_handler(&self) -> &PersistenceHandler<R, Fcm> {
         &self.persistence_handler
     }
 
+    fn account_export_key(&self) -> Result<&[u8; 32], AccountError> {
+        self.server_misc_settings
+            .account_export_key
+            .as_ref()
+            .ok_or_else(|| AccountError::msg("No account export key is set"))
+    }
+
     /// Returns the misc settings
     pub fn get_misc_settings(&self) -> &ServerMiscSettings {
         &self.server_misc_settings
@@ -365,3 +1185,16 @@ impl<R: Ratchet, Fcm: Ratchet> AccountManager<R, Fcm> {
         &self.backend_ty
     }
 }
+
+/// Counts the impersonal accounts, deactivated or not, towards
+/// [`ServerMiscSettings::max_registered_accounts`]
+async fn count_registered_accounts<R: Ratchet, Fcm: Ratchet>(
+    persistence_handler: &PersistenceHandler<R, Fcm>,
+) -> Result<usize, AccountError> {
+    Ok(persistence_handler
+        .get_clients_metadata(None)
+        .await?
+        .into_iter()
+        .filter(|metadata| !metadata.is_personal)
+        .count())
+}
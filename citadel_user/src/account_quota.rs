@@ -0,0 +1,129 @@
+use crate::misc::AccountError;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Caps the number of impersonal accounts registered to a node. The count is cached, so that
+/// concurrent registrations each reserve a slot atomically instead of counting the backend
+pub(crate) struct AccountQuota {
+    max: usize,
+    registered: AtomicUsize,
+}
+
+/// A slot reserved by [`AccountQuota::try_reserve`]. Unless committed once the account is saved,
+/// the slot is released on drop, such as when the registration fails
+pub(crate) struct QuotaReservation<'a> {
+    quota: &'a AccountQuota,
+    committed: bool,
+}
+
+impl AccountQuota {
+    pub fn new(max: usize, registered: usize) -> Self {
+        Self {
+            max,
+            registered: AtomicUsize::new(registered),
+        }
+    }
+
+    /// Reserves a slot for one more account, failing with [`AccountError::QuotaExceeded`] if every
+    /// slot is taken
+    pub fn try_reserve(&self) -> Result<QuotaReservation<'_>, AccountError> {
+        self.registered
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |registered| {
+                (registered < self.max).then_some(registered + 1)
+            })
+            .map_err(|_| AccountError::QuotaExceeded(self.max))?;
+
+        Ok(QuotaReservation {
+            quota: self,
+            committed: false,
+        })
+    }
+
+    /// Frees the slots of `count` deleted accounts
+    pub fn release(&self, count: usize) {
+        let _ = self
+            .registered
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |registered| {
+                Some(registered.saturating_sub(count))
+            });
+    }
+
+    /// Overwrites the cached count, such as after many accounts are deleted at once
+    pub fn reset(&self, registered: usize) {
+        self.registered.store(registered, Ordering::SeqCst);
+    }
+
+    pub fn registered(&self) -> usize {
+        self.registered.load(Ordering::SeqCst)
+    }
+}
+
+impl QuotaReservation<'_> {
+    /// Keeps the slot taken by the newly saved account
+    pub fn commit(mut self) {
+        self.committed = true;
+    }
+}
+
+impl Drop for QuotaReservation<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.quota.release(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::account_quota::AccountQuota;
+    use crate::misc::AccountError;
+    use std::sync::Arc;
+
+    #[test]
+    fn rejects_reservations_past_the_max() {
+        let quota = AccountQuota::new(3, 1);
+        quota.try_reserve().unwrap().commit();
+        quota.try_reserve().unwrap().commit();
+        assert!(matches!(
+            quota.try_reserve(),
+            Err(AccountError::QuotaExceeded(3))
+        ));
+
+        quota.release(1);
+        quota.try_reserve().unwrap().commit();
+        assert_eq!(quota.registered(), 3);
+    }
+
+    #[test]
+    fn uncommitted_reservations_are_released() {
+        let quota = AccountQuota::new(1, 0);
+        drop(quota.try_reserve().unwrap());
+        assert_eq!(quota.registered(), 0);
+        quota.try_reserve().unwrap().commit();
+        assert_eq!(quota.registered(), 1);
+    }
+
+    #[test]
+    fn concurrent_reservations_never_exceed_the_max() {
+        let quota = Arc::new(AccountQuota::new(10, 0));
+        let threads = (0..32)
+            .map(|_| {
+                let quota = quota.clone();
+                std::thread::spawn(move || {
+                    quota
+                        .try_reserve()
+                        .map(|reservation| reservation.commit())
+                        .is_ok()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let reserved = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .filter(|reserved| *reserved)
+            .count();
+
+        assert_eq!(reserved, 10);
+        assert_eq!(quota.registered(), 10);
+    }
+}
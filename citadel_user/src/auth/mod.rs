@@ -1,5 +1,9 @@
 #![allow(missing_docs, dead_code)]
-use citadel_crypt::argon::argon_container::ArgonContainerType;
+use crate::misc::AccountError;
+use citadel_crypt::argon::argon_container::{
+    ArgonContainerType, ArgonSettings, ArgonStatus, AsyncArgon, ServerArgonContainer,
+};
+use citadel_crypt::prelude::SecBuffer;
 use serde::{Deserialize, Serialize};
 
 /// For handling misc requirements
@@ -27,6 +31,13 @@ impl DeclaredAuthenticationMode {
         }
     }
 
+    pub fn set_username(&mut self, new_username: String) {
+        match self {
+            Self::Argon { username, .. } => *username = new_username,
+            Self::Passwordless { username, .. } => *username = new_username,
+        }
+    }
+
     pub fn full_name(&self) -> &str {
         match self {
             Self::Argon { full_name, .. } => full_name.as_str(),
@@ -48,3 +59,42 @@ impl DeclaredAuthenticationMode {
         }
     }
 }
+
+/// Hashes the password with Argon2id using the memory and time costs of `settings`. The returned
+/// container holds only the salt, the settings and the hash, and is what gets persisted in place of
+/// the password
+pub async fn hash_password(
+    password: SecBuffer,
+    settings: ArgonSettings,
+) -> Result<ServerArgonContainer, AccountError> {
+    match AsyncArgon::hash(password, settings.clone())
+        .await
+        .map_err(|err| AccountError::Generic(err.message))?
+    {
+        ArgonStatus::HashSuccess(hash) => Ok(ServerArgonContainer::new(settings, hash)),
+        ArgonStatus::HashFailed(err) => Err(AccountError::Generic(format!(
+            "Unable to hash password: {err}"
+        ))),
+        _ => Err(AccountError::Generic("Unable to hash password".to_string())),
+    }
+}
+
+/// Checks the `candidate` password against a container produced by [`hash_password`]. Returns
+/// Ok(false) if the password does not match
+pub async fn verify_password(
+    hash: ServerArgonContainer,
+    candidate: SecBuffer,
+) -> Result<bool, AccountError> {
+    match AsyncArgon::verify(candidate, hash)
+        .await
+        .map_err(|err| AccountError::Generic(err.message))?
+    {
+        ArgonStatus::VerificationSuccess => Ok(true),
+        ArgonStatus::VerificationFailed(None) => Ok(false),
+        ArgonStatus::VerificationFailed(Some(err)) => {
+            log::error!(target: "citadel", "Password verification failed: {}", &err);
+            Err(AccountError::Generic(err))
+        }
+        _ => Ok(false),
+    }
+}
@@ -1,15 +1,19 @@
+use crate::auth;
 use crate::auth::DeclaredAuthenticationMode;
-use crate::misc::AccountError;
+use crate::misc::{check_credential_formatting_with, AccountError, CredentialPolicy};
 use crate::server_misc_settings::ServerMiscSettings;
 use bstr::ByteSlice;
 use citadel_crypt::argon::argon_container::{
-    ArgonContainerType, ArgonSettings, ArgonStatus, AsyncArgon, ServerArgonContainer,
+    ArgonContainerType, ArgonSettings, ArgonStatus, AsyncArgon,
 };
 use citadel_crypt::prelude::SecBuffer;
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use sha3::Digest;
 
+/// The full name given to passwordless clients
+const PASSWORDLESS_FULL_NAME: &str = "authless.client";
+
 /// When creating credentials, this is required
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(variant_size_differences)]
@@ -88,6 +92,23 @@ impl ProposedCredentials {
         })
     }
 
+    /// Same as [`Self::new_register`], but first checks the trimmed credentials against `policy`.
+    /// Since the server only receives the hash of the password, the password rules of `policy`
+    /// are enforced here, before the password is hashed
+    pub async fn new_register_with_policy<T: Into<String> + Send, R: Into<String> + Send>(
+        policy: &CredentialPolicy,
+        full_name: T,
+        username: R,
+        password_unhashed: SecBuffer,
+    ) -> Result<Self, AccountError> {
+        let (username, full_name, password_unhashed) =
+            Self::sanitize_and_prepare(username, full_name, password_unhashed.as_ref(), true);
+        let password = std::str::from_utf8(password_unhashed.as_ref())
+            .map_err(|_| AccountError::msg("Password must be valid UTF-8"))?;
+        check_credential_formatting_with(policy, &username, Some(password), &full_name)?;
+        Self::new_register(full_name, username, password_unhashed).await
+    }
+
     async fn argon_hash(
         password_unhashed: SecBuffer,
         settings: ArgonSettings,
@@ -155,7 +176,7 @@ impl ProposedCredentials {
         match self {
             Self::Disabled { username } => DeclaredAuthenticationMode::Passwordless {
                 username,
-                full_name: "authless.client".to_string(),
+                full_name: PASSWORDLESS_FULL_NAME.to_string(),
             },
             Self::Enabled {
                 username,
@@ -190,6 +211,25 @@ impl ProposedCredentials {
 
 // Serverside impls
 impl ProposedCredentials {
+    /// Checks the username and full name against `policy`. Called by the server before the
+    /// credentials are hashed, so that malformed registrations are rejected cheaply. The password
+    /// arrives hashed, so its rules are checked client-side by [`Self::new_register_with_policy`]
+    pub fn check_formatting(&self, policy: &CredentialPolicy) -> Result<(), AccountError> {
+        match self {
+            Self::Enabled {
+                username,
+                full_name,
+                ..
+            } => check_credential_formatting_with::<_, &str, _>(policy, username, None, full_name),
+            Self::Disabled { username } => check_credential_formatting_with::<_, &str, _>(
+                policy,
+                username,
+                None,
+                PASSWORDLESS_FULL_NAME,
+            ),
+        }
+    }
+
     /// Called when the server registers the client-provided credentials
     pub async fn derive_server_container(
         self,
@@ -216,20 +256,12 @@ impl ProposedCredentials {
                 let settings =
                     server_argon_settings.derive_new_with_custom_ad(username.clone().into_bytes());
 
-                match AsyncArgon::hash(password_hashed, settings.clone())
-                    .await
-                    .map_err(|err| AccountError::Generic(err.message))?
-                {
-                    ArgonStatus::HashSuccess(hash_x2) => Ok(DeclaredAuthenticationMode::Argon {
-                        username,
-                        full_name,
-                        argon: ArgonContainerType::Server(ServerArgonContainer::new(
-                            settings, hash_x2,
-                        )),
-                    }),
-
-                    _ => Err(AccountError::Generic("Unable to hash password".to_string())),
-                }
+                let argon = auth::hash_password(password_hashed, settings).await?;
+                Ok(DeclaredAuthenticationMode::Argon {
+                    username,
+                    full_name,
+                    argon: ArgonContainerType::Server(argon),
+                })
             }
         }
     }
@@ -247,23 +279,11 @@ impl ProposedCredentials {
 
         match argon_container {
             ArgonContainerType::Server(server_container) => {
-                match AsyncArgon::verify(password_hashed, server_container)
-                    .await
-                    .map_err(|err| AccountError::Generic(err.message))?
-                {
-                    ArgonStatus::VerificationSuccess => Ok(()),
-
-                    ArgonStatus::VerificationFailed(None) => {
-                        log::warn!(target: "citadel", "Invalid password specified ...");
-                        Err(AccountError::InvalidPassword)
-                    }
-
-                    ArgonStatus::VerificationFailed(Some(err)) => {
-                        log::error!(target: "citadel", "Password verification failed: {}", &err);
-                        Err(AccountError::Generic(err))
-                    }
-
-                    _ => Err(AccountError::InvalidPassword),
+                if auth::verify_password(server_container, password_hashed).await? {
+                    Ok(())
+                } else {
+                    log::warn!(target: "citadel", "Invalid password specified ...");
+                    Err(AccountError::InvalidPassword)
                 }
             }
 
@@ -1,29 +1,48 @@
 use super::utils::StreamableTargetInformation;
 use crate::account_loader::load_cnac_files;
-use crate::backend::memory::MemoryBackend;
+use crate::backend::memory::{MemoryBackend, UsernameIndex};
+use crate::backend::metrics::{BackendMetrics, BackendMetricsRecorder};
 use crate::backend::utils::{ObjectTransferStatus, VirtualObjectMetadata};
-use crate::backend::BackendConnection;
+use crate::backend::{check_username_change, BackendConnection};
 use crate::client_account::{ClientNetworkAccount, MutualPeer};
-use crate::directory_store::DirectoryStore;
-use crate::misc::{AccountError, CNACMetadata};
+use crate::directory_store::{BasePath, DirectoryStore};
+use crate::misc::{AccountError, ByteMapEntry, CNACMetadata, ClientSummary, CredentialPolicy};
+use crate::peer_list::PeerListDelta;
 use crate::prelude::CNAC_SERIALIZED_EXTENSION;
 use crate::serialization::SyncIO;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use citadel_crypt::misc::TransferType;
 use citadel_crypt::prelude::SecurityLevel;
 use citadel_crypt::stacked_ratchet::Ratchet;
 use citadel_crypt::streaming_crypt_scrambler::ObjectSource;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use parking_lot::{Mutex, MutexGuard};
+use sha3::Digest;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
-use tokio_stream::StreamExt;
+
+/// The directory, relative to the virtual directory, holding deduplicated RE-VFS blobs
+const REVFS_DEDUP_DIR: &str = ".dedup";
+/// The file, relative to the config directory, holding the expiration times of byte map values
+const BYTE_MAP_EXPIRIES_FILE: &str = "byte_map_expiries";
+/// The file, relative to the config directory, holding the deactivation times of deactivated clients
+const DEACTIVATIONS_FILE: &str = "deactivations";
+/// The file, relative to the config directory, holding the time each client last connected
+const LAST_CONNECTS_FILE: &str = "last_connects";
 
 /// For handling I/O with the local filesystem
 pub struct FilesystemBackend<R: Ratchet, Fcm: Ratchet> {
     memory_backend: MemoryBackend<R, Fcm>,
     directory_store: Option<DirectoryStore>,
     home_dir: String,
+    revfs_deduplication: bool,
+    revfs_dedup_lock: Mutex<()>,
+    metrics: BackendMetricsRecorder,
 }
 
 #[async_trait]
@@ -31,8 +50,56 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for FilesystemBackend<R
     async fn connect(&mut self) -> Result<(), AccountError> {
         let directory_store = crate::directory_store::setup_directories(self.home_dir.clone())?;
         let map = load_cnac_files(&directory_store)?;
+        // the username index is not saved, but rebuilt from the loaded clients
+        *self.memory_backend.usernames.get_mut() = UsernameIndex::from_clients(&map);
         // ensure the in-memory database has the clients loaded
         *self.memory_backend.clients.get_mut() = map;
+        let expiries_path = directory_store.make_path(BasePath::ConfigDir, BYTE_MAP_EXPIRIES_FILE);
+        if expiries_path.exists() {
+            let bytes = std::fs::read(&expiries_path).map_err(|err| {
+                AccountError::io(err, format!("Unable to read {}", expiries_path.display()))
+            })?;
+            *self.memory_backend.byte_map_expiries.get_mut() =
+                SyncIO::deserialize_from_vector(&bytes)?;
+        }
+        let last_connects_path = directory_store.make_path(BasePath::ConfigDir, LAST_CONNECTS_FILE);
+        if last_connects_path.exists() {
+            let bytes = std::fs::read(&last_connects_path).map_err(|err| {
+                AccountError::io(
+                    err,
+                    format!("Unable to read {}", last_connects_path.display()),
+                )
+            })?;
+            *self.memory_backend.last_connects.get_mut() = SyncIO::deserialize_from_vector(&bytes)?;
+        }
+        let deactivations_path = directory_store.make_path(BasePath::ConfigDir, DEACTIVATIONS_FILE);
+        let mut deactivations: HashMap<u64, SystemTime> = if deactivations_path.exists() {
+            let bytes = std::fs::read(&deactivations_path).map_err(|err| {
+                AccountError::io(
+                    err,
+                    format!("Unable to read {}", deactivations_path.display()),
+                )
+            })?;
+            SyncIO::deserialize_from_vector(&bytes)?
+        } else {
+            HashMap::new()
+        };
+        // the location of the saved CNAC is authoritative, since the process may have stopped
+        // between moving the file and saving the deactivation times
+        let deactivated_cids = std::fs::read_dir(directory_store.nac_dir_deactivated.as_str())
+            .map_err(|err| {
+                AccountError::io(
+                    err,
+                    format!("Unable to read {}", directory_store.nac_dir_deactivated),
+                )
+            })?
+            .filter_map(|entry| get_cid_from_cnac_path(&entry.ok()?.path()))
+            .collect::<Vec<u64>>();
+        deactivations.retain(|cid, _| deactivated_cids.contains(cid));
+        for cid in deactivated_cids {
+            let _ = deactivations.entry(cid).or_insert_with(SystemTime::now);
+        }
+        *self.memory_backend.deactivated.get_mut() = deactivations;
         self.directory_store = Some(directory_store);
 
         Ok(())
@@ -42,14 +109,37 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for FilesystemBackend<R
         Ok(true)
     }
 
+    async fn disconnect(&self) -> Result<(), AccountError> {
+        self.save_all().await
+    }
+
+    fn metrics(&self) -> BackendMetrics {
+        self.metrics.snapshot()
+    }
+
     #[allow(unused_results)]
     async fn save_cnac(&self, cnac: &ClientNetworkAccount<R, Fcm>) -> Result<(), AccountError> {
         // save to filesystem, then, synchronize to memory
         let bytes = cnac.generate_proper_bytes()?;
         let cid = cnac.get_cid();
         let path = self.generate_cnac_local_save_path(cid, cnac.is_personal());
+        // the username is claimed first, so that a CNAC holding a taken username is never written
+        let previous_username = self
+            .memory_backend
+            .usernames
+            .write()
+            .claim(cid, &cnac.get_username())?;
         // TODO: The below line of code fails
-        std::fs::write(path, bytes).map_err(|err| AccountError::Generic(err.to_string()))?;
+        if let Err(err) = std::fs::write(&path, bytes) {
+            self.memory_backend
+                .usernames
+                .write()
+                .restore(cid, previous_username);
+            return Err(AccountError::io(
+                err,
+                format!("Unable to save CNAC to {}", path.display()),
+            ));
+        }
         self.memory_backend.save_cnac(cnac).await
     }
 
@@ -64,6 +154,10 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for FilesystemBackend<R
         self.memory_backend.cid_is_registered(cid).await
     }
 
+    async fn is_personal(&self, cid: u64) -> Result<Option<bool>, AccountError> {
+        self.memory_backend.is_personal(cid).await
+    }
+
     async fn delete_cnac_by_cid(&self, cid: u64) -> Result<(), AccountError> {
         let is_personal = self
             .memory_backend
@@ -72,37 +166,100 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for FilesystemBackend<R
             .get(&cid)
             .ok_or(AccountError::ClientNonExists(cid))?
             .is_personal();
-        self.memory_backend.delete_cnac_by_cid(cid).await?;
+        // the path depends upon the deactivation state, so it must be generated before deletion
         let path = self.generate_cnac_local_save_path(cid, is_personal);
-        std::fs::remove_file(path).map_err(|err| AccountError::Generic(err.to_string()))
+        self.memory_backend.delete_cnac_by_cid(cid).await?;
+        self.save_byte_map_expiries()?;
+        self.save_deactivations()?;
+        self.save_last_connects()?;
+        std::fs::remove_file(&path).map_err(|err| {
+            AccountError::io(err, format!("Unable to delete CNAC at {}", path.display()))
+        })
     }
 
     async fn purge(&self) -> Result<usize, AccountError> {
         let paths = {
             let mut write = self.memory_backend.clients.write();
-            write
-                .drain()
+            let paths = std::mem::take(&mut *write)
+                .into_iter()
                 .map(|(cid, cnac)| self.generate_cnac_local_save_path(cid, cnac.is_personal()))
-                .collect::<Vec<PathBuf>>()
+                .collect::<Vec<PathBuf>>();
+            self.memory_backend.deactivated.write().clear();
+            self.memory_backend.last_connects.write().clear();
+            *self.memory_backend.usernames.write() = UsernameIndex::default();
+            paths
         };
 
         let count = paths.len();
 
         for path in paths {
-            tokio::fs::remove_file(path)
-                .await
-                .map_err(|err| AccountError::Generic(err.to_string()))?;
+            tokio::fs::remove_file(&path).await.map_err(|err| {
+                AccountError::io(err, format!("Unable to delete CNAC at {}", path.display()))
+            })?;
         }
 
         // delete the home directory
         let home_dir = self.directory_store.as_ref().unwrap().home.as_str();
         tokio::fs::remove_dir_all(home_dir)
             .await
-            .map_err(|err| AccountError::Generic(err.to_string()))?;
+            .map_err(|err| AccountError::io(err, format!("Unable to delete {home_dir}")))?;
 
         Ok(count)
     }
 
+    async fn deactivate_cnac(&self, cid: u64) -> Result<(), AccountError> {
+        if !self.memory_backend.cnac_is_active(cid).await? {
+            return Ok(());
+        }
+
+        let is_personal = self.cnac_is_personal(cid)?;
+        let active_path = self.generate_cnac_local_save_path(cid, is_personal);
+        self.memory_backend.deactivate_cnac(cid).await?;
+        let deactivated_path = self.generate_cnac_local_save_path(cid, is_personal);
+        std::fs::rename(&active_path, &deactivated_path).map_err(|err| {
+            AccountError::io(
+                err,
+                format!(
+                    "Unable to move {} to {}",
+                    active_path.display(),
+                    deactivated_path.display()
+                ),
+            )
+        })?;
+        self.save_deactivations()
+    }
+
+    async fn reactivate_cnac(&self, cid: u64) -> Result<(), AccountError> {
+        if self.memory_backend.cnac_is_active(cid).await? {
+            return Ok(());
+        }
+
+        let is_personal = self.cnac_is_personal(cid)?;
+        let deactivated_path = self.generate_cnac_local_save_path(cid, is_personal);
+        self.memory_backend.reactivate_cnac(cid).await?;
+        let active_path = self.generate_cnac_local_save_path(cid, is_personal);
+        std::fs::rename(&deactivated_path, &active_path).map_err(|err| {
+            AccountError::io(
+                err,
+                format!(
+                    "Unable to move {} to {}",
+                    deactivated_path.display(),
+                    active_path.display()
+                ),
+            )
+        })?;
+        self.save_deactivations()
+    }
+
+    async fn purge_deactivated_before(&self, cutoff: SystemTime) -> Result<usize, AccountError> {
+        let expired = self.memory_backend.deactivated_before(cutoff);
+        for cid in &expired {
+            self.delete_cnac_by_cid(*cid).await?;
+        }
+
+        Ok(expired.len())
+    }
+
     async fn get_registered_impersonal_cids(
         &self,
         limit: Option<i32>,
@@ -112,29 +269,76 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for FilesystemBackend<R
             .await
     }
 
+    async fn get_registered_impersonal_cids_paged(
+        &self,
+        offset: u64,
+        limit: u32,
+    ) -> Result<Vec<u64>, AccountError> {
+        self.memory_backend
+            .get_registered_impersonal_cids_paged(offset, limit)
+            .await
+    }
+
+    async fn get_registered_impersonal_cids_created_between(
+        &self,
+        created_after: Option<DateTime<Utc>>,
+        created_before: Option<DateTime<Utc>>,
+        limit: Option<i32>,
+    ) -> Result<Option<Vec<u64>>, AccountError> {
+        self.memory_backend
+            .get_registered_impersonal_cids_created_between(created_after, created_before, limit)
+            .await
+    }
+
     async fn get_username_by_cid(&self, cid: u64) -> Result<Option<String>, AccountError> {
         self.memory_backend.get_username_by_cid(cid).await
     }
 
+    async fn get_cid_by_stored_username(
+        &self,
+        username: &str,
+    ) -> Result<Option<u64>, AccountError> {
+        self.memory_backend
+            .get_cid_by_stored_username(username)
+            .await
+    }
+
+    async fn change_username(
+        &self,
+        cid: u64,
+        new_username: &str,
+        credential_policy: &CredentialPolicy,
+    ) -> Result<(), AccountError> {
+        let cnac = check_username_change(self, cid, new_username, credential_policy).await?;
+        let previous_username = cnac.get_username();
+        let peers = self.memory_backend.rename_client(&cnac, new_username)?;
+        let renamed = std::iter::once(&cnac).chain(&peers).collect::<Vec<_>>();
+
+        if let Err(err) = self.save_cnacs_atomically(&renamed) {
+            // no CNAC was persisted, so the rename is undone in memory as well
+            let _ = self.memory_backend.rename_client(&cnac, &previous_username);
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
     async fn register_p2p_as_server(&self, cid0: u64, cid1: u64) -> Result<(), AccountError> {
         self.memory_backend
             .register_p2p_as_server(cid0, cid1)
             .await?;
-        let (cnac0, cnac1) = {
-            let read = self.memory_backend.clients.read();
-            let cnac0 = read
-                .get(&cid0)
-                .cloned()
-                .ok_or(AccountError::ClientNonExists(cid0))?;
-            let cnac1 = read
-                .get(&cid1)
-                .cloned()
-                .ok_or(AccountError::ClientNonExists(cid1))?;
-            (cnac0, cnac1)
-        };
+        let [cnac0, cnac1] = self.get_cnac_pair(cid0, cid1)?;
+
+        if let Err(err) = self.save_cnacs_atomically(&[&cnac0, &cnac1]) {
+            // neither CNAC was persisted, so the registration is undone in memory as well
+            let _ = self
+                .memory_backend
+                .deregister_p2p_as_server(cid0, cid1)
+                .await;
+            return Err(err);
+        }
 
-        self.save_cnac(&cnac0).await?;
-        self.save_cnac(&cnac1).await
+        Ok(())
     }
 
     async fn register_p2p_as_client(
@@ -153,21 +357,15 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for FilesystemBackend<R
         self.memory_backend
             .deregister_p2p_as_server(cid0, cid1)
             .await?;
-        let (cnac0, cnac1) = {
-            let read = self.memory_backend.clients.read();
-            let cnac0 = read
-                .get(&cid0)
-                .cloned()
-                .ok_or(AccountError::ClientNonExists(cid0))?;
-            let cnac1 = read
-                .get(&cid1)
-                .cloned()
-                .ok_or(AccountError::ClientNonExists(cid1))?;
-            (cnac0, cnac1)
-        };
+        let [cnac0, cnac1] = self.get_cnac_pair(cid0, cid1)?;
+
+        if let Err(err) = self.save_cnacs_atomically(&[&cnac0, &cnac1]) {
+            // neither CNAC was persisted, so the deregistration is undone in memory as well
+            let _ = self.memory_backend.register_p2p_as_server(cid0, cid1).await;
+            return Err(err);
+        }
 
-        self.save_cnac(&cnac0).await?;
-        self.save_cnac(&cnac1).await
+        Ok(())
     }
 
     async fn deregister_p2p_as_client(
@@ -191,6 +389,12 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for FilesystemBackend<R
             .await
     }
 
+    async fn get_hyperlan_peer_count(&self, implicated_cid: u64) -> Result<usize, AccountError> {
+        self.memory_backend
+            .get_hyperlan_peer_count(implicated_cid)
+            .await
+    }
+
     async fn get_client_metadata(
         &self,
         implicated_cid: u64,
@@ -200,11 +404,71 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for FilesystemBackend<R
             .await
     }
 
-    async fn get_clients_metadata(
+    async fn stream_clients_metadata(
         &self,
-        limit: Option<i32>,
-    ) -> Result<Vec<CNACMetadata>, AccountError> {
-        self.memory_backend.get_clients_metadata(limit).await
+    ) -> Result<BoxStream<'_, Result<CNACMetadata, AccountError>>, AccountError> {
+        let dirs = self.directory_store.as_ref().unwrap();
+        let mut read_dirs = Vec::with_capacity(3);
+        for dir in [
+            &dirs.nac_dir_impersonal,
+            &dirs.nac_dir_personal,
+            &dirs.nac_dir_deactivated,
+        ] {
+            read_dirs.push(
+                tokio::fs::read_dir(dir)
+                    .await
+                    .map_err(|err| AccountError::io(err, format!("Unable to read {dir}")))?,
+            );
+        }
+
+        // walk the account directories lazily, resolving each saved CNAC to its loaded client
+        Ok(futures::stream::iter(read_dirs)
+            .flat_map(|read_dir| {
+                futures::stream::unfold(Some(read_dir), |read_dir| async move {
+                    let mut read_dir = read_dir?;
+                    match read_dir.next_entry().await {
+                        Ok(Some(entry)) => Some((Ok(entry.path()), Some(read_dir))),
+                        Ok(None) => None,
+                        Err(err) => Some((
+                            Err(AccountError::io(err, "Unable to read an account directory")),
+                            None,
+                        )),
+                    }
+                })
+            })
+            .filter_map(move |path| {
+                futures::future::ready(match path {
+                    Ok(path) => get_cid_from_cnac_path(&path).and_then(|cid| {
+                        self.memory_backend
+                            .clients
+                            .read()
+                            .get(&cid)
+                            .map(|cnac| Ok(self.memory_backend.get_metadata(cnac)))
+                    }),
+                    Err(err) => Some(Err(err)),
+                })
+            })
+            .boxed())
+    }
+
+    async fn get_client_summary(&self, cid: u64) -> Result<Option<ClientSummary>, AccountError> {
+        self.memory_backend.get_client_summary(cid).await
+    }
+
+    async fn set_last_connect(&self, cid: u64, time: DateTime<Utc>) -> Result<(), AccountError> {
+        self.memory_backend.set_last_connect(cid, time).await?;
+        self.save_last_connects()
+    }
+
+    async fn get_last_connect(&self, cid: u64) -> Result<Option<DateTime<Utc>>, AccountError> {
+        self.memory_backend.get_last_connect(cid).await
+    }
+
+    async fn get_last_connects(
+        &self,
+        cids: &[u64],
+    ) -> Result<HashMap<u64, DateTime<Utc>>, AccountError> {
+        self.memory_backend.get_last_connects(cids).await
     }
 
     async fn get_hyperlan_peer_by_cid(
@@ -260,11 +524,16 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for FilesystemBackend<R
         &self,
         cnac: &ClientNetworkAccount<R, Fcm>,
         peers: Vec<MutualPeer>,
-    ) -> Result<(), AccountError> {
-        self.memory_backend
+    ) -> Result<PeerListDelta, AccountError> {
+        let delta = self
+            .memory_backend
             .synchronize_hyperlan_peer_list_as_client(cnac, peers)
             .await?;
-        self.save_cnac(cnac).await
+        if !delta.is_empty() {
+            self.save_cnac(cnac).await?;
+        }
+
+        Ok(delta)
     }
 
     async fn get_byte_map_value(
@@ -279,6 +548,17 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for FilesystemBackend<R
             .await
     }
 
+    async fn get_byte_map_values(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        keys: &[(String, String)],
+    ) -> Result<HashMap<(String, String), Vec<u8>>, AccountError> {
+        self.memory_backend
+            .get_byte_map_values(implicated_cid, peer_cid, keys)
+            .await
+    }
+
     async fn remove_byte_map_value(
         &self,
         implicated_cid: u64,
@@ -290,7 +570,7 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for FilesystemBackend<R
             .memory_backend
             .remove_byte_map_value(implicated_cid, peer_cid, key, sub_key)
             .await?;
-        self.save_cnac_by_cid(implicated_cid).await.map(|_| res)
+        self.save_byte_map_state(implicated_cid).await.map(|_| res)
     }
 
     async fn store_byte_map_value(
@@ -305,7 +585,82 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for FilesystemBackend<R
             .memory_backend
             .store_byte_map_value(implicated_cid, peer_cid, key, sub_key, value)
             .await?;
-        self.save_cnac_by_cid(implicated_cid).await.map(|_| res)
+        self.save_byte_map_state(implicated_cid).await.map(|_| res)
+    }
+
+    async fn store_byte_map_value_with_expiry(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> Result<Option<Vec<u8>>, AccountError> {
+        let res = self
+            .memory_backend
+            .store_byte_map_value_with_expiry(implicated_cid, peer_cid, key, sub_key, value, ttl)
+            .await?;
+        self.save_byte_map_state(implicated_cid).await.map(|_| res)
+    }
+
+    async fn compare_and_swap_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        expected: Option<Vec<u8>>,
+        new: Vec<u8>,
+    ) -> Result<bool, AccountError> {
+        let swapped = self
+            .memory_backend
+            .compare_and_swap_byte_map_value(implicated_cid, peer_cid, key, sub_key, expected, new)
+            .await?;
+        if swapped {
+            self.save_byte_map_state(implicated_cid).await?;
+        }
+
+        Ok(swapped)
+    }
+
+    async fn get_or_insert_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        default: Box<dyn FnOnce() -> Vec<u8> + Send + '_>,
+    ) -> Result<Vec<u8>, AccountError> {
+        let mut inserted = false;
+        // the memory backend holds the account's lock across the check-and-insert
+        let value = self
+            .memory_backend
+            .get_or_insert_byte_map_value(
+                implicated_cid,
+                peer_cid,
+                key,
+                sub_key,
+                Box::new(|| {
+                    inserted = true;
+                    default()
+                }),
+            )
+            .await?;
+        if inserted {
+            self.save_byte_map_state(implicated_cid).await?;
+        }
+
+        Ok(value)
+    }
+
+    async fn copy_byte_map(&self, from_cid: u64, to_cid: u64) -> Result<usize, AccountError> {
+        let copied = self.memory_backend.copy_byte_map(from_cid, to_cid).await?;
+        if copied != 0 {
+            self.save_byte_map_state(to_cid).await?;
+        }
+
+        Ok(copied)
     }
 
     async fn get_byte_map_values_by_key(
@@ -318,7 +673,7 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for FilesystemBackend<R
             .memory_backend
             .get_byte_map_values_by_key(implicated_cid, peer_cid, key)
             .await?;
-        self.save_cnac_by_cid(implicated_cid).await.map(|_| res)
+        self.save_byte_map_state(implicated_cid).await.map(|_| res)
     }
 
     async fn remove_byte_map_values_by_key(
@@ -331,7 +686,17 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for FilesystemBackend<R
             .memory_backend
             .remove_byte_map_values_by_key(implicated_cid, peer_cid, key)
             .await?;
-        self.save_cnac_by_cid(implicated_cid).await.map(|_| res)
+        self.save_byte_map_state(implicated_cid).await.map(|_| res)
+    }
+
+    async fn get_byte_map_entries(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Vec<ByteMapEntry>, AccountError> {
+        self.memory_backend
+            .get_byte_map_entries(implicated_cid, peer_cid)
+            .await
     }
 
     async fn stream_object_to_backend(
@@ -355,9 +720,17 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for FilesystemBackend<R
         .await?;
 
         log::info!(target: "citadel", "Will stream object to {file_path:?}");
-        let file = tokio::fs::File::create(&file_path)
-            .await
-            .map_err(|err| AccountError::IoError(err.to_string()))?;
+        if is_virtual_file && self.revfs_deduplication && file_path.exists() {
+            // the existing file may share its contents with other references, so release it
+            // rather than truncating it below
+            self.revfs_dedup_release(sink_metadata.get_cid(), &file_path)?;
+            delete_paths(&[&file_path]).await?;
+        }
+
+        let cid = sink_metadata.get_cid();
+        let file = tokio::fs::File::create(&file_path).await.map_err(|err| {
+            AccountError::io(err, format!("Unable to create {}", file_path.display()))
+        })?;
 
         let _ = status_tx.send(ObjectTransferStatus::ReceptionBeginning(
             file_path.clone(),
@@ -365,10 +738,15 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for FilesystemBackend<R
         ));
 
         let mut size = 0;
+        let dedup = is_virtual_file && self.revfs_deduplication;
+        let mut hasher = sha3::Sha3_256::default();
         let mut writer = tokio::io::BufWriter::new(file);
         let mut reader = tokio_util::io::StreamReader::new(
             tokio_stream::wrappers::UnboundedReceiverStream::new(source).map(|r| {
                 size += r.len();
+                if dedup {
+                    hasher.update(&r);
+                }
                 Ok(std::io::Cursor::new(r)) as Result<std::io::Cursor<Vec<u8>>, std::io::Error>
             }),
         );
@@ -377,20 +755,27 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for FilesystemBackend<R
             // start by writing the metadata file next to it
             let metadata_path = get_revfs_file_metadata_path(&file_path);
             let serialized = metadata.serialize_to_vector()?;
-            tokio::fs::write(metadata_path, serialized)
+            tokio::fs::write(&metadata_path, serialized)
                 .await
-                .map_err(|err| AccountError::IoError(err.to_string()))?
+                .map_err(|err| {
+                    AccountError::io(err, format!("Unable to write {}", metadata_path.display()))
+                })?
         }
 
         if let Err(err) = tokio::io::copy(&mut reader, &mut writer).await {
             log::error!(target: "citadel", "Error while copying from reader to writer: {}", err);
         }
 
-        writer
-            .into_inner()
-            .sync_all()
-            .await
-            .map_err(|err| AccountError::IoError(err.to_string()))
+        writer.into_inner().sync_all().await.map_err(|err| {
+            AccountError::io(err, format!("Unable to sync {}", file_path.display()))
+        })?;
+
+        if dedup {
+            let digest = hex_digest(hasher);
+            self.revfs_dedup_store(cid, &file_path, &digest)?;
+        }
+
+        Ok(())
     }
 
     async fn revfs_get_file_info(
@@ -413,9 +798,9 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for FilesystemBackend<R
         let metadata_path = get_revfs_file_metadata_path(&file_path);
         // first, figure out what security level it was encrypted at. This data should be passed back to the client pulling
         // this file
-        let raw_metadata = tokio::fs::read(&metadata_path)
-            .await
-            .map_err(|err| AccountError::IoError(err.to_string()))?;
+        let raw_metadata = tokio::fs::read(&metadata_path).await.map_err(|err| {
+            AccountError::io(err, format!("Unable to read {}", metadata_path.display()))
+        })?;
         let metadata: VirtualObjectMetadata =
             VirtualObjectMetadata::deserialize_from_owned_vector(raw_metadata)?;
 
@@ -442,11 +827,83 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for FilesystemBackend<R
         .await?;
         let metadata_path = get_revfs_file_metadata_path(&file_path);
 
+        if self.revfs_deduplication {
+            self.revfs_dedup_release(cid, &file_path)?;
+        }
+
         delete_paths(&[metadata_path, file_path]).await
     }
 }
 
 impl<R: Ratchet, Fcm: Ratchet> FilesystemBackend<R, Fcm> {
+    /// Enables content-addressed deduplication for RE-VFS objects. When an owner stores an
+    /// object identical to one they already stored, the existing copy is referenced instead
+    /// of writing a new one. Objects are never shared between owners
+    pub fn with_revfs_deduplication(mut self, enabled: bool) -> Self {
+        self.revfs_deduplication = enabled;
+        self
+    }
+
+    /// Acquires the RE-VFS deduplication lock, counting the acquisition as contended if another
+    /// operation holds it
+    fn lock_revfs_dedup(&self) -> MutexGuard<'_, ()> {
+        self.revfs_dedup_lock.try_lock().unwrap_or_else(|| {
+            self.metrics.on_lock_contended();
+            self.revfs_dedup_lock.lock()
+        })
+    }
+
+    /// Registers a reference to the object at `file_path`, replacing it with a link to an
+    /// identical blob if the owner already has one
+    fn revfs_dedup_store(
+        &self,
+        cid: u64,
+        file_path: &Path,
+        digest: &str,
+    ) -> Result<(), AccountError> {
+        let directory_store = self.directory_store.as_ref().unwrap();
+        let (blob_path, refs_path) = get_revfs_blob_paths(directory_store, cid, digest)?;
+        let _lock = self.lock_revfs_dedup();
+
+        if blob_path.exists() {
+            std::fs::remove_file(file_path)?;
+            std::fs::hard_link(&blob_path, file_path)?;
+        } else {
+            std::fs::hard_link(file_path, &blob_path)?;
+        }
+
+        let refs = read_revfs_ref_count(&refs_path);
+        std::fs::write(&refs_path, (refs + 1).to_string())?;
+        Ok(())
+    }
+
+    /// Releases the reference held by the object at `file_path`, freeing the underlying blob
+    /// once no references remain. The object itself is not removed
+    fn revfs_dedup_release(&self, cid: u64, file_path: &Path) -> Result<(), AccountError> {
+        let mut hasher = sha3::Sha3_256::default();
+        let _ = std::io::copy(&mut std::fs::File::open(file_path)?, &mut hasher)?;
+        let digest = hex_digest(hasher);
+
+        let directory_store = self.directory_store.as_ref().unwrap();
+        let (blob_path, refs_path) = get_revfs_blob_paths(directory_store, cid, &digest)?;
+        let _lock = self.lock_revfs_dedup();
+
+        if !blob_path.exists() {
+            // stored before deduplication was enabled
+            return Ok(());
+        }
+
+        let refs = read_revfs_ref_count(&refs_path).saturating_sub(1);
+        if refs == 0 {
+            std::fs::remove_file(&blob_path)?;
+            let _ = std::fs::remove_file(&refs_path);
+        } else {
+            std::fs::write(&refs_path, refs.to_string())?;
+        }
+
+        Ok(())
+    }
+
     async fn save_cnac_by_cid(&self, cid: u64) -> Result<(), AccountError> {
         let cnac = self
             .memory_backend
@@ -458,9 +915,174 @@ impl<R: Ratchet, Fcm: Ratchet> FilesystemBackend<R, Fcm> {
         self.save_cnac(&cnac).await
     }
 
+    /// Persists every client, along with the expiration times of all byte map values, the
+    /// deactivation times of all deactivated clients and the last connect time of each client
+    async fn save_all(&self) -> Result<(), AccountError> {
+        if self.directory_store.is_none() {
+            return Ok(());
+        }
+
+        let cnacs = self
+            .memory_backend
+            .clients
+            .read()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        for cnac in &cnacs {
+            self.save_cnac(cnac).await?;
+        }
+
+        self.save_byte_map_expiries()?;
+        self.save_deactivations()?;
+        self.save_last_connects()
+    }
+
+    /// Persists both the client's byte map and the expiration times of all byte map values
+    async fn save_byte_map_state(&self, cid: u64) -> Result<(), AccountError> {
+        self.save_cnac_by_cid(cid).await?;
+        self.save_byte_map_expiries()
+    }
+
+    fn save_byte_map_expiries(&self) -> Result<(), AccountError> {
+        let bytes = self
+            .memory_backend
+            .byte_map_expiries
+            .read()
+            .serialize_to_vector()?;
+        let path = self
+            .directory_store
+            .as_ref()
+            .unwrap()
+            .make_path(BasePath::ConfigDir, BYTE_MAP_EXPIRIES_FILE);
+        std::fs::write(&path, bytes)
+            .map_err(|err| AccountError::io(err, format!("Unable to write {}", path.display())))
+    }
+
+    fn save_deactivations(&self) -> Result<(), AccountError> {
+        let bytes = self
+            .memory_backend
+            .deactivated
+            .read()
+            .serialize_to_vector()?;
+        let path = self
+            .directory_store
+            .as_ref()
+            .unwrap()
+            .make_path(BasePath::ConfigDir, DEACTIVATIONS_FILE);
+        std::fs::write(&path, bytes)
+            .map_err(|err| AccountError::io(err, format!("Unable to write {}", path.display())))
+    }
+
+    fn save_last_connects(&self) -> Result<(), AccountError> {
+        let bytes = self
+            .memory_backend
+            .last_connects
+            .read()
+            .serialize_to_vector()?;
+        let path = self
+            .directory_store
+            .as_ref()
+            .unwrap()
+            .make_path(BasePath::ConfigDir, LAST_CONNECTS_FILE);
+        std::fs::write(&path, bytes)
+            .map_err(|err| AccountError::io(err, format!("Unable to write {}", path.display())))
+    }
+
+    fn cnac_is_personal(&self, cid: u64) -> Result<bool, AccountError> {
+        self.memory_backend
+            .clients
+            .read()
+            .get(&cid)
+            .map(|cnac| cnac.is_personal())
+            .ok_or(AccountError::ClientNonExists(cid))
+    }
+
+    fn get_cnac_pair(
+        &self,
+        cid0: u64,
+        cid1: u64,
+    ) -> Result<[ClientNetworkAccount<R, Fcm>; 2], AccountError> {
+        let read = self.memory_backend.clients.read();
+        let cnac0 = read
+            .get(&cid0)
+            .cloned()
+            .ok_or(AccountError::ClientNonExists(cid0))?;
+        let cnac1 = read
+            .get(&cid1)
+            .cloned()
+            .ok_or(AccountError::ClientNonExists(cid1))?;
+        Ok([cnac0, cnac1])
+    }
+
+    /// Persists every CNAC in `cnacs`, or none of them. Each CNAC is first written to a temporary
+    /// file, and the temporary files only replace the stored CNACs once every write has succeeded.
+    /// Each stored CNAC is moved to a backup before being replaced, so that if a later replacement
+    /// fails, the CNACs already replaced are restored
+    fn save_cnacs_atomically(
+        &self,
+        cnacs: &[&ClientNetworkAccount<R, Fcm>],
+    ) -> Result<(), AccountError> {
+        let mut staged = Vec::with_capacity(cnacs.len());
+        for cnac in cnacs {
+            let path = self.generate_cnac_local_save_path(cnac.get_cid(), cnac.is_personal());
+            let staging_path = path.with_extension(format!("{CNAC_SERIALIZED_EXTENSION}.tmp"));
+            let staged_write = cnac.generate_proper_bytes().and_then(|bytes| {
+                std::fs::write(&staging_path, bytes).map_err(|err| {
+                    AccountError::io(err, format!("Unable to write {}", staging_path.display()))
+                })
+            });
+
+            if let Err(err) = staged_write {
+                for (staging_path, _) in staged {
+                    let _ = std::fs::remove_file(staging_path);
+                }
+
+                return Err(err);
+            }
+
+            staged.push((staging_path, path));
+        }
+
+        let mut replaced = Vec::with_capacity(staged.len());
+        for (staging_path, path) in &staged {
+            match replace_with_backup(staging_path, path) {
+                Ok(backup) => replaced.push((path, backup)),
+                Err(err) => {
+                    for (path, backup) in replaced.into_iter().rev() {
+                        let _ = match backup {
+                            Some(backup) => std::fs::rename(backup, path),
+                            None => std::fs::remove_file(path),
+                        };
+                    }
+
+                    for (staging_path, _) in &staged {
+                        let _ = std::fs::remove_file(staging_path);
+                    }
+
+                    return Err(err);
+                }
+            }
+        }
+
+        for backup in replaced.into_iter().filter_map(|(_, backup)| backup) {
+            let _ = std::fs::remove_file(backup);
+        }
+
+        Ok(())
+    }
+
+    /// Deactivated clients are saved to a separate directory, regardless of `is_personal`
     fn generate_cnac_local_save_path(&self, cid: u64, is_personal: bool) -> PathBuf {
         let dirs = self.directory_store.as_ref().unwrap();
-        if is_personal {
+        if self.memory_backend.deactivated.read().contains_key(&cid) {
+            PathBuf::from(format!(
+                "{}{}.{}",
+                dirs.nac_dir_deactivated.as_str(),
+                cid,
+                CNAC_SERIALIZED_EXTENSION
+            ))
+        } else if is_personal {
             PathBuf::from(format!(
                 "{}{}.{}",
                 dirs.nac_dir_personal.as_str(),
@@ -478,12 +1100,49 @@ impl<R: Ratchet, Fcm: Ratchet> FilesystemBackend<R, Fcm> {
     }
 }
 
+/// Moves `staging_path` to `path`, first moving the file at `path`, if any, to a backup. Returns
+/// the path of the backup. If the move fails, the file at `path` is restored from the backup
+fn replace_with_backup(staging_path: &Path, path: &Path) -> Result<Option<PathBuf>, AccountError> {
+    let backup = if path.exists() {
+        let backup = path.with_extension(format!("{CNAC_SERIALIZED_EXTENSION}.bak"));
+        std::fs::rename(path, &backup).map_err(|err| {
+            AccountError::io(
+                err,
+                format!("Unable to move {} to {}", path.display(), backup.display()),
+            )
+        })?;
+        Some(backup)
+    } else {
+        None
+    };
+
+    if let Err(err) = std::fs::rename(staging_path, path) {
+        if let Some(backup) = backup.as_ref() {
+            let _ = std::fs::rename(backup, path);
+        }
+
+        return Err(AccountError::io(
+            err,
+            format!(
+                "Unable to move {} to {}",
+                staging_path.display(),
+                path.display()
+            ),
+        ));
+    }
+
+    Ok(backup)
+}
+
 impl<R: Ratchet, Fcm: Ratchet> From<String> for FilesystemBackend<R, Fcm> {
     fn from(home_dir: String) -> Self {
         Self {
             home_dir,
             memory_backend: MemoryBackend::default(),
             directory_store: None,
+            revfs_deduplication: false,
+            revfs_dedup_lock: Mutex::new(()),
+            metrics: BackendMetricsRecorder::default(),
         }
     }
 }
@@ -508,9 +1167,9 @@ async fn get_file_path(
             let mut base_path = PathBuf::from(format!("{save_path}{source_cid}"));
 
             // create the directory in case it doesn't exist
-            tokio::fs::create_dir_all(&base_path)
-                .await
-                .map_err(|err| AccountError::IoError(err.to_string()))?;
+            tokio::fs::create_dir_all(&base_path).await.map_err(|err| {
+                AccountError::io(err, format!("Unable to create {}", base_path.display()))
+            })?;
 
             // finally, add the file name
             base_path.push(name);
@@ -530,12 +1189,58 @@ async fn get_file_path(
             // create the directory in case it doesn't exist
             tokio::fs::create_dir_all(&file_path_dir)
                 .await
-                .map_err(|err| AccountError::IoError(err.to_string()))?;
+                .map_err(|err| {
+                    AccountError::io(err, format!("Unable to create {}", file_path_dir.display()))
+                })?;
             Ok(file_path)
         }
     }
 }
 
+/// Returns the blob and reference-count paths for `digest` inside the owner's dedup store.
+/// The store lives beside, not within, the owner's virtual directory, so it cannot collide
+/// with any virtual path
+/// Parses the CID from a path generated by [`FilesystemBackend::generate_cnac_local_save_path`]
+fn get_cid_from_cnac_path(path: &Path) -> Option<u64> {
+    if path.extension()? != CNAC_SERIALIZED_EXTENSION {
+        return None;
+    }
+
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+fn get_revfs_blob_paths(
+    directory_store: &DirectoryStore,
+    cid: u64,
+    digest: &str,
+) -> Result<(PathBuf, PathBuf), AccountError> {
+    let blob_dir = PathBuf::from(format!(
+        "{}{REVFS_DEDUP_DIR}{}{cid}",
+        directory_store.virtual_dir,
+        std::path::MAIN_SEPARATOR
+    ));
+    std::fs::create_dir_all(&blob_dir)?;
+    Ok((
+        blob_dir.join(digest),
+        blob_dir.join(format!("{digest}.refs")),
+    ))
+}
+
+fn read_revfs_ref_count(refs_path: &Path) -> usize {
+    std::fs::read_to_string(refs_path)
+        .ok()
+        .and_then(|refs| refs.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+fn hex_digest(hasher: sha3::Sha3_256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
 fn get_revfs_file_metadata_path<P: AsRef<Path>>(path: P) -> PathBuf {
     let mut metadata_path = format!("{}", path.as_ref().display());
     metadata_path.push_str(crate::misc::VIRTUAL_FILE_METADATA_EXT);
@@ -545,9 +1250,10 @@ fn get_revfs_file_metadata_path<P: AsRef<Path>>(path: P) -> PathBuf {
 async fn delete_paths<T: AsRef<Path>, R: AsRef<[T]>>(paths: R) -> Result<(), AccountError> {
     let paths = paths.as_ref();
     for path in paths {
+        let path: &Path = path.as_ref();
         tokio::fs::remove_file(path)
             .await
-            .map_err(|err| AccountError::IoError(err.to_string()))?;
+            .map_err(|err| AccountError::io(err, format!("Unable to delete {}", path.display())))?;
     }
 
     Ok(())
@@ -1,23 +1,55 @@
 use super::utils::StreamableTargetInformation;
 use crate::backend::utils::ObjectTransferStatus;
-use crate::backend::BackendConnection;
+use crate::backend::{check_username_change, normalize_username, BackendConnection};
 use crate::client_account::{ClientNetworkAccount, MutualPeer};
-use crate::misc::{AccountError, CNACMetadata};
+use crate::misc::{
+    created_within, AccountError, ByteMapEntry, CNACMetadata, ClientSummary, CredentialPolicy,
+};
+use crate::peer_list::PeerListDelta;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use citadel_crypt::stacked_ratchet::Ratchet;
+use futures::stream::BoxStream;
+use futures::StreamExt;
 use parking_lot::RwLock;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
-pub(crate) struct MemoryBackend<R: Ratchet, Fcm: Ratchet> {
-    pub(crate) clients: RwLock<HashMap<u64, ClientNetworkAccount<R, Fcm>>>,
+/// Expiration times of byte map values, indexed by (implicated_cid, peer_cid, key), then sub_key
+pub(crate) type ByteMapExpiries = HashMap<(u64, u64, String), HashMap<String, SystemTime>>;
+
+/// Keeps every account in memory, so nothing persists between program executions. Wrap in a
+/// [`PersistenceHandler`](crate::backend::PersistenceHandler) to share it between account managers
+pub struct MemoryBackend<R: Ratchet, Fcm: Ratchet> {
+    /// Ordered by CID, so that clients are paged without sorting
+    pub(crate) clients: RwLock<BTreeMap<u64, ClientNetworkAccount<R, Fcm>>>,
+    pub(crate) byte_map_expiries: RwLock<ByteMapExpiries>,
+    /// The deactivation times of deactivated clients
+    pub(crate) deactivated: RwLock<HashMap<u64, SystemTime>>,
+    /// The times of the last successful connect of each client that has connected
+    pub(crate) last_connects: RwLock<HashMap<u64, SystemTime>>,
+    /// Always locked after `clients`, if both are locked
+    pub(crate) usernames: RwLock<UsernameIndex>,
+}
+
+/// The client holding each [normalized](normalize_username) username, along with the normalized
+/// username held by each client
+#[derive(Default)]
+pub(crate) struct UsernameIndex {
+    holders: HashMap<String, u64>,
+    usernames: HashMap<u64, String>,
 }
 
 impl<R: Ratchet, Fcm: Ratchet> Default for MemoryBackend<R, Fcm> {
     fn default() -> Self {
         Self {
-            clients: RwLock::new(HashMap::new()),
+            clients: RwLock::new(BTreeMap::new()),
+            byte_map_expiries: RwLock::new(HashMap::new()),
+            deactivated: RwLock::new(HashMap::new()),
+            last_connects: RwLock::new(HashMap::new()),
+            usernames: RwLock::new(UsernameIndex::default()),
         }
     }
 }
@@ -35,7 +67,9 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for MemoryBackend<R, Fc
     #[allow(unused_results)]
     async fn save_cnac(&self, cnac: &ClientNetworkAccount<R, Fcm>) -> Result<(), AccountError> {
         let cid = cnac.get_cid();
-        self.clients.write().insert(cid, cnac.clone());
+        let mut clients = self.clients.write();
+        self.usernames.write().claim(cid, &cnac.get_username())?;
+        clients.insert(cid, cnac.clone());
         Ok(())
     }
 
@@ -50,6 +84,10 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for MemoryBackend<R, Fc
         Ok(self.clients.read().contains_key(&cid))
     }
 
+    async fn is_personal(&self, cid: u64) -> Result<Option<bool>, AccountError> {
+        Ok(self.clients.read().get(&cid).map(|r| r.is_personal()))
+    }
+
     #[allow(unused_results)]
     async fn delete_cnac_by_cid(&self, cid: u64) -> Result<(), AccountError> {
         let mut write = self.clients.write();
@@ -66,6 +104,13 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for MemoryBackend<R, Fc
             }
         }
 
+        self.byte_map_expiries
+            .write()
+            .retain(|(implicated_cid, ..), _| *implicated_cid != cid);
+        self.deactivated.write().remove(&cid);
+        self.last_connects.write().remove(&cid);
+        self.usernames.write().release(cid);
+
         Ok(())
     }
 
@@ -73,15 +118,55 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for MemoryBackend<R, Fc
         let mut write = self.clients.write();
         let len = write.len();
         write.clear();
+        self.byte_map_expiries.write().clear();
+        self.deactivated.write().clear();
+        self.last_connects.write().clear();
+        *self.usernames.write() = UsernameIndex::default();
         Ok(len)
     }
 
+    #[allow(unused_results)]
+    async fn deactivate_cnac(&self, cid: u64) -> Result<(), AccountError> {
+        if !self.clients.read().contains_key(&cid) {
+            return Err(AccountError::ClientNonExists(cid));
+        }
+
+        self.deactivated
+            .write()
+            .entry(cid)
+            .or_insert_with(SystemTime::now);
+        Ok(())
+    }
+
+    #[allow(unused_results)]
+    async fn reactivate_cnac(&self, cid: u64) -> Result<(), AccountError> {
+        if !self.clients.read().contains_key(&cid) {
+            return Err(AccountError::ClientNonExists(cid));
+        }
+
+        self.deactivated.write().remove(&cid);
+        Ok(())
+    }
+
+    async fn purge_deactivated_before(&self, cutoff: SystemTime) -> Result<usize, AccountError> {
+        let expired = self.deactivated_before(cutoff);
+        for cid in &expired {
+            self.delete_cnac_by_cid(*cid).await?;
+        }
+
+        Ok(expired.len())
+    }
+
     async fn get_registered_impersonal_cids(
         &self,
         limit: Option<i32>,
     ) -> Result<Option<Vec<u64>>, AccountError> {
         let read = self.clients.read();
-        let iter = read.iter().filter(|r| !r.1.is_personal()).map(|r| r.0);
+        let deactivated = self.deactivated.read();
+        let iter = read
+            .iter()
+            .filter(|r| !r.1.is_personal() && !deactivated.contains_key(r.0))
+            .map(|r| r.0);
 
         let ret: Vec<u64> = if let Some(limit) = limit {
             iter.take(limit as _).copied().collect()
@@ -96,14 +181,77 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for MemoryBackend<R, Fc
         }
     }
 
+    async fn get_registered_impersonal_cids_paged(
+        &self,
+        offset: u64,
+        limit: u32,
+    ) -> Result<Vec<u64>, AccountError> {
+        let read = self.clients.read();
+        let deactivated = self.deactivated.read();
+        Ok(read
+            .iter()
+            .filter(|r| !r.1.is_personal() && !deactivated.contains_key(r.0))
+            .map(|r| *r.0)
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect())
+    }
+
+    async fn get_registered_impersonal_cids_created_between(
+        &self,
+        created_after: Option<DateTime<Utc>>,
+        created_before: Option<DateTime<Utc>>,
+        limit: Option<i32>,
+    ) -> Result<Option<Vec<u64>>, AccountError> {
+        let read = self.clients.read();
+        let deactivated = self.deactivated.read();
+        let ret: Vec<u64> = read
+            .iter()
+            .filter(|r| !r.1.is_personal() && !deactivated.contains_key(r.0))
+            .filter(|r| {
+                created_within(
+                    &r.1.get_metadata().creation_date,
+                    created_after,
+                    created_before,
+                )
+            })
+            .map(|r| *r.0)
+            .take(limit.map(|limit| limit as usize).unwrap_or(usize::MAX))
+            .collect();
+
+        if ret.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(ret))
+        }
+    }
+
     async fn get_username_by_cid(&self, cid: u64) -> Result<Option<String>, AccountError> {
         Ok(self.clients.read().get(&cid).map(|r| r.get_username()))
     }
 
+    async fn get_cid_by_stored_username(
+        &self,
+        username: &str,
+    ) -> Result<Option<u64>, AccountError> {
+        Ok(self.usernames.read().holder(username))
+    }
+
+    async fn change_username(
+        &self,
+        cid: u64,
+        new_username: &str,
+        credential_policy: &CredentialPolicy,
+    ) -> Result<(), AccountError> {
+        let cnac = check_username_change(self, cid, new_username, credential_policy).await?;
+        let _ = self.rename_client(&cnac, new_username)?;
+        Ok(())
+    }
+
     async fn register_p2p_as_server(&self, cid0: u64, cid1: u64) -> Result<(), AccountError> {
         let read = self.clients.read();
         let cnac0 = read.get(&cid0).ok_or(AccountError::ClientNonExists(cid0))?;
-        let cnac1 = read.get(&cid1).ok_or(AccountError::ClientNonExists(cid0))?;
+        let cnac1 = read.get(&cid1).ok_or(AccountError::ClientNonExists(cid1))?;
         cnac0.register_hyperlan_p2p_as_server(cnac1)
     }
 
@@ -125,7 +273,7 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for MemoryBackend<R, Fc
     async fn deregister_p2p_as_server(&self, cid0: u64, cid1: u64) -> Result<(), AccountError> {
         let read = self.clients.read();
         let cnac0 = read.get(&cid0).ok_or(AccountError::ClientNonExists(cid0))?;
-        let cnac1 = read.get(&cid1).ok_or(AccountError::ClientNonExists(cid0))?;
+        let cnac1 = read.get(&cid1).ok_or(AccountError::ClientNonExists(cid1))?;
 
         cnac0.deregister_hyperlan_p2p_as_server(cnac1)
     }
@@ -155,32 +303,75 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for MemoryBackend<R, Fc
         }
     }
 
+    async fn get_hyperlan_peer_count(&self, implicated_cid: u64) -> Result<usize, AccountError> {
+        Ok(self
+            .clients
+            .read()
+            .get(&implicated_cid)
+            .map(|cnac| cnac.get_hyperlan_peer_count())
+            .unwrap_or(0))
+    }
+
     async fn get_client_metadata(
         &self,
         implicated_cid: u64,
     ) -> Result<Option<CNACMetadata>, AccountError> {
         let read = self.clients.read();
         if let Some(cnac) = read.get(&implicated_cid) {
-            Ok(Some(cnac.get_metadata()))
+            Ok(Some(self.get_metadata(cnac)))
         } else {
             Ok(None)
         }
     }
 
-    async fn get_clients_metadata(
+    async fn stream_clients_metadata(
         &self,
-        limit: Option<i32>,
-    ) -> Result<Vec<CNACMetadata>, AccountError> {
-        let read = self.clients.read();
-        if let Some(limit) = limit {
-            Ok(read
-                .values()
-                .take(limit as _)
-                .map(|r| r.get_metadata())
-                .collect())
-        } else {
-            Ok(read.values().map(|r| r.get_metadata()).collect())
+    ) -> Result<BoxStream<'_, Result<CNACMetadata, AccountError>>, AccountError> {
+        // only the CIDs are collected up-front; each client is looked up as the stream is polled
+        let cids = self.clients.read().keys().copied().collect::<Vec<u64>>();
+        Ok(futures::stream::iter(cids)
+            .filter_map(move |cid| {
+                futures::future::ready(
+                    self.clients
+                        .read()
+                        .get(&cid)
+                        .map(|cnac| Ok(self.get_metadata(cnac))),
+                )
+            })
+            .boxed())
+    }
+
+    async fn get_client_summary(&self, cid: u64) -> Result<Option<ClientSummary>, AccountError> {
+        Ok(self.clients.read().get(&cid).map(|cnac| {
+            let mut summary = cnac.get_summary();
+            summary.metadata.is_active = !self.deactivated.read().contains_key(&cid);
+            summary
+        }))
+    }
+
+    #[allow(unused_results)]
+    async fn set_last_connect(&self, cid: u64, time: DateTime<Utc>) -> Result<(), AccountError> {
+        if !self.clients.read().contains_key(&cid) {
+            return Err(AccountError::ClientNonExists(cid));
         }
+
+        self.last_connects.write().insert(cid, time.into());
+        Ok(())
+    }
+
+    async fn get_last_connect(&self, cid: u64) -> Result<Option<DateTime<Utc>>, AccountError> {
+        Ok(self.last_connects.read().get(&cid).copied().map(Into::into))
+    }
+
+    async fn get_last_connects(
+        &self,
+        cids: &[u64],
+    ) -> Result<HashMap<u64, DateTime<Utc>>, AccountError> {
+        let last_connects = self.last_connects.read();
+        Ok(cids
+            .iter()
+            .filter_map(|cid| Some((*cid, (*last_connects.get(cid)?).into())))
+            .collect())
     }
 
     async fn get_hyperlan_peer_by_cid(
@@ -256,9 +447,16 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for MemoryBackend<R, Fc
         &self,
         cnac: &ClientNetworkAccount<R, Fcm>,
         peers: Vec<MutualPeer>,
-    ) -> Result<(), AccountError> {
-        cnac.synchronize_hyperlan_peer_list(peers);
-        Ok(())
+    ) -> Result<PeerListDelta, AccountError> {
+        let delta = PeerListDelta::between(
+            &cnac.get_hyperlan_peer_mutuals().unwrap_or_default(),
+            &peers,
+        );
+        if !delta.is_empty() {
+            cnac.synchronize_hyperlan_peer_list(peers);
+        }
+
+        Ok(delta)
     }
 
     async fn get_byte_map_value(
@@ -270,6 +468,7 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for MemoryBackend<R, Fc
     ) -> Result<Option<Vec<u8>>, AccountError> {
         let read = self.clients.read();
         if let Some(cnac) = read.get(&implicated_cid) {
+            self.purge_expired_byte_map_values(cnac, implicated_cid, peer_cid, key);
             let mut lock = cnac.write();
             Ok(lock
                 .byte_map
@@ -284,6 +483,39 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for MemoryBackend<R, Fc
         }
     }
 
+    async fn get_byte_map_values(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        keys: &[(String, String)],
+    ) -> Result<HashMap<(String, String), Vec<u8>>, AccountError> {
+        let read = self.clients.read();
+        let cnac = if let Some(cnac) = read.get(&implicated_cid) {
+            cnac
+        } else {
+            return Ok(Default::default());
+        };
+
+        for key in keys.iter().map(|(key, _)| key).collect::<HashSet<_>>() {
+            self.purge_expired_byte_map_values(cnac, implicated_cid, peer_cid, key);
+        }
+
+        let lock = cnac.read();
+        let values = if let Some(values) = lock.byte_map.get(&peer_cid) {
+            values
+        } else {
+            return Ok(Default::default());
+        };
+
+        Ok(keys
+            .iter()
+            .filter_map(|(key, sub_key)| {
+                let value = values.get(key)?.get(sub_key)?;
+                Some(((key.clone(), sub_key.clone()), value.clone()))
+            })
+            .collect())
+    }
+
     async fn remove_byte_map_value(
         &self,
         implicated_cid: u64,
@@ -293,6 +525,8 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for MemoryBackend<R, Fc
     ) -> Result<Option<Vec<u8>>, AccountError> {
         let read = self.clients.read();
         if let Some(cnac) = read.get(&implicated_cid) {
+            self.purge_expired_byte_map_values(cnac, implicated_cid, peer_cid, key);
+            self.set_byte_map_expiry(implicated_cid, peer_cid, key, sub_key, None);
             let mut lock = cnac.write();
             Ok(lock
                 .byte_map
@@ -314,19 +548,144 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for MemoryBackend<R, Fc
         sub_key: &str,
         value: Vec<u8>,
     ) -> Result<Option<Vec<u8>>, AccountError> {
+        Ok(self.insert_byte_map_value(implicated_cid, peer_cid, key, sub_key, value, None))
+    }
+
+    async fn store_byte_map_value_with_expiry(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> Result<Option<Vec<u8>>, AccountError> {
+        let expires_at = SystemTime::now() + ttl;
+        Ok(self.insert_byte_map_value(
+            implicated_cid,
+            peer_cid,
+            key,
+            sub_key,
+            value,
+            Some(expires_at),
+        ))
+    }
+
+    async fn compare_and_swap_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        expected: Option<Vec<u8>>,
+        new: Vec<u8>,
+    ) -> Result<bool, AccountError> {
         let read = self.clients.read();
         if let Some(cnac) = read.get(&implicated_cid) {
+            self.purge_expired_byte_map_values(cnac, implicated_cid, peer_cid, key);
+            // both locks are held across the read-compare-write, in the same order as the purge
+            let mut expiries = self.byte_map_expiries.write();
             let mut lock = cnac.write();
-            Ok(lock
+            let values = lock
                 .byte_map
                 .entry(peer_cid)
                 .or_default()
                 .entry(key.to_string())
-                .or_default()
-                .insert(sub_key.to_string(), value))
+                .or_default();
+
+            if values.get(sub_key) != expected.as_ref() {
+                return Ok(false);
+            }
+
+            let entry_key = (implicated_cid, peer_cid, key.to_string());
+            if let Some(sub_map) = expiries.get_mut(&entry_key) {
+                let _ = sub_map.remove(sub_key);
+                if sub_map.is_empty() {
+                    let _ = expiries.remove(&entry_key);
+                }
+            }
+
+            let _ = values.insert(sub_key.to_string(), new);
+            Ok(true)
         } else {
-            Ok(None)
+            Ok(false)
+        }
+    }
+
+    async fn get_or_insert_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        default: Box<dyn FnOnce() -> Vec<u8> + Send + '_>,
+    ) -> Result<Vec<u8>, AccountError> {
+        let read = self.clients.read();
+        let cnac = read
+            .get(&implicated_cid)
+            .ok_or(AccountError::ClientNonExists(implicated_cid))?;
+        self.purge_expired_byte_map_values(cnac, implicated_cid, peer_cid, key);
+        // both locks are held across the check-and-insert, in the same order as the purge. Any
+        // value that remains is unexpired, and an inserted value has no expiry to record
+        let _expiries = self.byte_map_expiries.write();
+        let mut lock = cnac.write();
+        let value = lock
+            .byte_map
+            .entry(peer_cid)
+            .or_default()
+            .entry(key.to_string())
+            .or_default()
+            .entry(sub_key.to_string())
+            .or_insert_with(default);
+        Ok(value.clone())
+    }
+
+    async fn copy_byte_map(&self, from_cid: u64, to_cid: u64) -> Result<usize, AccountError> {
+        if from_cid == to_cid {
+            return Err(AccountError::msg("Cannot copy a byte map onto itself"));
+        }
+
+        let read = self.clients.read();
+        let from = read
+            .get(&from_cid)
+            .ok_or(AccountError::ClientNonExists(from_cid))?;
+        let to = read
+            .get(&to_cid)
+            .ok_or(AccountError::ClientNonExists(to_cid))?;
+        let byte_map = from.read().byte_map.clone();
+        let now = SystemTime::now();
+        let mut copied = 0;
+
+        for (peer_cid, keys) in byte_map {
+            for (key, values) in keys {
+                let expiries = self
+                    .byte_map_expiries
+                    .read()
+                    .get(&(from_cid, peer_cid, key.clone()))
+                    .cloned()
+                    .unwrap_or_default();
+
+                for (sub_key, value) in values {
+                    let expires_at = expiries.get(&sub_key).copied();
+                    if matches!(expires_at, Some(expires_at) if expires_at <= now) {
+                        continue;
+                    }
+
+                    self.set_byte_map_expiry(to_cid, peer_cid, &key, &sub_key, expires_at);
+                    let _ = to
+                        .write()
+                        .byte_map
+                        .entry(peer_cid)
+                        .or_default()
+                        .entry(key.clone())
+                        .or_default()
+                        .insert(sub_key, value);
+                    copied += 1;
+                }
+            }
         }
+
+        Ok(copied)
     }
 
     async fn get_byte_map_values_by_key(
@@ -337,6 +696,7 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for MemoryBackend<R, Fc
     ) -> Result<HashMap<String, Vec<u8>>, AccountError> {
         let read = self.clients.read();
         if let Some(cnac) = read.get(&implicated_cid) {
+            self.purge_expired_byte_map_values(cnac, implicated_cid, peer_cid, key);
             let mut lock = cnac.write();
             let map = lock
                 .byte_map
@@ -359,6 +719,11 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for MemoryBackend<R, Fc
     ) -> Result<HashMap<String, Vec<u8>>, AccountError> {
         let read = self.clients.read();
         if let Some(cnac) = read.get(&implicated_cid) {
+            self.purge_expired_byte_map_values(cnac, implicated_cid, peer_cid, key);
+            let _ =
+                self.byte_map_expiries
+                    .write()
+                    .remove(&(implicated_cid, peer_cid, key.to_string()));
             let mut lock = cnac.write();
             let submap = lock
                 .byte_map
@@ -372,6 +737,46 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for MemoryBackend<R, Fc
         }
     }
 
+    async fn get_byte_map_entries(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Vec<ByteMapEntry>, AccountError> {
+        let keys = self
+            .clients
+            .read()
+            .get(&implicated_cid)
+            .and_then(|cnac| cnac.read().byte_map.get(&peer_cid).cloned())
+            .unwrap_or_default();
+        let now = SystemTime::now();
+        let mut entries = Vec::new();
+
+        for (key, values) in keys {
+            let expiries = self
+                .byte_map_expiries
+                .read()
+                .get(&(implicated_cid, peer_cid, key.clone()))
+                .cloned()
+                .unwrap_or_default();
+
+            for (sub_key, value) in values {
+                let expires_at = expiries.get(&sub_key).copied();
+                if matches!(expires_at, Some(expires_at) if expires_at <= now) {
+                    continue;
+                }
+
+                entries.push(ByteMapEntry {
+                    key: key.clone(),
+                    sub_key,
+                    value,
+                    expires_at,
+                });
+            }
+        }
+
+        Ok(entries)
+    }
+
     async fn stream_object_to_backend(
         &self,
         source: UnboundedReceiver<Vec<u8>>,
@@ -382,6 +787,209 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for MemoryBackend<R, Fc
     }
 }
 
+impl<R: Ratchet, Fcm: Ratchet> MemoryBackend<R, Fcm> {
+    /// Returns the metadata of the CNAC, marked inactive if the CNAC has been deactivated
+    pub(crate) fn get_metadata(&self, cnac: &ClientNetworkAccount<R, Fcm>) -> CNACMetadata {
+        let mut metadata = cnac.get_metadata();
+        metadata.is_active = !self.deactivated.read().contains_key(&metadata.cid);
+        metadata
+    }
+
+    /// Renames `cnac`, along with its entry in the peer list of each of its peers. Returns the
+    /// peers whose lists were updated. Fails if another client holds `username`, which may have
+    /// been taken since it was checked
+    pub(crate) fn rename_client(
+        &self,
+        cnac: &ClientNetworkAccount<R, Fcm>,
+        username: &str,
+    ) -> Result<Vec<ClientNetworkAccount<R, Fcm>>, AccountError> {
+        let cid = cnac.get_cid();
+        // holding the write lock keeps peers from being registered or removed mid-rename
+        let clients = self.clients.write();
+        let _ = self.usernames.write().claim(cid, username)?;
+        cnac.set_username(username);
+        Ok(cnac
+            .get_hyperlan_peer_list()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|peer_cid| clients.get(&peer_cid))
+            .filter(|peer| peer.set_hyperlan_peer_username(cid, username))
+            .cloned()
+            .collect())
+    }
+
+    /// Returns the CIDs of the clients deactivated before `cutoff`
+    pub(crate) fn deactivated_before(&self, cutoff: SystemTime) -> Vec<u64> {
+        self.deactivated
+            .read()
+            .iter()
+            .filter(|(_, deactivated_at)| **deactivated_at < cutoff)
+            .map(|(cid, _)| *cid)
+            .collect()
+    }
+
+    fn insert_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        value: Vec<u8>,
+        expires_at: Option<SystemTime>,
+    ) -> Option<Vec<u8>> {
+        let read = self.clients.read();
+        let cnac = read.get(&implicated_cid)?;
+        self.purge_expired_byte_map_values(cnac, implicated_cid, peer_cid, key);
+        self.set_byte_map_expiry(implicated_cid, peer_cid, key, sub_key, expires_at);
+        let mut lock = cnac.write();
+        lock.byte_map
+            .entry(peer_cid)
+            .or_default()
+            .entry(key.to_string())
+            .or_default()
+            .insert(sub_key.to_string(), value)
+    }
+
+    /// Sets or, if `expires_at` is None, clears the expiration time of a byte map value
+    fn set_byte_map_expiry(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        expires_at: Option<SystemTime>,
+    ) {
+        let mut expiries = self.byte_map_expiries.write();
+        let entry_key = (implicated_cid, peer_cid, key.to_string());
+        if let Some(expires_at) = expires_at {
+            let _ = expiries
+                .entry(entry_key)
+                .or_default()
+                .insert(sub_key.to_string(), expires_at);
+        } else if let Some(sub_map) = expiries.get_mut(&entry_key) {
+            let _ = sub_map.remove(sub_key);
+            if sub_map.is_empty() {
+                let _ = expiries.remove(&entry_key);
+            }
+        }
+    }
+
+    /// Removes the expired values inside `key`, making them indistinguishable from values that
+    /// were never set
+    fn purge_expired_byte_map_values(
+        &self,
+        cnac: &ClientNetworkAccount<R, Fcm>,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+    ) {
+        let mut expiries = self.byte_map_expiries.write();
+        let entry_key = (implicated_cid, peer_cid, key.to_string());
+        let sub_map = match expiries.get_mut(&entry_key) {
+            Some(sub_map) => sub_map,
+            None => return,
+        };
+
+        let now = SystemTime::now();
+        let expired = sub_map
+            .iter()
+            .filter(|(_, expires_at)| **expires_at <= now)
+            .map(|(sub_key, _)| sub_key.clone())
+            .collect::<Vec<String>>();
+
+        if expired.is_empty() {
+            return;
+        }
+
+        let mut lock = cnac.write();
+        if let Some(values) = lock
+            .byte_map
+            .get_mut(&peer_cid)
+            .and_then(|map| map.get_mut(key))
+        {
+            for sub_key in &expired {
+                let _ = values.remove(sub_key);
+            }
+        }
+
+        for sub_key in &expired {
+            let _ = sub_map.remove(sub_key);
+        }
+
+        if sub_map.is_empty() {
+            let _ = expiries.remove(&entry_key);
+        }
+    }
+}
+
+impl UsernameIndex {
+    /// Indexes the usernames of `clients`, such as when loading clients saved before the index
+    /// existed. If several clients hold the same username, the client with the lowest CID keeps it
+    pub fn from_clients<R: Ratchet, Fcm: Ratchet>(
+        clients: &BTreeMap<u64, ClientNetworkAccount<R, Fcm>>,
+    ) -> Self {
+        let mut index = Self::default();
+        for (&cid, cnac) in clients {
+            let normalized = normalize_username(&cnac.get_username());
+            let holder_cid = *index.holders.entry(normalized.clone()).or_insert(cid);
+            if holder_cid != cid {
+                log::warn!(target: "citadel", "Clients {} and {} hold the same username {}", holder_cid, cid, normalized);
+            }
+
+            let _ = index.usernames.insert(cid, normalized);
+        }
+
+        index
+    }
+
+    /// Returns the CID of the client holding `username`, if any
+    pub fn holder(&self, username: &str) -> Option<u64> {
+        self.holders.get(&normalize_username(username)).copied()
+    }
+
+    /// Has `cid` hold `username` in place of the username it held before, which is returned.
+    /// Fails with [`AccountError::ClientExists`] if another client holds `username`, unless `cid`
+    /// held it as well when the index was built
+    pub fn claim(&mut self, cid: u64, username: &str) -> Result<Option<String>, AccountError> {
+        let normalized = normalize_username(username);
+        if self.usernames.get(&cid) == Some(&normalized) {
+            return Ok(Some(normalized));
+        }
+
+        if let Some(holder_cid) = self.holders.get(&normalized) {
+            return Err(AccountError::ClientExists(*holder_cid));
+        }
+
+        let previous = self.release(cid);
+        let _ = self.holders.insert(normalized.clone(), cid);
+        let _ = self.usernames.insert(cid, normalized);
+        Ok(previous)
+    }
+
+    /// Has `cid` hold the username it held before a [`Self::claim`]
+    pub fn restore(&mut self, cid: u64, previous: Option<String>) {
+        if self.usernames.get(&cid) == previous.as_ref() {
+            return;
+        }
+
+        let _ = self.release(cid);
+        if let Some(previous) = previous {
+            let _ = self.holders.entry(previous.clone()).or_insert(cid);
+            let _ = self.usernames.insert(cid, previous);
+        }
+    }
+
+    /// Frees the username held by `cid`, returning it
+    pub fn release(&mut self, cid: u64) -> Option<String> {
+        let normalized = self.usernames.remove(&cid)?;
+        if self.holders.get(&normalized) == Some(&cid) {
+            let _ = self.holders.remove(&normalized);
+        }
+
+        Some(normalized)
+    }
+}
+
 pub(crate) async fn no_backend_streaming(
     mut source: UnboundedReceiver<Vec<u8>>,
     _sink_metadata: Arc<dyn StreamableTargetInformation>,
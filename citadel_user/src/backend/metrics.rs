@@ -0,0 +1,904 @@
+use crate::backend::utils::misc::StreamableTargetInformation;
+use crate::backend::utils::ObjectTransferStatus;
+use crate::backend::BackendConnection;
+use crate::client_account::{ClientNetworkAccount, MutualPeer};
+use crate::external_services::apns::ApnsKeys;
+use crate::misc::{
+    AccountError, ByteMapChange, ByteMapEntry, CNACMetadata, ClientSummary, CredentialPolicy,
+    GroupInfo,
+};
+use crate::peer_list::PeerListDelta;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use citadel_crypt::prelude::SecurityLevel;
+use citadel_crypt::stacked_ratchet::Ratchet;
+use citadel_crypt::streaming_crypt_scrambler::ObjectSource;
+use futures::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/// A snapshot of the health of a backend, as returned by [`BackendConnection::metrics`]. Backends
+/// that do not keep metrics report zero for every field
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct BackendMetrics {
+    /// The number of operations in flight, each of which holds a connection to the backend
+    pub active_connections: u64,
+    /// The number of operations completed, successfully or not
+    pub total_queries: u64,
+    /// The number of operations that returned an error
+    pub failed_queries: u64,
+    /// The mean time taken by a completed operation, in milliseconds
+    pub avg_latency_ms: f64,
+    /// The number of times an operation had to wait on a lock held by another operation
+    pub lock_contentions: u64,
+}
+
+/// Counts the operations of a backend. Every counter is updated with relaxed ordering, so a
+/// snapshot taken during a burst of operations may be momentarily inconsistent
+#[derive(Debug, Default)]
+pub struct BackendMetricsRecorder {
+    active_connections: AtomicU64,
+    total_queries: AtomicU64,
+    failed_queries: AtomicU64,
+    total_latency_micros: AtomicU64,
+    lock_contentions: AtomicU64,
+}
+
+impl BackendMetricsRecorder {
+    /// Runs `operation`, counting it as in flight until it completes or is dropped
+    pub async fn record<T>(
+        &self,
+        operation: impl Future<Output = Result<T, AccountError>>,
+    ) -> Result<T, AccountError> {
+        let _in_flight = InFlightGuard::new(&self.active_connections);
+        let start = Instant::now();
+        let result = operation.await;
+        self.on_completed(start.elapsed(), result.is_err());
+        result
+    }
+
+    /// Should be called each time an operation waits on a lock held by another operation
+    pub fn on_lock_contended(&self) {
+        let _ = self.lock_contentions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_completed(&self, latency: Duration, failed: bool) {
+        let _ = self.total_queries.fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .total_latency_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+        if failed {
+            let _ = self.failed_queries.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns a copy of the current metrics
+    pub fn snapshot(&self) -> BackendMetrics {
+        let total_queries = self.total_queries.load(Ordering::Relaxed);
+        let avg_latency_ms = if total_queries == 0 {
+            0f64
+        } else {
+            self.total_latency_micros.load(Ordering::Relaxed) as f64
+                / total_queries as f64
+                / 1000f64
+        };
+
+        BackendMetrics {
+            active_connections: self.active_connections.load(Ordering::Relaxed),
+            total_queries,
+            failed_queries: self.failed_queries.load(Ordering::Relaxed),
+            avg_latency_ms,
+            lock_contentions: self.lock_contentions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct InFlightGuard<'a>(&'a AtomicU64);
+
+impl<'a> InFlightGuard<'a> {
+    fn new(count: &'a AtomicU64) -> Self {
+        let _ = count.fetch_add(1, Ordering::Relaxed);
+        Self(count)
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        let _ = self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Wraps a backend, recording the latency and outcome of each of its operations. The SQL, redis,
+/// mongo and sled backends created by the [`AccountManager`](crate::account_manager::AccountManager)
+/// are wrapped automatically, while the filesystem backend only counts lock contentions. Operations
+/// composed of several others, such as [`BackendConnection::store_group`], count once
+pub struct MeteredBackend<B> {
+    inner: B,
+    recorder: BackendMetricsRecorder,
+}
+
+impl<B> MeteredBackend<B> {
+    /// Wraps `inner`, with every counter starting at zero
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            recorder: BackendMetricsRecorder::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl<R: Ratchet, Fcm: Ratchet, B: BackendConnection<R, Fcm>> BackendConnection<R, Fcm>
+    for MeteredBackend<B>
+{
+    async fn connect(&mut self) -> Result<(), AccountError> {
+        self.inner.connect().await
+    }
+
+    async fn is_connected(&self) -> Result<bool, AccountError> {
+        self.recorder.record(self.inner.is_connected()).await
+    }
+
+    async fn disconnect(&self) -> Result<(), AccountError> {
+        self.inner.disconnect().await
+    }
+
+    fn metrics(&self) -> BackendMetrics {
+        let inner = self.inner.metrics();
+        let mut metrics = self.recorder.snapshot();
+        metrics.lock_contentions += inner.lock_contentions;
+        metrics
+    }
+
+    async fn save_cnac(&self, cnac: &ClientNetworkAccount<R, Fcm>) -> Result<(), AccountError> {
+        self.recorder.record(self.inner.save_cnac(cnac)).await
+    }
+
+    async fn get_cnac_by_cid(
+        &self,
+        cid: u64,
+    ) -> Result<Option<ClientNetworkAccount<R, Fcm>>, AccountError> {
+        self.recorder.record(self.inner.get_cnac_by_cid(cid)).await
+    }
+
+    async fn get_client_by_username(
+        &self,
+        username: &str,
+    ) -> Result<Option<ClientNetworkAccount<R, Fcm>>, AccountError> {
+        self.recorder
+            .record(self.inner.get_client_by_username(username))
+            .await
+    }
+
+    async fn cid_is_registered(&self, cid: u64) -> Result<bool, AccountError> {
+        self.recorder
+            .record(self.inner.cid_is_registered(cid))
+            .await
+    }
+
+    async fn is_personal(&self, cid: u64) -> Result<Option<bool>, AccountError> {
+        self.recorder.record(self.inner.is_personal(cid)).await
+    }
+
+    async fn delete_cnac_by_cid(&self, cid: u64) -> Result<(), AccountError> {
+        self.recorder
+            .record(self.inner.delete_cnac_by_cid(cid))
+            .await
+    }
+
+    async fn purge(&self) -> Result<usize, AccountError> {
+        self.recorder.record(self.inner.purge()).await
+    }
+
+    async fn deactivate_cnac(&self, cid: u64) -> Result<(), AccountError> {
+        self.recorder.record(self.inner.deactivate_cnac(cid)).await
+    }
+
+    async fn reactivate_cnac(&self, cid: u64) -> Result<(), AccountError> {
+        self.recorder.record(self.inner.reactivate_cnac(cid)).await
+    }
+
+    async fn cnac_is_active(&self, cid: u64) -> Result<bool, AccountError> {
+        self.recorder.record(self.inner.cnac_is_active(cid)).await
+    }
+
+    async fn purge_deactivated_before(&self, cutoff: SystemTime) -> Result<usize, AccountError> {
+        self.recorder
+            .record(self.inner.purge_deactivated_before(cutoff))
+            .await
+    }
+
+    async fn username_exists(&self, username: &str) -> Result<bool, AccountError> {
+        self.recorder
+            .record(self.inner.username_exists(username))
+            .await
+    }
+
+    async fn get_registered_impersonal_cids(
+        &self,
+        limit: Option<i32>,
+    ) -> Result<Option<Vec<u64>>, AccountError> {
+        self.recorder
+            .record(self.inner.get_registered_impersonal_cids(limit))
+            .await
+    }
+
+    async fn get_registered_impersonal_cids_paged(
+        &self,
+        offset: u64,
+        limit: u32,
+    ) -> Result<Vec<u64>, AccountError> {
+        self.recorder
+            .record(
+                self.inner
+                    .get_registered_impersonal_cids_paged(offset, limit),
+            )
+            .await
+    }
+
+    async fn get_registered_impersonal_cids_created_between(
+        &self,
+        created_after: Option<DateTime<Utc>>,
+        created_before: Option<DateTime<Utc>>,
+        limit: Option<i32>,
+    ) -> Result<Option<Vec<u64>>, AccountError> {
+        self.recorder
+            .record(self.inner.get_registered_impersonal_cids_created_between(
+                created_after,
+                created_before,
+                limit,
+            ))
+            .await
+    }
+
+    async fn get_username_by_cid(&self, cid: u64) -> Result<Option<String>, AccountError> {
+        self.recorder
+            .record(self.inner.get_username_by_cid(cid))
+            .await
+    }
+
+    fn get_cid_by_username(&self, username: &str) -> u64 {
+        self.inner.get_cid_by_username(username)
+    }
+
+    async fn get_cid_by_stored_username(
+        &self,
+        username: &str,
+    ) -> Result<Option<u64>, AccountError> {
+        self.recorder
+            .record(self.inner.get_cid_by_stored_username(username))
+            .await
+    }
+
+    async fn change_username(
+        &self,
+        cid: u64,
+        new_username: &str,
+        credential_policy: &CredentialPolicy,
+    ) -> Result<(), AccountError> {
+        self.recorder
+            .record(
+                self.inner
+                    .change_username(cid, new_username, credential_policy),
+            )
+            .await
+    }
+
+    async fn register_p2p_as_server(&self, cid0: u64, cid1: u64) -> Result<(), AccountError> {
+        self.recorder
+            .record(self.inner.register_p2p_as_server(cid0, cid1))
+            .await
+    }
+
+    async fn register_p2p_as_client(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        peer_username: String,
+    ) -> Result<(), AccountError> {
+        self.recorder
+            .record(
+                self.inner
+                    .register_p2p_as_client(implicated_cid, peer_cid, peer_username),
+            )
+            .await
+    }
+
+    async fn deregister_p2p_as_server(&self, cid0: u64, cid1: u64) -> Result<(), AccountError> {
+        self.recorder
+            .record(self.inner.deregister_p2p_as_server(cid0, cid1))
+            .await
+    }
+
+    async fn deregister_p2p_as_client(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Option<MutualPeer>, AccountError> {
+        self.recorder
+            .record(
+                self.inner
+                    .deregister_p2p_as_client(implicated_cid, peer_cid),
+            )
+            .await
+    }
+
+    async fn get_hyperlan_peer_list(
+        &self,
+        implicated_cid: u64,
+    ) -> Result<Option<Vec<u64>>, AccountError> {
+        self.recorder
+            .record(self.inner.get_hyperlan_peer_list(implicated_cid))
+            .await
+    }
+
+    async fn get_hyperlan_peer_count(&self, implicated_cid: u64) -> Result<usize, AccountError> {
+        self.recorder
+            .record(self.inner.get_hyperlan_peer_count(implicated_cid))
+            .await
+    }
+
+    async fn get_client_metadata(
+        &self,
+        implicated_cid: u64,
+    ) -> Result<Option<CNACMetadata>, AccountError> {
+        self.recorder
+            .record(self.inner.get_client_metadata(implicated_cid))
+            .await
+    }
+
+    async fn get_clients_metadata(
+        &self,
+        limit: Option<i32>,
+    ) -> Result<Vec<CNACMetadata>, AccountError> {
+        self.recorder
+            .record(self.inner.get_clients_metadata(limit))
+            .await
+    }
+
+    // only opening the stream is recorded, since it is consumed at the pace of the caller
+    async fn stream_clients_metadata(
+        &self,
+    ) -> Result<BoxStream<'_, Result<CNACMetadata, AccountError>>, AccountError> {
+        self.recorder
+            .record(self.inner.stream_clients_metadata())
+            .await
+    }
+
+    async fn find_duplicate_usernames(&self) -> Result<Vec<(String, Vec<u64>)>, AccountError> {
+        self.recorder
+            .record(self.inner.find_duplicate_usernames())
+            .await
+    }
+
+    async fn get_client_summary(&self, cid: u64) -> Result<Option<ClientSummary>, AccountError> {
+        self.recorder
+            .record(self.inner.get_client_summary(cid))
+            .await
+    }
+
+    async fn set_last_connect(&self, cid: u64, time: DateTime<Utc>) -> Result<(), AccountError> {
+        self.recorder
+            .record(self.inner.set_last_connect(cid, time))
+            .await
+    }
+
+    async fn get_last_connect(&self, cid: u64) -> Result<Option<DateTime<Utc>>, AccountError> {
+        self.recorder.record(self.inner.get_last_connect(cid)).await
+    }
+
+    async fn get_last_connects(
+        &self,
+        cids: &[u64],
+    ) -> Result<HashMap<u64, DateTime<Utc>>, AccountError> {
+        self.recorder
+            .record(self.inner.get_last_connects(cids))
+            .await
+    }
+
+    async fn update_apns_keys(&self, cid: u64, keys: Option<ApnsKeys>) -> Result<(), AccountError> {
+        self.recorder
+            .record(self.inner.update_apns_keys(cid, keys))
+            .await
+    }
+
+    async fn get_apns_keys(&self, cid: u64) -> Result<Option<ApnsKeys>, AccountError> {
+        self.recorder.record(self.inner.get_apns_keys(cid)).await
+    }
+
+    async fn get_hyperlan_peer_by_cid(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Option<MutualPeer>, AccountError> {
+        self.recorder
+            .record(
+                self.inner
+                    .get_hyperlan_peer_by_cid(implicated_cid, peer_cid),
+            )
+            .await
+    }
+
+    async fn set_peer_alias(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        alias: Option<String>,
+    ) -> Result<(), AccountError> {
+        self.recorder
+            .record(self.inner.set_peer_alias(implicated_cid, peer_cid, alias))
+            .await
+    }
+
+    async fn get_peer_alias(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Option<String>, AccountError> {
+        self.recorder
+            .record(self.inner.get_peer_alias(implicated_cid, peer_cid))
+            .await
+    }
+
+    async fn hyperlan_peer_exists(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<bool, AccountError> {
+        self.recorder
+            .record(self.inner.hyperlan_peer_exists(implicated_cid, peer_cid))
+            .await
+    }
+
+    async fn hyperlan_peers_are_mutuals(
+        &self,
+        implicated_cid: u64,
+        peers: &[u64],
+    ) -> Result<Vec<bool>, AccountError> {
+        self.recorder
+            .record(self.inner.hyperlan_peers_are_mutuals(implicated_cid, peers))
+            .await
+    }
+
+    async fn get_hyperlan_peers(
+        &self,
+        implicated_cid: u64,
+        peers: &[u64],
+    ) -> Result<Vec<MutualPeer>, AccountError> {
+        self.recorder
+            .record(self.inner.get_hyperlan_peers(implicated_cid, peers))
+            .await
+    }
+
+    async fn get_mutual_hyperlan_peers(
+        &self,
+        implicated_cid: u64,
+        peers: &[u64],
+    ) -> Result<Vec<MutualPeer>, AccountError> {
+        self.recorder
+            .record(self.inner.get_mutual_hyperlan_peers(implicated_cid, peers))
+            .await
+    }
+
+    async fn get_hyperlan_peer_by_username(
+        &self,
+        implicated_cid: u64,
+        username: &str,
+    ) -> Result<Option<MutualPeer>, AccountError> {
+        self.recorder
+            .record(
+                self.inner
+                    .get_hyperlan_peer_by_username(implicated_cid, username),
+            )
+            .await
+    }
+
+    async fn get_hyperlan_peer_list_as_server(
+        &self,
+        implicated_cid: u64,
+    ) -> Result<Option<Vec<MutualPeer>>, AccountError> {
+        self.recorder
+            .record(self.inner.get_hyperlan_peer_list_as_server(implicated_cid))
+            .await
+    }
+
+    async fn search_hyperlan_peers_by_prefix(
+        &self,
+        implicated_cid: u64,
+        prefix: &str,
+        limit: u32,
+    ) -> Result<Vec<MutualPeer>, AccountError> {
+        self.recorder
+            .record(
+                self.inner
+                    .search_hyperlan_peers_by_prefix(implicated_cid, prefix, limit),
+            )
+            .await
+    }
+
+    async fn synchronize_hyperlan_peer_list_as_client(
+        &self,
+        cnac: &ClientNetworkAccount<R, Fcm>,
+        peers: Vec<MutualPeer>,
+    ) -> Result<PeerListDelta, AccountError> {
+        self.recorder
+            .record(
+                self.inner
+                    .synchronize_hyperlan_peer_list_as_client(cnac, peers),
+            )
+            .await
+    }
+
+    async fn get_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+    ) -> Result<Option<Vec<u8>>, AccountError> {
+        self.recorder
+            .record(
+                self.inner
+                    .get_byte_map_value(implicated_cid, peer_cid, key, sub_key),
+            )
+            .await
+    }
+
+    async fn get_byte_map_values(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        keys: &[(String, String)],
+    ) -> Result<HashMap<(String, String), Vec<u8>>, AccountError> {
+        self.recorder
+            .record(
+                self.inner
+                    .get_byte_map_values(implicated_cid, peer_cid, keys),
+            )
+            .await
+    }
+
+    async fn remove_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+    ) -> Result<Option<Vec<u8>>, AccountError> {
+        self.recorder
+            .record(
+                self.inner
+                    .remove_byte_map_value(implicated_cid, peer_cid, key, sub_key),
+            )
+            .await
+    }
+
+    async fn store_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        value: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, AccountError> {
+        self.recorder
+            .record(
+                self.inner
+                    .store_byte_map_value(implicated_cid, peer_cid, key, sub_key, value),
+            )
+            .await
+    }
+
+    async fn store_byte_map_value_with_expiry(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> Result<Option<Vec<u8>>, AccountError> {
+        self.recorder
+            .record(self.inner.store_byte_map_value_with_expiry(
+                implicated_cid,
+                peer_cid,
+                key,
+                sub_key,
+                value,
+                ttl,
+            ))
+            .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn compare_and_swap_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        expected: Option<Vec<u8>>,
+        new: Vec<u8>,
+    ) -> Result<bool, AccountError> {
+        self.recorder
+            .record(self.inner.compare_and_swap_byte_map_value(
+                implicated_cid,
+                peer_cid,
+                key,
+                sub_key,
+                expected,
+                new,
+            ))
+            .await
+    }
+
+    async fn get_or_insert_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        default: Box<dyn FnOnce() -> Vec<u8> + Send + '_>,
+    ) -> Result<Vec<u8>, AccountError> {
+        self.recorder
+            .record(self.inner.get_or_insert_byte_map_value(
+                implicated_cid,
+                peer_cid,
+                key,
+                sub_key,
+                default,
+            ))
+            .await
+    }
+
+    async fn copy_byte_map(&self, from_cid: u64, to_cid: u64) -> Result<usize, AccountError> {
+        self.recorder
+            .record(self.inner.copy_byte_map(from_cid, to_cid))
+            .await
+    }
+
+    async fn get_byte_map_values_by_key(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+    ) -> Result<HashMap<String, Vec<u8>>, AccountError> {
+        self.recorder
+            .record(
+                self.inner
+                    .get_byte_map_values_by_key(implicated_cid, peer_cid, key),
+            )
+            .await
+    }
+
+    async fn remove_byte_map_values_by_key(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+    ) -> Result<HashMap<String, Vec<u8>>, AccountError> {
+        self.recorder
+            .record(
+                self.inner
+                    .remove_byte_map_values_by_key(implicated_cid, peer_cid, key),
+            )
+            .await
+    }
+
+    async fn get_byte_map_entries(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Vec<ByteMapEntry>, AccountError> {
+        self.recorder
+            .record(self.inner.get_byte_map_entries(implicated_cid, peer_cid))
+            .await
+    }
+
+    async fn list_byte_map_keys(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Vec<String>, AccountError> {
+        self.recorder
+            .record(self.inner.list_byte_map_keys(implicated_cid, peer_cid))
+            .await
+    }
+
+    async fn export_byte_map_for_peer(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Vec<u8>, AccountError> {
+        self.recorder
+            .record(
+                self.inner
+                    .export_byte_map_for_peer(implicated_cid, peer_cid),
+            )
+            .await
+    }
+
+    async fn import_byte_map_for_peer(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        blob: &[u8],
+        merge: bool,
+    ) -> Result<usize, AccountError> {
+        self.recorder
+            .record(
+                self.inner
+                    .import_byte_map_for_peer(implicated_cid, peer_cid, blob, merge),
+            )
+            .await
+    }
+
+    async fn subscribe_byte_map_changes(
+        &self,
+        key_filter: Option<&str>,
+    ) -> Result<BoxStream<'static, ByteMapChange>, AccountError> {
+        self.inner.subscribe_byte_map_changes(key_filter).await
+    }
+
+    async fn store_group(
+        &self,
+        owner_cid: u64,
+        group_id: u128,
+        name: Option<String>,
+        members: &[u64],
+    ) -> Result<(), AccountError> {
+        self.recorder
+            .record(self.inner.store_group(owner_cid, group_id, name, members))
+            .await
+    }
+
+    async fn add_group_members(
+        &self,
+        owner_cid: u64,
+        group_id: u128,
+        members: &[u64],
+    ) -> Result<bool, AccountError> {
+        self.recorder
+            .record(self.inner.add_group_members(owner_cid, group_id, members))
+            .await
+    }
+
+    async fn remove_group_members(
+        &self,
+        owner_cid: u64,
+        group_id: u128,
+        members: &[u64],
+    ) -> Result<bool, AccountError> {
+        self.recorder
+            .record(
+                self.inner
+                    .remove_group_members(owner_cid, group_id, members),
+            )
+            .await
+    }
+
+    async fn remove_group(&self, owner_cid: u64, group_id: u128) -> Result<bool, AccountError> {
+        self.recorder
+            .record(self.inner.remove_group(owner_cid, group_id))
+            .await
+    }
+
+    async fn get_client_groups(&self, cid: u64) -> Result<Vec<GroupInfo>, AccountError> {
+        self.recorder
+            .record(self.inner.get_client_groups(cid))
+            .await
+    }
+
+    async fn get_group_members(
+        &self,
+        owner_cid: u64,
+        group_id: u128,
+    ) -> Result<Option<Vec<u64>>, AccountError> {
+        self.recorder
+            .record(self.inner.get_group_members(owner_cid, group_id))
+            .await
+    }
+
+    async fn stream_object_to_backend(
+        &self,
+        source: UnboundedReceiver<Vec<u8>>,
+        sink_metadata: Arc<dyn StreamableTargetInformation>,
+        status_tx: UnboundedSender<ObjectTransferStatus>,
+    ) -> Result<(), AccountError> {
+        self.recorder
+            .record(
+                self.inner
+                    .stream_object_to_backend(source, sink_metadata, status_tx),
+            )
+            .await
+    }
+
+    async fn revfs_get_file_info(
+        &self,
+        cid: u64,
+        virtual_path: std::path::PathBuf,
+    ) -> Result<(Box<dyn ObjectSource>, SecurityLevel), AccountError> {
+        self.recorder
+            .record(self.inner.revfs_get_file_info(cid, virtual_path))
+            .await
+    }
+
+    async fn revfs_delete(
+        &self,
+        cid: u64,
+        virtual_path: std::path::PathBuf,
+    ) -> Result<(), AccountError> {
+        self.recorder
+            .record(self.inner.revfs_delete(cid, virtual_path))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::memory::MemoryBackend;
+    use crate::backend::metrics::{BackendMetricsRecorder, MeteredBackend};
+    use crate::backend::{BackendConnection, PersistenceHandler};
+    use crate::misc::AccountError;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn records_operations_and_failures() {
+        let recorder = BackendMetricsRecorder::default();
+        recorder
+            .record(async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert!(recorder
+            .record(async { Err::<(), _>(AccountError::msg("failed")) })
+            .await
+            .is_err());
+        recorder.on_lock_contended();
+
+        let metrics = recorder.snapshot();
+        assert_eq!(metrics.active_connections, 0);
+        assert_eq!(metrics.total_queries, 2);
+        assert_eq!(metrics.failed_queries, 1);
+        assert!(metrics.avg_latency_ms >= 5f64);
+        assert_eq!(metrics.lock_contentions, 1);
+    }
+
+    #[tokio::test]
+    async fn dropped_operations_are_no_longer_active() {
+        let recorder = BackendMetricsRecorder::default();
+        let operation = recorder.record(futures::future::pending::<Result<(), AccountError>>());
+        assert!(tokio::time::timeout(Duration::from_millis(10), operation)
+            .await
+            .is_err());
+
+        let metrics = recorder.snapshot();
+        assert_eq!(metrics.active_connections, 0);
+        assert_eq!(metrics.total_queries, 0);
+    }
+
+    #[tokio::test]
+    async fn metered_backend_counts_each_operation() {
+        let handler: PersistenceHandler =
+            PersistenceHandler::create(MeteredBackend::new(MemoryBackend::default()))
+                .await
+                .unwrap();
+        assert_eq!(handler.metrics().total_queries, 0);
+
+        assert!(!handler.cid_is_registered(1).await.unwrap());
+        assert!(handler.get_cnac_by_cid(1).await.unwrap().is_none());
+        assert!(handler.deactivate_cnac(1).await.is_err());
+
+        let metrics = handler.metrics();
+        assert_eq!(metrics.total_queries, 3);
+        assert_eq!(metrics.failed_queries, 1);
+        assert_eq!(metrics.active_connections, 0);
+    }
+}
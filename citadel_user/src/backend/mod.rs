@@ -1,13 +1,24 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::hash::Hasher;
 use std::ops::Deref;
+#[cfg(all(feature = "sled", not(target_family = "wasm")))]
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+use unicode_normalization::UnicodeNormalization;
 
 use citadel_crypt::fcm::fcm_ratchet::ThinRatchet;
 use citadel_crypt::stacked_ratchet::{Ratchet, StackedRatchet};
 
+use crate::backend::metrics::BackendMetrics;
+#[cfg(all(feature = "mongo", not(coverage)))]
+use crate::backend::mongo_backend::MongoConnectionOptions;
 #[cfg(all(feature = "sql", not(coverage)))]
 use crate::backend::mysql_backend::SqlConnectionOptions;
 #[cfg(all(feature = "redis", not(coverage)))]
@@ -15,9 +26,17 @@ use crate::backend::redis_backend::RedisConnectionOptions;
 use crate::backend::utils::misc::StreamableTargetInformation;
 use crate::backend::utils::ObjectTransferStatus;
 use crate::client_account::{ClientNetworkAccount, MutualPeer};
-use crate::misc::{AccountError, CNACMetadata};
+use crate::external_services::apns::ApnsKeys;
+use crate::misc::{
+    check_credential_formatting_with, created_within, format_timestamp, parse_formatted_timestamp,
+    AccountError, ByteMapChange, ByteMapEntry, CNACMetadata, ClientSummary, CredentialPolicy,
+    GroupInfo, GroupRole,
+};
+use crate::peer_list::PeerListDelta;
+use crate::serialization::SyncIO;
 use citadel_crypt::prelude::SecurityLevel;
 use citadel_crypt::streaming_crypt_scrambler::ObjectSource;
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::UnboundedSender;
 
 /// Implementation for the default filesystem backend
@@ -26,16 +45,29 @@ pub mod filesystem_backend;
 /// Implementation for an in-memory backend. No synchronization occurs.
 /// This is useful for no-fs environments
 pub mod memory;
+/// Health metrics for backends
+pub mod metrics;
+#[cfg(all(feature = "mongo", not(coverage)))]
+/// Implementation for the MongoDB backend
+pub mod mongo_backend;
 #[cfg(all(feature = "sql", not(coverage)))]
 /// Implementation for the SQL backend
 pub mod mysql_backend;
 #[cfg(all(feature = "redis", not(coverage)))]
 /// Implementation for the redis backend
 pub mod redis_backend;
+#[cfg(all(feature = "sled", not(target_family = "wasm")))]
+/// Implementation for the embedded sled backend
+pub mod sled_backend;
 /// Utils for the backend trait
 #[allow(missing_docs)]
 pub mod utils;
 
+/// The default number of times a remote backend retries connecting before giving up
+pub const DEFAULT_CONNECT_RETRIES: u32 = 3;
+/// The default delay before the first connect retry. Each subsequent retry doubles the delay
+pub const DEFAULT_CONNECT_BACKOFF: Duration = Duration::from_millis(500);
+
 /// Used when constructing the account manager
 #[derive(Clone, Debug, Eq, PartialEq)]
 #[allow(variant_size_differences)]
@@ -53,6 +85,12 @@ pub enum BackendType {
     #[cfg(all(feature = "redis", not(coverage)))]
     /// Synchronization will occur on a remote redis database
     Redis(String, RedisConnectionOptions),
+    #[cfg(all(feature = "mongo", not(coverage)))]
+    /// Synchronization will occur on a remote MongoDB database
+    Mongo(String, MongoConnectionOptions),
+    #[cfg(all(feature = "sled", not(target_family = "wasm")))]
+    /// Synchronization will occur on an embedded sled database in the given directory
+    Sled(PathBuf),
 }
 
 impl BackendType {
@@ -67,6 +105,13 @@ impl BackendType {
             }
         }
 
+        #[cfg(all(feature = "mongo", not(coverage)))]
+        {
+            if addr.starts_with("mongodb") {
+                return Ok(BackendType::mongo(addr));
+            }
+        }
+
         #[cfg(all(feature = "sql", not(coverage)))]
         {
             if addr.starts_with("mysql")
@@ -77,6 +122,13 @@ impl BackendType {
             }
         }
 
+        #[cfg(all(feature = "sled", not(target_family = "wasm")))]
+        {
+            if addr.starts_with("sled:") {
+                return Ok(Self::sled(addr.replacen("sled:", "", 1)));
+            }
+        }
+
         #[cfg(all(feature = "filesystem", not(target_family = "wasm")))]
         {
             if addr.starts_with("file:") {
@@ -84,7 +136,7 @@ impl BackendType {
             }
         }
 
-        Err(AccountError::msg(format!("The addr '{addr}' is not a valid target (hint: ensure either 'redis', 'sql' or 'filesystem' features are enabled when compiling")))
+        Err(AccountError::msg(format!("The addr '{addr}' is not a valid target (hint: ensure either 'redis', 'sql', 'mongo', 'sled' or 'filesystem' features are enabled when compiling")))
     }
 
     #[cfg(all(feature = "filesystem", not(target_family = "wasm")))]
@@ -94,6 +146,13 @@ impl BackendType {
         Self::Filesystem(path.into().replace("file:", ""))
     }
 
+    #[cfg(all(feature = "sled", not(target_family = "wasm")))]
+    /// For requesting the use of an embedded sled database stored in the directory `path`.
+    /// URL format: sled:/path/to/directory
+    pub fn sled<T: Into<PathBuf>>(path: T) -> Self {
+        Self::Sled(path.into())
+    }
+
     #[cfg(all(feature = "redis", not(coverage)))]
     /// For requesting the use of the redis backend driver.
     /// URL format: redis://[<username>][:<password>@]<hostname>[:port][/<db>]
@@ -109,6 +168,20 @@ impl BackendType {
         BackendType::Redis(url.into(), opts)
     }
 
+    #[cfg(all(feature = "mongo", not(coverage)))]
+    /// For requesting the use of the MongoDB backend driver.
+    /// URL format: mongodb://[<username>:<password>@]<hostname>[:port][/<database>]
+    /// or mongodb+srv://[<username>:<password>@]<hostname>[/<database>]
+    pub fn mongo<T: Into<String>>(url: T) -> BackendType {
+        Self::mongo_with(url, Default::default())
+    }
+
+    #[cfg(all(feature = "mongo", not(coverage)))]
+    /// Like [`Self::mongo`], but with custom options
+    pub fn mongo_with<T: Into<String>>(url: T, opts: MongoConnectionOptions) -> BackendType {
+        BackendType::Mongo(url.into(), opts)
+    }
+
     /// For requesting the use of the SqlBackend driver. Url should be in the form:
     /// "mysql://username:password@ip/database"
     /// "postgres:// [...]"
@@ -125,6 +198,112 @@ impl BackendType {
     pub fn sql_with<T: Into<String>>(url: T, opts: SqlConnectionOptions) -> BackendType {
         BackendType::SQLDatabase(url.into(), opts)
     }
+
+    /// Describes this backend in the URL format accepted by [`Self::new`], with any password
+    /// within the URL redacted
+    pub fn to_redacted_url(&self) -> String {
+        match self {
+            BackendType::InMemory => "memory".to_string(),
+            #[cfg(all(feature = "filesystem", not(target_family = "wasm")))]
+            BackendType::Filesystem(path) => format!("file:{path}"),
+            #[cfg(all(feature = "sql", not(coverage)))]
+            BackendType::SQLDatabase(url, _) => crate::misc::redact_url(url),
+            #[cfg(all(feature = "redis", not(coverage)))]
+            BackendType::Redis(url, _) => crate::misc::redact_url(url),
+            #[cfg(all(feature = "mongo", not(coverage)))]
+            BackendType::Mongo(url, _) => crate::misc::redact_url(url),
+            #[cfg(all(feature = "sled", not(target_family = "wasm")))]
+            BackendType::Sled(path) => format!("sled:{}", path.display()),
+        }
+    }
+}
+
+impl FromStr for BackendType {
+    type Err = AccountError;
+
+    /// Parses a backend by the scheme of `s`: `memory`, `file:<dir>` (or `filesystem:<dir>`),
+    /// `sled:<dir>`, `redis://...`, `mongodb://...`, or `mysql://...`, `postgres://...` and
+    /// `sqlite://...` for SQL. Schemes whose backend was not compiled in return an error naming
+    /// the feature to enable
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, rest) = s.split_once(':').unwrap_or((s, ""));
+
+        match scheme.to_ascii_lowercase().as_str() {
+            "memory" if rest.is_empty() => Ok(BackendType::InMemory),
+
+            "file" | "filesystem" => {
+                if rest.is_empty() {
+                    return Err(AccountError::msg(format!(
+                        "The filesystem backend requires a directory (e.g., '{scheme}:/path/to/dir')"
+                    )));
+                }
+
+                #[cfg(all(feature = "filesystem", not(target_family = "wasm")))]
+                {
+                    Ok(BackendType::filesystem(rest))
+                }
+                #[cfg(not(all(feature = "filesystem", not(target_family = "wasm"))))]
+                {
+                    Err(backend_feature_disabled(scheme, "filesystem"))
+                }
+            }
+
+            "sled" => {
+                #[cfg(all(feature = "sled", not(target_family = "wasm")))]
+                {
+                    Ok(BackendType::sled(rest))
+                }
+                #[cfg(not(all(feature = "sled", not(target_family = "wasm"))))]
+                {
+                    Err(backend_feature_disabled(scheme, "sled"))
+                }
+            }
+
+            "redis" | "rediss" | "redis+unix" => {
+                #[cfg(all(feature = "redis", not(coverage)))]
+                {
+                    Ok(BackendType::redis(s))
+                }
+                #[cfg(not(all(feature = "redis", not(coverage))))]
+                {
+                    Err(backend_feature_disabled(scheme, "redis"))
+                }
+            }
+
+            "mongodb" | "mongodb+srv" => {
+                #[cfg(all(feature = "mongo", not(coverage)))]
+                {
+                    Ok(BackendType::mongo(s))
+                }
+                #[cfg(not(all(feature = "mongo", not(coverage))))]
+                {
+                    Err(backend_feature_disabled(scheme, "mongo"))
+                }
+            }
+
+            "mysql" | "postgres" | "postgresql" | "sqlite" => {
+                #[cfg(all(feature = "sql", not(coverage)))]
+                {
+                    Ok(BackendType::sql(s))
+                }
+                #[cfg(not(all(feature = "sql", not(coverage))))]
+                {
+                    Err(backend_feature_disabled(scheme, "sql"))
+                }
+            }
+
+            _ => Err(AccountError::msg(format!(
+                "Unknown backend scheme '{scheme}' (expected one of 'memory', 'file', 'sled', 'redis', 'mongodb', 'mysql', 'postgres' or 'sqlite')"
+            ))),
+        }
+    }
+}
+
+#[allow(dead_code)]
+fn backend_feature_disabled(scheme: &str, feature: &str) -> AccountError {
+    AccountError::msg(format!(
+        "The '{scheme}' backend is unavailable because the '{feature}' feature was not enabled when compiling"
+    ))
 }
 
 /// An interface for synchronizing information do differing target
@@ -134,6 +313,16 @@ pub trait BackendConnection<R: Ratchet, Fcm: Ratchet>: Send + Sync {
     async fn connect(&mut self) -> Result<(), AccountError>;
     /// Determines if connected or not
     async fn is_connected(&self) -> Result<bool, AccountError>;
+    /// Flushes any pending writes and releases the connections held by the backend. Once called,
+    /// the backend, along with every handler sharing it, must no longer be used
+    async fn disconnect(&self) -> Result<(), AccountError> {
+        Ok(())
+    }
+    /// Returns a snapshot of the health of this backend. Backends that keep no metrics return
+    /// [`BackendMetrics::default`]. See [`metrics::MeteredBackend`]
+    fn metrics(&self) -> BackendMetrics {
+        BackendMetrics::default()
+    }
     /// Saves the entire cnac to the DB
     async fn save_cnac(&self, cnac: &ClientNetworkAccount<R, Fcm>) -> Result<(), AccountError>;
     /// Find a CNAC by cid
@@ -141,34 +330,114 @@ pub trait BackendConnection<R: Ratchet, Fcm: Ratchet>: Send + Sync {
         &self,
         cid: u64,
     ) -> Result<Option<ClientNetworkAccount<R, Fcm>>, AccountError>;
-    /// Gets the client by username
+    /// Gets the client by username, ignoring case. See [`Self::get_cid_by_stored_username`]
     async fn get_client_by_username(
         &self,
         username: &str,
     ) -> Result<Option<ClientNetworkAccount<R, Fcm>>, AccountError> {
-        self.get_cnac_by_cid(username_to_cid(username)).await
+        match self.get_cid_by_stored_username(username).await? {
+            Some(cid) => self.get_cnac_by_cid(cid).await,
+            None => Ok(None),
+        }
     }
     /// Determines if a CID is registered
     async fn cid_is_registered(&self, cid: u64) -> Result<bool, AccountError>;
+    /// Determines if a CID belongs to a personal account, without loading the CNAC. Returns `None`
+    /// if the CID is not registered
+    async fn is_personal(&self, cid: u64) -> Result<Option<bool>, AccountError>;
     /// Removes a CNAC by cid
     async fn delete_cnac_by_cid(&self, cid: u64) -> Result<(), AccountError>;
     /// Removes all CNACs
     async fn purge(&self) -> Result<usize, AccountError>;
-    /// Determines if a username exists
+    /// Deactivates a CNAC without deleting it. A deactivated CNAC remains retrievable by CID, but
+    /// is excluded from [`Self::get_registered_impersonal_cids`] and may not connect. Deactivating
+    /// an already-deactivated CNAC keeps its original deactivation time
+    async fn deactivate_cnac(&self, cid: u64) -> Result<(), AccountError>;
+    /// Reactivates a CNAC deactivated by [`Self::deactivate_cnac`]
+    async fn reactivate_cnac(&self, cid: u64) -> Result<(), AccountError>;
+    /// Determines if a CID is registered and active
+    async fn cnac_is_active(&self, cid: u64) -> Result<bool, AccountError> {
+        Ok(self
+            .get_client_metadata(cid)
+            .await?
+            .map(|metadata| metadata.is_active)
+            .unwrap_or(false))
+    }
+    /// Permanently removes every CNAC deactivated before `cutoff`, returning the number removed
+    async fn purge_deactivated_before(&self, cutoff: SystemTime) -> Result<usize, AccountError>;
+    /// Determines if a client holds `username`, ignoring case. See
+    /// [`Self::get_cid_by_stored_username`]
     async fn username_exists(&self, username: &str) -> Result<bool, AccountError> {
-        self.cid_is_registered(username_to_cid(username)).await
+        Ok(self.get_cid_by_stored_username(username).await?.is_some())
     }
-    /// Returns a list of impersonal cids
+    /// Returns a list of active impersonal cids
     async fn get_registered_impersonal_cids(
         &self,
         limit: Option<i32>,
     ) -> Result<Option<Vec<u64>>, AccountError>;
+    /// Returns up to `limit` impersonal cids in ascending order, skipping the first `offset`.
+    /// The ordering is stable across calls, allowing callers to page through all registered clients
+    async fn get_registered_impersonal_cids_paged(
+        &self,
+        offset: u64,
+        limit: u32,
+    ) -> Result<Vec<u64>, AccountError>;
+    /// Returns up to `limit` active impersonal cids created strictly after `created_after` and
+    /// strictly before `created_before`, where either bound may be omitted. Backends that index
+    /// the creation date should override this, since the default scans the metadata of every client
+    async fn get_registered_impersonal_cids_created_between(
+        &self,
+        created_after: Option<DateTime<Utc>>,
+        created_before: Option<DateTime<Utc>>,
+        limit: Option<i32>,
+    ) -> Result<Option<Vec<u64>>, AccountError> {
+        let active = self
+            .get_registered_impersonal_cids(None)
+            .await?
+            .unwrap_or_default()
+            .into_iter()
+            .collect::<HashSet<u64>>();
+        let limit = limit.map(|limit| limit as usize).unwrap_or(usize::MAX);
+        let mut ret = Vec::new();
+        let mut stream = self.stream_clients_metadata().await?;
+        while let Some(metadata) = stream.try_next().await? {
+            if ret.len() >= limit {
+                break;
+            }
+
+            if active.contains(&metadata.cid)
+                && created_within(&metadata.creation_date, created_after, created_before)
+            {
+                ret.push(metadata.cid);
+            }
+        }
+
+        Ok(if ret.is_empty() { None } else { Some(ret) })
+    }
     /// Gets the username by CID
     async fn get_username_by_cid(&self, cid: u64) -> Result<Option<String>, AccountError>;
     /// Gets the CID by username
     fn get_cid_by_username(&self, username: &str) -> u64 {
         username_to_cid(username)
     }
+    /// Returns the CID of the client whose stored username matches `username` once both are
+    /// [normalized](normalize_username), as kept in the username index of the backend. Unlike
+    /// [`Self::get_cid_by_username`], this does not assume the CID was derived from the username,
+    /// and thus finds clients renamed after registration. The index is written along with each
+    /// CNAC, and is backfilled from the saved CNACs when connecting to a backend that predates it
+    async fn get_cid_by_stored_username(&self, username: &str)
+        -> Result<Option<u64>, AccountError>;
+    /// Changes the username of `cid`, along with the username stored for it in the peer list of
+    /// each of its peers. The CID and peers of the client are unchanged. Fails with
+    /// [`AccountError::InvalidUsername`] if `new_username` does not conform to
+    /// `credential_policy`, which should be the policy the node registers clients with, or with
+    /// [`AccountError::ClientExists`] if another client holds `new_username`
+    async fn change_username(
+        &self,
+        cid: u64,
+        new_username: &str,
+        credential_policy: &CredentialPolicy,
+    ) -> Result<(), AccountError>;
     /// Registers two peers together
     async fn register_p2p_as_server(&self, cid0: u64, cid1: u64) -> Result<(), AccountError>;
     /// registers p2p as client
@@ -191,6 +460,15 @@ pub trait BackendConnection<R: Ratchet, Fcm: Ratchet>: Send + Sync {
         &self,
         implicated_cid: u64,
     ) -> Result<Option<Vec<u64>>, AccountError>;
+    /// Returns the number of hyperlan peers for the client, or 0 if it has none. Backends that can
+    /// count the peers without fetching them should override this
+    async fn get_hyperlan_peer_count(&self, implicated_cid: u64) -> Result<usize, AccountError> {
+        Ok(self
+            .get_hyperlan_peer_list(implicated_cid)
+            .await?
+            .map(|peers| peers.len())
+            .unwrap_or(0))
+    }
     /// Returns the metadata for a client
     async fn get_client_metadata(
         &self,
@@ -200,13 +478,177 @@ pub trait BackendConnection<R: Ratchet, Fcm: Ratchet>: Send + Sync {
     async fn get_clients_metadata(
         &self,
         limit: Option<i32>,
-    ) -> Result<Vec<CNACMetadata>, AccountError>;
+    ) -> Result<Vec<CNACMetadata>, AccountError> {
+        let stream = self.stream_clients_metadata().await?;
+        if let Some(limit) = limit {
+            stream.take(limit as _).try_collect().await
+        } else {
+            stream.try_collect().await
+        }
+    }
+    /// Streams the metadata of every client without loading every client at once. Useful for
+    /// migrations or audits over large deployments
+    async fn stream_clients_metadata(
+        &self,
+    ) -> Result<BoxStream<'_, Result<CNACMetadata, AccountError>>, AccountError>;
+    /// Returns each username held by more than one client, along with the CIDs of those clients
+    /// in ascending order. Usernames are compared ignoring case, as they are when registering.
+    /// Duplicates only arise if the username index was bypassed, such as by editing the backend
+    /// directly, and leave lookups by username unable to tell the clients apart
+    async fn find_duplicate_usernames(&self) -> Result<Vec<(String, Vec<u64>)>, AccountError> {
+        let mut holders: HashMap<String, Vec<u64>> = HashMap::new();
+        let mut clients = self.stream_clients_metadata().await?;
+        while let Some(metadata) = clients.try_next().await? {
+            holders
+                .entry(normalize_username(&metadata.username))
+                .or_default()
+                .push(metadata.cid);
+        }
+
+        let mut duplicates = holders
+            .into_iter()
+            .filter(|(_, cids)| cids.len() > 1)
+            .map(|(username, mut cids)| {
+                cids.sort_unstable();
+                (username, cids)
+            })
+            .collect::<Vec<_>>();
+        duplicates.sort_unstable();
+        Ok(duplicates)
+    }
+    /// Returns the metadata, peer count, and push configuration presence for a client in one call.
+    /// Backends that can fetch these together should override this
+    async fn get_client_summary(&self, cid: u64) -> Result<Option<ClientSummary>, AccountError> {
+        let cnac = if let Some(cnac) = self.get_cnac_by_cid(cid).await? {
+            cnac
+        } else {
+            return Ok(None);
+        };
+
+        let peer_count = self.get_hyperlan_peer_count(cid).await?;
+        let mut summary = cnac.get_summary();
+        summary.metadata.is_active = self.cnac_is_active(cid).await?;
+
+        Ok(Some(ClientSummary {
+            peer_count,
+            ..summary
+        }))
+    }
+    /// Records `time` as the last successful connect of `cid`. Only the timestamp is written,
+    /// rather than the entire CNAC. Fails with [`AccountError::ClientNonExists`] if `cid` is not
+    /// registered
+    async fn set_last_connect(&self, cid: u64, time: DateTime<Utc>) -> Result<(), AccountError> {
+        if !self.cid_is_registered(cid).await? {
+            return Err(AccountError::ClientNonExists(cid));
+        }
+
+        let _ = self
+            .store_byte_map_value(
+                cid,
+                0,
+                LAST_CONNECT,
+                LAST_CONNECT,
+                format_timestamp(time).into_bytes(),
+            )
+            .await?;
+        Ok(())
+    }
+    /// Returns the time `cid` last connected successfully, or None if it never has
+    async fn get_last_connect(&self, cid: u64) -> Result<Option<DateTime<Utc>>, AccountError> {
+        self.get_byte_map_value(cid, 0, LAST_CONNECT, LAST_CONNECT)
+            .await?
+            .map(|time| parse_formatted_timestamp(&String::from_utf8_lossy(&time)))
+            .transpose()
+    }
+    /// Returns the time each of `cids` last connected successfully. Clients that never connected
+    /// are omitted. Backends that can fetch these together should override this
+    async fn get_last_connects(
+        &self,
+        cids: &[u64],
+    ) -> Result<HashMap<u64, DateTime<Utc>>, AccountError> {
+        let mut last_connects = HashMap::with_capacity(cids.len());
+        for cid in cids {
+            if let Some(time) = self.get_last_connect(*cid).await? {
+                let _ = last_connects.insert(*cid, time);
+            }
+        }
+
+        Ok(last_connects)
+    }
+    /// Stores the APNs keys of `cid`, used to wake its device. Passing `None` removes them. Fails
+    /// with [`AccountError::ClientNonExists`] if `cid` is not registered
+    async fn update_apns_keys(&self, cid: u64, keys: Option<ApnsKeys>) -> Result<(), AccountError> {
+        if !self.cid_is_registered(cid).await? {
+            return Err(AccountError::ClientNonExists(cid));
+        }
+
+        if let Some(keys) = keys {
+            let _ = self
+                .store_byte_map_value(cid, 0, APNS_KEYS, APNS_KEYS, keys.serialize_to_vector()?)
+                .await?;
+        } else {
+            let _ = self
+                .remove_byte_map_value(cid, 0, APNS_KEYS, APNS_KEYS)
+                .await?;
+        }
+
+        Ok(())
+    }
+    /// Returns the APNs keys of `cid`, or None if none were stored
+    async fn get_apns_keys(&self, cid: u64) -> Result<Option<ApnsKeys>, AccountError> {
+        self.get_byte_map_value(cid, 0, APNS_KEYS, APNS_KEYS)
+            .await?
+            .map(ApnsKeys::deserialize_from_owned_vector)
+            .transpose()
+    }
     /// Gets hyperlan peer
     async fn get_hyperlan_peer_by_cid(
         &self,
         implicated_cid: u64,
         peer_cid: u64,
     ) -> Result<Option<MutualPeer>, AccountError>;
+    /// Sets the alias `implicated_cid` gives to `peer_cid`, returned by [`Self::get_peer_alias`].
+    /// Aliases are local to `implicated_cid` and do not affect the peer's own account. Passing
+    /// `None` clears the alias
+    async fn set_peer_alias(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        alias: Option<String>,
+    ) -> Result<(), AccountError> {
+        if !self.hyperlan_peer_exists(implicated_cid, peer_cid).await? {
+            return Err(AccountError::ClientNonExists(peer_cid));
+        }
+
+        if let Some(alias) = alias {
+            let _ = self
+                .store_byte_map_value(
+                    implicated_cid,
+                    peer_cid,
+                    PEER_ALIAS,
+                    PEER_ALIAS,
+                    alias.into_bytes(),
+                )
+                .await?;
+        } else {
+            let _ = self
+                .remove_byte_map_value(implicated_cid, peer_cid, PEER_ALIAS, PEER_ALIAS)
+                .await?;
+        }
+
+        Ok(())
+    }
+    /// Returns the alias `implicated_cid` gives to `peer_cid`, if any
+    async fn get_peer_alias(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Option<String>, AccountError> {
+        self.get_byte_map_value(implicated_cid, peer_cid, PEER_ALIAS, PEER_ALIAS)
+            .await?
+            .map(|alias| String::from_utf8(alias).map_err(|err| AccountError::msg(err.to_string())))
+            .transpose()
+    }
     /// Determines if the peer exists or not
     async fn hyperlan_peer_exists(
         &self,
@@ -225,6 +667,21 @@ pub trait BackendConnection<R: Ratchet, Fcm: Ratchet>: Send + Sync {
         implicated_cid: u64,
         peers: &[u64],
     ) -> Result<Vec<MutualPeer>, AccountError>;
+    /// Returns the containers of only the input cids that are mutual to the implicated cid, in the
+    /// order given. Unlike [`Self::hyperlan_peers_are_mutuals`], non-mutuals are skipped entirely
+    async fn get_mutual_hyperlan_peers(
+        &self,
+        implicated_cid: u64,
+        peers: &[u64],
+    ) -> Result<Vec<MutualPeer>, AccountError> {
+        let mut mutuals = self
+            .get_hyperlan_peers(implicated_cid, peers)
+            .await?
+            .into_iter()
+            .map(|peer| (peer.cid, peer))
+            .collect::<HashMap<_, _>>();
+        Ok(peers.iter().filter_map(|cid| mutuals.remove(cid)).collect())
+    }
     /// Gets hyperland peer by username
     async fn get_hyperlan_peer_by_username(
         &self,
@@ -239,12 +696,40 @@ pub trait BackendConnection<R: Ratchet, Fcm: Ratchet>: Send + Sync {
         &self,
         implicated_cid: u64,
     ) -> Result<Option<Vec<MutualPeer>>, AccountError>;
-    /// Synchronizes the list locally. Returns true if needs to be saved
+    /// Returns up to `limit` peers of `implicated_cid` whose username starts with `prefix`,
+    /// ignoring case, ordered by username. Backends that can filter by username should override
+    /// this, since the default loads the entire peer list
+    async fn search_hyperlan_peers_by_prefix(
+        &self,
+        implicated_cid: u64,
+        prefix: &str,
+        limit: u32,
+    ) -> Result<Vec<MutualPeer>, AccountError> {
+        let prefix = normalize_username(prefix);
+        let mut peers = self
+            .get_hyperlan_peer_list_as_server(implicated_cid)
+            .await?
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|peer| {
+                let username = normalize_username(peer.username.as_deref()?);
+                username.starts_with(&prefix).then_some((username, peer))
+            })
+            .collect::<Vec<_>>();
+        peers.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(peers
+            .into_iter()
+            .take(limit as usize)
+            .map(|(_, peer)| peer)
+            .collect())
+    }
+    /// Replaces the local peer list of `cnac` with `peers`, as sent by the server. Returns the
+    /// changes made, which are empty if the lists already matched, in which case nothing is saved
     async fn synchronize_hyperlan_peer_list_as_client(
         &self,
         cnac: &ClientNetworkAccount<R, Fcm>,
         peers: Vec<MutualPeer>,
-    ) -> Result<(), AccountError>;
+    ) -> Result<PeerListDelta, AccountError>;
     /// Returns a vector of bytes from the byte map
     async fn get_byte_map_value(
         &self,
@@ -253,6 +738,14 @@ pub trait BackendConnection<R: Ratchet, Fcm: Ratchet>: Send + Sync {
         key: &str,
         sub_key: &str,
     ) -> Result<Option<Vec<u8>>, AccountError>;
+    /// Returns the value stored under each `(key, sub_key)` pair of `keys` in a single round trip.
+    /// Pairs without a value, or whose value expired, are omitted
+    async fn get_byte_map_values(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        keys: &[(String, String)],
+    ) -> Result<HashMap<(String, String), Vec<u8>>, AccountError>;
     /// Removes a value from the byte map, returning the previous value
     async fn remove_byte_map_value(
         &self,
@@ -270,6 +763,45 @@ pub trait BackendConnection<R: Ratchet, Fcm: Ratchet>: Send + Sync {
         sub_key: &str,
         value: Vec<u8>,
     ) -> Result<Option<Vec<u8>>, AccountError>;
+    /// Stores a value in the byte map that expires after `ttl`, either creating or overwriting any
+    /// pre-existing value. Once expired, the value is treated as though it was never set
+    async fn store_byte_map_value_with_expiry(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> Result<Option<Vec<u8>>, AccountError>;
+    /// Atomically replaces the value in the byte map with `new`, but only if the current value
+    /// equals `expected`. An `expected` of `None` only sets the value if it is absent (or expired).
+    /// Returns true if the swap occurred. Any expiry on the previous value is cleared
+    #[allow(clippy::too_many_arguments)]
+    async fn compare_and_swap_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        expected: Option<Vec<u8>>,
+        new: Vec<u8>,
+    ) -> Result<bool, AccountError>;
+    /// Returns the value in the byte map, or if it is absent (or expired), atomically stores and
+    /// returns the value produced by `default`. `default` only runs when a value is stored. The
+    /// stored value does not expire. See [`PersistenceHandler::get_or_insert_byte_map_value`]
+    async fn get_or_insert_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        default: Box<dyn FnOnce() -> Vec<u8> + Send + '_>,
+    ) -> Result<Vec<u8>, AccountError>;
+    /// Copies every unexpired byte map value owned by `from_cid` to `to_cid`, keeping any expiry.
+    /// Values under `to_cid` with the same peer, key and sub key are overwritten. Returns the
+    /// number of values copied
+    async fn copy_byte_map(&self, from_cid: u64, to_cid: u64) -> Result<usize, AccountError>;
     /// Obtains a list of K,V pairs such that they reside inside `key`
     async fn get_byte_map_values_by_key(
         &self,
@@ -284,6 +816,208 @@ pub trait BackendConnection<R: Ratchet, Fcm: Ratchet>: Send + Sync {
         peer_cid: u64,
         key: &str,
     ) -> Result<HashMap<String, Vec<u8>>, AccountError>;
+    /// Returns every unexpired value in the byte map of the relationship between `implicated_cid`
+    /// and `peer_cid`, along with its expiry
+    async fn get_byte_map_entries(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Vec<ByteMapEntry>, AccountError>;
+    /// Returns, in sorted order, the distinct keys holding at least one unexpired value in the
+    /// byte map of the relationship between `implicated_cid` and `peer_cid`. Empty if there are none.
+    /// Internal keys, see [`INTERNAL_BYTE_MAP_KEY_PREFIX`], are left out
+    async fn list_byte_map_keys(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Vec<String>, AccountError> {
+        let keys = self
+            .get_byte_map_entries(implicated_cid, peer_cid)
+            .await?
+            .into_iter()
+            .map(|entry| entry.key)
+            .filter(|key| !is_internal_byte_map_key(key))
+            .collect::<BTreeSet<String>>();
+        Ok(keys.into_iter().collect())
+    }
+    /// Exports every unexpired value in the byte map of the relationship between `implicated_cid`
+    /// and `peer_cid` as a self-describing blob, to be restored with
+    /// [`Self::import_byte_map_for_peer`]. Values under internal keys, see
+    /// [`INTERNAL_BYTE_MAP_KEY_PREFIX`], are left out
+    async fn export_byte_map_for_peer(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Vec<u8>, AccountError> {
+        export_byte_map::<R, Fcm, _>(self, implicated_cid, peer_cid, false).await
+    }
+    /// Imports a blob produced by [`Self::export_byte_map_for_peer`] into the byte map of the
+    /// relationship between `implicated_cid` and `peer_cid`, which need not be the relationship it
+    /// was exported from. If `merge` is false, the relationship's byte map is cleared first.
+    /// Otherwise, only values present in the blob are overwritten. Values that expired since the
+    /// export are skipped. Values under internal keys, see [`INTERNAL_BYTE_MAP_KEY_PREFIX`], are
+    /// neither cleared nor imported. Returns the number of values imported
+    async fn import_byte_map_for_peer(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        blob: &[u8],
+        merge: bool,
+    ) -> Result<usize, AccountError> {
+        import_byte_map::<R, Fcm, _>(self, implicated_cid, peer_cid, blob, merge, false).await
+    }
+    /// Returns a stream of the changes made to the byte map by every node sharing this backend,
+    /// restricted to values stored under `key_filter` if given. Only changes made through
+    /// [`Self::store_byte_map_value`], [`Self::store_byte_map_value_with_expiry`] and
+    /// [`Self::remove_byte_map_value`] are published. Backends that cannot publish changes, which
+    /// is all but redis with [`RedisConnectionOptions::enable_pubsub`] set, return an error
+    async fn subscribe_byte_map_changes(
+        &self,
+        _key_filter: Option<&str>,
+    ) -> Result<BoxStream<'static, ByteMapChange>, AccountError> {
+        Err(AccountError::msg(
+            "This backend does not publish byte map changes",
+        ))
+    }
+    /// Stores a group owned by `owner_cid`, overwriting any group with the same id. The owner is
+    /// always a member of its group
+    async fn store_group(
+        &self,
+        owner_cid: u64,
+        group_id: u128,
+        name: Option<String>,
+        members: &[u64],
+    ) -> Result<(), AccountError> {
+        let mut record = GroupRecord {
+            owner_cid,
+            group_id,
+            name,
+            members: vec![owner_cid],
+        };
+        record.add_members(members);
+        let _ = self
+            .store_byte_map_value(
+                owner_cid,
+                0,
+                GROUPS,
+                &group_id.to_string(),
+                record.serialize_to_vector()?,
+            )
+            .await?;
+
+        for member in &record.members {
+            store_group_membership(self, *member, owner_cid, group_id).await?;
+        }
+
+        Ok(())
+    }
+    /// Adds members to a stored group. Returns false if the group does not exist
+    async fn add_group_members(
+        &self,
+        owner_cid: u64,
+        group_id: u128,
+        members: &[u64],
+    ) -> Result<bool, AccountError> {
+        let updated = update_group(self, owner_cid, group_id, |record| {
+            record.add_members(members)
+        })
+        .await?;
+        if updated.is_none() {
+            return Ok(false);
+        }
+
+        for member in members {
+            store_group_membership(self, *member, owner_cid, group_id).await?;
+        }
+
+        Ok(true)
+    }
+    /// Removes members from a stored group. The owner cannot be removed; remove the group instead.
+    /// Returns false if the group does not exist
+    async fn remove_group_members(
+        &self,
+        owner_cid: u64,
+        group_id: u128,
+        members: &[u64],
+    ) -> Result<bool, AccountError> {
+        let updated = update_group(self, owner_cid, group_id, |record| {
+            record
+                .members
+                .retain(|member| *member == owner_cid || !members.contains(member))
+        })
+        .await?;
+        if updated.is_none() {
+            return Ok(false);
+        }
+
+        for member in members.iter().filter(|member| **member != owner_cid) {
+            let _ = self
+                .remove_byte_map_value(
+                    *member,
+                    0,
+                    GROUP_MEMBERSHIPS,
+                    &group_membership_sub_key(owner_cid, group_id),
+                )
+                .await?;
+        }
+
+        Ok(true)
+    }
+    /// Removes a stored group along with the membership of each of its members. Returns false if
+    /// the group does not exist
+    async fn remove_group(&self, owner_cid: u64, group_id: u128) -> Result<bool, AccountError> {
+        let record = if let Some(record) = self
+            .remove_byte_map_value(owner_cid, 0, GROUPS, &group_id.to_string())
+            .await?
+        {
+            GroupRecord::deserialize_from_owned_vector(record)?
+        } else {
+            return Ok(false);
+        };
+
+        for member in record.members {
+            let _ = self
+                .remove_byte_map_value(
+                    member,
+                    0,
+                    GROUP_MEMBERSHIPS,
+                    &group_membership_sub_key(owner_cid, group_id),
+                )
+                .await?;
+        }
+
+        Ok(true)
+    }
+    /// Returns every stored group `cid` is a member of, including the groups it owns
+    async fn get_client_groups(&self, cid: u64) -> Result<Vec<GroupInfo>, AccountError> {
+        let memberships = self
+            .get_byte_map_values_by_key(cid, 0, GROUP_MEMBERSHIPS)
+            .await?;
+        let mut groups = Vec::with_capacity(memberships.len());
+        for membership in memberships.into_values() {
+            let (owner_cid, group_id) = <(u64, u128)>::deserialize_from_owned_vector(membership)?;
+            // memberships are written separately from the group, so skip any that are stale
+            match get_group(self, owner_cid, group_id).await? {
+                Some(record) if record.members.contains(&cid) => {
+                    groups.push(record.into_group_info(cid))
+                }
+                _ => {}
+            }
+        }
+
+        groups.sort_by_key(|group| (group.owner_cid, group.group_id));
+        Ok(groups)
+    }
+    /// Returns the members of a stored group, or None if the group does not exist
+    async fn get_group_members(
+        &self,
+        owner_cid: u64,
+        group_id: u128,
+    ) -> Result<Option<Vec<u64>>, AccountError> {
+        Ok(get_group(self, owner_cid, group_id)
+            .await?
+            .map(|record| record.members))
+    }
     /// Streams an object to the backend
     async fn stream_object_to_backend(
         &self,
@@ -316,9 +1050,298 @@ pub trait BackendConnection<R: Ratchet, Fcm: Ratchet>: Send + Sync {
     }
 }
 
+/// Byte map keys beginning with this prefix are reserved for values the library stores on behalf of
+/// an account. They are left out of [`BackendConnection::list_byte_map_keys`], and are neither
+/// exported by [`BackendConnection::export_byte_map_for_peer`] nor imported by
+/// [`BackendConnection::import_byte_map_for_peer`]
+pub const INTERNAL_BYTE_MAP_KEY_PREFIX: &str = "_INTERNAL_";
+
+/// Whether `key` is reserved, see [`INTERNAL_BYTE_MAP_KEY_PREFIX`]
+pub fn is_internal_byte_map_key(key: &str) -> bool {
+    key.starts_with(INTERNAL_BYTE_MAP_KEY_PREFIX)
+}
+
+/// Implements [`BackendConnection::export_byte_map_for_peer`]. Account exports pass
+/// `include_internal` so that internal values follow the account to its new node
+pub(crate) async fn export_byte_map<R: Ratchet, Fcm: Ratchet, B>(
+    backend: &B,
+    implicated_cid: u64,
+    peer_cid: u64,
+    include_internal: bool,
+) -> Result<Vec<u8>, AccountError>
+where
+    B: BackendConnection<R, Fcm> + ?Sized,
+{
+    let entries = backend
+        .get_byte_map_entries(implicated_cid, peer_cid)
+        .await?
+        .into_iter()
+        .filter(|entry| include_internal || !is_internal_byte_map_key(&entry.key))
+        .collect();
+    ByteMapExport {
+        version: BYTE_MAP_EXPORT_VERSION,
+        implicated_cid,
+        peer_cid,
+        entries,
+    }
+    .serialize_to_vector()
+}
+
+/// Implements [`BackendConnection::import_byte_map_for_peer`]. Account imports pass
+/// `include_internal` to restore the internal values exported alongside the account
+pub(crate) async fn import_byte_map<R: Ratchet, Fcm: Ratchet, B>(
+    backend: &B,
+    implicated_cid: u64,
+    peer_cid: u64,
+    blob: &[u8],
+    merge: bool,
+    include_internal: bool,
+) -> Result<usize, AccountError>
+where
+    B: BackendConnection<R, Fcm> + ?Sized,
+{
+    let export = ByteMapExport::deserialize_from_vector(blob)?;
+    if export.version != BYTE_MAP_EXPORT_VERSION {
+        return Err(AccountError::msg(format!(
+            "Unsupported byte map export version {}",
+            export.version
+        )));
+    }
+
+    if !merge {
+        for entry in backend
+            .get_byte_map_entries(implicated_cid, peer_cid)
+            .await?
+        {
+            if include_internal || !is_internal_byte_map_key(&entry.key) {
+                let _ = backend
+                    .remove_byte_map_value(implicated_cid, peer_cid, &entry.key, &entry.sub_key)
+                    .await?;
+            }
+        }
+    }
+
+    let now = SystemTime::now();
+    let mut imported = 0;
+    for entry in export.entries {
+        let ByteMapEntry {
+            key,
+            sub_key,
+            value,
+            expires_at,
+        } = entry;
+
+        if !include_internal && is_internal_byte_map_key(&key) {
+            continue;
+        }
+
+        match expires_at {
+            Some(expires_at) => {
+                let ttl = match expires_at.duration_since(now) {
+                    Ok(ttl) if !ttl.is_zero() => ttl,
+                    _ => continue,
+                };
+                let _ = backend
+                    .store_byte_map_value_with_expiry(
+                        implicated_cid,
+                        peer_cid,
+                        &key,
+                        &sub_key,
+                        value,
+                        ttl,
+                    )
+                    .await?;
+            }
+            None => {
+                let _ = backend
+                    .store_byte_map_value(implicated_cid, peer_cid, &key, &sub_key, value)
+                    .await?;
+            }
+        }
+
+        imported += 1;
+    }
+
+    Ok(imported)
+}
+
+/// Bumped whenever the format of [`ByteMapExport`] changes
+const BYTE_MAP_EXPORT_VERSION: u32 = 1;
+
+/// The blob produced by [`BackendConnection::export_byte_map_for_peer`]
+#[derive(Serialize, Deserialize)]
+struct ByteMapExport {
+    version: u32,
+    /// The relationship the entries were exported from
+    implicated_cid: u64,
+    peer_cid: u64,
+    entries: Vec<ByteMapEntry>,
+}
+
+// peer alias byte map key layout, used by the default impls of set_peer_alias and get_peer_alias:
+// implicated cid -> peer cid -> PEER_ALIAS -> PEER_ALIAS -> alias
+const PEER_ALIAS: &str = "peer_alias";
+
+// last connect byte map key layout, used by the default impls of set_last_connect and
+// get_last_connect:
+// cid -> 0 -> LAST_CONNECT -> LAST_CONNECT -> RFC 3339 timestamp
+const LAST_CONNECT: &str = "_INTERNAL_LAST_CONNECT";
+
+// apns byte map key layout, used by the default impls of update_apns_keys and get_apns_keys:
+// cid -> 0 -> APNS_KEYS -> APNS_KEYS -> ApnsKeys
+const APNS_KEYS: &str = "_INTERNAL_APNS_KEYS";
+
+// group byte map key layout:
+// owner cid -> GROUPS -> group id -> GroupRecord
+// member cid -> GROUP_MEMBERSHIPS -> "owner cid:group id" -> (owner cid, group id)
+const GROUPS: &str = "_INTERNAL_GROUPS";
+const GROUP_MEMBERSHIPS: &str = "_INTERNAL_GROUP_MEMBERSHIPS";
+
+#[derive(Serialize, Deserialize)]
+struct GroupRecord {
+    owner_cid: u64,
+    group_id: u128,
+    name: Option<String>,
+    members: Vec<u64>,
+}
+
+impl GroupRecord {
+    fn add_members(&mut self, members: &[u64]) {
+        for member in members {
+            if !self.members.contains(member) {
+                self.members.push(*member);
+            }
+        }
+    }
+
+    fn into_group_info(self, cid: u64) -> GroupInfo {
+        let role = if cid == self.owner_cid {
+            GroupRole::Owner
+        } else {
+            GroupRole::Member
+        };
+
+        GroupInfo {
+            group_id: self.group_id,
+            owner_cid: self.owner_cid,
+            name: self.name,
+            members: self.members,
+            role,
+        }
+    }
+}
+
+fn group_membership_sub_key(owner_cid: u64, group_id: u128) -> String {
+    format!("{owner_cid}:{group_id}")
+}
+
+async fn get_group<R: Ratchet, Fcm: Ratchet, B: BackendConnection<R, Fcm> + ?Sized>(
+    backend: &B,
+    owner_cid: u64,
+    group_id: u128,
+) -> Result<Option<GroupRecord>, AccountError> {
+    backend
+        .get_byte_map_value(owner_cid, 0, GROUPS, &group_id.to_string())
+        .await?
+        .map(GroupRecord::deserialize_from_owned_vector)
+        .transpose()
+}
+
+/// Applies `update` to the group, retrying if the group changes concurrently. Returns None if
+/// the group does not exist
+async fn update_group<R: Ratchet, Fcm: Ratchet, B: BackendConnection<R, Fcm> + ?Sized>(
+    backend: &B,
+    owner_cid: u64,
+    group_id: u128,
+    update: impl Fn(&mut GroupRecord),
+) -> Result<Option<GroupRecord>, AccountError> {
+    let sub_key = group_id.to_string();
+    loop {
+        let current = if let Some(current) = backend
+            .get_byte_map_value(owner_cid, 0, GROUPS, &sub_key)
+            .await?
+        {
+            current
+        } else {
+            return Ok(None);
+        };
+
+        let mut record = GroupRecord::deserialize_from_vector(&current)?;
+        update(&mut record);
+        if backend
+            .compare_and_swap_byte_map_value(
+                owner_cid,
+                0,
+                GROUPS,
+                &sub_key,
+                Some(current),
+                record.serialize_to_vector()?,
+            )
+            .await?
+        {
+            return Ok(Some(record));
+        }
+    }
+}
+
+/// Checks that `cid` may be renamed to `new_username` as per
+/// [`BackendConnection::change_username`], returning the client to rename
+pub(crate) async fn check_username_change<
+    R: Ratchet,
+    Fcm: Ratchet,
+    B: BackendConnection<R, Fcm> + ?Sized,
+>(
+    backend: &B,
+    cid: u64,
+    new_username: &str,
+    credential_policy: &CredentialPolicy,
+) -> Result<ClientNetworkAccount<R, Fcm>, AccountError> {
+    let cnac = backend
+        .get_cnac_by_cid(cid)
+        .await?
+        .ok_or(AccountError::ClientNonExists(cid))?;
+    check_credential_formatting_with::<_, &str, _>(
+        credential_policy,
+        new_username,
+        None,
+        cnac.get_metadata().full_name,
+    )
+    .map_err(|_| AccountError::InvalidUsername)?;
+
+    // the client whose CID was derived from `new_username` holds it, even if since renamed
+    let derived_cid = backend.get_cid_by_username(new_username);
+    if derived_cid != cid && backend.cid_is_registered(derived_cid).await? {
+        return Err(AccountError::ClientExists(derived_cid));
+    }
+
+    match backend.get_cid_by_stored_username(new_username).await? {
+        Some(holder_cid) if holder_cid != cid => Err(AccountError::ClientExists(holder_cid)),
+        _ => Ok(cnac),
+    }
+}
+
+async fn store_group_membership<R: Ratchet, Fcm: Ratchet, B: BackendConnection<R, Fcm> + ?Sized>(
+    backend: &B,
+    member_cid: u64,
+    owner_cid: u64,
+    group_id: u128,
+) -> Result<(), AccountError> {
+    let _ = backend
+        .store_byte_map_value(
+            member_cid,
+            0,
+            GROUP_MEMBERSHIPS,
+            &group_membership_sub_key(owner_cid, group_id),
+            (owner_cid, group_id).serialize_to_vector()?,
+        )
+        .await?;
+    Ok(())
+}
+
 /// This is what every C/NAC gets. This gets called before making I/O operations
 pub struct PersistenceHandler<R: Ratchet = StackedRatchet, Fcm: Ratchet = ThinRatchet> {
     inner: Arc<dyn BackendConnection<R, Fcm>>,
+    cid_generator: Arc<dyn CidGenerator>,
 }
 
 impl<R: Ratchet, Fcm: Ratchet> PersistenceHandler<R, Fcm> {
@@ -330,8 +1353,82 @@ impl<R: Ratchet, Fcm: Ratchet> PersistenceHandler<R, Fcm> {
         inner.connect().await?;
         Ok(Self {
             inner: Arc::new(inner),
+            cid_generator: Arc::new(UsernameHashCidGenerator),
         })
     }
+
+    /// Derives the CIDs of usernames with `cid_generator` instead of [`username_to_cid`]
+    pub fn with_cid_generator(mut self, cid_generator: Arc<dyn CidGenerator>) -> Self {
+        self.cid_generator = cid_generator;
+        self
+    }
+
+    /// Gets the CID by username, as derived by this handler's [`CidGenerator`]
+    pub fn get_cid_by_username(&self, username: &str) -> u64 {
+        self.cid_generator.generate_cid(username)
+    }
+
+    /// Gets the client by username, ignoring case. Clients renamed after registration are found by
+    /// their current username, but not by the username their CID was derived from
+    pub async fn get_client_by_username(
+        &self,
+        username: &str,
+    ) -> Result<Option<ClientNetworkAccount<R, Fcm>>, AccountError> {
+        self.inner.get_client_by_username(username).await
+    }
+
+    /// Determines if a username exists, ignoring case. Since CIDs are derived from usernames, the
+    /// username a renamed client registered with continues to exist
+    pub async fn username_exists(&self, username: &str) -> Result<bool, AccountError> {
+        if self
+            .inner
+            .cid_is_registered(self.get_cid_by_username(username))
+            .await?
+        {
+            return Ok(true);
+        }
+
+        self.inner.username_exists(username).await
+    }
+
+    /// Gets hyperland peer by username. Peers registered to this node are found ignoring case
+    pub async fn get_hyperlan_peer_by_username(
+        &self,
+        implicated_cid: u64,
+        username: &str,
+    ) -> Result<Option<MutualPeer>, AccountError> {
+        if let Some(peer) = self
+            .inner
+            .get_hyperlan_peer_by_cid(implicated_cid, self.get_cid_by_username(username))
+            .await?
+        {
+            return Ok(Some(peer));
+        }
+
+        match self.inner.get_cid_by_stored_username(username).await? {
+            Some(peer_cid) => {
+                self.inner
+                    .get_hyperlan_peer_by_cid(implicated_cid, peer_cid)
+                    .await
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the value in the byte map, or if it is absent (or expired), atomically stores and
+    /// returns the value produced by `default`. `default` only runs when a value is stored
+    pub async fn get_or_insert_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        default: impl FnOnce() -> Vec<u8> + Send,
+    ) -> Result<Vec<u8>, AccountError> {
+        self.inner
+            .get_or_insert_byte_map_value(implicated_cid, peer_cid, key, sub_key, Box::new(default))
+            .await
+    }
 }
 
 impl<R: Ratchet, Fcm: Ratchet> Deref for PersistenceHandler<R, Fcm> {
@@ -346,6 +1443,7 @@ impl<R: Ratchet, Fcm: Ratchet> Clone for PersistenceHandler<R, Fcm> {
     fn clone(&self) -> Self {
         Self {
             inner: self.inner.clone(),
+            cid_generator: self.cid_generator.clone(),
         }
     }
 }
@@ -356,3 +1454,76 @@ pub fn username_to_cid(username: &str) -> u64 {
     hasher.write(username.as_bytes());
     hasher.finish()
 }
+
+/// Returns the form of `username` kept in the username index of each backend, which is used for
+/// uniqueness and lookups. Usernames are compared after NFKC normalization and full case folding,
+/// so "Bob", "BOB" and "ｂｏｂ" are the same username, as are "Straße" and "STRASSE". The original
+/// form is kept for display
+///
+/// Migration note: the CID of an account is still derived from the username as registered, so
+/// nodes agree on the CID whether or not they compare usernames ignoring case. Backends saved
+/// before the index existed are backfilled upon connecting. If two existing usernames are equal
+/// once normalized, the client with the lower CID keeps the username in the index, and both are
+/// reported by [`BackendConnection::find_duplicate_usernames`]
+pub fn normalize_username(username: &str) -> String {
+    // std has no case folding, but lowercasing, uppercasing, then lowercasing again merges the
+    // same characters, including those such as "ß" and "ẞ" which fold to several characters
+    username
+        .nfkc()
+        .collect::<String>()
+        .to_lowercase()
+        .to_uppercase()
+        .to_lowercase()
+        .nfkc()
+        .collect()
+}
+
+/// Incremented whenever [`normalize_username`] changes, so that backends which keep the username
+/// index across restarts rebuild it upon connecting
+pub(crate) const USERNAME_INDEX_VERSION: u32 = 1;
+
+/// Derives the CID of an account from its username. During registration, the client proposes
+/// the CID it derives while the server stores the account under the CID it derives, so every
+/// node in a deployment must use the same generator. Lookups by username go through it as well
+pub trait CidGenerator: Send + Sync {
+    /// Returns the CID for `username`. The output must depend only on `username`
+    fn generate_cid(&self, username: &str) -> u64;
+}
+
+/// The default [`CidGenerator`], which hashes the username via [`username_to_cid`]
+#[derive(Default, Debug, Copy, Clone)]
+pub struct UsernameHashCidGenerator;
+
+impl CidGenerator for UsernameHashCidGenerator {
+    fn generate_cid(&self, username: &str) -> u64 {
+        username_to_cid(username)
+    }
+}
+
+/// Runs `connect` until it succeeds, retrying up to `retries` times. The delay between attempts
+/// starts at `backoff` and doubles after each failed attempt
+#[cfg(all(any(feature = "sql", feature = "redis"), not(coverage)))]
+pub(crate) async fn connect_with_backoff<T, F, Fut>(
+    backend: &str,
+    retries: u32,
+    backoff: Duration,
+    mut connect: F,
+) -> Result<T, AccountError>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, AccountError>>,
+{
+    let mut attempt = 0;
+    loop {
+        match connect().await {
+            Ok(ret) => return Ok(ret),
+            Err(err) if attempt < retries => {
+                attempt += 1;
+                let delay = backoff.saturating_mul(2u32.saturating_pow(attempt - 1));
+                log::warn!(target: "citadel", "Unable to connect to the {} backend: {:?}. Retrying in {:?} (attempt {}/{})", backend, err, delay, attempt, retries);
+                tokio::time::sleep(delay).await;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
@@ -0,0 +1,1403 @@
+use super::utils::StreamableTargetInformation;
+use crate::backend::memory::no_backend_streaming;
+use crate::backend::utils::ObjectTransferStatus;
+use crate::backend::{
+    check_username_change, normalize_username, BackendConnection, USERNAME_INDEX_VERSION,
+};
+use crate::client_account::{ClientNetworkAccount, MutualPeer};
+use crate::misc::{AccountError, ByteMapEntry, CNACMetadata, CredentialPolicy};
+use crate::peer_list::PeerListDelta;
+use crate::prelude::{ClientNetworkAccountInner, HYPERLAN_IDX};
+use crate::serialization::SyncIO;
+use async_trait::async_trait;
+use citadel_crypt::stacked_ratchet::Ratchet;
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+use mongodb::bson::spec::BinarySubtype;
+use mongodb::bson::{doc, Binary, DateTime, Document};
+use mongodb::error::{ErrorKind, WriteError, WriteFailure};
+use mongodb::options::{
+    ClientOptions, Collation, CollationStrength, FindOneAndReplaceOptions, FindOneOptions,
+    FindOptions, IndexOptions, ReturnDocument, UpdateOptions,
+};
+use mongodb::{Client, Collection, Database, IndexModel};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/// The database used when neither the URL nor the options specify one
+const DEFAULT_DATABASE: &str = "citadel";
+const CNACS_COLLECTION: &str = "cnacs";
+const PEERS_COLLECTION: &str = "peers";
+const BYTE_MAP_COLLECTION: &str = "bytemap";
+/// Holds the version of the username index
+const META_COLLECTION: &str = "meta";
+
+/// Backend struct for MongoDB
+pub(crate) struct MongoBackend<R: Ratchet, Fcm: Ratchet> {
+    url: String,
+    conn_options: MongoConnectionOptions,
+    client: Option<Client>,
+    db: Option<Database>,
+    _pd: PhantomData<(R, Fcm)>,
+}
+
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+/// For setting custom options for the internal MongoDB client, which pools its own connections
+pub struct MongoConnectionOptions {
+    /// The database to use. Defaults to the database in the URL, or "citadel" if the URL has none
+    pub database: Option<String>,
+    /// The maximum number of connections in the pool
+    pub max_pool_size: Option<u32>,
+    /// The minimum number of connections in the pool
+    pub min_pool_size: Option<u32>,
+    /// How long a connection can remain idle in the pool before being closed
+    pub max_idle_time: Option<Duration>,
+    /// After the specified time during the connection process, times out
+    pub connect_timeout: Option<Duration>,
+    /// How long to wait for a suitable server before an operation fails
+    pub server_selection_timeout: Option<Duration>,
+}
+
+/// A CNAC, keyed by its CID. CIDs are stored as strings since BSON has no unsigned 64-bit integer.
+/// The deactivation time is deliberately absent, so that saving a CNAC leaves it untouched
+#[derive(Serialize, Deserialize)]
+struct CnacDocument {
+    #[serde(rename = "_id")]
+    cid: String,
+    is_personal: bool,
+    username: String,
+    /// The [normalized](crate::backend::normalize_username) username, used for uniqueness.
+    /// Absent from documents of clients holding the same username as a client with a lower CID
+    /// when the index was backfilled
+    #[serde(default)]
+    username_lower: String,
+    full_name: String,
+    creation_date: String,
+    /// The parsed creation date, used for range queries. Absent from documents saved before
+    /// clients could be queried by creation date
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<DateTime>,
+    bin: Binary,
+}
+
+/// The username fields of a [`CnacDocument`]
+#[derive(Deserialize)]
+struct UsernameDocument {
+    #[serde(rename = "_id")]
+    cid: String,
+    username: String,
+    #[serde(default)]
+    username_lower: Option<String>,
+}
+
+/// A [`CnacDocument`] without the serialized CNAC
+#[derive(Serialize, Deserialize)]
+struct CnacMetadataDocument {
+    #[serde(rename = "_id")]
+    cid: String,
+    is_personal: bool,
+    username: String,
+    full_name: String,
+    creation_date: String,
+    /// Present only while the client is deactivated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deactivated_at: Option<DateTime>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PeerDocument {
+    cid: String,
+    peer_cid: String,
+    username: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ByteMapDocument {
+    cid: String,
+    peer_cid: String,
+    id: String,
+    sub_id: String,
+    bin: Binary,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<DateTime>,
+}
+
+impl From<CnacMetadataDocument> for Option<CNACMetadata> {
+    fn from(doc: CnacMetadataDocument) -> Self {
+        Some(CNACMetadata {
+            cid: u64::from_str(&doc.cid).ok()?,
+            is_personal: doc.is_personal,
+            username: doc.username,
+            full_name: doc.full_name,
+            creation_date: doc.creation_date,
+            is_active: doc.deactivated_at.is_none(),
+        })
+    }
+}
+
+impl PeerDocument {
+    fn into_mutual_peer(self) -> Option<MutualPeer> {
+        Some(MutualPeer {
+            parent_icid: HYPERLAN_IDX,
+            cid: u64::from_str(&self.peer_cid).ok()?,
+            username: self.username,
+        })
+    }
+}
+
+impl ByteMapDocument {
+    /// Returns the value, unless it expired but has yet to be swept by the TTL index
+    fn into_live_value(self) -> Option<Vec<u8>> {
+        match self.expires_at {
+            Some(expires_at) if expires_at <= DateTime::now() => None,
+            _ => Some(self.bin.bytes),
+        }
+    }
+}
+
+#[async_trait]
+impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for MongoBackend<R, Fcm> {
+    async fn connect(&mut self) -> Result<(), AccountError> {
+        let mut options = ClientOptions::parse(self.url.as_str()).await?;
+        options.max_pool_size = self.conn_options.max_pool_size;
+        options.min_pool_size = self.conn_options.min_pool_size;
+        options.max_idle_time = self.conn_options.max_idle_time;
+        options.connect_timeout = self.conn_options.connect_timeout;
+        options.server_selection_timeout = self.conn_options.server_selection_timeout;
+
+        let client = Client::with_options(options)?;
+        let db = match self.conn_options.database.as_ref() {
+            Some(database) => client.database(database),
+            None => client
+                .default_database()
+                .unwrap_or_else(|| client.database(DEFAULT_DATABASE)),
+        };
+
+        let cnacs = db.collection::<Document>(CNACS_COLLECTION);
+        let peers = db.collection::<Document>(PEERS_COLLECTION);
+        let byte_map = db.collection::<Document>(BYTE_MAP_COLLECTION);
+
+        let unique = IndexOptions::builder().unique(true).build();
+        let _ = cnacs
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! { "username": 1 })
+                    .options(unique.clone())
+                    .build(),
+                None,
+            )
+            .await?;
+        // sparse, since the documents of duplicate usernames found when backfilling lack the field
+        let _ = cnacs
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! { "username_lower": 1 })
+                    .options(IndexOptions::builder().unique(true).sparse(true).build())
+                    .build(),
+                None,
+            )
+            .await?;
+        // sparse, since documents saved before clients could be queried by creation date lack the
+        // field until they are next saved
+        let _ = cnacs
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! { "created_at": 1 })
+                    .options(IndexOptions::builder().sparse(true).build())
+                    .build(),
+                None,
+            )
+            .await?;
+        // CIDs are strings, so they are compared numerically when paging through clients
+        let _ = cnacs
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! { "is_personal": 1, "_id": 1 })
+                    .options(
+                        IndexOptions::builder()
+                            .collation(numeric_collation())
+                            .build(),
+                    )
+                    .build(),
+                None,
+            )
+            .await?;
+        let _ = peers
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! { "cid": 1, "peer_cid": 1 })
+                    .build(),
+                None,
+            )
+            .await?;
+        let _ = byte_map
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! { "cid": 1, "peer_cid": 1, "id": 1, "sub_id": 1 })
+                    .options(unique)
+                    .build(),
+                None,
+            )
+            .await?;
+        // lets the server sweep expired byte map values in the background
+        let _ = byte_map
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! { "expires_at": 1 })
+                    .options(
+                        IndexOptions::builder()
+                            .expire_after(Duration::from_secs(0))
+                            .build(),
+                    )
+                    .build(),
+                None,
+            )
+            .await?;
+
+        backfill_username_index(&db).await?;
+        self.client = Some(client);
+        self.db = Some(db);
+        Ok(())
+    }
+
+    async fn is_connected(&self) -> Result<bool, AccountError> {
+        let _ = self.get_db()?.run_command(doc! { "ping": 1 }, None).await?;
+        Ok(true)
+    }
+
+    async fn disconnect(&self) -> Result<(), AccountError> {
+        if let Some(client) = self.client.clone() {
+            client.shutdown().await;
+        }
+
+        Ok(())
+    }
+
+    async fn save_cnac(&self, cnac: &ClientNetworkAccount<R, Fcm>) -> Result<(), AccountError> {
+        let bytes = cnac.generate_proper_bytes()?;
+        let metadata = cnac.get_metadata();
+        let cid = metadata.cid.to_string();
+        let username_lower = normalize_username(&metadata.username);
+        let document = CnacDocument {
+            cid: cid.clone(),
+            is_personal: metadata.is_personal,
+            username_lower: username_lower.clone(),
+            username: metadata.username,
+            full_name: metadata.full_name,
+            created_at: metadata
+                .creation_date_parsed()
+                .ok()
+                .map(|created_at| DateTime::from_millis(created_at.timestamp_millis())),
+            creation_date: metadata.creation_date,
+            bin: to_binary(bytes),
+        };
+        let mut document = mongodb::bson::to_document(&document)
+            .map_err(|err| AccountError::Generic(err.to_string()))?;
+
+        let cnacs = self.get_db()?.collection::<Document>(CNACS_COLLECTION);
+        let err = match upsert_cnac_document(&cnacs, &cid, document.clone()).await {
+            Ok(()) => return Ok(()),
+            Err(err) if is_duplicate_key_error(&err) => err,
+            Err(err) => return Err(err.into()),
+        };
+
+        // a client keeping its username may save, even if it is a duplicate held by another
+        let stored_username = self.get_username_by_cid(metadata.cid).await?;
+        if stored_username.as_deref().map(normalize_username) != Some(username_lower) {
+            return Err(err.into());
+        }
+
+        let _ = document.remove("username_lower");
+        Ok(upsert_cnac_document(&cnacs, &cid, document).await?)
+    }
+
+    async fn get_cnac_by_cid(
+        &self,
+        cid: u64,
+    ) -> Result<Option<ClientNetworkAccount<R, Fcm>>, AccountError> {
+        if let Some(document) = self
+            .cnacs()?
+            .find_one(doc! { "_id": cid.to_string() }, None)
+            .await?
+        {
+            let inner = ClientNetworkAccountInner::<R, Fcm>::deserialize_from_owned_vector(
+                document.bin.bytes,
+            )?;
+            Ok(Some(inner.into()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn cid_is_registered(&self, cid: u64) -> Result<bool, AccountError> {
+        Ok(self
+            .cnacs()?
+            .count_documents(doc! { "_id": cid.to_string() }, None)
+            .await?
+            != 0)
+    }
+
+    async fn is_personal(&self, cid: u64) -> Result<Option<bool>, AccountError> {
+        self.get_db()?
+            .collection::<Document>(CNACS_COLLECTION)
+            .find_one(
+                doc! { "_id": cid.to_string() },
+                FindOneOptions::builder()
+                    .projection(doc! { "is_personal": 1 })
+                    .build(),
+            )
+            .await?
+            .map(|doc| {
+                doc.get_bool("is_personal")
+                    .map_err(|err| AccountError::msg(err.to_string()))
+            })
+            .transpose()
+    }
+
+    async fn delete_cnac_by_cid(&self, cid: u64) -> Result<(), AccountError> {
+        let cid = cid.to_string();
+        let deleted = self
+            .cnacs()?
+            .delete_one(doc! { "_id": cid.as_str() }, None)
+            .await?;
+        if deleted.deleted_count == 0 {
+            return Err(AccountError::ClientNonExists(u64::from_str(&cid)?));
+        }
+
+        let _ = self
+            .peers()?
+            .delete_many(
+                doc! { "$or": [{ "cid": cid.as_str() }, { "peer_cid": cid.as_str() }] },
+                None,
+            )
+            .await?;
+        let _ = self
+            .byte_map()?
+            .delete_many(doc! { "cid": cid.as_str() }, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn purge(&self) -> Result<usize, AccountError> {
+        let _ = self.peers()?.delete_many(doc! {}, None).await?;
+        let _ = self.byte_map()?.delete_many(doc! {}, None).await?;
+        let deleted = self.cnacs()?.delete_many(doc! {}, None).await?;
+        Ok(deleted.deleted_count as usize)
+    }
+
+    async fn deactivate_cnac(&self, cid: u64) -> Result<(), AccountError> {
+        // the original deactivation time is kept if the client is already deactivated
+        let _ = self
+            .cnacs()?
+            .update_one(
+                doc! { "_id": cid.to_string(), "deactivated_at": null },
+                doc! { "$set": { "deactivated_at": DateTime::now() } },
+                None,
+            )
+            .await?;
+
+        if self.cid_is_registered(cid).await? {
+            Ok(())
+        } else {
+            Err(AccountError::ClientNonExists(cid))
+        }
+    }
+
+    async fn reactivate_cnac(&self, cid: u64) -> Result<(), AccountError> {
+        let updated = self
+            .cnacs()?
+            .update_one(
+                doc! { "_id": cid.to_string() },
+                doc! { "$unset": { "deactivated_at": "" } },
+                None,
+            )
+            .await?;
+
+        if updated.matched_count != 0 {
+            Ok(())
+        } else {
+            Err(AccountError::ClientNonExists(cid))
+        }
+    }
+
+    async fn purge_deactivated_before(&self, cutoff: SystemTime) -> Result<usize, AccountError> {
+        let expired = self
+            .cnac_metadata()?
+            .find(
+                doc! { "deactivated_at": { "$lt": DateTime::from_system_time(cutoff) } },
+                FindOptions::builder().projection(doc! { "bin": 0 }).build(),
+            )
+            .await?
+            .try_filter_map(|doc| futures::future::ok(u64::from_str(&doc.cid).ok()))
+            .try_collect::<Vec<u64>>()
+            .await?;
+
+        // peers and byte map values must be removed alongside each client
+        for cid in &expired {
+            self.delete_cnac_by_cid(*cid).await?;
+        }
+
+        Ok(expired.len())
+    }
+
+    async fn get_registered_impersonal_cids(
+        &self,
+        limit: Option<i32>,
+    ) -> Result<Option<Vec<u64>>, AccountError> {
+        let options = FindOptions::builder()
+            .projection(doc! { "bin": 0 })
+            .limit(limit.map(|limit| limit as i64))
+            .build();
+        let ret = self
+            .cnac_metadata()?
+            .find(
+                doc! { "is_personal": false, "deactivated_at": null },
+                options,
+            )
+            .await?
+            .try_filter_map(|doc| futures::future::ok(u64::from_str(&doc.cid).ok()))
+            .try_collect::<Vec<u64>>()
+            .await?;
+
+        if ret.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(ret))
+        }
+    }
+
+    async fn get_registered_impersonal_cids_paged(
+        &self,
+        offset: u64,
+        limit: u32,
+    ) -> Result<Vec<u64>, AccountError> {
+        let options = FindOptions::builder()
+            .projection(doc! { "bin": 0 })
+            .sort(doc! { "is_personal": 1, "_id": 1 })
+            .collation(numeric_collation())
+            .skip(offset)
+            .limit(limit as i64)
+            .build();
+        Ok(self
+            .cnac_metadata()?
+            .find(
+                doc! { "is_personal": false, "deactivated_at": null },
+                options,
+            )
+            .await?
+            .try_filter_map(|doc| futures::future::ok(u64::from_str(&doc.cid).ok()))
+            .try_collect::<Vec<u64>>()
+            .await?)
+    }
+
+    async fn get_registered_impersonal_cids_created_between(
+        &self,
+        created_after: Option<chrono::DateTime<chrono::Utc>>,
+        created_before: Option<chrono::DateTime<chrono::Utc>>,
+        limit: Option<i32>,
+    ) -> Result<Option<Vec<u64>>, AccountError> {
+        let mut filter = doc! { "is_personal": false, "deactivated_at": null };
+        let mut created_at = Document::new();
+        if let Some(after) = created_after {
+            created_at.insert("$gt", DateTime::from_millis(after.timestamp_millis()));
+        }
+
+        if let Some(before) = created_before {
+            created_at.insert("$lt", DateTime::from_millis(before.timestamp_millis()));
+        }
+
+        if !created_at.is_empty() {
+            filter.insert("created_at", created_at);
+        }
+
+        let options = FindOptions::builder()
+            .projection(doc! { "bin": 0 })
+            .limit(limit.map(|limit| limit as i64))
+            .build();
+        let ret = self
+            .cnac_metadata()?
+            .find(filter, options)
+            .await?
+            .try_filter_map(|doc| futures::future::ok(u64::from_str(&doc.cid).ok()))
+            .try_collect::<Vec<u64>>()
+            .await?;
+
+        if ret.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(ret))
+        }
+    }
+
+    async fn get_username_by_cid(&self, cid: u64) -> Result<Option<String>, AccountError> {
+        Ok(self
+            .get_metadata_document(cid)
+            .await?
+            .map(|doc| doc.username))
+    }
+
+    async fn get_cid_by_stored_username(
+        &self,
+        username: &str,
+    ) -> Result<Option<u64>, AccountError> {
+        Ok(self
+            .cnac_metadata()?
+            .find_one(
+                doc! { "username_lower": normalize_username(username) },
+                FindOneOptions::builder()
+                    .projection(doc! { "bin": 0 })
+                    .build(),
+            )
+            .await?
+            .and_then(|doc| u64::from_str(&doc.cid).ok()))
+    }
+
+    async fn change_username(
+        &self,
+        cid: u64,
+        new_username: &str,
+        credential_policy: &CredentialPolicy,
+    ) -> Result<(), AccountError> {
+        let cnac = check_username_change(self, cid, new_username, credential_policy).await?;
+        let previous_username = cnac.get_username();
+        cnac.set_username(new_username);
+        // the unique index on username_lower rejects a username taken since it was checked
+        self.save_cnac(&cnac).await?;
+
+        let renamed_peers = self
+            .peers()?
+            .update_many(
+                doc! { "peer_cid": cid.to_string() },
+                doc! { "$set": { "username": new_username } },
+                None,
+            )
+            .await;
+
+        if let Err(err) = renamed_peers {
+            // the updates are not transactional, so the client is renamed back
+            cnac.set_username(previous_username);
+            let _ = self.save_cnac(&cnac).await;
+            return Err(err.into());
+        }
+
+        Ok(())
+    }
+
+    async fn register_p2p_as_server(&self, cid0: u64, cid1: u64) -> Result<(), AccountError> {
+        let username0 = self.get_username_by_cid(cid0).await?;
+        let username1 = self.get_username_by_cid(cid1).await?;
+        let insert = self
+            .peers()?
+            .insert_many(
+                [
+                    PeerDocument {
+                        cid: cid1.to_string(),
+                        peer_cid: cid0.to_string(),
+                        username: username0,
+                    },
+                    PeerDocument {
+                        cid: cid0.to_string(),
+                        peer_cid: cid1.to_string(),
+                        username: username1,
+                    },
+                ],
+                None,
+            )
+            .await;
+
+        if let Err(err) = insert {
+            // the inserts are not transactional, so remove whichever direction was written
+            let _ = self.deregister_p2p_as_server(cid0, cid1).await;
+            return Err(err.into());
+        }
+
+        Ok(())
+    }
+
+    async fn register_p2p_as_client(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        peer_username: String,
+    ) -> Result<(), AccountError> {
+        log::trace!(target: "citadel", "Registering p2p ({} <-> {}) as client", implicated_cid, peer_cid);
+        let _ = self
+            .peers()?
+            .insert_one(
+                PeerDocument {
+                    cid: implicated_cid.to_string(),
+                    peer_cid: peer_cid.to_string(),
+                    username: Some(peer_username),
+                },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn deregister_p2p_as_server(&self, cid0: u64, cid1: u64) -> Result<(), AccountError> {
+        let cid0 = cid0.to_string();
+        let cid1 = cid1.to_string();
+        let _ = self
+            .peers()?
+            .delete_many(
+                doc! { "$or": [
+                    { "cid": cid0.as_str(), "peer_cid": cid1.as_str() },
+                    { "cid": cid1.as_str(), "peer_cid": cid0.as_str() },
+                ] },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn deregister_p2p_as_client(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Option<MutualPeer>, AccountError> {
+        Ok(self
+            .peers()?
+            .find_one_and_delete(peer_filter(implicated_cid, peer_cid), None)
+            .await?
+            .and_then(PeerDocument::into_mutual_peer))
+    }
+
+    async fn get_hyperlan_peer_list(
+        &self,
+        implicated_cid: u64,
+    ) -> Result<Option<Vec<u64>>, AccountError> {
+        let peers = self
+            .find_peers(doc! { "cid": implicated_cid.to_string() })
+            .await?
+            .into_iter()
+            .map(|peer| peer.cid)
+            .collect::<Vec<u64>>();
+
+        if peers.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(peers))
+        }
+    }
+
+    async fn get_hyperlan_peer_count(&self, implicated_cid: u64) -> Result<usize, AccountError> {
+        Ok(self
+            .peers()?
+            .count_documents(doc! { "cid": implicated_cid.to_string() }, None)
+            .await? as usize)
+    }
+
+    async fn get_client_metadata(
+        &self,
+        implicated_cid: u64,
+    ) -> Result<Option<CNACMetadata>, AccountError> {
+        Ok(self
+            .get_metadata_document(implicated_cid)
+            .await?
+            .and_then(Into::into))
+    }
+
+    async fn stream_clients_metadata(
+        &self,
+    ) -> Result<BoxStream<'_, Result<CNACMetadata, AccountError>>, AccountError> {
+        // documents are pulled through a server-side cursor as the stream is polled
+        let cursor = self
+            .cnac_metadata()?
+            .find(
+                None,
+                FindOptions::builder().projection(doc! { "bin": 0 }).build(),
+            )
+            .await?;
+
+        Ok(cursor
+            .map_err(AccountError::from)
+            .try_filter_map(|doc| futures::future::ok(doc.into()))
+            .boxed())
+    }
+
+    async fn get_hyperlan_peer_by_cid(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Option<MutualPeer>, AccountError> {
+        Ok(self
+            .peers()?
+            .find_one(peer_filter(implicated_cid, peer_cid), None)
+            .await?
+            .and_then(PeerDocument::into_mutual_peer))
+    }
+
+    async fn hyperlan_peer_exists(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<bool, AccountError> {
+        Ok(self
+            .peers()?
+            .count_documents(peer_filter(implicated_cid, peer_cid), None)
+            .await?
+            != 0)
+    }
+
+    async fn hyperlan_peers_are_mutuals(
+        &self,
+        implicated_cid: u64,
+        peers: &[u64],
+    ) -> Result<Vec<bool>, AccountError> {
+        let mutuals = self.get_hyperlan_peers(implicated_cid, peers).await?;
+        Ok(peers
+            .iter()
+            .map(|cid| mutuals.iter().any(|peer| peer.cid == *cid))
+            .collect())
+    }
+
+    async fn get_hyperlan_peers(
+        &self,
+        implicated_cid: u64,
+        peers: &[u64],
+    ) -> Result<Vec<MutualPeer>, AccountError> {
+        if peers.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let peer_cids = peers.iter().map(|cid| cid.to_string()).collect::<Vec<_>>();
+        self.find_peers(doc! {
+            "cid": implicated_cid.to_string(),
+            "peer_cid": { "$in": peer_cids },
+        })
+        .await
+    }
+
+    async fn get_hyperlan_peer_list_as_server(
+        &self,
+        implicated_cid: u64,
+    ) -> Result<Option<Vec<MutualPeer>>, AccountError> {
+        let peers = self
+            .find_peers(doc! { "cid": implicated_cid.to_string() })
+            .await?;
+
+        if peers.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(peers))
+        }
+    }
+
+    async fn search_hyperlan_peers_by_prefix(
+        &self,
+        implicated_cid: u64,
+        prefix: &str,
+        limit: u32,
+    ) -> Result<Vec<MutualPeer>, AccountError> {
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+
+        let pattern = format!("^{}", escape_regex(prefix));
+        // a secondary-strength collation orders usernames ignoring case
+        let options = FindOptions::builder()
+            .sort(doc! { "username": 1 })
+            .collation(
+                Collation::builder()
+                    .locale("en")
+                    .strength(CollationStrength::Secondary)
+                    .build(),
+            )
+            .limit(limit as i64)
+            .build();
+        Ok(self
+            .peers()?
+            .find(
+                doc! {
+                    "cid": implicated_cid.to_string(),
+                    "username": { "$regex": pattern, "$options": "i" },
+                },
+                options,
+            )
+            .await?
+            .try_filter_map(|peer| futures::future::ok(peer.into_mutual_peer()))
+            .try_collect()
+            .await?)
+    }
+
+    async fn synchronize_hyperlan_peer_list_as_client(
+        &self,
+        cnac: &ClientNetworkAccount<R, Fcm>,
+        peers: Vec<MutualPeer>,
+    ) -> Result<PeerListDelta, AccountError> {
+        log::trace!(target: "citadel", "Synchronizing peer list for {}", cnac.get_cid());
+        let existing = self
+            .get_hyperlan_peer_list_as_server(cnac.get_cid())
+            .await?
+            .unwrap_or_default();
+        let delta = PeerListDelta::between(&existing, &peers);
+        if !delta.is_empty() {
+            let implicated_cid = cnac.get_cid().to_string();
+            let collection = self.peers()?;
+            let _ = collection
+                .delete_many(doc! { "cid": implicated_cid.as_str() }, None)
+                .await?;
+            let documents = peers.into_iter().map(|peer| PeerDocument {
+                cid: implicated_cid.clone(),
+                peer_cid: peer.cid.to_string(),
+                username: peer.username,
+            });
+            let _ = collection.insert_many(documents, None).await?;
+        }
+
+        Ok(delta)
+    }
+
+    async fn get_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+    ) -> Result<Option<Vec<u8>>, AccountError> {
+        let mut filter = byte_map_filter(implicated_cid, peer_cid, key);
+        let _ = filter.insert("sub_id", sub_key);
+        Ok(self
+            .byte_map()?
+            .find_one(filter, None)
+            .await?
+            .and_then(ByteMapDocument::into_live_value))
+    }
+
+    async fn get_byte_map_values(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        keys: &[(String, String)],
+    ) -> Result<HashMap<(String, String), Vec<u8>>, AccountError> {
+        if keys.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let pairs = keys
+            .iter()
+            .map(|(key, sub_key)| doc! { "id": key, "sub_id": sub_key })
+            .collect::<Vec<Document>>();
+        let filter = doc! { "cid": implicated_cid.to_string(), "peer_cid": peer_cid.to_string(), "$or": pairs };
+        Ok(self
+            .byte_map()?
+            .find(filter, None)
+            .await?
+            .try_filter_map(|doc| {
+                let pair = (doc.id.clone(), doc.sub_id.clone());
+                futures::future::ok(doc.into_live_value().map(|value| (pair, value)))
+            })
+            .try_collect()
+            .await?)
+    }
+
+    async fn remove_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+    ) -> Result<Option<Vec<u8>>, AccountError> {
+        let mut filter = byte_map_filter(implicated_cid, peer_cid, key);
+        let _ = filter.insert("sub_id", sub_key);
+        Ok(self
+            .byte_map()?
+            .find_one_and_delete(filter, None)
+            .await?
+            .and_then(ByteMapDocument::into_live_value))
+    }
+
+    async fn store_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        value: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, AccountError> {
+        self.insert_byte_map_value(implicated_cid, peer_cid, key, sub_key, value, None)
+            .await
+    }
+
+    async fn store_byte_map_value_with_expiry(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> Result<Option<Vec<u8>>, AccountError> {
+        let expires_at = DateTime::from_system_time(SystemTime::now() + ttl);
+        self.insert_byte_map_value(
+            implicated_cid,
+            peer_cid,
+            key,
+            sub_key,
+            value,
+            Some(expires_at),
+        )
+        .await
+    }
+
+    async fn compare_and_swap_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        expected: Option<Vec<u8>>,
+        new: Vec<u8>,
+    ) -> Result<bool, AccountError> {
+        let mut filter = byte_map_filter(implicated_cid, peer_cid, key);
+        let _ = filter.insert("sub_id", sub_key);
+        let document = ByteMapDocument {
+            cid: implicated_cid.to_string(),
+            peer_cid: peer_cid.to_string(),
+            id: key.to_string(),
+            sub_id: sub_key.to_string(),
+            bin: to_binary(new),
+            expires_at: None,
+        };
+
+        let collection = self.byte_map()?;
+        match expected {
+            // single-document writes are atomic, so matching on the expected value is enough
+            Some(expected) => {
+                let _ = filter.insert("bin", to_binary(expected));
+                let _ = filter.insert("$or", not_expired());
+                let replaced = collection.replace_one(filter, document, None).await?;
+                Ok(replaced.matched_count == 1)
+            }
+
+            None => {
+                // an expired value has yet to be swept by the TTL index, so treat it as absent
+                let _ = collection
+                    .delete_one(
+                        {
+                            let mut filter = filter.clone();
+                            let _ = filter.insert("expires_at", doc! { "$lte": DateTime::now() });
+                            filter
+                        },
+                        None,
+                    )
+                    .await?;
+                // the unique index on the value's address rejects the insert if it is present
+                match collection.insert_one(document, None).await {
+                    Ok(_) => Ok(true),
+                    Err(err) if is_duplicate_key_error(&err) => Ok(false),
+                    Err(err) => Err(err.into()),
+                }
+            }
+        }
+    }
+
+    async fn get_or_insert_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        default: Box<dyn FnOnce() -> Vec<u8> + Send + '_>,
+    ) -> Result<Vec<u8>, AccountError> {
+        if let Some(value) = self
+            .get_byte_map_value(implicated_cid, peer_cid, key, sub_key)
+            .await?
+        {
+            return Ok(value);
+        }
+
+        let default = default();
+        let mut filter = byte_map_filter(implicated_cid, peer_cid, key);
+        let _ = filter.insert("sub_id", sub_key);
+        let collection = self.byte_map()?;
+        // an expired value has yet to be swept by the TTL index, so treat it as absent
+        let _ = collection
+            .delete_one(
+                {
+                    let mut filter = filter.clone();
+                    let _ = filter.insert("expires_at", doc! { "$lte": DateTime::now() });
+                    filter
+                },
+                None,
+            )
+            .await?;
+
+        let document = ByteMapDocument {
+            cid: implicated_cid.to_string(),
+            peer_cid: peer_cid.to_string(),
+            id: key.to_string(),
+            sub_id: sub_key.to_string(),
+            bin: to_binary(default.clone()),
+            expires_at: None,
+        };
+
+        // the unique index on the value's address rejects the insert if the value was set since
+        // it was read, in which case the value that won is returned
+        match collection.insert_one(document, None).await {
+            Ok(_) => Ok(default),
+            Err(err) if is_duplicate_key_error(&err) => collection
+                .find_one(filter, None)
+                .await?
+                .and_then(ByteMapDocument::into_live_value)
+                .ok_or_else(|| {
+                    AccountError::msg("The byte map value expired while it was being inserted")
+                }),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn copy_byte_map(&self, from_cid: u64, to_cid: u64) -> Result<usize, AccountError> {
+        let documents: Vec<ByteMapDocument> = self
+            .byte_map()?
+            .find(
+                doc! { "cid": from_cid.to_string(), "$or": not_expired() },
+                None,
+            )
+            .await?
+            .try_collect()
+            .await?;
+
+        let copied = documents.len();
+        for document in documents {
+            let peer_cid = u64::from_str(&document.peer_cid)
+                .map_err(|err| AccountError::msg(err.to_string()))?;
+            let _ = self
+                .insert_byte_map_value(
+                    to_cid,
+                    peer_cid,
+                    &document.id,
+                    &document.sub_id,
+                    document.bin.bytes,
+                    document.expires_at,
+                )
+                .await?;
+        }
+
+        Ok(copied)
+    }
+
+    async fn get_byte_map_values_by_key(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+    ) -> Result<HashMap<String, Vec<u8>>, AccountError> {
+        self.find_byte_map_values(byte_map_filter(implicated_cid, peer_cid, key))
+            .await
+    }
+
+    async fn remove_byte_map_values_by_key(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+    ) -> Result<HashMap<String, Vec<u8>>, AccountError> {
+        let filter = byte_map_filter(implicated_cid, peer_cid, key);
+        let values = self.find_byte_map_values(filter.clone()).await?;
+        let _ = self.byte_map()?.delete_many(filter, None).await?;
+        Ok(values)
+    }
+
+    async fn get_byte_map_entries(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Vec<ByteMapEntry>, AccountError> {
+        let documents: Vec<ByteMapDocument> = self
+            .byte_map()?
+            .find(
+                doc! {
+                    "cid": implicated_cid.to_string(),
+                    "peer_cid": peer_cid.to_string(),
+                    "$or": not_expired(),
+                },
+                None,
+            )
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(documents
+            .into_iter()
+            .map(|document| ByteMapEntry {
+                key: document.id,
+                sub_key: document.sub_id,
+                value: document.bin.bytes,
+                expires_at: document.expires_at.map(DateTime::to_system_time),
+            })
+            .collect())
+    }
+
+    async fn stream_object_to_backend(
+        &self,
+        source: UnboundedReceiver<Vec<u8>>,
+        sink_metadata: Arc<dyn StreamableTargetInformation>,
+        status_tx: UnboundedSender<ObjectTransferStatus>,
+    ) -> Result<(), AccountError> {
+        no_backend_streaming(source, sink_metadata, status_tx).await
+    }
+}
+
+impl<R: Ratchet, Fcm: Ratchet> MongoBackend<R, Fcm> {
+    pub(crate) fn new(url: String, conn_options: MongoConnectionOptions) -> Self {
+        Self {
+            url,
+            conn_options,
+            client: None,
+            db: None,
+            _pd: Default::default(),
+        }
+    }
+
+    fn get_db(&self) -> Result<&Database, AccountError> {
+        self.db
+            .as_ref()
+            .ok_or_else(|| AccountError::msg("MongoDB client not loaded"))
+    }
+
+    fn cnacs(&self) -> Result<Collection<CnacDocument>, AccountError> {
+        Ok(self.get_db()?.collection(CNACS_COLLECTION))
+    }
+
+    fn cnac_metadata(&self) -> Result<Collection<CnacMetadataDocument>, AccountError> {
+        Ok(self.get_db()?.collection(CNACS_COLLECTION))
+    }
+
+    fn peers(&self) -> Result<Collection<PeerDocument>, AccountError> {
+        Ok(self.get_db()?.collection(PEERS_COLLECTION))
+    }
+
+    fn byte_map(&self) -> Result<Collection<ByteMapDocument>, AccountError> {
+        Ok(self.get_db()?.collection(BYTE_MAP_COLLECTION))
+    }
+
+    async fn get_metadata_document(
+        &self,
+        cid: u64,
+    ) -> Result<Option<CnacMetadataDocument>, AccountError> {
+        Ok(self
+            .cnac_metadata()?
+            .find_one(
+                doc! { "_id": cid.to_string() },
+                FindOneOptions::builder()
+                    .projection(doc! { "bin": 0 })
+                    .build(),
+            )
+            .await?)
+    }
+
+    async fn find_peers(&self, filter: Document) -> Result<Vec<MutualPeer>, AccountError> {
+        Ok(self
+            .peers()?
+            .find(filter, None)
+            .await?
+            .try_filter_map(|peer| futures::future::ok(peer.into_mutual_peer()))
+            .try_collect()
+            .await?)
+    }
+
+    async fn find_byte_map_values(
+        &self,
+        filter: Document,
+    ) -> Result<HashMap<String, Vec<u8>>, AccountError> {
+        Ok(self
+            .byte_map()?
+            .find(filter, None)
+            .await?
+            .try_filter_map(|doc| {
+                let sub_key = doc.sub_id.clone();
+                futures::future::ok(doc.into_live_value().map(|value| (sub_key, value)))
+            })
+            .try_collect()
+            .await?)
+    }
+
+    async fn insert_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        value: Vec<u8>,
+        expires_at: Option<DateTime>,
+    ) -> Result<Option<Vec<u8>>, AccountError> {
+        let mut filter = byte_map_filter(implicated_cid, peer_cid, key);
+        let _ = filter.insert("sub_id", sub_key);
+        let document = ByteMapDocument {
+            cid: implicated_cid.to_string(),
+            peer_cid: peer_cid.to_string(),
+            id: key.to_string(),
+            sub_id: sub_key.to_string(),
+            bin: to_binary(value),
+            expires_at,
+        };
+
+        // replacing the whole document also clears any previous expiry
+        Ok(self
+            .byte_map()?
+            .find_one_and_replace(
+                filter,
+                document,
+                FindOneAndReplaceOptions::builder()
+                    .upsert(true)
+                    .return_document(ReturnDocument::Before)
+                    .build(),
+            )
+            .await?
+            .and_then(ByteMapDocument::into_live_value))
+    }
+}
+
+fn not_expired() -> Vec<Document> {
+    vec![
+        doc! { "expires_at": null },
+        doc! { "expires_at": { "$gt": DateTime::now() } },
+    ]
+}
+
+async fn upsert_cnac_document(
+    cnacs: &Collection<Document>,
+    cid: &str,
+    document: Document,
+) -> Result<(), mongodb::error::Error> {
+    let _ = cnacs
+        .update_one(
+            doc! { "_id": cid },
+            doc! { "$set": document },
+            UpdateOptions::builder().upsert(true).build(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Sets the username_lower of each CNAC saved before the username index existed, or before
+/// [`normalize_username`] last changed, unless done already. If several clients hold the same
+/// username, the client with the lowest CID keeps it, and the field is left unset for the others
+async fn backfill_username_index(db: &Database) -> Result<(), AccountError> {
+    let meta = db.collection::<Document>(META_COLLECTION);
+    let version = i64::from(USERNAME_INDEX_VERSION);
+    if meta
+        .find_one(doc! { "_id": "usernames", "version": version }, None)
+        .await?
+        .is_some()
+    {
+        return Ok(());
+    }
+
+    let cnacs = db.collection::<UsernameDocument>(CNACS_COLLECTION);
+    let mut documents = cnacs
+        .find(
+            None,
+            FindOptions::builder()
+                .projection(doc! { "username": 1, "username_lower": 1 })
+                .build(),
+        )
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+    documents.sort_unstable_by_key(|document| u64::from_str(&document.cid).unwrap_or(u64::MAX));
+
+    let mut holders = HashMap::new();
+    let mut updates = Vec::new();
+    for document in documents {
+        let normalized = normalize_username(&document.username);
+        let username_lower = match holders.get(&normalized) {
+            Some(holder_cid) => {
+                log::warn!(target: "citadel", "Clients {} and {} hold the same username {}", holder_cid, document.cid, normalized);
+                None
+            }
+
+            None => {
+                let _ = holders.insert(normalized.clone(), document.cid.clone());
+                Some(normalized)
+            }
+        };
+
+        if document.username_lower != username_lower {
+            updates.push((document.cid, username_lower));
+        }
+    }
+
+    // every stale value is unset before any is set, so that none collides with one not yet updated
+    for (cid, _) in &updates {
+        let _ = cnacs
+            .update_one(
+                doc! { "_id": cid },
+                doc! { "$unset": { "username_lower": "" } },
+                None,
+            )
+            .await?;
+    }
+
+    for (cid, username_lower) in updates {
+        if let Some(username_lower) = username_lower {
+            let _ = cnacs
+                .update_one(
+                    doc! { "_id": cid },
+                    doc! { "$set": { "username_lower": username_lower } },
+                    None,
+                )
+                .await?;
+        }
+    }
+
+    let _ = meta
+        .update_one(
+            doc! { "_id": "usernames" },
+            doc! { "$set": { "version": version } },
+            UpdateOptions::builder().upsert(true).build(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Orders CIDs numerically, even though they are stored as strings
+fn numeric_collation() -> Collation {
+    Collation::builder()
+        .locale("en")
+        .numeric_ordering(true)
+        .build()
+}
+
+fn is_duplicate_key_error(err: &mongodb::error::Error) -> bool {
+    matches!(
+        err.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(WriteError { code: 11000, .. }))
+    )
+}
+
+fn to_binary(bytes: Vec<u8>) -> Binary {
+    Binary {
+        subtype: BinarySubtype::Generic,
+        bytes,
+    }
+}
+
+fn peer_filter(implicated_cid: u64, peer_cid: u64) -> Document {
+    doc! { "cid": implicated_cid.to_string(), "peer_cid": peer_cid.to_string() }
+}
+
+fn byte_map_filter(implicated_cid: u64, peer_cid: u64, key: &str) -> Document {
+    doc! { "cid": implicated_cid.to_string(), "peer_cid": peer_cid.to_string(), "id": key }
+}
+
+/// Escapes the metacharacters of `input`, so that it matches literally within a regex
+fn escape_regex(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for char in input.chars() {
+        if "\\.+*?()|[]{}^$".contains(char) {
+            escaped.push('\\');
+        }
+
+        escaped.push(char);
+    }
+
+    escaped
+}
@@ -1,30 +1,47 @@
 use super::utils::StreamableTargetInformation;
 use crate::backend::memory::no_backend_streaming;
 use crate::backend::utils::ObjectTransferStatus;
-use crate::backend::{BackendConnection, BackendType};
+use crate::backend::{
+    check_username_change, connect_with_backoff, is_internal_byte_map_key, normalize_username,
+    BackendConnection, BackendType, DEFAULT_CONNECT_BACKOFF, DEFAULT_CONNECT_RETRIES,
+};
 use crate::client_account::{ClientNetworkAccount, MutualPeer};
-use crate::misc::{AccountError, CNACMetadata, MAX_USERNAME_LENGTH};
+use crate::misc::{
+    parse_formatted_timestamp, AccountError, ByteMapEntry, CNACMetadata, CredentialPolicy,
+    MAX_USERNAME_BYTES,
+};
+use crate::peer_list::PeerListDelta;
 use crate::prelude::{ClientNetworkAccountInner, HYPERLAN_IDX};
 use crate::serialization::SyncIO;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use citadel_crypt::fcm::fcm_ratchet::ThinRatchet;
 use citadel_crypt::stacked_ratchet::{Ratchet, StackedRatchet};
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
 use itertools::Itertools;
-use sqlx::any::{AnyArguments, AnyPoolOptions, AnyQueryResult, AnyRow};
+use sqlx::any::{AnyArguments, AnyConnectOptions, AnyPoolOptions, AnyQueryResult, AnyRow};
+use sqlx::mysql::MySqlSslMode;
+use sqlx::postgres::PgSslMode;
 use sqlx::{AnyPool, Arguments, Executor, Row};
 use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
 use std::marker::PhantomData;
 use std::ops::DerefMut;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
 /// A container for handling db conns
 pub struct SqlBackend<R: Ratchet = StackedRatchet, Fcm: Ratchet = ThinRatchet> {
     url: String,
     conn: Option<AnyPool>,
+    replicas: Vec<AnyPool>,
+    // the replica that serves the next read
+    next_replica: AtomicUsize,
     variant: SqlVariant,
     opts: SqlConnectionOptions,
     _pd: PhantomData<(R, Fcm)>,
@@ -54,6 +71,65 @@ pub struct SqlConnectionOptions {
     pub max_lifetime: Option<Duration>,
     /// Catch and release (CAR) mode. Holding connections pools may be undesirbale for certain platforms with execution restrictions, thus, CAR mode does not keep connections
     pub car_mode: Option<bool>,
+    /// The number of times to retry the initial connection before failing. Default 3
+    pub connect_retries: Option<u32>,
+    /// The delay before the first connect retry, doubling after each failed attempt. Default 500ms
+    pub connect_backoff: Option<Duration>,
+    /// If set, connections to MySQL and PostgreSQL use TLS. Default none, in which case any
+    /// `sslmode` within the URL applies
+    pub tls: Option<SqlTlsConfig>,
+    /// If non-empty, queries that only read are spread across the replicas at these URLs in turn,
+    /// while writes go to the primary URL. Each replica's schema is managed by the primary. Since
+    /// replication may lag, a read may not yet reflect a write that just completed. If empty, the
+    /// primary serves every query
+    pub read_replica_urls: Vec<String>,
+}
+
+#[derive(Default, Debug, Clone, Eq, PartialEq)]
+/// TLS options for connections to MySQL and PostgreSQL databases
+pub struct SqlTlsConfig {
+    /// The PEM-encoded root certificate the server's certificate must chain to. If unset, the
+    /// certificates trusted by the local operating system are used
+    pub root_cert: Option<PathBuf>,
+    /// The PEM-encoded certificate presented to the server, for servers requiring client authentication
+    pub client_cert: Option<PathBuf>,
+    /// The PEM-encoded private key of `client_cert`
+    pub client_key: Option<PathBuf>,
+    /// If enabled, the connection is encrypted but the server's certificate is not verified.
+    /// Intended for development only
+    pub accept_invalid_certs: bool,
+}
+
+impl SqlTlsConfig {
+    /// Ensures each configured file is readable, so that a bad path fails with a clear error
+    /// instead of an opaque handshake failure
+    fn check_files(&self) -> Result<(), AccountError> {
+        let files = [
+            ("root certificate", &self.root_cert),
+            ("client certificate", &self.client_cert),
+            ("client key", &self.client_key),
+        ];
+
+        for (name, path) in files
+            .into_iter()
+            .filter_map(|(name, path)| Some((name, path.as_ref()?)))
+        {
+            std::fs::File::open(path).map_err(|err| {
+                AccountError::Generic(format!(
+                    "Unable to read the SQL TLS {name} at {}: {err}",
+                    path.display()
+                ))
+            })?;
+        }
+
+        if self.client_cert.is_some() != self.client_key.is_some() {
+            return Err(AccountError::msg(
+                "The SQL TLS client certificate and client key must be set together",
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 impl From<&'_ SqlConnectionOptions> for AnyPoolOptions {
@@ -90,65 +166,51 @@ impl From<&'_ SqlConnectionOptions> for AnyPoolOptions {
 #[async_trait]
 impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm> {
     async fn connect(&mut self) -> Result<(), AccountError> {
-        let conn = self.generate_conn().await?;
-
-        if !self.opts.car_mode.unwrap_or(CAR_MODE_DEFAULT) {
-            self.conn = Some(conn.clone());
-        }
-
-        //let conn = AnyPool::connect_with(&self.url).await?;
-
-        // we use varchar(20) for a u64 since u64::MAX char count = 20
-
-        // The below works on MySql, Postgre, SqLite,
-        let bin_type = if self.variant == SqlVariant::Postgre {
-            "TEXT"
-        } else {
-            "LONGTEXT"
-        };
-        // we no longer use bool due to postgresql bug with t/f not being mapped properly
-        let cmd = format!("CREATE TABLE IF NOT EXISTS cnacs(cid VARCHAR(20) NOT NULL, is_personal BOOL, username VARCHAR({MAX_USERNAME_LENGTH}) UNIQUE, full_name TEXT, creation_date TEXT, bin {bin_type}, PRIMARY KEY (cid))");
-        let cmd2 = format!("CREATE TABLE IF NOT EXISTS peers(peer_cid VARCHAR(20), username VARCHAR({MAX_USERNAME_LENGTH}), cid VARCHAR(20), CONSTRAINT fk_cid FOREIGN KEY (cid) REFERENCES cnacs(cid) ON DELETE CASCADE)");
-        //let cmd3 = format!("CREATE TABLE IF NOT EXISTS bytemap(cid VARCHAR(20) NOT NULL, peer_cid VARCHAR(20), key TEXT, bin TEXT, CONSTRAINT fk_cid FOREIGN KEY (cid) REFERENCES cnacs(cid) ON DELETE CASCADE)");
-        let cmd3 = format!("CREATE TABLE IF NOT EXISTS bytemap(cid VARCHAR(20) NOT NULL, peer_cid VARCHAR(20), id TEXT, sub_id TEXT, bin {bin_type}, CONSTRAINT fk_cid2 FOREIGN KEY (cid) REFERENCES cnacs(cid) ON DELETE CASCADE)");
-
-        // The following commands below allow us to remove entries and automatically remove corresponding values
-        let cmd4 = match self.variant {
-            SqlVariant::MySQL => {
-                let _ = conn
-                    .execute("DROP TRIGGER IF EXISTS post_cid_delete")
-                    .await?;
-
-                "CREATE TRIGGER post_cid_delete AFTER DELETE ON cnacs FOR EACH ROW DELETE FROM peers WHERE peers.cid = old.cid OR peers.peer_cid = old.cid"
+        // misconfigured TLS would only fail again on each retry
+        if let Some(tls) = self.opts.tls.as_ref() {
+            if self.variant == SqlVariant::Sqlite {
+                return Err(AccountError::msg("TLS is not supported for SQLite"));
             }
 
-            SqlVariant::Sqlite => {
-                let _ = conn
-                    .execute("DROP TRIGGER IF EXISTS post_cid_delete")
-                    .await?;
-
-                "CREATE TRIGGER post_cid_delete AFTER DELETE ON cnacs FOR EACH ROW BEGIN DELETE FROM peers WHERE peers.cid = old.cid OR peers.peer_cid = old.cid; END"
-            }
+            tls.check_files()?;
+        }
 
-            SqlVariant::Postgre => {
-                let _ = conn
-                    .execute("DROP TRIGGER IF EXISTS post_cid_delete ON cnacs")
-                    .await?;
-                let _ = conn
-                    .execute("DROP FUNCTION IF EXISTS post_cid_delete")
-                    .await?;
+        let conn = connect_with_backoff(
+            "SQL",
+            self.opts.connect_retries.unwrap_or(DEFAULT_CONNECT_RETRIES),
+            self.opts.connect_backoff.unwrap_or(DEFAULT_CONNECT_BACKOFF),
+            || self.generate_conn(&self.url),
+        )
+        .await?;
 
-                let create_function = "CREATE OR REPLACE FUNCTION post_cid_delete() RETURNS TRIGGER LANGUAGE PLPGSQL AS $$ BEGIN DELETE FROM peers WHERE peers.cid = old.cid OR peers.peer_cid = old.cid; RETURN NULL; END; $$";
-                let _ = conn.execute(create_function).await?;
+        if !self.opts.car_mode.unwrap_or(CAR_MODE_DEFAULT) {
+            self.conn = Some(conn.clone());
+        }
 
-                "CREATE TRIGGER post_cid_delete AFTER DELETE ON cnacs FOR EACH ROW EXECUTE PROCEDURE post_cid_delete()"
+        // a primary that turns out to be read-only is still usable for reads, so long as its
+        // schema was set up beforehand
+        match self.init_schema(&conn).await {
+            Err(AccountError::ReadOnlyBackend(err)) => {
+                log::warn!(target: "citadel", "SQL backend is read-only; skipping schema setup: {}", err)
             }
-        };
+            res => res?,
+        }
 
-        // TODO: Create trigger for byte_map
+        let mut replicas = Vec::with_capacity(self.opts.read_replica_urls.len());
+        for replica_url in &self.opts.read_replica_urls {
+            let replica = connect_with_backoff(
+                "SQL replica",
+                self.opts.connect_retries.unwrap_or(DEFAULT_CONNECT_RETRIES),
+                self.opts.connect_backoff.unwrap_or(DEFAULT_CONNECT_BACKOFF),
+                || self.generate_conn(replica_url),
+            )
+            .await?;
+            replicas.push(replica);
+        }
 
-        let joined: String = [cmd, cmd2, cmd3, cmd4.to_string()].join(";");
-        let _result = conn.execute(&*joined).await?;
+        if !self.opts.car_mode.unwrap_or(CAR_MODE_DEFAULT) {
+            self.replicas = replicas;
+        }
 
         Ok(())
     }
@@ -158,6 +220,19 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
         Ok(!conn.is_closed())
     }
 
+    // in CAR mode, each connection is already closed once the operation that opened it completes
+    async fn disconnect(&self) -> Result<(), AccountError> {
+        if let Some(conn) = self.conn.as_ref() {
+            conn.close().await;
+        }
+
+        for replica in &self.replicas {
+            replica.close().await;
+        }
+
+        Ok(())
+    }
+
     async fn save_cnac(&self, cnac: &ClientNetworkAccount<R, Fcm>) -> Result<(), AccountError> {
         let conn = &(self.get_conn().await?);
         // The issue: at endpoints, mutuals are being saved inside CNAC, but not the database. We see here that mutuals are not synced to database
@@ -170,29 +245,55 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
         let query = match self.variant {
             SqlVariant::MySQL => {
                 // INSERT INTO cnacs VALUES('1') AS new ON DUPLICATE KEY UPDATE cid=new.cid
-                "INSERT INTO cnacs VALUES(?, ?, ?, ?, ?, ?) AS new ON DUPLICATE KEY UPDATE cid=new.cid, is_personal=new.is_personal, username=new.username, full_name=new.full_name, creation_date=new.creation_date, bin=new.bin"
+                "INSERT INTO cnacs (cid, is_personal, username, username_lower, full_name, creation_date, created_at, bin) VALUES(?, ?, ?, ?, ?, ?, ?, ?) AS new ON DUPLICATE KEY UPDATE cid=new.cid, is_personal=new.is_personal, username=new.username, username_lower=new.username_lower, full_name=new.full_name, creation_date=new.creation_date, created_at=new.created_at, bin=new.bin"
             }
 
             SqlVariant::Postgre | SqlVariant::Sqlite => {
                 // INSERT INTO cnacs VALUES('1', 'test') ON CONFLICT(cid) DO UPDATE SET cid=excluded.cid
-                "INSERT INTO cnacs VALUES(?, ?, ?, ?, ?, ?) ON CONFLICT(cid) DO UPDATE SET cid=excluded.cid, is_personal=excluded.is_personal, username=excluded.username, full_name=excluded.full_name, creation_date=excluded.creation_date, bin=excluded.bin"
+                "INSERT INTO cnacs (cid, is_personal, username, username_lower, full_name, creation_date, created_at, bin) VALUES(?, ?, ?, ?, ?, ?, ?, ?) ON CONFLICT(cid) DO UPDATE SET cid=excluded.cid, is_personal=excluded.is_personal, username=excluded.username, username_lower=excluded.username_lower, full_name=excluded.full_name, creation_date=excluded.creation_date, created_at=excluded.created_at, bin=excluded.bin"
             }
         };
 
         let query = self.format(query);
 
-        let mut args = AnyArguments::default();
-        args.add(metadata.cid.to_string());
-        args.add(metadata.is_personal);
-        args.add(metadata.username);
-        args.add(metadata.full_name);
-        args.add(metadata.creation_date);
-        args.add(serded);
+        let created_at = metadata
+            .creation_date_parsed()
+            .ok()
+            .map(|created_at| created_at.timestamp_millis());
+
+        let username_lower = normalize_username(&metadata.username);
+        let args = |username_lower: Option<String>| {
+            let mut args = AnyArguments::default();
+            args.add(metadata.cid.to_string());
+            args.add(metadata.is_personal);
+            args.add(metadata.username.clone());
+            args.add(username_lower);
+            args.add(metadata.full_name.clone());
+            args.add(metadata.creation_date.clone());
+            args.add(created_at);
+            args.add(serded.clone());
+            args
+        };
+
+        let err = match sqlx::query_with(query.as_str(), args(Some(username_lower.clone())))
+            .execute(conn)
+            .await
+        {
+            Ok(_) => return Ok(()),
+            Err(err) => err,
+        };
+
+        // a client keeping its username may save, even if it is a duplicate held by another. Its
+        // username_lower was left unset when backfilling, and remains so
+        let stored_username = self.get_username_by_cid(metadata.cid).await?;
+        if stored_username.as_deref().map(normalize_username) != Some(username_lower) {
+            return Err(err.into());
+        }
 
-        let _query = sqlx::query_with(query.as_str(), args)
+        let _query = sqlx::query_with(query.as_str(), args(None))
             .execute(conn)
             .await
-            .map_err(|err| AccountError::Generic(format!("{err:?}")))?;
+            .map_err(AccountError::from)?;
 
         Ok(())
     }
@@ -201,7 +302,7 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
         &self,
         cid: u64,
     ) -> Result<Option<ClientNetworkAccount<R, Fcm>>, AccountError> {
-        let conn = &(self.get_conn().await?);
+        let conn = &(self.get_read_conn().await?);
         let query: Option<AnyRow> = sqlx::query(
             self.format("SELECT bin FROM cnacs WHERE cid = ? LIMIT 1")
                 .as_str(),
@@ -213,7 +314,7 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
     }
 
     async fn cid_is_registered(&self, cid: u64) -> Result<bool, AccountError> {
-        let conn = &(self.get_conn().await?);
+        let conn = &(self.get_read_conn().await?);
         let query = sqlx::query(
             self.format("SELECT cid FROM cnacs WHERE cid = ? LIMIT 1")
                 .as_str(),
@@ -224,6 +325,20 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
         Ok(query.len() == 1)
     }
 
+    async fn is_personal(&self, cid: u64) -> Result<Option<bool>, AccountError> {
+        let conn = &(self.get_read_conn().await?);
+        let query: Option<AnyRow> = sqlx::query(
+            self.format("SELECT is_personal FROM cnacs WHERE cid = ? LIMIT 1")
+                .as_str(),
+        )
+        .bind(cid.to_string())
+        .fetch_optional(conn)
+        .await?;
+        query
+            .map(|row| self.get_bool(&row, "is_personal"))
+            .transpose()
+    }
+
     async fn delete_cnac_by_cid(&self, cid: u64) -> Result<(), AccountError> {
         let conn = &(self.get_conn().await?);
         let query: AnyQueryResult =
@@ -246,16 +361,75 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
         Ok(query.rows_affected() as usize)
     }
 
+    async fn deactivate_cnac(&self, cid: u64) -> Result<(), AccountError> {
+        let conn = &(self.get_conn().await?);
+        // the original deactivation time is kept if the client is already deactivated
+        let query: AnyQueryResult = sqlx::query(
+            self.format(
+                "UPDATE cnacs SET active = ?, deactivated_at = ? WHERE cid = ? AND active = ?",
+            )
+            .as_str(),
+        )
+        .bind(false)
+        .bind(unix_millis_now())
+        .bind(cid.to_string())
+        .bind(true)
+        .execute(conn)
+        .await?;
+
+        if query.rows_affected() != 0 || self.cid_is_registered(cid).await? {
+            Ok(())
+        } else {
+            Err(AccountError::ClientNonExists(cid))
+        }
+    }
+
+    async fn reactivate_cnac(&self, cid: u64) -> Result<(), AccountError> {
+        let conn = &(self.get_conn().await?);
+        let query: AnyQueryResult = sqlx::query(
+            self.format("UPDATE cnacs SET active = ?, deactivated_at = NULL WHERE cid = ?")
+                .as_str(),
+        )
+        .bind(true)
+        .bind(cid.to_string())
+        .execute(conn)
+        .await?;
+
+        if query.rows_affected() != 0 {
+            Ok(())
+        } else {
+            Err(AccountError::ClientNonExists(cid))
+        }
+    }
+
+    async fn purge_deactivated_before(&self, cutoff: SystemTime) -> Result<usize, AccountError> {
+        let conn = &(self.get_conn().await?);
+        let query: AnyQueryResult = sqlx::query(
+            self.format("DELETE FROM cnacs WHERE active = ? AND deactivated_at < ?")
+                .as_str(),
+        )
+        .bind(false)
+        .bind(unix_millis(cutoff))
+        .execute(conn)
+        .await?;
+        Ok(query.rows_affected() as usize)
+    }
+
     async fn get_registered_impersonal_cids(
         &self,
         limit: Option<i32>,
     ) -> Result<Option<Vec<u64>>, AccountError> {
-        let conn = &(self.get_conn().await?);
+        let conn = &(self.get_read_conn().await?);
         let cmd = limit
-            .map(|limit| format!("SELECT cid FROM cnacs WHERE is_personal = ? LIMIT {limit}",))
-            .unwrap_or_else(|| "SELECT cid FROM cnacs WHERE is_personal = ?".to_string());
+            .map(|limit| {
+                format!("SELECT cid FROM cnacs WHERE is_personal = ? AND active = ? LIMIT {limit}",)
+            })
+            .unwrap_or_else(|| {
+                "SELECT cid FROM cnacs WHERE is_personal = ? AND active = ?".to_string()
+            });
         let query: Vec<AnyRow> = sqlx::query(self.format(cmd).as_str())
             .bind(false)
+            .bind(true)
             .fetch_all(conn)
             .await?;
         let ret: Vec<u64> = query
@@ -271,8 +445,70 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
         }
     }
 
+    async fn get_registered_impersonal_cids_created_between(
+        &self,
+        created_after: Option<DateTime<Utc>>,
+        created_before: Option<DateTime<Utc>>,
+        limit: Option<i32>,
+    ) -> Result<Option<Vec<u64>>, AccountError> {
+        let conn = &(self.get_read_conn().await?);
+        let mut cmd = "SELECT cid FROM cnacs WHERE is_personal = ? AND active = ?".to_string();
+        if created_after.is_some() {
+            cmd.push_str(" AND created_at > ?");
+        }
+
+        if created_before.is_some() {
+            cmd.push_str(" AND created_at < ?");
+        }
+
+        if let Some(limit) = limit {
+            cmd.push_str(&format!(" LIMIT {limit}"));
+        }
+
+        let cmd = self.format(cmd);
+        let mut query = sqlx::query(cmd.as_str()).bind(false).bind(true);
+        for bound in [created_after, created_before].into_iter().flatten() {
+            query = query.bind(bound.timestamp_millis());
+        }
+
+        let ret: Vec<u64> = query
+            .fetch_all(conn)
+            .await?
+            .into_iter()
+            .filter_map(|r| r.try_get::<String, _>("cid").ok())
+            .filter_map(|r| u64::from_str(&r).ok())
+            .collect();
+
+        if ret.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(ret))
+        }
+    }
+
+    async fn get_registered_impersonal_cids_paged(
+        &self,
+        offset: u64,
+        limit: u32,
+    ) -> Result<Vec<u64>, AccountError> {
+        let conn = &(self.get_read_conn().await?);
+        // cids are stored as strings without leading zeros, so ordering by length first
+        // yields numeric order
+        let cmd = format!("SELECT cid FROM cnacs WHERE is_personal = ? AND active = ? ORDER BY LENGTH(cid), cid LIMIT {limit} OFFSET {offset}");
+        let query: Vec<AnyRow> = sqlx::query(self.format(cmd).as_str())
+            .bind(false)
+            .bind(true)
+            .fetch_all(conn)
+            .await?;
+        Ok(query
+            .into_iter()
+            .filter_map(|r| r.try_get::<String, _>("cid").ok())
+            .filter_map(|r| u64::from_str(&r).ok())
+            .collect())
+    }
+
     async fn get_username_by_cid(&self, cid: u64) -> Result<Option<String>, AccountError> {
-        let conn = &(self.get_conn().await?);
+        let conn = &(self.get_read_conn().await?);
         let query: Option<AnyRow> = sqlx::query(
             self.format("SELECT username FROM cnacs WHERE cid = ? LIMIT 1")
                 .as_str(),
@@ -287,12 +523,66 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
         }
     }
 
+    async fn get_cid_by_stored_username(
+        &self,
+        username: &str,
+    ) -> Result<Option<u64>, AccountError> {
+        let conn = &(self.get_read_conn().await?);
+        let query: Option<AnyRow> = sqlx::query(
+            self.format("SELECT cid FROM cnacs WHERE username_lower = ? LIMIT 1")
+                .as_str(),
+        )
+        .bind(normalize_username(username))
+        .fetch_optional(conn)
+        .await?;
+        Ok(query
+            .and_then(|row| row.try_get::<String, _>("cid").ok())
+            .and_then(|cid| u64::from_str(&cid).ok()))
+    }
+
+    async fn change_username(
+        &self,
+        cid: u64,
+        new_username: &str,
+        credential_policy: &CredentialPolicy,
+    ) -> Result<(), AccountError> {
+        let cnac = check_username_change(self, cid, new_username, credential_policy).await?;
+        cnac.set_username(new_username);
+        let serded = base64::encode(cnac.generate_proper_bytes()?);
+        let cid = cid.to_string();
+
+        let conn = &(self.get_conn().await?);
+        // the client and the entry for it in each peer's list are renamed in a single transaction
+        let mut tx = conn.begin().await?;
+        let _ = sqlx::query(
+            self.format("UPDATE cnacs SET username = ?, username_lower = ?, bin = ? WHERE cid = ?")
+                .as_str(),
+        )
+        .bind(new_username)
+        .bind(normalize_username(new_username))
+        .bind(serded)
+        .bind(cid.as_str())
+        .execute(tx.deref_mut())
+        .await?;
+        let _ = sqlx::query(
+            self.format("UPDATE peers SET username = ? WHERE peer_cid = ?")
+                .as_str(),
+        )
+        .bind(new_username)
+        .bind(cid.as_str())
+        .execute(tx.deref_mut())
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
     // We want to also update the CNACs involved
     async fn register_p2p_as_server(&self, cid0: u64, cid1: u64) -> Result<(), AccountError> {
         let conn = &(self.get_conn().await?);
         let cid0 = cid0.to_string();
         let cid1 = cid1.to_string();
 
+        // both directions are inserted by a single statement, so either both rows exist or neither
         let _query = sqlx::query(self.format("INSERT INTO peers (peer_cid, cid, username) VALUES (?, ?, (SELECT username FROM cnacs WHERE cid=?)),(?, ?, (SELECT username FROM cnacs WHERE cid=?))").as_str())
             .bind(cid0.as_str()).bind(cid1.as_str()).bind(cid0.as_str())
             .bind(cid1.as_str()).bind(cid0.as_str()).bind(cid1.as_str())
@@ -327,6 +617,7 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
         let cid0 = cid0.to_string();
         let cid1 = cid1.to_string();
 
+        // both directions are removed by a single statement, so either both rows remain or neither
         let _query = sqlx::query(
             self.format(
                 "DELETE FROM peers WHERE (peer_cid = ? AND cid = ?) OR (peer_cid = ? AND cid = ?)",
@@ -384,7 +675,7 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
         &self,
         implicated_cid: u64,
     ) -> Result<Option<Vec<u64>>, AccountError> {
-        let conn = &(self.get_conn().await?);
+        let conn = &(self.get_read_conn().await?);
         let query: Vec<AnyRow> = sqlx::query(
             self.format("SELECT peer_cid FROM peers WHERE cid = ?")
                 .as_str(),
@@ -404,63 +695,153 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
         }
     }
 
+    async fn get_hyperlan_peer_count(&self, implicated_cid: u64) -> Result<usize, AccountError> {
+        let conn = &(self.get_read_conn().await?);
+        let query: AnyRow = sqlx::query(
+            self.format("SELECT COUNT(*) as count FROM peers WHERE cid = ?")
+                .as_str(),
+        )
+        .bind(implicated_cid.to_string())
+        .fetch_one(conn)
+        .await?;
+
+        Ok(query.try_get::<i64, _>("count").unwrap_or(0) as usize)
+    }
+
     async fn get_client_metadata(
         &self,
         implicated_cid: u64,
     ) -> Result<Option<CNACMetadata>, AccountError> {
-        let conn = &(self.get_conn().await?);
+        let conn = &(self.get_read_conn().await?);
         // cnacs(cid VARCHAR(20) NOT NULL, is_connected BOOL, is_personal BOOL, username VARCHAR({}) UNIQUE, full_name TEXT, creation_date TEXT, bin LONGTEXT, PRIMARY KEY (cid)
-        let query: Option<AnyRow> = sqlx::query(self.format("SELECT is_personal, username, full_name, creation_date FROM cnacs WHERE cid = ? LIMIT 1").as_str()).bind(implicated_cid.to_string()).fetch_optional(conn).await?;
+        let query: Option<AnyRow> = sqlx::query(self.format("SELECT is_personal, username, full_name, creation_date, active FROM cnacs WHERE cid = ? LIMIT 1").as_str()).bind(implicated_cid.to_string()).fetch_optional(conn).await?;
 
         if let Some(query) = query {
             let is_personal = self.get_bool(&query, "is_personal")?;
             let username = query.try_get("username")?;
             let full_name = query.try_get("full_name")?;
             let creation_date = query.try_get("creation_date")?;
+            let is_active = self.get_bool(&query, "active")?;
             Ok(Some(CNACMetadata {
                 cid: implicated_cid,
                 is_personal,
                 username,
                 full_name,
                 creation_date,
+                is_active,
             }))
         } else {
             Ok(None)
         }
     }
 
-    async fn get_clients_metadata(
+    async fn stream_clients_metadata(
         &self,
-        limit: Option<i32>,
-    ) -> Result<Vec<CNACMetadata>, AccountError> {
+    ) -> Result<BoxStream<'_, Result<CNACMetadata, AccountError>>, AccountError> {
+        const QUERY: &str =
+            "SELECT cid, is_personal, username, full_name, creation_date, active FROM cnacs";
+
+        if let Some(conn) = self.conn.as_ref() {
+            // rows are pulled through a server-side cursor as the stream is polled
+            return Ok(sqlx::query(QUERY)
+                .fetch(conn)
+                .map_err(AccountError::from)
+                .try_filter_map(move |row| futures::future::ok(self.row_to_metadata(&row)))
+                .boxed());
+        }
+
+        // CAR mode does not hold connections, so a new connection is used for each page instead
+        const PAGE_SIZE: usize = 256;
+        Ok(futures::stream::unfold(Some(0), move |offset| async move {
+            let offset = offset?;
+            let query = format!("{QUERY} ORDER BY cid LIMIT {PAGE_SIZE} OFFSET {offset}");
+            let rows: Result<Vec<AnyRow>, AccountError> = match self.get_read_conn().await {
+                Ok(conn) => sqlx::query(query.as_str())
+                    .fetch_all(&conn)
+                    .await
+                    .map_err(AccountError::from),
+                Err(err) => Err(err),
+            };
+
+            match rows {
+                Ok(rows) => {
+                    let next = (rows.len() == PAGE_SIZE).then_some(offset + PAGE_SIZE);
+                    let page = rows
+                        .iter()
+                        .filter_map(|row| self.row_to_metadata(row))
+                        .map(Ok)
+                        .collect::<Vec<_>>();
+                    Some((page, next))
+                }
+
+                Err(err) => Some((vec![Err(err)], None)),
+            }
+        })
+        .flat_map(futures::stream::iter)
+        .boxed())
+    }
+
+    async fn set_last_connect(&self, cid: u64, time: DateTime<Utc>) -> Result<(), AccountError> {
         let conn = &(self.get_conn().await?);
-        // cnacs(cid VARCHAR(20) NOT NULL, is_connected BOOL, is_personal BOOL,  username VARCHAR({}) UNIQUE, full_name TEXT, creation_date TEXT, bin LONGTEXT, PRIMARY KEY (cid)
-        let query = if let Some(limit) = limit {
-            format!(
-                "SELECT cid, is_personal, username, full_name, creation_date FROM cnacs LIMIT {limit}",
-            )
+        let query: AnyQueryResult = sqlx::query(
+            self.format("UPDATE cnacs SET last_connect = ? WHERE cid = ?")
+                .as_str(),
+        )
+        .bind(time.timestamp_millis())
+        .bind(cid.to_string())
+        .execute(conn)
+        .await?;
+
+        // MySQL reports no affected rows if the time is unchanged
+        if query.rows_affected() != 0 || self.cid_is_registered(cid).await? {
+            Ok(())
         } else {
-            "SELECT cid, is_personal, username, full_name, creation_date FROM cnacs".to_string()
-        };
+            Err(AccountError::ClientNonExists(cid))
+        }
+    }
+
+    async fn get_last_connect(&self, cid: u64) -> Result<Option<DateTime<Utc>>, AccountError> {
+        let conn = &(self.get_read_conn().await?);
+        let query: Option<AnyRow> = sqlx::query(
+            self.format("SELECT last_connect FROM cnacs WHERE cid = ? LIMIT 1")
+                .as_str(),
+        )
+        .bind(cid.to_string())
+        .fetch_optional(conn)
+        .await?;
+
+        if let Some(row) = query {
+            let last_connect: Option<i64> = row.try_get("last_connect")?;
+            Ok(last_connect.map(|millis| from_unix_millis(millis).into()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn get_last_connects(
+        &self,
+        cids: &[u64],
+    ) -> Result<HashMap<u64, DateTime<Utc>>, AccountError> {
+        if cids.is_empty() {
+            return Ok(HashMap::new());
+        }
 
-        let query: Vec<AnyRow> = sqlx::query(query.as_str()).fetch_all(conn).await?;
+        let conn = &(self.get_read_conn().await?);
+        let insert = self.construct_arg_insert_any(cids);
+        let query = format!("WITH input(cid) AS (VALUES {insert}) SELECT cnacs.cid, cnacs.last_connect FROM input INNER JOIN cnacs ON input.cid = cnacs.cid WHERE cnacs.last_connect IS NOT NULL");
 
+        let query: Vec<AnyRow> = sqlx::query(self.format(query).as_str())
+            .fetch_all(conn)
+            .await?;
         Ok(query
             .into_iter()
-            .filter_map(|query| {
-                let cid = query.try_get::<String, _>("cid").ok()?;
-                let cid = u64::from_str(cid.as_str()).ok()?;
-                let is_personal = self.get_bool(&query, "is_personal").ok()?;
-                let username = query.try_get("username").ok()?;
-                let full_name = query.try_get("full_name").ok()?;
-                let creation_date = query.try_get("creation_date").ok()?;
-                Some(CNACMetadata {
-                    cid,
-                    is_personal,
-                    username,
-                    full_name,
-                    creation_date,
-                })
+            .filter_map(|row| {
+                let cid: String = row.try_get("cid").ok()?;
+                let last_connect: i64 = row.try_get("last_connect").ok()?;
+                Some((
+                    u64::from_str(&cid).ok()?,
+                    from_unix_millis(last_connect).into(),
+                ))
             })
             .collect())
     }
@@ -470,7 +851,7 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
         implicated_cid: u64,
         peer_cid: u64,
     ) -> Result<Option<MutualPeer>, AccountError> {
-        let conn = &(self.get_conn().await?);
+        let conn = &(self.get_read_conn().await?);
         let query: Option<AnyRow> = sqlx::query(
             self.format("SELECT username FROM peers WHERE cid = ? AND peer_cid = ? LIMIT 1")
                 .as_str(),
@@ -500,7 +881,7 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
         implicated_cid: u64,
         peer_cid: u64,
     ) -> Result<bool, AccountError> {
-        let conn = &(self.get_conn().await?);
+        let conn = &(self.get_read_conn().await?);
         let query: AnyRow = sqlx::query(
             self.format(
                 "SELECT COUNT(*) as count FROM peers WHERE peer_cid = ? AND cid = ? LIMIT 1",
@@ -524,7 +905,7 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
             return Ok(Vec::new());
         }
 
-        let conn = &(self.get_conn().await?);
+        let conn = &(self.get_read_conn().await?);
         let limit = peers.len();
 
         let insert = self.construct_arg_insert_any(peers);
@@ -557,7 +938,7 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
             return Ok(Vec::new());
         }
 
-        let conn = &(self.get_conn().await?);
+        let conn = &(self.get_read_conn().await?);
         let limit = peers.len();
 
         let insert = self.construct_arg_insert_any(peers);
@@ -587,7 +968,7 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
         &self,
         implicated_cid: u64,
     ) -> Result<Option<Vec<MutualPeer>>, AccountError> {
-        let conn = &(self.get_conn().await?);
+        let conn = &(self.get_read_conn().await?);
         let query: Vec<AnyRow> = sqlx::query(self.format("SELECT peers.peer_cid, peers.username FROM cnacs INNER JOIN peers ON cnacs.cid = peers.cid WHERE peers.cid = ?").as_str()).bind(implicated_cid.to_string()).fetch_all(conn).await?;
         let mut ret = Vec::with_capacity(query.len());
 
@@ -609,14 +990,52 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
         }
     }
 
-    // We always return false here, since there's no need for manual saving
+    async fn search_hyperlan_peers_by_prefix(
+        &self,
+        implicated_cid: u64,
+        prefix: &str,
+        limit: u32,
+    ) -> Result<Vec<MutualPeer>, AccountError> {
+        let conn = &(self.get_read_conn().await?);
+        // '!' escapes the wildcards of LIKE, since the meaning of a backslash in a string literal
+        // differs between variants
+        let pattern = normalize_username(prefix)
+            .replace('!', "!!")
+            .replace('%', "!%")
+            .replace('_', "!_");
+        let cmd = format!("SELECT peer_cid, username FROM peers WHERE cid = ? AND LOWER(username) LIKE ? ESCAPE '!' ORDER BY LOWER(username) LIMIT {limit}");
+        let query: Vec<AnyRow> = sqlx::query(self.format(cmd).as_str())
+            .bind(implicated_cid.to_string())
+            .bind(format!("{pattern}%"))
+            .fetch_all(conn)
+            .await?;
+        Ok(query
+            .into_iter()
+            .filter_map(|row| {
+                let peer_cid: String = row.try_get("peer_cid").ok()?;
+                let peer_cid = u64::from_str(&peer_cid).ok()?;
+                let username: String = row.try_get("username").ok()?;
+                Some(MutualPeer {
+                    parent_icid: HYPERLAN_IDX,
+                    cid: peer_cid,
+                    username: Some(username),
+                })
+            })
+            .collect())
+    }
+
     async fn synchronize_hyperlan_peer_list_as_client(
         &self,
         cnac: &ClientNetworkAccount<R, Fcm>,
         peers: Vec<MutualPeer>,
-    ) -> Result<(), AccountError> {
+    ) -> Result<PeerListDelta, AccountError> {
         log::trace!(target: "citadel", "Synchronizing peer list for {}", cnac.get_cid());
-        if !peers.is_empty() {
+        let existing = self
+            .get_hyperlan_peer_list_as_server(cnac.get_cid())
+            .await?
+            .unwrap_or_default();
+        let delta = PeerListDelta::between(&existing, &peers);
+        if !delta.is_empty() {
             let conn = &(self.get_conn().await?);
 
             let mut tx = conn.begin().await?;
@@ -642,7 +1061,7 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
             //self.save_cnac(cnac.clone()).await?;
         }
 
-        Ok(())
+        Ok(delta)
     }
 
     async fn get_byte_map_value(
@@ -653,11 +1072,14 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
         sub_key: &str,
     ) -> Result<Option<Vec<u8>>, AccountError> {
         let conn = &(self.get_conn().await?);
-        let row: Option<AnyRow> = sqlx::query(self.format("SELECT bin FROM bytemap WHERE cid = ? AND peer_cid = ? AND id = ? AND sub_id = ? LIMIT 1").as_str())
+        self.sweep_expired_byte_map_values(conn, implicated_cid, peer_cid, key)
+            .await?;
+        let row: Option<AnyRow> = sqlx::query(self.format("SELECT bin FROM bytemap WHERE cid = ? AND peer_cid = ? AND id = ? AND sub_id = ? AND (expires_at IS NULL OR expires_at > ?) LIMIT 1").as_str())
             .bind(implicated_cid.to_string())
             .bind(peer_cid.to_string())
             .bind(key)
             .bind(sub_key)
+            .bind(unix_millis_now())
             .fetch_optional(conn).await?;
 
         if let Some(row) = row {
@@ -671,6 +1093,39 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
         }
     }
 
+    async fn get_byte_map_values(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        keys: &[(String, String)],
+    ) -> Result<HashMap<(String, String), Vec<u8>>, AccountError> {
+        if keys.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let conn = &(self.get_read_conn().await?);
+        let pairs = vec!["(?, ?)"; keys.len()].join(", ");
+        let query = self.format(format!("SELECT id, sub_id, bin FROM bytemap WHERE cid = ? AND peer_cid = ? AND (id, sub_id) IN ({pairs}) AND (expires_at IS NULL OR expires_at > ?)"));
+        let mut query = sqlx::query(query.as_str())
+            .bind(implicated_cid.to_string())
+            .bind(peer_cid.to_string());
+        for (key, sub_key) in keys {
+            query = query.bind(key.as_str()).bind(sub_key.as_str());
+        }
+
+        let rows: Vec<AnyRow> = query.bind(unix_millis_now()).fetch_all(conn).await?;
+
+        let mut ret = HashMap::new();
+        for row in rows {
+            let key = row.try_get::<String, _>("id")?;
+            let sub_key = row.try_get::<String, _>("sub_id")?;
+            let bin = base64::decode(row.try_get::<String, _>("bin")?)?;
+            let _ = ret.insert((key, sub_key), bin);
+        }
+
+        Ok(ret)
+    }
+
     async fn remove_byte_map_value(
         &self,
         implicated_cid: u64,
@@ -711,41 +1166,212 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
         sub_key: &str,
         value: Vec<u8>,
     ) -> Result<Option<Vec<u8>>, AccountError> {
+        self.insert_byte_map_value(implicated_cid, peer_cid, key, sub_key, value, None)
+            .await
+    }
+
+    async fn store_byte_map_value_with_expiry(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> Result<Option<Vec<u8>>, AccountError> {
+        let expires_at = unix_millis_now().saturating_add(ttl.as_millis() as i64);
+        self.insert_byte_map_value(
+            implicated_cid,
+            peer_cid,
+            key,
+            sub_key,
+            value,
+            Some(expires_at),
+        )
+        .await
+    }
+
+    async fn compare_and_swap_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        expected: Option<Vec<u8>>,
+        new: Vec<u8>,
+    ) -> Result<bool, AccountError> {
         let conn = self.get_conn().await?;
+        self.sweep_expired_byte_map_values(&conn, implicated_cid, peer_cid, key)
+            .await?;
         let mut tx = conn.begin().await?;
-        let bytes_base64 = base64::encode(value);
-        let get_query = self.format("SELECT bin FROM bytemap WHERE cid = ? AND peer_cid = ? AND id = ? AND sub_id = ? LIMIT 1");
-        let set_query = self
-            .format("INSERT INTO bytemap (cid, peer_cid, id, sub_id, bin) VALUES (?, ?, ?, ?, ?)");
+        // writing to the owning account's row locks it until the transaction ends, which
+        // serializes concurrent swaps (including "set if absent", where no bytemap row exists)
+        let lock_query = self.format("UPDATE cnacs SET cid = cid WHERE cid = ?");
+        let get_query = self.format("SELECT bin FROM bytemap WHERE cid = ? AND peer_cid = ? AND id = ? AND sub_id = ? AND (expires_at IS NULL OR expires_at > ?) LIMIT 1");
+        let delete_query = self
+            .format("DELETE FROM bytemap WHERE cid = ? AND peer_cid = ? AND id = ? AND sub_id = ?");
+        let set_query = self.format(
+            "INSERT INTO bytemap (cid, peer_cid, id, sub_id, bin, expires_at) VALUES (?, ?, ?, ?, ?, NULL)",
+        );
+
+        let _query = sqlx::query(&lock_query)
+            .bind(implicated_cid.to_string())
+            .execute(&mut tx)
+            .await?;
 
         let row: Option<AnyRow> = sqlx::query(&get_query)
             .bind(implicated_cid.to_string())
             .bind(peer_cid.to_string())
             .bind(key)
             .bind(sub_key)
+            .bind(unix_millis_now())
             .fetch_optional(&mut tx)
             .await?;
 
+        let current = match row {
+            Some(row) => Some(base64::decode(row.try_get::<String, _>("bin")?)?),
+            None => None,
+        };
+
+        if current != expected {
+            return Ok(false);
+        }
+
+        let _query = sqlx::query(&delete_query)
+            .bind(implicated_cid.to_string())
+            .bind(peer_cid.to_string())
+            .bind(key)
+            .bind(sub_key)
+            .execute(&mut tx)
+            .await?;
+
         let _query = sqlx::query(&set_query)
             .bind(implicated_cid.to_string())
             .bind(peer_cid.to_string())
             .bind(key)
             .bind(sub_key)
-            .bind(bytes_base64)
+            .bind(base64::encode(new))
             .execute(&mut tx)
             .await?;
 
         tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn get_or_insert_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        default: Box<dyn FnOnce() -> Vec<u8> + Send + '_>,
+    ) -> Result<Vec<u8>, AccountError> {
+        if let Some(value) = self
+            .get_byte_map_value(implicated_cid, peer_cid, key, sub_key)
+            .await?
+        {
+            return Ok(value);
+        }
 
+        let default = default();
+        let conn = self.get_conn().await?;
+        let mut tx = conn.begin().await?;
+        // the bytemap table has no unique index on a value's address for ON CONFLICT to act
+        // upon. Instead, writing to the owning account's row locks it until the transaction
+        // ends, which serializes concurrent inserts in the same way as compare-and-swap
+        let lock_query = self.format("UPDATE cnacs SET cid = cid WHERE cid = ?");
+        let get_query = self.format("SELECT bin FROM bytemap WHERE cid = ? AND peer_cid = ? AND id = ? AND sub_id = ? AND (expires_at IS NULL OR expires_at > ?) LIMIT 1");
+        let delete_query = self
+            .format("DELETE FROM bytemap WHERE cid = ? AND peer_cid = ? AND id = ? AND sub_id = ?");
+        let set_query = self.format(
+            "INSERT INTO bytemap (cid, peer_cid, id, sub_id, bin, expires_at) VALUES (?, ?, ?, ?, ?, NULL)",
+        );
+
+        let _query = sqlx::query(&lock_query)
+            .bind(implicated_cid.to_string())
+            .execute(&mut tx)
+            .await?;
+
+        let row: Option<AnyRow> = sqlx::query(&get_query)
+            .bind(implicated_cid.to_string())
+            .bind(peer_cid.to_string())
+            .bind(key)
+            .bind(sub_key)
+            .bind(unix_millis_now())
+            .fetch_optional(&mut tx)
+            .await?;
+
+        // the value was set since it was read
         if let Some(row) = row {
-            match row.try_get::<String, _>("bin") {
-                Ok(val) => Ok(Some(base64::decode(val)?)),
+            return Ok(base64::decode(row.try_get::<String, _>("bin")?)?);
+        }
 
-                _ => Ok(None),
-            }
-        } else {
-            Ok(None)
+        // removes an expired value, if any
+        let _query = sqlx::query(&delete_query)
+            .bind(implicated_cid.to_string())
+            .bind(peer_cid.to_string())
+            .bind(key)
+            .bind(sub_key)
+            .execute(&mut tx)
+            .await?;
+
+        let _query = sqlx::query(&set_query)
+            .bind(implicated_cid.to_string())
+            .bind(peer_cid.to_string())
+            .bind(key)
+            .bind(sub_key)
+            .bind(base64::encode(&default))
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(default)
+    }
+
+    async fn copy_byte_map(&self, from_cid: u64, to_cid: u64) -> Result<usize, AccountError> {
+        let conn = self.get_conn().await?;
+        let mut tx = conn.begin().await?;
+        let get_query = self.format("SELECT peer_cid, id, sub_id, bin, expires_at FROM bytemap WHERE cid = ? AND (expires_at IS NULL OR expires_at > ?)");
+        let delete_query = self
+            .format("DELETE FROM bytemap WHERE cid = ? AND peer_cid = ? AND id = ? AND sub_id = ?");
+        let insert_query = self.format(
+            "INSERT INTO bytemap (cid, peer_cid, id, sub_id, bin, expires_at) VALUES (?, ?, ?, ?, ?, ?)",
+        );
+
+        let rows: Vec<AnyRow> = sqlx::query(&get_query)
+            .bind(from_cid.to_string())
+            .bind(unix_millis_now())
+            .fetch_all(&mut tx)
+            .await?;
+
+        for row in &rows {
+            let peer_cid: String = row.try_get("peer_cid")?;
+            let key: String = row.try_get("id")?;
+            let sub_key: String = row.try_get("sub_id")?;
+            let bin: String = row.try_get("bin")?;
+            let expires_at: Option<i64> = row.try_get("expires_at")?;
+
+            let _query = sqlx::query(&delete_query)
+                .bind(to_cid.to_string())
+                .bind(&peer_cid)
+                .bind(&key)
+                .bind(&sub_key)
+                .execute(&mut tx)
+                .await?;
+
+            let _query = sqlx::query(&insert_query)
+                .bind(to_cid.to_string())
+                .bind(peer_cid)
+                .bind(key)
+                .bind(sub_key)
+                .bind(bin)
+                .bind(expires_at)
+                .execute(&mut tx)
+                .await?;
         }
+
+        tx.commit().await?;
+        Ok(rows.len())
     }
 
     async fn get_byte_map_values_by_key(
@@ -755,15 +1381,18 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
         key: &str,
     ) -> Result<HashMap<String, Vec<u8>>, AccountError> {
         let conn = &(self.get_conn().await?);
+        self.sweep_expired_byte_map_values(conn, implicated_cid, peer_cid, key)
+            .await?;
         let rows: Vec<AnyRow> = sqlx::query(
             self.format(
-                "SELECT sub_id, bin FROM bytemap WHERE cid = ? AND peer_cid = ? AND id = ?",
+                "SELECT sub_id, bin FROM bytemap WHERE cid = ? AND peer_cid = ? AND id = ? AND (expires_at IS NULL OR expires_at > ?)",
             )
             .as_str(),
         )
         .bind(implicated_cid.to_string())
         .bind(peer_cid.to_string())
         .bind(key)
+        .bind(unix_millis_now())
         .fetch_all(conn)
         .await?;
 
@@ -802,6 +1431,68 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
         Ok(values)
     }
 
+    async fn get_byte_map_entries(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Vec<ByteMapEntry>, AccountError> {
+        let conn = &(self.get_conn().await?);
+        let rows: Vec<AnyRow> = sqlx::query(
+            self.format(
+                "SELECT id, sub_id, bin, expires_at FROM bytemap WHERE cid = ? AND peer_cid = ? AND (expires_at IS NULL OR expires_at > ?)",
+            )
+            .as_str(),
+        )
+        .bind(implicated_cid.to_string())
+        .bind(peer_cid.to_string())
+        .bind(unix_millis_now())
+        .fetch_all(conn)
+        .await?;
+
+        let mut ret = Vec::with_capacity(rows.len());
+        for row in rows {
+            let bin = row.try_get::<String, _>("bin")?;
+            let expires_at: Option<i64> = row.try_get("expires_at")?;
+            ret.push(ByteMapEntry {
+                key: row.try_get("id")?,
+                sub_key: row.try_get("sub_id")?,
+                value: base64::decode(bin)?,
+                expires_at: expires_at.map(from_unix_millis),
+            });
+        }
+
+        Ok(ret)
+    }
+
+    async fn list_byte_map_keys(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Vec<String>, AccountError> {
+        let conn = &(self.get_conn().await?);
+        let rows: Vec<AnyRow> = sqlx::query(
+            self.format(
+                "SELECT DISTINCT id FROM bytemap WHERE cid = ? AND peer_cid = ? AND (expires_at IS NULL OR expires_at > ?) ORDER BY id",
+            )
+            .as_str(),
+        )
+        .bind(implicated_cid.to_string())
+        .bind(peer_cid.to_string())
+        .bind(unix_millis_now())
+        .fetch_all(conn)
+        .await?;
+
+        let mut keys = Vec::with_capacity(rows.len());
+        for row in rows {
+            let key: String = row.try_get("id")?;
+            if !is_internal_byte_map_key(&key) {
+                keys.push(key);
+            }
+        }
+
+        Ok(keys)
+    }
+
     async fn stream_object_to_backend(
         &self,
         source: UnboundedReceiver<Vec<u8>>,
@@ -813,9 +1504,296 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SqlBackend<R, Fcm>
 }
 
 impl<R: Ratchet, Fcm: Ratchet> SqlBackend<R, Fcm> {
+    async fn insert_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        value: Vec<u8>,
+        expires_at: Option<i64>,
+    ) -> Result<Option<Vec<u8>>, AccountError> {
+        let conn = self.get_conn().await?;
+        self.sweep_expired_byte_map_values(&conn, implicated_cid, peer_cid, key)
+            .await?;
+        let mut tx = conn.begin().await?;
+        let bytes_base64 = base64::encode(value);
+        let get_query = self.format("SELECT bin FROM bytemap WHERE cid = ? AND peer_cid = ? AND id = ? AND sub_id = ? AND (expires_at IS NULL OR expires_at > ?) LIMIT 1");
+        let delete_query = self
+            .format("DELETE FROM bytemap WHERE cid = ? AND peer_cid = ? AND id = ? AND sub_id = ?");
+        let set_query = self.format(
+            "INSERT INTO bytemap (cid, peer_cid, id, sub_id, bin, expires_at) VALUES (?, ?, ?, ?, ?, ?)",
+        );
+
+        let row: Option<AnyRow> = sqlx::query(&get_query)
+            .bind(implicated_cid.to_string())
+            .bind(peer_cid.to_string())
+            .bind(key)
+            .bind(sub_key)
+            .bind(unix_millis_now())
+            .fetch_optional(&mut tx)
+            .await?;
+
+        // overwrite the previous value, including its expiry, if any
+        let _query = sqlx::query(&delete_query)
+            .bind(implicated_cid.to_string())
+            .bind(peer_cid.to_string())
+            .bind(key)
+            .bind(sub_key)
+            .execute(&mut tx)
+            .await?;
+
+        let _query = sqlx::query(&set_query)
+            .bind(implicated_cid.to_string())
+            .bind(peer_cid.to_string())
+            .bind(key)
+            .bind(sub_key)
+            .bind(bytes_base64)
+            .bind(expires_at)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        if let Some(row) = row {
+            match row.try_get::<String, _>("bin") {
+                Ok(val) => Ok(Some(base64::decode(val)?)),
+
+                _ => Ok(None),
+            }
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Lazily deletes the expired values inside `key`
+    async fn sweep_expired_byte_map_values(
+        &self,
+        conn: &AnyPool,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+    ) -> Result<(), AccountError> {
+        let _ = sqlx::query(
+            self.format("DELETE FROM bytemap WHERE cid = ? AND peer_cid = ? AND id = ? AND expires_at IS NOT NULL AND expires_at <= ?")
+                .as_str(),
+        )
+        .bind(implicated_cid.to_string())
+        .bind(peer_cid.to_string())
+        .bind(key)
+        .bind(unix_millis_now())
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Sets the username_lower of each row to its [normalized](normalize_username) username, where
+    /// it was saved before the column existed or before the normalization last changed. If
+    /// several clients hold the same username, the client with the lowest CID keeps it, and
+    /// username_lower is left NULL for the others
+    async fn backfill_username_index(&self, conn: &AnyPool) -> Result<(), AccountError> {
+        let rows: Vec<AnyRow> = sqlx::query("SELECT cid, username, username_lower FROM cnacs")
+            .fetch_all(conn)
+            .await?;
+        let mut rows = rows
+            .into_iter()
+            .map(|row| {
+                Ok((
+                    row.try_get::<String, _>("cid")?,
+                    row.try_get::<String, _>("username")?,
+                    row.try_get::<Option<String>, _>("username_lower")?,
+                ))
+            })
+            .collect::<Result<Vec<_>, AccountError>>()?;
+        rows.sort_unstable_by_key(|(cid, ..)| u64::from_str(cid).unwrap_or(u64::MAX));
+
+        let mut holders = HashMap::new();
+        let mut updates = Vec::new();
+        for (cid, username, stored) in rows {
+            let normalized = normalize_username(&username);
+            let username_lower = match holders.get(&normalized) {
+                Some(holder_cid) => {
+                    log::warn!(target: "citadel", "Clients {} and {} hold the same username {}", holder_cid, cid, normalized);
+                    None
+                }
+
+                None => {
+                    let _ = holders.insert(normalized.clone(), cid.clone());
+                    Some(normalized)
+                }
+            };
+
+            if stored != username_lower {
+                updates.push((cid, username_lower));
+            }
+        }
+
+        // every stale value is cleared before any is set, so that none collides with one not yet
+        // updated
+        for (cid, _) in &updates {
+            let _ = sqlx::query(
+                self.format("UPDATE cnacs SET username_lower = NULL WHERE cid = ?")
+                    .as_str(),
+            )
+            .bind(cid.as_str())
+            .execute(conn)
+            .await?;
+        }
+
+        for (cid, username_lower) in updates {
+            if let Some(username_lower) = username_lower {
+                let _ = sqlx::query(
+                    self.format("UPDATE cnacs SET username_lower = ? WHERE cid = ?")
+                        .as_str(),
+                )
+                .bind(username_lower)
+                .bind(cid)
+                .execute(conn)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates the tables, and migrates tables created by older versions
+    async fn init_schema(&self, conn: &AnyPool) -> Result<(), AccountError> {
+        //let conn = AnyPool::connect_with(&self.url).await?;
+
+        // we use varchar(20) for a u64 since u64::MAX char count = 20
+
+        // The below works on MySql, Postgre, SqLite,
+        let bin_type = if self.variant == SqlVariant::Postgre {
+            "TEXT"
+        } else {
+            "LONGTEXT"
+        };
+        // we no longer use bool due to postgresql bug with t/f not being mapped properly
+        let cmd = format!("CREATE TABLE IF NOT EXISTS cnacs(cid VARCHAR(20) NOT NULL, is_personal BOOL, username VARCHAR({MAX_USERNAME_BYTES}) UNIQUE, username_lower VARCHAR({MAX_USERNAME_BYTES}) UNIQUE, full_name TEXT, creation_date TEXT, bin {bin_type}, active BOOL DEFAULT TRUE, deactivated_at BIGINT, created_at BIGINT, last_connect BIGINT, PRIMARY KEY (cid))");
+        let cmd2 = format!("CREATE TABLE IF NOT EXISTS peers(peer_cid VARCHAR(20), username VARCHAR({MAX_USERNAME_BYTES}), cid VARCHAR(20), CONSTRAINT fk_cid FOREIGN KEY (cid) REFERENCES cnacs(cid) ON DELETE CASCADE)");
+        //let cmd3 = format!("CREATE TABLE IF NOT EXISTS bytemap(cid VARCHAR(20) NOT NULL, peer_cid VARCHAR(20), key TEXT, bin TEXT, CONSTRAINT fk_cid FOREIGN KEY (cid) REFERENCES cnacs(cid) ON DELETE CASCADE)");
+        let cmd3 = format!("CREATE TABLE IF NOT EXISTS bytemap(cid VARCHAR(20) NOT NULL, peer_cid VARCHAR(20), id TEXT, sub_id TEXT, bin {bin_type}, expires_at BIGINT, CONSTRAINT fk_cid2 FOREIGN KEY (cid) REFERENCES cnacs(cid) ON DELETE CASCADE)");
+
+        // The following commands below allow us to remove entries and automatically remove corresponding values
+        let cmd4 = match self.variant {
+            SqlVariant::MySQL => {
+                let _ = conn
+                    .execute("DROP TRIGGER IF EXISTS post_cid_delete")
+                    .await?;
+
+                "CREATE TRIGGER post_cid_delete AFTER DELETE ON cnacs FOR EACH ROW DELETE FROM peers WHERE peers.cid = old.cid OR peers.peer_cid = old.cid"
+            }
+
+            SqlVariant::Sqlite => {
+                let _ = conn
+                    .execute("DROP TRIGGER IF EXISTS post_cid_delete")
+                    .await?;
+
+                "CREATE TRIGGER post_cid_delete AFTER DELETE ON cnacs FOR EACH ROW BEGIN DELETE FROM peers WHERE peers.cid = old.cid OR peers.peer_cid = old.cid; END"
+            }
+
+            SqlVariant::Postgre => {
+                let _ = conn
+                    .execute("DROP TRIGGER IF EXISTS post_cid_delete ON cnacs")
+                    .await?;
+                let _ = conn
+                    .execute("DROP FUNCTION IF EXISTS post_cid_delete")
+                    .await?;
+
+                let create_function = "CREATE OR REPLACE FUNCTION post_cid_delete() RETURNS TRIGGER LANGUAGE PLPGSQL AS $$ BEGIN DELETE FROM peers WHERE peers.cid = old.cid OR peers.peer_cid = old.cid; RETURN NULL; END; $$";
+                let _ = conn.execute(create_function).await?;
+
+                "CREATE TRIGGER post_cid_delete AFTER DELETE ON cnacs FOR EACH ROW EXECUTE PROCEDURE post_cid_delete()"
+            }
+        };
+
+        // TODO: Create trigger for byte_map
+
+        let joined: String = [cmd, cmd2, cmd3, cmd4.to_string()].join(";");
+        let _result = conn.execute(&*joined).await?;
+
+        // bytemap tables created before values could expire lack the expires_at column. This fails
+        // harmlessly when the column already exists
+        let _ = conn
+            .execute("ALTER TABLE bytemap ADD COLUMN expires_at BIGINT")
+            .await;
+        // likewise, cnacs tables created before clients could be deactivated lack these columns
+        let _ = conn
+            .execute("ALTER TABLE cnacs ADD COLUMN active BOOL DEFAULT TRUE")
+            .await;
+        let _ = conn
+            .execute("ALTER TABLE cnacs ADD COLUMN deactivated_at BIGINT")
+            .await;
+        // and cnacs tables created before usernames were case-insensitive lack username_lower,
+        // which is backfilled below
+        let _ = conn
+            .execute(
+                format!(
+                    "ALTER TABLE cnacs ADD COLUMN username_lower VARCHAR({MAX_USERNAME_BYTES})"
+                )
+                .as_str(),
+            )
+            .await;
+        // and username columns created before usernames were bounded in bytes are only as wide as
+        // the default character limit. MySQL and Postgres count VARCHAR widths in characters, so a
+        // width of MAX_USERNAME_BYTES fits any valid username, while SQLite ignores the width
+        let widen_username_columns = match self.variant {
+            SqlVariant::MySQL => vec![
+                format!("ALTER TABLE cnacs MODIFY username VARCHAR({MAX_USERNAME_BYTES})"),
+                format!("ALTER TABLE cnacs MODIFY username_lower VARCHAR({MAX_USERNAME_BYTES})"),
+                format!("ALTER TABLE peers MODIFY username VARCHAR({MAX_USERNAME_BYTES})"),
+            ],
+            SqlVariant::Postgre => vec![
+                format!("ALTER TABLE cnacs ALTER COLUMN username TYPE VARCHAR({MAX_USERNAME_BYTES})"),
+                format!(
+                    "ALTER TABLE cnacs ALTER COLUMN username_lower TYPE VARCHAR({MAX_USERNAME_BYTES})"
+                ),
+                format!("ALTER TABLE peers ALTER COLUMN username TYPE VARCHAR({MAX_USERNAME_BYTES})"),
+            ],
+            SqlVariant::Sqlite => vec![],
+        };
+        for cmd in widen_username_columns {
+            let _ = conn.execute(cmd.as_str()).await;
+        }
+        self.backfill_username_index(conn).await?;
+        let _ = conn
+            .execute("CREATE UNIQUE INDEX cnacs_username_lower ON cnacs (username_lower)")
+            .await;
+        // and cnacs tables created before connects were tracked lack last_connect
+        let _ = conn
+            .execute("ALTER TABLE cnacs ADD COLUMN last_connect BIGINT")
+            .await;
+        // and cnacs tables created before clients could be queried by creation date lack
+        // created_at, which mirrors creation_date in unix millis so that it can be compared
+        let _ = conn
+            .execute("ALTER TABLE cnacs ADD COLUMN created_at BIGINT")
+            .await;
+        let unindexed: Vec<AnyRow> =
+            sqlx::query("SELECT cid, creation_date FROM cnacs WHERE created_at IS NULL")
+                .fetch_all(conn)
+                .await?;
+        for row in unindexed {
+            let cid: String = row.try_get("cid")?;
+            let creation_date: String = row.try_get("creation_date")?;
+            if let Ok(created_at) = parse_formatted_timestamp(&creation_date) {
+                let _ = sqlx::query(
+                    self.format("UPDATE cnacs SET created_at = ? WHERE cid = ?")
+                        .as_str(),
+                )
+                .bind(created_at.timestamp_millis())
+                .bind(cid)
+                .execute(conn)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
     async fn get_conn(&self) -> Result<AnyPool, AccountError> {
         if self.opts.car_mode.unwrap_or(CAR_MODE_DEFAULT) {
-            self.generate_conn().await
+            self.generate_conn(&self.url).await
         } else {
             self.conn
                 .clone()
@@ -823,10 +1801,74 @@ impl<R: Ratchet, Fcm: Ratchet> SqlBackend<R, Fcm> {
         }
     }
 
-    async fn generate_conn(&self) -> Result<AnyPool, AccountError> {
+    /// Returns a connection to the next read replica in turn if any are configured, otherwise to
+    /// the primary
+    async fn get_read_conn(&self) -> Result<AnyPool, AccountError> {
+        let replica_urls = &self.opts.read_replica_urls;
+        if replica_urls.is_empty() {
+            return self.get_conn().await;
+        }
+
+        let idx = self.next_replica.fetch_add(1, Ordering::Relaxed) % replica_urls.len();
+        if self.opts.car_mode.unwrap_or(CAR_MODE_DEFAULT) {
+            self.generate_conn(&replica_urls[idx]).await
+        } else {
+            self.replicas
+                .get(idx)
+                .cloned()
+                .ok_or_else(|| AccountError::Generic("Replica connection not loaded".to_string()))
+        }
+    }
+
+    async fn generate_conn(&self, url: &str) -> Result<AnyPool, AccountError> {
         let opts: AnyPoolOptions = (&self.opts).into();
         log::trace!(target: "citadel", "Generating new connection ...");
-        Ok(opts.connect(&self.url).await?)
+        Ok(opts.connect_with(self.connect_options(url)?).await?)
+    }
+
+    fn connect_options(&self, url: &str) -> Result<AnyConnectOptions, AccountError> {
+        let mut connect_options = AnyConnectOptions::from_str(url)?;
+        let tls = if let Some(tls) = self.opts.tls.as_ref() {
+            tls
+        } else {
+            return Ok(connect_options);
+        };
+
+        if let Some(pg) = connect_options.as_postgres_mut() {
+            let mut options = pg.clone().ssl_mode(if tls.accept_invalid_certs {
+                PgSslMode::Require
+            } else {
+                PgSslMode::VerifyFull
+            });
+
+            if let Some(root_cert) = tls.root_cert.as_deref() {
+                options = options.ssl_root_cert(root_cert);
+            }
+
+            if let Some((cert, key)) = client_identity(tls) {
+                options = options.ssl_client_cert(cert).ssl_client_key(key);
+            }
+
+            *pg = options;
+        } else if let Some(mysql) = connect_options.as_mysql_mut() {
+            let mut options = mysql.clone().ssl_mode(if tls.accept_invalid_certs {
+                MySqlSslMode::Required
+            } else {
+                MySqlSslMode::VerifyIdentity
+            });
+
+            if let Some(root_cert) = tls.root_cert.as_deref() {
+                options = options.ssl_ca(root_cert);
+            }
+
+            if let Some((cert, key)) = client_identity(tls) {
+                options = options.ssl_client_cert(cert).ssl_client_key(key);
+            }
+
+            *mysql = options;
+        }
+
+        Ok(connect_options)
     }
 
     fn row_to_cnac(
@@ -890,11 +1932,47 @@ impl<R: Ratchet, Fcm: Ratchet> SqlBackend<R, Fcm> {
         }
     }
 
+    fn row_to_metadata(&self, row: &AnyRow) -> Option<CNACMetadata> {
+        let cid = row.try_get::<String, _>("cid").ok()?;
+        let cid = u64::from_str(cid.as_str()).ok()?;
+        let is_personal = self.get_bool(row, "is_personal").ok()?;
+        let username = row.try_get("username").ok()?;
+        let full_name = row.try_get("full_name").ok()?;
+        let creation_date = row.try_get("creation_date").ok()?;
+        let is_active = self.get_bool(row, "active").ok()?;
+        Some(CNACMetadata {
+            cid,
+            is_personal,
+            username,
+            full_name,
+            creation_date,
+            is_active,
+        })
+    }
+
     fn get_bool(&self, row: &AnyRow, key: &str) -> Result<bool, AccountError> {
         Ok(row.try_get(key)?)
     }
 }
 
+fn unix_millis_now() -> i64 {
+    unix_millis(SystemTime::now())
+}
+
+fn unix_millis(time: SystemTime) -> i64 {
+    time.duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+fn from_unix_millis(millis: i64) -> SystemTime {
+    std::time::UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64)
+}
+
+fn client_identity(tls: &SqlTlsConfig) -> Option<(&Path, &Path)> {
+    Some((tls.client_cert.as_deref()?, tls.client_key.as_deref()?))
+}
+
 impl<R: Ratchet, Fcm: Ratchet> TryFrom<BackendType> for SqlBackend<R, Fcm> {
     type Error = ();
 
@@ -905,6 +1983,8 @@ impl<R: Ratchet, Fcm: Ratchet> TryFrom<BackendType> for SqlBackend<R, Fcm> {
             BackendType::SQLDatabase(url, opts) => Ok(Self {
                 url,
                 conn: None,
+                replicas: Vec::new(),
+                next_replica: AtomicUsize::new(0),
                 variant,
                 opts,
                 _pd: Default::default(),
@@ -1,20 +1,31 @@
 use super::utils::StreamableTargetInformation;
 use crate::backend::memory::no_backend_streaming;
 use crate::backend::utils::ObjectTransferStatus;
-use crate::backend::BackendConnection;
+use crate::backend::{
+    check_username_change, connect_with_backoff, is_internal_byte_map_key, normalize_username,
+    BackendConnection, DEFAULT_CONNECT_BACKOFF, DEFAULT_CONNECT_RETRIES, USERNAME_INDEX_VERSION,
+};
 use crate::client_account::{ClientNetworkAccount, MutualPeer};
-use crate::misc::{AccountError, CNACMetadata};
+use crate::misc::{
+    AccountError, ByteMapChange, ByteMapChangeKind, ByteMapEntry, CNACMetadata, ClientSummary,
+    CredentialPolicy,
+};
+use crate::peer_list::PeerListDelta;
 use crate::prelude::{ClientNetworkAccountInner, HYPERLAN_IDX};
 use crate::serialization::SyncIO;
+use chrono::{DateTime, Utc};
 use citadel_crypt::stacked_ratchet::Ratchet;
+use futures::stream::BoxStream;
+use futures::StreamExt;
 use mobc::async_trait;
 use mobc::Manager;
 use mobc::Pool;
 use redis_base::{AsyncCommands, Client, ErrorKind, FromRedisValue, ToRedisArgs};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::marker::PhantomData;
+use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
 /// Backend struct for redis
@@ -26,20 +37,21 @@ pub(crate) struct RedisBackend<R: Ratchet, Fcm: Ratchet> {
 }
 
 type RedisPool = Pool<RedisConnectionManager>;
+type PooledConnection = mobc::Connection<RedisConnectionManager>;
 
 #[derive(Debug, Default, Clone, Eq, PartialEq)]
 /// For setting custom options for the internal redis connection pool
 pub struct RedisConnectionOptions {
-    /// Sets the number of connections. Default 10
+    /// Sets the number of connections. Default 10. Superseded by `max_pool_size`
     pub max_open: Option<u64>,
-    /// Sets the max idle time per connection
+    /// Sets the maximum number of idle connections kept in the pool
     pub max_idle: Option<u64>,
     /// Sets the max lifetime per connection
     pub max_lifetime: Option<Duration>,
     /// Sets the maximum lifetime of connection to be idle in the pool,
     /// resetting the timer when connection is used.
     pub max_idle_lifetime: Option<Duration>,
-    /// Sets the get timeout used by the inner pool
+    /// Sets the get timeout used by the inner pool. Superseded by `connection_timeout`
     pub get_timeout: Option<Duration>,
     /// Sets the interval how often a connection will be checked when returning
     /// an existing connection from the pool. If set to None, a connection is
@@ -51,6 +63,23 @@ pub struct RedisConnectionOptions {
     pub health_check: Option<bool>,
     /// When enabled, will use the clustering algorithms for redis
     pub clustering_support: bool,
+    /// The number of times to retry the initial connection before failing. Default 3
+    pub connect_retries: Option<u32>,
+    /// The delay before the first connect retry, doubling after each failed attempt. Default 500ms
+    pub connect_backoff: Option<Duration>,
+    /// When enabled, every byte map value stored or removed is published to the other nodes
+    /// sharing the redis server, and may be subscribed to via
+    /// [`BackendConnection::subscribe_byte_map_changes`]
+    pub enable_pubsub: bool,
+    /// The most connections the pool opens at once. Operations beyond this wait for a connection
+    /// to be returned to the pool, for up to `connection_timeout`. Default 10
+    pub max_pool_size: Option<u64>,
+    /// The number of connections opened upon connecting, which are then kept idle in the pool.
+    /// May not exceed `max_pool_size`. If `max_idle` is unset, it is set to this value
+    pub min_idle: Option<u64>,
+    /// How long an operation waits for a connection from the pool, including the time taken to
+    /// open a new one, before failing with [`AccountError::BackendUnavailable`]. Default 30s
+    pub connection_timeout: Option<Duration>,
 }
 
 struct RedisConnectionManager {
@@ -79,86 +108,173 @@ impl Manager for RedisConnectionManager {
 #[async_trait]
 impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm> {
     async fn connect(&mut self) -> Result<(), AccountError> {
-        let client = redis_base::Client::open(self.url.as_str())
-            .map_err(|err| AccountError::msg(err.to_string()))?;
+        let client = redis_base::Client::open(self.url.as_str()).map_err(AccountError::from)?;
 
         let manager = RedisConnectionManager { client };
         let mut builder = Pool::builder();
 
-        if let (Some(max_open), Some(max_idle)) = (
-            self.conn_options.max_open.as_ref(),
-            self.conn_options.max_idle.as_ref(),
-        ) {
-            if *max_open >= *max_idle {
+        let opts = &self.conn_options;
+        let max_pool_size = opts.max_pool_size.or(opts.max_open);
+        let min_idle = opts.min_idle.unwrap_or(0);
+        let max_idle = opts
+            .max_idle
+            .or_else(|| opts.min_idle.filter(|min_idle| *min_idle > 0));
+
+        // the pool would otherwise panic, or wait forever for connections it may never open
+        if max_pool_size == Some(0) {
+            return Err(AccountError::msg("max_pool_size must be greater than zero"));
+        }
+
+        if let Some(max_pool_size) = max_pool_size {
+            if min_idle > max_pool_size {
+                return Err(AccountError::msg(
+                    "min_idle must be less than or equal to max_pool_size",
+                ));
+            }
+
+            if max_idle.map_or(false, |max_idle| max_idle > max_pool_size) {
                 return Err(AccountError::msg(
-                    "Max open must be greater than or equal to max_idle",
+                    "max_idle must be less than or equal to max_pool_size",
                 ));
             }
         }
 
-        if let Some(val) = self.conn_options.max_open.as_ref() {
-            builder = builder.max_open(*val);
+        if max_idle.map_or(false, |max_idle| min_idle > max_idle) {
+            return Err(AccountError::msg(
+                "min_idle must be less than or equal to max_idle",
+            ));
         }
 
-        if let Some(val) = self.conn_options.max_idle.as_ref() {
-            builder = builder.max_idle(*val);
+        if let Some(val) = max_pool_size {
+            builder = builder.max_open(val);
         }
 
-        if let Some(val) = self.conn_options.max_idle_lifetime.as_ref() {
-            builder = builder.max_idle_lifetime(Some(*val));
+        if let Some(val) = max_idle {
+            builder = builder.max_idle(val);
         }
 
-        if let Some(val) = self.conn_options.get_timeout.as_ref() {
-            builder = builder.get_timeout(Some(*val));
+        if let Some(val) = opts.max_lifetime {
+            builder = builder.max_lifetime(Some(val));
         }
 
-        if let Some(val) = self.conn_options.health_check_interval.as_ref() {
-            builder = builder.health_check_interval(Some(*val))
+        if let Some(val) = opts.max_idle_lifetime {
+            builder = builder.max_idle_lifetime(Some(val));
         }
 
-        if let Some(val) = self.conn_options.health_check.as_ref() {
-            builder = builder.test_on_check_out(*val);
+        if let Some(val) = opts.connection_timeout.or(opts.get_timeout) {
+            builder = builder.get_timeout(Some(val));
         }
 
-        let pool = builder.build(manager); // panics if max_idle > max_size
+        if let Some(val) = opts.health_check_interval {
+            builder = builder.health_check_interval(Some(val))
+        }
+
+        if let Some(val) = opts.health_check {
+            builder = builder.test_on_check_out(val);
+        }
+
+        let pool = builder.build(manager);
 
         self.conn = Some(pool);
 
         // ensure that we can establish a connection
-        let _ = self.get_conn().await?;
+        let _ = connect_with_backoff(
+            "redis",
+            self.conn_options
+                .connect_retries
+                .unwrap_or(DEFAULT_CONNECT_RETRIES),
+            self.conn_options
+                .connect_backoff
+                .unwrap_or(DEFAULT_CONNECT_BACKOFF),
+            || self.get_conn(),
+        )
+        .await?;
 
-        Ok(())
+        // the connections return to the pool once dropped, where they are kept idle
+        let _idle = futures::future::try_join_all((0..min_idle).map(|_| self.get_conn())).await?;
+
+        self.backfill_username_index().await?;
+        self.backfill_active_impersonals().await
     }
 
     async fn is_connected(&self) -> Result<bool, AccountError> {
         self.get_conn().await.map(|_| true)
     }
 
+    // idle connections are closed immediately, and in-use connections once they are returned
+    async fn disconnect(&self) -> Result<(), AccountError> {
+        if let Some(pool) = self.conn.as_ref() {
+            pool.set_max_idle_conns(0).await;
+        }
+
+        Ok(())
+    }
+
     async fn save_cnac(&self, cnac: &ClientNetworkAccount<R, Fcm>) -> Result<(), AccountError> {
         let bytes = cnac.generate_proper_bytes()?;
-        let key = get_cid_to_cnac_key();
+        let cid = cnac.get_cid();
         let username = cnac.get_username();
         let mut conn = self.get_conn().await?;
-        let is_personals_key = if cnac.is_personal() {
+        let is_personal = cnac.is_personal();
+        let is_personals_key = if is_personal {
             get_personal_status_key()
         } else {
             get_impersonal_status_key()
         };
 
-        redis_base::pipe()
-            .atomic()
-            // username points to cid key
-            .set(get_username_key(&username), cnac.get_cid())
-            .ignore()
-            // cid key points to bytes
-            .hset(key, cnac.get_cid(), bytes)
-            .ignore()
-            .set(get_cid_to_username_key(cnac.get_cid()), &username)
-            .ignore()
-            .sadd(is_personals_key, cnac.get_cid())
-            .query_async(&mut conn)
-            .await
-            .map_err(|err| AccountError::msg(err.to_string()))
+        let previous_username: Option<String> = self
+            .get_with(get_cid_to_username_key(cid), &mut conn)
+            .await?;
+        // scripts execute atomically, so the username is claimed only if no other client holds
+        // it. A client keeping its username may save, even if it is a duplicate held by another
+        let holder_cid: Option<u64> = redis_base::Script::new(
+            r"
+            local holder = redis.call('hget', KEYS[4], ARGV[3])
+            if holder and holder ~= ARGV[1] and ARGV[3] ~= ARGV[5] then
+                return holder
+            end
+            if ARGV[3] ~= ARGV[5] and redis.call('hget', KEYS[4], ARGV[5]) == ARGV[1] then
+                redis.call('hdel', KEYS[4], ARGV[5])
+            end
+            if not holder then
+                redis.call('hset', KEYS[4], ARGV[3], ARGV[1])
+            end
+            redis.call('hset', KEYS[1], ARGV[1], ARGV[4])
+            redis.call('set', KEYS[2], ARGV[2])
+            redis.call('sadd', KEYS[3], ARGV[1])
+            if ARGV[6] == '0' and redis.call('hexists', KEYS[5], ARGV[1]) == 0 then
+                redis.call('zadd', KEYS[6], ARGV[7], ARGV[8])
+            end
+            return false
+        ",
+        )
+        .key(get_cid_to_cnac_key()) // 1
+        .key(get_cid_to_username_key(cid)) // 2
+        .key(is_personals_key) // 3
+        .key(get_usernames_key()) // 4
+        .key(get_deactivated_key()) // 5
+        .key(get_active_impersonals_key()) // 6
+        .arg(cid)
+        .arg(&username)
+        .arg(normalize_username(&username))
+        .arg(bytes)
+        .arg(
+            previous_username
+                .as_deref()
+                .map(normalize_username)
+                .unwrap_or_default(),
+        )
+        .arg(is_personal as u8)
+        .arg(active_impersonal_score(cid))
+        .arg(active_impersonal_member(cid))
+        .invoke_async(&mut *conn)
+        .await
+        .map_err(AccountError::from)?;
+
+        match holder_cid {
+            Some(holder_cid) => Err(AccountError::ClientExists(holder_cid)),
+            None => Ok(()),
+        }
     }
 
     async fn get_cnac_by_cid(
@@ -173,44 +289,98 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm
             .await?
             .hexists(get_cid_to_cnac_key(), cid)
             .await
-            .map_err(|err| AccountError::msg(err.to_string()))
+            .map_err(AccountError::from)
+    }
+
+    async fn is_personal(&self, cid: u64) -> Result<Option<bool>, AccountError> {
+        // the status sets are kept by save_cnac and delete_cnac_by_cid, so the CNAC is not fetched
+        let mut conn = self.get_conn().await?;
+        let (personal, impersonal): (bool, bool) = redis_base::pipe()
+            .sismember(get_personal_status_key(), cid)
+            .sismember(get_impersonal_status_key(), cid)
+            .query_async(&mut *conn)
+            .await
+            .map_err(AccountError::from)?;
+
+        Ok(match (personal, impersonal) {
+            (true, _) => Some(true),
+            (false, true) => Some(false),
+            (false, false) => None,
+        })
     }
 
     async fn delete_cnac_by_cid(&self, cid: u64) -> Result<(), AccountError> {
         // TODO: delete bytemap entries
         let mut conn = self.get_conn().await?;
-        redis_base::Script::new(&format!(
+        let username: Option<String> = self
+            .get_with(get_cid_to_username_key(cid), &mut conn)
+            .await?;
+        // the keys of each peer are passed in pairs after the fixed keys, and the peers' cids after
+        // the fixed args. Nothing is written if the peers changed since they were fetched
+        let script = redis_base::Script::new(&format!(
             r"
-            local username = redis.call('get', KEYS[4])
-            local username_key = '{LOCAL_USERNAME_PREFIX}.' .. username
-            local peer_cids = redis.call('hvals', KEYS[3])
-            redis.call('del', username_key)
-            redis.call('del', KEYS[7])
-            redis.call('hdel', KEYS[2], KEYS[1])
+            {MATCHES_ARGV_LUA}
+            if not matches_argv(redis.call('hvals', KEYS[2]), 4) then
+                return 0
+            end
+            local username = redis.call('get', KEYS[3])
+            if redis.call('hget', KEYS[9], ARGV[2]) == ARGV[1] then
+                redis.call('hdel', KEYS[9], ARGV[2])
+            end
+            redis.call('del', KEYS[6])
+            redis.call('hdel', KEYS[1], ARGV[1])
+            redis.call('del', KEYS[2])
             redis.call('del', KEYS[3])
-            redis.call('del', KEYS[4])
-            redis.call('srem', KEYS[5], KEYS[1])
-            redis.call('srem', KEYS[6], KEYS[1])
+            redis.call('srem', KEYS[4], ARGV[1])
+            redis.call('srem', KEYS[5], ARGV[1])
+            redis.call('hdel', KEYS[7], ARGV[1])
+            redis.call('hdel', KEYS[8], ARGV[1])
+            redis.call('zrem', KEYS[10], ARGV[3])
 
-            for _,peer_cid in ipairs(peer_cids)
+            for idx = 4, #ARGV
             do
-                local hkey_cid = '{PEER_CID_PREFIX}.' .. peer_cid
-                local hkey_username = '{PEER_USERNAME_PREFIX}.' .. peer_cid
-                redis.call('hdel', hkey_cid, username)
-                redis.call('hdel', hkey_username, KEYS[1])
+                local peer = 2 * (idx - 4) + 11
+                if username then
+                    redis.call('hdel', KEYS[peer], username)
+                end
+                redis.call('hdel', KEYS[peer + 1], ARGV[1])
             end
+            return 1
         ",
-        ))
-        .key(cid) // 1
-        .key(get_cid_to_cnac_key()) // 2
-        .key(get_peer_cid_key(cid)) // 3
-        .key(get_cid_to_username_key(cid)) // 4
-        .key(get_personal_status_key()) // 5
-        .key(get_impersonal_status_key()) // 6
-        .key(get_peer_username_key(cid)) // 7
-        .invoke_async(&mut conn)
-        .await
-        .map_err(|err| AccountError::msg(err.to_string()))
+        ));
+
+        loop {
+            let peer_cids = get_peer_cids(&mut conn, cid).await?;
+            let mut invocation = script.prepare_invoke();
+            let _ = invocation
+                .key(get_cid_to_cnac_key()) // 1
+                .key(get_peer_cid_key(cid)) // 2
+                .key(get_cid_to_username_key(cid)) // 3
+                .key(get_personal_status_key()) // 4
+                .key(get_impersonal_status_key()) // 5
+                .key(get_peer_username_key(cid)) // 6
+                .key(get_deactivated_key()) // 7
+                .key(get_last_connect_key()) // 8
+                .key(get_usernames_key()) // 9
+                .key(get_active_impersonals_key()) // 10
+                .arg(cid)
+                .arg(
+                    username
+                        .as_deref()
+                        .map(normalize_username)
+                        .unwrap_or_default(),
+                )
+                .arg(active_impersonal_member(cid));
+            add_peer_keys(&mut invocation, &peer_cids);
+
+            let applied: bool = invocation
+                .invoke_async(&mut *conn)
+                .await
+                .map_err(AccountError::from)?;
+            if applied {
+                return Ok(());
+            }
+        }
     }
 
     async fn purge(&self) -> Result<usize, AccountError> {
@@ -220,52 +390,231 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm
             .cmd("DBSIZE") // get the count that will be affected
             .cmd("FLUSHDB")
             .ignore()
-            .query_async(&mut conn)
+            .query_async(&mut *conn)
             .await
             .map(|ret: Vec<usize>| ret[0])
-            .map_err(|err| AccountError::msg(err.to_string()))
+            .map_err(AccountError::from)
+    }
+
+    async fn deactivate_cnac(&self, cid: u64) -> Result<(), AccountError> {
+        let mut conn = self.get_conn().await?;
+        // the original deactivation time is kept if the client is already deactivated
+        let exists: bool = redis_base::Script::new(
+            r"
+            if redis.call('hexists', KEYS[1], ARGV[1]) == 0 then
+                return 0
+            end
+            redis.call('hsetnx', KEYS[2], ARGV[1], ARGV[2])
+            redis.call('zrem', KEYS[3], ARGV[3])
+            return 1
+        ",
+        )
+        .key(get_cid_to_cnac_key())
+        .key(get_deactivated_key())
+        .key(get_active_impersonals_key())
+        .arg(cid)
+        .arg(unix_millis(SystemTime::now()))
+        .arg(active_impersonal_member(cid))
+        .invoke_async(&mut *conn)
+        .await
+        .map_err(AccountError::from)?;
+
+        if exists {
+            Ok(())
+        } else {
+            Err(AccountError::ClientNonExists(cid))
+        }
+    }
+
+    async fn reactivate_cnac(&self, cid: u64) -> Result<(), AccountError> {
+        let mut conn = self.get_conn().await?;
+        let exists: bool = redis_base::Script::new(
+            r"
+            if redis.call('hexists', KEYS[1], ARGV[1]) == 0 then
+                return 0
+            end
+            redis.call('hdel', KEYS[2], ARGV[1])
+            if redis.call('sismember', KEYS[3], ARGV[1]) == 1 then
+                redis.call('zadd', KEYS[4], ARGV[2], ARGV[3])
+            end
+            return 1
+        ",
+        )
+        .key(get_cid_to_cnac_key())
+        .key(get_deactivated_key())
+        .key(get_impersonal_status_key())
+        .key(get_active_impersonals_key())
+        .arg(cid)
+        .arg(active_impersonal_score(cid))
+        .arg(active_impersonal_member(cid))
+        .invoke_async(&mut *conn)
+        .await
+        .map_err(AccountError::from)?;
+
+        if exists {
+            Ok(())
+        } else {
+            Err(AccountError::ClientNonExists(cid))
+        }
+    }
+
+    async fn purge_deactivated_before(&self, cutoff: SystemTime) -> Result<usize, AccountError> {
+        let cutoff = unix_millis(cutoff);
+        let deactivated: HashMap<u64, u64> = self
+            .get_conn()
+            .await?
+            .hgetall(get_deactivated_key())
+            .await
+            .map_err(AccountError::from)?;
+        let expired = deactivated
+            .into_iter()
+            .filter(|(_, deactivated_at)| *deactivated_at < cutoff)
+            .map(|(cid, _)| cid)
+            .collect::<Vec<u64>>();
+
+        for cid in &expired {
+            self.delete_cnac_by_cid(*cid).await?;
+        }
+
+        Ok(expired.len())
     }
 
     async fn get_registered_impersonal_cids(
         &self,
-        _limit: Option<i32>,
+        limit: Option<i32>,
     ) -> Result<Option<Vec<u64>>, AccountError> {
-        // TODO: include limit
+        let cids = self
+            .get_active_impersonals(0, limit.map(|limit| limit as i64).unwrap_or(-1))
+            .await?;
+        Ok(if cids.is_empty() { None } else { Some(cids) })
+    }
+
+    async fn get_registered_impersonal_cids_paged(
+        &self,
+        offset: u64,
+        limit: u32,
+    ) -> Result<Vec<u64>, AccountError> {
+        self.get_active_impersonals(offset, limit as i64).await
+    }
+
+    async fn get_username_by_cid(&self, cid: u64) -> Result<Option<String>, AccountError> {
+        self.get(get_cid_to_username_key(cid)).await
+    }
+
+    async fn get_cid_by_stored_username(
+        &self,
+        username: &str,
+    ) -> Result<Option<u64>, AccountError> {
         self.get_conn()
             .await?
-            .smembers(get_impersonal_status_key())
+            .hget(get_usernames_key(), normalize_username(username))
             .await
-            .map(|r: Vec<u64>| if r.is_empty() { None } else { Some(r) })
-            .map_err(|err| AccountError::msg(err.to_string()))
+            .map_err(AccountError::from)
     }
 
-    async fn get_username_by_cid(&self, cid: u64) -> Result<Option<String>, AccountError> {
-        self.get(get_cid_to_username_key(cid)).await
+    async fn change_username(
+        &self,
+        cid: u64,
+        new_username: &str,
+        credential_policy: &CredentialPolicy,
+    ) -> Result<(), AccountError> {
+        let cnac = check_username_change(self, cid, new_username, credential_policy).await?;
+        let previous_username = cnac.get_username();
+        cnac.set_username(new_username);
+        let bytes = cnac.generate_proper_bytes()?;
+        let mut conn = self.get_conn().await?;
+        // scripts execute atomically, so the client and the entry for it in each peer's list are
+        // renamed together, unless another client took the username since it was checked. The
+        // keys of each peer are passed in pairs after the fixed keys, and the peers' cids after
+        // the fixed args. Nothing is written if the peers changed since they were fetched
+        let script = redis_base::Script::new(&format!(
+            r"
+            {MATCHES_ARGV_LUA}
+            if not matches_argv(redis.call('hvals', KEYS[3]), 6) then
+                return false
+            end
+            local holder = redis.call('hget', KEYS[4], ARGV[4])
+            if holder and holder ~= ARGV[1] then
+                return {{holder}}
+            end
+            local previous_username = redis.call('get', KEYS[2])
+            if redis.call('hget', KEYS[4], ARGV[5]) == ARGV[1] then
+                redis.call('hdel', KEYS[4], ARGV[5])
+            end
+            redis.call('hset', KEYS[4], ARGV[4], ARGV[1])
+            redis.call('set', KEYS[2], ARGV[2])
+            redis.call('hset', KEYS[1], ARGV[1], ARGV[3])
+
+            for idx = 6, #ARGV
+            do
+                local peer = 2 * (idx - 6) + 5
+                if previous_username then
+                    redis.call('hdel', KEYS[peer], previous_username)
+                end
+                redis.call('hset', KEYS[peer], ARGV[2], ARGV[1])
+                redis.call('hset', KEYS[peer + 1], ARGV[1], ARGV[2])
+            end
+            return {{}}
+        ",
+        ));
+
+        loop {
+            let peer_cids = get_peer_cids(&mut conn, cid).await?;
+            let mut invocation = script.prepare_invoke();
+            let _ = invocation
+                .key(get_cid_to_cnac_key()) // 1
+                .key(get_cid_to_username_key(cid)) // 2
+                .key(get_peer_cid_key(cid)) // 3
+                .key(get_usernames_key()) // 4
+                .arg(cid)
+                .arg(new_username)
+                .arg(&bytes)
+                .arg(normalize_username(new_username))
+                .arg(normalize_username(&previous_username));
+            add_peer_keys(&mut invocation, &peer_cids);
+
+            // None if the peers changed, otherwise the holder of the username, if not this client
+            let holder_cid: Option<Vec<u64>> = invocation
+                .invoke_async(&mut *conn)
+                .await
+                .map_err(AccountError::from)?;
+
+            match holder_cid.as_deref() {
+                None => continue,
+                Some([holder_cid, ..]) => return Err(AccountError::ClientExists(*holder_cid)),
+                Some([]) => return Ok(()),
+            }
+        }
     }
 
     async fn register_p2p_as_server(&self, cid0: u64, cid1: u64) -> Result<(), AccountError> {
         let mut conn = self.get_conn().await?;
+        // redis does not roll back a script that fails partway, so every value the writes depend
+        // on is checked before the first write. Both directions are then written together
         redis_base::Script::new(
             r"
-            local username1 = redis.call('get', KEYS[3])
-            local username2 = redis.call('get', KEYS[4])
-            redis.call('hset', KEYS[7], KEYS[2], username2)
-            redis.call('hset', KEYS[8], KEYS[1], username1)
-            redis.call('hset', KEYS[5], username2, KEYS[2])
-            redis.call('hset', KEYS[6], username1, KEYS[1])
+            local username1 = redis.call('get', KEYS[1])
+            local username2 = redis.call('get', KEYS[2])
+            if not username1 or not username2 then
+                return redis.error_reply('Both clients must be registered')
+            end
+            redis.call('hset', KEYS[5], ARGV[2], username2)
+            redis.call('hset', KEYS[6], ARGV[1], username1)
+            redis.call('hset', KEYS[3], username2, ARGV[2])
+            redis.call('hset', KEYS[4], username1, ARGV[1])
         ",
         )
-        .key(cid0) // 1
-        .key(cid1) // 2
-        .key(get_cid_to_username_key(cid0)) // 3
-        .key(get_cid_to_username_key(cid1)) // 4
-        .key(get_peer_cid_key(cid0)) // 5
-        .key(get_peer_cid_key(cid1)) // 6
-        .key(get_peer_username_key(cid0)) // 7
-        .key(get_peer_username_key(cid1)) // 8
-        .invoke_async(&mut conn)
+        .key(get_cid_to_username_key(cid0)) // 1
+        .key(get_cid_to_username_key(cid1)) // 2
+        .key(get_peer_cid_key(cid0)) // 3
+        .key(get_peer_cid_key(cid1)) // 4
+        .key(get_peer_username_key(cid0)) // 5
+        .key(get_peer_username_key(cid1)) // 6
+        .arg(cid0)
+        .arg(cid1)
+        .invoke_async(&mut *conn)
         .await
-        .map_err(|err| AccountError::msg(err.to_string()))
+        .map_err(AccountError::from)
     }
 
     async fn register_p2p_as_client(
@@ -285,35 +634,40 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm
             .ignore()
             .hset(get_peer_cid_key(implicated_cid), &peer_username, peer_cid)
             .ignore()
-            .query_async(&mut conn)
+            .query_async(&mut *conn)
             .await
-            .map_err(|err| AccountError::msg(err.to_string()))
+            .map_err(AccountError::from)
     }
 
     async fn deregister_p2p_as_server(&self, cid0: u64, cid1: u64) -> Result<(), AccountError> {
         let mut conn = self.get_conn().await?;
         // TODO: delete bytemap entries for p2p
+        // redis does not roll back a script that fails partway, so every value the writes depend
+        // on is checked before the first write. Both directions are then removed together
         redis_base::Script::new(
             r"
-            local peer_username1 = redis.call('get', KEYS[5])
-            local peer_username2 = redis.call('get', KEYS[6])
-            redis.call('hdel', KEYS[3], peer_username2)
-            redis.call('hdel', KEYS[4], peer_username1)
-            redis.call('hdel', KEYS[7], KEYS[2])
-            redis.call('hdel', KEYS[8], KEYS[1])
+            local peer_username1 = redis.call('get', KEYS[3])
+            local peer_username2 = redis.call('get', KEYS[4])
+            if not peer_username1 or not peer_username2 then
+                return redis.error_reply('Both clients must be registered')
+            end
+            redis.call('hdel', KEYS[1], peer_username2)
+            redis.call('hdel', KEYS[2], peer_username1)
+            redis.call('hdel', KEYS[5], ARGV[2])
+            redis.call('hdel', KEYS[6], ARGV[1])
         ",
         )
-        .key(cid0) // 1
-        .key(cid1) // 2
-        .key(get_peer_cid_key(cid0)) // 3
-        .key(get_peer_cid_key(cid1)) // 4
-        .key(get_cid_to_username_key(cid0)) // 5
-        .key(get_cid_to_username_key(cid1)) // 6
-        .key(get_peer_username_key(cid0)) // 7
-        .key(get_peer_username_key(cid1)) // 8
-        .invoke_async(&mut conn)
+        .key(get_peer_cid_key(cid0)) // 1
+        .key(get_peer_cid_key(cid1)) // 2
+        .key(get_cid_to_username_key(cid0)) // 3
+        .key(get_cid_to_username_key(cid1)) // 4
+        .key(get_peer_username_key(cid0)) // 5
+        .key(get_peer_username_key(cid1)) // 6
+        .arg(cid0)
+        .arg(cid1)
+        .invoke_async(&mut *conn)
         .await
-        .map_err(|err| AccountError::msg(err.to_string()))
+        .map_err(AccountError::from)
     }
 
     async fn deregister_p2p_as_client(
@@ -324,19 +678,21 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm
         let mut conn = self.get_conn().await?;
         redis_base::Script::new(
             r"
-            local peer_username = redis.call('hget', KEYS[4], KEYS[2])
-            redis.call('hdel', KEYS[3], peer_username)
-            redis.call('hdel', KEYS[4], KEYS[2])
+            local peer_username = redis.call('hget', KEYS[2], ARGV[1])
+            if not peer_username then
+                return false
+            end
+            redis.call('hdel', KEYS[1], peer_username)
+            redis.call('hdel', KEYS[2], ARGV[1])
             return peer_username
         ",
         )
-        .key(implicated_cid) // 1
-        .key(peer_cid) // 2
-        .key(get_peer_cid_key(implicated_cid)) // 3
-        .key(get_peer_username_key(implicated_cid)) // 4
-        .invoke_async(&mut conn)
+        .key(get_peer_cid_key(implicated_cid)) // 1
+        .key(get_peer_username_key(implicated_cid)) // 2
+        .arg(peer_cid)
+        .invoke_async(&mut *conn)
         .await
-        .map_err(|err| AccountError::msg(err.to_string()))
+        .map_err(AccountError::from)
         .map(|peer_username: Option<String>| {
             Some(MutualPeer {
                 parent_icid: HYPERLAN_IDX,
@@ -355,48 +711,165 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm
             .hkeys(get_peer_username_key(implicated_cid))
             .await
             .map(Some)
-            .map_err(|err| AccountError::msg(err.to_string()))
+            .map_err(AccountError::from)
+    }
+
+    async fn get_hyperlan_peer_count(&self, implicated_cid: u64) -> Result<usize, AccountError> {
+        // peers are stored in a hash keyed by peer CID, so HLEN counts them without fetching them
+        self.get_conn()
+            .await?
+            .hlen(get_peer_username_key(implicated_cid))
+            .await
+            .map_err(AccountError::from)
     }
 
     async fn get_client_metadata(
         &self,
         implicated_cid: u64,
     ) -> Result<Option<CNACMetadata>, AccountError> {
-        Ok(self
-            .fetch_cnac(implicated_cid)
-            .await?
-            .map(|r| r.get_metadata()))
+        if let Some(cnac) = self.fetch_cnac(implicated_cid).await? {
+            let mut metadata = cnac.get_metadata();
+            metadata.is_active = !self.is_deactivated(implicated_cid).await?;
+            Ok(Some(metadata))
+        } else {
+            Ok(None)
+        }
     }
 
-    async fn get_clients_metadata(
+    async fn stream_clients_metadata(
         &self,
-        limit: Option<i32>,
-    ) -> Result<Vec<CNACMetadata>, AccountError> {
-        // TODO: hard limit in script query, not post-query
+    ) -> Result<BoxStream<'_, Result<CNACMetadata, AccountError>>, AccountError> {
+        const SCAN_COUNT: usize = 256;
+        let deactivated: Arc<Vec<u64>> = Arc::new(
+            self.get_conn()
+                .await?
+                .hkeys(get_deactivated_key())
+                .await
+                .map_err(AccountError::from)?,
+        );
+        // HSCAN incrementally iterates the CNAC hash, starting and ending at cursor 0
+        Ok(futures::stream::unfold(Some(0u64), move |cursor| {
+            let deactivated = deactivated.clone();
+            async move {
+                let cursor = cursor?;
+                let scan = async {
+                    let mut conn = self.get_conn().await?;
+                    redis_base::cmd("HSCAN")
+                        .arg(get_cid_to_cnac_key())
+                        .arg(cursor)
+                        .arg("COUNT")
+                        .arg(SCAN_COUNT)
+                        .query_async::<_, (u64, Vec<Vec<u8>>)>(&mut *conn)
+                        .await
+                        .map_err(AccountError::from)
+                };
+
+                match scan.await {
+                    Ok((next_cursor, fields_and_values)) => {
+                        let page = fields_and_values
+                            .into_iter()
+                            .skip(1)
+                            .step_by(2)
+                            .map(|bytes| {
+                                self.cnac_bytes_to_cnac(bytes).map(|cnac| {
+                                    let mut metadata = cnac.get_metadata();
+                                    metadata.is_active = !deactivated.contains(&metadata.cid);
+                                    metadata
+                                })
+                            })
+                            .collect::<Vec<_>>();
+                        let next_cursor = (next_cursor != 0).then_some(next_cursor);
+                        Some((page, next_cursor))
+                    }
+
+                    Err(err) => Some((vec![Err(err)], None)),
+                }
+            }
+        })
+        .flat_map(futures::stream::iter)
+        .boxed())
+    }
+
+    async fn get_client_summary(&self, cid: u64) -> Result<Option<ClientSummary>, AccountError> {
         let mut conn = self.get_conn().await?;
-        let result: Vec<Vec<u8>> = redis_base::Script::new(
+        let (cnac_bytes, peer_count, deactivated): (Option<Vec<u8>>, usize, bool) =
+            redis_base::pipe()
+                .hget(get_cid_to_cnac_key(), cid)
+                .hlen(get_peer_username_key(cid))
+                .hexists(get_deactivated_key(), cid)
+                .query_async(&mut *conn)
+                .await
+                .map_err(AccountError::from)?;
+
+        if let Some(bytes) = cnac_bytes {
+            let mut summary = self.cnac_bytes_to_cnac(bytes)?.get_summary();
+            summary.metadata.is_active = !deactivated;
+            Ok(Some(ClientSummary {
+                peer_count,
+                ..summary
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn set_last_connect(&self, cid: u64, time: DateTime<Utc>) -> Result<(), AccountError> {
+        let mut conn = self.get_conn().await?;
+        let exists: bool = redis_base::Script::new(
             r"
-            local vals = redis.call('hvals', KEYS[1])
-            local limit = tonumber(ARGV[1])
-            if limit > 0 and limit > table.getn(vals) then
-                return unpack(vals, 1, limit)
+            if redis.call('hexists', KEYS[1], ARGV[1]) == 0 then
+                return 0
             end
-
-            return vals
+            redis.call('hset', KEYS[2], ARGV[1], ARGV[2])
+            return 1
         ",
         )
-        .arg(limit.unwrap_or(0))
         .key(get_cid_to_cnac_key())
-        .invoke_async(&mut conn)
+        .key(get_last_connect_key())
+        .arg(cid)
+        .arg(unix_millis(time.into()))
+        .invoke_async(&mut *conn)
         .await
-        .map_err(|err| AccountError::msg(err.to_string()))?;
+        .map_err(AccountError::from)?;
+
+        if exists {
+            Ok(())
+        } else {
+            Err(AccountError::ClientNonExists(cid))
+        }
+    }
+
+    async fn get_last_connect(&self, cid: u64) -> Result<Option<DateTime<Utc>>, AccountError> {
+        self.get_conn()
+            .await?
+            .hget::<_, _, Option<u64>>(get_last_connect_key(), cid)
+            .await
+            .map(|millis| millis.map(from_unix_millis))
+            .map_err(AccountError::from)
+    }
 
-        let mut ret = vec![];
-        for bytes in result {
-            ret.push(self.cnac_bytes_to_cnac(bytes)?.get_metadata())
+    async fn get_last_connects(
+        &self,
+        cids: &[u64],
+    ) -> Result<HashMap<u64, DateTime<Utc>>, AccountError> {
+        if cids.is_empty() {
+            return Ok(HashMap::new());
         }
 
-        Ok(ret)
+        let mut conn = self.get_conn().await?;
+        let last_connects: Vec<Option<u64>> = redis_base::cmd("HMGET")
+            .arg(get_last_connect_key())
+            .arg(cids)
+            .query_async(&mut *conn)
+            .await
+            .map_err(AccountError::from)?;
+
+        Ok(cids
+            .iter()
+            .copied()
+            .zip(last_connects)
+            .filter_map(|(cid, millis)| Some((cid, from_unix_millis(millis?))))
+            .collect())
     }
 
     async fn get_hyperlan_peer_by_cid(
@@ -408,7 +881,7 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm
             .await?
             .hget(get_peer_username_key(implicated_cid), peer_cid)
             .await
-            .map_err(|err| AccountError::msg(err.to_string()))
+            .map_err(AccountError::from)
             .map(|peer_username: Option<String>| {
                 Some(MutualPeer {
                     parent_icid: HYPERLAN_IDX,
@@ -427,7 +900,7 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm
             .await?
             .hexists(get_peer_username_key(implicated_cid), peer_cid)
             .await
-            .map_err(|err| AccountError::msg(err.to_string()))
+            .map_err(AccountError::from)
     }
 
     #[allow(unused_results)]
@@ -440,11 +913,9 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm
         let script = redis_base::Script::new(
             r"
             local ret = {}
-            for idx,value in ipairs(KEYS)
+            for _,value in ipairs(ARGV)
             do
-                if idx > 1 then
-                    ret[#ret+1] = redis.call('hexists', KEYS[1], value)
-                end
+                ret[#ret+1] = redis.call('hexists', KEYS[1], value)
             end
 
             return ret
@@ -454,13 +925,13 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm
         let mut script = script.key(get_peer_username_key(implicated_cid));
 
         for peer in peers {
-            script.key(*peer);
+            script.arg(*peer);
         }
 
         script
-            .invoke_async(&mut conn)
+            .invoke_async(&mut *conn)
             .await
-            .map_err(|err| AccountError::msg(err.to_string()))
+            .map_err(AccountError::from)
     }
 
     #[allow(unused_results)]
@@ -473,10 +944,12 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm
         let script = redis_base::Script::new(
             r"
             local ret = {}
-            for idx,value in ipairs(KEYS)
+            for _,value in ipairs(ARGV)
             do
-                if idx > 1 then
-                    ret[#ret+1] = redis.call('hget', KEYS[1], value)
+                local username = redis.call('hget', KEYS[1], value)
+                if username then
+                    ret[#ret+1] = value
+                    ret[#ret+1] = username
                 end
             end
 
@@ -487,23 +960,25 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm
         let mut script = script.key(get_peer_username_key(implicated_cid));
 
         for peer in peers {
-            script.key(*peer);
+            script.arg(*peer);
         }
 
+        // non-mutuals are skipped, so each peer is returned as a (cid, username) pair
         script
-            .invoke_async(&mut conn)
+            .invoke_async(&mut *conn)
             .await
             .map(|ret: Vec<String>| {
-                ret.into_iter()
-                    .zip(peers.iter())
-                    .map(|(username, cid)| MutualPeer {
-                        parent_icid: HYPERLAN_IDX,
-                        cid: *cid,
-                        username: Some(username),
+                ret.chunks_exact(2)
+                    .filter_map(|pair| {
+                        Some(MutualPeer {
+                            parent_icid: HYPERLAN_IDX,
+                            cid: u64::from_str(&pair[0]).ok()?,
+                            username: Some(pair[1].clone()),
+                        })
                     })
                     .collect()
             })
-            .map_err(|err| AccountError::msg(err.to_string()))
+            .map_err(AccountError::from)
     }
 
     async fn get_hyperlan_peer_list_as_server(
@@ -515,7 +990,7 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm
             .await?
             .hgetall(get_peer_username_key(implicated_cid)) // get all (peer_cid, username)
             .await
-            .map_err(|err| AccountError::msg(err.to_string()))?;
+            .map_err(AccountError::from)?;
 
         let mut ret = Vec::with_capacity(usernames_map.len());
         for (cid, username) in usernames_map {
@@ -534,10 +1009,19 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm
         &self,
         cnac: &ClientNetworkAccount<R, Fcm>,
         peers: Vec<MutualPeer>,
-    ) -> Result<(), AccountError> {
+    ) -> Result<PeerListDelta, AccountError> {
+        let implicated_cid = cnac.get_cid();
+        let existing = self
+            .get_hyperlan_peer_list_as_server(implicated_cid)
+            .await?
+            .unwrap_or_default();
+        let delta = PeerListDelta::between(&existing, &peers);
+        if delta.is_empty() {
+            return Ok(delta);
+        }
+
         let mut conn = self.get_conn().await?;
         let mut pipe = redis_base::pipe();
-        let implicated_cid = cnac.get_cid();
         let peer_cid_key = get_peer_cid_key(implicated_cid);
         let peer_username_key = get_peer_username_key(implicated_cid);
 
@@ -547,7 +1031,7 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm
         let _: () = conn
             .del(&[&peer_cid_key, &peer_username_key])
             .await
-            .map_err(|err| AccountError::msg(err.to_string()))?;
+            .map_err(AccountError::from)?;
 
         // now, add back everything fresh
         for MutualPeer { cid, username, .. } in peers {
@@ -558,11 +1042,11 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm
         }
 
         let _: () = pipe
-            .query_async(&mut conn)
+            .query_async(&mut *conn)
             .await
-            .map_err(|err| AccountError::msg(err.to_string()))?;
+            .map_err(AccountError::from)?;
 
-        Ok(())
+        Ok(delta)
     }
 
     async fn get_byte_map_value(
@@ -572,11 +1056,79 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm
         key: &str,
         sub_key: &str,
     ) -> Result<Option<Vec<u8>>, AccountError> {
-        self.get_conn()
-            .await?
-            .hget(get_byte_map_key(implicated_cid, peer_cid, key), sub_key)
+        let mut conn = self.get_conn().await?;
+        redis_base::Script::new(
+            r"
+            local ret = redis.call('get', KEYS[3])
+            if ret then
+                return ret
+            end
+            return redis.call('hget', KEYS[1], ARGV[1])
+        ",
+        )
+        .key(get_byte_map_key(implicated_cid, peer_cid, key))
+        .key(get_byte_map_expiring_index_key(
+            implicated_cid,
+            peer_cid,
+            key,
+        ))
+        .key(get_byte_map_expiring_key(
+            implicated_cid,
+            peer_cid,
+            key,
+            sub_key,
+        ))
+        .arg(sub_key)
+        .invoke_async(&mut *conn)
+        .await
+        .map_err(AccountError::from)
+    }
+
+    async fn get_byte_map_values(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        keys: &[(String, String)],
+    ) -> Result<HashMap<(String, String), Vec<u8>>, AccountError> {
+        if keys.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let mut conn = self.get_conn().await?;
+        // each pair passes its hash followed by its expiring key, and its sub key
+        let script = redis_base::Script::new(
+            r"
+            local ret = {}
+            for idx, sub_key in ipairs(ARGV) do
+                ret[idx] = redis.call('get', KEYS[2 * idx]) or redis.call('hget', KEYS[2 * idx - 1], sub_key)
+            end
+            return ret
+        ",
+        );
+        let mut invocation = script.prepare_invoke();
+        for (key, sub_key) in keys {
+            let _ = invocation
+                .key(get_byte_map_key(implicated_cid, peer_cid, key))
+                .key(get_byte_map_expiring_key(
+                    implicated_cid,
+                    peer_cid,
+                    key,
+                    sub_key,
+                ))
+                .arg(sub_key);
+        }
+
+        let values: Vec<Option<Vec<u8>>> = invocation
+            .invoke_async(&mut *conn)
             .await
-            .map_err(|err| AccountError::msg(err.to_string()))
+            .map_err(AccountError::from)?;
+
+        Ok(keys
+            .iter()
+            .cloned()
+            .zip(values)
+            .filter_map(|(pair, value)| Some((pair, value?)))
+            .collect())
     }
 
     async fn remove_byte_map_value(
@@ -587,19 +1139,45 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm
         sub_key: &str,
     ) -> Result<Option<Vec<u8>>, AccountError> {
         let mut conn = self.get_conn().await?;
-        let key = get_byte_map_key(implicated_cid, peer_cid, key);
-        redis_base::Script::new(
+        let removed: Option<Vec<u8>> = redis_base::Script::new(
             r"
-            local ret = redis.call('hget', KEYS[1], KEYS[2])
-            redis.call('hdel', KEYS[1], KEYS[2])
+            local ret = redis.call('get', KEYS[3]) or redis.call('hget', KEYS[1], ARGV[1])
+            redis.call('del', KEYS[3])
+            redis.call('srem', KEYS[2], ARGV[1])
+            redis.call('hdel', KEYS[1], ARGV[1])
             return ret
         ",
         )
-        .key(key)
-        .key(sub_key)
-        .invoke_async(&mut conn)
+        .key(get_byte_map_key(implicated_cid, peer_cid, key))
+        .key(get_byte_map_expiring_index_key(
+            implicated_cid,
+            peer_cid,
+            key,
+        ))
+        .key(get_byte_map_expiring_key(
+            implicated_cid,
+            peer_cid,
+            key,
+            sub_key,
+        ))
+        .arg(sub_key)
+        .invoke_async(&mut *conn)
         .await
-        .map_err(|err| AccountError::msg(err.to_string()))
+        .map_err(AccountError::from)?;
+
+        if let Some(removed) = removed.clone() {
+            self.publish_byte_map_change(
+                &mut conn,
+                implicated_cid,
+                peer_cid,
+                key,
+                sub_key,
+                ByteMapChangeKind::Removed(removed),
+            )
+            .await?;
+        }
+
+        Ok(removed)
     }
 
     async fn store_byte_map_value(
@@ -611,20 +1189,253 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm
         value: Vec<u8>,
     ) -> Result<Option<Vec<u8>>, AccountError> {
         let mut conn = self.get_conn().await?;
-        let key = get_byte_map_key(implicated_cid, peer_cid, key);
-        redis_base::Script::new(
+        let previous: Option<Vec<u8>> = redis_base::Script::new(
+            r"
+            local ret = redis.call('get', KEYS[3]) or redis.call('hget', KEYS[1], ARGV[1])
+            redis.call('del', KEYS[3])
+            redis.call('srem', KEYS[2], ARGV[1])
+            redis.call('hset', KEYS[1], ARGV[1], ARGV[2])
+            return ret
+        ",
+        )
+        .key(get_byte_map_key(implicated_cid, peer_cid, key))
+        .key(get_byte_map_expiring_index_key(
+            implicated_cid,
+            peer_cid,
+            key,
+        ))
+        .key(get_byte_map_expiring_key(
+            implicated_cid,
+            peer_cid,
+            key,
+            sub_key,
+        ))
+        .arg(sub_key)
+        .arg(&value)
+        .invoke_async(&mut *conn)
+        .await
+        .map_err(AccountError::from)?;
+
+        self.publish_byte_map_change(
+            &mut conn,
+            implicated_cid,
+            peer_cid,
+            key,
+            sub_key,
+            ByteMapChangeKind::Stored(value),
+        )
+        .await?;
+        Ok(previous)
+    }
+
+    async fn store_byte_map_value_with_expiry(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> Result<Option<Vec<u8>>, AccountError> {
+        let mut conn = self.get_conn().await?;
+        // expiring values live in their own keys so that redis can expire them natively
+        let previous: Option<Vec<u8>> = redis_base::Script::new(
             r"
-            local ret = redis.call('hget', KEYS[1], KEYS[2])
-            redis.call('hset', KEYS[1], KEYS[2], ARGV[1])
+            local ret = redis.call('get', KEYS[3]) or redis.call('hget', KEYS[1], ARGV[1])
+            redis.call('hdel', KEYS[1], ARGV[1])
+            redis.call('set', KEYS[3], ARGV[2], 'PX', ARGV[3])
+            redis.call('sadd', KEYS[2], ARGV[1])
             return ret
         ",
         )
-        .key(key)
-        .key(sub_key)
-        .arg(value)
-        .invoke_async(&mut conn)
+        .key(get_byte_map_key(implicated_cid, peer_cid, key))
+        .key(get_byte_map_expiring_index_key(
+            implicated_cid,
+            peer_cid,
+            key,
+        ))
+        .key(get_byte_map_expiring_key(
+            implicated_cid,
+            peer_cid,
+            key,
+            sub_key,
+        ))
+        .arg(sub_key)
+        .arg(&value)
+        .arg(ttl.as_millis().max(1) as u64)
+        .invoke_async(&mut *conn)
         .await
-        .map_err(|err| AccountError::msg(err.to_string()))
+        .map_err(AccountError::from)?;
+
+        self.publish_byte_map_change(
+            &mut conn,
+            implicated_cid,
+            peer_cid,
+            key,
+            sub_key,
+            ByteMapChangeKind::Stored(value),
+        )
+        .await?;
+        Ok(previous)
+    }
+
+    async fn compare_and_swap_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        expected: Option<Vec<u8>>,
+        new: Vec<u8>,
+    ) -> Result<bool, AccountError> {
+        let mut conn = self.get_conn().await?;
+        // scripts run atomically, so no other command can interleave between the compare and swap
+        redis_base::Script::new(
+            r"
+            local cur = redis.call('get', KEYS[3]) or redis.call('hget', KEYS[1], ARGV[1])
+            if ARGV[4] == '1' then
+                if cur ~= ARGV[3] then
+                    return 0
+                end
+            elseif cur then
+                return 0
+            end
+            redis.call('del', KEYS[3])
+            redis.call('srem', KEYS[2], ARGV[1])
+            redis.call('hset', KEYS[1], ARGV[1], ARGV[2])
+            return 1
+        ",
+        )
+        .key(get_byte_map_key(implicated_cid, peer_cid, key))
+        .key(get_byte_map_expiring_index_key(
+            implicated_cid,
+            peer_cid,
+            key,
+        ))
+        .key(get_byte_map_expiring_key(
+            implicated_cid,
+            peer_cid,
+            key,
+            sub_key,
+        ))
+        .arg(sub_key)
+        .arg(new)
+        .arg(expected.as_deref().unwrap_or_default())
+        .arg(if expected.is_some() { "1" } else { "0" })
+        .invoke_async(&mut *conn)
+        .await
+        .map_err(AccountError::from)
+    }
+
+    async fn get_or_insert_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        default: Box<dyn FnOnce() -> Vec<u8> + Send + '_>,
+    ) -> Result<Vec<u8>, AccountError> {
+        if let Some(value) = self
+            .get_byte_map_value(implicated_cid, peer_cid, key, sub_key)
+            .await?
+        {
+            return Ok(value);
+        }
+
+        let default = default();
+        let mut conn = self.get_conn().await?;
+        // the value may have been set since it was read. Scripts run atomically, so the insert
+        // only occurs if it is still absent, otherwise the value that won is returned
+        let current: Option<Vec<u8>> = redis_base::Script::new(
+            r"
+            local cur = redis.call('get', KEYS[3]) or redis.call('hget', KEYS[1], ARGV[1])
+            if cur then
+                return cur
+            end
+            redis.call('srem', KEYS[2], ARGV[1])
+            redis.call('hset', KEYS[1], ARGV[1], ARGV[2])
+            return false
+        ",
+        )
+        .key(get_byte_map_key(implicated_cid, peer_cid, key))
+        .key(get_byte_map_expiring_index_key(
+            implicated_cid,
+            peer_cid,
+            key,
+        ))
+        .key(get_byte_map_expiring_key(
+            implicated_cid,
+            peer_cid,
+            key,
+            sub_key,
+        ))
+        .arg(sub_key)
+        .arg(default.as_slice())
+        .invoke_async(&mut *conn)
+        .await
+        .map_err(AccountError::from)?;
+
+        Ok(current.unwrap_or(default))
+    }
+
+    async fn copy_byte_map(&self, from_cid: u64, to_cid: u64) -> Result<usize, AccountError> {
+        let mut conn = self.get_conn().await?;
+        let persistent_keys =
+            scan_keys(&mut conn, format!("{BYTE_MAP_PREFIX}.{from_cid}.*")).await?;
+        let expiring_index_keys = scan_keys(
+            &mut conn,
+            format!("{BYTE_MAP_EXPIRING_INDEX_PREFIX}.{from_cid}.*"),
+        )
+        .await?;
+        let mut copied = 0;
+
+        for hash_key in persistent_keys {
+            let (peer_cid, key) =
+                parse_byte_map_key(&hash_key, &format!("{BYTE_MAP_PREFIX}.{from_cid}."))?;
+            let values: HashMap<String, Vec<u8>> =
+                conn.hgetall(&hash_key).await.map_err(AccountError::from)?;
+            for (sub_key, value) in values {
+                let _ = self
+                    .store_byte_map_value(to_cid, peer_cid, &key, &sub_key, value)
+                    .await?;
+                copied += 1;
+            }
+        }
+
+        for index_key in expiring_index_keys {
+            let (peer_cid, key) = parse_byte_map_key(
+                &index_key,
+                &format!("{BYTE_MAP_EXPIRING_INDEX_PREFIX}.{from_cid}."),
+            )?;
+            let sub_keys: Vec<String> = conn
+                .smembers(&index_key)
+                .await
+                .map_err(AccountError::from)?;
+            for sub_key in sub_keys {
+                let value_key = get_byte_map_expiring_key(from_cid, peer_cid, &key, &sub_key);
+                let (value, ttl_ms): (Option<Vec<u8>>, i64) = redis_base::pipe()
+                    .get(&value_key)
+                    .pttl(&value_key)
+                    .query_async(&mut *conn)
+                    .await
+                    .map_err(AccountError::from)?;
+                if let (Some(value), true) = (value, ttl_ms > 0) {
+                    let _ = self
+                        .store_byte_map_value_with_expiry(
+                            to_cid,
+                            peer_cid,
+                            &key,
+                            &sub_key,
+                            value,
+                            Duration::from_millis(ttl_ms as u64),
+                        )
+                        .await?;
+                    copied += 1;
+                }
+            }
+        }
+
+        Ok(copied)
     }
 
     async fn get_byte_map_values_by_key(
@@ -633,11 +1444,41 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm
         peer_cid: u64,
         key: &str,
     ) -> Result<HashMap<String, Vec<u8>>, AccountError> {
-        self.get_conn()
-            .await?
-            .hgetall(get_byte_map_key(implicated_cid, peer_cid, key))
-            .await
-            .map_err(|err| AccountError::msg(err.to_string()))
+        let mut conn = self.get_conn().await?;
+        // the expiring key of each sub key follows the fixed keys, and the sub keys are the args.
+        // Nothing is read if the expiring sub keys changed since they were fetched
+        let script = redis_base::Script::new(&format!(
+            r"
+            {MATCHES_ARGV_LUA}
+            if not matches_argv(redis.call('smembers', KEYS[2]), 1) then
+                return false
+            end
+            local ret = redis.call('hgetall', KEYS[1])
+            for idx, sub_key in ipairs(ARGV) do
+                local value = redis.call('get', KEYS[idx + 2])
+                if value then
+                    table.insert(ret, sub_key)
+                    table.insert(ret, value)
+                else
+                    redis.call('srem', KEYS[2], sub_key)
+                end
+            end
+            return ret
+        ",
+        ));
+
+        loop {
+            let invocation =
+                prepare_expiring_invocation(&script, &mut conn, implicated_cid, peer_cid, key)
+                    .await?;
+            let values: Option<HashMap<String, Vec<u8>>> = invocation
+                .invoke_async(&mut *conn)
+                .await
+                .map_err(AccountError::from)?;
+            if let Some(values) = values {
+                return Ok(values);
+            }
+        }
     }
 
     async fn remove_byte_map_values_by_key(
@@ -647,18 +1488,176 @@ impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for RedisBackend<R, Fcm
         key: &str,
     ) -> Result<HashMap<String, Vec<u8>>, AccountError> {
         let mut conn = self.get_conn().await?;
-        let key = get_byte_map_key(implicated_cid, peer_cid, key);
-        redis_base::Script::new(
+        // laid out as in get_byte_map_values_by_key. Nothing is removed if the expiring sub keys
+        // changed since they were fetched
+        let script = redis_base::Script::new(&format!(
             r"
+            {MATCHES_ARGV_LUA}
+            if not matches_argv(redis.call('smembers', KEYS[2]), 1) then
+                return false
+            end
             local ret = redis.call('hgetall', KEYS[1])
-            redis.call('del', KEYS[1])
+            for idx, sub_key in ipairs(ARGV) do
+                local value = redis.call('get', KEYS[idx + 2])
+                if value then
+                    table.insert(ret, sub_key)
+                    table.insert(ret, value)
+                    redis.call('del', KEYS[idx + 2])
+                end
+            end
+            redis.call('del', KEYS[1], KEYS[2])
             return ret
         ",
-        )
-        .key(key)
-        .invoke_async(&mut conn)
-        .await
-        .map_err(|err| AccountError::msg(err.to_string()))
+        ));
+
+        loop {
+            let invocation =
+                prepare_expiring_invocation(&script, &mut conn, implicated_cid, peer_cid, key)
+                    .await?;
+            let values: Option<HashMap<String, Vec<u8>>> = invocation
+                .invoke_async(&mut *conn)
+                .await
+                .map_err(AccountError::from)?;
+            if let Some(values) = values {
+                return Ok(values);
+            }
+        }
+    }
+
+    async fn get_byte_map_entries(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Vec<ByteMapEntry>, AccountError> {
+        let mut conn = self.get_conn().await?;
+        let persistent_prefix = format!("{BYTE_MAP_PREFIX}.{implicated_cid}.{peer_cid}.");
+        let expiring_index_prefix =
+            format!("{BYTE_MAP_EXPIRING_INDEX_PREFIX}.{implicated_cid}.{peer_cid}.");
+        let persistent_keys = scan_keys(&mut conn, format!("{persistent_prefix}*")).await?;
+        let expiring_index_keys = scan_keys(&mut conn, format!("{expiring_index_prefix}*")).await?;
+        let now = SystemTime::now();
+        let mut entries = Vec::new();
+
+        for hash_key in persistent_keys {
+            let key = strip_byte_map_prefix(&hash_key, &persistent_prefix)?;
+            let values: HashMap<String, Vec<u8>> =
+                conn.hgetall(&hash_key).await.map_err(AccountError::from)?;
+            entries.extend(values.into_iter().map(|(sub_key, value)| ByteMapEntry {
+                key: key.clone(),
+                sub_key,
+                value,
+                expires_at: None,
+            }));
+        }
+
+        for index_key in expiring_index_keys {
+            let key = strip_byte_map_prefix(&index_key, &expiring_index_prefix)?;
+            let sub_keys: Vec<String> = conn
+                .smembers(&index_key)
+                .await
+                .map_err(AccountError::from)?;
+            for sub_key in sub_keys {
+                let value_key = get_byte_map_expiring_key(implicated_cid, peer_cid, &key, &sub_key);
+                let (value, ttl_ms): (Option<Vec<u8>>, i64) = redis_base::pipe()
+                    .get(&value_key)
+                    .pttl(&value_key)
+                    .query_async(&mut *conn)
+                    .await
+                    .map_err(AccountError::from)?;
+                if let (Some(value), true) = (value, ttl_ms > 0) {
+                    entries.push(ByteMapEntry {
+                        key: key.clone(),
+                        sub_key,
+                        value,
+                        expires_at: Some(now + Duration::from_millis(ttl_ms as u64)),
+                    });
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    async fn list_byte_map_keys(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Vec<String>, AccountError> {
+        let mut conn = self.get_conn().await?;
+        let persistent_prefix = format!("{BYTE_MAP_PREFIX}.{implicated_cid}.{peer_cid}.");
+        let expiring_index_prefix =
+            format!("{BYTE_MAP_EXPIRING_INDEX_PREFIX}.{implicated_cid}.{peer_cid}.");
+        // redis deletes a hash once its last field is removed, so each remaining hash is non-empty
+        let mut keys = scan_keys(&mut conn, format!("{persistent_prefix}*"))
+            .await?
+            .into_iter()
+            .map(|hash_key| strip_byte_map_prefix(&hash_key, &persistent_prefix))
+            .collect::<Result<BTreeSet<String>, AccountError>>()?;
+
+        // the index outlives the values it lists, so a key only counts if one of them is unexpired
+        for index_key in scan_keys(&mut conn, format!("{expiring_index_prefix}*")).await? {
+            let key = strip_byte_map_prefix(&index_key, &expiring_index_prefix)?;
+            if keys.contains(&key) {
+                continue;
+            }
+
+            let sub_keys: Vec<String> = conn
+                .smembers(&index_key)
+                .await
+                .map_err(AccountError::from)?;
+            let value_keys = sub_keys
+                .iter()
+                .map(|sub_key| get_byte_map_expiring_key(implicated_cid, peer_cid, &key, sub_key))
+                .collect::<Vec<String>>();
+            if value_keys.is_empty() {
+                continue;
+            }
+
+            let live: usize = conn.exists(value_keys).await.map_err(AccountError::from)?;
+            if live > 0 {
+                let _ = keys.insert(key);
+            }
+        }
+
+        Ok(keys
+            .into_iter()
+            .filter(|key| !is_internal_byte_map_key(key))
+            .collect())
+    }
+
+    async fn subscribe_byte_map_changes(
+        &self,
+        key_filter: Option<&str>,
+    ) -> Result<BoxStream<'static, ByteMapChange>, AccountError> {
+        if !self.conn_options.enable_pubsub {
+            return Err(AccountError::msg(
+                "Pubsub is not enabled for this redis backend",
+            ));
+        }
+
+        // a connection in subscriber mode cannot issue other commands, so it is kept out of the pool
+        let client = redis_base::Client::open(self.url.as_str()).map_err(AccountError::from)?;
+        let mut pubsub = client
+            .get_async_connection()
+            .await
+            .map_err(AccountError::from)?
+            .into_pubsub();
+        match key_filter {
+            Some(key) => pubsub.subscribe(get_byte_map_channel(key)).await,
+            None => {
+                pubsub
+                    .psubscribe(format!("{BYTE_MAP_CHANNEL_PREFIX}.*"))
+                    .await
+            }
+        }
+        .map_err(AccountError::from)?;
+
+        Ok(pubsub
+            .into_on_message()
+            .filter_map(|msg| async move {
+                ByteMapChange::deserialize_from_vector(msg.get_payload_bytes()).ok()
+            })
+            .boxed())
     }
 
     async fn stream_object_to_backend(
@@ -693,10 +1692,7 @@ impl<R: Ratchet, Fcm: Ratchet> RedisBackend<R, Fcm> {
         key: K,
         client: &mut redis_base::aio::Connection,
     ) -> Result<Option<RV>, AccountError> {
-        client
-            .get(key)
-            .await
-            .map_err(|err| AccountError::msg(err.to_string()))
+        client.get(key).await.map_err(AccountError::from)
     }
 
     async fn fetch_cnac(
@@ -715,6 +1711,14 @@ impl<R: Ratchet, Fcm: Ratchet> RedisBackend<R, Fcm> {
         }
     }
 
+    async fn is_deactivated(&self, cid: u64) -> Result<bool, AccountError> {
+        self.get_conn()
+            .await?
+            .hexists(get_deactivated_key(), cid)
+            .await
+            .map_err(AccountError::from)
+    }
+
     fn cnac_bytes_to_cnac(
         &self,
         bytes: Vec<u8>,
@@ -724,29 +1728,219 @@ impl<R: Ratchet, Fcm: Ratchet> RedisBackend<R, Fcm> {
         Ok(deserialized.into())
     }
 
-    async fn get_conn(&self) -> Result<redis_base::aio::Connection, AccountError> {
-        Ok(self
-            .conn
+    /// Publishes a change to the byte map, if pubsub is enabled
+    async fn publish_byte_map_change(
+        &self,
+        conn: &mut redis_base::aio::Connection,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        kind: ByteMapChangeKind,
+    ) -> Result<(), AccountError> {
+        if !self.conn_options.enable_pubsub {
+            return Ok(());
+        }
+
+        let change = ByteMapChange {
+            implicated_cid,
+            peer_cid,
+            key: key.to_string(),
+            sub_key: sub_key.to_string(),
+            kind,
+        };
+        conn.publish::<_, _, ()>(get_byte_map_channel(key), change.serialize_to_vector()?)
+            .await
+            .map_err(AccountError::from)
+    }
+
+    /// Returns up to `count` active impersonal cids in ascending order, skipping the first
+    /// `offset`. A negative `count` returns every cid past `offset`
+    async fn get_active_impersonals(
+        &self,
+        offset: u64,
+        count: i64,
+    ) -> Result<Vec<u64>, AccountError> {
+        let mut conn = self.get_conn().await?;
+        redis_base::cmd("ZRANGEBYSCORE")
+            .arg(get_active_impersonals_key())
+            .arg("-inf")
+            .arg("+inf")
+            .arg("LIMIT")
+            .arg(offset)
+            .arg(count)
+            .query_async(&mut *conn)
+            .await
+            .map_err(AccountError::from)
+    }
+
+    /// Builds the sorted set of active impersonal cids from the saved clients, unless it was built
+    /// already, such that clients saved before the set existed are paged as well
+    async fn backfill_active_impersonals(&self) -> Result<(), AccountError> {
+        let mut conn = self.get_conn().await?;
+        let version: Option<u32> = self
+            .get_with(get_active_impersonals_version_key(), &mut conn)
+            .await?;
+        if version == Some(ACTIVE_IMPERSONALS_INDEX_VERSION) {
+            return Ok(());
+        }
+
+        let (cids, deactivated): (Vec<u64>, Vec<u64>) = redis_base::pipe()
+            .smembers(get_impersonal_status_key())
+            .hkeys(get_deactivated_key())
+            .query_async(&mut *conn)
+            .await
+            .map_err(AccountError::from)?;
+
+        let mut pipe = redis_base::pipe();
+        let _ = pipe.atomic().del(get_active_impersonals_key()).ignore();
+        for cid in cids.into_iter().filter(|cid| !deactivated.contains(cid)) {
+            let _ = pipe
+                .cmd("ZADD")
+                .arg(get_active_impersonals_key())
+                .arg(active_impersonal_score(cid))
+                .arg(active_impersonal_member(cid))
+                .ignore();
+        }
+
+        pipe.set(
+            get_active_impersonals_version_key(),
+            ACTIVE_IMPERSONALS_INDEX_VERSION,
+        )
+        .ignore()
+        .query_async(&mut *conn)
+        .await
+        .map_err(AccountError::from)
+    }
+
+    /// Builds the username index from the saved clients, unless it was built already, such that
+    /// clients saved before the index existed are indexed. If several clients hold the same
+    /// username, the client with the lowest CID keeps it
+    async fn backfill_username_index(&self) -> Result<(), AccountError> {
+        let mut conn = self.get_conn().await?;
+        let version: Option<u32> = self
+            .get_with(get_usernames_version_key(), &mut conn)
+            .await?;
+        if version == Some(USERNAME_INDEX_VERSION) {
+            return Ok(());
+        }
+
+        let mut cids: Vec<u64> = conn
+            .hkeys(get_cid_to_cnac_key())
+            .await
+            .map_err(AccountError::from)?;
+        cids.sort_unstable();
+        let usernames: Vec<Option<String>> = if cids.is_empty() {
+            Vec::new()
+        } else {
+            let mut pipe = redis_base::pipe();
+            for cid in &cids {
+                let _ = pipe.get(get_cid_to_username_key(*cid));
+            }
+
+            pipe.query_async(&mut *conn)
+                .await
+                .map_err(AccountError::from)?
+        };
+
+        let mut holders = HashMap::new();
+        let mut pipe = redis_base::pipe();
+        let _ = pipe.atomic().del(get_usernames_key()).ignore();
+        for (cid, username) in cids.into_iter().zip(usernames) {
+            let normalized = match username {
+                Some(username) => normalize_username(&username),
+                None => continue,
+            };
+
+            match holders.get(&normalized) {
+                Some(holder_cid) => {
+                    log::warn!(target: "citadel", "Clients {} and {} hold the same username {}", holder_cid, cid, normalized);
+                }
+
+                None => {
+                    let _ = pipe.hset(get_usernames_key(), &normalized, cid).ignore();
+                    let _ = holders.insert(normalized, cid);
+                }
+            }
+        }
+
+        // the index replaces the key each username was stored under before
+        for key in scan_keys(&mut conn, format!("{LEGACY_USERNAME_PREFIX}.*")).await? {
+            let _ = pipe.del(key).ignore();
+        }
+
+        pipe.set(get_usernames_version_key(), USERNAME_INDEX_VERSION)
+            .ignore()
+            .query_async(&mut *conn)
+            .await
+            .map_err(AccountError::from)
+    }
+
+    /// Checks a connection out of the pool, which is returned to the pool once dropped
+    async fn get_conn(&self) -> Result<PooledConnection, AccountError> {
+        self.conn
             .as_ref()
             .ok_or_else(|| AccountError::msg("Redis client not loaded"))?
             .get()
             .await
-            .map_err(|err| AccountError::msg(err.to_string()))?)
-        .map(|conn| conn.into_inner())
+            .map_err(|err| match err {
+                mobc::Error::Inner(err) => AccountError::from(err),
+                err => AccountError::BackendUnavailable(err.to_string()),
+            })
     }
 }
 
-const LOCAL_USERNAME_PREFIX: &str = "username.local";
+/// The prefix of the keys each username was stored under before the username index existed
+const LEGACY_USERNAME_PREFIX: &str = "username.local";
+const USERNAMES: &str = "usernames.normalized";
+const USERNAMES_VERSION: &str = "usernames.normalized.version";
 const LOCAL_CID_PREFIX: &str = "clients";
 const LOCAL_CID_TO_USERNAME: &str = "cid.to.username.local";
 const PEER_CID_PREFIX: &str = "peers_for.cid";
 const PEER_USERNAME_PREFIX: &str = "peers_for.username";
 const BYTE_MAP_PREFIX: &str = "byte_map";
+const BYTE_MAP_EXPIRING_PREFIX: &str = "byte_map_expiring";
+const BYTE_MAP_EXPIRING_INDEX_PREFIX: &str = "byte_map_expiring_index";
+const BYTE_MAP_CHANNEL_PREFIX: &str = "byte_map_changes";
 const CID_TO_IMPERSONALS: &str = "clients.impersonals";
+const ACTIVE_IMPERSONALS: &str = "clients.impersonals.active";
+const ACTIVE_IMPERSONALS_VERSION: &str = "clients.impersonals.active.version";
+/// Bumped whenever the layout of the sorted set of active impersonal cids changes
+const ACTIVE_IMPERSONALS_INDEX_VERSION: u32 = 1;
 const CID_TO_PERSONALS: &str = "clients.personals";
+const CID_TO_DEACTIVATION_TIME: &str = "clients.deactivated";
+const CID_TO_LAST_CONNECT_TIME: &str = "clients.last_connect";
+
+/// Defines `matches_argv(members, first)`, which returns true if `members` holds exactly the
+/// values of `ARGV[first..]`. Redis requires every key a script touches to be passed through
+/// `KEYS`, so keys derived from stored values are fetched before the script runs. The script
+/// checks the values it was passed against those stored with this function, and is retried if
+/// they changed in between
+const MATCHES_ARGV_LUA: &str = r"
+local function matches_argv(members, first)
+    if #members ~= #ARGV - first + 1 then
+        return false
+    end
+    local expected = {}
+    for idx = first, #ARGV do
+        expected[ARGV[idx]] = true
+    end
+    for _, member in ipairs(members) do
+        if not expected[member] then
+            return false
+        end
+    end
+    return true
+end
+";
+
+/// Maps each [normalized](normalize_username) username to the cid of the client holding it
+fn get_usernames_key() -> &'static str {
+    USERNAMES
+}
 
-fn get_username_key(username: &str) -> String {
-    format!("{LOCAL_USERNAME_PREFIX}.{username}")
+fn get_usernames_version_key() -> &'static str {
+    USERNAMES_VERSION
 }
 
 fn get_cid_to_cnac_key() -> &'static str {
@@ -769,10 +1963,267 @@ fn get_byte_map_key(implicated_cid: u64, peer_cid: u64, key: &str) -> String {
     format!("{BYTE_MAP_PREFIX}.{implicated_cid}.{peer_cid}.{key}",)
 }
 
+/// The channel on which changes to values inside `key` are published
+fn get_byte_map_channel(key: &str) -> String {
+    format!("{BYTE_MAP_CHANNEL_PREFIX}.{key}")
+}
+
+/// Holds a single expiring byte map value. An empty `sub_key` yields the prefix shared by all
+/// expiring values inside `key`
+fn get_byte_map_expiring_key(
+    implicated_cid: u64,
+    peer_cid: u64,
+    key: &str,
+    sub_key: &str,
+) -> String {
+    format!("{BYTE_MAP_EXPIRING_PREFIX}.{implicated_cid}.{peer_cid}.{key}.{sub_key}")
+}
+
+/// Holds the set of sub keys inside `key` whose values expire
+fn get_byte_map_expiring_index_key(implicated_cid: u64, peer_cid: u64, key: &str) -> String {
+    format!("{BYTE_MAP_EXPIRING_INDEX_PREFIX}.{implicated_cid}.{peer_cid}.{key}")
+}
+
+/// Returns the cids of the peers of `cid`, whose keys are passed to the scripts that update them
+async fn get_peer_cids(
+    conn: &mut redis_base::aio::Connection,
+    cid: u64,
+) -> Result<Vec<u64>, AccountError> {
+    conn.hvals(get_peer_cid_key(cid))
+        .await
+        .map_err(AccountError::from)
+}
+
+/// Passes the peer cid and peer username keys of each of `peer_cids` in pairs, followed by the
+/// peer cids as args
+fn add_peer_keys(invocation: &mut redis_base::ScriptInvocation<'_>, peer_cids: &[u64]) {
+    for peer_cid in peer_cids {
+        let _ = invocation
+            .key(get_peer_cid_key(*peer_cid))
+            .key(get_peer_username_key(*peer_cid));
+    }
+
+    for peer_cid in peer_cids {
+        let _ = invocation.arg(*peer_cid);
+    }
+}
+
+/// Prepares `script` with the byte map hash and expiring index of `key`, followed by the expiring
+/// key of each sub key currently in the index. The sub keys are passed as args
+async fn prepare_expiring_invocation<'a>(
+    script: &'a redis_base::Script,
+    conn: &mut redis_base::aio::Connection,
+    implicated_cid: u64,
+    peer_cid: u64,
+    key: &str,
+) -> Result<redis_base::ScriptInvocation<'a>, AccountError> {
+    let index_key = get_byte_map_expiring_index_key(implicated_cid, peer_cid, key);
+    let sub_keys: Vec<String> = conn
+        .smembers(&index_key)
+        .await
+        .map_err(AccountError::from)?;
+    let mut invocation = script.prepare_invoke();
+    let _ = invocation
+        .key(get_byte_map_key(implicated_cid, peer_cid, key))
+        .key(index_key);
+    for sub_key in &sub_keys {
+        let _ = invocation.key(get_byte_map_expiring_key(
+            implicated_cid,
+            peer_cid,
+            key,
+            sub_key,
+        ));
+    }
+
+    for sub_key in &sub_keys {
+        let _ = invocation.arg(sub_key);
+    }
+
+    Ok(invocation)
+}
+
+/// Collects every key matching `pattern`
+async fn scan_keys(
+    conn: &mut redis_base::aio::Connection,
+    pattern: String,
+) -> Result<Vec<String>, AccountError> {
+    let mut iter = conn
+        .scan_match::<_, String>(pattern)
+        .await
+        .map_err(AccountError::from)?;
+    let mut keys = Vec::new();
+    while let Some(key) = iter.next_item().await {
+        keys.push(key);
+    }
+
+    Ok(keys)
+}
+
+/// Extracts the peer cid and key from a byte map key that begins with `prefix`
+fn parse_byte_map_key(full_key: &str, prefix: &str) -> Result<(u64, String), AccountError> {
+    full_key
+        .strip_prefix(prefix)
+        .and_then(|rest| rest.split_once('.'))
+        .and_then(|(peer_cid, key)| Some((u64::from_str(peer_cid).ok()?, key.to_string())))
+        .ok_or_else(|| AccountError::msg(format!("Invalid byte map key {full_key}")))
+}
+
+/// Returns the byte map key of a redis key holding values of a single peer relationship
+fn strip_byte_map_prefix(full_key: &str, prefix: &str) -> Result<String, AccountError> {
+    full_key
+        .strip_prefix(prefix)
+        .map(ToString::to_string)
+        .ok_or_else(|| AccountError::msg(format!("Invalid byte map key {full_key}")))
+}
+
 fn get_impersonal_status_key() -> &'static str {
     CID_TO_IMPERSONALS
 }
 
+/// A sorted set of the impersonal cids that are not deactivated, which lets clients be paged in
+/// ascending cid order. See [`active_impersonal_score`]
+fn get_active_impersonals_key() -> &'static str {
+    ACTIVE_IMPERSONALS
+}
+
+fn get_active_impersonals_version_key() -> &'static str {
+    ACTIVE_IMPERSONALS_VERSION
+}
+
+/// Scores are doubles, so cids above 2^53 may share a score. Ties are ordered by member, which is
+/// zero-padded by [`active_impersonal_member`], so the set is still in exact cid order
+fn active_impersonal_score(cid: u64) -> f64 {
+    cid as f64
+}
+
+fn active_impersonal_member(cid: u64) -> String {
+    format!("{cid:020}")
+}
+
 fn get_personal_status_key() -> &'static str {
     CID_TO_PERSONALS
 }
+
+/// Maps the cid of each deactivated client to its deactivation time, in unix milliseconds
+fn get_deactivated_key() -> &'static str {
+    CID_TO_DEACTIVATION_TIME
+}
+
+/// Maps the cid of each client that has connected to the time of its last connect, in unix
+/// milliseconds
+fn get_last_connect_key() -> &'static str {
+    CID_TO_LAST_CONNECT_TIME
+}
+
+fn from_unix_millis(millis: u64) -> DateTime<Utc> {
+    (std::time::UNIX_EPOCH + Duration::from_millis(millis)).into()
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::redis_backend::{RedisBackend, RedisConnectionOptions};
+    use crate::backend::BackendConnection;
+    use crate::misc::AccountError;
+    use citadel_crypt::fcm::fcm_ratchet::ThinRatchet;
+    use citadel_crypt::stacked_ratchet::StackedRatchet;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    type TestBackend = RedisBackend<StackedRatchet, ThinRatchet>;
+
+    /// The redis server listed in the same variable as the integration tests, if any
+    fn redis_url() -> Option<String> {
+        std::env::var("TESTING_SQL_SERVER_ADDR_SERVER")
+            .unwrap_or_default()
+            .split(',')
+            .find(|addr| addr.starts_with("redis"))
+            .map(str::to_string)
+    }
+
+    #[tokio::test]
+    async fn rejects_inconsistent_pool_sizes_at_connect() {
+        let invalid = [
+            RedisConnectionOptions {
+                max_pool_size: Some(2),
+                min_idle: Some(4),
+                ..Default::default()
+            },
+            RedisConnectionOptions {
+                max_pool_size: Some(0),
+                ..Default::default()
+            },
+            RedisConnectionOptions {
+                max_pool_size: Some(2),
+                max_idle: Some(4),
+                ..Default::default()
+            },
+            RedisConnectionOptions {
+                max_idle: Some(1),
+                min_idle: Some(2),
+                ..Default::default()
+            },
+        ];
+
+        for opts in invalid {
+            // nothing listens on port 1, so only a rejected configuration fails quickly
+            let mut backend = TestBackend::new("redis://127.0.0.1:1/".to_string(), opts);
+            let err = backend.connect().await.err().unwrap();
+            assert!(matches!(err, AccountError::Generic(_)), "{err:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn operations_beyond_the_pool_size_wait_for_a_connection() {
+        let url = match redis_url() {
+            Some(url) => url,
+            None => return,
+        };
+
+        let opts = RedisConnectionOptions {
+            max_pool_size: Some(2),
+            min_idle: Some(1),
+            connection_timeout: Some(Duration::from_millis(500)),
+            ..Default::default()
+        };
+        let mut backend = TestBackend::new(url, opts);
+        backend.connect().await.unwrap();
+        let backend = Arc::new(backend);
+
+        // many more concurrent operations than connections queue rather than fail
+        let tasks = (0..16)
+            .map(|_| {
+                let backend = backend.clone();
+                tokio::spawn(async move { backend.is_deactivated(0).await })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            assert!(!task.await.unwrap().unwrap());
+        }
+
+        // while every connection is checked out, the timeout fires
+        let held = (
+            backend.get_conn().await.unwrap(),
+            backend.get_conn().await.unwrap(),
+        );
+        let err = backend.get_conn().await.err().unwrap();
+        assert!(
+            matches!(err, AccountError::BackendUnavailable(_)),
+            "{err:?}"
+        );
+
+        // a waiting operation proceeds once a connection is returned
+        let waiting = {
+            let backend = backend.clone();
+            tokio::spawn(async move { backend.is_deactivated(0).await })
+        };
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        drop(held);
+        assert!(!waiting.await.unwrap().unwrap());
+    }
+}
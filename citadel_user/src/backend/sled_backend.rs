@@ -0,0 +1,1076 @@
+use super::utils::StreamableTargetInformation;
+use crate::backend::memory::no_backend_streaming;
+use crate::backend::utils::ObjectTransferStatus;
+use crate::backend::{
+    check_username_change, normalize_username, BackendConnection, USERNAME_INDEX_VERSION,
+};
+use crate::client_account::{ClientNetworkAccount, MutualPeer};
+use crate::misc::{AccountError, ByteMapEntry, CNACMetadata, CredentialPolicy};
+use crate::peer_list::PeerListDelta;
+use crate::prelude::{ClientNetworkAccountInner, HYPERLAN_IDX};
+use crate::serialization::SyncIO;
+use async_trait::async_trait;
+use citadel_crypt::stacked_ratchet::Ratchet;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sled::transaction::{ConflictableTransactionError, TransactionError};
+use sled::{Batch, Db, IVec, Transactional, Tree};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock, Weak};
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+const CNACS_TREE: &str = "cnacs";
+const PEERS_TREE: &str = "peers";
+const BYTE_MAP_TREE: &str = "bytemap";
+const USERNAMES_TREE: &str = "usernames";
+/// The key, inside the default tree, of the version of the username index
+const USERNAMES_VERSION_KEY: &str = "usernames.version";
+
+/// Backend struct for an embedded sled database. Each record is stored under a single key, so
+/// many small accounts do not each cost a file as they do with the filesystem backend
+pub(crate) struct SledBackend<R: Ratchet, Fcm: Ratchet> {
+    path: PathBuf,
+    trees: Option<SledTrees>,
+    _pd: PhantomData<(R, Fcm)>,
+}
+
+struct SledTrees {
+    db: Arc<Db>,
+    /// CID -> [`CnacRecord`]
+    cnacs: Tree,
+    /// CID ++ peer CID -> the peer's username
+    peers: Tree,
+    /// CID ++ peer CID ++ key length ++ key ++ sub key -> [`ByteMapRecord`]
+    byte_map: Tree,
+    /// [Normalized](normalize_username) username -> CID
+    usernames: Tree,
+}
+
+/// A CNAC along with the metadata needed to list clients without deserializing the CNAC
+#[derive(Serialize, Deserialize)]
+struct CnacRecord {
+    metadata: CNACMetadata,
+    /// Present only while the client is deactivated
+    deactivated_at: Option<SystemTime>,
+    bin: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ByteMapRecord {
+    value: Vec<u8>,
+    expires_at: Option<SystemTime>,
+}
+
+impl CnacRecord {
+    fn into_metadata(self) -> CNACMetadata {
+        CNACMetadata {
+            is_active: self.deactivated_at.is_none(),
+            ..self.metadata
+        }
+    }
+}
+
+impl ByteMapRecord {
+    /// Returns the value, unless it expired
+    fn into_live_value(self) -> Option<Vec<u8>> {
+        match self.expires_at {
+            Some(expires_at) if expires_at <= SystemTime::now() => None,
+            _ => Some(self.value),
+        }
+    }
+}
+
+#[async_trait]
+impl<R: Ratchet, Fcm: Ratchet> BackendConnection<R, Fcm> for SledBackend<R, Fcm> {
+    async fn connect(&mut self) -> Result<(), AccountError> {
+        let db = open_shared(&self.path)?;
+        self.trees = Some(SledTrees {
+            cnacs: db.open_tree(CNACS_TREE)?,
+            peers: db.open_tree(PEERS_TREE)?,
+            byte_map: db.open_tree(BYTE_MAP_TREE)?,
+            usernames: db.open_tree(USERNAMES_TREE)?,
+            db,
+        });
+        self.backfill_username_index().await
+    }
+
+    async fn is_connected(&self) -> Result<bool, AccountError> {
+        Ok(self.trees.is_some())
+    }
+
+    async fn disconnect(&self) -> Result<(), AccountError> {
+        self.flush().await
+    }
+
+    async fn save_cnac(&self, cnac: &ClientNetworkAccount<R, Fcm>) -> Result<(), AccountError> {
+        let bin = cnac.generate_proper_bytes()?;
+        let metadata = cnac.get_metadata();
+        let cid = metadata.cid;
+        let username = normalize_username(&metadata.username);
+        let trees = self.trees()?;
+
+        // the username is claimed in the same transaction, so that it is never held by two clients
+        (&trees.cnacs, &trees.usernames)
+            .transaction(|(cnacs, usernames)| {
+                let key = cid.to_be_bytes().to_vec();
+                let record = cnacs
+                    .get(&key)?
+                    .map(|record| CnacRecord::deserialize_from_vector(&record))
+                    .transpose()
+                    .map_err(ConflictableTransactionError::Abort)?;
+                let previous_username = record
+                    .as_ref()
+                    .map(|record| normalize_username(&record.metadata.username));
+                let holder_cid = usernames
+                    .get(username.as_bytes())?
+                    .map(|holder_cid| decode_cid(&holder_cid))
+                    .transpose()
+                    .map_err(ConflictableTransactionError::Abort)?;
+
+                // a client keeping its username may save, even if it is a duplicate held by another
+                if previous_username.as_ref() != Some(&username) {
+                    if let Some(holder_cid) = holder_cid.filter(|holder_cid| *holder_cid != cid) {
+                        return Err(ConflictableTransactionError::Abort(
+                            AccountError::ClientExists(holder_cid),
+                        ));
+                    }
+
+                    if let Some(previous_username) = previous_username {
+                        if usernames.get(previous_username.as_bytes())?.as_deref() == Some(&key[..])
+                        {
+                            let _ = usernames.remove(previous_username.as_bytes())?;
+                        }
+                    }
+                }
+
+                if holder_cid.is_none() {
+                    let _ = usernames.insert(username.as_bytes(), key.clone())?;
+                }
+
+                // the deactivation time is kept across saves
+                let record = CnacRecord {
+                    metadata: metadata.clone(),
+                    deactivated_at: record.and_then(|record| record.deactivated_at),
+                    bin: bin.clone(),
+                }
+                .serialize_to_vector()
+                .map_err(ConflictableTransactionError::Abort)?;
+                let _ = cnacs.insert(key, record)?;
+                Ok(())
+            })
+            .map_err(|err| match err {
+                TransactionError::Abort(err) => err,
+                TransactionError::Storage(err) => err.into(),
+            })?;
+
+        self.flush().await
+    }
+
+    async fn get_cnac_by_cid(
+        &self,
+        cid: u64,
+    ) -> Result<Option<ClientNetworkAccount<R, Fcm>>, AccountError> {
+        if let Some(record) = self.get_cnac_record(cid)? {
+            let inner =
+                ClientNetworkAccountInner::<R, Fcm>::deserialize_from_owned_vector(record.bin)?;
+            Ok(Some(inner.into()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn cid_is_registered(&self, cid: u64) -> Result<bool, AccountError> {
+        Ok(self.trees()?.cnacs.contains_key(cid.to_be_bytes())?)
+    }
+
+    async fn is_personal(&self, cid: u64) -> Result<Option<bool>, AccountError> {
+        Ok(self
+            .get_cnac_record(cid)?
+            .map(|record| record.metadata.is_personal))
+    }
+
+    async fn delete_cnac_by_cid(&self, cid: u64) -> Result<(), AccountError> {
+        let trees = self.trees()?;
+        let record = match trees.cnacs.remove(cid.to_be_bytes())? {
+            Some(record) => CnacRecord::deserialize_from_vector(&record)?,
+            None => return Err(AccountError::ClientNonExists(cid)),
+        };
+
+        // the username is freed, unless it was held by another client all along
+        let _ = trees.usernames.compare_and_swap(
+            normalize_username(&record.metadata.username).as_bytes(),
+            Some(&cid.to_be_bytes()[..]),
+            None as Option<&[u8]>,
+        )?;
+
+        // remove both directions of each peer relation
+        let mut peers = Batch::default();
+        for entry in trees.peers.scan_prefix(cid.to_be_bytes()) {
+            let (key, _) = entry?;
+            let peer_cid = decode_cid(&key[8..])?;
+            peers.remove(peer_key(peer_cid, cid));
+            peers.remove(key);
+        }
+        trees.peers.apply_batch(peers)?;
+
+        let mut byte_map = Batch::default();
+        for key in trees.byte_map.scan_prefix(cid.to_be_bytes()).keys() {
+            byte_map.remove(key?);
+        }
+        trees.byte_map.apply_batch(byte_map)?;
+
+        self.flush().await
+    }
+
+    async fn purge(&self) -> Result<usize, AccountError> {
+        let trees = self.trees()?;
+        let count = trees.cnacs.len();
+        trees.peers.clear()?;
+        trees.byte_map.clear()?;
+        trees.usernames.clear()?;
+        trees.cnacs.clear()?;
+        self.flush().await?;
+        Ok(count)
+    }
+
+    async fn deactivate_cnac(&self, cid: u64) -> Result<(), AccountError> {
+        // the original deactivation time is kept if the client is already deactivated
+        let updated = self.update_cnac_record(cid, |record| {
+            record.map(|record| CnacRecord {
+                deactivated_at: record.deactivated_at.or_else(|| Some(SystemTime::now())),
+                ..record
+            })
+        })?;
+
+        if updated {
+            self.flush().await
+        } else {
+            Err(AccountError::ClientNonExists(cid))
+        }
+    }
+
+    async fn reactivate_cnac(&self, cid: u64) -> Result<(), AccountError> {
+        let updated = self.update_cnac_record(cid, |record| {
+            record.map(|record| CnacRecord {
+                deactivated_at: None,
+                ..record
+            })
+        })?;
+
+        if updated {
+            self.flush().await
+        } else {
+            Err(AccountError::ClientNonExists(cid))
+        }
+    }
+
+    async fn purge_deactivated_before(&self, cutoff: SystemTime) -> Result<usize, AccountError> {
+        let mut expired = Vec::new();
+        for record in self.trees()?.cnacs.iter().values() {
+            let record = CnacRecord::deserialize_from_vector(&record?)?;
+            if matches!(record.deactivated_at, Some(deactivated_at) if deactivated_at < cutoff) {
+                expired.push(record.metadata.cid);
+            }
+        }
+
+        // peers and byte map values must be removed alongside each client
+        for cid in &expired {
+            self.delete_cnac_by_cid(*cid).await?;
+        }
+
+        Ok(expired.len())
+    }
+
+    async fn get_registered_impersonal_cids(
+        &self,
+        limit: Option<i32>,
+    ) -> Result<Option<Vec<u64>>, AccountError> {
+        let limit = limit.map(|limit| limit as usize).unwrap_or(usize::MAX);
+        let mut ret = Vec::new();
+        for record in self.trees()?.cnacs.iter().values() {
+            if ret.len() >= limit {
+                break;
+            }
+
+            let record = CnacRecord::deserialize_from_vector(&record?)?;
+            if !record.metadata.is_personal && record.deactivated_at.is_none() {
+                ret.push(record.metadata.cid);
+            }
+        }
+
+        if ret.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(ret))
+        }
+    }
+
+    async fn get_registered_impersonal_cids_paged(
+        &self,
+        offset: u64,
+        limit: u32,
+    ) -> Result<Vec<u64>, AccountError> {
+        // keys are big-endian CIDs, so the tree iterates in ascending CID order
+        let mut skipped = 0;
+        let mut ret = Vec::new();
+        for record in self.trees()?.cnacs.iter().values() {
+            if ret.len() >= limit as usize {
+                break;
+            }
+
+            let record = CnacRecord::deserialize_from_vector(&record?)?;
+            if record.metadata.is_personal || record.deactivated_at.is_some() {
+                continue;
+            }
+
+            if skipped < offset {
+                skipped += 1;
+            } else {
+                ret.push(record.metadata.cid);
+            }
+        }
+
+        Ok(ret)
+    }
+
+    async fn get_username_by_cid(&self, cid: u64) -> Result<Option<String>, AccountError> {
+        Ok(self
+            .get_cnac_record(cid)?
+            .map(|record| record.metadata.username))
+    }
+
+    async fn get_cid_by_stored_username(
+        &self,
+        username: &str,
+    ) -> Result<Option<u64>, AccountError> {
+        self.trees()?
+            .usernames
+            .get(normalize_username(username).as_bytes())?
+            .map(|cid| decode_cid(&cid))
+            .transpose()
+    }
+
+    async fn change_username(
+        &self,
+        cid: u64,
+        new_username: &str,
+        credential_policy: &CredentialPolicy,
+    ) -> Result<(), AccountError> {
+        let cnac = check_username_change(self, cid, new_username, credential_policy).await?;
+        let previous_username = normalize_username(&cnac.get_username());
+        let normalized = normalize_username(new_username);
+        cnac.set_username(new_username);
+        let metadata = cnac.get_metadata();
+        let bin = cnac.generate_proper_bytes()?;
+        let username = Some(new_username.to_string()).serialize_to_vector()?;
+        let peer_cids = self.get_hyperlan_peer_list(cid).await?.unwrap_or_default();
+        let trees = self.trees()?;
+
+        // the client, its username and the entry for it in each peer's list are renamed in a single
+        // transaction, unless another client took the username since it was checked
+        (&trees.cnacs, &trees.peers, &trees.usernames)
+            .transaction(|(cnacs, peers, usernames)| {
+                let key = cid.to_be_bytes().to_vec();
+                let record = cnacs.get(&key)?.ok_or(ConflictableTransactionError::Abort(
+                    AccountError::ClientNonExists(cid),
+                ))?;
+                if let Some(holder_cid) = usernames.get(normalized.as_bytes())? {
+                    let holder_cid =
+                        decode_cid(&holder_cid).map_err(ConflictableTransactionError::Abort)?;
+                    if holder_cid != cid {
+                        return Err(ConflictableTransactionError::Abort(
+                            AccountError::ClientExists(holder_cid),
+                        ));
+                    }
+                }
+
+                if usernames.get(previous_username.as_bytes())?.as_deref() == Some(&key[..]) {
+                    let _ = usernames.remove(previous_username.as_bytes())?;
+                }
+                let _ = usernames.insert(normalized.as_bytes(), key.clone())?;
+                // the deactivation time is kept across renames
+                let record = CnacRecord {
+                    metadata: metadata.clone(),
+                    bin: bin.clone(),
+                    ..CnacRecord::deserialize_from_vector(&record)
+                        .map_err(ConflictableTransactionError::Abort)?
+                }
+                .serialize_to_vector()
+                .map_err(ConflictableTransactionError::Abort)?;
+                let _ = cnacs.insert(key, record)?;
+
+                for peer_cid in &peer_cids {
+                    let key = peer_key(*peer_cid, cid);
+                    // a peer deregistered since its list was read is skipped
+                    if peers.get(&key)?.is_some() {
+                        let _ = peers.insert(key, username.clone())?;
+                    }
+                }
+
+                Ok(())
+            })
+            .map_err(|err| match err {
+                TransactionError::Abort(err) => err,
+                TransactionError::Storage(err) => err.into(),
+            })?;
+
+        self.flush().await
+    }
+
+    async fn register_p2p_as_server(&self, cid0: u64, cid1: u64) -> Result<(), AccountError> {
+        let username0 = self
+            .get_username_by_cid(cid0)
+            .await?
+            .ok_or(AccountError::ClientNonExists(cid0))?;
+        let username1 = self
+            .get_username_by_cid(cid1)
+            .await?
+            .ok_or(AccountError::ClientNonExists(cid1))?;
+
+        // batches are applied atomically, so either both directions are written or neither
+        let mut batch = Batch::default();
+        batch.insert(peer_key(cid1, cid0), Some(username0).serialize_to_vector()?);
+        batch.insert(peer_key(cid0, cid1), Some(username1).serialize_to_vector()?);
+        Ok(self.trees()?.peers.apply_batch(batch)?)
+    }
+
+    async fn register_p2p_as_client(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        peer_username: String,
+    ) -> Result<(), AccountError> {
+        log::trace!(target: "citadel", "Registering p2p ({} <-> {}) as client", implicated_cid, peer_cid);
+        let _ = self.trees()?.peers.insert(
+            peer_key(implicated_cid, peer_cid),
+            Some(peer_username).serialize_to_vector()?,
+        )?;
+        Ok(())
+    }
+
+    async fn deregister_p2p_as_server(&self, cid0: u64, cid1: u64) -> Result<(), AccountError> {
+        let mut batch = Batch::default();
+        batch.remove(peer_key(cid0, cid1));
+        batch.remove(peer_key(cid1, cid0));
+        Ok(self.trees()?.peers.apply_batch(batch)?)
+    }
+
+    async fn deregister_p2p_as_client(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Option<MutualPeer>, AccountError> {
+        self.trees()?
+            .peers
+            .remove(peer_key(implicated_cid, peer_cid))?
+            .map(|username| decode_mutual_peer(peer_cid, &username))
+            .transpose()
+    }
+
+    async fn get_hyperlan_peer_list(
+        &self,
+        implicated_cid: u64,
+    ) -> Result<Option<Vec<u64>>, AccountError> {
+        let peers = self
+            .trees()?
+            .peers
+            .scan_prefix(implicated_cid.to_be_bytes())
+            .keys()
+            .map(|key| decode_cid(&key?[8..]))
+            .collect::<Result<Vec<u64>, AccountError>>()?;
+
+        if peers.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(peers))
+        }
+    }
+
+    async fn get_hyperlan_peer_count(&self, implicated_cid: u64) -> Result<usize, AccountError> {
+        let mut count = 0;
+        for key in self
+            .trees()?
+            .peers
+            .scan_prefix(implicated_cid.to_be_bytes())
+            .keys()
+        {
+            let _ = key?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    async fn get_client_metadata(
+        &self,
+        implicated_cid: u64,
+    ) -> Result<Option<CNACMetadata>, AccountError> {
+        Ok(self
+            .get_cnac_record(implicated_cid)?
+            .map(CnacRecord::into_metadata))
+    }
+
+    async fn stream_clients_metadata(
+        &self,
+    ) -> Result<BoxStream<'_, Result<CNACMetadata, AccountError>>, AccountError> {
+        // records are read from the tree as the stream is polled
+        Ok(futures::stream::iter(self.trees()?.cnacs.iter().values())
+            .map(|record| Ok(CnacRecord::deserialize_from_vector(&record?)?.into_metadata()))
+            .boxed())
+    }
+
+    async fn get_hyperlan_peer_by_cid(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Option<MutualPeer>, AccountError> {
+        self.trees()?
+            .peers
+            .get(peer_key(implicated_cid, peer_cid))?
+            .map(|username| decode_mutual_peer(peer_cid, &username))
+            .transpose()
+    }
+
+    async fn hyperlan_peer_exists(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<bool, AccountError> {
+        Ok(self
+            .trees()?
+            .peers
+            .contains_key(peer_key(implicated_cid, peer_cid))?)
+    }
+
+    async fn hyperlan_peers_are_mutuals(
+        &self,
+        implicated_cid: u64,
+        peers: &[u64],
+    ) -> Result<Vec<bool>, AccountError> {
+        let tree = &self.trees()?.peers;
+        peers
+            .iter()
+            .map(|peer_cid| Ok(tree.contains_key(peer_key(implicated_cid, *peer_cid))?))
+            .collect()
+    }
+
+    async fn get_hyperlan_peers(
+        &self,
+        implicated_cid: u64,
+        peers: &[u64],
+    ) -> Result<Vec<MutualPeer>, AccountError> {
+        let mut ret = Vec::with_capacity(peers.len());
+        for peer_cid in peers {
+            if let Some(peer) = self
+                .get_hyperlan_peer_by_cid(implicated_cid, *peer_cid)
+                .await?
+            {
+                ret.push(peer);
+            }
+        }
+
+        Ok(ret)
+    }
+
+    async fn get_hyperlan_peer_list_as_server(
+        &self,
+        implicated_cid: u64,
+    ) -> Result<Option<Vec<MutualPeer>>, AccountError> {
+        let peers = self
+            .trees()?
+            .peers
+            .scan_prefix(implicated_cid.to_be_bytes())
+            .map(|entry| {
+                let (key, username) = entry?;
+                decode_mutual_peer(decode_cid(&key[8..])?, &username)
+            })
+            .collect::<Result<Vec<MutualPeer>, AccountError>>()?;
+
+        if peers.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(peers))
+        }
+    }
+
+    async fn synchronize_hyperlan_peer_list_as_client(
+        &self,
+        cnac: &ClientNetworkAccount<R, Fcm>,
+        peers: Vec<MutualPeer>,
+    ) -> Result<PeerListDelta, AccountError> {
+        log::trace!(target: "citadel", "Synchronizing peer list for {}", cnac.get_cid());
+        let existing = self
+            .get_hyperlan_peer_list_as_server(cnac.get_cid())
+            .await?
+            .unwrap_or_default();
+        let delta = PeerListDelta::between(&existing, &peers);
+        if !delta.is_empty() {
+            let implicated_cid = cnac.get_cid();
+            let tree = &self.trees()?.peers;
+            let mut batch = Batch::default();
+            for key in tree.scan_prefix(implicated_cid.to_be_bytes()).keys() {
+                batch.remove(key?);
+            }
+
+            for peer in peers {
+                batch.insert(
+                    peer_key(implicated_cid, peer.cid),
+                    peer.username.serialize_to_vector()?,
+                );
+            }
+
+            tree.apply_batch(batch)?;
+        }
+
+        Ok(delta)
+    }
+
+    async fn get_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+    ) -> Result<Option<Vec<u8>>, AccountError> {
+        self.trees()?
+            .byte_map
+            .get(byte_map_key(implicated_cid, peer_cid, key, sub_key))?
+            .map(decode_live_value)
+            .transpose()
+            .map(Option::flatten)
+    }
+
+    async fn get_byte_map_values(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        keys: &[(String, String)],
+    ) -> Result<HashMap<(String, String), Vec<u8>>, AccountError> {
+        let tree = &self.trees()?.byte_map;
+        let mut ret = HashMap::new();
+        for (key, sub_key) in keys {
+            if let Some(record) = tree.get(byte_map_key(implicated_cid, peer_cid, key, sub_key))? {
+                if let Some(value) = decode_live_value(record)? {
+                    let _ = ret.insert((key.clone(), sub_key.clone()), value);
+                }
+            }
+        }
+
+        Ok(ret)
+    }
+
+    async fn remove_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+    ) -> Result<Option<Vec<u8>>, AccountError> {
+        self.trees()?
+            .byte_map
+            .remove(byte_map_key(implicated_cid, peer_cid, key, sub_key))?
+            .map(decode_live_value)
+            .transpose()
+            .map(Option::flatten)
+    }
+
+    async fn store_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        value: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, AccountError> {
+        self.insert_byte_map_value(implicated_cid, peer_cid, key, sub_key, value, None)
+    }
+
+    async fn store_byte_map_value_with_expiry(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> Result<Option<Vec<u8>>, AccountError> {
+        self.insert_byte_map_value(
+            implicated_cid,
+            peer_cid,
+            key,
+            sub_key,
+            value,
+            Some(SystemTime::now() + ttl),
+        )
+    }
+
+    async fn compare_and_swap_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        expected: Option<Vec<u8>>,
+        new: Vec<u8>,
+    ) -> Result<bool, AccountError> {
+        let tree = &self.trees()?.byte_map;
+        let address = byte_map_key(implicated_cid, peer_cid, key, sub_key);
+        let new = ByteMapRecord {
+            value: new,
+            expires_at: None,
+        }
+        .serialize_to_vector()?;
+
+        loop {
+            // the raw record is swapped rather than the value, since an expired record must be
+            // replaced as though it were absent
+            let current = tree.get(&address)?;
+            let current_value = current
+                .clone()
+                .map(decode_live_value)
+                .transpose()?
+                .flatten();
+            if current_value != expected {
+                return Ok(false);
+            }
+
+            if tree
+                .compare_and_swap(&address, current, Some(new.as_slice()))?
+                .is_ok()
+            {
+                return Ok(true);
+            }
+        }
+    }
+
+    async fn get_or_insert_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        default: Box<dyn FnOnce() -> Vec<u8> + Send + '_>,
+    ) -> Result<Vec<u8>, AccountError> {
+        let tree = &self.trees()?.byte_map;
+        let address = byte_map_key(implicated_cid, peer_cid, key, sub_key);
+        let mut default = Some(default);
+        let mut new = None;
+
+        loop {
+            let current = tree.get(&address)?;
+            if let Some(value) = current
+                .clone()
+                .map(decode_live_value)
+                .transpose()?
+                .flatten()
+            {
+                return Ok(value);
+            }
+
+            // the default is produced at most once, even if the swap must be retried
+            if new.is_none() {
+                let value = (default.take().unwrap())();
+                let record = ByteMapRecord {
+                    value: value.clone(),
+                    expires_at: None,
+                }
+                .serialize_to_vector()?;
+                new = Some((value, record));
+            }
+
+            let (value, record) = new.as_ref().unwrap();
+            // an expired record is replaced as though it were absent
+            if tree
+                .compare_and_swap(&address, current, Some(record.as_slice()))?
+                .is_ok()
+            {
+                return Ok(value.clone());
+            }
+        }
+    }
+
+    async fn copy_byte_map(&self, from_cid: u64, to_cid: u64) -> Result<usize, AccountError> {
+        if from_cid == to_cid {
+            return Err(AccountError::msg("Cannot copy a byte map onto itself"));
+        }
+
+        let tree = &self.trees()?.byte_map;
+        let mut batch = Batch::default();
+        let mut copied = 0;
+        for entry in tree.scan_prefix(from_cid.to_be_bytes()) {
+            let (address, record) = entry?;
+            if decode_live_value(record.clone())?.is_none() {
+                continue;
+            }
+
+            // only the CID prefix differs, so the expiry is kept along with the value
+            let mut to_address = to_cid.to_be_bytes().to_vec();
+            to_address.extend_from_slice(&address[8..]);
+            batch.insert(to_address, record);
+            copied += 1;
+        }
+
+        tree.apply_batch(batch)?;
+        Ok(copied)
+    }
+
+    async fn get_byte_map_values_by_key(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+    ) -> Result<HashMap<String, Vec<u8>>, AccountError> {
+        let prefix = byte_map_prefix(implicated_cid, peer_cid, key);
+        let mut ret = HashMap::new();
+        for entry in self.trees()?.byte_map.scan_prefix(&prefix) {
+            let (address, record) = entry?;
+            if let Some(value) = decode_live_value(record)? {
+                let _ = ret.insert(decode_sub_key(&address[prefix.len()..])?, value);
+            }
+        }
+
+        Ok(ret)
+    }
+
+    async fn remove_byte_map_values_by_key(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+    ) -> Result<HashMap<String, Vec<u8>>, AccountError> {
+        let tree = &self.trees()?.byte_map;
+        let prefix = byte_map_prefix(implicated_cid, peer_cid, key);
+        let mut batch = Batch::default();
+        let mut ret = HashMap::new();
+        for entry in tree.scan_prefix(&prefix) {
+            let (address, record) = entry?;
+            if let Some(value) = decode_live_value(record)? {
+                let _ = ret.insert(decode_sub_key(&address[prefix.len()..])?, value);
+            }
+            batch.remove(address);
+        }
+
+        tree.apply_batch(batch)?;
+        Ok(ret)
+    }
+
+    async fn get_byte_map_entries(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<Vec<ByteMapEntry>, AccountError> {
+        let prefix = peer_key(implicated_cid, peer_cid);
+        let mut entries = Vec::new();
+        for entry in self.trees()?.byte_map.scan_prefix(&prefix) {
+            let (address, record) = entry?;
+            let ByteMapRecord { value, expires_at } =
+                ByteMapRecord::deserialize_from_vector(&record)?;
+            if matches!(expires_at, Some(expires_at) if expires_at <= SystemTime::now()) {
+                continue;
+            }
+
+            let (key, sub_key) = decode_byte_map_address(&address[prefix.len()..])?;
+            entries.push(ByteMapEntry {
+                key,
+                sub_key,
+                value,
+                expires_at,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    async fn stream_object_to_backend(
+        &self,
+        source: UnboundedReceiver<Vec<u8>>,
+        sink_metadata: Arc<dyn StreamableTargetInformation>,
+        status_tx: UnboundedSender<ObjectTransferStatus>,
+    ) -> Result<(), AccountError> {
+        no_backend_streaming(source, sink_metadata, status_tx).await
+    }
+}
+
+impl<R: Ratchet, Fcm: Ratchet> SledBackend<R, Fcm> {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            trees: None,
+            _pd: Default::default(),
+        }
+    }
+
+    fn trees(&self) -> Result<&SledTrees, AccountError> {
+        self.trees
+            .as_ref()
+            .ok_or_else(|| AccountError::msg("Sled database not loaded"))
+    }
+
+    /// Builds the username index from the saved clients, unless it was built already, such that
+    /// clients saved before the index existed are indexed. If several clients hold the same
+    /// username, the client with the lowest CID keeps it
+    async fn backfill_username_index(&self) -> Result<(), AccountError> {
+        let trees = self.trees()?;
+        let version = USERNAME_INDEX_VERSION.to_be_bytes();
+        if trees.db.get(USERNAMES_VERSION_KEY)?.as_deref() == Some(&version[..]) {
+            return Ok(());
+        }
+
+        trees.usernames.clear()?;
+        // the keys are big-endian CIDs, so the records are visited in ascending order of CID
+        for record in trees.cnacs.iter().values() {
+            let metadata = CnacRecord::deserialize_from_vector(&record?)?.metadata;
+            let username = normalize_username(&metadata.username);
+            if let Err(err) = trees.usernames.compare_and_swap(
+                username.as_bytes(),
+                None as Option<&[u8]>,
+                Some(&metadata.cid.to_be_bytes()[..]),
+            )? {
+                let holder_cid = err
+                    .current
+                    .and_then(|holder_cid| decode_cid(&holder_cid).ok())
+                    .unwrap_or_default();
+                log::warn!(target: "citadel", "Clients {} and {} hold the same username {}", holder_cid, metadata.cid, username);
+            }
+        }
+
+        let _ = trees.db.insert(USERNAMES_VERSION_KEY, &version[..])?;
+        self.flush().await
+    }
+
+    /// Persists every write so far. Byte map values are otherwise flushed periodically by sled
+    async fn flush(&self) -> Result<(), AccountError> {
+        let _ = self.trees()?.db.flush_async().await?;
+        Ok(())
+    }
+
+    fn get_cnac_record(&self, cid: u64) -> Result<Option<CnacRecord>, AccountError> {
+        self.trees()?
+            .cnacs
+            .get(cid.to_be_bytes())?
+            .map(|record| CnacRecord::deserialize_from_vector(&record))
+            .transpose()
+    }
+
+    /// Atomically replaces the record of `cid` with the output of `update`, which receives the
+    /// current record. Returns false if `update` returned None, leaving the record untouched
+    fn update_cnac_record(
+        &self,
+        cid: u64,
+        update: impl Fn(Option<CnacRecord>) -> Option<CnacRecord>,
+    ) -> Result<bool, AccountError> {
+        let tree = &self.trees()?.cnacs;
+        let key = cid.to_be_bytes();
+        loop {
+            let current = tree.get(key)?;
+            let record = current
+                .as_ref()
+                .map(|record| CnacRecord::deserialize_from_vector(record))
+                .transpose()?;
+            let new = match update(record) {
+                Some(new) => new.serialize_to_vector()?,
+                None => return Ok(false),
+            };
+
+            if tree.compare_and_swap(key, current, Some(new))?.is_ok() {
+                return Ok(true);
+            }
+        }
+    }
+
+    fn insert_byte_map_value(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+        key: &str,
+        sub_key: &str,
+        value: Vec<u8>,
+        expires_at: Option<SystemTime>,
+    ) -> Result<Option<Vec<u8>>, AccountError> {
+        // replacing the whole record also clears any previous expiry
+        let record = ByteMapRecord { value, expires_at }.serialize_to_vector()?;
+        self.trees()?
+            .byte_map
+            .insert(byte_map_key(implicated_cid, peer_cid, key, sub_key), record)?
+            .map(decode_live_value)
+            .transpose()
+            .map(Option::flatten)
+    }
+}
+
+/// sled locks its directory for as long as it is open, so every backend opened on the same path
+/// within this process shares a single handle. The directory is unlocked once all of them drop
+fn open_shared(path: &Path) -> Result<Arc<Db>, AccountError> {
+    static OPEN_DATABASES: OnceLock<Mutex<HashMap<PathBuf, Weak<Db>>>> = OnceLock::new();
+    let mut open_databases = OPEN_DATABASES.get_or_init(Default::default).lock();
+    if let Some(db) = open_databases.get(path).and_then(Weak::upgrade) {
+        return Ok(db);
+    }
+
+    let db = Arc::new(sled::open(path)?);
+    open_databases.retain(|_, db| db.strong_count() != 0);
+    let _ = open_databases.insert(path.to_path_buf(), Arc::downgrade(&db));
+    Ok(db)
+}
+
+fn peer_key(implicated_cid: u64, peer_cid: u64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(16);
+    key.extend_from_slice(&implicated_cid.to_be_bytes());
+    key.extend_from_slice(&peer_cid.to_be_bytes());
+    key
+}
+
+/// The key is length-prefixed so that scanning one key never matches another it is a prefix of
+fn byte_map_prefix(implicated_cid: u64, peer_cid: u64, key: &str) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(20 + key.len());
+    prefix.extend_from_slice(&peer_key(implicated_cid, peer_cid));
+    prefix.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    prefix.extend_from_slice(key.as_bytes());
+    prefix
+}
+
+fn byte_map_key(implicated_cid: u64, peer_cid: u64, key: &str, sub_key: &str) -> Vec<u8> {
+    let mut address = byte_map_prefix(implicated_cid, peer_cid, key);
+    address.extend_from_slice(sub_key.as_bytes());
+    address
+}
+
+fn decode_cid(bytes: &[u8]) -> Result<u64, AccountError> {
+    bytes
+        .try_into()
+        .map(u64::from_be_bytes)
+        .map_err(|_| AccountError::msg("Corrupt CID in sled key"))
+}
+
+fn decode_sub_key(bytes: &[u8]) -> Result<String, AccountError> {
+    String::from_utf8(bytes.to_vec()).map_err(|err| AccountError::msg(err.to_string()))
+}
+
+/// Splits the remainder of a byte map address following its [`peer_key`] into its key and sub key
+fn decode_byte_map_address(bytes: &[u8]) -> Result<(String, String), AccountError> {
+    let corrupt = || AccountError::msg("Corrupt byte map key in sled key");
+    let key_len = bytes
+        .get(..4)
+        .and_then(|len| len.try_into().ok())
+        .map(u32::from_be_bytes)
+        .ok_or_else(corrupt)? as usize;
+    let key = bytes.get(4..4 + key_len).ok_or_else(corrupt)?;
+    Ok((decode_sub_key(key)?, decode_sub_key(&bytes[4 + key_len..])?))
+}
+
+fn decode_mutual_peer(peer_cid: u64, username: &[u8]) -> Result<MutualPeer, AccountError> {
+    Ok(MutualPeer {
+        parent_icid: HYPERLAN_IDX,
+        cid: peer_cid,
+        username: Option::<String>::deserialize_from_vector(username)?,
+    })
+}
+
+fn decode_live_value(record: IVec) -> Result<Option<Vec<u8>>, AccountError> {
+    Ok(ByteMapRecord::deserialize_from_vector(&record)?.into_live_value())
+}
@@ -10,7 +10,9 @@ use std::task::{Context, Poll};
 use crate::misc::AccountError;
 use citadel_crypt::misc::TransferType;
 use citadel_crypt::prelude::SecurityLevel;
+use parking_lot::Mutex;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 
 /// Misc utils/traits
@@ -72,6 +74,7 @@ pub struct ObjectTransferHandler {
     pub is_revfs_pull: bool,
     pub orientation: ObjectTransferOrientation,
     start_recv_tx: Option<tokio::sync::oneshot::Sender<bool>>,
+    stats: ObjectTransferStatsTracker,
 }
 
 impl Stream for ObjectTransferHandler {
@@ -99,11 +102,32 @@ impl ObjectTransferHandler {
             orientation,
             start_recv_tx,
             is_revfs_pull,
+            stats: ObjectTransferStatsTracker::default(),
         };
 
         (this, tx)
     }
 
+    /// Replaces the stream of statuses this handle yields, returning the previous stream.
+    /// Allows the statuses to be observed before being forwarded to the handle's owner
+    pub fn replace_status_stream(
+        &mut self,
+        inner: UnboundedReceiver<ObjectTransferStatus>,
+    ) -> UnboundedReceiver<ObjectTransferStatus> {
+        std::mem::replace(&mut self.inner, inner)
+    }
+
+    /// Returns a snapshot of the acknowledgement, round-trip and throughput statistics for this transfer.
+    /// Statistics are only collected on the sending side
+    pub fn stats(&self) -> ObjectTransferStats {
+        self.stats.snapshot()
+    }
+
+    /// Returns a handle to the tracker that updates the statistics returned by [`Self::stats`]
+    pub fn stats_tracker(&self) -> ObjectTransferStatsTracker {
+        self.stats.clone()
+    }
+
     /// When the local handle type is for a Receiver,
     /// the receiver must accept the transfer before
     /// receiving the data
@@ -134,6 +158,86 @@ impl ObjectTransferHandler {
     }
 }
 
+/// The maximum number of RTT samples retained per transfer. Older samples are discarded first
+pub const MAX_RTT_SAMPLES: usize = 64;
+
+/// Acknowledgement, round-trip and throughput statistics for a single object transfer. Groups are
+/// sent over reliable transports and never transmitted again, so losses on the underlying network
+/// surface as longer round-trip times rather than as retransmissions
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObjectTransferStats {
+    /// The number of groups transmitted
+    pub groups_sent: usize,
+    /// The number of groups the adjacent node has fully acknowledged
+    pub groups_acknowledged: usize,
+    /// The number of plaintext bytes the adjacent node has acknowledged
+    pub bytes_acknowledged: usize,
+    /// The acknowledged throughput since the first group was sent, in MB/s
+    pub throughput_mb_per_s: f32,
+    /// The most recent round-trip times, measured from the start of a group's
+    /// transmission until its final WAVE_ACK
+    pub rtt_samples: Vec<Duration>,
+}
+
+impl ObjectTransferStats {
+    /// Returns the mean of the retained RTT samples
+    pub fn mean_rtt(&self) -> Option<Duration> {
+        if self.rtt_samples.is_empty() {
+            None
+        } else {
+            Some(self.rtt_samples.iter().sum::<Duration>() / self.rtt_samples.len() as u32)
+        }
+    }
+}
+
+/// Shared between the protocol and the [`ObjectTransferHandler`] to keep the statistics
+/// of a transfer up to date
+#[derive(Debug, Clone, Default)]
+pub struct ObjectTransferStatsTracker {
+    inner: Arc<Mutex<ObjectTransferStatsState>>,
+}
+
+#[derive(Debug, Default)]
+struct ObjectTransferStatsState {
+    stats: ObjectTransferStats,
+    first_transmission: Option<Instant>,
+}
+
+impl ObjectTransferStatsTracker {
+    /// Should be called each time a group is transmitted
+    pub fn on_group_sent(&self) {
+        let mut state = self.inner.lock();
+        let _ = state.first_transmission.get_or_insert_with(Instant::now);
+        state.stats.groups_sent += 1;
+    }
+
+    /// Should be called once the final WAVE_ACK for a group is received
+    pub fn on_group_acknowledged(&self, plaintext_len: usize, rtt: Duration) {
+        let mut state = self.inner.lock();
+        let elapsed = state
+            .first_transmission
+            .map(|first| first.elapsed().as_secs_f32())
+            .unwrap_or_default();
+        let stats = &mut state.stats;
+        stats.groups_acknowledged += 1;
+        stats.bytes_acknowledged += plaintext_len;
+        if elapsed > 0f32 {
+            stats.throughput_mb_per_s = (stats.bytes_acknowledged as f32 / 1_000_000f32) / elapsed;
+        }
+
+        if stats.rtt_samples.len() == MAX_RTT_SAMPLES {
+            let _ = stats.rtt_samples.remove(0);
+        }
+
+        stats.rtt_samples.push(rtt);
+    }
+
+    /// Returns a copy of the current statistics
+    pub fn snapshot(&self) -> ObjectTransferStats {
+        self.inner.lock().stats.clone()
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum ObjectTransferOrientation {
     Receiver,
@@ -244,3 +348,42 @@ fn can_print_progress(relative_group_id: usize, total_groups: usize) -> bool {
 fn get_progress_percent(relative_group_id: usize, total_groups: usize) -> f32 {
     100f32 * (relative_group_id as f32 / total_groups as f32)
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::utils::{ObjectTransferStatsTracker, MAX_RTT_SAMPLES};
+    use std::time::Duration;
+
+    #[test]
+    fn test_transfer_stats_accumulate() {
+        const GROUPS: usize = 500;
+        let tracker = ObjectTransferStatsTracker::default();
+
+        for _ in 0..GROUPS {
+            tracker.on_group_sent();
+            std::thread::sleep(Duration::from_micros(10));
+            tracker.on_group_acknowledged(1000, Duration::from_millis(5));
+        }
+
+        let stats = tracker.snapshot();
+        assert_eq!(stats.groups_sent, GROUPS);
+        assert_eq!(stats.groups_acknowledged, GROUPS);
+        assert_eq!(stats.bytes_acknowledged, GROUPS * 1000);
+        assert!(stats.throughput_mb_per_s > 0f32);
+        assert_eq!(stats.rtt_samples.len(), MAX_RTT_SAMPLES);
+        assert_eq!(stats.mean_rtt(), Some(Duration::from_millis(5)));
+    }
+
+    #[test]
+    fn test_transfer_stats_before_acknowledgement() {
+        let tracker = ObjectTransferStatsTracker::default();
+        assert_eq!(tracker.snapshot().mean_rtt(), None);
+
+        tracker.on_group_sent();
+        let stats = tracker.snapshot();
+        assert_eq!(stats.groups_sent, 1);
+        assert_eq!(stats.groups_acknowledged, 0);
+        assert_eq!(stats.throughput_mb_per_s, 0f32);
+        assert_eq!(stats.mean_rtt(), None);
+    }
+}
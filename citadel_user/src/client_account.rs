@@ -2,16 +2,18 @@ use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
 use crate::misc::{
-    check_credential_formatting, get_present_formatted_timestamp, AccountError, CNACMetadata,
+    check_credential_formatting_with, get_present_formatted_timestamp, AccountError, CNACMetadata,
+    ClientSummary, CredentialPolicy,
 };
+use crate::peer_list::{PeerList, PeerListLoading};
 use crate::prelude::ConnectionInfo;
-use multimap::MultiMap;
 
 use citadel_crypt::endpoint_crypto_container::PeerSessionCrypto;
 use citadel_crypt::stacked_ratchet::Ratchet;
 use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::fmt::Formatter;
 
+use crate::account_features::FeatureSet;
 use crate::auth::proposed_credentials::ProposedCredentials;
 use crate::auth::DeclaredAuthenticationMode;
 use crate::serialization::SyncIO;
@@ -73,9 +75,11 @@ pub struct ClientNetworkAccountInner<R: Ratchet = StackedRatchet, Fcm: Ratchet =
     /// input: iCID, output: (equal iCID or HyperWAN iCID, CID).
     /// if iCID == 0, then that implies a personal HyperLAN Client
     /// Suppose we input key k to retrieve tuple (i, j). If k == i, then the peer j is in k. If k != i, then j is in i (i.e., a HyperWAN client).
-    pub mutuals: MultiMap<u64, MutualPeer>,
-    /// Toolset which contains all the drills
-    #[serde(bound = "")]
+    ///
+    /// Large peer lists are stored compressed, or indexed if loaded lazily
+    pub mutuals: PeerList,
+    /// Toolset which contains all the drills. Passed through the [`KeyCustody`](crate::key_custody::KeyCustody), if set
+    #[serde(bound = "", with = "crate::key_custody::custodial")]
     pub crypt_container: PeerSessionCrypto<R>,
     /// RTDB config for client-side communications
     #[cfg(feature = "google-services")]
@@ -86,6 +90,8 @@ pub struct ClientNetworkAccountInner<R: Ratchet = StackedRatchet, Fcm: Ratchet =
     pub auth_store: DeclaredAuthenticationMode,
     /// peer id -> key -> sub_key -> bytes
     pub byte_map: HashMap<u64, HashMap<String, HashMap<String, Vec<u8>>>>,
+    /// The capabilities the server grants to this client
+    pub account_features: FeatureSet,
     _pd: PhantomData<Fcm>,
 }
 
@@ -114,10 +120,12 @@ impl<R: Ratchet, Fcm: Ratchet> ClientNetworkAccount<R, Fcm> {
         adjacent_nac: ConnectionInfo,
         auth_store: DeclaredAuthenticationMode,
         base_hyper_ratchet: R,
+        credential_policy: &CredentialPolicy,
     ) -> Result<Self, AccountError> {
         log::trace!(target: "citadel", "Creating CNAC w/valid cid: {:?}", valid_cid);
         // TODO: move this to validation in citadel_proto (or this may be redundant)
-        check_credential_formatting::<_, &str, _>(
+        check_credential_formatting_with::<_, &str, _>(
+            credential_policy,
             auth_store.username(),
             None,
             auth_store.full_name(),
@@ -127,7 +135,7 @@ impl<R: Ratchet, Fcm: Ratchet> ClientNetworkAccount<R, Fcm> {
             Toolset::<R>::new(valid_cid, base_hyper_ratchet),
             is_personal,
         );
-        let mutuals = MultiMap::new();
+        let mutuals = PeerList::default();
         let byte_map = HashMap::default();
         let client_rtdb_config = None;
         let inner = ClientNetworkAccountInner::<R, Fcm> {
@@ -140,6 +148,7 @@ impl<R: Ratchet, Fcm: Ratchet> ClientNetworkAccount<R, Fcm> {
             mutuals,
             crypt_container,
             byte_map,
+            account_features: FeatureSet::default(),
             _pd: Default::default(),
         };
         let this = Self::from(inner);
@@ -177,10 +186,19 @@ impl<R: Ratchet, Fcm: Ratchet> ClientNetworkAccount<R, Fcm> {
         hyper_ratchet: R,
         auth_store: DeclaredAuthenticationMode,
         conn_info: ConnectionInfo,
+        credential_policy: &CredentialPolicy,
     ) -> Result<Self, AccountError> {
         const IS_PERSONAL: bool = true;
         // We supply none to the valid cid
-        Self::new(valid_cid, IS_PERSONAL, conn_info, auth_store, hyper_ratchet).await
+        Self::new(
+            valid_cid,
+            IS_PERSONAL,
+            conn_info,
+            auth_store,
+            hyper_ratchet,
+            credential_policy,
+        )
+        .await
     }
 
     /// Returns the username of this client
@@ -188,6 +206,12 @@ impl<R: Ratchet, Fcm: Ratchet> ClientNetworkAccount<R, Fcm> {
         self.read().auth_store.username().to_string()
     }
 
+    /// Replaces the username of this client. The CID, which was derived from the original
+    /// username, is unchanged
+    pub(crate) fn set_username<T: Into<String>>(&self, username: T) {
+        self.write().auth_store.set_username(username.into());
+    }
+
     /// Checks the credentials for validity. Used for the login process.
     pub async fn validate_credentials(
         &self,
@@ -267,56 +291,42 @@ impl<R: Ratchet, Fcm: Ratchet> ClientNetworkAccount<R, Fcm> {
 
     /// Returns a set of hyperlan peers
     pub(crate) fn get_hyperlan_peer_list(&self) -> Option<Vec<u64>> {
-        let this = self.read();
-        let hyperlan_peers = this.mutuals.get_vec(&HYPERLAN_IDX)?;
-        Some(
-            hyperlan_peers
-                .iter()
-                .map(|peer| peer.cid)
-                .collect::<Vec<u64>>(),
-        )
+        self.read().mutuals.cids(HYPERLAN_IDX)
+    }
+
+    /// Returns the number of hyperlan peers
+    pub(crate) fn get_hyperlan_peer_count(&self) -> usize {
+        self.read().mutuals.len(HYPERLAN_IDX)
     }
 
     /// Returns a set of hyperlan peers
     pub(crate) fn get_hyperlan_peer_mutuals(&self) -> Option<Vec<MutualPeer>> {
-        let this = self.read();
-        this.mutuals.get_vec(&HYPERLAN_IDX).cloned()
+        self.read().mutuals.peers(HYPERLAN_IDX)
     }
 
     /// Returns a set of hyperlan peers
     #[allow(dead_code)]
     pub(crate) fn get_hyperwan_peer_list(&self, icid: u64) -> Option<Vec<u64>> {
-        let this = self.read();
-        let hyperwan_peers = this.mutuals.get_vec(&icid)?;
-        Some(
-            hyperwan_peers
-                .iter()
-                .map(|peer| peer.cid)
-                .collect::<Vec<u64>>(),
-        )
+        self.read().mutuals.cids(icid)
     }
 
     /// Gets the desired HyperLAN peer by CID (clones)
     pub(crate) fn get_hyperlan_peer(&self, cid: u64) -> Option<MutualPeer> {
-        let read = self.read();
-        let hyperlan_peers = read.mutuals.get_vec(&HYPERLAN_IDX)?;
-        hyperlan_peers.iter().find(|peer| peer.cid == cid).cloned()
+        self.read().mutuals.get(HYPERLAN_IDX, cid)
     }
 
     /// Returns the wanted peers
     pub(crate) fn get_hyperlan_peers(&self, peers: impl AsRef<[u64]>) -> Option<Vec<MutualPeer>> {
         let read = self.read();
-        let peers = peers.as_ref();
-        let hyperlan_peers = read.mutuals.get_vec(&HYPERLAN_IDX)?;
+        if !read.mutuals.contains_icid(HYPERLAN_IDX) {
+            return None;
+        }
+
         Some(
             peers
+                .as_ref()
                 .iter()
-                .filter_map(|peer_wanted| {
-                    hyperlan_peers
-                        .iter()
-                        .find(|peer| peer.cid == *peer_wanted)
-                        .cloned()
-                })
+                .filter_map(|peer_wanted| read.mutuals.get(HYPERLAN_IDX, *peer_wanted))
                 .collect(),
         )
     }
@@ -357,17 +367,31 @@ impl<R: Ratchet, Fcm: Ratchet> ClientNetworkAccount<R, Fcm> {
         Ok(())
     }
 
-    /// Deregisters two peers as server
+    /// Deregisters two peers as server. Either both directions are removed, or neither
     #[allow(unused_results)]
     pub(crate) fn deregister_hyperlan_p2p_as_server(
         &self,
-        other: &ClientNetworkAccount<R, Fcm>,
+        other_orig: &ClientNetworkAccount<R, Fcm>,
     ) -> Result<(), AccountError> {
-        self.remove_hyperlan_peer(other.get_cid())
-            .ok_or_else(|| AccountError::ClientNonExists(other.get_cid()))?;
-        other.remove_hyperlan_peer(self.get_cid()).ok_or_else(|| {
-            AccountError::Generic("Could not remove self from other cnac".to_string())
-        })?;
+        let this_cid = self.inner.cid;
+        let other_cid = other_orig.inner.cid;
+
+        // both locks are held until both directions are removed
+        let mut this = self.write();
+        let mut other = other_orig.write();
+
+        if !this.mutuals.contains(HYPERLAN_IDX, other_cid) {
+            return Err(AccountError::ClientNonExists(other_cid));
+        }
+
+        if !other.mutuals.contains(HYPERLAN_IDX, this_cid) {
+            return Err(AccountError::Generic(
+                "Could not remove self from other cnac".to_string(),
+            ));
+        }
+
+        let _ = this.mutuals.remove(HYPERLAN_IDX, other_cid);
+        let _ = other.mutuals.remove(HYPERLAN_IDX, this_cid);
 
         Ok(())
     }
@@ -376,14 +400,10 @@ impl<R: Ratchet, Fcm: Ratchet> ClientNetworkAccount<R, Fcm> {
     pub(crate) fn hyperlan_peers_exist(&self, peers: impl AsRef<[u64]>) -> Vec<bool> {
         let read = self.read();
         let peers = peers.as_ref();
-        if let Some(hyperlan_peers) = read.mutuals.get_vec(&HYPERLAN_IDX) {
+        if read.mutuals.contains_icid(HYPERLAN_IDX) {
             peers
                 .iter()
-                .map(|peer| {
-                    hyperlan_peers
-                        .iter()
-                        .any(|hyperlan_peer| hyperlan_peer.cid == *peer)
-                })
+                .map(|peer| read.mutuals.contains(HYPERLAN_IDX, *peer))
                 .collect()
         } else {
             log::warn!(target: "citadel", "Attempted to check hyperlan list, but it does not exists");
@@ -396,11 +416,7 @@ impl<R: Ratchet, Fcm: Ratchet> ClientNetworkAccount<R, Fcm> {
     ///
     /// Returns true if the data was mutated
     pub(crate) fn synchronize_hyperlan_peer_list(&self, peers: Vec<MutualPeer>) {
-        let mut this = self.write();
-        let ClientNetworkAccountInner::<R, Fcm> { mutuals, .. } = &mut *this;
-
-        let _ = mutuals.remove(&HYPERLAN_IDX);
-        mutuals.insert_many(HYPERLAN_IDX, peers);
+        self.write().mutuals.replace(HYPERLAN_IDX, peers);
     }
 
     /// ONLY run this after you're sure the peer doesn't already exist
@@ -418,21 +434,43 @@ impl<R: Ratchet, Fcm: Ratchet> ClientNetworkAccount<R, Fcm> {
         );
     }
 
+    /// Replaces the username stored for the peer `cid`. Returns false if `cid` is not a peer
+    pub(crate) fn set_hyperlan_peer_username<T: Into<String>>(
+        &self,
+        cid: u64,
+        username: T,
+    ) -> bool {
+        let mut write = self.write();
+        if let Some(peer) = write.mutuals.remove(HYPERLAN_IDX, cid) {
+            write.mutuals.insert(
+                HYPERLAN_IDX,
+                MutualPeer {
+                    username: Some(username.into()),
+                    ..peer
+                },
+            );
+            true
+        } else {
+            false
+        }
+    }
+
     /// Returns Some if success, None otherwise
     #[allow(unused_results)]
     pub(crate) fn remove_hyperlan_peer(&self, cid: u64) -> Option<MutualPeer> {
         log::trace!(target: "citadel", "[remove peer] implicated_cid: {} | peer_cid: {}", self.get_cid(), cid);
         let mut write = self.write();
-        if let Some(hyperlan_peers) = write.mutuals.get_vec_mut(&HYPERLAN_IDX) {
-            if let Some(idx) = hyperlan_peers.iter().position(|peer| peer.cid == cid) {
-                let removed_peer = hyperlan_peers.remove(idx);
-                return Some(removed_peer);
-            } else {
-                log::warn!(target: "citadel", "Peer {} not found within cnac {}", cid, write.cid);
-            }
+        let removed_peer = write.mutuals.remove(HYPERLAN_IDX, cid);
+        if removed_peer.is_none() && write.mutuals.contains_icid(HYPERLAN_IDX) {
+            log::warn!(target: "citadel", "Peer {} not found within cnac {}", cid, write.cid);
         }
 
-        None
+        removed_peer
+    }
+
+    /// Sets how the peer list is loaded the next time this CNAC is deserialized
+    pub fn set_peer_list_loading(&self, loading: PeerListLoading) {
+        self.write().mutuals.set_loading(loading);
     }
 
     /*
@@ -452,7 +490,8 @@ impl<R: Ratchet, Fcm: Ratchet> ClientNetworkAccount<R, Fcm> {
         Ok(serialized)
     }
 
-    /// Returns the metadata for this CNAC
+    /// Returns the metadata for this CNAC. Deactivation is tracked by the backend rather than the
+    /// CNAC itself, so the returned metadata is always marked active
     pub(crate) fn get_metadata(&self) -> CNACMetadata {
         let read = self.read();
         let cid = read.cid;
@@ -466,6 +505,19 @@ impl<R: Ratchet, Fcm: Ratchet> ClientNetworkAccount<R, Fcm> {
             full_name,
             is_personal,
             creation_date,
+            is_active: true,
+        }
+    }
+
+    /// Returns the metadata, hyperlan peer count, and push configuration presence of this CNAC
+    pub(crate) fn get_summary(&self) -> ClientSummary {
+        let metadata = self.get_metadata();
+        let peer_count = self.get_hyperlan_peer_count();
+        let has_push_config = self.read().client_rtdb_config.is_some();
+        ClientSummary {
+            metadata,
+            peer_count,
+            has_push_config,
         }
     }
 
@@ -14,6 +14,7 @@ pub enum BasePath {
     NacDirBase,
     NacDirImpersonal,
     NacDirPersonal,
+    NacDirDeactivated,
     ServerDir,
     ConfigDir,
     VirtualDir,
@@ -31,6 +32,8 @@ pub struct DirectoryStore {
     pub nac_dir_impersonal: String,
     /// Directory for personal accounts
     pub nac_dir_personal: String,
+    /// Directory for deactivated accounts, both personal and impersonal
+    pub nac_dir_deactivated: String,
     /// For server-only files
     pub server_dir: String,
     /// Configuration files for either server or client
@@ -49,6 +52,7 @@ impl DirectoryStore {
             BasePath::NacDirBase => &self.nac_dir_base,
             BasePath::NacDirImpersonal => &self.nac_dir_impersonal,
             BasePath::NacDirPersonal => &self.nac_dir_personal,
+            BasePath::NacDirDeactivated => &self.nac_dir_deactivated,
             BasePath::ServerDir => &self.server_dir,
             BasePath::ConfigDir => &self.config_dir,
             BasePath::VirtualDir => &self.virtual_dir,
@@ -84,6 +88,7 @@ fn setup_directory(mut home_dir: String) -> Result<DirectoryStore, AccountError>
         nac_dir_base: append_to_path(home.clone(), "accounts/"),
         nac_dir_impersonal: append_to_path(home.clone(), "accounts/impersonal/"),
         nac_dir_personal: append_to_path(home.clone(), "accounts/personal/"),
+        nac_dir_deactivated: append_to_path(home.clone(), "accounts/deactivated/"),
         server_dir: hyxe_server_dir,
         config_dir: append_to_path(home.clone(), "config/"),
         virtual_dir: append_to_path(home.clone(), "virtual/"),
@@ -105,11 +110,17 @@ pub fn setup_directories(home_dir: String) -> Result<DirectoryStore, AccountErro
     base.and(mkdir(store.nac_dir_base.as_str()))
         .and(mkdir(store.nac_dir_impersonal.as_str()))
         .and(mkdir(store.nac_dir_personal.as_str()))
+        .and(mkdir(store.nac_dir_deactivated.as_str()))
         .and(mkdir(store.server_dir.as_str()))
         .and(mkdir(store.config_dir.as_str()))
         .and(mkdir(store.virtual_dir.as_str()))
         .and(mkdir(store.file_transfer_dir.as_str()))
-        .map_err(|err| AccountError::IoError(err.to_string()))?;
+        .map_err(|err| {
+            AccountError::io(
+                err,
+                format!("Unable to create the directories under {}", store.home),
+            )
+        })?;
 
     Ok(store)
 }
@@ -1,6 +1,10 @@
+/// For Apple Push Notification service
+pub mod apns;
 /// For services
 #[cfg(feature = "google-services")]
 pub mod google_auth;
+/// For diagnosing push delivery
+pub mod push;
 /// For rtdb
 #[cfg(feature = "google-services")]
 pub mod rtdb;
@@ -30,6 +34,36 @@ pub struct ServicesHandler {
 #[cfg(not(feature = "google-services"))]
 pub struct ServicesHandler;
 
+#[cfg(not(feature = "google-services"))]
+impl ServicesHandler {
+    /// Returns true if a push provider is configured. Always false without the `google-services` feature
+    pub fn push_available(&self) -> bool {
+        false
+    }
+
+    /// Sends a push-wake to `peer_cid` on behalf of `implicated_cid`. Without the
+    /// `google-services` feature, no push provider exists, so this always returns
+    /// [`AccountError::PushUnavailable`](crate::misc::AccountError::PushUnavailable)
+    pub async fn send_push_wake(
+        &self,
+        _payload: Vec<u8>,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<(), crate::misc::AccountError> {
+        log::trace!(target: "citadel", "Skipping push-wake from {implicated_cid} to {peer_cid}: no push provider configured");
+        Err(crate::misc::AccountError::PushUnavailable)
+    }
+
+    /// Sends a test push to `cid`. Without the `google-services` feature, no push provider
+    /// exists, so this always returns [`AccountError::PushUnavailable`](crate::misc::AccountError::PushUnavailable)
+    pub async fn send_test_push(
+        &self,
+        _cid: u64,
+    ) -> Result<crate::external_services::push::PushDeliveryReport, crate::misc::AccountError> {
+        Err(crate::misc::AccountError::PushUnavailable)
+    }
+}
+
 #[cfg(all(any(feature = "google-services"), not(target_family = "wasm")))]
 #[derive(serde::Deserialize, Debug, Default, Clone)]
 /// An object used to determine the settings for the external services
@@ -51,6 +85,9 @@ pub struct ServicesObject {
     pub google_auth_jwt: Option<JsonWebToken>,
     /// Google's real time database config
     pub rtdb: Option<RtdbConfig>,
+    /// Whether the server has a push provider configured. When false, push-wake requests
+    /// fail with [`AccountError::PushUnavailable`](crate::misc::AccountError::PushUnavailable)
+    pub push_available: bool,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Default, Debug, Clone)]
@@ -64,6 +101,8 @@ pub struct RtdbConfig {
 
 #[cfg(all(any(feature = "google-services"), not(target_family = "wasm")))]
 pub mod service {
+    use crate::external_services::push::{PushDeliveryReport, PushProvider, TEST_PUSH_PAYLOAD};
+    use crate::external_services::service_interface::ExternalServiceChannel;
     use crate::external_services::ServicesObject;
     use crate::misc::AccountError;
     use firebase_rtdb::FirebaseRTDB;
@@ -82,9 +121,42 @@ pub mod service {
             }
 
             ret.rtdb = self.rtdb_config.clone();
+            ret.push_available = self.push_available();
 
             Ok(ret)
         }
+
+        /// Returns true if a push provider is configured
+        pub fn push_available(&self) -> bool {
+            self.rtdb_root_instance.is_some()
+        }
+
+        /// Sends a push-wake to `peer_cid` on behalf of `implicated_cid`. If no push provider is
+        /// configured, the attempt is skipped and [`AccountError::PushUnavailable`] is returned
+        pub async fn send_push_wake(
+            &self,
+            payload: Vec<u8>,
+            implicated_cid: u64,
+            peer_cid: u64,
+        ) -> Result<(), AccountError> {
+            let mut rtdb_instance = self.rtdb_root_instance.clone().ok_or_else(|| {
+                log::trace!(target: "citadel", "Skipping push-wake from {implicated_cid} to {peer_cid}: no push provider configured");
+                AccountError::PushUnavailable
+            })?;
+
+            rtdb_instance.refresh()?;
+            rtdb_instance.send(payload, implicated_cid, peer_cid).await
+        }
+
+        /// Sends a test push to `cid` through the configured push provider. If none is
+        /// configured, [`AccountError::PushUnavailable`] is returned
+        pub async fn send_test_push(&self, cid: u64) -> Result<PushDeliveryReport, AccountError> {
+            self.rtdb_root_instance
+                .as_ref()
+                .ok_or(AccountError::PushUnavailable)?
+                .send_test_push(cid, TEST_PUSH_PAYLOAD)
+                .await
+        }
     }
 
     impl crate::external_services::ServicesConfig {
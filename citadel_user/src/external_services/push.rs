@@ -0,0 +1,143 @@
+use crate::misc::AccountError;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+/// The payload delivered by [`AccountManager::send_test_push`](crate::account_manager::AccountManager::send_test_push).
+/// Clients do not treat it as a wake
+pub const TEST_PUSH_PAYLOAD: &[u8] = b"citadel-test-push";
+
+/// The outcome of a push delivery, as reported by the push provider
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PushDeliveryStatus {
+    /// The provider accepted the notification
+    Delivered,
+    /// The provider rejected the device token of the recipient, which should no longer be used
+    TokenInvalid,
+    /// The provider rejected the credentials this node authenticates with. The token of the
+    /// recipient may still be valid
+    ProviderAuthFailed,
+    /// The provider is throttling deliveries
+    RateLimited,
+    /// The provider rejected the notification for any other reason
+    Failed,
+    /// Push is disabled on this node, so the notification was discarded without being sent
+    Dropped,
+}
+
+/// The result of a test push, meant for display when diagnosing push delivery
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct PushDeliveryReport {
+    /// The CID the test push was sent to
+    pub cid: u64,
+    /// The outcome reported by the provider
+    pub status: PushDeliveryStatus,
+    /// The raw response of the provider
+    pub provider_response: String,
+}
+
+/// A push provider capable of delivering a test notification. Overrides the provider configured
+/// through the external services when set in
+/// [`ServerMiscSettings`](crate::server_misc_settings::ServerMiscSettings)
+#[async_trait]
+pub trait PushProvider: Send + Sync {
+    /// Delivers `payload` to `cid` without waking its device, returning the provider's response.
+    /// Errors are reserved for failures that occur before the provider responds
+    async fn send_test_push(
+        &self,
+        cid: u64,
+        payload: &[u8],
+    ) -> Result<PushDeliveryReport, AccountError>;
+
+    /// Delivers a push-wake to `peer_cid` on behalf of `implicated_cid`. By default, providers
+    /// only deliver test pushes, and this returns [`AccountError::PushUnavailable`]
+    async fn send_push_wake(
+        &self,
+        _payload: Vec<u8>,
+        _implicated_cid: u64,
+        _peer_cid: u64,
+    ) -> Result<(), AccountError> {
+        Err(AccountError::PushUnavailable)
+    }
+}
+
+/// A [`PushProvider`] for deployments without push, such as LAN-only installs. Every
+/// notification is logged and discarded, so no external push service needs to be configured
+#[derive(Default, Debug, Clone, Copy)]
+pub struct NoopPushProvider;
+
+#[async_trait]
+impl PushProvider for NoopPushProvider {
+    async fn send_test_push(
+        &self,
+        cid: u64,
+        _payload: &[u8],
+    ) -> Result<PushDeliveryReport, AccountError> {
+        log::trace!(target: "citadel", "Dropping test push to {cid}: push is disabled");
+        Ok(PushDeliveryReport {
+            cid,
+            status: PushDeliveryStatus::Dropped,
+            provider_response: "push is disabled on this node".to_string(),
+        })
+    }
+
+    async fn send_push_wake(
+        &self,
+        _payload: Vec<u8>,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<(), AccountError> {
+        log::trace!(target: "citadel", "Dropping push-wake from {implicated_cid} to {peer_cid}: push is disabled");
+        Ok(())
+    }
+}
+
+impl PushDeliveryStatus {
+    /// Classifies the raw error response of a provider
+    pub fn from_error_response(response: &str) -> Self {
+        let response = response.to_lowercase();
+        if ["rate limit", "quota", "too many"]
+            .iter()
+            .any(|needle| response.contains(needle))
+        {
+            Self::RateLimited
+        } else if ["auth", "permission", "credential"]
+            .iter()
+            .any(|needle| response.contains(needle))
+        {
+            Self::ProviderAuthFailed
+        } else if response.contains("token") {
+            Self::TokenInvalid
+        } else {
+            Self::Failed
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::external_services::push::PushDeliveryStatus;
+
+    #[test]
+    fn classifies_error_responses() {
+        assert_eq!(
+            PushDeliveryStatus::from_error_response(r#"{"error": "Auth token is expired"}"#),
+            PushDeliveryStatus::ProviderAuthFailed
+        );
+        assert_eq!(
+            PushDeliveryStatus::from_error_response(r#"{"error": "Permission denied"}"#),
+            PushDeliveryStatus::ProviderAuthFailed
+        );
+        assert_eq!(
+            PushDeliveryStatus::from_error_response(r#"{"error": "Invalid registration token"}"#),
+            PushDeliveryStatus::TokenInvalid
+        );
+        assert_eq!(
+            PushDeliveryStatus::from_error_response("Too Many Requests"),
+            PushDeliveryStatus::RateLimited
+        );
+        assert_eq!(
+            PushDeliveryStatus::from_error_response("Internal Server Error"),
+            PushDeliveryStatus::Failed
+        );
+    }
+}
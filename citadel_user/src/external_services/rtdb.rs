@@ -1,3 +1,4 @@
+use crate::external_services::push::{PushDeliveryReport, PushDeliveryStatus, PushProvider};
 use crate::external_services::service_interface::{ExternalServiceChannel, RawExternalPacket};
 use crate::external_services::JsonWebToken;
 use crate::misc::AccountError;
@@ -112,3 +113,50 @@ impl ExternalServiceChannel for RtdbInstance {
             .map_err(|err| AccountError::Generic(err.inner))?)
     }
 }
+
+#[async_trait]
+impl PushProvider for RtdbInstance {
+    async fn send_test_push(
+        &self,
+        cid: u64,
+        payload: &[u8],
+    ) -> Result<PushDeliveryReport, AccountError> {
+        let mut instance = self.clone();
+        instance.refresh()?;
+        // written outside the peers' packet nodes, so the device does not treat it as a wake
+        let response = instance
+            .root()
+            .await
+            .map_err(|err| AccountError::Generic(err.inner))?
+            .child("users")
+            .child(cid.to_string())
+            .final_node("diagnostics")
+            .post(payload)
+            .await;
+
+        let (status, provider_response) = match response {
+            Ok(response) => (PushDeliveryStatus::Delivered, response),
+            Err(err) => (
+                PushDeliveryStatus::from_error_response(&err.inner),
+                err.inner,
+            ),
+        };
+
+        Ok(PushDeliveryReport {
+            cid,
+            status,
+            provider_response,
+        })
+    }
+
+    async fn send_push_wake(
+        &self,
+        payload: Vec<u8>,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<(), AccountError> {
+        let mut instance = self.clone();
+        instance.refresh()?;
+        instance.send(payload, implicated_cid, peer_cid).await
+    }
+}
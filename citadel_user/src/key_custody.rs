@@ -0,0 +1,67 @@
+use crate::misc::AccountError;
+use std::sync::{Arc, OnceLock};
+
+/// Wraps the key material of each CNAC before it is serialized, and unwraps it once deserialized,
+/// so that stored CNACs only ever contain wrapped keys. Implementations may delegate to an HSM or
+/// KMS for deployments where ratchet material must never be stored in plaintext
+pub trait KeyCustody: Send + Sync {
+    /// Wraps the serialized key material of a CNAC
+    fn wrap(&self, key_material: &[u8]) -> Result<Vec<u8>, AccountError>;
+    /// Recovers the serialized key material from the output of [`Self::wrap`]
+    fn unwrap(&self, wrapped: &[u8]) -> Result<Vec<u8>, AccountError>;
+}
+
+static KEY_CUSTODY: OnceLock<Option<Arc<dyn KeyCustody>>> = OnceLock::new();
+
+/// Sets the custody of the key material of every CNAC serialized or deserialized by this process.
+/// Until set, key material is stored as-is. The custody may only be set once, and only before the
+/// first CNAC is serialized or deserialized
+///
+/// Migration note: the wrapped key material is stored in a different format than plaintext key
+/// material, so CNACs stored before a custody was set cannot be loaded afterwards, and vice versa
+pub fn set_key_custody<T: KeyCustody + 'static>(custody: T) -> Result<(), AccountError> {
+    KEY_CUSTODY
+        .set(Some(Arc::new(custody)))
+        .map_err(|_| AccountError::msg("The key custody was already set or in use"))
+}
+
+fn key_custody() -> Option<&'static Arc<dyn KeyCustody>> {
+    KEY_CUSTODY.get_or_init(|| None).as_ref()
+}
+
+/// Passes a field through the [`KeyCustody`] set by [`set_key_custody`], if any. For use with
+/// `#[serde(with = "crate::key_custody::custodial")]`
+pub(crate) mod custodial {
+    use super::key_custody;
+    use crate::serialization::{bytes_to_type, type_to_bytes};
+    use serde::de::{DeserializeOwned, Error as _};
+    use serde::ser::Error as _;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<T: Serialize, S: Serializer>(
+        key_material: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if let Some(custody) = key_custody() {
+            let key_material = type_to_bytes(key_material).map_err(S::Error::custom)?;
+            custody
+                .wrap(&key_material)
+                .map_err(S::Error::custom)?
+                .serialize(serializer)
+        } else {
+            key_material.serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, T: DeserializeOwned, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        if let Some(custody) = key_custody() {
+            let wrapped = Vec::<u8>::deserialize(deserializer)?;
+            let key_material = custody.unwrap(&wrapped).map_err(D::Error::custom)?;
+            bytes_to_type(&key_material).map_err(D::Error::custom)
+        } else {
+            T::deserialize(deserializer)
+        }
+    }
+}
@@ -48,11 +48,17 @@ pub mod network_account;
 /// evoc_null(web 3.0) => void && let void alloc finite && set network evoc_null(!HyperWAN)
 pub mod client_account;
 
+/// The encrypted format in which accounts are exported from, and imported into, a node
+pub mod account_export;
+/// The capabilities granted to each account
+pub mod account_features;
 #[cfg(all(feature = "filesystem", not(target_family = "wasm")))]
 /// This provides methods to load all locally-stored files
 pub mod account_loader;
 /// The server in legacy_citadel_proto requires a means of handling the user database. This module contains the means of achieving this
 pub mod account_manager;
+/// The cap on the number of accounts registered to a node
+mod account_quota;
 /// For authentication
 pub mod auth;
 /// For handling different I/O operations
@@ -62,9 +68,17 @@ pub mod backend;
 pub mod directory_store;
 /// For services
 pub mod external_services;
+/// Custody of the key material within stored CNACs, such as by an HSM or KMS
+pub mod key_custody;
 /// For errors
 pub mod misc;
+/// The mutual peers of each account, which may be loaded eagerly or on demand
+pub mod peer_list;
+/// Limits on how often a source address may register accounts
+pub mod registration_limit;
 /// Contains basic subroutines for serialization
 pub mod serialization;
 ///
 pub mod server_misc_settings;
+/// A key-value store kept in sync between both sides of a relationship through the byte map
+pub mod synced_kv;
@@ -0,0 +1,455 @@
+use crate::client_account::MutualPeer;
+use crate::misc::compressed_peer_list::{self, IndexedPeer, PeerListEncoding};
+use crate::misc::AccountError;
+use crate::serialization::SyncIO;
+use multimap::MultiMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// Determines how much of a peer list is deserialized along with its CNAC
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum PeerListLoading {
+    /// Every entry is deserialized along with the CNAC. The list is stored compressed, making
+    /// this the better choice for small lists
+    #[default]
+    Eager,
+    /// Only an index of CIDs is deserialized along with the CNAC. Each entry is deserialized the
+    /// first time it is accessed, avoiding the cost of materializing large lists when only a few
+    /// peers are needed
+    Lazy,
+}
+
+/// The mutual peers of a CNAC, keyed by the interserver CID of each peer
+#[derive(Default)]
+pub struct PeerList {
+    loading: PeerListLoading,
+    entries: HashMap<u64, Vec<PeerListEntry>>,
+}
+
+struct PeerListEntry {
+    cid: u64,
+    bytes: Option<Vec<u8>>,
+    peer: OnceLock<MutualPeer>,
+}
+
+impl PeerListEntry {
+    fn loaded(peer: MutualPeer) -> Self {
+        Self {
+            cid: peer.cid,
+            bytes: None,
+            peer: OnceLock::from(peer),
+        }
+    }
+
+    /// Returns the peer, deserializing it if this is the first access
+    fn peer(&self) -> Option<&MutualPeer> {
+        if let Some(peer) = self.peer.get() {
+            return Some(peer);
+        }
+
+        match MutualPeer::deserialize_from_vector(self.bytes.as_deref()?) {
+            Ok(peer) => Some(self.peer.get_or_init(|| peer)),
+            Err(err) => {
+                log::warn!(target: "citadel", "Unable to deserialize entry for peer {}: {:?}", self.cid, err);
+                None
+            }
+        }
+    }
+
+    fn into_peer(self) -> Option<MutualPeer> {
+        let _ = self.peer();
+        self.peer.into_inner()
+    }
+}
+
+impl PeerList {
+    /// Creates an empty list that serializes using `loading`
+    pub fn new(loading: PeerListLoading) -> Self {
+        Self {
+            loading,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Returns the loading mode used once this list is serialized
+    pub fn loading(&self) -> PeerListLoading {
+        self.loading
+    }
+
+    /// Sets the loading mode used once this list is next serialized
+    pub fn set_loading(&mut self, loading: PeerListLoading) {
+        self.loading = loading;
+    }
+
+    /// Returns the peer `cid` within `icid`, deserializing only that entry
+    pub fn get(&self, icid: u64, cid: u64) -> Option<MutualPeer> {
+        self.entries
+            .get(&icid)?
+            .iter()
+            .find(|entry| entry.cid == cid)?
+            .peer()
+            .cloned()
+    }
+
+    /// Returns every peer within `icid`, or None if no peer was ever added to `icid`
+    pub fn peers(&self, icid: u64) -> Option<Vec<MutualPeer>> {
+        Some(
+            self.entries
+                .get(&icid)?
+                .iter()
+                .filter_map(|entry| entry.peer().cloned())
+                .collect(),
+        )
+    }
+
+    /// Returns the CIDs of every peer within `icid` without deserializing any entry, or None if no
+    /// peer was ever added to `icid`
+    pub fn cids(&self, icid: u64) -> Option<Vec<u64>> {
+        Some(
+            self.entries
+                .get(&icid)?
+                .iter()
+                .map(|entry| entry.cid)
+                .collect(),
+        )
+    }
+
+    /// Returns the number of peers within `icid`
+    pub fn len(&self, icid: u64) -> usize {
+        self.entries.get(&icid).map(Vec::len).unwrap_or(0)
+    }
+
+    /// Returns true if no peer exists within any interserver CID
+    pub fn is_empty(&self) -> bool {
+        self.entries.values().all(Vec::is_empty)
+    }
+
+    /// Returns true if `cid` is a peer within `icid`
+    pub fn contains(&self, icid: u64, cid: u64) -> bool {
+        self.entries
+            .get(&icid)
+            .map(|entries| entries.iter().any(|entry| entry.cid == cid))
+            .unwrap_or(false)
+    }
+
+    /// Returns true if any peer was ever added to `icid`
+    pub fn contains_icid(&self, icid: u64) -> bool {
+        self.entries.contains_key(&icid)
+    }
+
+    /// Adds `peer` within `icid`
+    pub fn insert(&mut self, icid: u64, peer: MutualPeer) {
+        self.entries
+            .entry(icid)
+            .or_default()
+            .push(PeerListEntry::loaded(peer));
+    }
+
+    /// Removes the peer `cid` within `icid`, returning it if it existed
+    pub fn remove(&mut self, icid: u64, cid: u64) -> Option<MutualPeer> {
+        let entries = self.entries.get_mut(&icid)?;
+        let idx = entries.iter().position(|entry| entry.cid == cid)?;
+        entries.remove(idx).into_peer()
+    }
+
+    /// Replaces every peer within `icid` with `peers`
+    pub fn replace(&mut self, icid: u64, peers: Vec<MutualPeer>) {
+        let _ = self
+            .entries
+            .insert(icid, peers.into_iter().map(PeerListEntry::loaded).collect());
+    }
+
+    /// Returns the number of entries that have been deserialized
+    pub fn loaded_len(&self) -> usize {
+        self.entries
+            .values()
+            .flatten()
+            .filter(|entry| entry.peer.get().is_some())
+            .count()
+    }
+
+    fn encode(&self) -> Result<PeerListEncoding, AccountError> {
+        match self.loading {
+            PeerListLoading::Eager => {
+                let mut peers = MultiMap::new();
+                for (icid, entries) in &self.entries {
+                    // keeps the interserver CID even if it no longer contains peers
+                    peers.insert_many(
+                        *icid,
+                        entries.iter().filter_map(|entry| entry.peer().cloned()),
+                    );
+                }
+
+                compressed_peer_list::encode(&peers)
+            }
+
+            PeerListLoading::Lazy => {
+                let mut indexed = Vec::new();
+                for (icid, entries) in &self.entries {
+                    for entry in entries {
+                        let bytes = match (entry.bytes.as_ref(), entry.peer.get()) {
+                            (Some(bytes), _) => bytes.clone(),
+                            (None, Some(peer)) => peer.serialize_to_vector()?,
+                            (None, None) => continue,
+                        };
+
+                        indexed.push(IndexedPeer {
+                            icid: *icid,
+                            cid: entry.cid,
+                            bytes,
+                        });
+                    }
+                }
+
+                Ok(PeerListEncoding::Indexed(indexed))
+            }
+        }
+    }
+
+    fn decode(encoding: PeerListEncoding) -> Result<Self, AccountError> {
+        if let PeerListEncoding::Indexed(indexed) = encoding {
+            let mut this = Self::new(PeerListLoading::Lazy);
+            for IndexedPeer { icid, cid, bytes } in indexed {
+                this.entries.entry(icid).or_default().push(PeerListEntry {
+                    cid,
+                    bytes: Some(bytes),
+                    peer: OnceLock::new(),
+                });
+            }
+
+            Ok(this)
+        } else {
+            let peers: MultiMap<u64, MutualPeer> = compressed_peer_list::decode(encoding)?;
+            let mut this = Self::new(PeerListLoading::Eager);
+            for (icid, peers) in peers {
+                this.replace(icid, peers);
+            }
+
+            Ok(this)
+        }
+    }
+}
+
+/// The difference between the peer list of a client and the peer list the server sent it, as
+/// returned when the client synchronizes its list. Peers are matched by CID
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PeerListDelta {
+    /// Peers the server lists, but the client did not
+    pub added: Vec<MutualPeer>,
+    /// Peers the client listed, but the server no longer does
+    pub removed: Vec<MutualPeer>,
+    /// Peers listed by both whose username changed
+    pub changed: Vec<MutualPeer>,
+}
+
+impl PeerListDelta {
+    /// Computes the changes that turn `old` into `new`. Entries are ordered as in the list they
+    /// are taken from
+    pub fn between(old: &[MutualPeer], new: &[MutualPeer]) -> Self {
+        let old_by_cid = old
+            .iter()
+            .map(|peer| (peer.cid, peer))
+            .collect::<HashMap<_, _>>();
+        let new_by_cid = new
+            .iter()
+            .map(|peer| (peer.cid, peer))
+            .collect::<HashMap<_, _>>();
+
+        let mut delta = Self::default();
+        for peer in new {
+            match old_by_cid.get(&peer.cid) {
+                None => delta.added.push(peer.clone()),
+                Some(existing) if existing.username != peer.username => {
+                    delta.changed.push(peer.clone())
+                }
+                Some(_) => {}
+            }
+        }
+
+        delta.removed = old
+            .iter()
+            .filter(|peer| !new_by_cid.contains_key(&peer.cid))
+            .cloned()
+            .collect();
+        delta
+    }
+
+    /// Returns true if the lists are the same, in which case nothing needs to be saved
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl Serialize for PeerList {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.encode()
+            .map_err(serde::ser::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PeerList {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Self::decode(PeerListEncoding::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client_account::{MutualPeer, HYPERLAN_IDX};
+    use crate::peer_list::{PeerList, PeerListDelta, PeerListLoading};
+    use crate::serialization::SyncIO;
+    use multimap::MultiMap;
+    use rstest::rstest;
+
+    fn synthetic_peer(cid: u64) -> MutualPeer {
+        MutualPeer {
+            parent_icid: HYPERLAN_IDX,
+            cid,
+            username: Some(format!("peer_username_{cid}")),
+        }
+    }
+
+    fn synthetic_peer_list(loading: PeerListLoading, len: u64) -> PeerList {
+        let mut peers = PeerList::new(loading);
+        for cid in 0..len {
+            peers.insert(HYPERLAN_IDX, synthetic_peer(1000 + cid));
+        }
+
+        peers
+    }
+
+    #[test]
+    fn test_peer_list_delta_add_only() {
+        let old = vec![synthetic_peer(1), synthetic_peer(2)];
+        let new = vec![synthetic_peer(1), synthetic_peer(2), synthetic_peer(3)];
+        let delta = PeerListDelta::between(&old, &new);
+        assert_eq!(delta.added, vec![synthetic_peer(3)]);
+        assert!(delta.removed.is_empty());
+        assert!(delta.changed.is_empty());
+        assert!(!delta.is_empty());
+
+        // the first sync adds every peer
+        let delta = PeerListDelta::between(&[], &new);
+        assert_eq!(delta.added, new);
+    }
+
+    #[test]
+    fn test_peer_list_delta_remove_only() {
+        let old = vec![synthetic_peer(1), synthetic_peer(2), synthetic_peer(3)];
+        let new = vec![synthetic_peer(2)];
+        let delta = PeerListDelta::between(&old, &new);
+        assert!(delta.added.is_empty());
+        assert_eq!(delta.removed, vec![synthetic_peer(1), synthetic_peer(3)]);
+        assert!(delta.changed.is_empty());
+
+        let delta = PeerListDelta::between(&old, &[]);
+        assert_eq!(delta.removed, old);
+    }
+
+    #[test]
+    fn test_peer_list_delta_mixed() {
+        let old = vec![synthetic_peer(1), synthetic_peer(2), synthetic_peer(3)];
+        let renamed = MutualPeer {
+            username: Some("renamed".to_string()),
+            ..synthetic_peer(2)
+        };
+        let new = vec![renamed.clone(), synthetic_peer(3), synthetic_peer(4)];
+        let delta = PeerListDelta::between(&old, &new);
+        assert_eq!(delta.added, vec![synthetic_peer(4)]);
+        assert_eq!(delta.removed, vec![synthetic_peer(1)]);
+        assert_eq!(delta.changed, vec![renamed]);
+        assert_eq!(PeerListDelta::between(&old, &old), PeerListDelta::default());
+    }
+
+    #[rstest]
+    #[case(PeerListLoading::Eager)]
+    #[case(PeerListLoading::Lazy)]
+    fn test_peer_list_round_trip(#[case] loading: PeerListLoading) {
+        let mut peers = synthetic_peer_list(loading, 100);
+        assert_eq!(peers.remove(HYPERLAN_IDX, 1050), Some(synthetic_peer(1050)));
+
+        let bytes = peers.serialize_to_vector().unwrap();
+        let decoded = PeerList::deserialize_from_vector(&bytes).unwrap();
+        assert_eq!(decoded.loading(), loading);
+        assert_eq!(decoded.len(HYPERLAN_IDX), 99);
+        assert!(!decoded.contains(HYPERLAN_IDX, 1050));
+        assert_eq!(decoded.peers(HYPERLAN_IDX), peers.peers(HYPERLAN_IDX));
+    }
+
+    #[test]
+    fn test_lazy_peer_list_lookup_loads_single_entry() {
+        const LEN: u64 = 20_000;
+        let bytes = synthetic_peer_list(PeerListLoading::Lazy, LEN)
+            .serialize_to_vector()
+            .unwrap();
+        let peers = PeerList::deserialize_from_vector(&bytes).unwrap();
+        assert_eq!(peers.len(HYPERLAN_IDX), LEN as usize);
+        assert_eq!(peers.loaded_len(), 0);
+
+        assert_eq!(
+            peers.get(HYPERLAN_IDX, 1000 + LEN / 2),
+            Some(synthetic_peer(1000 + LEN / 2))
+        );
+        assert!(peers.contains(HYPERLAN_IDX, 1000));
+        assert_eq!(peers.loaded_len(), 1);
+
+        // the eager encoding materializes every entry upon deserialization
+        let bytes = synthetic_peer_list(PeerListLoading::Eager, LEN)
+            .serialize_to_vector()
+            .unwrap();
+        let peers = PeerList::deserialize_from_vector(&bytes).unwrap();
+        assert_eq!(peers.loaded_len(), LEN as usize);
+    }
+
+    #[test]
+    fn test_peer_list_reads_legacy_mutuals() {
+        // the layouts written before peer lists could be loaded lazily, which must still decode
+        #[derive(serde::Serialize)]
+        struct LegacyMutualPeer {
+            parent_icid: u64,
+            cid: u64,
+            username: Option<String>,
+        }
+
+        #[derive(serde::Serialize)]
+        enum LegacyPeerListEncoding {
+            Raw(Vec<u8>),
+            Deflated(Vec<u8>),
+        }
+
+        let legacy_bytes = |len: u64, deflate: bool| {
+            let mut mutuals = MultiMap::new();
+            for cid in 0..len {
+                let peer = synthetic_peer(cid);
+                mutuals.insert(
+                    HYPERLAN_IDX,
+                    LegacyMutualPeer {
+                        parent_icid: peer.parent_icid,
+                        cid: peer.cid,
+                        username: peer.username,
+                    },
+                );
+            }
+
+            let bytes = crate::serialization::type_to_bytes(&mutuals).unwrap();
+            let encoding = if deflate {
+                LegacyPeerListEncoding::Deflated(miniz_oxide::deflate::compress_to_vec(&bytes, 6))
+            } else {
+                LegacyPeerListEncoding::Raw(bytes)
+            };
+            crate::serialization::type_to_bytes(&encoding).unwrap()
+        };
+
+        for (len, deflate) in [(3, false), (2000, true)] {
+            let peers = PeerList::deserialize_from_vector(&legacy_bytes(len, deflate)).unwrap();
+            assert_eq!(peers.loading(), PeerListLoading::Eager);
+            assert_eq!(peers.len(HYPERLAN_IDX), len as usize);
+            assert_eq!(
+                peers.get(HYPERLAN_IDX, len - 1),
+                Some(synthetic_peer(len - 1))
+            );
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use crate::misc::AccountError;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+/// Decides whether a registration attempt from a source address may proceed. Implementations
+/// backed by shared storage, such as redis, allow a limit to be enforced across a cluster of nodes
+#[async_trait]
+pub trait RegistrationRateLimiter: Send + Sync {
+    /// Records a registration attempt from `source`. Returns false if the attempt exceeds the
+    /// limit and must be rejected
+    async fn try_acquire(&self, source: SocketAddr) -> Result<bool, AccountError>;
+}
+
+/// Allows up to `max_per_window` registration attempts per source IP address within each fixed
+/// window, counted by this node alone. A limit of 0 disables the limiter
+pub struct LocalRegistrationRateLimiter {
+    max_per_window: usize,
+    window: Duration,
+    sources: Mutex<HashMap<IpAddr, AttemptWindow>>,
+}
+
+struct AttemptWindow {
+    started: Instant,
+    attempts: usize,
+}
+
+/// Once this many sources are tracked, sources whose window has elapsed are forgotten
+const PRUNE_THRESHOLD: usize = 1024;
+
+impl LocalRegistrationRateLimiter {
+    pub fn new(max_per_window: usize, window: Duration) -> Self {
+        Self {
+            max_per_window,
+            window,
+            sources: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Allows up to `max_per_minute` registration attempts per source IP address each minute
+    pub fn per_minute(max_per_minute: usize) -> Self {
+        Self::new(max_per_minute, Duration::from_secs(60))
+    }
+
+    fn try_acquire_at(&self, source: IpAddr, now: Instant) -> bool {
+        if self.max_per_window == 0 {
+            return true;
+        }
+
+        let mut sources = self.sources.lock();
+        if sources.len() >= PRUNE_THRESHOLD {
+            sources.retain(|_, window| now.duration_since(window.started) < self.window);
+        }
+
+        let window = sources.entry(source).or_insert(AttemptWindow {
+            started: now,
+            attempts: 0,
+        });
+
+        if now.duration_since(window.started) >= self.window {
+            window.started = now;
+            window.attempts = 0;
+        }
+
+        if window.attempts >= self.max_per_window {
+            log::warn!(target: "citadel", "Source {} exceeded {} registration attempts per {:?}", source, self.max_per_window, self.window);
+            return false;
+        }
+
+        window.attempts += 1;
+        true
+    }
+}
+
+#[async_trait]
+impl RegistrationRateLimiter for LocalRegistrationRateLimiter {
+    async fn try_acquire(&self, source: SocketAddr) -> Result<bool, AccountError> {
+        // the port differs between connections from the same host, so only the IP is counted
+        Ok(self.try_acquire_at(source.ip(), Instant::now()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::registration_limit::LocalRegistrationRateLimiter;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::{Duration, Instant};
+
+    const SOURCE: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn rejects_attempts_over_the_limit() {
+        let limiter = LocalRegistrationRateLimiter::new(3, WINDOW);
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.try_acquire_at(SOURCE, now));
+        }
+
+        assert!(!limiter.try_acquire_at(SOURCE, now));
+
+        // other sources are unaffected
+        assert!(limiter.try_acquire_at(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), now));
+
+        // once the window elapses, the source may register again
+        assert!(limiter.try_acquire_at(SOURCE, now + WINDOW));
+    }
+
+    #[test]
+    fn zero_limit_disables_limiter() {
+        let limiter = LocalRegistrationRateLimiter::new(0, WINDOW);
+        let now = Instant::now();
+
+        for _ in 0..100 {
+            assert!(limiter.try_acquire_at(SOURCE, now));
+        }
+    }
+}
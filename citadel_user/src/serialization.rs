@@ -93,14 +93,14 @@ pub fn bincode_config() -> bincode2::Config {
 }
 
 /// Deserializes the bytes, T, into type D
-fn bytes_to_type<'a, D: Deserialize<'a>>(bytes: &'a [u8]) -> Result<D, AccountError> {
+pub(crate) fn bytes_to_type<'a, D: Deserialize<'a>>(bytes: &'a [u8]) -> Result<D, AccountError> {
     bincode_config()
         .deserialize(bytes)
         .map_err(|err| AccountError::IoError(err.to_string()))
 }
 
 /// Converts a type, D to Vec<u8>
-fn type_to_bytes<D: Serialize>(input: D) -> Result<Vec<u8>, AccountError> {
+pub(crate) fn type_to_bytes<D: Serialize>(input: D) -> Result<Vec<u8>, AccountError> {
     bincode_config()
         .serialize(&input)
         .map_err(|err| AccountError::IoError(err.to_string()))
@@ -1,14 +1,228 @@
+use crate::account_features::FeatureSet;
+use crate::account_manager::AccountManagerListener;
+use crate::backend::{CidGenerator, UsernameHashCidGenerator};
+use crate::external_services::push::PushProvider;
+use crate::misc::{Clock, CredentialPolicy};
+use crate::peer_list::PeerListLoading;
+use crate::registration_limit::RegistrationRateLimiter;
+use citadel_crypt::endpoint_crypto_container::DEFAULT_TRUNCATION_GRACE;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Determines how a new connection is handled once the number of pending handshakes is capped
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum PendingHandshakeOverflow {
+    /// The new connection is dropped. The reported error hints when a slot is expected to free
+    #[default]
+    Reject,
+    /// The oldest pending handshake is dropped to make room for the new connection
+    EvictOldest,
+}
+
+/// Determines how a connect is handled once its CID holds `max_sessions_per_cid` sessions
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum OnSessionLimit {
+    /// The connect is rejected
+    #[default]
+    Reject,
+    /// The oldest session of the CID is ended to make room for the connect
+    EvictOldest,
+}
+
+/// The largest inbound packet, in bytes, a server accepts for each stage of the handshake. Packets
+/// over the limit end the session before they are buffered. The defaults leave ample room for the
+/// key transfer of the highest security level, as well as for maximum-length credentials
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub struct HandshakePayloadLimits {
+    /// The limit for pre-connect packets, which carry the key transfer of the session
+    pub pre_connect: usize,
+    /// The limit for register packets, which carry the key transfer and the proposed credentials
+    pub register: usize,
+    /// The limit for connect packets, which carry the credentials and connect metadata
+    pub connect: usize,
+}
+
+impl HandshakePayloadLimits {
+    /// The limit of the stage with the largest limit
+    pub fn max(&self) -> usize {
+        self.pre_connect.max(self.register).max(self.connect)
+    }
+}
+
+impl Default for HandshakePayloadLimits {
+    fn default() -> Self {
+        Self {
+            pre_connect: 4 * 1024 * 1024,
+            register: 4 * 1024 * 1024,
+            connect: 64 * 1024,
+        }
+    }
+}
+
+/// Allows a burst of up to `burst` inbound commands, refilled at `per_second` commands each second
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CommandRateLimit {
+    pub burst: u32,
+    pub per_second: u32,
+}
+
+impl CommandRateLimit {
+    pub fn new(burst: u32, per_second: u32) -> Self {
+        Self { burst, per_second }
+    }
+}
+
+/// The rate at which a session accepts each type of inbound control command from its remote end.
+/// Commands over the limit are dropped. Keep-alives and data, such as group and UDP packets, are
+/// never limited. No command is limited by default
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct InboundCommandLimits {
+    pub pre_connect: Option<CommandRateLimit>,
+    pub register: Option<CommandRateLimit>,
+    pub connect: Option<CommandRateLimit>,
+    pub disconnect: Option<CommandRateLimit>,
+    /// Rekey requests, which each require a key agreement
+    pub drill_update: Option<CommandRateLimit>,
+    pub deregister: Option<CommandRateLimit>,
+    /// Peer commands, including those relayed by the server on behalf of other clients
+    pub peer_cmd: Option<CommandRateLimit>,
+    /// File transfer requests and their responses
+    pub file: Option<CommandRateLimit>,
+    pub hole_punch: Option<CommandRateLimit>,
+}
+
 /// Miscellaneous settings for a node serving connections
 #[derive(Clone)]
 pub struct ServerMiscSettings {
     /// If enabled, allows inbound connections to use no credentials when logging-in
     pub allow_passwordless: bool,
+    /// The number of consecutive failed connect attempts a single source may make before
+    /// further attempts are rejected for a cooldown window. A value of 0 disables the cooldown
+    pub max_failed_connect_attempts: usize,
+    /// The initial cooldown window once `max_failed_connect_attempts` is reached. Each additional
+    /// failure past the threshold doubles the window. A successful connect resets the counter
+    pub failed_connect_cooldown: Duration,
+    /// The number of inbound connections that may be mid-handshake at once. Established sessions
+    /// do not count towards the cap. A value of 0 disables the cap
+    pub max_pending_handshakes: usize,
+    /// Determines what happens to new connections once `max_pending_handshakes` is reached
+    pub pending_handshake_overflow: PendingHandshakeOverflow,
+    /// Handshakes that have not completed within this long are reaped, closing the connection
+    pub pending_handshake_timeout: Duration,
+    /// If set, a resumption ticket valid for this long is issued to the client on each successful
+    /// connect. Presenting the ticket on the next connect skips the key agreement, after which the
+    /// client must rekey the session. Tickets are persisted through the backend, so they survive a
+    /// restart. Connects with UDP enabled never resume, since resumption skips NAT traversal.
+    /// Disabled by default
+    pub session_resumption_lifetime: Option<Duration>,
+    /// The policy used to check the format of credentials for accounts registered to this node
+    pub credential_policy: CredentialPolicy,
+    /// If enabled, RE-VFS objects stored more than once by the same owner are kept on disk once.
+    /// Only the filesystem backend supports this
+    pub revfs_deduplication: bool,
+    /// If enabled, connect attempts for CIDs that are not registered to this node are rejected as
+    /// soon as the first pre-connect packet arrives, before any key agreement takes place
+    pub require_prior_registration: bool,
+    /// The number of discarded invalid packets logged in full before further discards are only
+    /// sampled and counted
+    pub discard_log_detailed_limit: u64,
+    /// Once `discard_log_detailed_limit` is reached, one of every this many discarded packets is
+    /// still logged in full. A value of 0 logs none of them in full
+    pub discard_log_sample_rate: u64,
+    /// The minimum interval between summaries of the discarded packets that were not logged in full
+    pub discard_log_summary_interval: Duration,
+    /// Derives the CID of each account from its username. Every node in a deployment must use
+    /// the same generator. Defaults to hashing the username
+    pub cid_generator: Arc<dyn CidGenerator>,
+    /// When the ratchet history is truncated while an in-flight transfer still references the
+    /// oldest version, the truncation is deferred for up to this long
+    pub drill_truncation_grace: Duration,
+    /// The capabilities granted to each newly registered account. Defaults to every capability
+    pub default_account_features: FeatureSet,
+    /// If set, test pushes and push-wakes are delivered through this provider instead of the one
+    /// configured through the external services. Deployments without push may set a
+    /// [`NoopPushProvider`](crate::external_services::push::NoopPushProvider) to discard them
+    pub push_provider: Option<Arc<dyn PushProvider>>,
+    /// Determines how the peer list of each newly registered account is loaded. Lazy loading
+    /// suits accounts expected to accumulate large peer lists. Defaults to eager loading
+    pub peer_list_loading: PeerListLoading,
+    /// If set, notified of each account registered, deregistered, or changed through the
+    /// [`AccountManager`](crate::account_manager::AccountManager) of this node
+    pub account_listener: Option<Arc<dyn AccountManagerListener>>,
+    /// The largest inbound packet accepted for each stage of the handshake, before the session
+    /// is connected
+    pub handshake_payload_limits: HandshakePayloadLimits,
+    /// The number of registration attempts a single source IP address may make each minute
+    /// before further attempts are rejected. A value of 0 disables the limit
+    pub max_registrations_per_minute: usize,
+    /// If set, registration attempts are checked against this limiter instead of one enforcing
+    /// `max_registrations_per_minute` on this node alone
+    pub registration_rate_limiter: Option<Arc<dyn RegistrationRateLimiter>>,
+    /// If set, the creation date of each account registered through the
+    /// [`AccountManager`](crate::account_manager::AccountManager) of this node is read from this
+    /// clock instead of the system clock
+    pub clock: Option<Arc<dyn Clock>>,
+    /// The rate at which each session of this node accepts each type of inbound control command
+    pub inbound_command_limits: InboundCommandLimits,
+    /// The number of sessions a single CID may hold on this node at once. A node holds at most
+    /// one session per CID, which is also the limit when `None`. Nodes configured with a larger
+    /// value fail to build
+    pub max_sessions_per_cid: Option<u32>,
+    /// Determines what happens to a connect that would exceed `max_sessions_per_cid`
+    pub on_session_limit: OnSessionLimit,
+    /// The key that encrypts the accounts exported through
+    /// [`AccountManager::export_cnac`](crate::account_manager::AccountManager::export_cnac). The
+    /// node importing an account must use the same key. Exports and imports fail while unset
+    pub account_export_key: Option<[u8; 32]>,
+    /// If set, registrations are rejected with
+    /// [`AccountError::QuotaExceeded`](crate::misc::AccountError::QuotaExceeded) once this many
+    /// impersonal accounts, deactivated or not, are registered to this node. The count is kept by
+    /// this node, so nodes sharing a backend each enforce the cap separately
+    pub max_registered_accounts: Option<usize>,
+    /// If set, inbound packets whose header timestamp differs from the local clock by more than
+    /// this are dropped, which bounds how long a captured packet may be replayed. Packets left
+    /// unstamped, such as UDP and hole punch packets, are exempt. Disabled by default
+    pub max_clock_skew: Option<Duration>,
+    /// If enabled, `max_clock_skew` is measured from the clock offset estimated for each session
+    /// rather than from the local clock, tolerating an adjacent node whose clock is consistently
+    /// off while still dropping packets that stray from that offset
+    pub normalize_clock_offset: bool,
 }
 
 impl Default for ServerMiscSettings {
     fn default() -> Self {
         Self {
             allow_passwordless: true,
+            max_failed_connect_attempts: 5,
+            failed_connect_cooldown: Duration::from_secs(5),
+            max_pending_handshakes: 1024,
+            pending_handshake_overflow: PendingHandshakeOverflow::default(),
+            pending_handshake_timeout: Duration::from_secs(30),
+            session_resumption_lifetime: None,
+            credential_policy: CredentialPolicy::default(),
+            revfs_deduplication: false,
+            require_prior_registration: false,
+            discard_log_detailed_limit: 10,
+            discard_log_sample_rate: 1000,
+            discard_log_summary_interval: Duration::from_secs(10),
+            cid_generator: Arc::new(UsernameHashCidGenerator),
+            drill_truncation_grace: DEFAULT_TRUNCATION_GRACE,
+            default_account_features: FeatureSet::default(),
+            push_provider: None,
+            peer_list_loading: PeerListLoading::default(),
+            account_listener: None,
+            handshake_payload_limits: HandshakePayloadLimits::default(),
+            max_registrations_per_minute: 30,
+            registration_rate_limiter: None,
+            clock: None,
+            inbound_command_limits: InboundCommandLimits::default(),
+            max_sessions_per_cid: None,
+            on_session_limit: OnSessionLimit::default(),
+            account_export_key: None,
+            max_registered_accounts: None,
+            max_clock_skew: None,
+            normalize_clock_offset: false,
         }
     }
 }
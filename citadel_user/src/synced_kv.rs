@@ -0,0 +1,338 @@
+use crate::backend::PersistenceHandler;
+use crate::misc::{AccountError, Clock, SystemClock};
+use crate::serialization::SyncIO;
+use citadel_crypt::fcm::fcm_ratchet::ThinRatchet;
+use citadel_crypt::stacked_ratchet::{Ratchet, StackedRatchet};
+use futures::stream::BoxStream;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+const MAP_KEY_PREFIX: &str = "_INTERNAL_SYNCED_KV_";
+
+/// How often [`SyncedKvStore::watch`] checks for writes made by the other side
+pub const DEFAULT_WATCH_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Determines which write wins when both sides of a relationship write the same key
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ConflictPolicy {
+    /// The write with the latest timestamp, as read from the clock of the side that wrote it,
+    /// wins. Equal timestamps are won by the side with the greater CID. If the clocks of the two
+    /// sides are skewed, an earlier write may win over a later one
+    LastWriterWins,
+    /// A write made after observing the other side's latest write to the key wins, regardless
+    /// of either side's clock. Writes made concurrently, such that neither observed the other,
+    /// are won by the side with the greater CID
+    VersionVector,
+}
+
+impl ConflictPolicy {
+    fn resolve<'a>(&self, a: &'a SyncedEntry, b: &'a SyncedEntry) -> &'a SyncedEntry {
+        let a_wins = match self {
+            ConflictPolicy::LastWriterWins => (a.timestamp, a.writer) >= (b.timestamp, b.writer),
+            ConflictPolicy::VersionVector => {
+                match (
+                    dominates(&a.version, &b.version),
+                    dominates(&b.version, &a.version),
+                ) {
+                    (true, false) => true,
+                    (false, true) => false,
+                    _ => a.writer >= b.writer,
+                }
+            }
+        };
+
+        if a_wins {
+            a
+        } else {
+            b
+        }
+    }
+}
+
+/// The latest write by one side of the relationship to a key
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct SyncedEntry {
+    /// The serialized value, or `None` once deleted
+    value: Option<Vec<u8>>,
+    /// Unix millis, as read from the writer's clock
+    timestamp: i64,
+    writer: u64,
+    /// The number of writes to the key by each side that this write observed, including itself
+    version: BTreeMap<u64, u64>,
+}
+
+/// Returns true if `a` observed every write that `b` observed
+fn dominates(a: &BTreeMap<u64, u64>, b: &BTreeMap<u64, u64>) -> bool {
+    b.iter()
+        .all(|(cid, count)| a.get(cid).copied().unwrap_or(0) >= *count)
+}
+
+/// A key-value store shared by both sides of a relationship. Each side writes only to its own
+/// half of the relationship's byte map, and reads resolve each key across both halves using the
+/// [`ConflictPolicy`] chosen at construction, such that both sides converge on the same value.
+/// Both sides must therefore use the same policy and store name, and share the backend holding
+/// the byte map, such as a server storing the relationship of two of its clients, or a database
+/// reachable by both peers
+pub struct SyncedKvStore<V, R: Ratchet = StackedRatchet, Fcm: Ratchet = ThinRatchet> {
+    pers: PersistenceHandler<R, Fcm>,
+    local_cid: u64,
+    peer_cid: u64,
+    map_key: String,
+    policy: ConflictPolicy,
+    clock: Arc<dyn Clock>,
+    poll_interval: Duration,
+    changed: Arc<Notify>,
+    _pd: PhantomData<fn() -> V>,
+}
+
+impl<V, R: Ratchet, Fcm: Ratchet> Clone for SyncedKvStore<V, R, Fcm> {
+    fn clone(&self) -> Self {
+        Self {
+            pers: self.pers.clone(),
+            local_cid: self.local_cid,
+            peer_cid: self.peer_cid,
+            map_key: self.map_key.clone(),
+            policy: self.policy,
+            clock: self.clock.clone(),
+            poll_interval: self.poll_interval,
+            changed: self.changed.clone(),
+            _pd: Default::default(),
+        }
+    }
+}
+
+impl<V, R, Fcm> SyncedKvStore<V, R, Fcm>
+where
+    V: Serialize + DeserializeOwned + Send + 'static,
+    R: Ratchet,
+    Fcm: Ratchet,
+{
+    /// Creates the store named `name` for the relationship between `local_cid` and `peer_cid`,
+    /// from the perspective of `local_cid`
+    pub fn new(
+        pers: PersistenceHandler<R, Fcm>,
+        local_cid: u64,
+        peer_cid: u64,
+        name: &str,
+        policy: ConflictPolicy,
+    ) -> Self {
+        Self {
+            pers,
+            local_cid,
+            peer_cid,
+            map_key: format!("{MAP_KEY_PREFIX}{name}"),
+            policy,
+            clock: Arc::new(SystemClock),
+            poll_interval: DEFAULT_WATCH_POLL_INTERVAL,
+            changed: Arc::new(Notify::new()),
+            _pd: Default::default(),
+        }
+    }
+
+    /// Timestamps writes using `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sets how often [`Self::watch`] checks for writes made by the other side. Writes made
+    /// through this store, or its clones, are observed immediately
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Returns the policy resolving conflicting writes
+    pub fn policy(&self) -> ConflictPolicy {
+        self.policy
+    }
+
+    /// Returns the converged value of `key`
+    pub async fn get(&self, key: &str) -> Result<Option<V>, AccountError> {
+        self.get_raw(key).await?.map(decode).transpose()
+    }
+
+    /// Returns the converged value of every key that has not been deleted
+    pub async fn get_all(&self) -> Result<HashMap<String, V>, AccountError> {
+        let mut local = self.read_half(self.local_cid, self.peer_cid).await?;
+        let remote = self.read_half(self.peer_cid, self.local_cid).await?;
+        let mut ret = HashMap::new();
+
+        for (key, remote) in remote {
+            let entry = match local.remove(&key) {
+                Some(local) => self.policy.resolve(&local, &remote).clone(),
+                None => remote,
+            };
+
+            if let Some(value) = entry.value {
+                let _ = ret.insert(key, decode(value)?);
+            }
+        }
+
+        for (key, local) in local {
+            if let Some(value) = local.value {
+                let _ = ret.insert(key, decode(value)?);
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Sets the value of `key`
+    pub async fn set(&self, key: &str, value: &V) -> Result<(), AccountError> {
+        self.write(key, Some(value.serialize_to_vector()?)).await
+    }
+
+    /// Deletes `key`. The deletion is itself a write, and conflicts with writes made by the
+    /// other side like any other
+    pub async fn delete(&self, key: &str) -> Result<(), AccountError> {
+        self.write(key, None).await
+    }
+
+    /// Returns a stream yielding the converged value of `key`, then the value after each change
+    pub fn watch(&self, key: &str) -> BoxStream<'static, Result<Option<V>, AccountError>> {
+        let watch = Watch {
+            store: self.clone(),
+            key: key.to_string(),
+            last: None,
+            started: false,
+        };
+
+        Box::pin(futures::stream::unfold(watch, |mut watch| async move {
+            let next = watch.next_change().await;
+            Some((next, watch))
+        }))
+    }
+
+    async fn get_raw(&self, key: &str) -> Result<Option<Vec<u8>>, AccountError> {
+        let (_, local, remote) = self.read_entries(key).await?;
+        let entry = match (local, remote) {
+            (Some(local), Some(remote)) => self.policy.resolve(&local, &remote).clone(),
+            (Some(entry), None) | (None, Some(entry)) => entry,
+            (None, None) => return Ok(None),
+        };
+
+        Ok(entry.value)
+    }
+
+    /// Writes to the local half of the byte map. The write is retried if the local half changes
+    /// between reading the entries and swapping in the new one
+    async fn write(&self, key: &str, value: Option<Vec<u8>>) -> Result<(), AccountError> {
+        loop {
+            let (local_raw, local, remote) = self.read_entries(key).await?;
+            let mut version = local.map(|entry| entry.version).unwrap_or_default();
+            for (cid, count) in remote.into_iter().flat_map(|entry| entry.version) {
+                let observed = version.entry(cid).or_default();
+                *observed = std::cmp::max(*observed, count);
+            }
+            *version.entry(self.local_cid).or_default() += 1;
+
+            let entry = SyncedEntry {
+                value: value.clone(),
+                timestamp: self.clock.now().timestamp_millis(),
+                writer: self.local_cid,
+                version,
+            };
+
+            if self
+                .pers
+                .compare_and_swap_byte_map_value(
+                    self.local_cid,
+                    self.peer_cid,
+                    &self.map_key,
+                    key,
+                    local_raw,
+                    entry.serialize_to_vector()?,
+                )
+                .await?
+            {
+                self.changed.notify_waiters();
+                return Ok(());
+            }
+
+            // a swap also fails if there is no local half to write to
+            if !self.pers.cid_is_registered(self.local_cid).await? {
+                return Err(AccountError::ClientNonExists(self.local_cid));
+            }
+        }
+    }
+
+    /// Returns the raw local entry of `key`, along with the decoded local and remote entries
+    async fn read_entries(
+        &self,
+        key: &str,
+    ) -> Result<(Option<Vec<u8>>, Option<SyncedEntry>, Option<SyncedEntry>), AccountError> {
+        let local_raw = self
+            .pers
+            .get_byte_map_value(self.local_cid, self.peer_cid, &self.map_key, key)
+            .await?;
+        let remote_raw = self
+            .pers
+            .get_byte_map_value(self.peer_cid, self.local_cid, &self.map_key, key)
+            .await?;
+        let local = local_raw
+            .as_deref()
+            .map(SyncedEntry::deserialize_from_vector)
+            .transpose()?;
+        let remote = remote_raw
+            .as_deref()
+            .map(SyncedEntry::deserialize_from_vector)
+            .transpose()?;
+        Ok((local_raw, local, remote))
+    }
+
+    async fn read_half(
+        &self,
+        implicated_cid: u64,
+        peer_cid: u64,
+    ) -> Result<HashMap<String, SyncedEntry>, AccountError> {
+        self.pers
+            .get_byte_map_values_by_key(implicated_cid, peer_cid, &self.map_key)
+            .await?
+            .into_iter()
+            .map(|(key, raw)| Ok((key, SyncedEntry::deserialize_from_vector(&raw)?)))
+            .collect()
+    }
+}
+
+struct Watch<V, R: Ratchet, Fcm: Ratchet> {
+    store: SyncedKvStore<V, R, Fcm>,
+    key: String,
+    /// The last value yielded
+    last: Option<Option<Vec<u8>>>,
+    started: bool,
+}
+
+impl<V, R, Fcm> Watch<V, R, Fcm>
+where
+    V: Serialize + DeserializeOwned + Send + 'static,
+    R: Ratchet,
+    Fcm: Ratchet,
+{
+    /// Waits until the converged value differs from the last value yielded
+    async fn next_change(&mut self) -> Result<Option<V>, AccountError> {
+        loop {
+            if self.started {
+                let notified = self.store.changed.notified();
+                let poll = tokio::time::sleep(self.store.poll_interval);
+                let _ = futures::future::select(Box::pin(notified), Box::pin(poll)).await;
+            }
+            self.started = true;
+
+            let current = self.store.get_raw(&self.key).await?;
+            if self.last.as_ref() != Some(&current) {
+                self.last = Some(current.clone());
+                return current.map(decode).transpose();
+            }
+        }
+    }
+}
+
+fn decode<V: Serialize + DeserializeOwned>(value: Vec<u8>) -> Result<V, AccountError> {
+    V::deserialize_from_owned_vector(value)
+}
@@ -0,0 +1,111 @@
+// The key custody is set once per process, so these tests run in their own binary
+#[cfg(test)]
+mod tests {
+    use citadel_crypt::prelude::ConstructorOpts;
+    use citadel_crypt::stacked_ratchet::constructor::{
+        BobToAliceTransferType, StackedRatchetConstructor,
+    };
+    use citadel_crypt::stacked_ratchet::StackedRatchet;
+    use citadel_pqcrypto::algorithm_dictionary::KemAlgorithm;
+    use citadel_pqcrypto::prelude::algorithm_dictionary::EncryptionAlgorithm;
+    use citadel_user::auth::DeclaredAuthenticationMode;
+    use citadel_user::client_account::{ClientNetworkAccount, ClientNetworkAccountInner};
+    use citadel_user::key_custody::{set_key_custody, KeyCustody};
+    use citadel_user::misc::{AccountError, CredentialPolicy};
+    use citadel_user::prelude::ConnectionInfo;
+    use citadel_user::serialization::SyncIO;
+    use std::str::FromStr;
+
+    const CID: u64 = 10;
+    const HEADER: &[u8] = b"wrapped-by-mock-custody";
+    const MASK: u8 = 0xA5;
+
+    /// Stands in for an HSM by masking the key material behind a header
+    struct MockCustody;
+
+    impl KeyCustody for MockCustody {
+        fn wrap(&self, key_material: &[u8]) -> Result<Vec<u8>, AccountError> {
+            Ok(HEADER
+                .iter()
+                .copied()
+                .chain(key_material.iter().map(|byte| byte ^ MASK))
+                .collect())
+        }
+
+        fn unwrap(&self, wrapped: &[u8]) -> Result<Vec<u8>, AccountError> {
+            wrapped
+                .strip_prefix(HEADER)
+                .map(|masked| masked.iter().map(|byte| byte ^ MASK).collect())
+                .ok_or_else(|| AccountError::Generic("Not wrapped by this custody".to_string()))
+        }
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    }
+
+    fn gen(cid: u64) -> StackedRatchet {
+        let opts = ConstructorOpts::new_vec_init(
+            Some(KemAlgorithm::Kyber + EncryptionAlgorithm::AES_GCM_256),
+            1,
+        );
+        let mut alice = StackedRatchetConstructor::new_alice(opts.clone(), cid, 0, None).unwrap();
+        let bob = StackedRatchetConstructor::new_bob(cid, 0, opts, alice.stage0_alice().unwrap())
+            .unwrap();
+        alice
+            .stage1_alice(BobToAliceTransferType::Default(bob.stage0_bob().unwrap()))
+            .unwrap();
+        alice.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn stored_key_material_is_wrapped() -> Result<(), AccountError> {
+        set_key_custody(MockCustody)?;
+        // the custody may not be replaced once set
+        assert!(set_key_custody(MockCustody).is_err());
+
+        let cnac: ClientNetworkAccount = ClientNetworkAccount::new(
+            CID,
+            false,
+            ConnectionInfo {
+                addr: std::net::SocketAddr::from_str("127.0.0.1:12345").unwrap(),
+            },
+            DeclaredAuthenticationMode::Passwordless {
+                username: "custody".to_string(),
+                full_name: "Key Custody".to_string(),
+            },
+            gen(CID),
+            &CredentialPolicy::default(),
+        )
+        .await?;
+
+        let key_material = cnac.read().crypt_container.serialize_to_vector()?;
+        let stored = cnac.generate_proper_bytes()?;
+        assert!(contains(&stored, &MockCustody.wrap(&key_material)?));
+        assert!(!contains(&stored, &key_material));
+
+        let restored = ClientNetworkAccountInner::<StackedRatchet>::deserialize_from_owned_vector(
+            stored.clone(),
+        )?;
+        assert_eq!(restored.cid, CID);
+        assert_eq!(
+            restored.crypt_container.serialize_to_vector()?,
+            key_material
+        );
+
+        // stored key material that was not wrapped by the custody is rejected
+        let mut tampered = stored;
+        let header = tampered
+            .windows(HEADER.len())
+            .position(|window| window == HEADER)
+            .unwrap();
+        tampered[header] ^= 1;
+        assert!(
+            ClientNetworkAccountInner::<StackedRatchet>::deserialize_from_owned_vector(tampered)
+                .is_err()
+        );
+        Ok(())
+    }
+}